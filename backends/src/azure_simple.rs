@@ -1,16 +1,21 @@
 use crate::backend::{Backend, BackendType, ObjectInfo};
 use crate::retry::{RetryConfig, retry_with_backoff};
 use async_trait::async_trait;
+use azure_core::http::ClientOptions;
 use azure_identity::DeveloperToolsCredential;
-use azure_storage_blob::clients::BlobContainerClient;
+use azure_storage_blob::clients::{BlobContainerClient, BlobContainerClientOptions};
 use azure_storage_blob::models::{
     BlobClientGetPropertiesResultHeaders, BlobContainerClientListBlobsOptions,
 };
 use bytes::Bytes;
-use ghostsnap_core::{Error, Result};
+use ghostsnap_core::proxy::azure_transport;
+use ghostsnap_core::{Error, ProxyConfig, Result};
 use serde::{Deserialize, Serialize};
 use url::Url;
 
+/// How often to check on a server-side copy that is still pending.
+const COPY_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AzureConfig {
     pub account_name: String,
@@ -58,7 +63,7 @@ impl AzureBackend {
             let sas = sas.trim_start_matches('?');
             let url = Url::parse(&format!("{}/{}?{}", endpoint, container, sas))
                 .map_err(|e| Error::Backend(format!("Invalid Azure URL: {}", e)))?;
-            return BlobContainerClient::new(url, None, None)
+            return BlobContainerClient::new(url, None, Self::client_options()?)
                 .map_err(|e| Error::Backend(format!("Failed to create Azure client: {}", e)));
         }
 
@@ -71,10 +76,25 @@ impl AzureBackend {
         })?;
         let url = Url::parse(&format!("{}/{}", endpoint, container))
             .map_err(|e| Error::Backend(format!("Invalid Azure URL: {}", e)))?;
-        BlobContainerClient::new(url, Some(credential), None)
+        BlobContainerClient::new(url, Some(credential), Self::client_options()?)
             .map_err(|e| Error::Backend(format!("Failed to create Azure client: {}", e)))
     }
 
+    /// Client options that route requests through the proxy from the
+    /// environment, if one is set.
+    fn client_options() -> Result<Option<BlobContainerClientOptions>> {
+        let Some(proxy) = ProxyConfig::from_env() else {
+            return Ok(None);
+        };
+        Ok(Some(BlobContainerClientOptions {
+            client_options: ClientOptions {
+                transport: Some(azure_transport(&proxy)?),
+                ..Default::default()
+            },
+            ..Default::default()
+        }))
+    }
+
     pub fn with_prefix(mut self, prefix: String) -> Self {
         self.prefix = prefix;
         self
@@ -228,6 +248,43 @@ impl Backend for AzureBackend {
         })
     }
 
+    /// Server-side Copy Blob. Copies within one storage account finish almost
+    /// immediately, but the API is asynchronous, so wait for the copy status
+    /// to leave `pending`.
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let source = self.client.blob_client(&self.full_key(from));
+        let target = self.client.blob_client(&self.full_key(to));
+
+        target
+            .start_copy_from_url(source.url().to_string(), None)
+            .await
+            .map_err(|e| Error::Backend(format!("Failed to copy {} to {}: {}", from, to, e)))?;
+
+        loop {
+            let response = target
+                .get_properties(None)
+                .await
+                .map_err(|e| Error::Backend(format!("Failed to stat {}: {}", to, e)))?;
+            let status = response
+                .copy_status()
+                .ok()
+                .flatten()
+                .map(|status| status.to_string());
+            match status.as_deref() {
+                Some("pending") => tokio::time::sleep(COPY_POLL_INTERVAL).await,
+                None | Some("success") => break,
+                Some(status) => {
+                    return Err(Error::Backend(format!(
+                        "Copy of {} to {} ended with status {}",
+                        from, to, status
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     fn backend_type(&self) -> BackendType {
         BackendType::Azure
     }
@@ -7,7 +7,8 @@ use crate::backend::{Backend, BackendType, ObjectInfo};
 use crate::retry::{RetryConfig, retry_with_backoff};
 use async_trait::async_trait;
 use bytes::Bytes;
-use ghostsnap_core::{Error, Result};
+use ghostsnap_core::proxy::reqwest_client;
+use ghostsnap_core::{Error, ProxyConfig, Result, parse_retry_after};
 use reqwest::{Client, header};
 use serde::Deserialize;
 use std::sync::Arc;
@@ -81,10 +82,10 @@ pub struct B2Backend {
 
 impl B2Backend {
     pub fn new(config: B2Config) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(300))
-            .build()
-            .map_err(|e| Error::Backend(format!("Failed to create HTTP client: {}", e)))?;
+        let client = reqwest_client(
+            ProxyConfig::from_env().as_ref(),
+            Client::builder().timeout(std::time::Duration::from_secs(300)),
+        )?;
 
         Ok(Self {
             config,
@@ -146,12 +147,7 @@ impl B2Backend {
             .map_err(|e| Error::Backend(format!("B2 auth failed: {}", e)))?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            return Err(Error::Backend(format!(
-                "B2 auth failed ({}): {}",
-                status, body
-            )));
+            return Err(status_error("B2 auth", response).await);
         }
 
         response
@@ -179,12 +175,7 @@ impl B2Backend {
             .map_err(|e| Error::Backend(format!("B2 get_upload_url failed: {}", e)))?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            return Err(Error::Backend(format!(
-                "B2 get_upload_url failed ({}): {}",
-                status, body
-            )));
+            return Err(status_error("B2 get_upload_url", response).await);
         }
 
         response
@@ -193,6 +184,36 @@ impl B2Backend {
             .map_err(|e| Error::Backend(format!("B2 upload_url parse failed: {}", e)))
     }
 
+    /// Looks up the ID of the current version of `full_path`.
+    async fn file_id(&self, auth: &AuthResponse, full_path: &str) -> Result<String> {
+        let list_url = format!("{}/b2api/v2/b2_list_file_names", auth.api_url);
+        let list_body = serde_json::json!({
+            "bucketId": self.config.bucket_id,
+            "prefix": full_path,
+            "maxFileCount": 1
+        });
+
+        let list_response = self
+            .client
+            .post(&list_url)
+            .header(header::AUTHORIZATION, &auth.authorization_token)
+            .json(&list_body)
+            .send()
+            .await
+            .map_err(|e| Error::Backend(format!("B2 list for file ID failed: {}", e)))?;
+
+        let list: ListFilesResponse = list_response
+            .json()
+            .await
+            .map_err(|e| Error::Backend(format!("B2 list parse failed: {}", e)))?;
+
+        list.files
+            .into_iter()
+            .find(|f| f.file_name == full_path)
+            .map(|f| f.file_id)
+            .ok_or_else(|| Error::Backend(format!("File not found: {}", full_path)))
+    }
+
     fn full_path(&self, path: &str) -> String {
         if self.config.prefix.is_empty() {
             path.to_string()
@@ -202,6 +223,23 @@ impl B2Backend {
     }
 }
 
+/// Builds the error for a failed B2 API response, keeping its status and
+/// `Retry-After` header (B2 answers 503 with one when it is busy).
+async fn status_error(operation: &str, response: reqwest::Response) -> Error {
+    let status = response.status().as_u16();
+    let retry_after = response
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+    let body = response.text().await.unwrap_or_default();
+    Error::BackendStatus {
+        status,
+        message: format!("{} failed: {}", operation, body),
+        retry_after,
+    }
+}
+
 #[async_trait]
 impl Backend for B2Backend {
     async fn init(&self) -> Result<()> {
@@ -264,12 +302,7 @@ impl Backend for B2Backend {
                 .map_err(|e| Error::Backend(format!("B2 read failed: {}", e)))?;
 
             if !response.status().is_success() {
-                let status = response.status();
-                let body = response.text().await.unwrap_or_default();
-                return Err(Error::Backend(format!(
-                    "B2 read failed ({}): {}",
-                    status, body
-                )));
+                return Err(status_error("B2 read", response).await);
             }
 
             let bytes = response
@@ -308,12 +341,7 @@ impl Backend for B2Backend {
                 .map_err(|e| Error::Backend(format!("B2 write failed: {}", e)))?;
 
             if !response.status().is_success() {
-                let status = response.status();
-                let body = response.text().await.unwrap_or_default();
-                return Err(Error::Backend(format!(
-                    "B2 write failed ({}): {}",
-                    status, body
-                )));
+                return Err(status_error("B2 write", response).await);
             }
 
             Ok(())
@@ -325,38 +353,12 @@ impl Backend for B2Backend {
         let auth = self.ensure_auth().await?;
         let full_path = self.full_path(path);
 
-        // First, get the file ID
-        let list_url = format!("{}/b2api/v2/b2_list_file_names", auth.api_url);
-        let list_body = serde_json::json!({
-            "bucketId": self.config.bucket_id,
-            "prefix": full_path,
-            "maxFileCount": 1
-        });
-
-        let list_response = self
-            .client
-            .post(&list_url)
-            .header(header::AUTHORIZATION, &auth.authorization_token)
-            .json(&list_body)
-            .send()
-            .await
-            .map_err(|e| Error::Backend(format!("B2 list for delete failed: {}", e)))?;
-
-        let list: ListFilesResponse = list_response
-            .json()
-            .await
-            .map_err(|e| Error::Backend(format!("B2 list parse failed: {}", e)))?;
-
-        let file = list
-            .files
-            .iter()
-            .find(|f| f.file_name == full_path)
-            .ok_or_else(|| Error::Backend(format!("File not found: {}", path)))?;
+        let file_id = self.file_id(&auth, &full_path).await?;
 
         // Now delete by file ID
         let delete_url = format!("{}/b2api/v2/b2_delete_file_version", auth.api_url);
         let delete_body = serde_json::json!({
-            "fileId": file.file_id,
+            "fileId": file_id,
             "fileName": full_path
         });
 
@@ -370,17 +372,43 @@ impl Backend for B2Backend {
             .map_err(|e| Error::Backend(format!("B2 delete failed: {}", e)))?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            return Err(Error::Backend(format!(
-                "B2 delete failed ({}): {}",
-                status, body
-            )));
+            return Err(status_error("B2 delete", response).await);
         }
 
         Ok(())
     }
 
+    /// `b2_copy_file` duplicates the file inside B2 without downloading it.
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let auth = self.ensure_auth().await?;
+        let source_id = self.file_id(&auth, &self.full_path(from)).await?;
+
+        let copy_url = format!("{}/b2api/v2/b2_copy_file", auth.api_url);
+        let copy_body = serde_json::json!({
+            "sourceFileId": source_id,
+            "fileName": self.full_path(to)
+        });
+
+        let client = self.client.clone();
+
+        retry_with_backoff(&self.retry_config, "b2_copy", || async {
+            let response = client
+                .post(&copy_url)
+                .header(header::AUTHORIZATION, &auth.authorization_token)
+                .json(&copy_body)
+                .send()
+                .await
+                .map_err(|e| Error::Backend(format!("B2 copy failed: {}", e)))?;
+
+            if !response.status().is_success() {
+                return Err(status_error("B2 copy", response).await);
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
     async fn list(&self, prefix: &str) -> Result<Vec<String>> {
         let auth = self.ensure_auth().await?;
         let full_prefix = self.full_path(prefix);
@@ -1,6 +1,6 @@
 use async_trait::async_trait;
 use bytes::Bytes;
-use ghostsnap_core::Result;
+use ghostsnap_core::{ChunkID, Result};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BackendType {
@@ -11,6 +11,8 @@ pub enum BackendType {
     B2,
     Sftp,
     Rclone,
+    /// Fault-injecting test storage, see [`crate::mock`]
+    Mock,
 }
 
 #[async_trait]
@@ -29,6 +31,23 @@ pub trait Backend: Send + Sync {
 
     async fn stat(&self, path: &str) -> Result<ObjectInfo>;
 
+    /// Copies the object at `from` to `to`, replacing any existing object.
+    ///
+    /// Backends with a server-side copy API override this so the data never
+    /// passes through this host; the default downloads and re-uploads it.
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let data = self.read(from).await?;
+        self.write(to, data).await
+    }
+
+    /// Checks many chunks for existence in one request.
+    ///
+    /// Only backends that maintain a server-side chunk index implement this;
+    /// the default returns `None` so callers fall back to the repository index.
+    async fn has_chunks(&self, _chunk_ids: &[ChunkID]) -> Result<Option<Vec<bool>>> {
+        Ok(None)
+    }
+
     fn backend_type(&self) -> BackendType;
 }
 
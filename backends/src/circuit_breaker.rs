@@ -0,0 +1,152 @@
+//! Circuit breaker for backends that are down.
+//!
+//! Without one, every operation against an unreachable backend goes through
+//! all of its retries, so a backup of thousands of chunks takes hours to
+//! fail. The breaker counts consecutive transient failures across all
+//! operations sharing a [`RetryConfig`](crate::RetryConfig); once it trips,
+//! operations fail immediately with [`Error::BackendUnavailable`] until the
+//! cool-down has passed. Then a single probe is let through: its success
+//! closes the circuit, its failure starts another cool-down.
+
+use ghostsnap_core::{Error, Result};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// When the circuit breaker trips and how long it stays open.
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failed attempts that open the circuit
+    pub failure_threshold: u32,
+    /// How long operations fail fast before the backend is probed again
+    pub cool_down: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 10,
+            cool_down: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    last_error: String,
+    /// Set while the circuit is open
+    open_until: Option<Instant>,
+    /// A probe is in flight after the cool-down
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Whether operations currently fail fast.
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap().open_until.is_some()
+    }
+
+    /// Checks whether an attempt may go to the backend. Fails with
+    /// [`Error::BackendUnavailable`] while the circuit is open, and while
+    /// another caller is probing after the cool-down.
+    pub fn check(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let Some(open_until) = state.open_until else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        if now < open_until || state.probing {
+            return Err(Error::BackendUnavailable {
+                failures: state.consecutive_failures,
+                retry_in: open_until.saturating_duration_since(now),
+                last_error: state.last_error.clone(),
+            });
+        }
+
+        info!("Probing backend after circuit breaker cool-down");
+        state.probing = true;
+        Ok(())
+    }
+
+    /// Records an attempt the backend answered, closing the circuit.
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.open_until.is_some() {
+            info!("Backend recovered, closing circuit breaker");
+        }
+        *state = BreakerState::default();
+    }
+
+    /// Records a transient failure, opening the circuit at the threshold.
+    pub fn record_failure(&self, error: &dyn std::fmt::Display) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        state.last_error = error.to_string();
+        state.probing = false;
+
+        if state.consecutive_failures >= self.config.failure_threshold {
+            if state.open_until.is_none() {
+                warn!(
+                    failures = state.consecutive_failures,
+                    cool_down_secs = self.config.cool_down.as_secs(),
+                    error = %error,
+                    "Backend keeps failing, opening circuit breaker"
+                );
+            }
+            state.open_until = Some(Instant::now() + self.config.cool_down);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_trips_fails_fast_and_recovers() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 3,
+            cool_down: Duration::from_millis(50),
+        });
+
+        for _ in 0..2 {
+            breaker.check().unwrap();
+            breaker.record_failure(&"connection refused");
+        }
+        assert!(!breaker.is_open());
+        breaker.record_failure(&"connection refused");
+        assert!(breaker.is_open());
+
+        let err = breaker.check().unwrap_err();
+        assert!(matches!(err, Error::BackendUnavailable { failures: 3, .. }));
+        assert!(err.to_string().contains("connection refused"));
+
+        // One probe after the cool-down; a failed probe reopens the circuit
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        breaker.check().unwrap();
+        assert!(breaker.check().is_err());
+        breaker.record_failure(&"connection refused");
+        assert!(breaker.check().is_err());
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        breaker.check().unwrap();
+        breaker.record_success();
+        assert!(!breaker.is_open());
+        breaker.check().unwrap();
+    }
+}
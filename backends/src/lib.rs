@@ -1,8 +1,10 @@
 pub mod azure_simple;
 pub mod b2;
 pub mod backend;
+pub mod circuit_breaker;
 pub mod local;
 pub mod minio;
+pub mod mock;
 pub mod rclone;
 pub mod retry;
 pub mod s3;
@@ -11,9 +13,11 @@ pub mod sftp;
 pub use azure_simple::{AzureBackend, AzureConfig, AzureSimpleBackend};
 pub use b2::{B2Backend, B2Config};
 pub use backend::{Backend, BackendType, ObjectInfo};
+pub use circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
 pub use local::LocalBackend;
 pub use minio::{BucketMetrics, MinIOBackend, MinIOConfig};
+pub use mock::{Fault, FaultKind, MockBackend, Operation};
 pub use rclone::RcloneBackend;
-pub use retry::{RetryConfig, Retryable, retry_with_backoff};
+pub use retry::{AdaptiveConcurrency, RetryConfig, Retryable, retry_with_backoff};
 pub use s3::{S3Backend, S3SseConfig, SseType};
 pub use sftp::{SftpAuth, SftpBackend, SftpConfig};
@@ -190,6 +190,28 @@ impl Backend for LocalBackend {
         Ok(results)
     }
 
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let source = self.full_path(from);
+        let target = self.full_path(to);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        // Same temp file + rename as `atomic_write`, so readers never see a
+        // partial copy.
+        let temp_path = target.with_extension("tmp");
+
+        retry_with_backoff(&self.retry_config, "local_copy", || async {
+            fs::copy(&source, &temp_path)
+                .await
+                .map_err(|e| Error::Backend(format!("Failed to copy {} to {}: {}", from, to, e)))?;
+            fs::rename(&temp_path, &target)
+                .await
+                .map_err(|e| Error::Backend(format!("Failed to rename temp file: {}", e)))?;
+            Ok(())
+        })
+        .await
+    }
+
     async fn stat(&self, path: &str) -> Result<ObjectInfo> {
         let full_path = self.full_path(path);
         let metadata = fs::metadata(&full_path)
@@ -427,4 +449,19 @@ mod tests {
         let files = backend.list("nonexistent").await.unwrap();
         assert!(files.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_copy() {
+        let temp = tempdir().unwrap();
+        let backend = LocalBackend::new(temp.path());
+        backend.init().await.unwrap();
+
+        let data = Bytes::from("Pack contents");
+        backend.write("data/a.pack", data.clone()).await.unwrap();
+        backend.copy("data/a.pack", "new/b.pack").await.unwrap();
+
+        assert_eq!(backend.read("new/b.pack").await.unwrap(), data);
+        assert_eq!(backend.read("data/a.pack").await.unwrap(), data);
+        assert!(!temp.path().join("new/b.tmp").exists());
+    }
 }
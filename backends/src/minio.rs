@@ -11,7 +11,9 @@ use aws_sdk_s3::{
 };
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 use bytes::Bytes;
-use ghostsnap_core::{Error, Result};
+use ghostsnap_core::storage::{s3_error, s3_http_client};
+use ghostsnap_core::validation::check_bucket_name;
+use ghostsnap_core::{Error, ProxyConfig, Result, S3StorageClasses, S3Tls, Validator};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tokio::time::sleep;
@@ -30,13 +32,25 @@ pub struct MinIOConfig {
     pub multipart_threshold: usize,
     pub chunk_size: usize,
     pub max_concurrency: usize,
+    /// Storage class for all objects, unless `storage_classes` sets one for
+    /// the object's type
     pub storage_class: Option<String>,
+    /// Per-object-type storage classes (packs vs. metadata)
+    #[serde(default)]
+    pub storage_classes: S3StorageClasses,
     pub server_side_encryption: Option<String>,
     pub retry_attempts: u32,
     pub retry_delay_ms: u64,
     pub bandwidth_limit_mbps: Option<f64>,
     pub enable_checksums: bool,
     pub enable_versioning: bool,
+    /// Custom CA bundle, client certificate and hostname checking
+    #[serde(default)]
+    pub tls: S3Tls,
+    /// Proxy URL (`http://`, `socks5://`, `socks5h://`); the proxy from the
+    /// environment is used when unset
+    #[serde(default)]
+    pub proxy: Option<String>,
 }
 
 impl Default for MinIOConfig {
@@ -54,16 +68,107 @@ impl Default for MinIOConfig {
             chunk_size: 16 * 1024 * 1024,          // 16MB per part
             max_concurrency: 8,
             storage_class: None,
+            storage_classes: S3StorageClasses::default(),
             server_side_encryption: None,
             retry_attempts: 3,
             retry_delay_ms: 1000,
             bandwidth_limit_mbps: None,
             enable_checksums: true,
             enable_versioning: false,
+            tls: S3Tls::default(),
+            proxy: None,
         }
     }
 }
 
+/// Smallest part S3 accepts in a multipart upload (except the last one).
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+/// Largest part S3 accepts in a multipart upload.
+const MAX_PART_SIZE: usize = 5 * 1024 * 1024 * 1024;
+
+impl MinIOConfig {
+    /// Checks the settings before a client is built, naming each bad field.
+    pub fn validate(&self) -> Result<()> {
+        let mut v = Validator::new();
+
+        v.check(
+            self.endpoint.starts_with("http://") || self.endpoint.starts_with("https://"),
+            "endpoint",
+            format!("'{}' must be an http:// or https:// URL", self.endpoint),
+        );
+        if let Err(message) = check_bucket_name(&self.bucket) {
+            v.error("bucket", message);
+        }
+        v.check(!self.region.is_empty(), "region", "must not be empty");
+        v.check(
+            self.access_key.is_empty() == self.secret_key.is_empty(),
+            "secret_key",
+            "access_key and secret_key must be set together",
+        );
+
+        v.check(
+            self.chunk_size >= MIN_PART_SIZE,
+            "chunk_size",
+            format!(
+                "{} bytes is below the 5 MiB minimum part size of multipart uploads",
+                self.chunk_size
+            ),
+        );
+        v.check(
+            self.chunk_size <= MAX_PART_SIZE,
+            "chunk_size",
+            format!(
+                "{} bytes exceeds the 5 GiB maximum part size of multipart uploads",
+                self.chunk_size
+            ),
+        );
+        v.check(
+            self.multipart_threshold >= self.chunk_size,
+            "multipart_threshold",
+            format!(
+                "{} bytes is smaller than chunk_size ({} bytes)",
+                self.multipart_threshold, self.chunk_size
+            ),
+        );
+        v.check(
+            self.max_concurrency > 0,
+            "max_concurrency",
+            "must be at least 1",
+        );
+
+        if let Some(mbps) = self.bandwidth_limit_mbps {
+            v.check(
+                mbps.is_finite() && mbps > 0.0,
+                "bandwidth_limit_mbps",
+                format!("{} must be a positive number", mbps),
+            );
+        }
+        if let Some(class) = &self.storage_class {
+            v.check(
+                StorageClass::values().contains(&class.as_str()),
+                "storage_class",
+                format!("unknown storage class '{}'", class),
+            );
+        }
+        if let Some(sse) = &self.server_side_encryption {
+            v.check(
+                ServerSideEncryption::values().contains(&sse.as_str()),
+                "server_side_encryption",
+                format!(
+                    "'{}' must be one of {}",
+                    sse,
+                    ServerSideEncryption::values().join(", ")
+                ),
+            );
+        }
+        if let Some(proxy) = &self.proxy {
+            v.check_result(ProxyConfig::parse(proxy), "proxy");
+        }
+
+        v.finish()
+    }
+}
+
 pub struct MinIOBackend {
     client: Client,
     config: MinIOConfig,
@@ -112,6 +217,8 @@ impl BandwidthLimiter {
 
 impl MinIOBackend {
     pub async fn new(config: MinIOConfig) -> Result<Self> {
+        config.validate()?;
+
         let credentials = Credentials::new(
             &config.access_key,
             &config.secret_key,
@@ -120,14 +227,20 @@ impl MinIOBackend {
             "ghostsnap-minio",
         );
 
-        let s3_config = S3ConfigBuilder::new()
+        let mut s3_config = S3ConfigBuilder::new()
             .credentials_provider(credentials)
             .region(Region::new(config.region.clone()))
             .endpoint_url(&config.endpoint)
-            .force_path_style(config.path_style)
-            .build();
+            .force_path_style(config.path_style);
+        let proxy = match &config.proxy {
+            Some(proxy) => Some(ProxyConfig::parse(proxy)?),
+            None => ProxyConfig::from_env(),
+        };
+        if let Some(http_client) = s3_http_client(&config.tls, proxy.as_ref())? {
+            s3_config = s3_config.http_client(http_client);
+        }
 
-        let client = Client::from_conf(s3_config);
+        let client = Client::from_conf(s3_config.build());
 
         let bandwidth_limiter = config.bandwidth_limit_mbps.map(BandwidthLimiter::new);
 
@@ -135,7 +248,7 @@ impl MinIOBackend {
             client,
             config: config.clone(),
             bandwidth_limiter,
-            retry_config: RetryConfig::default(), // Use default retry config
+            retry_config: RetryConfig::default().with_adaptive_concurrency(config.max_concurrency),
         };
 
         backend.ensure_bucket_exists().await?;
@@ -178,7 +291,7 @@ impl MinIOBackend {
                         .bucket(&bucket)
                         .send()
                         .await
-                        .map_err(|e| Error::Backend(format!("Failed to create bucket: {:?}", e)))
+                        .map_err(|e| s3_error("Failed to create bucket", &e))
                 })
                 .await?;
 
@@ -209,7 +322,7 @@ impl MinIOBackend {
                 .versioning_configuration(versioning_config.clone())
                 .send()
                 .await
-                .map_err(|e| Error::Backend(format!("Failed to enable versioning: {:?}", e)))
+                .map_err(|e| s3_error("Failed to enable versioning", &e))
         })
         .await?;
 
@@ -224,6 +337,15 @@ impl MinIOBackend {
         }
     }
 
+    /// Storage class for the object at `path`.
+    fn storage_class_for(&self, path: &str) -> Option<String> {
+        self.config
+            .storage_classes
+            .for_path(path)
+            .or(self.config.storage_class.as_deref())
+            .map(str::to_string)
+    }
+
     // Note: Bandwidth throttling not yet implemented
     // Will be enabled in future version with interior mutability pattern
     #[allow(dead_code)]
@@ -239,7 +361,7 @@ impl MinIOBackend {
         let data_clone = data.clone();
         let bucket = self.config.bucket.clone();
         let key = self.full_key(path);
-        let storage_class = self.config.storage_class.clone();
+        let storage_class = self.storage_class_for(path);
         let server_side_encryption = self.config.server_side_encryption.clone();
         let enable_checksums = self.config.enable_checksums;
         let client = self.client.clone();
@@ -269,7 +391,7 @@ impl MinIOBackend {
 
             req.send()
                 .await
-                .map_err(|e| Error::Backend(format!("Failed to upload: {:?}", e)))
+                .map_err(|e| s3_error("Failed to upload", &e))
         })
         .await
     }
@@ -278,7 +400,7 @@ impl MinIOBackend {
         let key = self.full_key(path);
         let bucket = self.config.bucket.clone();
         let client = self.client.clone();
-        let storage_class = self.config.storage_class.clone();
+        let storage_class = self.storage_class_for(path);
         let server_side_encryption = self.config.server_side_encryption.clone();
 
         // Initiate multipart upload
@@ -298,9 +420,10 @@ impl MinIOBackend {
                     request = request.server_side_encryption(encryption);
                 }
 
-                request.send().await.map_err(|e| {
-                    Error::Backend(format!("Failed to create multipart upload: {:?}", e))
-                })
+                request
+                    .send()
+                    .await
+                    .map_err(|e| s3_error("Failed to create multipart upload", &e))
             })
             .await?;
 
@@ -346,7 +469,7 @@ impl MinIOBackend {
                     request
                         .send()
                         .await
-                        .map_err(|e| Error::Backend(format!("Failed to upload part: {:?}", e)))
+                        .map_err(|e| s3_error("Failed to upload part", &e))
                 })
                 .await?;
 
@@ -372,9 +495,7 @@ impl MinIOBackend {
                 .multipart_upload(completed_upload.clone())
                 .send()
                 .await
-                .map_err(|e| {
-                    Error::Backend(format!("Failed to complete multipart upload: {:?}", e))
-                })
+                .map_err(|e| s3_error("Failed to complete multipart upload", &e))
         })
         .await?;
 
@@ -398,7 +519,7 @@ impl MinIOBackend {
             let page = request
                 .send()
                 .await
-                .map_err(|e| Error::Backend(format!("Failed to list objects: {:?}", e)))?;
+                .map_err(|e| s3_error("Failed to list objects", &e))?;
 
             for object in page.contents() {
                 total_size += object.size().unwrap_or(0) as u64;
@@ -456,7 +577,7 @@ impl Backend for MinIOBackend {
                 .key(&key)
                 .send()
                 .await
-                .map_err(|e| Error::Backend(format!("Failed to check existence: {:?}", e)))
+                .map_err(|e| s3_error("Failed to check existence", &e))
         })
         .await
         {
@@ -477,7 +598,7 @@ impl Backend for MinIOBackend {
                 .key(&key)
                 .send()
                 .await
-                .map_err(|e| Error::Backend(format!("Failed to read object {}: {:?}", path, e)))
+                .map_err(|e| s3_error(format_args!("Failed to read object {}", path), &e))
         })
         .await?;
 
@@ -508,16 +629,23 @@ impl Backend for MinIOBackend {
         let bucket = self.config.bucket.clone();
         let key = self.full_key(path);
         let client = self.client.clone();
+        let storage_class = self.storage_class_for(path);
 
         retry_with_backoff(&self.retry_config, "minio_write", || async {
-            client
+            let mut request = client
                 .put_object()
                 .bucket(&bucket)
                 .key(&key)
-                .body(ByteStream::from(data.clone()))
+                .body(ByteStream::from(data.clone()));
+
+            if let Some(ref storage_class) = storage_class {
+                request = request.storage_class(StorageClass::from(storage_class.as_str()));
+            }
+
+            request
                 .send()
                 .await
-                .map_err(|e| Error::Backend(format!("Failed to write object: {:?}", e)))
+                .map_err(|e| s3_error("Failed to write object", &e))
         })
         .await?;
 
@@ -536,7 +664,7 @@ impl Backend for MinIOBackend {
                 .key(&key)
                 .send()
                 .await
-                .map_err(|e| Error::Backend(format!("Failed to delete object {}: {:?}", path, e)))
+                .map_err(|e| s3_error(format_args!("Failed to delete object {}", path), &e))
         })
         .await?;
 
@@ -563,7 +691,7 @@ impl Backend for MinIOBackend {
             let page = request
                 .send()
                 .await
-                .map_err(|e| Error::Backend(format!("Failed to list objects: {:?}", e)))?;
+                .map_err(|e| s3_error("Failed to list objects", &e))?;
 
             for object in page.contents() {
                 if let Some(key) = object.key() {
@@ -600,7 +728,7 @@ impl Backend for MinIOBackend {
                 .key(&key)
                 .send()
                 .await
-                .map_err(|e| Error::Backend(format!("Failed to stat object {}: {:?}", path, e)))
+                .map_err(|e| s3_error(format_args!("Failed to stat object {}", path), &e))
         })
         .await?;
 
@@ -620,6 +748,41 @@ impl Backend for MinIOBackend {
         })
     }
 
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let bucket = self.config.bucket.clone();
+        let copy_source = format!("{}/{}", self.config.bucket, self.full_key(from));
+        let key = self.full_key(to);
+        let storage_class = self.storage_class_for(to);
+        let server_side_encryption = self.config.server_side_encryption.clone();
+        let client = self.client.clone();
+
+        retry_with_backoff(&self.retry_config, "minio_copy", || async {
+            let mut request = client
+                .copy_object()
+                .bucket(&bucket)
+                .key(&key)
+                .copy_source(&copy_source);
+
+            if let Some(ref storage_class) = storage_class {
+                request = request.storage_class(StorageClass::from(storage_class.as_str()));
+            }
+
+            if let Some(ref sse) = server_side_encryption {
+                // Parse is infallible for AWS SDK enums, so we can directly unwrap
+                let encryption = sse.parse::<ServerSideEncryption>().unwrap();
+                request = request.server_side_encryption(encryption);
+            }
+
+            request
+                .send()
+                .await
+                .map_err(|e| s3_error(format_args!("Failed to copy object {} to {}", from, to), &e))
+        })
+        .await?;
+
+        Ok(())
+    }
+
     fn backend_type(&self) -> BackendType {
         BackendType::S3 // MinIO is S3-compatible
     }
@@ -627,3 +790,41 @@ impl Backend for MinIOBackend {
 
 use base64;
 use md5;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_config() {
+        assert!(MinIOConfig::default().validate().is_ok());
+
+        let config = MinIOConfig {
+            bucket: String::new(),
+            chunk_size: 128 * 1024 * 1024,
+            server_side_encryption: Some("aes".to_string()),
+            ..Default::default()
+        };
+        let Err(Error::InvalidConfig(errors)) = config.validate() else {
+            panic!("invalid config accepted");
+        };
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            ["bucket", "multipart_threshold", "server_side_encryption"]
+        );
+        assert_eq!(errors[0].to_string(), "bucket: must not be empty");
+
+        let config = MinIOConfig {
+            chunk_size: 1024,
+            ..Default::default()
+        };
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .to_string()
+                .contains("chunk_size")
+        );
+    }
+}
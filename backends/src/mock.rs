@@ -0,0 +1,364 @@
+//! Fault-injecting storage for tests.
+//!
+//! [`MockBackend`] wraps repository storage and misbehaves on request: it
+//! adds latency to every call, and [`Fault`]s make matching requests fail
+//! transiently, get throttled, or store only part of the data written. It
+//! implements both [`Backend`] and [`RepositoryStorage`], so a repository
+//! opened with `Repository::init_with_storage` or `open_with_storage` runs
+//! its whole storage path through it.
+//!
+//! Clones share their faults and counters: keep one to inject faults into
+//! storage that a repository has taken ownership of.
+
+use crate::backend::{Backend, BackendType, ObjectInfo};
+use async_trait::async_trait;
+use bytes::Bytes;
+use ghostsnap_core::storage::{
+    ObjectMetadata, RepositoryLocation, RepositoryStorage, local_storage,
+};
+use ghostsnap_core::{Error, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Storage operation a [`Fault`] applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Operation {
+    Exists,
+    Read,
+    Write,
+    Delete,
+    List,
+    Stat,
+}
+
+/// How a faulty request fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    /// Fails with HTTP 500, which callers retry
+    Transient,
+    /// Fails with HTTP 503 "SlowDown", asking to wait `retry_after`
+    Throttle { retry_after: Duration },
+    /// Stores the first half of the data, then fails with HTTP 500, like an
+    /// upload cut off midway on storage without atomic writes
+    PartialWrite,
+}
+
+/// A fault affecting the next `times` requests of one operation whose path
+/// starts with `prefix`.
+#[derive(Debug, Clone)]
+pub struct Fault {
+    operation: Operation,
+    kind: FaultKind,
+    prefix: String,
+    times: u32,
+}
+
+impl Fault {
+    pub fn transient(operation: Operation) -> Self {
+        Self::new(operation, FaultKind::Transient)
+    }
+
+    pub fn throttle(operation: Operation, retry_after: Duration) -> Self {
+        Self::new(operation, FaultKind::Throttle { retry_after })
+    }
+
+    pub fn partial_write() -> Self {
+        Self::new(Operation::Write, FaultKind::PartialWrite)
+    }
+
+    fn new(operation: Operation, kind: FaultKind) -> Self {
+        Self {
+            operation,
+            kind,
+            prefix: String::new(),
+            times: 1,
+        }
+    }
+
+    /// Only affects paths starting with `prefix`, e.g. `data/`.
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Affects this many requests instead of one.
+    pub fn with_times(mut self, times: u32) -> Self {
+        self.times = times;
+        self
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    faults: Vec<Fault>,
+    calls: BTreeMap<Operation, u64>,
+    injected: u64,
+}
+
+/// Storage wrapper that injects latency and faults. See the module docs.
+#[derive(Clone)]
+pub struct MockBackend {
+    inner: Arc<dyn RepositoryStorage>,
+    latency: Duration,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockBackend {
+    pub fn new(inner: Box<dyn RepositoryStorage>) -> Self {
+        Self {
+            inner: Arc::from(inner),
+            latency: Duration::ZERO,
+            state: Arc::default(),
+        }
+    }
+
+    /// Wraps local storage at `path`.
+    pub fn local<P: AsRef<Path>>(path: P) -> Self {
+        Self::new(local_storage(path))
+    }
+
+    /// Delays every request by `latency`.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// This backend as repository storage, sharing faults and counters.
+    pub fn storage(&self) -> Box<dyn RepositoryStorage> {
+        Box::new(self.clone())
+    }
+
+    pub fn inject(&self, fault: Fault) {
+        self.state.lock().unwrap().faults.push(fault);
+    }
+
+    pub fn clear_faults(&self) {
+        self.state.lock().unwrap().faults.clear();
+    }
+
+    /// Requests of `operation` made so far, including failed ones.
+    pub fn calls(&self, operation: Operation) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.calls.get(&operation).copied().unwrap_or(0)
+    }
+
+    /// Requests that a fault made fail so far.
+    pub fn faults_injected(&self) -> u64 {
+        self.state.lock().unwrap().injected
+    }
+
+    /// Flips a byte of the stored object at `path`, bypassing faults.
+    pub async fn corrupt(&self, path: &str, offset: usize) -> Result<()> {
+        let mut data = self.inner.read(path).await?.to_vec();
+        if data.is_empty() {
+            return Err(Error::Other(format!(
+                "Cannot corrupt empty object {}",
+                path
+            )));
+        }
+        let offset = offset % data.len();
+        data[offset] ^= 0xff;
+        self.inner.write(path, data.into()).await
+    }
+
+    /// Counts the request, waits out the latency and takes the first
+    /// matching fault, if any.
+    async fn begin(&self, operation: Operation, path: &str) -> Option<FaultKind> {
+        if !self.latency.is_zero() {
+            tokio::time::sleep(self.latency).await;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        *state.calls.entry(operation).or_default() += 1;
+        let index = state
+            .faults
+            .iter()
+            .position(|fault| fault.operation == operation && path.starts_with(&fault.prefix))?;
+        let fault = &mut state.faults[index];
+        let kind = fault.kind;
+        fault.times -= 1;
+        if fault.times == 0 {
+            state.faults.remove(index);
+        }
+        state.injected += 1;
+        Some(kind)
+    }
+
+    /// Fails the request if a fault matches it.
+    async fn check(&self, operation: Operation, path: &str) -> Result<()> {
+        match self.begin(operation, path).await {
+            Some(kind) => Err(fault_error(kind, operation, path)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn fault_error(kind: FaultKind, operation: Operation, path: &str) -> Error {
+    match kind {
+        FaultKind::Throttle { retry_after } => Error::BackendStatus {
+            status: 503,
+            message: format!("SlowDown: injected on {:?} {}", operation, path),
+            retry_after: Some(retry_after),
+        },
+        FaultKind::Transient | FaultKind::PartialWrite => Error::BackendStatus {
+            status: 500,
+            message: format!("Injected failure on {:?} {}", operation, path),
+            retry_after: None,
+        },
+    }
+}
+
+#[async_trait]
+impl RepositoryStorage for MockBackend {
+    fn location(&self) -> &RepositoryLocation {
+        self.inner.location()
+    }
+
+    async fn init(&self) -> Result<()> {
+        self.inner.init().await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool> {
+        self.check(Operation::Exists, path).await?;
+        self.inner.exists(path).await
+    }
+
+    async fn read(&self, path: &str) -> Result<Bytes> {
+        self.check(Operation::Read, path).await?;
+        self.inner.read(path).await
+    }
+
+    async fn write(&self, path: &str, data: Bytes) -> Result<()> {
+        match self.begin(Operation::Write, path).await {
+            None => self.inner.write(path, data).await,
+            Some(FaultKind::PartialWrite) => {
+                self.inner.write(path, data.slice(..data.len() / 2)).await?;
+                Err(fault_error(FaultKind::PartialWrite, Operation::Write, path))
+            }
+            Some(kind) => Err(fault_error(kind, Operation::Write, path)),
+        }
+    }
+
+    async fn delete(&self, path: &str) -> Result<()> {
+        self.check(Operation::Delete, path).await?;
+        self.inner.delete(path).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        self.check(Operation::List, prefix).await?;
+        self.inner.list(prefix).await
+    }
+
+    async fn metadata(&self, path: &str) -> Result<ObjectMetadata> {
+        self.check(Operation::Stat, path).await?;
+        self.inner.metadata(path).await
+    }
+}
+
+#[async_trait]
+impl Backend for MockBackend {
+    async fn init(&self) -> Result<()> {
+        RepositoryStorage::init(self).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool> {
+        RepositoryStorage::exists(self, path).await
+    }
+
+    async fn read(&self, path: &str) -> Result<Bytes> {
+        RepositoryStorage::read(self, path).await
+    }
+
+    async fn write(&self, path: &str, data: Bytes) -> Result<()> {
+        RepositoryStorage::write(self, path, data).await
+    }
+
+    async fn delete(&self, path: &str) -> Result<()> {
+        RepositoryStorage::delete(self, path).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        RepositoryStorage::list(self, prefix).await
+    }
+
+    async fn stat(&self, path: &str) -> Result<ObjectInfo> {
+        let metadata = RepositoryStorage::metadata(self, path).await?;
+        Ok(ObjectInfo {
+            path: path.to_string(),
+            size: metadata.size,
+            modified: metadata.modified_at,
+        })
+    }
+
+    fn backend_type(&self) -> BackendType {
+        BackendType::Mock
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retry::{RetryConfig, retry_with_backoff};
+    use tempfile::TempDir;
+
+    fn fast_retries() -> RetryConfig {
+        RetryConfig {
+            initial_backoff: Duration::from_millis(1),
+            jitter: false,
+            ..RetryConfig::quick().without_circuit_breaker()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transient_faults_are_retried() {
+        let dir = TempDir::new().unwrap();
+        let mock = MockBackend::local(dir.path());
+        mock.inject(
+            Fault::transient(Operation::Write)
+                .with_prefix("data/")
+                .with_times(2),
+        );
+
+        // Other prefixes are unaffected
+        Backend::write(&mock, "keys/a", Bytes::from_static(b"key"))
+            .await
+            .unwrap();
+
+        retry_with_backoff(&fast_retries(), "mock_write", || {
+            Backend::write(&mock, "data/a", Bytes::from_static(b"data"))
+        })
+        .await
+        .unwrap();
+        assert_eq!(mock.calls(Operation::Write), 4);
+        assert_eq!(mock.faults_injected(), 2);
+        assert_eq!(Backend::read(&mock, "data/a").await.unwrap(), "data");
+    }
+
+    #[tokio::test]
+    async fn test_throttle_and_partial_write() {
+        let dir = TempDir::new().unwrap();
+        let mock = MockBackend::local(dir.path()).with_latency(Duration::from_millis(1));
+        Backend::write(&mock, "data/a", Bytes::from_static(b"0123456789"))
+            .await
+            .unwrap();
+
+        mock.inject(Fault::throttle(Operation::Read, Duration::from_millis(5)));
+        let err = Backend::read(&mock, "data/a").await.unwrap_err();
+        assert!(err.is_throttling());
+        assert_eq!(err.retry_after(), Some(Duration::from_millis(5)));
+        assert_eq!(Backend::read(&mock, "data/a").await.unwrap(), "0123456789");
+
+        mock.inject(Fault::partial_write());
+        assert!(
+            Backend::write(&mock, "data/b", Bytes::from_static(b"0123456789"))
+                .await
+                .is_err()
+        );
+        assert_eq!(Backend::read(&mock, "data/b").await.unwrap(), "01234");
+
+        mock.corrupt("data/a", 1).await.unwrap();
+        assert_ne!(Backend::read(&mock, "data/a").await.unwrap(), "0123456789");
+    }
+}
@@ -187,6 +187,23 @@ impl Backend for RcloneBackend {
         Ok(())
     }
 
+    /// `rclone copyto` between two paths on the same remote uses the
+    /// provider's server-side copy where it has one.
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let source = self.full_path(from);
+        let target = self.full_path(to);
+        let (success, _, stderr) = self.run_rclone(&["copyto", &source, &target]).await?;
+
+        if !success {
+            return Err(Error::Backend(format!(
+                "Failed to copy {} to {}: {}",
+                from, to, stderr
+            )));
+        }
+
+        Ok(())
+    }
+
     async fn delete(&self, path: &str) -> Result<()> {
         let full_path = self.full_path(path);
         let (success, _, stderr) = self.run_rclone(&["deletefile", &full_path]).await?;
@@ -1,8 +1,16 @@
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
+use ghostsnap_core::metrics;
 use rand::Rng;
 use std::future::Future;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
 use tokio::time::sleep;
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
+
+/// Upper bound on a `Retry-After` delay that is honored, in case a service
+/// asks for something unreasonable.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
 
 /// Configuration for retry behavior with exponential backoff
 #[derive(Debug, Clone)]
@@ -17,6 +25,12 @@ pub struct RetryConfig {
     pub backoff_multiplier: f64,
     /// Add jitter to prevent thundering herd
     pub jitter: bool,
+    /// Limits concurrent operations and lowers the limit while the backend
+    /// is throttling; shared by every clone of this config
+    pub concurrency: Option<Arc<AdaptiveConcurrency>>,
+    /// Fails operations fast once the backend keeps failing; shared by
+    /// every clone of this config
+    pub circuit_breaker: Option<Arc<CircuitBreaker>>,
 }
 
 impl Default for RetryConfig {
@@ -27,6 +41,10 @@ impl Default for RetryConfig {
             max_backoff: Duration::from_secs(30),
             backoff_multiplier: 2.0,
             jitter: true,
+            concurrency: None,
+            circuit_breaker: Some(Arc::new(CircuitBreaker::new(
+                CircuitBreakerConfig::default(),
+            ))),
         }
     }
 }
@@ -52,6 +70,27 @@ impl RetryConfig {
         }
     }
 
+    /// Runs at most `max_concurrency` operations at a time, fewer while the
+    /// backend is throttling.
+    pub fn with_adaptive_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.concurrency = Some(Arc::new(AdaptiveConcurrency::new(max_concurrency)));
+        self
+    }
+
+    /// Replaces the circuit breaker, e.g. to trip sooner for interactive
+    /// use.
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = Some(Arc::new(CircuitBreaker::new(config)));
+        self
+    }
+
+    /// Retries every operation up to `max_attempts` however long the backend
+    /// has been failing.
+    pub fn without_circuit_breaker(mut self) -> Self {
+        self.circuit_breaker = None;
+        self
+    }
+
     /// Calculate backoff duration for a given attempt
     fn backoff_duration(&self, attempt: u32) -> Duration {
         let base_duration =
@@ -73,6 +112,16 @@ impl RetryConfig {
 /// Trait to determine if an error is retryable
 pub trait Retryable {
     fn is_retryable(&self) -> bool;
+
+    /// Delay the backend asked for before retrying (`Retry-After`)
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Whether the backend rejected the request for being sent too many
+    fn is_throttled(&self) -> bool {
+        false
+    }
 }
 
 impl Retryable for ghostsnap_core::Error {
@@ -90,7 +139,14 @@ impl Retryable for ghostsnap_core::Error {
                     || msg.contains("try again")
                     || msg.contains("503")
                     || msg.contains("429")
+                    || msg.contains("SlowDown")
+            }
+            // Throttling, timeouts and server-side failures
+            ghostsnap_core::Error::BackendStatus { status, .. } => {
+                matches!(status, 408 | 429 | 500 | 502 | 503 | 504)
             }
+            // Context only annotates the underlying error
+            ghostsnap_core::Error::Context { inner, .. } => inner.is_retryable(),
             // Don't retry on authentication, validation, or corruption errors
             ghostsnap_core::Error::InvalidPassword
             | ghostsnap_core::Error::RepositoryNotFound { .. }
@@ -101,9 +157,128 @@ impl Retryable for ghostsnap_core::Error {
             _ => false,
         }
     }
+
+    fn retry_after(&self) -> Option<Duration> {
+        ghostsnap_core::Error::retry_after(self)
+    }
+
+    fn is_throttled(&self) -> bool {
+        self.is_throttling()
+    }
+}
+
+/// Concurrency limit that adapts to backend throttling: halved when the
+/// backend throttles, raised by one again after a limit's worth of
+/// successful operations (additive increase, multiplicative decrease).
+#[derive(Debug)]
+pub struct AdaptiveConcurrency {
+    max: usize,
+    state: Mutex<ConcurrencyState>,
+    released: Notify,
+}
+
+#[derive(Debug)]
+struct ConcurrencyState {
+    limit: usize,
+    in_flight: usize,
+    successes: usize,
+    last_decrease: Option<Instant>,
+}
+
+impl AdaptiveConcurrency {
+    /// Minimum time between two decreases, so one burst of throttled
+    /// requests lowers the limit once rather than once per request.
+    const DECREASE_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            max,
+            state: Mutex::new(ConcurrencyState {
+                limit: max,
+                in_flight: 0,
+                successes: 0,
+                last_decrease: None,
+            }),
+            released: Notify::new(),
+        }
+    }
+
+    /// Current limit on concurrent operations.
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    /// Waits until an operation may start. The slot is freed when the
+    /// returned permit is dropped.
+    pub async fn acquire(&self) -> ConcurrencyPermit<'_> {
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.in_flight < state.limit {
+                    state.in_flight += 1;
+                    return ConcurrencyPermit { limiter: self };
+                }
+            }
+            released.await;
+        }
+    }
+
+    pub fn on_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.successes += 1;
+        if state.successes >= state.limit && state.limit < self.max {
+            state.limit += 1;
+            state.successes = 0;
+            drop(state);
+            self.released.notify_waiters();
+        }
+    }
+
+    pub fn on_throttle(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .last_decrease
+            .is_some_and(|at| at.elapsed() < Self::DECREASE_INTERVAL)
+        {
+            return;
+        }
+        let limit = (state.limit / 2).max(1);
+        if limit < state.limit {
+            info!(
+                from = state.limit,
+                to = limit,
+                "Backend is throttling, reducing concurrency"
+            );
+        }
+        state.limit = limit;
+        state.successes = 0;
+        state.last_decrease = Some(Instant::now());
+    }
+}
+
+/// A running operation's slot in an [`AdaptiveConcurrency`] limit.
+pub struct ConcurrencyPermit<'a> {
+    limiter: &'a AdaptiveConcurrency,
+}
+
+impl Drop for ConcurrencyPermit<'_> {
+    fn drop(&mut self) {
+        self.limiter.state.lock().unwrap().in_flight -= 1;
+        self.limiter.released.notify_waiters();
+    }
 }
 
 /// Retry a future operation with exponential backoff
+///
+/// A `Retry-After` delay sent with a retryable error replaces the backoff
+/// when it is longer. Throttling errors also lower the config's adaptive
+/// concurrency limit, if it has one. While the config's circuit breaker is
+/// open, the operation fails with `Error::BackendUnavailable` without being
+/// attempted.
 pub async fn retry_with_backoff<F, Fut, T, E>(
     config: &RetryConfig,
     operation_name: &str,
@@ -112,12 +287,46 @@ pub async fn retry_with_backoff<F, Fut, T, E>(
 where
     F: FnMut() -> Fut,
     Fut: Future<Output = Result<T, E>>,
-    E: Retryable + std::fmt::Display,
+    E: Retryable + std::fmt::Display + From<ghostsnap_core::Error>,
 {
     let mut last_error = None;
+    metrics::record_operation(operation_name);
 
     for attempt in 0..config.max_attempts {
-        match operation().await {
+        if let Some(breaker) = &config.circuit_breaker
+            && let Err(unavailable) = breaker.check()
+        {
+            debug!(
+                operation = operation_name,
+                "Circuit breaker open, failing immediately"
+            );
+            metrics::record_failure(operation_name);
+            return Err(unavailable.into());
+        }
+
+        let permit = match &config.concurrency {
+            Some(limiter) => Some(limiter.acquire().await),
+            None => None,
+        };
+        let result = operation().await;
+        drop(permit);
+
+        if let Some(breaker) = &config.circuit_breaker {
+            match &result {
+                Err(error) if error.is_retryable() => breaker.record_failure(error),
+                // A definite answer, even an error, means the backend is up
+                _ => breaker.record_success(),
+            }
+        }
+        if let Some(limiter) = &config.concurrency {
+            match &result {
+                Ok(_) => limiter.on_success(),
+                Err(error) if error.is_throttled() => limiter.on_throttle(),
+                Err(_) => {}
+            }
+        }
+
+        match result {
             Ok(result) => {
                 if attempt > 0 {
                     debug!(
@@ -142,15 +351,21 @@ where
 
                 // Don't sleep after the last attempt
                 if attempt < config.max_attempts - 1 {
-                    let backoff = config.backoff_duration(attempt);
+                    let mut backoff = config.backoff_duration(attempt);
+                    let error = last_error.as_ref().unwrap();
+                    if let Some(retry_after) = error.retry_after() {
+                        backoff = backoff.max(retry_after.min(MAX_RETRY_AFTER));
+                    }
                     warn!(
                         operation = operation_name,
                         attempt = attempt + 1,
                         max_attempts = config.max_attempts,
                         backoff_ms = backoff.as_millis(),
-                        error = %last_error.as_ref().unwrap(),
+                        throttled = error.is_throttled(),
+                        error = %error,
                         "Operation failed, retrying after backoff"
                     );
+                    metrics::record_retry(operation_name, backoff, error.is_throttled());
                     sleep(backoff).await;
                 }
             }
@@ -165,6 +380,7 @@ where
         error = %error,
         "Operation failed after all retry attempts"
     );
+    metrics::record_failure(operation_name);
     Err(error)
 }
 
@@ -185,6 +401,7 @@ mod tests {
             max_backoff: Duration::from_millis(50),
             backoff_multiplier: 2.0,
             jitter: false,
+            ..Default::default()
         };
 
         let result = retry_with_backoff(&config, "test_operation", || {
@@ -218,6 +435,7 @@ mod tests {
             max_backoff: Duration::from_millis(50),
             backoff_multiplier: 2.0,
             jitter: false,
+            ..Default::default()
         };
 
         let result = retry_with_backoff(&config, "test_operation", || {
@@ -255,6 +473,111 @@ mod tests {
         assert_eq!(attempts.load(Ordering::SeqCst), 1); // Should not retry
     }
 
+    #[tokio::test]
+    async fn test_retry_after_and_throttling() {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let attempts_clone = attempts.clone();
+
+        let config = RetryConfig {
+            initial_backoff: Duration::from_millis(1),
+            jitter: false,
+            ..Default::default()
+        }
+        .with_adaptive_concurrency(8);
+
+        let started = Instant::now();
+        let result = retry_with_backoff(&config, "test_throttled_operation", || {
+            let attempts = attempts_clone.clone();
+            async move {
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Err(ghostsnap_core::Error::BackendStatus {
+                        status: 503,
+                        message: "SlowDown".to_string(),
+                        retry_after: Some(Duration::from_millis(200)),
+                    })
+                } else {
+                    Ok(1)
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 1);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        let retries = &metrics::retry_metrics().operations["test_throttled_operation"];
+        assert_eq!((retries.operations, retries.retries), (1, 1));
+        assert_eq!(retries.throttled, 1);
+        assert!(retries.backoff >= Duration::from_millis(200));
+        // Halved by the throttle; one success is not enough to raise it
+        assert_eq!(config.concurrency.as_ref().unwrap().limit(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_stops_retries() {
+        let attempts = Arc::new(AtomicU32::new(0));
+
+        let config = RetryConfig {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(1),
+            jitter: false,
+            ..Default::default()
+        }
+        .with_circuit_breaker(CircuitBreakerConfig {
+            failure_threshold: 3,
+            cool_down: Duration::from_secs(60),
+        });
+
+        let operation = || {
+            let attempts = attempts.clone();
+            async move {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err::<i32, _>(ghostsnap_core::Error::Io(std::io::Error::other(
+                    "Connection refused",
+                )))
+            }
+        };
+
+        let result = retry_with_backoff(&config, "test_operation", operation).await;
+        assert!(matches!(
+            result,
+            Err(ghostsnap_core::Error::BackendUnavailable { failures: 3, .. })
+        ));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // Later operations are not attempted at all
+        let result = retry_with_backoff(&config, "test_operation", operation).await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_concurrency() {
+        let limiter = AdaptiveConcurrency::new(4);
+        let first = limiter.acquire().await;
+        let _second = limiter.acquire().await;
+
+        limiter.on_throttle();
+        assert_eq!(limiter.limit(), 2);
+        // Repeated throttling within the interval counts once
+        limiter.on_throttle();
+        assert_eq!(limiter.limit(), 2);
+
+        // Both slots are taken until one is released
+        let waiter = limiter.acquire();
+        tokio::pin!(waiter);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), waiter.as_mut())
+                .await
+                .is_err()
+        );
+        drop(first);
+        let _third = waiter.await;
+
+        limiter.on_success();
+        limiter.on_success();
+        assert_eq!(limiter.limit(), 3);
+    }
+
     #[test]
     fn test_backoff_duration_calculation() {
         let config = RetryConfig {
@@ -1,12 +1,16 @@
 use crate::backend::{Backend, BackendType, ObjectInfo};
 use crate::retry::{RetryConfig, retry_with_backoff};
 use async_trait::async_trait;
-use aws_config::BehaviorVersion;
+use aws_config::{BehaviorVersion, SdkConfig};
 use aws_sdk_s3::Client;
+use aws_sdk_s3::config::{RequestChecksumCalculation, ResponseChecksumValidation};
 use aws_sdk_s3::primitives::ByteStream;
-use aws_sdk_s3::types::ServerSideEncryption;
+use aws_sdk_s3::types::{ChecksumAlgorithm, ChecksumMode, ServerSideEncryption, StorageClass};
 use bytes::Bytes;
-use ghostsnap_core::{Error, Result};
+use ghostsnap_core::storage::{
+    S3Credentials, s3_config_loader, s3_error, s3_http_client, verify_s3_sha256,
+};
+use ghostsnap_core::{Error, ProxyConfig, Result, S3Checksum, S3StorageClasses, S3Tls};
 
 /// Server-Side Encryption configuration for S3
 #[derive(Debug, Clone, Default)]
@@ -33,20 +37,16 @@ pub struct S3Backend {
     prefix: String,
     retry_config: RetryConfig,
     sse_config: S3SseConfig,
+    storage_classes: S3StorageClasses,
+    checksum: S3Checksum,
+    tls: S3Tls,
+    proxy: Option<ProxyConfig>,
 }
 
 impl S3Backend {
     pub async fn new(bucket: String, prefix: String) -> Result<Self> {
         let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
-        let client = Client::new(&config);
-
-        Ok(Self {
-            client,
-            bucket,
-            prefix,
-            retry_config: RetryConfig::default(),
-            sse_config: S3SseConfig::default(),
-        })
+        Self::from_sdk_config(&config, bucket, prefix)
     }
 
     pub async fn with_endpoint(bucket: String, prefix: String, endpoint: String) -> Result<Self> {
@@ -54,15 +54,54 @@ impl S3Backend {
             .endpoint_url(endpoint)
             .load()
             .await;
-        let client = Client::new(&config);
+        Self::from_sdk_config(&config, bucket, prefix)
+    }
 
-        Ok(Self {
-            client,
+    /// Creates a backend that authenticates with explicit credentials
+    /// (anonymous, static keys, web identity and/or an assumed role) instead
+    /// of the default AWS credential chain.
+    pub async fn with_credentials(
+        bucket: String,
+        prefix: String,
+        endpoint: Option<String>,
+        region: Option<String>,
+        credentials: &S3Credentials,
+    ) -> Result<Self> {
+        let config = s3_config_loader(endpoint.as_deref(), region.as_deref(), credentials)
+            .await?
+            .load()
+            .await;
+        Self::from_sdk_config(&config, bucket, prefix)
+    }
+
+    /// Builds the backend from an SDK config, applying the proxy from the
+    /// environment.
+    fn from_sdk_config(config: &SdkConfig, bucket: String, prefix: String) -> Result<Self> {
+        let backend = Self {
+            client: Client::new(config),
             bucket,
             prefix,
             retry_config: RetryConfig::default(),
             sse_config: S3SseConfig::default(),
-        })
+            storage_classes: S3StorageClasses::default(),
+            checksum: S3Checksum::default(),
+            tls: S3Tls::default(),
+            proxy: ProxyConfig::from_env(),
+        };
+        backend.rebuild_http_client()
+    }
+
+    fn rebuild_http_client(mut self) -> Result<Self> {
+        if let Some(http_client) = s3_http_client(&self.tls, self.proxy.as_ref())? {
+            let config = self
+                .client
+                .config()
+                .to_builder()
+                .http_client(http_client)
+                .build();
+            self.client = Client::from_conf(config);
+        }
+        Ok(self)
     }
 
     pub fn with_retry_config(mut self, retry_config: RetryConfig) -> Self {
@@ -99,6 +138,40 @@ impl S3Backend {
         &self.sse_config
     }
 
+    /// Configure storage classes for pack files and metadata objects
+    pub fn with_storage_classes(mut self, storage_classes: S3StorageClasses) -> Self {
+        self.storage_classes = storage_classes;
+        self
+    }
+
+    /// Configure the integrity checksum sent with uploads and checked on reads
+    pub fn with_checksum(mut self, checksum: S3Checksum) -> Self {
+        if checksum == S3Checksum::Off {
+            let config = self
+                .client
+                .config()
+                .to_builder()
+                .request_checksum_calculation(RequestChecksumCalculation::WhenRequired)
+                .response_checksum_validation(ResponseChecksumValidation::WhenRequired)
+                .build();
+            self.client = Client::from_conf(config);
+        }
+        self.checksum = checksum;
+        self
+    }
+
+    /// Configure a custom CA bundle, client certificate or hostname checking
+    pub fn with_tls(mut self, tls: &S3Tls) -> Result<Self> {
+        self.tls = tls.clone();
+        self.rebuild_http_client()
+    }
+
+    /// Route requests through `proxy` instead of the proxy from the environment
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> Result<Self> {
+        self.proxy = Some(proxy);
+        self.rebuild_http_client()
+    }
+
     fn full_key(&self, path: &str) -> String {
         if self.prefix.is_empty() {
             path.to_string()
@@ -135,7 +208,7 @@ impl Backend for S3Backend {
                 if e.to_string().contains("NotFound") {
                     Ok(false)
                 } else {
-                    Err(Error::Backend(format!("Failed to check existence: {}", e)))
+                    Err(s3_error("Failed to check existence", &e))
                 }
             }
         }
@@ -146,15 +219,17 @@ impl Backend for S3Backend {
         let bucket = self.bucket.clone();
         let key = self.full_key(path);
         let path_copy = path.to_string();
+        let checksum_mode = (self.checksum == S3Checksum::Sha256).then_some(ChecksumMode::Enabled);
 
         retry_with_backoff(&self.retry_config, "s3_read", || async {
             let response = client
                 .get_object()
                 .bucket(&bucket)
                 .key(&key)
+                .set_checksum_mode(checksum_mode.clone())
                 .send()
                 .await
-                .map_err(|e| Error::Backend(format!("Failed to read {}: {}", path_copy, e)))?;
+                .map_err(|e| s3_error(format_args!("Failed to read {}", path_copy), &e))?;
 
             let data = response
                 .body
@@ -173,6 +248,8 @@ impl Backend for S3Backend {
         let key = self.full_key(path);
         let path_copy = path.to_string();
         let sse_config = self.sse_config.clone();
+        let storage_class = self.storage_classes.for_path(path).map(StorageClass::from);
+        let sha256 = self.checksum == S3Checksum::Sha256;
 
         retry_with_backoff(&self.retry_config, "s3_write", || async {
             let body = ByteStream::from(data.to_vec());
@@ -193,10 +270,21 @@ impl Backend for S3Backend {
                 }
             }
 
-            request
+            if let Some(ref class) = storage_class {
+                request = request.storage_class(class.clone());
+            }
+
+            if sha256 {
+                request = request.checksum_algorithm(ChecksumAlgorithm::Sha256);
+            }
+
+            let output = request
                 .send()
                 .await
-                .map_err(|e| Error::Backend(format!("Failed to write {}: {}", path_copy, e)))?;
+                .map_err(|e| s3_error(format_args!("Failed to write {}", path_copy), &e))?;
+            if sha256 {
+                verify_s3_sha256(&path_copy, &data, output.checksum_sha256())?;
+            }
 
             Ok(())
         })
@@ -210,7 +298,7 @@ impl Backend for S3Backend {
             .key(self.full_key(path))
             .send()
             .await
-            .map_err(|e| Error::Backend(format!("Failed to delete {}: {}", path, e)))?;
+            .map_err(|e| s3_error(format_args!("Failed to delete {}", path), &e))?;
 
         Ok(())
     }
@@ -234,7 +322,7 @@ impl Backend for S3Backend {
             let response = request
                 .send()
                 .await
-                .map_err(|e| Error::Backend(format!("Failed to list: {}", e)))?;
+                .map_err(|e| s3_error("Failed to list", &e))?;
 
             if let Some(contents) = response.contents {
                 for object in contents {
@@ -269,7 +357,7 @@ impl Backend for S3Backend {
             .key(self.full_key(path))
             .send()
             .await
-            .map_err(|e| Error::Backend(format!("Failed to stat {}: {}", path, e)))?;
+            .map_err(|e| s3_error(format_args!("Failed to stat {}", path), &e))?;
 
         let size = response.content_length.unwrap_or(0) as u64;
         let modified = response
@@ -287,6 +375,56 @@ impl Backend for S3Backend {
         })
     }
 
+    /// `CopyObject` within the bucket. A single request copies up to 5 GiB,
+    /// well above the pack size.
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let client = self.client.clone();
+        let bucket = self.bucket.clone();
+        let copy_source = format!("{}/{}", self.bucket, self.full_key(from));
+        let key = self.full_key(to);
+        let description = format!("{} to {}", from, to);
+        let sse_config = self.sse_config.clone();
+        let storage_class = self.storage_classes.for_path(to).map(StorageClass::from);
+        let checksum_algorithm =
+            (self.checksum == S3Checksum::Sha256).then_some(ChecksumAlgorithm::Sha256);
+
+        retry_with_backoff(&self.retry_config, "s3_copy", || async {
+            let mut request = client
+                .copy_object()
+                .bucket(&bucket)
+                .key(&key)
+                .copy_source(&copy_source);
+
+            // The copy gets the bucket defaults unless SSE and class are repeated
+            match sse_config.sse_type {
+                SseType::None => {}
+                SseType::Aes256 => {
+                    request = request.server_side_encryption(ServerSideEncryption::Aes256);
+                }
+                SseType::Kms => {
+                    request = request.server_side_encryption(ServerSideEncryption::AwsKms);
+                    if let Some(ref key_id) = sse_config.kms_key_id {
+                        request = request.ssekms_key_id(key_id);
+                    }
+                }
+            }
+
+            if let Some(ref class) = storage_class {
+                request = request.storage_class(class.clone());
+            }
+
+            request = request.set_checksum_algorithm(checksum_algorithm.clone());
+
+            request
+                .send()
+                .await
+                .map_err(|e| s3_error(format_args!("Failed to copy {}", description), &e))?;
+
+            Ok(())
+        })
+        .await
+    }
+
     fn backend_type(&self) -> BackendType {
         BackendType::S3
     }
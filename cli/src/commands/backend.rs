@@ -0,0 +1,381 @@
+//! Backend command for storage-specific repository settings.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap backend tier show                       # Packs per access tier
+//! ghostsnap backend tier set cool --all             # Demote every pack
+//! ghostsnap backend tier set hot 3fa2 --priority high
+//! ghostsnap backend tier rules --tier data=archive  # Tier for new packs
+//! ghostsnap backend tier rules --clear
+//! ghostsnap backend replication-status --replica s3:backup-replica/repo
+//! ```
+
+use anyhow::{Result, anyhow};
+use clap::{Args, Subcommand};
+use ghostsnap_core::storage::{RepositoryLocation, storage_for_location};
+use ghostsnap_core::{
+    AccessTier, AzureAccessTiers, PackID, RehydratePriority, ReplicaObjectStatus, Repository,
+    check_replica,
+};
+use std::collections::BTreeMap;
+use tracing::info;
+
+/// Backend command for managing how the storage service keeps repository data.
+#[derive(Args)]
+pub struct BackendCommand {
+    #[command(subcommand)]
+    subcommand: BackendSubcommand,
+}
+
+#[derive(Subcommand)]
+enum BackendSubcommand {
+    /// Manage Azure blob access tiers of pack files.
+    #[command(subcommand)]
+    Tier(TierSubcommand),
+
+    /// Check that recently written objects exist on replica locations.
+    ReplicationStatus(ReplicationStatusCommand),
+}
+
+#[derive(Subcommand)]
+enum TierSubcommand {
+    /// Show how many packs are in each access tier.
+    Show(TierShowCommand),
+
+    /// Move packs to another access tier (rehydrates archived packs).
+    Set(TierSetCommand),
+
+    /// Show or change the tiers applied to newly written blobs.
+    Rules(TierRulesCommand),
+}
+
+impl BackendCommand {
+    pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+
+        let password = crate::password::repository_password(cli)?;
+
+        info!("Opening repository at: {}", repo_location.display());
+        let mut repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+
+        match &self.subcommand {
+            BackendSubcommand::Tier(TierSubcommand::Show(cmd)) => cmd.run(&repo).await,
+            BackendSubcommand::Tier(TierSubcommand::Set(cmd)) => cmd.run(&repo).await,
+            BackendSubcommand::Tier(TierSubcommand::Rules(cmd)) => cmd.run(&mut repo).await,
+            BackendSubcommand::ReplicationStatus(cmd) => cmd.run(cli, &repo).await,
+        }
+    }
+}
+
+// === Tier Show Command ===
+
+#[derive(Args)]
+struct TierShowCommand {
+    /// List the tier of every pack
+    #[arg(long)]
+    packs: bool,
+}
+
+impl TierShowCommand {
+    async fn run(&self, repo: &Repository) -> Result<()> {
+        let pack_ids = repo.list_packs().await?;
+
+        let mut tiers: BTreeMap<String, usize> = BTreeMap::new();
+        let mut rehydrating = 0;
+        for pack_id in &pack_ids {
+            let Some(status) = repo.pack_access_tier(pack_id).await? else {
+                return Err(unsupported(repo));
+            };
+            let tier = status
+                .tier
+                .map_or_else(|| "unknown".to_string(), |tier| tier.to_string());
+            if self.packs {
+                match status.rehydrating_to {
+                    Some(target) => println!("{}  {} (rehydrating to {})", pack_id, tier, target),
+                    None => println!("{}  {}", pack_id, tier),
+                }
+            }
+            if status.rehydrating_to.is_some() {
+                rehydrating += 1;
+            }
+            *tiers.entry(tier).or_default() += 1;
+        }
+
+        println!("Packs by access tier ({} total):", pack_ids.len());
+        for (tier, count) in &tiers {
+            println!("  {:<8} {}", tier, count);
+        }
+        if rehydrating > 0 {
+            println!("  ({} rehydrating)", rehydrating);
+        }
+
+        Ok(())
+    }
+}
+
+// === Tier Set Command ===
+
+#[derive(Args)]
+struct TierSetCommand {
+    /// Target tier: hot, cool, cold or archive
+    tier: AccessTier,
+
+    /// Packs to move (full or short IDs)
+    #[arg(required_unless_present = "all")]
+    packs: Vec<String>,
+
+    /// Move every pack in the repository
+    #[arg(long, conflicts_with = "packs")]
+    all: bool,
+
+    /// Rehydration priority when moving archived packs: standard or high
+    #[arg(long, default_value = "standard")]
+    priority: RehydratePriority,
+}
+
+impl TierSetCommand {
+    async fn run(&self, repo: &Repository) -> Result<()> {
+        let all_packs = repo.list_packs().await?;
+        let pack_ids = if self.all {
+            all_packs
+        } else {
+            self.packs
+                .iter()
+                .map(|prefix| resolve_pack(&all_packs, prefix))
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        let mut moved = 0;
+        let mut rehydrating = 0;
+        for pack_id in &pack_ids {
+            let Some(status) = repo.pack_access_tier(pack_id).await? else {
+                return Err(unsupported(repo));
+            };
+            if status.tier == Some(self.tier) {
+                continue;
+            }
+            repo.set_pack_access_tier(pack_id, self.tier, self.priority)
+                .await?;
+            if !status.is_readable() {
+                rehydrating += 1;
+            }
+            moved += 1;
+        }
+
+        println!(
+            "Moved {} of {} pack(s) to {}",
+            moved,
+            pack_ids.len(),
+            self.tier
+        );
+        if rehydrating > 0 {
+            println!(
+                "{} pack(s) were archived and are being rehydrated ({} priority); they stay unreadable until that completes",
+                rehydrating, self.priority
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn resolve_pack(pack_ids: &[PackID], prefix: &str) -> Result<PackID> {
+    let mut matches = pack_ids.iter().filter(|id| id.as_str().starts_with(prefix));
+    match (matches.next(), matches.next()) {
+        (Some(id), None) => Ok(id.clone()),
+        (Some(_), Some(_)) => Err(anyhow!("Ambiguous pack ID prefix: {}", prefix)),
+        (None, _) => Err(anyhow!("Pack not found: {}", prefix)),
+    }
+}
+
+// === Tier Rules Command ===
+
+#[derive(Args)]
+struct TierRulesCommand {
+    /// Tier for an object type, as TYPE=TIER with TYPE `data` (pack files)
+    /// or `metadata` (everything else). Repeatable.
+    #[arg(long = "tier", value_name = "TYPE=TIER")]
+    tiers: Vec<String>,
+
+    /// Remove all rules (new blobs use the account's default tier)
+    #[arg(long, conflicts_with = "tiers")]
+    clear: bool,
+}
+
+impl TierRulesCommand {
+    async fn run(&self, repo: &mut Repository) -> Result<()> {
+        let current = repo
+            .azure_transport()
+            .map(|config| config.access_tiers.clone())
+            .ok_or_else(|| unsupported(repo))?;
+
+        if self.tiers.is_empty() && !self.clear {
+            print_rules(&current);
+            return Ok(());
+        }
+
+        let mut rules = if self.clear {
+            AzureAccessTiers::default()
+        } else {
+            current
+        };
+        for assignment in &self.tiers {
+            rules.set(assignment)?;
+        }
+
+        repo.set_azure_access_tiers(rules.clone()).await?;
+        print_rules(&rules);
+        if rules.data_is_archived() {
+            println!(
+                "New packs go to the Archive tier: restores will have to rehydrate them first"
+            );
+        }
+        println!("Existing blobs keep their tier; use `backend tier set` to move them");
+
+        Ok(())
+    }
+}
+
+// === Replication Status Command ===
+
+#[derive(Args)]
+struct ReplicationStatusCommand {
+    /// Replica location, in the same syntax as --repo. Repeatable.
+    #[arg(long = "replica", value_name = "LOCATION", required = true)]
+    replicas: Vec<String>,
+
+    /// Endpoint URL for S3 replicas on another site (e.g. a second MinIO
+    /// cluster); defaults to the primary's AWS_ENDPOINT_URL
+    #[arg(long, value_name = "URL")]
+    replica_endpoint: Option<String>,
+
+    /// Number of newest snapshots whose objects are sampled
+    #[arg(long, default_value_t = 3)]
+    snapshots: usize,
+
+    /// Maximum number of packs sampled
+    #[arg(long, default_value_t = 50)]
+    max_packs: usize,
+
+    /// Objects younger than this may still be in flight and are reported as
+    /// pending instead of failing the check (e.g. 15m, 1h)
+    #[arg(long, default_value = "15m")]
+    max_lag: String,
+}
+
+impl ReplicationStatusCommand {
+    async fn run(&self, cli: &crate::Cli, repo: &Repository) -> Result<()> {
+        let max_lag = chrono::Duration::from_std(crate::config::parse_duration(&self.max_lag)?)?;
+        let paths = repo.recent_objects(self.snapshots, self.max_packs).await?;
+        if paths.is_empty() {
+            println!("No snapshots to sample");
+            return Ok(());
+        }
+
+        let now = chrono::Utc::now();
+        let mut lagging = 0;
+        for replica in &self.replicas {
+            let location = self.replica_location(cli, replica)?;
+            let storage = storage_for_location(&location)
+                .await
+                .map_err(|e| anyhow!("Failed to open replica {}: {}", replica, e))?;
+            let report = check_replica(repo, storage.as_ref(), &paths).await?;
+
+            let present = report.checks.len() - report.out_of_sync().count();
+            println!(
+                "Replica {}: {}/{} sampled objects present",
+                location.display(),
+                present,
+                report.checks.len()
+            );
+            for check in report.out_of_sync() {
+                let age = now - check.written_at;
+                let problem = match &check.status {
+                    ReplicaObjectStatus::Missing if age < max_lag => "pending",
+                    ReplicaObjectStatus::Missing => "MISSING",
+                    ReplicaObjectStatus::SizeMismatch { .. } => "SIZE MISMATCH",
+                    ReplicaObjectStatus::Error(_) => "ERROR",
+                    ReplicaObjectStatus::Present => continue,
+                };
+                let detail = match &check.status {
+                    ReplicaObjectStatus::SizeMismatch { primary, replica } => {
+                        format!(" ({} bytes, replica has {})", primary, replica)
+                    }
+                    ReplicaObjectStatus::Error(e) => format!(" ({})", e),
+                    _ => String::new(),
+                };
+                println!(
+                    "  {:<13} {} written {} ago{}",
+                    problem,
+                    check.path,
+                    format_age(age),
+                    detail
+                );
+            }
+
+            match report.lag(now) {
+                Some(lag) if lag >= max_lag => {
+                    println!("  Replication lag: at least {}", format_age(lag));
+                    lagging += 1;
+                }
+                Some(_) => println!(
+                    "  In sync apart from objects written in the last {}",
+                    self.max_lag
+                ),
+                None => println!("  In sync"),
+            }
+        }
+
+        if lagging > 0 {
+            return Err(anyhow!(
+                "{} of {} replicas are behind by more than {}",
+                lagging,
+                self.replicas.len(),
+                self.max_lag
+            ));
+        }
+        Ok(())
+    }
+
+    fn replica_location(&self, cli: &crate::Cli, replica: &str) -> Result<RepositoryLocation> {
+        let mut location = RepositoryLocation::parse(replica)?;
+        if let (RepositoryLocation::S3(s3), Some(endpoint)) =
+            (&mut location, &self.replica_endpoint)
+        {
+            s3.endpoint = Some(endpoint.clone());
+        }
+        crate::commands::apply_proxy(cli, location)
+    }
+}
+
+/// Formats a duration as e.g. `3d 4h`, `2h 5m` or `40s`.
+fn format_age(age: chrono::Duration) -> String {
+    let secs = age.num_seconds().max(0);
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+fn print_rules(rules: &AzureAccessTiers) {
+    if rules.is_empty() {
+        println!("No access tier rules (new blobs use the account's default tier)");
+        return;
+    }
+    println!("Access tiers for new blobs:");
+    let describe = |tier: Option<AccessTier>| {
+        tier.map_or_else(|| "account default".to_string(), |tier| tier.to_string())
+    };
+    println!("  data:     {}", describe(rules.data));
+    println!("  metadata: {}", describe(rules.metadata));
+}
+
+fn unsupported(repo: &Repository) -> anyhow::Error {
+    anyhow!(
+        "Access tiers are only supported for Azure repositories, not {}",
+        repo.location().display()
+    )
+}
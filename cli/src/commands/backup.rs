@@ -1,16 +1,19 @@
+use crate::filter::PathFilter;
 use anyhow::{Result, anyhow};
-use clap::Args;
-use ghostsnap_core::pack::PackFile;
-use ghostsnap_core::pack::PackManager;
+use chrono::{DateTime, Utc};
+use clap::{Args, FromArgMatches};
+use ghostsnap_core::repository::CLOCK_SKEW_TOLERANCE;
 use ghostsnap_core::snapshot::{Snapshot, Tree};
-use ghostsnap_core::{LockManager, LockType, NodeType, Repository, chunker::Chunker, types::TreeNode};
-use globset::{Glob, GlobSet, GlobSetBuilder};
+use ghostsnap_core::{
+    Access, BackupManifest, BandwidthSchedule, FsSource, LockType, NodeType, RateLimiter,
+    SnapshotFilter, SnapshotID, SourceObserver, SourceWriter, StoredContents, types::TreeNode,
+};
 use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
-use std::collections::HashMap;
-use std::io::{self, Write};
+use std::collections::{BTreeMap, HashMap};
+use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Instant;
-use tokio::fs;
 use tracing::{debug, info, warn};
 use walkdir::WalkDir;
 
@@ -25,22 +28,49 @@ pub struct BackupCommand {
     #[arg(long, short = 'e', help = "Exclude patterns (glob syntax)")]
     exclude: Vec<String>,
 
+    #[arg(
+        long,
+        help = "Read exclude patterns from a file (one per line, # for comments); repeatable"
+    )]
+    exclude_file: Vec<PathBuf>,
+
     #[arg(long, help = "Exclude if file present in directory")]
     exclude_if_present: Vec<String>,
 
+    #[arg(
+        long,
+        help = "Only back up files matching these patterns (glob syntax); excludes still apply"
+    )]
+    include: Vec<String>,
+
     #[arg(long, short = 'x', help = "Stay on same filesystem")]
     one_file_system: bool,
 
+    #[arg(
+        long,
+        help = "Back up the files symlinks point to instead of the links themselves"
+    )]
+    follow_symlinks: bool,
+
     #[arg(long, short = 'n', help = "Dry run - don't actually backup")]
-    dry_run: bool,
+    pub dry_run: bool,
 
     #[arg(long, help = "Parent snapshot ID for incremental backup")]
-    parent: Option<String>,
+    parent: Option<SnapshotID>,
 
     #[arg(long, help = "Hostname override")]
     hostname: Option<String>,
 
-    #[arg(long, help = "Don't backup extended attributes")]
+    #[arg(
+        long,
+        help = "Snapshot time override (RFC 3339, e.g. 2021-06-01T02:00:00Z) for importing historical data"
+    )]
+    time: Option<String>,
+
+    #[arg(
+        long,
+        help = "Don't backup extended attributes (file capabilities are still backed up)"
+    )]
     no_xattr: bool,
 
     #[arg(
@@ -49,11 +79,126 @@ pub struct BackupCommand {
     )]
     max_file_size: Option<String>,
 
+    #[arg(
+        long,
+        help = "Stop if the files to back up total more than this (e.g., 500G); asks when run interactively"
+    )]
+    max_total_size: Option<String>,
+
+    #[arg(
+        long,
+        help = "Stop if there are more files than this to back up; asks when run interactively"
+    )]
+    max_file_count: Option<u64>,
+
     #[arg(long, help = "Don't detect and preserve hardlinks")]
     no_hardlinks: bool,
+
+    #[arg(
+        long,
+        help = "Re-upload every chunk instead of deduplicating against existing data (self-contained snapshot)"
+    )]
+    standalone: bool,
+
+    #[arg(
+        long,
+        help = "Upload bandwidth limit outside of any window (e.g., 10M, 512K)"
+    )]
+    limit_upload: Option<String>,
+
+    #[arg(
+        long,
+        help = "Time-of-day bandwidth window HH:MM-HH:MM=RATE (e.g., 08:00-20:00=10M); repeatable"
+    )]
+    bandwidth_window: Vec<String>,
+
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        value_parser = clap::value_parser!(i32).range(-20..=19),
+        help = "CPU scheduling priority (nice value, 19 = lowest)"
+    )]
+    nice: Option<i32>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "I/O scheduling class (best-effort, idle); Linux only"
+    )]
+    io_class: Option<crate::priority::IoClass>,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Maximum files opened or stat'ed per second while scanning and chunking"
+    )]
+    max_read_ops: Option<u32>,
+
+    #[arg(
+        long,
+        short = 'j',
+        default_value_t = 4,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Number of files to read and chunk, and packs to upload, at once"
+    )]
+    jobs: u64,
+
+    #[arg(
+        long,
+        help = "Target size of new packs (e.g., 16M, 256M); defaults to the repository's pack-size setting or the backend's default"
+    )]
+    pack_size: Option<String>,
+
+    #[arg(
+        long,
+        help = "Write a manifest of the snapshot (paths, sizes, BLAKE3 hashes) to this file or directory"
+    )]
+    manifest: Option<PathBuf>,
+
+    #[arg(
+        long,
+        env = "GHOSTSNAP_MANIFEST_KEY",
+        requires = "manifest",
+        help = "Ed25519 key file to sign the manifest with (see `ghostsnap manifest keygen`)"
+    )]
+    manifest_key: Option<PathBuf>,
+
+    /// Directory that node names are relative to instead of each backed-up
+    /// path, so that several paths don't overlap in the snapshot
+    #[arg(skip)]
+    base: Option<PathBuf>,
+
+    /// Snapshots whose newest is the parent when `--parent` isn't given
+    #[arg(skip)]
+    parent_filter: Option<SnapshotFilter>,
 }
 
 impl BackupCommand {
+    /// A backup as given by command-line `args` (paths and options), for
+    /// commands that run a preset backup.
+    pub fn from_args<I, S>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<std::ffi::OsString> + Clone,
+    {
+        let command = Self::augment_args(clap::Command::new("backup").no_binary_name(true));
+        let matches = command.try_get_matches_from(args)?;
+        Ok(Self::from_arg_matches(&matches)?)
+    }
+
+    /// Names nodes relative to `base`, which must contain every path.
+    pub fn with_base(mut self, base: PathBuf) -> Self {
+        self.base = Some(base);
+        self
+    }
+
+    /// Takes the newest snapshot matching `filter`, if any, as the parent
+    /// unless `--parent` is given.
+    pub fn with_latest_parent(mut self, filter: SnapshotFilter) -> Self {
+        self.parent_filter = Some(filter);
+        self
+    }
+
     /// Parses a human-readable size string (e.g., "1G", "500M", "100K") into bytes.
     fn parse_size(&self, size_str: &str) -> Result<u64> {
         let size_str = size_str.trim().to_uppercase();
@@ -82,15 +227,7 @@ impl BackupCommand {
     pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
         let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
 
-        let password = cli
-            .password
-            .clone()
-            .or_else(|| {
-                print!("Enter repository password: ");
-                io::stdout().flush().ok()?;
-                rpassword::read_password().ok()
-            })
-            .ok_or_else(|| anyhow!("Password required"))?;
+        let password = crate::password::repository_password(cli)?;
 
         // Parse max file size if provided
         let max_file_size = match &self.max_file_size {
@@ -98,12 +235,52 @@ impl BackupCommand {
             None => None,
         };
 
+        let limits = crate::commands::BackupLimits::parse(
+            self.max_total_size.as_deref(),
+            self.max_file_count,
+        )?;
+        let pack_size = self
+            .pack_size
+            .as_deref()
+            .map(crate::commands::parse_pack_size)
+            .transpose()?;
+
+        let snapshot_time = match &self.time {
+            Some(time) => Some(parse_snapshot_time(time)?),
+            None => None,
+        };
+
+        let manifest_key = match &self.manifest {
+            Some(path) if !self.dry_run => {
+                crate::commands::prepare_manifest(path, self.manifest_key.as_deref())?
+            }
+            _ => None,
+        };
+
+        crate::priority::lower_priority(self.nice, self.io_class)?;
+        let read_limiter = crate::priority::read_ops_limiter(self.max_read_ops);
+
+        let schedule =
+            BandwidthSchedule::parse(self.limit_upload.as_deref(), &self.bandwidth_window)?;
+
         info!("Opening repository at: {}", repo_location.display());
-        let repo = Repository::open_at_location(repo_location, &password).await?;
+        let mut repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+
+        if schedule.is_limited() {
+            repo.set_rate_limiter(Some(Arc::new(RateLimiter::new(schedule))));
+        }
+
+        if snapshot_time.is_none() {
+            crate::commands::warn_clock_skew(&repo).await;
+        }
+        if !self.dry_run {
+            crate::commands::require_access(&repo, Access::Append, "backup").await?;
+            crate::commands::warn_storage_clock(&repo).await;
+        }
 
         // Acquire exclusive lock for backup operation
         let _lock = if let Some(repo_path) = repo.local_path() {
-            let lock_manager = LockManager::new(repo_path);
+            let lock_manager = crate::commands::lock_manager(cli, repo_path)?;
             Some(lock_manager.acquire(LockType::Exclusive, "backup").await?)
         } else {
             tracing::warn!("Repository locking not supported for remote repositories");
@@ -116,8 +293,27 @@ impl BackupCommand {
 
         let paths: Vec<PathBuf> = self.paths.iter().map(PathBuf::from).collect();
 
-        // Build exclude pattern matcher
-        let excludes = self.build_exclude_matcher()?;
+        let parent = match (&self.parent, &self.parent_filter) {
+            (Some(parent), _) => Some(parent.clone()),
+            (None, Some(filter)) => {
+                let summaries = repo.snapshot_summaries(false).await?;
+                let newest = filter.newest(summaries.iter().map(|s| &s.snapshot), None);
+                if let Some(snapshot) = newest {
+                    info!("Using parent snapshot {}", snapshot.id.short());
+                }
+                newest.map(|snapshot| snapshot.id.clone())
+            }
+            (None, None) => None,
+        };
+
+        // Build include/exclude pattern matcher; the repository's excludes
+        // apply to every backup
+        let mut exclude_patterns = repo.settings().excludes.clone();
+        exclude_patterns.extend(self.exclude.iter().cloned());
+        for file in &self.exclude_file {
+            exclude_patterns.extend(crate::config::read_exclude_file(file)?);
+        }
+        let filter = PathFilter::new(&exclude_patterns, &self.include, &self.exclude_if_present)?;
 
         info!("Starting backup of {} paths", paths.len());
 
@@ -137,8 +333,10 @@ impl BackupCommand {
         let mut total_dirs = 0u64;
         let mut total_symlinks = 0u64;
         let mut total_hardlinks = 0u64;
+        let mut total_special = 0u64;
         let mut total_size = 0u64;
         let mut skipped_large = 0u64;
+        let mut unreadable = 0u64;
         let mut file_list = Vec::new();
 
         // Track inodes for hardlink detection (inode -> first relative path seen)
@@ -150,23 +348,27 @@ impl BackupCommand {
                 return Err(anyhow!("Path does not exist: {}", path.display()));
             }
 
-            let mut walker = WalkDir::new(path).follow_links(false);
+            let mut walker = WalkDir::new(path).follow_links(self.follow_symlinks);
             if self.one_file_system {
                 walker = walker.same_file_system(true);
             }
-            for entry in walker.into_iter().filter_map(|e| e.ok())
-            {
-                let entry_path = entry.path();
-
-                // Check exclude patterns
-                if self.should_exclude(entry_path, &excludes) {
-                    debug!("Excluding: {}", entry_path.display());
-                    continue;
+            for entry in walker {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        crate::commands::warn_walk_error(&e);
+                        unreadable += 1;
+                        continue;
+                    }
+                };
+                if let Some(limiter) = &read_limiter {
+                    limiter.throttle(1).await;
                 }
+                let entry_path = entry.path();
 
-                // Check exclude-if-present
-                if self.check_exclude_if_present(entry_path) {
-                    debug!("Excluding (marker file present): {}", entry_path.display());
+                // Check include/exclude patterns and marker files
+                if let Some(reason) = filter.skip_reason(entry_path, entry.file_type().is_dir()) {
+                    debug!("Excluding ({}): {}", reason.as_str(), entry_path.display());
                     continue;
                 }
 
@@ -178,7 +380,9 @@ impl BackupCommand {
                     }
                 };
 
-                let relative_path = entry_path.strip_prefix(path).unwrap_or(entry_path);
+                let relative_path = entry_path
+                    .strip_prefix(self.base.as_deref().unwrap_or(path))
+                    .unwrap_or(entry_path);
 
                 // Get Unix-specific metadata including inode
                 #[cfg(unix)]
@@ -214,12 +418,10 @@ impl BackupCommand {
                     })
                     .unwrap_or(0);
 
-                // Capture extended attributes if enabled
-                let xattr = if !self.no_xattr {
-                    read_xattrs(entry_path)
-                } else {
-                    None
-                };
+                // Capture extended attributes if enabled. File capabilities
+                // are captured regardless: losing them breaks binaries such
+                // as ping after a full-system restore.
+                let xattr = read_xattrs(entry_path, !self.no_xattr);
 
                 if metadata.is_file() {
                     // Check max file size
@@ -244,24 +446,24 @@ impl BackupCommand {
 
                     // Check for hardlinks
                     #[cfg(unix)]
-                    let (is_hardlink, hardlink_target) = if !self.no_hardlinks && nlink > 1 {
+                    let hardlink_target = if !self.no_hardlinks && nlink > 1 {
                         let inode_key = (dev, inode);
                         if let Some(first_path) = inode_map.get(&inode_key) {
                             // This is a subsequent hardlink to an already-seen file
                             total_hardlinks += 1;
-                            (true, Some(first_path.clone()))
+                            Some(first_path.clone())
                         } else {
                             // First occurrence of this inode
                             inode_map
                                 .insert(inode_key, relative_path.to_string_lossy().to_string());
-                            (false, None)
+                            None
                         }
                     } else {
-                        (false, None)
+                        None
                     };
 
                     #[cfg(not(unix))]
-                    let (is_hardlink, hardlink_target): (bool, Option<String>) = (false, None);
+                    let hardlink_target: Option<String> = None;
 
                     let node = TreeNode {
                         name: relative_path.to_string_lossy().to_string(),
@@ -276,20 +478,18 @@ impl BackupCommand {
                         chunks: Vec::new(),
                         xattr: xattr.clone(),
                         sparse_holes,
-                        inode: if !self.no_hardlinks && nlink > 1 {
-                            Some(inode)
-                        } else {
-                            None
-                        },
+                        // Also recognizes unchanged files in the next backup
+                        inode: (inode != 0).then_some(inode),
                         nlink: if !self.no_hardlinks && nlink > 1 {
                             Some(nlink)
                         } else {
                             None
                         },
                         hardlink_target,
+                        device: None,
                     };
 
-                    file_list.push((entry_path.to_path_buf(), node, is_hardlink));
+                    file_list.push((entry_path.to_path_buf(), node));
                 } else if metadata.is_dir() {
                     total_dirs += 1;
 
@@ -309,9 +509,10 @@ impl BackupCommand {
                         inode: None,
                         nlink: None,
                         hardlink_target: None,
+                        device: None,
                     };
 
-                    file_list.push((entry_path.to_path_buf(), node, false));
+                    file_list.push((entry_path.to_path_buf(), node));
                 } else if metadata.is_symlink() {
                     total_symlinks += 1;
 
@@ -344,9 +545,36 @@ impl BackupCommand {
                         inode: None,
                         nlink: None,
                         hardlink_target: None,
+                        device: None,
+                    };
+
+                    file_list.push((entry_path.to_path_buf(), node));
+                } else if let Some((node_type, device)) =
+                    crate::commands::special_file_type(&metadata)
+                {
+                    // Devices and FIFOs are recorded as metadata only
+                    total_special += 1;
+
+                    let node = TreeNode {
+                        name: relative_path.to_string_lossy().to_string(),
+                        node_type,
+                        mode,
+                        uid,
+                        gid,
+                        size: 0,
+                        mtime,
+                        link_target: None,
+                        subtree_id: None,
+                        chunks: Vec::new(),
+                        xattr,
+                        sparse_holes: None,
+                        inode: None,
+                        nlink: None,
+                        hardlink_target: None,
+                        device,
                     };
 
-                    file_list.push((entry_path.to_path_buf(), node, false));
+                    file_list.push((entry_path.to_path_buf(), node));
                 }
             }
         }
@@ -358,20 +586,38 @@ impl BackupCommand {
         if total_hardlinks > 0 {
             scan_summary.push_str(&format!(", {} hardlinks", total_hardlinks));
         }
+        if total_special > 0 {
+            scan_summary.push_str(&format!(", {} devices/FIFOs", total_special));
+        }
         if skipped_large > 0 {
             scan_summary.push_str(&format!(", {} skipped (too large)", skipped_large));
         }
+        if unreadable > 0 {
+            scan_summary.push_str(&format!(", {} unreadable", unreadable));
+        }
         scan_summary.push_str(&format!(" ({})", HumanBytes(total_size)));
 
         pb.finish_with_message(scan_summary);
 
+        if let Some(exceeded) = limits.exceeded(total_files, total_size) {
+            if self.dry_run {
+                println!("Warning: {}", exceeded);
+            } else if !std::io::stdin().is_terminal() {
+                return Err(anyhow!(
+                    "Backup aborted: {}; raise --max-total-size or --max-file-count if this is expected",
+                    exceeded
+                ));
+            } else if !crate::password::ask_yes_no(&format!(
+                "Warning: {}. Back up anyway?",
+                exceeded
+            ))? {
+                return Err(anyhow!("Backup aborted: {}", exceeded));
+            }
+        }
+
         if !self.dry_run {
             println!("Backing up {} items...", file_list.len());
 
-            let chunker = Chunker::new_default();
-            let mut pack_manager = PackManager::new(64 * 1024 * 1024);
-            let mut processed_nodes = Vec::new();
-
             let backup_pb = ProgressBar::new(total_size);
             backup_pb.set_style(
                 ProgressStyle::default_bar()
@@ -381,63 +627,51 @@ impl BackupCommand {
             );
 
             let start_time = Instant::now();
-            let mut bytes_processed = 0u64;
-            let mut new_chunks = 0u64;
-            let mut dedup_chunks = 0u64;
-            let mut failed_files = 0u64;
-
-            for (i, (file_path, mut node, is_hardlink)) in file_list.into_iter().enumerate() {
-                backup_pb.set_message(node.name.clone());
-
-                // Only process files for chunking (skip hardlinks - they reference the original)
-                if node.node_type == NodeType::File && !is_hardlink {
-                    match self
-                        .process_file_with_stats(&repo, &chunker, &mut pack_manager, &file_path)
-                        .await
-                    {
-                        Ok((chunks, new, dedup)) => {
-                            node.chunks = chunks;
-                            new_chunks += new;
-                            dedup_chunks += dedup;
-                            debug!("Successfully processed: {}", node.name);
-                        }
-                        Err(e) => {
-                            warn!("Failed to process {}: {}", node.name, e);
-                            failed_files += 1;
-                            bytes_processed += node.size;
-                            backup_pb.set_position(bytes_processed);
-                            continue; // Skip this node - don't save broken entry
-                        }
-                    }
-                    bytes_processed += node.size;
-                    backup_pb.set_position(bytes_processed);
-                } else if is_hardlink {
-                    // Hardlinks don't need chunk processing - they'll reference the original
-                    debug!(
-                        "Hardlink detected: {} -> {:?}",
-                        node.name, node.hardlink_target
-                    );
-                    bytes_processed += node.size;
-                    backup_pb.set_position(bytes_processed);
-                }
-
-                processed_nodes.push(node);
+            let mut progress = BackupProgress {
+                pb: &backup_pb,
+                record_hashes: self.manifest.is_some(),
+                content_hashes: HashMap::new(),
+                bytes_processed: 0,
+                new_chunks: 0,
+                dedup_chunks: 0,
+                unchanged_files: 0,
+                // Entries the scan couldn't get to are missing as well
+                failed_files: unreadable,
+            };
 
-                // Periodically save completed packs
-                if i % 100 == 0
-                    && let Some(pack) = pack_manager.finish_current_pack()
-                    && let Err(e) = self.save_pack_and_index(&repo, &pack).await
-                {
-                    warn!("Failed to save pack: {}", e);
-                }
+            // Standalone backups only deduplicate against chunks written by this run
+            let mut writer = SourceWriter::new(&repo)
+                .with_read_limiter(read_limiter.as_ref())
+                .with_standalone(self.standalone)
+                .with_jobs(self.jobs as usize);
+            if let Some(size) = pack_size {
+                writer = writer.with_pack_size(size);
             }
-
-            // Save final pack
-            if let Some(pack) = pack_manager.finish_current_pack()
-                && let Err(e) = self.save_pack_and_index(&repo, &pack).await
-            {
-                warn!("Failed to save final pack: {}", e);
+            if let Some(parent_id) = &parent {
+                if self.manifest.is_some() {
+                    // The manifest needs the hash of every file
+                    info!("Reading every file for the manifest despite --parent");
+                } else {
+                    let parent = repo.load_snapshot(parent_id).await?;
+                    let parent_tree = repo.load_tree(&parent.tree).await?;
+                    writer = writer.with_parent(&parent_tree, parent.time);
+                }
             }
+            let mut tree = Tree::new();
+            writer
+                .add_source(&mut FsSource::new(file_list), &mut tree, &mut progress)
+                .await?;
+            writer.flush().await?;
+
+            let BackupProgress {
+                content_hashes,
+                bytes_processed,
+                new_chunks,
+                dedup_chunks,
+                unchanged_files,
+                failed_files,
+                ..
+            } = progress;
 
             let elapsed = start_time.elapsed();
             let throughput = if elapsed.as_secs() > 0 {
@@ -454,23 +688,29 @@ impl BackupCommand {
                 HumanBytes(throughput)
             ));
 
-            // Create and save tree
-            let mut tree = Tree::new();
-            for node in processed_nodes {
-                tree.add_node(node);
-            }
-
             let tree_id = repo.save_tree(&tree).await?;
 
             // Create snapshot with optional hostname override
             let mut snapshot = Snapshot::new(paths.clone(), tree_id);
 
-            if let Some(parent_id) = &self.parent {
+            if let Some(parent_id) = &parent {
                 snapshot = snapshot.with_parent(parent_id.clone());
             }
 
             snapshot = snapshot.with_tags(self.tag.clone());
-            snapshot = snapshot.with_excludes(self.exclude.clone());
+            snapshot = snapshot.with_excludes(exclude_patterns.clone());
+            snapshot = snapshot.with_includes(self.include.clone());
+            snapshot = snapshot
+                .with_standalone(self.standalone)
+                .with_packs(writer.packs().to_vec());
+            let (user_names, group_names) = crate::idmap::owner_names(
+                tree.nodes.iter().map(|n| n.uid),
+                tree.nodes.iter().map(|n| n.gid),
+            );
+            snapshot = snapshot.with_owner_names(user_names, group_names);
+            if let Some(time) = snapshot_time {
+                snapshot = snapshot.with_time(time);
+            }
 
             // Apply hostname override if specified
             if let Some(hostname) = &self.hostname {
@@ -483,6 +723,37 @@ impl BackupCommand {
             // Save index to disk
             repo.save_index().await?;
 
+            if let Err(e) = repo.refresh_stats_cache().await {
+                warn!("Failed to update stats cache: {}", e);
+            }
+            if let Err(e) = repo.refresh_refcounts().await {
+                warn!("Failed to update chunk reference counts: {}", e);
+            }
+            if crate::telemetry::is_recording(cli.quiet)
+                && let Ok(cache) = repo.stats_cache(false).await
+            {
+                crate::telemetry::record(cli.quiet, |counters| {
+                    counters.record_backup(repo.location(), cache.stored_size())
+                });
+            }
+
+            // Written last: the snapshot and index are complete even if this
+            // fails.
+            let manifest_path = match &self.manifest {
+                Some(path) => {
+                    let manifest = BackupManifest::new(&snapshot, &tree, &content_hashes);
+                    let written = manifest.write(path, manifest_key.as_ref()).map_err(|e| {
+                        anyhow!(
+                            "Snapshot {} was saved, but writing the manifest failed: {}",
+                            snapshot.short_id(),
+                            e
+                        )
+                    })?;
+                    Some(written)
+                }
+                None => None,
+            };
+
             if failed_files > 0 {
                 println!("Backup completed with {} failed files", failed_files);
             } else {
@@ -496,6 +767,12 @@ impl BackupCommand {
             if total_hardlinks > 0 {
                 println!("Hardlinks: {}", total_hardlinks);
             }
+            if total_special > 0 {
+                println!("Devices/FIFOs: {} (metadata only)", total_special);
+            }
+            if unchanged_files > 0 {
+                println!("Unchanged (not read): {}", unchanged_files);
+            }
             if failed_files > 0 {
                 println!("Failed: {}", failed_files);
             }
@@ -514,6 +791,17 @@ impl BackupCommand {
                 HumanBytes(throughput)
             );
             println!("Tree: {}", tree_id.short_string());
+            if let Some(path) = manifest_path {
+                let signed = if self.manifest_key.is_some() {
+                    " (signed)"
+                } else {
+                    ""
+                };
+                println!("Manifest: {}{}", path.display(), signed);
+            }
+            if self.standalone {
+                println!("Mode: standalone (all chunks re-uploaded)");
+            }
         } else {
             println!(
                 "Dry run completed - would backup {} files, {} dirs, {} symlinks ({})",
@@ -526,139 +814,98 @@ impl BackupCommand {
 
         Ok(())
     }
+}
 
-    /// Builds a GlobSet from exclude patterns.
-    fn build_exclude_matcher(&self) -> Result<GlobSet> {
-        let mut builder = GlobSetBuilder::new();
-
-        for pattern in &self.exclude {
-            let glob = Glob::new(pattern)
-                .map_err(|e| anyhow!("Invalid exclude pattern '{}': {}", pattern, e))?;
-            builder.add(glob);
-        }
-
-        builder
-            .build()
-            .map_err(|e| anyhow!("Failed to build exclude matcher: {}", e))
-    }
-
-    /// Checks if a path matches any exclude pattern.
-    fn should_exclude(&self, path: &Path, excludes: &GlobSet) -> bool {
-        if excludes.is_empty() {
-            return false;
-        }
+/// Shows progress and collects totals as files are backed up.
+struct BackupProgress<'a> {
+    pb: &'a ProgressBar,
+    record_hashes: bool,
+    /// Content hashes by path, for the manifest
+    content_hashes: HashMap<String, blake3::Hash>,
+    bytes_processed: u64,
+    new_chunks: u64,
+    dedup_chunks: u64,
+    /// Files taken from the parent snapshot without reading them
+    unchanged_files: u64,
+    failed_files: u64,
+}
 
-        // Check path as-is
-        if excludes.is_match(path) {
-            return true;
+impl SourceObserver for BackupProgress<'_> {
+    fn added(&mut self, node: &TreeNode, contents: Option<&StoredContents>) {
+        self.pb.set_message(node.name.clone());
+        if let Some(contents) = contents {
+            if self.record_hashes {
+                self.content_hashes
+                    .insert(node.name.clone(), contents.content_hash);
+            }
+            self.new_chunks += contents.new_chunks;
+            self.dedup_chunks += contents.dedup_chunks;
+            debug!("Successfully processed: {}", node.name);
+        } else if let Some(target) = &node.hardlink_target {
+            // Hardlinks reference the original instead of storing contents
+            debug!("Hardlink detected: {} -> {}", node.name, target);
         }
-
-        // Also check just the file/dir name
-        if let Some(name) = path.file_name()
-            && excludes.is_match(name)
-        {
-            return true;
+        if node.node_type == NodeType::File {
+            self.bytes_processed += node.size;
+            self.pb.set_position(self.bytes_processed);
         }
-
-        false
     }
 
-    /// Checks if directory contains any exclude-if-present marker files.
-    fn check_exclude_if_present(&self, path: &Path) -> bool {
-        if self.exclude_if_present.is_empty() {
-            return false;
-        }
-
-        // Only check for directories
-        let dir = if path.is_dir() {
-            path
-        } else if let Some(parent) = path.parent() {
-            parent
-        } else {
-            return false;
-        };
-
-        for marker in &self.exclude_if_present {
-            if dir.join(marker).exists() {
-                return true;
-            }
-        }
-
-        false
+    fn unchanged(&mut self, node: &TreeNode) {
+        self.pb.set_message(node.name.clone());
+        debug!("Unchanged since parent: {}", node.name);
+        self.unchanged_files += 1;
+        self.bytes_processed += node.size;
+        self.pb.set_position(self.bytes_processed);
     }
 
-    /// Process a file and return (chunk_refs, new_chunks_count, dedup_chunks_count)
-    async fn process_file_with_stats(
-        &self,
-        repo: &Repository,
-        chunker: &Chunker,
-        pack_manager: &mut PackManager,
-        file_path: &PathBuf,
-    ) -> Result<(Vec<ghostsnap_core::ChunkRef>, u64, u64)> {
-        let file_data = fs::read(file_path).await?;
-        let chunks = chunker.chunk_data(&file_data);
-        let mut chunk_refs = Vec::new();
-        let mut new_count = 0u64;
-        let mut dedup_count = 0u64;
-
-        for chunk in chunks {
-            let chunk_id = chunk.id();
-
-            // Check if chunk already exists (deduplication)
-            if !repo.has_chunk(&chunk_id).await? {
-                if let Some(finished_pack) = pack_manager.add_chunk(chunk_id, chunk.data())? {
-                    self.save_pack_and_index(repo, &finished_pack).await?;
-                }
-                new_count += 1;
-            } else {
-                dedup_count += 1;
-            }
-
-            chunk_refs.push(ghostsnap_core::ChunkRef {
-                id: chunk_id,
-                offset: 0,
-                length: chunk.data().len() as u32,
-            });
-        }
-
-        Ok((chunk_refs, new_count, dedup_count))
+    fn failed(
+        &mut self,
+        node: &TreeNode,
+        error: ghostsnap_core::Error,
+    ) -> ghostsnap_core::Result<()> {
+        // Skip this node rather than save a broken entry
+        warn!("Failed to process {}: {}", node.name, error);
+        self.failed_files += 1;
+        self.bytes_processed += node.size;
+        self.pb.set_position(self.bytes_processed);
+        Ok(())
     }
+}
 
-    async fn save_pack_and_index(&self, repo: &Repository, pack: &PackFile) -> Result<()> {
-        repo.save_pack(pack).await?;
-
-        for (chunk_id, chunk_entry) in &pack.chunks {
-            repo.save_chunk_location(
-                chunk_id,
-                &pack.header.pack_id,
-                chunk_entry.offset,
-                chunk_entry.length,
-            )
-            .await?;
-        }
-
-        info!(
-            "Saved pack: {} with {} chunks",
-            pack.header.pack_id,
-            pack.chunks.len()
-        );
-        Ok(())
+/// Parses a `--time` value. Times in the future are refused: they would
+/// sort after every real backup and skew retention.
+fn parse_snapshot_time(input: &str) -> Result<DateTime<Utc>> {
+    let time = DateTime::parse_from_rfc3339(input)
+        .map_err(|e| anyhow!("Invalid --time '{}' (expected RFC 3339): {}", input, e))?
+        .with_timezone(&Utc);
+    if time > Utc::now() + CLOCK_SKEW_TOLERANCE {
+        return Err(anyhow!(
+            "Snapshot time {} is in the future",
+            time.to_rfc3339()
+        ));
     }
+    Ok(time)
 }
 
-/// Read extended attributes from a file (Unix only).
+/// Read extended attributes from a file (Unix only). Without `all`, only
+/// the file capabilities (`security.capability`) are read.
 #[cfg(unix)]
-fn read_xattrs(path: &Path) -> Option<HashMap<String, Vec<u8>>> {
-    let attrs: Vec<_> = match xattr::list(path) {
-        Ok(iter) => iter.collect(),
-        Err(_) => return None,
+fn read_xattrs(path: &Path, all: bool) -> Option<BTreeMap<String, Vec<u8>>> {
+    let attrs: Vec<_> = if all {
+        match xattr::list(path) {
+            Ok(iter) => iter.collect(),
+            Err(_) => return None,
+        }
+    } else {
+        vec![ghostsnap_core::CAPABILITY_XATTR.into()]
     };
 
     if attrs.is_empty() {
         return None;
     }
 
-    let mut result = HashMap::new();
+    let mut result = BTreeMap::new();
     for attr_name in attrs {
         if let Ok(Some(value)) = xattr::get(path, &attr_name) {
             // Convert OsString to String, skipping non-UTF8 names
@@ -676,7 +923,7 @@ fn read_xattrs(path: &Path) -> Option<HashMap<String, Vec<u8>>> {
 }
 
 #[cfg(not(unix))]
-fn read_xattrs(_path: &Path) -> Option<HashMap<String, Vec<u8>>> {
+fn read_xattrs(_path: &Path, _all: bool) -> Option<BTreeMap<String, Vec<u8>>> {
     None
 }
 
@@ -0,0 +1,184 @@
+//! Bench command for measuring throughput on this machine.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap bench                                  # Measure and recommend
+//! ghostsnap bench --size 256M --target 50          # More data, slower link
+//! ghostsnap --repo /backup/repo bench --apply      # Store the recommendation
+//! ```
+//!
+//! See `ghostsnap_core::bench` for what is measured and how settings are
+//! chosen.
+
+use anyhow::{Result, anyhow};
+use clap::Args;
+use ghostsnap_core::bench::{self, BenchReport, DEFAULT_TARGET_MB_PER_SEC, Recommendation};
+use indicatif::HumanBytes;
+
+#[derive(Args)]
+pub struct BenchCommand {
+    #[arg(
+        long,
+        default_value = "64M",
+        help = "Data to process per measurement (e.g., 16M, 1G)"
+    )]
+    size: String,
+
+    #[arg(
+        long,
+        default_value_t = DEFAULT_TARGET_MB_PER_SEC,
+        help = "Throughput in MB/s that compression should keep up with, e.g. your upload speed"
+    )]
+    target: f64,
+
+    #[arg(
+        long,
+        help = "Store the recommended chunk size and compression level in the repository (--repo)"
+    )]
+    apply: bool,
+
+    #[arg(long, help = "Output in JSON format")]
+    json: bool,
+}
+
+impl BenchCommand {
+    pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let size = crate::commands::parse_size(&self.size)? as usize;
+        if size == 0 {
+            return Err(anyhow!("--size must be greater than zero"));
+        }
+        if self.target <= 0.0 {
+            return Err(anyhow!("--target must be greater than zero"));
+        }
+
+        if !self.json {
+            println!(
+                "Benchmarking with {} of sample data...",
+                HumanBytes(size as u64)
+            );
+        }
+        let report = tokio::task::spawn_blocking(move || bench::run(size)).await??;
+        let recommendation = report.recommend(self.target);
+
+        if self.json {
+            print_json(&report, &recommendation)?;
+        } else {
+            print_report(&report, &recommendation);
+        }
+
+        if self.apply {
+            self.apply(cli, &recommendation).await?;
+        }
+        Ok(())
+    }
+
+    async fn apply(&self, cli: &crate::Cli, recommendation: &Recommendation) -> Result<()> {
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+        let password = crate::password::repository_password(cli)?;
+        let mut repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+
+        repo.set_compression_level(Some(recommendation.compression_level))
+            .await?;
+        println!();
+        println!(
+            "Stored compression level {} in the repository",
+            recommendation.compression_level
+        );
+
+        // New chunk boundaries would stop new backups deduplicating against
+        // the data already stored
+        let current = repo
+            .config()
+            .chunk_size
+            .unwrap_or(ghostsnap_core::chunker::DEFAULT_CHUNK_SIZE);
+        if current == recommendation.chunk_size {
+            println!("Chunk size is already {} KiB", current / 1024);
+        } else if repo.list_snapshots().await?.is_empty() {
+            repo.set_chunk_size(Some(recommendation.chunk_size)).await?;
+            println!(
+                "Stored chunk size {} KiB in the repository",
+                recommendation.chunk_size / 1024
+            );
+        } else {
+            println!(
+                "Kept chunk size {} KiB: the repository has snapshots, and new chunk boundaries \
+                 would not deduplicate against them",
+                current / 1024
+            );
+        }
+        Ok(())
+    }
+}
+
+fn print_report(report: &BenchReport, recommendation: &Recommendation) {
+    println!();
+    println!("Chunking (FastCDC):");
+    for (avg_size, throughput) in &report.chunking {
+        println!(
+            "  {:>5} KiB average   {:>8.0} MB/s",
+            avg_size / 1024,
+            throughput.mb_per_sec()
+        );
+    }
+    println!();
+    println!("Hashing:");
+    println!(
+        "  BLAKE3              {:>8.0} MB/s",
+        report.hashing.mb_per_sec()
+    );
+    println!();
+    println!("Compression:");
+    for result in &report.compression {
+        println!(
+            "  {} level {:<2}       {:>8.0} MB/s   {:>5.1}% of input",
+            result.algorithm,
+            result.level,
+            result.throughput.mb_per_sec(),
+            result.ratio * 100.0
+        );
+    }
+    println!("  (packs are compressed with zlib; zstd is shown for comparison)");
+    println!();
+    println!("Encryption:");
+    for cipher in &report.ciphers {
+        println!(
+            "  {:<24} {:>8.0} MB/s",
+            cipher.name,
+            cipher.throughput.mb_per_sec()
+        );
+    }
+    println!();
+    println!("Recommended settings:");
+    for reason in &recommendation.reasons {
+        println!("  {}", reason);
+    }
+}
+
+fn print_json(report: &BenchReport, recommendation: &Recommendation) -> Result<()> {
+    let output = serde_json::json!({
+        "chunking": report.chunking.iter().map(|(avg_size, throughput)| serde_json::json!({
+            "avg_size": avg_size,
+            "mb_per_sec": throughput.mb_per_sec(),
+        })).collect::<Vec<_>>(),
+        "hashing": { "blake3_mb_per_sec": report.hashing.mb_per_sec() },
+        "compression": report.compression.iter().map(|result| serde_json::json!({
+            "algorithm": result.algorithm,
+            "level": result.level,
+            "mb_per_sec": result.throughput.mb_per_sec(),
+            "ratio": result.ratio,
+        })).collect::<Vec<_>>(),
+        "ciphers": report.ciphers.iter().map(|cipher| serde_json::json!({
+            "name": cipher.name,
+            "mb_per_sec": cipher.throughput.mb_per_sec(),
+        })).collect::<Vec<_>>(),
+        "recommendation": {
+            "chunk_size": recommendation.chunk_size,
+            "compression_level": recommendation.compression_level,
+            "cipher": recommendation.cipher,
+            "reasons": recommendation.reasons,
+        },
+    });
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
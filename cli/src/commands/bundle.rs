@@ -0,0 +1,146 @@
+//! Bundle command for air-gapped snapshot transfer.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap bundle export abc123 --output snapshot.gsbundle
+//! ghostsnap --repo /vault/repo bundle import snapshot.gsbundle
+//! ```
+
+use anyhow::{Result, anyhow};
+use clap::{Args, Subcommand};
+use ghostsnap_core::{LockType, Repository, SnapshotBundle};
+use indicatif::HumanBytes;
+use std::path::PathBuf;
+use tracing::info;
+
+/// Bundle command for exporting and importing portable snapshot files.
+#[derive(Args)]
+pub struct BundleCommand {
+    /// Password used to encrypt/decrypt the bundle (defaults to the repository password).
+    #[arg(long, env = "GHOSTSNAP_BUNDLE_PASSWORD", global = true)]
+    bundle_password: Option<String>,
+
+    #[command(subcommand)]
+    subcommand: BundleSubcommand,
+}
+
+#[derive(Subcommand)]
+enum BundleSubcommand {
+    /// Export a snapshot and all data it references to a single file.
+    Export(BundleExportCommand),
+
+    /// Import a bundle file into the repository.
+    Import(BundleImportCommand),
+}
+
+impl BundleCommand {
+    pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+
+        let password = crate::password::repository_password(cli)?;
+
+        info!("Opening repository at: {}", repo_location.display());
+        let (repo, password) =
+            crate::commands::open_repository_with_password(cli, repo_location, &password).await?;
+
+        let bundle_password = self.bundle_password.clone().unwrap_or(password);
+
+        match &self.subcommand {
+            BundleSubcommand::Export(cmd) => cmd.run(&repo, &bundle_password).await,
+            BundleSubcommand::Import(cmd) => cmd.run(cli, &repo, &bundle_password).await,
+        }
+    }
+}
+
+// === Export Command ===
+
+#[derive(Args)]
+struct BundleExportCommand {
+    /// Snapshot ID (full or short prefix).
+    snapshot_id: String,
+
+    /// Output bundle file.
+    #[arg(long, short = 'o')]
+    output: PathBuf,
+}
+
+impl BundleExportCommand {
+    async fn run(&self, repo: &Repository, bundle_password: &str) -> Result<()> {
+        if self.output.exists() {
+            return Err(anyhow!(
+                "Output file already exists: {}",
+                self.output.display()
+            ));
+        }
+
+        let snapshot_id = crate::commands::resolve_snapshot_prefix(repo, &self.snapshot_id).await?;
+
+        println!("Collecting data for snapshot {}...", snapshot_id.short());
+        let bundle = repo.export_bundle(&snapshot_id).await?;
+
+        let bytes = bundle.to_bytes(bundle_password)?;
+        tokio::fs::write(&self.output, &bytes).await?;
+
+        println!("Bundle written to {}", self.output.display());
+        println!(
+            "  Files: {} | Chunks: {} | Data: {} | Bundle: {}",
+            bundle.tree.file_count(),
+            bundle.chunks.len(),
+            HumanBytes(bundle.data_size()),
+            HumanBytes(bytes.len() as u64)
+        );
+
+        Ok(())
+    }
+}
+
+// === Import Command ===
+
+#[derive(Args)]
+struct BundleImportCommand {
+    /// Bundle file to import.
+    input: PathBuf,
+}
+
+impl BundleImportCommand {
+    async fn run(&self, cli: &crate::Cli, repo: &Repository, bundle_password: &str) -> Result<()> {
+        let _lock = if let Some(repo_path) = repo.local_path() {
+            let lock_manager = crate::commands::lock_manager(cli, repo_path)?;
+            Some(
+                lock_manager
+                    .acquire(LockType::Exclusive, "bundle import")
+                    .await?,
+            )
+        } else {
+            tracing::warn!("Repository locking not supported for remote repositories");
+            None
+        };
+
+        let bytes = tokio::fs::read(&self.input).await?;
+        let bundle = SnapshotBundle::from_bytes(&bytes, bundle_password)?;
+
+        if repo.list_snapshots().await?.contains(&bundle.snapshot.id) {
+            return Err(anyhow!(
+                "Snapshot {} already exists in this repository",
+                bundle.snapshot.short_id()
+            ));
+        }
+
+        println!(
+            "Importing snapshot {} ({})",
+            bundle.snapshot.short_id(),
+            bundle.snapshot.summary()
+        );
+        let stats = repo.import_bundle(&bundle).await?;
+
+        println!("Import completed!");
+        println!(
+            "  Chunks imported: {} | Already present: {}",
+            stats.chunks_imported, stats.chunks_skipped
+        );
+        println!("  Snapshot: {}", stats.snapshot_id.short());
+
+        Ok(())
+    }
+}
@@ -1,9 +1,26 @@
+//! Check command for verifying repository integrity.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap --repo /backup/repo check
+//! ghostsnap --repo /backup/repo check --read-data
+//! ghostsnap --repo /backup/repo check --snapshot latest --read-data
+//! ```
+//!
+//! The checks themselves live in [`ghostsnap_core::check`]; this command
+//! shows their progress, adds the optional index cross-check and the
+//! unused and foreign object reports, and fails if any problem was found.
+
 use anyhow::{Result, anyhow};
 use clap::Args;
-use ghostsnap_core::Repository;
-use indicatif::{ProgressBar, ProgressStyle};
-use std::collections::HashSet;
-use std::io::{self, Write};
+use ghostsnap_core::check;
+use ghostsnap_core::{
+    BandwidthSchedule, CheckObserver, CheckOptions, CheckProblem, CheckStep, RateLimiter,
+    Repository, SnapshotFilter,
+};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::sync::Arc;
 use tracing::warn;
 
 #[derive(Args)]
@@ -11,234 +28,93 @@ pub struct CheckCommand {
     #[arg(long, help = "Read and verify all data (slow but thorough)")]
     read_data: bool,
 
-    #[arg(long, help = "Check specific snapshot only")]
+    #[arg(
+        long,
+        help = "Only verify what this snapshot (ID or `latest`) needs to be restored"
+    )]
     snapshot: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "snapshot",
+        help = "List unused chunks and cross-check pack contents against the index"
+    )]
+    check_unused: bool,
+
+    #[arg(
+        long,
+        default_value_t = 8,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Number of objects to verify at once"
+    )]
+    parallel: u64,
+
+    #[arg(long, help = "Download bandwidth limit (e.g., 10M, 512K)")]
+    max_download_rate: Option<String>,
 }
 
 impl CheckCommand {
     pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
         let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
 
-        let password = cli
-            .password
-            .clone()
-            .or_else(|| {
-                print!("Enter repository password: ");
-                io::stdout().flush().ok()?;
-                rpassword::read_password().ok()
-            })
-            .ok_or_else(|| anyhow!("Password required"))?;
-
-        let repo = Repository::open_at_location(repo_location, &password).await?;
-
-        println!("Checking repository integrity...");
-        println!();
-
-        let mut errors = 0;
-        let mut warnings = 0;
+        let password = crate::password::repository_password(cli)?;
 
-        // 1. Check all snapshots
-        let snapshots = if let Some(ref id) = self.snapshot {
-            vec![id.clone()]
-        } else {
-            repo.list_snapshots().await?
+        let schedule = BandwidthSchedule::parse(self.max_download_rate.as_deref(), &[])?;
+        let mut repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+        if schedule.is_limited() {
+            repo.set_download_limiter(Some(Arc::new(RateLimiter::new(schedule))));
+        }
+        let options = CheckOptions {
+            read_data: self.read_data,
+            parallel: self.parallel as usize,
         };
 
-        println!("[1/5] Checking {} snapshots...", snapshots.len());
-        let pb = ProgressBar::new(snapshots.len() as u64);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{bar:40} {pos}/{len} snapshots")
-                .unwrap(),
-        );
-
-        let mut all_tree_ids = HashSet::new();
-        let mut all_chunk_ids = HashSet::new();
-
-        for snapshot_id in &snapshots {
-            match repo.load_snapshot(snapshot_id).await {
-                Ok(snapshot) => {
-                    all_tree_ids.insert(snapshot.tree);
-
-                    // Load tree and collect chunk IDs
-                    match repo.load_tree(&snapshot.tree).await {
-                        Ok(tree) => {
-                            for node in &tree.nodes {
-                                for chunk_ref in &node.chunks {
-                                    all_chunk_ids.insert(chunk_ref.id);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            warn!(
-                                "Cannot load tree {} for snapshot {}: {}",
-                                snapshot.tree.short_string(),
-                                snapshot_id,
-                                e
-                            );
-                            errors += 1;
-                        }
-                    }
-                }
-                Err(e) => {
-                    warn!("Cannot load snapshot {}: {}", snapshot_id, e);
-                    errors += 1;
-                }
-            }
-            pb.inc(1);
+        if let Some(snapshot_id) = &self.snapshot {
+            return self.check_snapshot(&repo, snapshot_id, &options).await;
         }
-        pb.finish_and_clear();
-        println!(
-            "  Snapshots: {} checked, {} errors",
-            snapshots.len(),
-            errors
-        );
 
-        // 2. Check tree objects
-        println!("[2/5] Checking {} tree objects...", all_tree_ids.len());
-        let tree_errors_before = errors;
-        for tree_id in &all_tree_ids {
-            if let Err(e) = repo.load_tree(tree_id).await {
-                warn!("Cannot load tree {}: {}", tree_id.short_string(), e);
-                errors += 1;
-            }
-        }
-        println!(
-            "  Trees: {} checked, {} errors",
-            all_tree_ids.len(),
-            errors - tree_errors_before
-        );
+        println!("Checking repository integrity...");
+        println!();
 
-        // 3. Check chunk index consistency
-        println!("[3/5] Checking {} chunk references...", all_chunk_ids.len());
-        let pb = ProgressBar::new(all_chunk_ids.len() as u64);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{bar:40} {pos}/{len} chunks")
-                .unwrap(),
-        );
+        // Cross-checking reads every pack header, so only do it when pack data
+        // is being read anyway or unused data was explicitly requested.
+        let cross_check = self.read_data || self.check_unused;
+        let steps = if cross_check { 7 } else { 6 };
 
-        let mut missing_chunks = 0;
-        let index = repo.index();
-        let index_guard = index.read().await;
+        let mut progress = CheckProgress::new(&repo, steps, self.read_data);
+        let report = check::check_repository(&repo, &options, &mut progress).await?;
+        progress.finish();
 
-        for chunk_id in &all_chunk_ids {
-            if !index_guard.has_chunk(chunk_id) {
-                warn!(
-                    "Chunk {} referenced but not in index",
-                    chunk_id.short_string()
-                );
-                missing_chunks += 1;
-            }
-            pb.inc(1);
-        }
-        drop(index_guard);
-        pb.finish_and_clear();
+        let mut errors = report.problems.len();
+        let mut warnings = 0;
 
-        if missing_chunks > 0 {
-            errors += missing_chunks;
-            println!(
-                "  Chunks: {} referenced, {} missing from index",
-                all_chunk_ids.len(),
-                missing_chunks
-            );
-        } else {
-            println!(
-                "  Chunks: {} referenced, all present in index",
-                all_chunk_ids.len()
-            );
+        if cross_check {
+            println!("[7/7] Cross-checking index against pack contents...");
+            let (cross_errors, cross_warnings) = cross_check_index(&repo).await?;
+            errors += cross_errors;
+            warnings += cross_warnings;
         }
 
-        // 4. Check pack files
-        let packs = repo.list_packs().await?;
-        let existing_packs: HashSet<_> = packs.iter().cloned().collect();
-
-        // 4a. Verify index pack references point to existing packs
-        println!("[4/5] Verifying index pack references...");
+        // Check for orphaned data (chunks in index but not referenced)
         let index = repo.index();
         let index_guard = index.read().await;
-        let mut referenced_packs: HashSet<String> = HashSet::new();
-        for (_, location) in index_guard.iter_chunks() {
-            referenced_packs.insert(location.pack_id.clone());
-        }
-        drop(index_guard);
-
-        let missing_packs: Vec<_> = referenced_packs
-            .difference(&existing_packs)
+        let orphaned: Vec<_> = index_guard
+            .iter_chunk_ids()
+            .filter(|chunk_id| !report.referenced_chunks.contains(chunk_id))
             .collect();
-
-        if !missing_packs.is_empty() {
-            for pack_id in &missing_packs {
-                warn!("Pack {} referenced in index but does not exist", pack_id);
-            }
-            errors += missing_packs.len();
-            println!(
-                "  Index references: {} packs, {} missing",
-                referenced_packs.len(),
-                missing_packs.len()
-            );
-        } else {
-            println!(
-                "  Index references: {} packs, all present",
-                referenced_packs.len()
-            );
-        }
-
-        // 4b. Check pack file integrity
-        println!("[5/5] Checking {} pack files...", packs.len());
-
-        if self.read_data {
-            let pb = ProgressBar::new(packs.len() as u64);
-            pb.set_style(
-                ProgressStyle::default_bar()
-                    .template("{bar:40} {pos}/{len} packs")
-                    .unwrap(),
-            );
-
-            let mut pack_errors = 0;
-            for pack_id in &packs {
-                match repo.load_pack(pack_id).await {
-                    Ok(_pack) => {
-                        // Pack loaded successfully (decrypted and deserialized)
-                    }
-                    Err(e) => {
-                        warn!("Cannot load pack {}: {}", pack_id, e);
-                        pack_errors += 1;
-                    }
-                }
-                pb.inc(1);
-            }
-            pb.finish_and_clear();
-            errors += pack_errors;
-            println!(
-                "  Packs: {} checked (read all data), {} errors",
-                packs.len(),
-                pack_errors
-            );
-        } else {
-            // Just check pack files exist
-            let mut pack_errors = 0;
-            for pack_id in &packs {
-                if !repo.pack_exists(pack_id).await? {
-                    warn!("Pack file missing: {}", pack_id);
-                    pack_errors += 1;
+        if self.check_unused {
+            for chunk_id in &orphaned {
+                if let Some(location) = index_guard.get_chunk(chunk_id) {
+                    println!(
+                        "  unused chunk {} in pack {}",
+                        chunk_id.short_string(),
+                        location.pack_id
+                    );
                 }
             }
-            errors += pack_errors;
-            println!(
-                "  Packs: {} exist, {} missing (use --read-data for full verification)",
-                packs.len(),
-                pack_errors
-            );
         }
-
-        // Check for orphaned data (chunks in index but not referenced)
-        let index = repo.index();
-        let index_guard = index.read().await;
-        let indexed_chunks: HashSet<_> = index_guard.iter_chunks().map(|(id, _)| *id).collect();
         drop(index_guard);
 
-        let orphaned: Vec<_> = indexed_chunks.difference(&all_chunk_ids).collect();
         if !orphaned.is_empty() {
             warnings += 1;
             println!();
@@ -249,6 +125,20 @@ impl CheckCommand {
             println!("  Run 'ghostsnap prune' to reclaim space");
         }
 
+        // Objects that ghostsnap did not write are left alone, but reported
+        let foreign = repo.foreign_objects().await?;
+        if !foreign.is_empty() {
+            warnings += 1;
+            println!();
+            println!(
+                "Warning: {} foreign objects found (not written by ghostsnap, ignored):",
+                foreign.len()
+            );
+            for path in &foreign {
+                println!("  {}", path);
+            }
+        }
+
         // Summary
         println!();
         if errors == 0 && warnings == 0 {
@@ -268,4 +158,252 @@ impl CheckCommand {
             Ok(())
         }
     }
+
+    /// Verifies only what one snapshot needs to be restored.
+    async fn check_snapshot(
+        &self,
+        repo: &Repository,
+        snapshot_id: &str,
+        options: &CheckOptions,
+    ) -> Result<()> {
+        let snapshot_id =
+            crate::commands::resolve_snapshot(repo, snapshot_id, &SnapshotFilter::default())
+                .await?;
+        let snapshot = repo.load_snapshot(&snapshot_id).await?;
+        println!(
+            "Checking snapshot {} ({}, {})...",
+            snapshot.short_id(),
+            snapshot.time.format("%Y-%m-%d %H:%M:%S UTC"),
+            snapshot.hostname
+        );
+        println!();
+
+        let mut progress = CheckProgress::new(repo, 3, self.read_data);
+        let report = check::check_snapshot(repo, &snapshot, options, &mut progress).await?;
+        progress.finish();
+
+        println!();
+        if !report.is_ok() {
+            println!("Found {} errors", report.problems.len());
+            return Err(anyhow!(
+                "Snapshot {} check failed with {} errors",
+                snapshot.short_id(),
+                report.problems.len()
+            ));
+        }
+        println!("Snapshot {} can be restored", snapshot.short_id());
+        Ok(())
+    }
+}
+
+/// Shows each step of a check with a progress bar and a summary line, and
+/// logs problems as they are found.
+struct CheckProgress<'a> {
+    repo: &'a Repository,
+    steps: usize,
+    read_data: bool,
+    /// Number of the step in progress
+    number: usize,
+    /// Step in progress, with the number of objects it checks
+    current: Option<(CheckStep, usize)>,
+    /// Problems found by the step in progress
+    problems: usize,
+    bar: Option<ProgressBar>,
+}
+
+impl<'a> CheckProgress<'a> {
+    fn new(repo: &'a Repository, steps: usize, read_data: bool) -> Self {
+        Self {
+            repo,
+            steps,
+            read_data,
+            number: 0,
+            current: None,
+            problems: 0,
+            bar: None,
+        }
+    }
+
+    /// Prints the summary of the step in progress.
+    fn finish(&mut self) {
+        if let Some(bar) = self.bar.take() {
+            bar.finish_and_clear();
+        }
+        let Some((step, objects)) = self.current.take() else {
+            return;
+        };
+        let problems = std::mem::take(&mut self.problems);
+
+        match step {
+            CheckStep::Config if problems == 0 => println!("  Config: OK"),
+            CheckStep::Config => println!("  Config: invalid"),
+            CheckStep::Snapshots => {
+                println!("  Snapshots: {} checked, {} errors", objects, problems)
+            }
+            CheckStep::Trees => println!("  Trees: {} checked, {} errors", objects, problems),
+            CheckStep::Chunks if problems == 0 => {
+                println!("  Chunks: {} referenced, all present in index", objects)
+            }
+            CheckStep::Chunks => println!(
+                "  Chunks: {} referenced, {} missing from index",
+                objects, problems
+            ),
+            CheckStep::IndexedPacks if problems == 0 => {
+                println!("  Index references: {} packs, all present", objects)
+            }
+            CheckStep::IndexedPacks => println!(
+                "  Index references: {} packs, {} missing",
+                objects, problems
+            ),
+            CheckStep::Packs if self.read_data => println!(
+                "  Packs: {} checked (read {}), {} errors",
+                objects,
+                HumanBytes(self.repo.bytes_read()),
+                problems
+            ),
+            CheckStep::Packs => println!(
+                "  Packs: {} checked, {} errors (use --read-data to verify their contents)",
+                objects, problems
+            ),
+        }
+    }
+}
+
+impl CheckObserver for CheckProgress<'_> {
+    fn step(&mut self, step: CheckStep, objects: usize) {
+        self.finish();
+        self.number += 1;
+        if step == CheckStep::Config {
+            println!("[{}/{}] Checking config...", self.number, self.steps);
+        } else {
+            println!(
+                "[{}/{}] Checking {} {}...",
+                self.number,
+                self.steps,
+                objects,
+                step.noun()
+            );
+        }
+
+        let bar = ProgressBar::new(objects as u64);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template(&format!(
+                    "{{bar:40}} {{pos}}/{{len}} {} {{msg}}",
+                    step.noun()
+                ))
+                .unwrap(),
+        );
+        self.bar = Some(bar);
+        self.current = Some((step, objects));
+    }
+
+    fn checked(&mut self, objects: usize) {
+        if let Some(bar) = &self.bar {
+            bar.inc(objects as u64);
+            bar.set_message(format!("({} read)", HumanBytes(self.repo.bytes_read())));
+        }
+    }
+
+    fn problem(&mut self, problem: &CheckProblem) {
+        self.problems += 1;
+        warn!("{}", problem);
+    }
+}
+
+/// Compares pack contents with the index, printing each problem together with
+/// a suggested repair. Returns the number of errors and warnings found.
+async fn cross_check_index(repo: &Repository) -> Result<(usize, usize)> {
+    let report = repo.cross_check_index().await?;
+    let mut errors = 0;
+    let mut warnings = 0;
+
+    for (pack_id, e) in &report.unreadable_packs {
+        warn!("Cannot read pack {}: {}", pack_id, e);
+    }
+    if !report.unreadable_packs.is_empty() {
+        errors += report.unreadable_packs.len();
+        println!(
+            "  Unreadable packs: {} (restore them from another copy of the repository; \
+             snapshots using their chunks cannot be restored completely)",
+            report.unreadable_packs.len()
+        );
+    }
+
+    for mismatch in &report.mismatched_entries {
+        match &mismatch.actual {
+            Some(actual) => warn!(
+                "Chunk {}: index says pack {} offset {} length {}, \
+                 pack contents say pack {} offset {} length {}",
+                mismatch.chunk_id.short_string(),
+                mismatch.indexed.pack_id,
+                mismatch.indexed.offset,
+                mismatch.indexed.length,
+                actual.pack_id,
+                actual.offset,
+                actual.length
+            ),
+            None => warn!(
+                "Chunk {}: indexed in pack {} but not stored in any pack",
+                mismatch.chunk_id.short_string(),
+                mismatch.indexed.pack_id
+            ),
+        }
+    }
+    if !report.mismatched_entries.is_empty() {
+        errors += report.mismatched_entries.len();
+        let relocatable = report
+            .mismatched_entries
+            .iter()
+            .filter(|m| m.actual.is_some())
+            .count();
+        println!(
+            "  Wrong index entries: {} ({} can be pointed at the location found in the packs; \
+             the rest are lost and the affected files must be backed up again)",
+            report.mismatched_entries.len(),
+            relocatable
+        );
+    }
+
+    for (chunk_id, pack_id) in &report.unindexed_chunks {
+        warn!(
+            "Chunk {} stored in pack {} but missing from index",
+            chunk_id.short_string(),
+            pack_id
+        );
+    }
+    if !report.unindexed_chunks.is_empty() {
+        warnings += 1;
+        println!(
+            "  Unindexed chunks: {} (stored but unreachable; the next backup re-uploads \
+             any that are still needed, after which their packs can be removed)",
+            report.unindexed_chunks.len()
+        );
+    }
+
+    for (chunk_id, pack_ids) in &report.duplicate_chunks {
+        warn!(
+            "Chunk {} stored in {} packs: {}",
+            chunk_id.short_string(),
+            pack_ids.len(),
+            pack_ids.join(", ")
+        );
+    }
+    if !report.duplicate_chunks.is_empty() {
+        warnings += 1;
+        println!(
+            "  Duplicate chunks: {} (only the indexed copy is used; repacking the listed \
+             packs reclaims the space taken by the others)",
+            report.duplicate_chunks.len()
+        );
+    }
+
+    if report.is_consistent() {
+        println!(
+            "  Index matches {} chunks in {} packs",
+            report.stored_chunks, report.packs_checked
+        );
+    }
+
+    Ok((errors, warnings))
 }
@@ -0,0 +1,293 @@
+//! Config command for checking configuration files and editing the settings
+//! stored in a repository.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap config validate                            # Default search paths
+//! ghostsnap config validate -c /etc/ghostsnap/jobs.toml
+//!
+//! ghostsnap --repo /backup/repo config get             # All settings
+//! ghostsnap --repo /backup/repo config get pack-size
+//! ghostsnap --repo /backup/repo config set compression-level 9
+//! ghostsnap --repo /backup/repo config set exclude '*.tmp' '*/cache/*'
+//! ghostsnap --repo /backup/repo config set keep-daily 7
+//! ghostsnap --repo /backup/repo config unset pack-size
+//! ```
+//!
+//! Repository settings are encrypted and apply to every client of the
+//! repository. Retention keys edit the default rules of the retention policy
+//! (see `policy`).
+
+use crate::config::JobConfig;
+use anyhow::{Result, anyhow};
+use clap::{Args, Subcommand, ValueEnum};
+use ghostsnap_core::{
+    Error, LockManager, LockType, PolicyScope, RepoSettings, Repository, RepositoryLock,
+    RetentionRules,
+};
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct ConfigCommand {
+    #[command(subcommand)]
+    subcommand: ConfigSubcommand,
+}
+
+#[derive(Subcommand)]
+enum ConfigSubcommand {
+    /// Check a job configuration file without connecting to any repository.
+    Validate(ConfigValidateCommand),
+
+    /// Show one or all repository settings.
+    Get(ConfigGetCommand),
+
+    /// Change a repository setting for all clients.
+    Set(ConfigSetCommand),
+
+    /// Reset a repository setting to its default.
+    Unset(ConfigUnsetCommand),
+}
+
+impl ConfigCommand {
+    pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        match &self.subcommand {
+            ConfigSubcommand::Validate(cmd) => cmd.run(),
+            ConfigSubcommand::Get(cmd) => cmd.run(&open(cli).await?).await,
+            ConfigSubcommand::Set(cmd) => cmd.run(&mut open(cli).await?).await,
+            ConfigSubcommand::Unset(cmd) => cmd.run(&mut open(cli).await?).await,
+        }
+    }
+
+    /// Whether the subcommand opens the repository.
+    pub fn opens_repository(&self) -> bool {
+        !matches!(self.subcommand, ConfigSubcommand::Validate(_))
+    }
+}
+
+#[derive(Args)]
+struct ConfigValidateCommand {
+    /// Path to the job configuration file.
+    #[arg(long, short = 'c', env = "GHOSTSNAP_CONFIG")]
+    config: Option<PathBuf>,
+}
+
+impl ConfigValidateCommand {
+    fn run(&self) -> Result<()> {
+        let (config, path) = match &self.config {
+            Some(path) => (JobConfig::load(path)?, path.clone()),
+            None => JobConfig::find_and_load()?,
+        };
+
+        println!("Validating {}", path.display());
+        match config.validate() {
+            Ok(()) => {
+                println!("Configuration OK ({} jobs)", config.jobs.len());
+                Ok(())
+            }
+            Err(Error::InvalidConfig(errors)) => {
+                for error in &errors {
+                    println!("  {}", error);
+                }
+                Err(anyhow!(
+                    "{} invalid settings in {}",
+                    errors.len(),
+                    path.display()
+                ))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+async fn open(cli: &crate::Cli) -> Result<Repository> {
+    let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+
+    let password = crate::password::repository_password(cli)?;
+
+    crate::commands::open_repository(cli, repo_location, &password).await
+}
+
+/// A repository setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SettingKey {
+    /// zlib level (0-9) of new packs
+    CompressionLevel,
+    /// Target size of new packs, e.g. `32M`
+    PackSize,
+    /// Exclude patterns applied to every backup
+    Exclude,
+    /// Default retention: keep the last N snapshots
+    KeepLast,
+    /// Default retention: keep daily snapshots for N days
+    KeepDaily,
+    /// Default retention: keep weekly snapshots for N weeks
+    KeepWeekly,
+    /// Default retention: keep monthly snapshots for N months
+    KeepMonthly,
+    /// Default retention: keep yearly snapshots for N years
+    KeepYearly,
+}
+
+impl SettingKey {
+    fn name(self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
+    /// The retention rule this key edits, if it is a retention key.
+    fn rule(self, rules: &mut RetentionRules) -> Option<&mut Option<u32>> {
+        match self {
+            SettingKey::KeepLast => Some(&mut rules.keep_last),
+            SettingKey::KeepDaily => Some(&mut rules.keep_daily),
+            SettingKey::KeepWeekly => Some(&mut rules.keep_weekly),
+            SettingKey::KeepMonthly => Some(&mut rules.keep_monthly),
+            SettingKey::KeepYearly => Some(&mut rules.keep_yearly),
+            _ => None,
+        }
+    }
+
+    /// The current value, or `None` if unset.
+    async fn get(self, repo: &Repository) -> Result<Option<String>> {
+        let settings = repo.settings();
+        let value = match self {
+            SettingKey::CompressionLevel => settings.compression_level.map(|l| l.to_string()),
+            SettingKey::PackSize => settings.pack_size.map(|s| s.to_string()),
+            SettingKey::Exclude if settings.excludes.is_empty() => None,
+            SettingKey::Exclude => Some(settings.excludes.join(" ")),
+            _ => {
+                let mut rules = repo
+                    .load_retention_policy()
+                    .await?
+                    .map(|policy| policy.default)
+                    .unwrap_or_default();
+                self.rule(&mut rules)
+                    .and_then(|n| *n)
+                    .map(|n| n.to_string())
+            }
+        };
+        Ok(value)
+    }
+
+    /// Stores `values`, or resets the setting if there are none.
+    async fn set(self, repo: &mut Repository, values: &[String]) -> Result<()> {
+        let single = || match values {
+            [] => Ok(None),
+            [value] => Ok(Some(value.as_str())),
+            _ => Err(anyhow!("{} takes a single value", self.name())),
+        };
+
+        let number = |value: Option<&str>| {
+            value
+                .map(|n| n.parse())
+                .transpose()
+                .map_err(|_| anyhow!("{} must be a number", self.name()))
+        };
+
+        let mut settings: RepoSettings = repo.settings().clone();
+        match self {
+            SettingKey::CompressionLevel => settings.compression_level = number(single()?)?,
+            SettingKey::PackSize => {
+                settings.pack_size = single()?.map(crate::commands::parse_size).transpose()?;
+            }
+            SettingKey::Exclude => settings.excludes = values.to_vec(),
+            _ => return self.set_rule(repo, number(single()?)?).await,
+        }
+        repo.save_settings(settings).await?;
+        Ok(())
+    }
+
+    /// Sets a rule of the default retention rules.
+    async fn set_rule(self, repo: &Repository, value: Option<u32>) -> Result<()> {
+        let mut policy = repo.load_retention_policy().await?.unwrap_or_default();
+        let mut rules = policy.default.clone();
+        if let Some(rule) = self.rule(&mut rules) {
+            *rule = value;
+        }
+        policy.set_rules(PolicyScope::Default, rules);
+        if policy.is_empty() {
+            repo.delete_retention_policy().await?;
+        } else {
+            repo.save_retention_policy(&policy).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct ConfigGetCommand {
+    /// Setting to show; all settings if omitted
+    #[arg(value_enum)]
+    key: Option<SettingKey>,
+}
+
+impl ConfigGetCommand {
+    async fn run(&self, repo: &Repository) -> Result<()> {
+        if let Some(key) = self.key {
+            if let Some(value) = key.get(repo).await? {
+                println!("{}", value);
+            }
+            return Ok(());
+        }
+
+        for key in SettingKey::value_variants() {
+            let value = match key.get(repo).await? {
+                Some(value) => value,
+                // The default pack size depends on the backend
+                None if *key == SettingKey::PackSize => {
+                    format!("(default: {})", repo.target_pack_size())
+                }
+                None => "(default)".to_string(),
+            };
+            println!("{:<18} {}", key.name(), value);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct ConfigSetCommand {
+    #[arg(value_enum)]
+    key: SettingKey,
+
+    /// New value; `exclude` takes any number of patterns
+    #[arg(required = true, num_args = 1..)]
+    values: Vec<String>,
+}
+
+impl ConfigSetCommand {
+    async fn run(&self, repo: &mut Repository) -> Result<()> {
+        let _lock = lock(repo).await?;
+        self.key.set(repo, &self.values).await?;
+        println!("Set {} = {}", self.key.name(), self.values.join(" "));
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct ConfigUnsetCommand {
+    #[arg(value_enum)]
+    key: SettingKey,
+}
+
+impl ConfigUnsetCommand {
+    async fn run(&self, repo: &mut Repository) -> Result<()> {
+        let _lock = lock(repo).await?;
+        self.key.set(repo, &[]).await?;
+        println!("Reset {} to its default", self.key.name());
+        Ok(())
+    }
+}
+
+/// Serializes settings changes with other writers on local repositories.
+async fn lock(repo: &Repository) -> Result<Option<RepositoryLock>> {
+    match repo.local_path() {
+        Some(path) => Ok(Some(
+            LockManager::new(path)
+                .acquire(LockType::Exclusive, "config")
+                .await?,
+        )),
+        None => Ok(None),
+    }
+}
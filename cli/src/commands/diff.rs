@@ -1,16 +1,25 @@
 use anyhow::{Result, anyhow};
 use clap::Args;
-use ghostsnap_core::{ChunkID, NodeType, Repository};
-use std::collections::HashMap;
-use std::io::{self, Write};
+use ghostsnap_core::{Change, SnapshotID, diff_trees};
+use indicatif::HumanBytes;
 
 #[derive(Args)]
 pub struct DiffCommand {
-    #[arg(help = "First snapshot ID")]
-    snapshot1: String,
+    #[arg(help = "First snapshot ID (or `latest`)")]
+    snapshot1: Option<String>,
 
-    #[arg(help = "Second snapshot ID")]
-    snapshot2: String,
+    #[arg(help = "Second snapshot ID (or `latest`)")]
+    snapshot2: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "TIME",
+        help = "Use the newest snapshot taken at or before TIME (repeatable; after any snapshot IDs)"
+    )]
+    at: Vec<String>,
+
+    #[command(flatten)]
+    filter: crate::commands::SnapshotFilterArgs,
 
     #[arg(long, help = "Show metadata changes (permissions, ownership)")]
     metadata: bool,
@@ -19,54 +28,29 @@ pub struct DiffCommand {
     json: bool,
 }
 
-#[derive(Debug, Clone)]
-struct FileInfo {
-    #[allow(dead_code)]
-    name: String,
-    node_type: NodeType,
-    size: u64,
-    mode: u32,
-    uid: u32,
-    gid: u32,
-    mtime: i64,
-    chunks: Vec<ChunkID>,
-    link_target: Option<String>,
-}
-
-#[derive(Debug)]
-enum ChangeType {
-    Added,
-    Removed,
-    Modified {
-        old_size: u64,
-        new_size: u64,
-    },
-    TypeChanged {
-        old_type: NodeType,
-        new_type: NodeType,
-    },
-    MetadataChanged,
-}
-
 impl DiffCommand {
     pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
         let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
 
-        let password = cli
-            .password
-            .clone()
-            .or_else(|| {
-                print!("Enter repository password: ");
-                io::stdout().flush().ok()?;
-                rpassword::read_password().ok()
-            })
-            .ok_or_else(|| anyhow!("Password required"))?;
+        let password = crate::password::repository_password(cli)?;
 
-        let repo = Repository::open_at_location(repo_location, &password).await?;
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
 
-        // Resolve snapshot IDs
-        let id1 = self.resolve_snapshot_id(&repo, &self.snapshot1).await?;
-        let id2 = self.resolve_snapshot_id(&repo, &self.snapshot2).await?;
+        // Resolve snapshot IDs, then --at times, in order
+        let filter = self.filter.filter();
+        let mut ids = Vec::new();
+        for snapshot_id in self.snapshot1.iter().chain(&self.snapshot2) {
+            ids.push(crate::commands::resolve_snapshot(&repo, snapshot_id, &filter).await?);
+        }
+        for at in &self.at {
+            ids.push(crate::commands::snapshot_at(&repo, at, &filter).await?);
+        }
+        let [id1, id2] = <[SnapshotID; 2]>::try_from(ids).map_err(|ids| {
+            anyhow!(
+                "Two snapshots required (snapshot IDs or --at), got {}",
+                ids.len()
+            )
+        })?;
 
         // Load snapshots and trees
         let snapshot1 = repo.load_snapshot(&id1).await?;
@@ -75,170 +59,65 @@ impl DiffCommand {
         let tree1 = repo.load_tree(&snapshot1.tree).await?;
         let tree2 = repo.load_tree(&snapshot2.tree).await?;
 
-        // Build file maps
-        let files1: HashMap<String, FileInfo> = tree1
-            .nodes
-            .iter()
-            .map(|n| {
-                (
-                    n.name.clone(),
-                    FileInfo {
-                        name: n.name.clone(),
-                        node_type: n.node_type.clone(),
-                        size: n.size,
-                        mode: n.mode,
-                        uid: n.uid,
-                        gid: n.gid,
-                        mtime: n.mtime,
-                        chunks: n.chunks.iter().map(|c| c.id).collect(),
-                        link_target: n.link_target.clone(),
-                    },
-                )
-            })
-            .collect();
-
-        let files2: HashMap<String, FileInfo> = tree2
-            .nodes
-            .iter()
-            .map(|n| {
-                (
-                    n.name.clone(),
-                    FileInfo {
-                        name: n.name.clone(),
-                        node_type: n.node_type.clone(),
-                        size: n.size,
-                        mode: n.mode,
-                        uid: n.uid,
-                        gid: n.gid,
-                        mtime: n.mtime,
-                        chunks: n.chunks.iter().map(|c| c.id).collect(),
-                        link_target: n.link_target.clone(),
-                    },
-                )
-            })
-            .collect();
-
-        // Find changes
-        let mut changes: Vec<(String, ChangeType)> = Vec::new();
-
-        // Added files (in snapshot2 but not snapshot1)
-        for name in files2.keys() {
-            if !files1.contains_key(name) {
-                changes.push((name.clone(), ChangeType::Added));
-            }
-        }
-
-        // Removed files (in snapshot1 but not snapshot2)
-        for name in files1.keys() {
-            if !files2.contains_key(name) {
-                changes.push((name.clone(), ChangeType::Removed));
-            }
-        }
-
-        // Modified files (in both but different)
-        for (name, info1) in &files1 {
-            if let Some(info2) = files2.get(name) {
-                // Type changed?
-                if info1.node_type != info2.node_type {
-                    changes.push((
-                        name.clone(),
-                        ChangeType::TypeChanged {
-                            old_type: info1.node_type.clone(),
-                            new_type: info2.node_type.clone(),
-                        },
-                    ));
-                    continue;
-                }
-
-                // Content changed?
-                if info1.chunks != info2.chunks {
-                    changes.push((
-                        name.clone(),
-                        ChangeType::Modified {
-                            old_size: info1.size,
-                            new_size: info2.size,
-                        },
-                    ));
-                    continue;
-                }
-
-                // Symlink target changed?
-                if info1.link_target != info2.link_target {
-                    changes.push((
-                        name.clone(),
-                        ChangeType::Modified {
-                            old_size: info1.size,
-                            new_size: info2.size,
-                        },
-                    ));
-                    continue;
-                }
-
-                // Metadata changed?
-                if self.metadata
-                    && (info1.mode != info2.mode
-                        || info1.uid != info2.uid
-                        || info1.gid != info2.gid
-                        || info1.mtime != info2.mtime)
-                {
-                    changes.push((name.clone(), ChangeType::MetadataChanged));
-                }
-            }
-        }
-
-        // Sort changes by name
-        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        let diff = diff_trees(&tree1, &tree2, self.metadata);
+        let changes = &diff.changes;
 
         // Output
         if self.json {
             let json_changes: Vec<_> = changes
                 .iter()
                 .map(|(name, change)| match change {
-                    ChangeType::Added => serde_json::json!({
+                    Change::Added => serde_json::json!({
                         "path": name,
                         "change": "added",
                     }),
-                    ChangeType::Removed => serde_json::json!({
+                    Change::Removed => serde_json::json!({
                         "path": name,
                         "change": "removed",
                     }),
-                    ChangeType::Modified { old_size, new_size } => serde_json::json!({
+                    Change::Modified { old_size, new_size } => serde_json::json!({
                         "path": name,
                         "change": "modified",
                         "old_size": old_size,
                         "new_size": new_size,
                     }),
-                    ChangeType::TypeChanged { old_type, new_type } => serde_json::json!({
+                    Change::TypeChanged { old_type, new_type } => serde_json::json!({
                         "path": name,
                         "change": "type_changed",
                         "old_type": format!("{:?}", old_type),
                         "new_type": format!("{:?}", new_type),
                     }),
-                    ChangeType::MetadataChanged => serde_json::json!({
+                    Change::MetadataChanged => serde_json::json!({
                         "path": name,
                         "change": "metadata",
                     }),
+                    Change::Renamed { from } => serde_json::json!({
+                        "path": name,
+                        "change": "renamed",
+                        "from": from,
+                    }),
                 })
                 .collect();
 
             println!(
                 "{}",
                 serde_json::to_string_pretty(&serde_json::json!({
-                    "snapshot1": &id1[..8],
-                    "snapshot2": &id2[..8],
+                    "snapshot1": id1.short(),
+                    "snapshot2": id2.short(),
                     "changes": json_changes,
+                    "new_bytes": diff.new_bytes,
                 }))?
             );
         } else {
             println!("Comparing snapshots:");
             println!(
                 "  {} ({})",
-                &id1[..8],
+                id1.short(),
                 snapshot1.time.format("%Y-%m-%d %H:%M:%S")
             );
             println!(
                 "  {} ({})",
-                &id2[..8],
+                id2.short(),
                 snapshot2.time.format("%Y-%m-%d %H:%M:%S")
             );
             println!();
@@ -246,30 +125,16 @@ impl DiffCommand {
             if changes.is_empty() {
                 println!("No differences found");
             } else {
-                let added = changes
-                    .iter()
-                    .filter(|(_, c)| matches!(c, ChangeType::Added))
-                    .count();
-                let removed = changes
-                    .iter()
-                    .filter(|(_, c)| matches!(c, ChangeType::Removed))
-                    .count();
-                let modified = changes
-                    .iter()
-                    .filter(|(_, c)| matches!(c, ChangeType::Modified { .. }))
-                    .count();
-                let type_changed = changes
-                    .iter()
-                    .filter(|(_, c)| matches!(c, ChangeType::TypeChanged { .. }))
-                    .count();
-                let metadata = changes
-                    .iter()
-                    .filter(|(_, c)| matches!(c, ChangeType::MetadataChanged))
-                    .count();
+                let added = diff.count(|c| matches!(c, Change::Added));
+                let removed = diff.count(|c| matches!(c, Change::Removed));
+                let modified = diff.count(|c| matches!(c, Change::Modified { .. }));
+                let renamed = diff.count(|c| matches!(c, Change::Renamed { .. }));
+                let type_changed = diff.count(|c| matches!(c, Change::TypeChanged { .. }));
+                let metadata = diff.count(|c| matches!(c, Change::MetadataChanged));
 
                 println!(
-                    "Summary: {} added, {} removed, {} modified",
-                    added, removed, modified
+                    "Summary: {} added, {} removed, {} modified, {} renamed",
+                    added, removed, modified, renamed
                 );
                 if type_changed > 0 {
                     println!("         {} type changed", type_changed);
@@ -277,23 +142,25 @@ impl DiffCommand {
                 if metadata > 0 {
                     println!("         {} metadata changed", metadata);
                 }
+                println!("New data: {}", HumanBytes(diff.new_bytes));
                 println!();
 
-                for (name, change) in &changes {
+                for (name, change) in changes {
                     match change {
-                        ChangeType::Added => println!("+ {}", name),
-                        ChangeType::Removed => println!("- {}", name),
-                        ChangeType::Modified { old_size, new_size } => {
+                        Change::Added => println!("+ {}", name),
+                        Change::Removed => println!("- {}", name),
+                        Change::Modified { old_size, new_size } => {
                             if old_size != new_size {
                                 println!("M {} ({} -> {} bytes)", name, old_size, new_size);
                             } else {
                                 println!("M {}", name);
                             }
                         }
-                        ChangeType::TypeChanged { old_type, new_type } => {
+                        Change::TypeChanged { old_type, new_type } => {
                             println!("T {} ({:?} -> {:?})", name, old_type, new_type);
                         }
-                        ChangeType::MetadataChanged => println!("m {}", name),
+                        Change::MetadataChanged => println!("m {}", name),
+                        Change::Renamed { from } => println!("R {} -> {}", from, name),
                     }
                 }
             }
@@ -301,29 +168,4 @@ impl DiffCommand {
 
         Ok(())
     }
-
-    async fn resolve_snapshot_id(&self, repo: &Repository, snapshot_id: &str) -> Result<String> {
-        if snapshot_id.len() >= 36 {
-            return Ok(snapshot_id.to_string());
-        }
-
-        let all_snapshots = repo.list_snapshots().await?;
-        let matches: Vec<_> = all_snapshots
-            .iter()
-            .filter(|id| id.starts_with(snapshot_id))
-            .collect();
-
-        match matches.len() {
-            0 => Err(anyhow!(
-                "No snapshot found with ID starting with '{}'",
-                snapshot_id
-            )),
-            1 => Ok(matches[0].clone()),
-            _ => Err(anyhow!(
-                "Ambiguous snapshot ID '{}' - matches {} snapshots",
-                snapshot_id,
-                matches.len()
-            )),
-        }
-    }
 }
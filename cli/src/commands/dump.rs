@@ -1,6 +1,18 @@
+//! Dump command for writing snapshot contents to stdout.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap dump 1a2b3c4d var/backups/db.sql | psql mydb   # A file's contents
+//! ghostsnap dump 1a2b3c4d var/www > www.tar                # A directory as tar
+//! ghostsnap dump 1a2b3c4d / | tar -x -C /tmp/restore       # The whole snapshot
+//! ```
+
 use anyhow::{Result, anyhow};
 use clap::Args;
-use ghostsnap_core::{NodeType, Repository};
+use ghostsnap_core::snapshot::Tree;
+use ghostsnap_core::target::{plan_restore, restore_to};
+use ghostsnap_core::{NodeType, Repository, TarTarget};
 use std::io::{self, Write};
 
 #[derive(Args)]
@@ -8,7 +20,7 @@ pub struct DumpCommand {
     #[arg(help = "Snapshot ID (full or short prefix)")]
     snapshot_id: String,
 
-    #[arg(help = "Path to file within snapshot")]
+    #[arg(help = "Path within the snapshot; directories (and `/`) are written as a tar stream")]
     path: String,
 }
 
@@ -16,24 +28,22 @@ impl DumpCommand {
     pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
         let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
 
-        let password = cli
-            .password
-            .clone()
-            .or_else(|| {
-                // For dump, read password from stderr to keep stdout clean
-                eprint!("Enter repository password: ");
-                io::stderr().flush().ok()?;
-                rpassword::read_password().ok()
-            })
-            .ok_or_else(|| anyhow!("Password required"))?;
+        // The prompt goes to the terminal, so the dumped file on stdout stays clean
+        let password = crate::password::repository_password(cli)?;
 
-        let repo = Repository::open_at_location(repo_location, &password).await?;
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
 
         // Resolve snapshot ID
-        let full_snapshot_id = self.resolve_snapshot_id(&repo, &self.snapshot_id).await?;
+        let full_snapshot_id =
+            crate::commands::resolve_snapshot_prefix(&repo, &self.snapshot_id).await?;
         let snapshot = repo.load_snapshot(&full_snapshot_id).await?;
         let tree = repo.load_tree(&snapshot.tree).await?;
 
+        let path = self.path.trim_start_matches('/');
+        if path.is_empty() {
+            return dump_tar(&repo, &tree, &[]).await;
+        }
+
         // Find the file
         let node = tree
             .nodes
@@ -58,6 +68,10 @@ impl DumpCommand {
             node
         };
 
+        if resolved_node.node_type == NodeType::Directory {
+            return dump_tar(&repo, &tree, &[path.to_string()]).await;
+        }
+
         // Check it's a file
         if !matches!(resolved_node.node_type, NodeType::File) {
             if matches!(resolved_node.node_type, NodeType::Symlink) {
@@ -87,29 +101,13 @@ impl DumpCommand {
 
         Ok(())
     }
+}
 
-    async fn resolve_snapshot_id(&self, repo: &Repository, snapshot_id: &str) -> Result<String> {
-        if snapshot_id.len() >= 36 {
-            return Ok(snapshot_id.to_string());
-        }
-
-        let all_snapshots = repo.list_snapshots().await?;
-        let matches: Vec<_> = all_snapshots
-            .iter()
-            .filter(|id| id.starts_with(snapshot_id))
-            .collect();
-
-        match matches.len() {
-            0 => Err(anyhow!(
-                "No snapshot found with ID starting with '{}'",
-                snapshot_id
-            )),
-            1 => Ok(matches[0].clone()),
-            _ => Err(anyhow!(
-                "Ambiguous snapshot ID '{}' - matches {} snapshots",
-                snapshot_id,
-                matches.len()
-            )),
-        }
-    }
+/// Writes the entries at or below `paths` (everything when empty) to stdout
+/// as a tar stream.
+async fn dump_tar(repo: &Repository, tree: &Tree, paths: &[String]) -> Result<()> {
+    let nodes = plan_restore(tree, paths);
+    let mut target = TarTarget::new(io::BufWriter::new(io::stdout()));
+    restore_to(repo, tree, &nodes, &mut target).await?;
+    Ok(())
 }
@@ -1,8 +1,8 @@
 use anyhow::{Result, anyhow};
-use chrono::{DateTime, Datelike, Duration, Utc};
+use chrono::Utc;
 use clap::Args;
-use ghostsnap_core::{LockManager, LockType, Repository};
-use std::collections::{HashMap, HashSet};
+use ghostsnap_core::{Access, ForgetForecast, LockType, PolicyScope, RetentionRules};
+use indicatif::HumanBytes;
 use std::io::{self, Write};
 
 #[derive(Args)]
@@ -22,82 +22,81 @@ pub struct ForgetCommand {
     #[arg(long, help = "Keep yearly snapshots for N years")]
     keep_yearly: Option<u32>,
 
-    #[arg(long, help = "Only consider snapshots with these tags")]
-    tag: Vec<String>,
+    #[arg(
+        long,
+        conflicts_with_all = ["keep_last", "keep_daily", "keep_weekly", "keep_monthly", "keep_yearly"],
+        help = "Apply the retention policy stored in the repository"
+    )]
+    use_policy: bool,
 
-    #[arg(long, help = "Only consider snapshots from this host")]
-    host: Option<String>,
+    #[command(flatten)]
+    filter: crate::commands::SnapshotFilterArgs,
 
     #[arg(long, short = 'n', help = "Dry run - don't actually delete")]
-    dry_run: bool,
+    pub dry_run: bool,
 
     #[arg(long, help = "Actually delete snapshots (prune after forget)")]
     prune: bool,
 }
 
-#[derive(Debug)]
-struct SnapshotInfo {
-    id: String,
-    time: DateTime<Utc>,
-    hostname: String,
-    tags: Vec<String>,
-}
-
 impl ForgetCommand {
     pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
         let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
 
-        let password = cli
-            .password
-            .clone()
-            .or_else(|| {
-                print!("Enter repository password: ");
-                io::stdout().flush().ok()?;
-                rpassword::read_password().ok()
-            })
-            .ok_or_else(|| anyhow!("Password required"))?;
+        let password = crate::password::repository_password(cli)?;
 
-        let repo = Repository::open_at_location(repo_location, &password).await?;
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+        if !self.dry_run {
+            crate::commands::require_access(&repo, Access::Delete, "forget").await?;
+        }
 
-        // Acquire exclusive lock for forget operation
+        // Acquire exclusive lock for forget operation; a dry run only reads
+        let lock_type = if self.dry_run {
+            LockType::Shared
+        } else {
+            LockType::Exclusive
+        };
         let _lock = if let Some(repo_path) = repo.local_path() {
-            let lock_manager = LockManager::new(repo_path);
-            Some(lock_manager.acquire(LockType::Exclusive, "forget").await?)
+            let lock_manager = crate::commands::lock_manager(cli, repo_path)?;
+            Some(lock_manager.acquire(lock_type, "forget").await?)
         } else {
             tracing::warn!("Repository locking not supported for remote repositories");
             None
         };
 
+        let policy = if self.use_policy {
+            let policy = repo.load_retention_policy().await?.ok_or_else(|| {
+                anyhow!("Repository has no retention policy (set one with `ghostsnap policy set`)")
+            })?;
+            println!("Using repository retention policy:");
+            println!("  {}: {}", PolicyScope::Default, policy.default);
+            for (host, rules) in &policy.hosts {
+                println!("  {}: {}", PolicyScope::Host(host.clone()), rules);
+            }
+            for (tag, rules) in &policy.tags {
+                println!("  {}: {}", PolicyScope::Tag(tag.clone()), rules);
+            }
+            println!();
+            Some(policy)
+        } else {
+            None
+        };
+
         // Load all snapshots
         let snapshot_ids = repo.list_snapshots().await?;
         let mut snapshots = Vec::new();
 
         for id in snapshot_ids {
             if let Ok(snapshot) = repo.load_snapshot(&id).await {
-                let info = SnapshotInfo {
-                    id: id.clone(),
-                    time: snapshot.time,
-                    hostname: snapshot.hostname,
-                    tags: snapshot.tags,
-                };
-                snapshots.push(info);
+                snapshots.push(snapshot);
             }
         }
 
-        // Filter by host and tags
+        // Filter by host, tags and paths
+        let filter = self.filter.filter();
         let filtered: Vec<_> = snapshots
             .into_iter()
-            .filter(|s| {
-                if let Some(ref host) = self.host
-                    && &s.hostname != host
-                {
-                    return false;
-                }
-                if !self.tag.is_empty() && !self.tag.iter().any(|t| s.tags.contains(t)) {
-                    return false;
-                }
-                true
-            })
+            .filter(|s| filter.matches(s))
             .collect();
 
         if filtered.is_empty() {
@@ -110,7 +109,13 @@ impl ForgetCommand {
         sorted.sort_by_key(|s| std::cmp::Reverse(s.time));
 
         // Apply retention policies
-        let keep_ids = self.apply_retention_policies(&sorted);
+        let keep_ids = match &policy {
+            Some(policy) => policy.keep(&sorted, Utc::now()),
+            None => {
+                let refs: Vec<_> = sorted.iter().collect();
+                self.rules().keep(&refs, Utc::now())
+            }
+        };
 
         // Determine which to forget
         let forget_ids: Vec<_> = sorted
@@ -127,7 +132,7 @@ impl ForgetCommand {
             if keep_ids.contains(&s.id) {
                 println!(
                     "  {} {} {}",
-                    &s.id[..8],
+                    s.id.short(),
                     s.time.format("%Y-%m-%d %H:%M:%S"),
                     s.hostname
                 );
@@ -139,7 +144,7 @@ impl ForgetCommand {
         for s in &forget_ids {
             println!(
                 "  {} {} {}",
-                &s.id[..8],
+                s.id.short(),
                 s.time.format("%Y-%m-%d %H:%M:%S"),
                 s.hostname
             );
@@ -154,6 +159,22 @@ impl ForgetCommand {
         if self.dry_run {
             println!();
             println!("Dry run - no snapshots were deleted");
+            println!("Would delete:");
+            for s in &forget_ids {
+                println!("  snapshots/{}", s.id);
+            }
+
+            let ids: Vec<_> = forget_ids.iter().map(|s| s.id.clone()).collect();
+            let max_unused = super::prune::DEFAULT_MAX_UNUSED;
+            match repo
+                .forecast_forget(&ids, f64::from(max_unused) / 100.0)
+                .await
+            {
+                Ok(forecast) => print_forecast(&forecast, max_unused),
+                Err(e) => tracing::warn!("Failed to estimate reclaimable space: {}", e),
+            }
+
+            println!();
             println!("Run without --dry-run to actually delete");
         } else {
             println!();
@@ -164,6 +185,13 @@ impl ForgetCommand {
                 repo.delete_snapshot(&s.id).await?;
             }
 
+            if let Err(e) = repo.refresh_stats_cache().await {
+                tracing::warn!("Failed to update stats cache: {}", e);
+            }
+            if let Err(e) = repo.refresh_refcounts().await {
+                tracing::warn!("Failed to update chunk reference counts: {}", e);
+            }
+
             println!(" done");
 
             if self.prune {
@@ -180,97 +208,61 @@ impl ForgetCommand {
         Ok(())
     }
 
-    fn apply_retention_policies(&self, snapshots: &[SnapshotInfo]) -> HashSet<String> {
-        let mut keep = HashSet::new();
-        let now = Utc::now();
-
-        // Keep last N
-        if let Some(n) = self.keep_last {
-            for s in snapshots.iter().take(n as usize) {
-                keep.insert(s.id.clone());
-            }
-        }
-
-        // Keep daily (one per day for N days)
-        if let Some(n) = self.keep_daily {
-            let cutoff = now - Duration::days(n as i64);
-            let mut seen_days: HashMap<String, &SnapshotInfo> = HashMap::new();
-
-            for s in snapshots {
-                if s.time >= cutoff {
-                    let day_key = s.time.format("%Y-%m-%d").to_string();
-                    seen_days.entry(day_key).or_insert(s);
-                }
-            }
-
-            for s in seen_days.values() {
-                keep.insert(s.id.clone());
-            }
-        }
-
-        // Keep weekly (one per week for N weeks)
-        if let Some(n) = self.keep_weekly {
-            let cutoff = now - Duration::weeks(n as i64);
-            let mut seen_weeks: HashMap<String, &SnapshotInfo> = HashMap::new();
-
-            for s in snapshots {
-                if s.time >= cutoff {
-                    let week_key = format!("{}-W{:02}", s.time.year(), s.time.iso_week().week());
-                    seen_weeks.entry(week_key).or_insert(s);
-                }
-            }
-
-            for s in seen_weeks.values() {
-                keep.insert(s.id.clone());
-            }
-        }
-
-        // Keep monthly (one per month for N months)
-        if let Some(n) = self.keep_monthly {
-            let cutoff = now - Duration::days(n as i64 * 31); // Approximate
-            let mut seen_months: HashMap<String, &SnapshotInfo> = HashMap::new();
-
-            for s in snapshots {
-                if s.time >= cutoff {
-                    let month_key = s.time.format("%Y-%m").to_string();
-                    seen_months.entry(month_key).or_insert(s);
-                }
-            }
-
-            for s in seen_months.values() {
-                keep.insert(s.id.clone());
-            }
-        }
-
-        // Keep yearly (one per year for N years)
-        if let Some(n) = self.keep_yearly {
-            let cutoff = now - Duration::days(n as i64 * 365); // Approximate
-            let mut seen_years: HashMap<String, &SnapshotInfo> = HashMap::new();
-
-            for s in snapshots {
-                if s.time >= cutoff {
-                    let year_key = s.time.format("%Y").to_string();
-                    seen_years.entry(year_key).or_insert(s);
-                }
-            }
-
-            for s in seen_years.values() {
-                keep.insert(s.id.clone());
-            }
-        }
-
-        // If no policy specified, keep all
-        if self.keep_last.is_none()
-            && self.keep_daily.is_none()
-            && self.keep_weekly.is_none()
-            && self.keep_monthly.is_none()
-            && self.keep_yearly.is_none()
-        {
-            for s in snapshots {
-                keep.insert(s.id.clone());
-            }
+    fn rules(&self) -> RetentionRules {
+        RetentionRules {
+            keep_last: self.keep_last,
+            keep_daily: self.keep_daily,
+            keep_weekly: self.keep_weekly,
+            keep_monthly: self.keep_monthly,
+            keep_yearly: self.keep_yearly,
         }
+    }
+}
 
-        keep
+fn print_forecast(forecast: &ForgetForecast, max_unused: u32) {
+    println!();
+    println!("Space forecast (forget followed by prune):");
+    println!(
+        "  Unique to forgotten:  {} chunks, {}",
+        forecast.unique_chunks,
+        HumanBytes(forecast.unique_bytes)
+    );
+    println!(
+        "  Still shared:         {} chunks (kept by other snapshots)",
+        forecast.shared_chunks
+    );
+    if forecast.unused_chunks > 0 {
+        println!(
+            "  Already unused:       {} chunks, {}",
+            forecast.unused_chunks,
+            HumanBytes(forecast.unused_bytes)
+        );
+    }
+    println!(
+        "  Packs to delete:      {} ({})",
+        forecast.packs_deleted,
+        HumanBytes(forecast.deleted_bytes)
+    );
+    println!(
+        "  Packs to repack:      {} (read {}, write {})",
+        forecast.packs_repacked,
+        HumanBytes(forecast.repack_read_bytes),
+        HumanBytes(forecast.repack_write_bytes)
+    );
+    if forecast.packs_kept > 0 {
+        println!(
+            "  Not reclaimed:        {} in {} packs under {}% unused",
+            HumanBytes(forecast.kept_unused_bytes),
+            forecast.packs_kept,
+            max_unused
+        );
     }
+    println!(
+        "  Space reclaimed:      {}",
+        HumanBytes(forecast.reclaimed_bytes())
+    );
+    println!(
+        "  Repack I/O:           {}",
+        HumanBytes(forecast.repack_io_bytes())
+    );
 }
@@ -0,0 +1,232 @@
+//! Grep command for searching file contents inside a snapshot.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap grep a1b2c3d4 'listen 8080' etc/nginx
+//! ghostsnap grep a1b2c3d4 -i -l password
+//! ```
+//!
+//! File contents are streamed chunk by chunk, so nothing is restored to disk
+//! and memory stays bounded by the longest line.
+
+use anyhow::{Result, anyhow};
+use clap::Args;
+use ghostsnap_core::{NodeType, Repository, TreeNode};
+use regex::bytes::{Regex, RegexBuilder};
+use std::io::{self, Write};
+use tracing::{debug, warn};
+
+/// Bytes at the start of a file inspected for NUL bytes to detect binary
+/// content, as grep does.
+const BINARY_PROBE_LEN: usize = 8192;
+
+#[derive(Args)]
+pub struct GrepCommand {
+    #[arg(help = "Snapshot ID (full or short prefix)")]
+    snapshot_id: String,
+
+    #[arg(help = "Pattern to search for (regular expression)")]
+    pattern: String,
+
+    #[arg(help = "Only search files at or below this path")]
+    path: Option<String>,
+
+    #[arg(short = 'i', long, help = "Case-insensitive matching")]
+    ignore_case: bool,
+
+    #[arg(short = 'F', long, help = "Treat the pattern as a literal string")]
+    fixed_strings: bool,
+
+    #[arg(short = 'l', long, help = "Only print the paths of matching files")]
+    files_with_matches: bool,
+
+    #[arg(
+        short = 'n',
+        long,
+        help = "Prefix matching lines with their line number"
+    )]
+    line_number: bool,
+
+    #[arg(
+        short = 'a',
+        long,
+        help = "Search binary files as text instead of reporting only whether they match"
+    )]
+    text: bool,
+
+    #[arg(
+        long,
+        default_value = "100M",
+        help = "Skip files larger than this (e.g., 10M, 1G; 0 = no limit)"
+    )]
+    max_size: String,
+}
+
+impl GrepCommand {
+    pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+
+        // The prompt goes to the terminal, so matches on stdout stay clean
+        let password = crate::password::repository_password(cli)?;
+
+        let regex = self.build_regex()?;
+        let max_size = crate::commands::parse_size(&self.max_size)?;
+
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+        let full_snapshot_id =
+            crate::commands::resolve_snapshot_prefix(&repo, &self.snapshot_id).await?;
+        let snapshot = repo.load_snapshot(&full_snapshot_id).await?;
+        let tree = repo.load_tree(&snapshot.tree).await?;
+
+        let prefix = self.path.as_deref().map(|p| p.trim_matches('/'));
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        let mut matching_files = 0u64;
+        let mut skipped_large = 0u64;
+
+        for node in &tree.nodes {
+            if node.node_type != NodeType::File || !is_selected(&node.name, prefix) {
+                continue;
+            }
+            // Hardlinks share the original's chunks
+            let content = match &node.hardlink_target {
+                Some(target) => match tree.nodes.iter().find(|n| n.name == *target) {
+                    Some(original) => original,
+                    None => {
+                        warn!("Hardlink target missing: {} -> {}", node.name, target);
+                        continue;
+                    }
+                },
+                None => node,
+            };
+            if max_size > 0 && content.size > max_size {
+                debug!("Skipping {} ({} bytes)", node.name, content.size);
+                skipped_large += 1;
+                continue;
+            }
+
+            if self
+                .search_file(&repo, &regex, &node.name, content, &mut out)
+                .await?
+            {
+                matching_files += 1;
+            }
+        }
+        out.flush()?;
+
+        if skipped_large > 0 {
+            eprintln!(
+                "Skipped {} files larger than {} (see --max-size)",
+                skipped_large, self.max_size
+            );
+        }
+        if matching_files == 0 {
+            return Err(anyhow!("No matches for '{}'", self.pattern));
+        }
+        Ok(())
+    }
+
+    fn build_regex(&self) -> Result<Regex> {
+        let pattern = if self.fixed_strings {
+            regex::escape(&self.pattern)
+        } else {
+            self.pattern.clone()
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(self.ignore_case)
+            .build()
+            .map_err(|e| anyhow!("Invalid pattern '{}': {}", self.pattern, e))
+    }
+
+    /// Streams `content`'s chunks and prints the lines matching `regex` under
+    /// `path`. Returns whether anything matched.
+    async fn search_file(
+        &self,
+        repo: &Repository,
+        regex: &Regex,
+        path: &str,
+        content: &TreeNode,
+        out: &mut impl Write,
+    ) -> Result<bool> {
+        let mut pending: Vec<u8> = Vec::new();
+        let mut line_no = 0u64;
+        let mut matched = false;
+        let mut binary = false;
+
+        for (i, chunk_ref) in content.chunks.iter().enumerate() {
+            let data = repo.load_chunk(&chunk_ref.id).await?;
+            if i == 0 && !self.text {
+                binary = data[..data.len().min(BINARY_PROBE_LEN)].contains(&0);
+            }
+            pending.extend_from_slice(&data);
+
+            // Search the complete lines; an unterminated tail waits for the
+            // next chunk
+            let Some(end) = pending.iter().rposition(|&b| b == b'\n') else {
+                continue;
+            };
+            for line in pending[..end].split(|&b| b == b'\n') {
+                line_no += 1;
+                if regex.is_match(line) {
+                    matched = true;
+                    if binary || self.files_with_matches {
+                        break;
+                    }
+                    self.print_line(out, path, line_no, line)?;
+                }
+            }
+            pending.drain(..=end);
+
+            if matched && (binary || self.files_with_matches) {
+                pending.clear();
+                break;
+            }
+        }
+
+        if !pending.is_empty() && regex.is_match(&pending) {
+            matched = true;
+            if !binary && !self.files_with_matches {
+                self.print_line(out, path, line_no + 1, &pending)?;
+            }
+        }
+
+        if matched && self.files_with_matches {
+            writeln!(out, "{}", path)?;
+        } else if matched && binary {
+            writeln!(out, "Binary file {} matches", path)?;
+        }
+        Ok(matched)
+    }
+
+    fn print_line(
+        &self,
+        out: &mut impl Write,
+        path: &str,
+        line_no: u64,
+        line: &[u8],
+    ) -> Result<()> {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if self.line_number {
+            write!(out, "{}:{}:", path, line_no)?;
+        } else {
+            write!(out, "{}:", path)?;
+        }
+        out.write_all(line)?;
+        out.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// Returns whether `name` is at or below `prefix`.
+fn is_selected(name: &str, prefix: Option<&str>) -> bool {
+    match prefix {
+        None | Some("") => true,
+        Some(prefix) => {
+            name == prefix
+                || name
+                    .strip_prefix(prefix)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        }
+    }
+}
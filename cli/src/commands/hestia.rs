@@ -0,0 +1,386 @@
+//! HestiaCP integration.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap --repo /backup/ghostsnap hestia backup-system
+//! ghostsnap --repo /backup/ghostsnap snapshots --tag hestia:system
+//! ghostsnap --repo /backup/ghostsnap restore latest --tag hestia:system \
+//!     --target / etc/nginx
+//!
+//! ghostsnap --repo /backup/ghostsnap hestia backup-mail alice
+//! ghostsnap --repo /backup/ghostsnap hestia restore-mail alice info@example.com
+//!
+//! ghostsnap --repo /backup/ghostsnap hestia verify alice bob
+//! ```
+//!
+//! The system snapshot holds the panel's configuration and templates and the
+//! configuration of the services Hestia manages. Its paths are relative to
+//! the root directory, so restoring to `/` puts files back where they were.
+//!
+//! Mail snapshots hold one user's mail directory, with a subtree per mailbox
+//! (`<domain>/<mailbox>`). Maildir messages never change once delivered, so
+//! each backup takes the previous mail snapshot of the user as its parent and
+//! only reads newly delivered messages; the small message files are packed
+//! together rather than stored as an object each.
+//!
+//! `verify` is a restore drill per user: the newest snapshot of each kind
+//! tagged `user:<name>` (mail, websites, ...) is restored into a temporary
+//! directory and checked like `job drill` does.
+
+use super::backup::BackupCommand;
+use super::restore::RestoreCommand;
+use anyhow::{Result, anyhow};
+use clap::{Args, Subcommand};
+use ghostsnap_core::{Repository, SnapshotFilter, SnapshotSummary};
+use std::path::{Component, Path, PathBuf};
+
+/// Tag of system configuration snapshots.
+const SYSTEM_TAG: &str = "hestia:system";
+
+/// Tag of mail snapshots.
+const MAIL_TAG: &str = "hestia:mail";
+
+/// Files in a Maildir that Dovecot rebuilds on demand. Messages still being
+/// delivered (in `tmp/`) are excluded as well.
+const MAIL_EXCLUDES: &[&str] = &[
+    "dovecot.index*",
+    "dovecot.list.index*",
+    "dovecot-uidlist.lock",
+];
+
+/// Files a restored snapshot must contain, by kind. Website snapshots fail
+/// the drill unless some site's index.php comes back.
+const VERIFY_REQUIRE: &[(&str, &str)] = &[("hestia:web", "*/public_html/index.php")];
+
+/// Directories in the system snapshot, relative to the root. Services that
+/// aren't installed are skipped.
+const SYSTEM_PATHS: &[&str] = &[
+    "usr/local/hestia/conf",
+    "usr/local/hestia/data/templates",
+    "etc/nginx",
+    "etc/apache2",
+    "etc/mysql",
+    "etc/php",
+];
+
+/// Hestia command for backing up HestiaCP hosts.
+#[derive(Args)]
+pub struct HestiaCommand {
+    #[command(subcommand)]
+    subcommand: HestiaSubcommand,
+}
+
+#[derive(Subcommand)]
+enum HestiaSubcommand {
+    /// Back up the panel and service configuration as a `hestia:system`
+    /// snapshot.
+    BackupSystem(BackupSystemCommand),
+
+    /// Back up a user's mail as a `hestia:mail` snapshot.
+    BackupMail(BackupMailCommand),
+
+    /// Restore one mailbox from a user's mail snapshot.
+    RestoreMail(RestoreMailCommand),
+
+    /// Test-restore each user's latest snapshots and report PASS or FAIL.
+    Verify(VerifyCommand),
+}
+
+#[derive(Args)]
+struct BackupSystemCommand {
+    /// Root directory of the host (e.g. a mounted disk image)
+    #[arg(long, default_value = "/")]
+    root: PathBuf,
+
+    /// Additional snapshot tags
+    #[arg(long)]
+    tag: Vec<String>,
+
+    /// Exclude patterns (glob syntax)
+    #[arg(long, short = 'e')]
+    exclude: Vec<String>,
+}
+
+#[derive(Args)]
+struct BackupMailCommand {
+    /// Hestia user whose mail to back up
+    user: String,
+
+    /// Directory holding the users' home directories
+    #[arg(long, default_value = "/home")]
+    home: PathBuf,
+}
+
+#[derive(Args)]
+struct RestoreMailCommand {
+    /// Hestia user the mailbox belongs to
+    user: String,
+
+    /// Mailbox as `info@example.com` or `example.com/info`, or a whole
+    /// domain
+    mailbox: String,
+
+    /// Directory holding the users' home directories
+    #[arg(long, default_value = "/home")]
+    home: PathBuf,
+
+    /// Snapshot to restore from (defaults to the user's newest mail snapshot)
+    #[arg(long, default_value = "latest")]
+    snapshot: String,
+
+    /// Directory to restore into (defaults to the user's mail directory)
+    #[arg(long, short = 't')]
+    target: Option<PathBuf>,
+
+    /// Overwrite messages that are still present
+    #[arg(long)]
+    overwrite: bool,
+}
+
+#[derive(Args)]
+struct VerifyCommand {
+    /// Hestia users whose snapshots to test-restore
+    #[arg(required = true)]
+    users: Vec<String>,
+}
+
+impl HestiaCommand {
+    pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        match &self.subcommand {
+            HestiaSubcommand::BackupSystem(cmd) => cmd.run(cli).await,
+            HestiaSubcommand::BackupMail(cmd) => cmd.run(cli).await,
+            HestiaSubcommand::RestoreMail(cmd) => cmd.run(cli).await,
+            HestiaSubcommand::Verify(cmd) => cmd.run(cli).await,
+        }
+    }
+}
+
+impl BackupSystemCommand {
+    async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let paths: Vec<PathBuf> = SYSTEM_PATHS
+            .iter()
+            .map(|path| self.root.join(path))
+            .filter(|path| path.is_dir())
+            .collect();
+        if paths.is_empty() {
+            return Err(anyhow!(
+                "No Hestia configuration found under {}",
+                self.root.display()
+            ));
+        }
+        for path in &paths {
+            println!("Including {}", path.display());
+        }
+
+        let mut args: Vec<String> = paths
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        for tag in std::iter::once(SYSTEM_TAG).chain(self.tag.iter().map(String::as_str)) {
+            args.extend(["--tag".to_string(), tag.to_string()]);
+        }
+        for pattern in &self.exclude {
+            args.extend(["--exclude".to_string(), pattern.clone()]);
+        }
+        if cli.dry_run {
+            args.push("--dry-run".to_string());
+        }
+
+        BackupCommand::from_args(args)?
+            .with_base(self.root.clone())
+            .run(cli)
+            .await
+    }
+}
+
+impl BackupMailCommand {
+    async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let mail_dir = mail_dir(&self.home, &self.user)?;
+        if !mail_dir.is_dir() {
+            return Err(anyhow!(
+                "User {} has no mail directory at {}",
+                self.user,
+                mail_dir.display()
+            ));
+        }
+
+        let mut args = vec![
+            mail_dir.to_string_lossy().into_owned(),
+            "--tag".to_string(),
+            MAIL_TAG.to_string(),
+            "--tag".to_string(),
+            format!("user:{}", self.user),
+        ];
+        let tmp = format!("{}/*/tmp/*", mail_dir.display());
+        for pattern in MAIL_EXCLUDES.iter().copied().chain([tmp.as_str()]) {
+            args.extend(["--exclude".to_string(), pattern.to_string()]);
+        }
+        if cli.dry_run {
+            args.push("--dry-run".to_string());
+        }
+
+        BackupCommand::from_args(args)?
+            .with_latest_parent(mail_filter(&mail_dir))
+            .run(cli)
+            .await
+    }
+}
+
+impl RestoreMailCommand {
+    async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let mail_dir = mail_dir(&self.home, &self.user)?;
+        let mailbox = mailbox_path(&self.mailbox)?;
+        let target = self.target.as_ref().unwrap_or(&mail_dir);
+
+        let mut args = vec![
+            self.snapshot.clone(),
+            "--tag".to_string(),
+            MAIL_TAG.to_string(),
+            "--path".to_string(),
+            mail_dir.to_string_lossy().into_owned(),
+            "--target".to_string(),
+            target.to_string_lossy().into_owned(),
+            mailbox,
+        ];
+        if self.overwrite {
+            args.push("--overwrite".to_string());
+        }
+        if cli.dry_run {
+            args.push("--dry-run".to_string());
+        }
+
+        RestoreCommand::from_args(args)?.run(cli).await
+    }
+}
+
+impl VerifyCommand {
+    async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+        let password = crate::password::repository_password(cli)?;
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+        let summaries = repo.snapshot_summaries(false).await?;
+
+        let mut results = Vec::new();
+        for user in &self.users {
+            println!("User: {}", user);
+            println!("{}", "─".repeat(50));
+            let passed = match verify_user(&repo, &summaries, user).await {
+                Ok(passed) => passed,
+                Err(e) => {
+                    println!("  Error: {:#}", e);
+                    false
+                }
+            };
+            println!("Drill: {}", if passed { "PASS" } else { "FAIL" });
+            println!();
+            results.push((user, passed));
+        }
+
+        let failed: Vec<&str> = results
+            .iter()
+            .filter(|(_, passed)| !passed)
+            .map(|(user, _)| user.as_str())
+            .collect();
+        if results.len() > 1 {
+            for (user, passed) in &results {
+                println!("  {}  {}", if *passed { "PASS" } else { "FAIL" }, user);
+            }
+            println!();
+        }
+
+        if !failed.is_empty() {
+            return Err(anyhow!("Drill failed: {}", failed.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+/// Restores and checks the newest snapshot of each kind tagged with `user`.
+/// Returns whether every check passed.
+async fn verify_user(repo: &Repository, summaries: &[SnapshotSummary], user: &str) -> Result<bool> {
+    let user_tag = format!("user:{}", user);
+    let snapshots: Vec<_> = summaries
+        .iter()
+        .map(|summary| &summary.snapshot)
+        .filter(|snapshot| snapshot.tags.contains(&user_tag))
+        .collect();
+    let mut kinds: Vec<&String> = snapshots
+        .iter()
+        .flat_map(|snapshot| &snapshot.tags)
+        .filter(|tag| tag.starts_with("hestia:"))
+        .collect();
+    kinds.sort();
+    kinds.dedup();
+    if kinds.is_empty() {
+        return Err(anyhow!("No Hestia snapshots tagged {}", user_tag));
+    }
+
+    let mut passed = true;
+    for kind in kinds {
+        let Some(snapshot) = SnapshotFilter::new()
+            .with_tag(kind.as_str())
+            .newest(snapshots.iter().copied(), None)
+        else {
+            continue;
+        };
+        println!(
+            "  {}: snapshot {} ({})",
+            kind,
+            snapshot.short_id(),
+            snapshot.time.format("%Y-%m-%d %H:%M:%S")
+        );
+
+        let require: Vec<String> = VERIFY_REQUIRE
+            .iter()
+            .filter(|(tag, _)| tag == kind)
+            .map(|(_, pattern)| pattern.to_string())
+            .collect();
+        let scratch = tempfile::Builder::new()
+            .prefix("ghostsnap-drill-")
+            .tempdir()?;
+        let checks =
+            crate::drill::restore_and_check(repo, snapshot, scratch.path(), &require).await?;
+        for failure in &checks.failures {
+            println!("  Failed: {}", failure);
+        }
+        passed &= checks.passed();
+    }
+    Ok(passed)
+}
+
+/// The mail directory of `user`, as recorded in the user's mail snapshots.
+fn mail_dir(home: &Path, user: &str) -> Result<PathBuf> {
+    if user.is_empty() || user.contains('/') || user == "." || user == ".." {
+        return Err(anyhow!("Invalid user name '{}'", user));
+    }
+    let mail_dir = home.join(user).join("mail");
+    // Canonical, so that snapshots are found whichever way the home
+    // directory is given
+    Ok(std::fs::canonicalize(&mail_dir).unwrap_or(mail_dir))
+}
+
+/// Mail snapshots of the mail directory `mail_dir`.
+fn mail_filter(mail_dir: &Path) -> SnapshotFilter {
+    SnapshotFilter::new().with_tag(MAIL_TAG).with_path(mail_dir)
+}
+
+/// Path of a mailbox in a mail snapshot: `info@example.com` is stored under
+/// `example.com/info`.
+fn mailbox_path(mailbox: &str) -> Result<String> {
+    let path = match mailbox.split_once('@') {
+        Some((local, domain)) if !local.is_empty() && !domain.is_empty() => {
+            format!("{}/{}", domain, local)
+        }
+        Some(_) => return Err(anyhow!("Invalid mailbox '{}'", mailbox)),
+        None => mailbox.trim_matches('/').to_string(),
+    };
+    let valid = !path.is_empty()
+        && Path::new(&path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    if !valid {
+        return Err(anyhow!("Invalid mailbox '{}'", mailbox));
+    }
+    Ok(path)
+}
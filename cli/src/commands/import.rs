@@ -0,0 +1,646 @@
+//! Import command for legacy tar and duplicity archives.
+//!
+//! Each archive (or duplicity backup set) becomes one snapshot dated when
+//! the archive was made, so years of tarballs end up as a deduplicated
+//! snapshot history.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! # Hestia/cPanel tarballs encrypted with GPG
+//! ghostsnap import --decrypt-cmd "gpg --batch --decrypt" /backup/admin.*.tar.gpg
+//!
+//! # A full duplicity backup set (all volumes)
+//! ghostsnap import --decrypt-cmd "gpg --batch --decrypt" /srv/duplicity/duplicity-full.*
+//! ```
+//!
+//! Archives are decrypted by piping them through `--decrypt-cmd` (run with
+//! `sh -c`); its output, or the archive itself without one, must be a tar
+//! stream, optionally gzip-compressed.
+//!
+//! Snapshot times come from the archive name: duplicity's
+//! `20240115T051003Z`, Hestia's `2024-01-15_05-10-03` or a plain
+//! `2024-01-15`, falling back to the file's modification time.
+//!
+//! Only full duplicity sets can be imported: incremental sets store rdiff
+//! deltas against earlier sets and are skipped.
+//!
+//! With `--remove-archives`, each archive is deleted once its snapshot is
+//! saved and verified in the repository, so that importing can take over
+//! from a local tarball rotation. An archive whose snapshot (same name, time,
+//! hostname and import tags) exists already is verified and deleted rather
+//! than imported again. Hestia names archives alike on every server, so
+//! snapshots of other hosts never count.
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use clap::{Args, ValueEnum};
+use ghostsnap_core::snapshot::{Snapshot, Tree};
+use ghostsnap_core::source::has_contents;
+use ghostsnap_core::{BackupSource, LockType, NodeType, Repository, SourceWriter, TreeNode};
+use indicatif::HumanBytes;
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use tracing::{debug, info, warn};
+
+/// Gzip magic bytes.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ArchiveFormat {
+    /// Detect from the file name
+    Auto,
+    /// Plain tar archive, one snapshot per file
+    Tar,
+    /// Duplicity volumes, one snapshot per backup set
+    Duplicity,
+}
+
+#[derive(Args)]
+pub struct ImportCommand {
+    #[arg(required = true, help = "Archives to import")]
+    archives: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Command that decrypts an archive from stdin to stdout (e.g. \"gpg --batch --decrypt\")"
+    )]
+    decrypt_cmd: Option<String>,
+
+    #[arg(long, value_enum, default_value = "auto", help = "Archive format")]
+    format: ArchiveFormat,
+
+    #[arg(long, help = "Tag to add to every imported snapshot")]
+    tag: Vec<String>,
+
+    #[arg(long, help = "Hostname recorded in the snapshots")]
+    hostname: Option<String>,
+
+    #[arg(
+        long,
+        help = "Snapshot time (RFC 3339) when importing a single archive or set"
+    )]
+    time: Option<DateTime<Utc>>,
+
+    #[arg(
+        long,
+        short = 'n',
+        help = "Show what would be imported without reading archives"
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        long,
+        help = "Delete each archive once its snapshot is verified in the repository; archives imported earlier are deleted instead of imported again"
+    )]
+    remove_archives: bool,
+}
+
+/// Archives that become one snapshot.
+struct ImportSet {
+    /// Name shown in progress output and recorded as the snapshot path
+    name: String,
+    time: DateTime<Utc>,
+    volumes: Vec<PathBuf>,
+    duplicity: bool,
+}
+
+impl ImportCommand {
+    pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let sets = self.plan()?;
+        if sets.is_empty() {
+            println!("Nothing to import");
+            return Ok(());
+        }
+        if self.time.is_some() && sets.len() > 1 {
+            return Err(anyhow!(
+                "--time can only be used when importing a single archive ({} given)",
+                sets.len()
+            ));
+        }
+
+        for set in &sets {
+            println!(
+                "{} {} ({} volume{})",
+                set.time.format("%Y-%m-%d %H:%M:%S UTC"),
+                set.name,
+                set.volumes.len(),
+                if set.volumes.len() == 1 { "" } else { "s" }
+            );
+        }
+        if self.dry_run {
+            println!("DRY RUN - {} snapshots would be created", sets.len());
+            return Ok(());
+        }
+
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+        let password = crate::password::repository_password(cli)?;
+
+        info!("Opening repository at: {}", repo_location.display());
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+
+        let _lock = if let Some(repo_path) = repo.local_path() {
+            let lock_manager = crate::commands::lock_manager(cli, repo_path)?;
+            Some(lock_manager.acquire(LockType::Exclusive, "import").await?)
+        } else {
+            tracing::warn!("Repository locking not supported for remote repositories");
+            None
+        };
+
+        let imported = if self.remove_archives {
+            imported_snapshots(&repo).await?
+        } else {
+            Vec::new()
+        };
+        let hostname = self
+            .hostname
+            .clone()
+            .unwrap_or_else(ghostsnap_core::snapshot::current_hostname);
+
+        let mut writer = SourceWriter::new(&repo);
+        let mut created = 0;
+        for set in &sets {
+            let earlier = imported.iter().find(|snapshot| {
+                snapshot.paths == [PathBuf::from(&set.name)]
+                    && snapshot.time == set.time
+                    && snapshot.hostname == hostname
+                    && self.tag.iter().all(|tag| snapshot.tags.contains(tag))
+            });
+            let snapshot_id = match earlier {
+                Some(snapshot) => {
+                    println!(
+                        "Already imported {} as snapshot {}",
+                        set.name,
+                        snapshot.short_id()
+                    );
+                    snapshot.id.clone()
+                }
+                None => {
+                    let snapshot = self.import_set(&repo, &mut writer, set).await?;
+                    println!("Imported {} as snapshot {}", set.name, snapshot.short_id());
+                    created += 1;
+                    snapshot.id
+                }
+            };
+
+            if self.remove_archives {
+                // The archive may be the only other copy: the snapshot must
+                // survive a crash right after deleting it
+                repo.save_index().await?;
+                repo.verify_snapshot_present(&snapshot_id)
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "Snapshot {} of {} failed verification; keeping the archive",
+                            snapshot_id, set.name
+                        )
+                    })?;
+                for volume in &set.volumes {
+                    std::fs::remove_file(volume)
+                        .with_context(|| format!("Failed to remove {}", volume.display()))?;
+                }
+                println!("Removed {} after verifying its snapshot", set.name);
+            }
+        }
+        repo.save_index().await?;
+
+        if let Err(e) = repo.refresh_stats_cache().await {
+            warn!("Failed to update stats cache: {}", e);
+        }
+
+        println!(
+            "Imported {} snapshots ({} new data)",
+            created,
+            HumanBytes(writer.bytes_added())
+        );
+        Ok(())
+    }
+
+    /// Groups the archives into snapshots, oldest first.
+    fn plan(&self) -> Result<Vec<ImportSet>> {
+        let mut sets: Vec<ImportSet> = Vec::new();
+        let mut duplicity_sets: BTreeMap<String, Vec<(u32, PathBuf)>> = BTreeMap::new();
+
+        for path in &self.archives {
+            let file_name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .ok_or_else(|| anyhow!("Not a file: {}", path.display()))?;
+
+            let duplicity = match self.format {
+                ArchiveFormat::Auto => file_name.starts_with("duplicity-"),
+                ArchiveFormat::Tar => false,
+                ArchiveFormat::Duplicity => true,
+            };
+            if !duplicity {
+                sets.push(ImportSet {
+                    time: self.archive_time(path, &file_name)?,
+                    name: file_name,
+                    volumes: vec![path.clone()],
+                    duplicity: false,
+                });
+                continue;
+            }
+
+            let Some(volume) = DuplicityVolume::parse(&file_name) else {
+                // Manifests and signatures belong to the set but hold no files
+                debug!("Skipping duplicity file {}", file_name);
+                continue;
+            };
+            if volume.incremental {
+                warn!(
+                    "Skipping incremental duplicity volume {} (only full sets can be imported)",
+                    file_name
+                );
+                continue;
+            }
+            duplicity_sets
+                .entry(volume.timestamp)
+                .or_default()
+                .push((volume.number, path.clone()));
+        }
+
+        for (timestamp, mut volumes) in duplicity_sets {
+            volumes.sort();
+            let time = match self.time {
+                Some(time) => time,
+                None => parse_archive_time(&timestamp)
+                    .ok_or_else(|| anyhow!("Invalid duplicity timestamp: {}", timestamp))?,
+            };
+            sets.push(ImportSet {
+                name: format!("duplicity-full.{}", timestamp),
+                time,
+                volumes: volumes.into_iter().map(|(_, path)| path).collect(),
+                duplicity: true,
+            });
+        }
+
+        sets.sort_by_key(|set| set.time);
+        Ok(sets)
+    }
+
+    fn archive_time(&self, path: &Path, file_name: &str) -> Result<DateTime<Utc>> {
+        if let Some(time) = self.time {
+            return Ok(time);
+        }
+        if let Some(time) = parse_archive_time(file_name) {
+            return Ok(time);
+        }
+        let modified = std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        warn!(
+            "No date in archive name {}; using its modification time",
+            file_name
+        );
+        Ok(modified.into())
+    }
+
+    async fn import_set(
+        &self,
+        repo: &Repository,
+        writer: &mut SourceWriter<'_>,
+        set: &ImportSet,
+    ) -> Result<Snapshot> {
+        let mut tree = Tree::new();
+        let mut entries = ArchiveEntries::default();
+        for volume in &set.volumes {
+            info!("Reading {}", volume.display());
+            let (process, input) = self.open_decrypted(volume)?;
+            // The input is dropped when reading ends, so an abandoned decrypt
+            // process gets a broken pipe instead of blocking.
+            let result = read_volume(writer, &mut tree, &mut entries, set.duplicity, input).await;
+            if let Some(mut child) = process {
+                let status = child.wait()?;
+                if !status.success() {
+                    return Err(anyhow!(
+                        "Decrypt command failed for {} ({})",
+                        volume.display(),
+                        status
+                    ));
+                }
+            }
+            result.with_context(|| format!("Failed to import {}", volume.display()))?;
+        }
+
+        // Files duplicity split across volumes are complete only now
+        for (mut node, data) in std::mem::take(&mut entries.multivolume).into_values() {
+            let (chunks, contents) = writer.store(&data).await?;
+            node.size = contents.size;
+            node.chunks = chunks;
+            tree.add_node(node);
+        }
+        writer.flush().await?;
+
+        tree.nodes.sort_by(|a, b| a.name.cmp(&b.name));
+        let tree_id = repo.save_tree(&tree).await?;
+
+        let mut snapshot = Snapshot::new(vec![PathBuf::from(&set.name)], tree_id)
+            .with_tags(self.tag.clone())
+            .with_time(set.time)
+            .with_owner_names(entries.user_names, entries.group_names);
+        if let Some(hostname) = &self.hostname {
+            snapshot.hostname = hostname.clone();
+        }
+        repo.save_snapshot(&snapshot).await?;
+        Ok(snapshot)
+    }
+
+    /// Starts `--decrypt-cmd` on `volume`, or reads it directly without one.
+    fn open_decrypted(&self, volume: &Path) -> Result<(Option<Child>, Box<dyn Read>)> {
+        let input = std::fs::File::open(volume)
+            .with_context(|| format!("Failed to open {}", volume.display()))?;
+        let Some(command) = &self.decrypt_cmd else {
+            return Ok((None, Box::new(input)));
+        };
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(input)
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run decrypt command: {}", command))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("Decrypt command has no output"))?;
+        Ok((Some(child), Box::new(stdout)))
+    }
+}
+
+/// Snapshots that earlier imports may have made: those of a single path.
+async fn imported_snapshots(repo: &Repository) -> Result<Vec<Snapshot>> {
+    let mut imported = Vec::new();
+    for snapshot_id in repo.list_snapshots().await? {
+        let snapshot = repo.load_snapshot(&snapshot_id).await?;
+        if snapshot.paths.len() == 1 {
+            imported.push(snapshot);
+        }
+    }
+    Ok(imported)
+}
+
+/// Adds the entries of one (decrypted) tar stream to `tree`.
+async fn read_volume(
+    writer: &mut SourceWriter<'_>,
+    tree: &mut Tree,
+    entries: &mut ArchiveEntries,
+    duplicity: bool,
+    input: Box<dyn Read>,
+) -> Result<()> {
+    let mut input = BufReader::new(input);
+    let stream: Box<dyn Read> = if starts_with(&mut input, &GZIP_MAGIC)? {
+        Box::new(flate2::read::GzDecoder::new(input))
+    } else {
+        Box::new(input)
+    };
+
+    let mut archive = tar::Archive::new(stream);
+    let mut source = TarSource {
+        entries: archive.entries()?,
+        current: None,
+        duplicity,
+        collected: entries,
+    };
+    writer.add_source(&mut source, tree, &mut ()).await?;
+    Ok(())
+}
+
+/// The entries of one tar stream. Pieces of files duplicity split across
+/// volumes are collected rather than returned.
+struct TarSource<'a, 'b> {
+    entries: tar::Entries<'a, Box<dyn Read>>,
+    current: Option<tar::Entry<'a, Box<dyn Read>>>,
+    duplicity: bool,
+    collected: &'b mut ArchiveEntries,
+}
+
+impl BackupSource for TarSource<'_, '_> {
+    fn next_entry(&mut self) -> ghostsnap_core::Result<Option<TreeNode>> {
+        self.current = None;
+        for entry in self.entries.by_ref() {
+            let mut entry = entry?;
+            let path = entry.path()?.to_string_lossy().to_string();
+
+            let (name, piece) = if self.duplicity {
+                match duplicity_path(&path) {
+                    Some(DuplicityPath::Snapshot(name)) => (name, false),
+                    Some(DuplicityPath::Piece(name)) => (name, true),
+                    None => {
+                        debug!("Skipping duplicity entry {}", path);
+                        continue;
+                    }
+                }
+            } else {
+                (normalize(&path), false)
+            };
+            if name.is_empty() {
+                continue;
+            }
+
+            let header = entry.header();
+            self.collected.record_owner(header);
+            let Some(node) = node_from_header(&name, header)? else {
+                debug!("Skipping unsupported tar entry {}", path);
+                continue;
+            };
+
+            if piece {
+                let mut data = Vec::new();
+                if has_contents(&node) {
+                    entry.read_to_end(&mut data)?;
+                }
+                // Every piece carries the file's metadata; keep the first
+                let (_, buffer) = self
+                    .collected
+                    .multivolume
+                    .entry(name)
+                    .or_insert_with(|| (node, Vec::new()));
+                buffer.extend_from_slice(&data);
+                continue;
+            }
+
+            self.current = Some(entry);
+            return Ok(Some(node));
+        }
+        Ok(None)
+    }
+
+    fn open_entry(&mut self) -> ghostsnap_core::Result<Box<dyn Read + '_>> {
+        let entry = self
+            .current
+            .as_mut()
+            .ok_or_else(|| ghostsnap_core::Error::Other("No entry to open".to_string()))?;
+        Ok(Box::new(entry))
+    }
+}
+
+/// Entries collected from the volumes of one import set.
+#[derive(Default)]
+struct ArchiveEntries {
+    /// Files split across duplicity volumes, joined in volume order
+    multivolume: BTreeMap<String, (TreeNode, Vec<u8>)>,
+    user_names: BTreeMap<u32, String>,
+    group_names: BTreeMap<u32, String>,
+}
+
+impl ArchiveEntries {
+    fn record_owner(&mut self, header: &tar::Header) {
+        if let (Ok(uid), Ok(Some(name))) = (header.uid(), header.username())
+            && !name.is_empty()
+        {
+            self.user_names
+                .entry(uid as u32)
+                .or_insert_with(|| name.to_string());
+        }
+        if let (Ok(gid), Ok(Some(name))) = (header.gid(), header.groupname())
+            && !name.is_empty()
+        {
+            self.group_names
+                .entry(gid as u32)
+                .or_insert_with(|| name.to_string());
+        }
+    }
+}
+
+/// Converts a tar header; returns `None` for entry types that are not kept.
+fn node_from_header(name: &str, header: &tar::Header) -> ghostsnap_core::Result<Option<TreeNode>> {
+    let mut link_target = None;
+    let mut hardlink_target = None;
+    let mut device = None;
+    let node_type = match header.entry_type() {
+        tar::EntryType::Regular | tar::EntryType::Continuous => NodeType::File,
+        tar::EntryType::Directory => NodeType::Directory,
+        tar::EntryType::Symlink => {
+            link_target = header
+                .link_name()?
+                .map(|target| target.to_string_lossy().to_string());
+            NodeType::Symlink
+        }
+        tar::EntryType::Link => {
+            hardlink_target = header
+                .link_name()?
+                .map(|target| normalize(&target.to_string_lossy()));
+            NodeType::File
+        }
+        tar::EntryType::Char | tar::EntryType::Block => {
+            if let (Ok(Some(major)), Ok(Some(minor))) =
+                (header.device_major(), header.device_minor())
+            {
+                device = Some(ghostsnap_core::DeviceNumber { major, minor });
+            }
+            if header.entry_type() == tar::EntryType::Char {
+                NodeType::CharDevice
+            } else {
+                NodeType::BlockDevice
+            }
+        }
+        tar::EntryType::Fifo => NodeType::Fifo,
+        _ => return Ok(None),
+    };
+
+    Ok(Some(TreeNode {
+        name: name.to_string(),
+        node_type,
+        mode: header.mode()? & 0o7777,
+        uid: header.uid()? as u32,
+        gid: header.gid()? as u32,
+        size: 0,
+        mtime: header.mtime()? as i64,
+        link_target,
+        subtree_id: None,
+        chunks: Vec::new(),
+        xattr: None,
+        sparse_holes: None,
+        inode: None,
+        nlink: None,
+        hardlink_target,
+        device,
+    }))
+}
+
+/// Where a duplicity archive entry belongs.
+enum DuplicityPath {
+    /// A complete entry: `snapshot/<path>`
+    Snapshot(String),
+    /// A numbered piece of a file split across volumes:
+    /// `multivol_snapshot/<path>/<n>`
+    Piece(String),
+}
+
+fn duplicity_path(path: &str) -> Option<DuplicityPath> {
+    let path = normalize(path);
+    if let Some(rest) = path.strip_prefix("snapshot") {
+        return Some(DuplicityPath::Snapshot(normalize(rest)));
+    }
+    let rest = normalize(path.strip_prefix("multivol_snapshot")?);
+    let (name, number) = rest.rsplit_once('/')?;
+    number
+        .bytes()
+        .all(|b| b.is_ascii_digit())
+        .then(|| DuplicityPath::Piece(name.to_string()))
+}
+
+/// Parsed name of a duplicity volume:
+/// `duplicity-full.<time>.vol<n>.difftar[.gz|.gpg]` or
+/// `duplicity-inc.<from>.to.<time>.vol<n>.difftar[.gz|.gpg]`.
+struct DuplicityVolume {
+    incremental: bool,
+    timestamp: String,
+    number: u32,
+}
+
+impl DuplicityVolume {
+    fn parse(file_name: &str) -> Option<Self> {
+        let parts: Vec<&str> = file_name.split('.').collect();
+        let incremental = match *parts.first()? {
+            "duplicity-full" => false,
+            "duplicity-inc" => true,
+            _ => return None,
+        };
+        let volume_index = parts.iter().position(|p| p.starts_with("vol"))?;
+        if parts.get(volume_index + 1) != Some(&"difftar") {
+            return None;
+        }
+        let number = parts[volume_index][3..].parse().ok()?;
+        let timestamp = parts.get(volume_index.checked_sub(1)?)?.to_string();
+        Some(Self {
+            incremental,
+            timestamp,
+            number,
+        })
+    }
+}
+
+/// Date and time formats found in archive names, with their lengths.
+const ARCHIVE_TIME_FORMATS: &[(&str, usize)] = &[("%Y%m%dT%H%M%SZ", 16), ("%Y-%m-%d_%H-%M-%S", 19)];
+
+/// Finds a date in an archive name, preferring a full date and time over a
+/// plain date (taken as midnight UTC).
+fn parse_archive_time(name: &str) -> Option<DateTime<Utc>> {
+    let slices = |len: usize| (0..name.len()).filter_map(move |start| name.get(start..start + len));
+    for (format, len) in ARCHIVE_TIME_FORMATS {
+        if let Some(time) = slices(*len).find_map(|s| NaiveDateTime::parse_from_str(s, format).ok())
+        {
+            return Some(time.and_utc());
+        }
+    }
+    slices(10)
+        .find_map(|s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok())
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc())
+}
+
+/// Archive paths are relative; drop `./` and leading or trailing slashes.
+fn normalize(path: &str) -> String {
+    path.trim_start_matches("./").trim_matches('/').to_string()
+}
+
+/// Returns whether the buffered input starts with `magic`, without
+/// consuming it.
+fn starts_with(input: &mut BufReader<impl Read>, magic: &[u8]) -> Result<bool> {
+    use std::io::BufRead;
+    Ok(input.fill_buf()?.starts_with(magic))
+}
@@ -1,11 +1,19 @@
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
 use clap::{Args, ValueEnum};
-use ghostsnap_backends::{AzureBackend, Backend, LocalBackend, S3SseConfig, SseType};
+use ghostsnap_backends::{AzureBackend, Backend, S3SseConfig, SseType};
+use ghostsnap_core::AccessTier;
+use ghostsnap_core::CryptoProfile;
+use ghostsnap_core::EncryptionLayer;
+use ghostsnap_core::HashAlgorithm;
+use ghostsnap_core::PasswordKey;
+use ghostsnap_core::RepoConfig;
 use ghostsnap_core::Repository;
+use ghostsnap_core::S3Checksum;
 use ghostsnap_core::S3RepoSse;
 use ghostsnap_core::storage::{AzureLocation, RcloneLocation, RepositoryLocation, S3Location};
-use std::io::{self, Write};
-use tracing::info;
+use std::io::Write;
+use std::path::Path;
+use tracing::{info, warn};
 
 #[derive(Debug, Clone, Copy, ValueEnum, Default)]
 pub enum S3SseType {
@@ -59,6 +67,20 @@ pub struct InitCommand {
     #[arg(long, help = "KMS key ID for SSE-KMS encryption")]
     sse_kms_key_id: Option<String>,
 
+    #[arg(
+        long = "storage-class",
+        value_name = "TYPE=CLASS",
+        help = "S3 storage class per object type, e.g. metadata=STANDARD or data=STANDARD_IA (repeatable)"
+    )]
+    storage_classes: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "MODE",
+        help = "S3 integrity checksums: default (SDK), sha256 (trailer checksum, verified) or off (for services rejecting checksums)"
+    )]
+    s3_checksum: Option<S3Checksum>,
+
     // Azure options
     #[arg(long, help = "Azure container name")]
     container: Option<String>,
@@ -69,12 +91,67 @@ pub struct InitCommand {
     #[arg(long, help = "Azure blob prefix")]
     azure_prefix: Option<String>,
 
+    #[arg(
+        long = "access-tier",
+        value_name = "TYPE=TIER",
+        help = "Azure access tier per object type, e.g. data=cool or data=archive (repeatable)"
+    )]
+    access_tiers: Vec<String>,
+
     // Rclone options
     #[arg(long, help = "Rclone remote name (e.g., 'myremote', 'gdrive')")]
     remote: Option<String>,
 
     #[arg(long, help = "Rclone path within the remote")]
     rclone_path: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "encryption",
+        help = "Store data unencrypted (checksummed only) and without a password; only for storage that is already encrypted"
+    )]
+    insecure_no_encryption: bool,
+
+    #[arg(
+        long,
+        help = "Where data is encrypted: repo (default), backend (no repository encryption, rely on SSE or an encrypted disk) or both"
+    )]
+    encryption: Option<EncryptionLayer>,
+
+    #[arg(
+        long,
+        help = "Ask for the new password only once, without confirmation or strength prompt"
+    )]
+    no_confirm: bool,
+
+    #[arg(
+        long,
+        value_name = "URI",
+        help = "Copy the chunker polynomial, KDF cost and encryption layer of an existing repository"
+    )]
+    from_repo: Option<String>,
+
+    #[arg(
+        long,
+        requires = "from_repo",
+        help = "Also share the keys and retention policy of --from-repo; its password opens both repositories"
+    )]
+    copy_keys: bool,
+
+    #[arg(
+        long,
+        value_name = "ALGORITHM",
+        conflicts_with = "from_repo",
+        help = "Hash algorithm of chunk IDs: blake3 (default), keyed-blake3 (needs encryption) or sha256"
+    )]
+    hash: Option<HashAlgorithm>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["from_repo", "insecure_no_encryption"],
+        help = "Restrict the repository to FIPS-approved algorithms: AES-256-GCM, PBKDF2-HMAC-SHA256 and SHA-256 chunk IDs"
+    )]
+    fips: bool,
 }
 
 impl InitCommand {
@@ -136,15 +213,71 @@ impl InitCommand {
         };
         let backend_type = backend_type.as_str();
 
-        let password = cli
-            .password
-            .clone()
-            .or_else(|| {
-                print!("Enter repository password: ");
-                io::stdout().flush().ok()?;
-                rpassword::read_password().ok()
-            })
-            .ok_or_else(|| anyhow!("Password required"))?;
+        if !self.storage_classes.is_empty() && !matches!(backend_type, "s3" | "b2" | "minio") {
+            return Err(anyhow!(
+                "--storage-class is only supported for S3-compatible repositories"
+            ));
+        }
+        if self.s3_checksum.is_some() && !matches!(backend_type, "s3" | "b2" | "minio") {
+            return Err(anyhow!(
+                "--s3-checksum is only supported for S3-compatible repositories"
+            ));
+        }
+        if !self.access_tiers.is_empty() && backend_type != "azure" {
+            return Err(anyhow!(
+                "--access-tier is only supported for Azure repositories"
+            ));
+        }
+
+        let source = match &self.from_repo {
+            Some(uri) => {
+                let location =
+                    RepositoryLocation::parse(uri).map_err(|e| anyhow!(e.to_string()))?;
+                let location = crate::commands::apply_proxy(cli, location)?;
+                let config = Repository::read_config(&location).await?;
+                Some((location, config))
+            }
+            None => None,
+        };
+
+        let layer = if self.insecure_no_encryption {
+            EncryptionLayer::Backend
+        } else if let Some(layer) = self.encryption {
+            layer
+        } else if let Some((_, config)) = &source {
+            config.encryption_layer()
+        } else {
+            EncryptionLayer::Repo
+        };
+        let encryption = layer.mode();
+
+        let password = if encryption.is_encrypted() && self.copy_keys {
+            crate::password::password_or_prompt(
+                cli.password.as_deref(),
+                "Enter source repository password: ",
+            )?
+        } else if encryption.is_encrypted() {
+            crate::password::new_password(cli.password.as_deref(), !self.no_confirm)?
+        } else {
+            warn!(
+                "Creating an UNENCRYPTED repository: anyone who can read the storage can read the backups"
+            );
+            String::new()
+        };
+
+        if let Some(path) = &cli.keyfile {
+            if !encryption.is_encrypted() {
+                return Err(anyhow!(
+                    "--keyfile cannot be used without repository encryption"
+                ));
+            }
+            if !path.exists() && !self.copy_keys {
+                create_keyfile(path)?;
+                println!("Generated keyfile: {}", path.display());
+                println!("Keep a copy somewhere safe: the repository cannot be opened without it.");
+            }
+        }
+        let keys = crate::commands::key_provider(&password, cli.keyfile.as_deref())?;
 
         info!("Initializing repository at: {}", repo_input);
 
@@ -153,9 +286,7 @@ impl InitCommand {
                 let repo_location =
                     RepositoryLocation::parse(&repo_input).map_err(|e| anyhow!(e.to_string()))?;
                 match &repo_location {
-                    RepositoryLocation::Local(path) => {
-                        let _backend = LocalBackend::new(path);
-                    }
+                    RepositoryLocation::Local(_) => {}
                     RepositoryLocation::S3(_) => {
                         return Err(anyhow!(
                             "Use `--backend s3` when initializing an S3 repository URI"
@@ -177,11 +308,14 @@ impl InitCommand {
                         ));
                     }
                 }
-                let _repo = Repository::init_at_location(repo_location.clone(), &password).await?;
+                let repo = self
+                    .create_repository(repo_location.clone(), &keys, layer, source.as_ref())
+                    .await?;
                 println!(
                     "Successfully initialized repository at {}",
                     repo_location.display()
                 );
+                check_backend_encryption(&repo);
             }
 
             "s3" | "b2" | "minio" => {
@@ -196,7 +330,10 @@ impl InitCommand {
                         let bucket = self.bucket.clone().ok_or_else(|| {
                             anyhow!("S3 bucket required: pass an s3:/b2:/minio: URI or --bucket")
                         })?;
-                        S3Location::new(bucket, self.prefix.clone().unwrap_or_default())
+                        Box::new(S3Location::new(
+                            bucket,
+                            self.prefix.clone().unwrap_or_default(),
+                        ))
                     }
                     RepositoryLocation::Azure(_) => {
                         return Err(anyhow!("Use `--backend azure` for Azure repository URIs"));
@@ -226,6 +363,17 @@ impl InitCommand {
                 if location.bucket.is_empty() {
                     return Err(anyhow!("S3 bucket required (--bucket or a bucket in the URI)"));
                 }
+                for assignment in &self.storage_classes {
+                    location.storage_classes.set(assignment)?;
+                }
+                if let Some(checksum) = self.s3_checksum {
+                    location.checksum = checksum;
+                }
+                if location.storage_classes.data_is_archived() {
+                    warn!(
+                        "Packs will be stored in an archive class: restore, check --read-data and prune need them restored first"
+                    );
+                }
 
                 // Build SSE configuration
                 let sse_config = S3SseConfig {
@@ -233,9 +381,11 @@ impl InitCommand {
                     kms_key_id: self.sse_kms_key_id.clone(),
                 };
 
-                let repo_location = RepositoryLocation::S3(location.clone());
-                let mut repo =
-                    Repository::init_at_location(repo_location.clone(), &password).await?;
+                let repo_location =
+                    crate::commands::apply_proxy(cli, RepositoryLocation::S3(location.clone()))?;
+                let mut repo = self
+                    .create_repository(repo_location.clone(), &keys, layer, source.as_ref())
+                    .await?;
                 let persisted_sse = match sse_config.sse_type {
                     SseType::None => None,
                     SseType::Aes256 => Some(S3RepoSse {
@@ -273,6 +423,18 @@ impl InitCommand {
                     },
                     sse_info
                 );
+                check_backend_encryption(&repo);
+                let classes = &location.storage_classes;
+                if !classes.is_empty() {
+                    println!(
+                        "Storage classes: metadata={} data={}",
+                        classes.metadata.as_deref().unwrap_or("default"),
+                        classes.data.as_deref().unwrap_or("default")
+                    );
+                }
+                if !location.checksum.is_default() {
+                    println!("Checksums: {}", location.checksum);
+                }
             }
 
             "azure" => {
@@ -288,27 +450,29 @@ impl InitCommand {
 
                 // Validate Azure credentials by creating backend
                 println!("Validating Azure credentials...");
-                let backend = AzureBackend::new(account_name.clone(), container.clone())
+                AzureBackend::new(account_name.clone(), container.clone())
                     .await
                     .map_err(|e| anyhow!("Azure authentication failed: {}", e))?;
 
-                // Set prefix if provided
-                let _backend = if !prefix.is_empty() {
-                    backend.with_prefix(prefix.to_string())
-                } else {
-                    backend
-                };
-
                 // Create Azure location
-                let azure_location = AzureLocation::new(
-                    account_name.clone(),
-                    container.clone(),
-                    prefix.to_string(),
-                );
-                let repo_location = RepositoryLocation::Azure(azure_location);
+                let mut azure_location =
+                    AzureLocation::new(account_name.clone(), container.clone(), prefix.to_string());
+                for assignment in &self.access_tiers {
+                    azure_location.access_tiers.set(assignment)?;
+                }
+                if azure_location.access_tiers.data_is_archived() {
+                    warn!(
+                        "Packs will be stored in the Archive tier: restore, check --read-data and prune need them rehydrated first"
+                    );
+                }
+                let access_tiers = azure_location.access_tiers.clone();
+                let repo_location =
+                    crate::commands::apply_proxy(cli, RepositoryLocation::Azure(azure_location))?;
 
                 // Initialize the repository
-                let _repo = Repository::init_at_location(repo_location.clone(), &password).await?;
+                let repo = self
+                    .create_repository(repo_location.clone(), &keys, layer, source.as_ref())
+                    .await?;
 
                 println!(
                     "Successfully initialized Azure repository at {} (account: {} container: {} prefix: {})",
@@ -317,6 +481,17 @@ impl InitCommand {
                     container,
                     if prefix.is_empty() { "<root>" } else { prefix }
                 );
+                check_backend_encryption(&repo);
+                if !access_tiers.is_empty() {
+                    let describe = |tier: Option<AccessTier>| {
+                        tier.map_or_else(|| "default".to_string(), |tier| tier.to_string())
+                    };
+                    println!(
+                        "Access tiers: metadata={} data={}",
+                        describe(access_tiers.metadata),
+                        describe(access_tiers.data)
+                    );
+                }
             }
 
             "rclone" => {
@@ -340,7 +515,9 @@ impl InitCommand {
                 let repo_location = RepositoryLocation::Rclone(rclone_location);
 
                 // Initialize the repository
-                let _repo = Repository::init_at_location(repo_location.clone(), &password).await?;
+                let repo = self
+                    .create_repository(repo_location.clone(), &keys, layer, source.as_ref())
+                    .await?;
 
                 println!(
                     "Successfully initialized rclone repository at {} (remote: {} path: {})",
@@ -348,6 +525,7 @@ impl InitCommand {
                     remote,
                     if path.is_empty() { "<root>" } else { path }
                 );
+                check_backend_encryption(&repo);
             }
 
             "sftp" => {
@@ -366,7 +544,9 @@ impl InitCommand {
 
                 println!("Connecting to {}@{}...", location.user, location.host);
                 let repo_location = RepositoryLocation::Sftp(location.clone());
-                let _repo = Repository::init_at_location(repo_location.clone(), &password).await?;
+                let repo = self
+                    .create_repository(repo_location.clone(), &keys, layer, source.as_ref())
+                    .await?;
 
                 println!(
                     "Successfully initialized SFTP repository at {} (host: {} user: {} path: {})",
@@ -379,6 +559,7 @@ impl InitCommand {
                         &location.path
                     }
                 );
+                check_backend_encryption(&repo);
             }
 
             _ => {
@@ -389,8 +570,93 @@ impl InitCommand {
             }
         }
 
+        // Repository::init fails unless the marker and config read back intact
+        println!("Verified read-after-write of the repository marker and config");
+        if self.fips {
+            println!("Crypto profile: fips (AES-256-GCM, PBKDF2-HMAC-SHA256, SHA-256 chunk IDs)");
+        } else if let Some(hash) = self.hash.filter(|hash| !hash.is_default()) {
+            println!("Chunk IDs: {}", hash);
+        }
+
+        if let Some((location, config)) = &source {
+            println!(
+                "Copied settings of {}: chunker polynomial {:#x}, KDF cost (memory {} KiB, {} iterations), encryption layer {}",
+                location.display(),
+                config.chunker_polynomial,
+                config.kdf_params.memory,
+                config.kdf_params.iterations,
+                layer
+            );
+            if self.copy_keys {
+                println!(
+                    "Shares keys and retention policy with {}: the same password opens both",
+                    location.display()
+                );
+            }
+        }
+
         Ok(())
     }
+
+    /// Creates the repository, with the settings of `source` (`--from-repo`)
+    /// if given.
+    async fn create_repository(
+        &self,
+        location: RepositoryLocation,
+        keys: &PasswordKey,
+        layer: EncryptionLayer,
+        source: Option<&(RepositoryLocation, RepoConfig)>,
+    ) -> Result<Repository> {
+        let repo = match source {
+            Some((source, _)) => {
+                Repository::init_from(location, keys, source, Some(layer), self.copy_keys).await?
+            }
+            None => {
+                let profile = if self.fips {
+                    CryptoProfile::Fips
+                } else {
+                    CryptoProfile::Standard
+                };
+                let hash = self.hash.unwrap_or_else(|| profile.hash());
+                Repository::init_with_profile(location, keys, layer, hash, profile).await?
+            }
+        };
+        Ok(repo)
+    }
+}
+
+/// Warns when the chosen encryption layer expects the backend to encrypt
+/// data but no backend encryption could be detected.
+fn check_backend_encryption(repo: &Repository) {
+    let layer = repo.encryption_layer();
+    match repo.backend_encryption() {
+        Some(detected) => println!("Backend encryption: {}", detected),
+        None if layer == EncryptionLayer::Backend => warn!(
+            "No backend encryption detected: with --encryption backend the repository is stored in PLAINTEXT unless the storage encrypts it"
+        ),
+        None if layer.expects_backend() => {
+            warn!("No backend encryption detected; only repository encryption is in effect")
+        }
+        None => {}
+    }
+}
+
+/// Writes a new random keyfile readable only by the current user.
+fn create_keyfile(path: &Path) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create keyfile: {}", path.display()))?;
+    file.write_all(&ghostsnap_core::crypto::generate_keyfile())?;
+    file.sync_all()?;
+    Ok(())
 }
 
 /// Infer the backend type from a repository URI scheme.
@@ -11,21 +11,34 @@
 //! ghostsnap job validate nightly-web    # Validate job config
 //! ghostsnap job run nightly-web         # Run a backup job
 //! ghostsnap job run --all               # Run all jobs
+//! ghostsnap job drill --all             # Test-restore every job's latest snapshot
 //! ```
 
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
 use clap::{Args, Subcommand};
 use ghostsnap_core::lock::{LockManager, LockType};
+use ghostsnap_core::snapshot::Snapshot;
 use ghostsnap_core::storage::RepositoryLocation;
-use ghostsnap_core::Repository;
-use globset::{Glob, GlobSet, GlobSetBuilder};
+use ghostsnap_core::{
+    Access, BackupManifest, BandwidthSchedule, NodeType, PasswordKey, ProxyConfig, RateLimiter,
+    Repository, RetentionPolicy, SnapshotCopyStats, SnapshotFilter, SnapshotID,
+};
 use indicatif::{HumanBytes, HumanDuration};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use tracing::{debug, info, warn};
 
+use crate::commands::BackupLimits;
 use crate::config::{JobConfig, ResolvedJob};
-use crate::hooks::{HookConfig, execute_hook_with_output};
+use crate::filter::PathFilter;
+use crate::hooks::{HookConfig, execute_hook, format_hook_result};
 
 /// Job command for running config-driven backups.
 #[derive(Args)]
@@ -51,6 +64,9 @@ enum JobSubcommand {
 
     /// Run a backup job.
     Run(JobRunCommand),
+
+    /// Test-restore a job's latest snapshot and check the restored files.
+    Drill(JobDrillCommand),
 }
 
 impl JobCommand {
@@ -59,7 +75,15 @@ impl JobCommand {
             JobSubcommand::List(cmd) => cmd.run(&self.config).await,
             JobSubcommand::Show(cmd) => cmd.run(&self.config).await,
             JobSubcommand::Validate(cmd) => cmd.run(&self.config).await,
+            JobSubcommand::Run(cmd) if cli.dry_run => {
+                let cmd = JobRunCommand {
+                    dry_run: true,
+                    ..cmd.clone()
+                };
+                cmd.run(&self.config, cli).await
+            }
             JobSubcommand::Run(cmd) => cmd.run(&self.config, cli).await,
+            JobSubcommand::Drill(cmd) => cmd.run(&self.config, cli).await,
         }
     }
 }
@@ -149,12 +173,23 @@ impl JobShowCommand {
         for path in &resolved.paths {
             println!("  - {}", path.display());
         }
+        if let Some(base) = &resolved.base {
+            println!("  (named relative to {})", base.display());
+        }
 
         if !resolved.tags.is_empty() {
             println!();
             println!("Tags: {}", resolved.tags.join(", "));
         }
 
+        if !resolved.include.is_empty() {
+            println!();
+            println!("Includes:");
+            for pattern in &resolved.include {
+                println!("  - {}", pattern);
+            }
+        }
+
         if !resolved.exclude.is_empty() {
             println!();
             println!("Excludes:");
@@ -163,6 +198,30 @@ impl JobShowCommand {
             }
         }
 
+        if !resolved.copy_to.is_empty() {
+            println!();
+            println!("Copy to:");
+            for target in &resolved.copy_to {
+                println!("  - {}", target);
+            }
+        }
+
+        if resolved.limits != BackupLimits::default() {
+            println!();
+            println!("Limits:");
+            if let Some(size) = resolved.limits.max_total_size {
+                println!("  max_total_size: {}", HumanBytes(size));
+            }
+            if let Some(count) = resolved.limits.max_file_count {
+                println!("  max_file_count: {}", count);
+            }
+        }
+
+        if let Some(size) = resolved.pack_size {
+            println!();
+            println!("Pack size: {}", HumanBytes(size));
+        }
+
         if resolved.pre_hook.is_some() || resolved.post_hook.is_some() {
             println!();
             println!("Hooks:");
@@ -200,6 +259,21 @@ impl JobShowCommand {
             }
         }
 
+        if !resolved.drill_require.is_empty() || resolved.drill_command.is_some() {
+            println!();
+            println!("Restore drill:");
+            for pattern in &resolved.drill_require {
+                println!("  require: {}", pattern);
+            }
+            if let Some(ref command) = resolved.drill_command {
+                println!(
+                    "  command: {} (timeout: {:?})",
+                    truncate(command, 50),
+                    resolved.drill_timeout
+                );
+            }
+        }
+
         Ok(())
     }
 }
@@ -238,6 +312,24 @@ impl JobValidateCommand {
             }
         }
 
+        // Check copy targets
+        if !resolved.copy_to.is_empty() {
+            print!("Copy to: ");
+            let invalid: Vec<_> = resolved
+                .copy_to
+                .iter()
+                .filter_map(|target| RepositoryLocation::parse(target).err().map(|e| (target, e)))
+                .collect();
+            if invalid.is_empty() {
+                println!("OK ({} repositories)", resolved.copy_to.len());
+            } else {
+                println!("ERROR");
+                for (target, e) in invalid {
+                    errors.push(format!("Invalid copy target '{}': {}", target, e));
+                }
+            }
+        }
+
         // Check password source
         print!("Password source: ");
         if resolved.password_env.is_some() || resolved.password_file.is_some() {
@@ -261,6 +353,16 @@ impl JobValidateCommand {
             errors.push("No password source configured (password_env or password_file)".to_string());
         }
 
+        if let Some(ref keyfile) = resolved.keyfile {
+            print!("Keyfile: ");
+            if keyfile.exists() {
+                println!("OK ({})", keyfile.display());
+            } else {
+                println!("WARNING");
+                warnings.push(format!("Keyfile does not exist: {}", keyfile.display()));
+            }
+        }
+
         // Check paths
         print!("Paths: ");
         let mut missing_paths = Vec::new();
@@ -294,6 +396,21 @@ impl JobValidateCommand {
             warnings.push(format!("Shell not found: {}", resolved.shell));
         }
 
+        // Check bandwidth schedule
+        if resolved.limit_upload.is_some() || !resolved.bandwidth_windows.is_empty() {
+            print!("Bandwidth: ");
+            match BandwidthSchedule::parse(
+                resolved.limit_upload.as_deref(),
+                &resolved.bandwidth_windows,
+            ) {
+                Ok(_) => println!("OK ({} windows)", resolved.bandwidth_windows.len()),
+                Err(e) => {
+                    println!("ERROR");
+                    errors.push(format!("Invalid bandwidth schedule: {}", e));
+                }
+            }
+        }
+
         // Check hooks
         if resolved.pre_hook.is_some() || resolved.post_hook.is_some() {
             println!("Hooks: configured");
@@ -333,7 +450,7 @@ impl JobValidateCommand {
 
 // === Run Command ===
 
-#[derive(Args)]
+#[derive(Args, Clone)]
 struct JobRunCommand {
     /// Name of the job to run.
     name: Option<String>,
@@ -345,11 +462,41 @@ struct JobRunCommand {
     /// Dry run - don't actually backup.
     #[arg(long, short = 'n')]
     dry_run: bool,
+
+    /// Maximum number of jobs to run at once with --all.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    parallel: u64,
+
+    /// Write a JSON report of the run to this file; `{date}` in the path is
+    /// replaced by the date.
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// CPU scheduling priority (nice value, 19 = lowest); overrides the config.
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        value_parser = clap::value_parser!(i32).range(-20..=19)
+    )]
+    nice: Option<i32>,
+
+    /// I/O scheduling class (Linux only); overrides the config.
+    #[arg(long, value_enum)]
+    io_class: Option<crate::priority::IoClass>,
 }
 
 impl JobRunCommand {
     async fn run(&self, config_path: &Option<PathBuf>, cli: &crate::Cli) -> Result<()> {
         let (config, path) = load_config(config_path)?;
+        config.validate()?;
+        // Shared with the tasks of a parallel run
+        let config = Arc::new(config);
+        let started_at = Utc::now();
+
+        crate::priority::lower_priority(
+            self.nice.or(config.defaults.nice),
+            self.io_class.or(config.defaults.io_class),
+        )?;
 
         if self.all {
             // Run all jobs
@@ -362,24 +509,34 @@ impl JobRunCommand {
             println!("Running {} jobs from {}", job_names.len(), path.display());
             println!();
 
-            let mut success_count = 0;
-            let mut failure_count = 0;
-
-            for name in &job_names {
-                match self.run_single_job(&config, name, cli).await {
-                    Ok(_) => success_count += 1,
-                    Err(e) => {
-                        println!("Job '{}' failed: {}", name, e);
-                        failure_count += 1;
-                    }
+            let reports = if self.parallel > 1 {
+                self.run_parallel(Arc::clone(&config), job_names, cli.verbose)
+                    .await
+            } else {
+                let mut reports = Vec::new();
+                for name in job_names {
+                    reports.push(self.run_reported(&config, &name, cli.verbose, false).await);
                 }
-                println!();
-            }
+                reports
+            };
+
+            let failed: Vec<&str> = reports
+                .iter()
+                .filter(|report| report.error.is_some())
+                .map(|report| report.name.as_str())
+                .collect();
 
-            println!("Completed: {} succeeded, {} failed", success_count, failure_count);
+            println!(
+                "Completed: {} succeeded, {} failed",
+                reports.len() - failed.len(),
+                failed.len()
+            );
 
-            if failure_count > 0 {
-                return Err(anyhow!("{} job(s) failed", failure_count));
+            self.publish_report(&config, started_at, &reports).await?;
+
+            if !failed.is_empty() {
+                println!("Failed: {}", failed.join(", "));
+                return Err(anyhow!("{} job(s) failed", failed.len()));
             }
         } else {
             // Run single job
@@ -388,17 +545,124 @@ impl JobRunCommand {
                 .as_ref()
                 .ok_or_else(|| anyhow!("Job name required. Use --all to run all jobs."))?;
 
-            self.run_single_job(&config, name, cli).await?;
+            let mut out = JobOutput::new(false);
+            let mut report = JobReport::new(name);
+            let start = Instant::now();
+            let result = self
+                .run_single_job(&config, name, cli.verbose, &mut out, &mut report)
+                .await;
+            report.finish(start.elapsed(), &result);
+
+            self.publish_report(&config, started_at, std::slice::from_ref(&report))
+                .await?;
+            result?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the `--report` file and runs the `notify_command`, if set.
+    async fn publish_report(
+        &self,
+        config: &JobConfig,
+        started_at: DateTime<Utc>,
+        jobs: &[JobReport],
+    ) -> Result<()> {
+        let report = report_json(started_at, jobs);
+        let path = self
+            .report
+            .as_ref()
+            .map(|path| report_path(path, started_at));
+        if let Some(ref path) = path {
+            write_report(path, &report)?;
         }
 
+        if let Some(ref command) = config.defaults.notify_command {
+            let shell = config.defaults.shell.as_deref().unwrap_or("/bin/sh");
+            if let Err(e) = notify(command, shell, path.as_deref(), &report, jobs).await {
+                warn!("Failed to run notify command: {:#}", e);
+            }
+        }
         Ok(())
     }
 
+    /// Runs jobs concurrently, at most `--parallel` at a time.
+    ///
+    /// Jobs that share a repository (including `copy_to` targets) run one
+    /// after another, since concurrent writers would overwrite each other's
+    /// index. Each job's report is printed as one block when it finishes.
+    async fn run_parallel(
+        &self,
+        config: Arc<JobConfig>,
+        job_names: Vec<String>,
+        verbose: bool,
+    ) -> Vec<JobReport> {
+        let groups = group_by_repository(&config, job_names);
+        let semaphore = Arc::new(Semaphore::new(self.parallel as usize));
+        let mut tasks = JoinSet::new();
+
+        for group in groups {
+            let cmd = self.clone();
+            let config = Arc::clone(&config);
+            let semaphore = Arc::clone(&semaphore);
+
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let mut reports = Vec::new();
+                for name in group {
+                    reports.push(cmd.run_reported(&config, &name, verbose, true).await);
+                }
+                reports
+            });
+        }
+
+        let mut reports = Vec::new();
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok(group_reports) => reports.extend(group_reports),
+                Err(e) => {
+                    let mut report = JobReport::new("<unknown>");
+                    report.finish(Duration::ZERO, &Err(anyhow!("Job task failed: {}", e)));
+                    reports.push(report);
+                }
+            }
+        }
+        reports
+    }
+
+    /// Runs one job of a `--all` run and prints its report block.
+    async fn run_reported(
+        &self,
+        config: &JobConfig,
+        name: &str,
+        verbose: bool,
+        buffered: bool,
+    ) -> JobReport {
+        let mut out = JobOutput::new(buffered);
+        let mut report = JobReport::new(name);
+        let start = Instant::now();
+
+        let result = self
+            .run_single_job(config, name, verbose, &mut out, &mut report)
+            .await;
+        report.finish(start.elapsed(), &result);
+
+        if let Err(e) = result {
+            out.line(format!("Job '{}' failed: {}", name, e));
+        }
+        out.line("");
+        out.flush();
+
+        report
+    }
+
     async fn run_single_job(
         &self,
         config: &JobConfig,
         name: &str,
-        cli: &crate::Cli,
+        verbose: bool,
+        out: &mut JobOutput,
+        report: &mut JobReport,
     ) -> Result<()> {
         let job = config
             .get_job(name)
@@ -413,9 +677,11 @@ impl JobRunCommand {
 
         let total_start = Instant::now();
 
-        println!("Job: {}", resolved.name);
-        println!("Repository: {}", resolved.repository);
-        println!("{}", "─".repeat(50));
+        report.repository = Some(resolved.repository.clone());
+
+        out.line(format!("Job: {}", resolved.name));
+        out.line(format!("Repository: {}", resolved.repository));
+        out.line("─".repeat(50));
 
         // Resolve password
         let password = resolved.resolve_password()?;
@@ -430,8 +696,7 @@ impl JobRunCommand {
         }
 
         // Parse repository location
-        let repo_location = RepositoryLocation::parse(&resolved.repository)
-            .map_err(|e| anyhow!("Invalid repository: {}", e))?;
+        let (repo_location, proxy) = job_location(&resolved)?;
 
         // Execute pre-hook
         if let Some(ref hook_cmd) = resolved.pre_hook {
@@ -440,9 +705,12 @@ impl JobRunCommand {
                 timeout: resolved.pre_hook_timeout,
                 shell: resolved.shell.clone(),
                 working_dir: resolved.working_directory.clone(),
+                env: vec![("GHOSTSNAP_JOB".to_string(), resolved.name.clone())],
             };
 
-            let result = execute_hook_with_output("Pre-hook", &hook_config, cli.verbose).await?;
+            out.line("  Pre-hook: running...");
+            let result = execute_hook(&hook_config).await?;
+            out.lines(format_hook_result("Pre-hook", &result, verbose));
 
             if !result.success && resolved.stop_on_pre_hook_failure {
                 return Err(anyhow!("Pre-hook failed, aborting job"));
@@ -451,7 +719,16 @@ impl JobRunCommand {
 
         // Open repository
         info!("Opening repository: {}", resolved.repository);
-        let repo = Repository::open_at_location(repo_location.clone(), &password).await?;
+        let keys = crate::commands::key_provider(&password, resolved.keyfile.as_deref())?;
+        let mut repo = Repository::open_with_keys(repo_location.clone(), &keys).await?;
+
+        // Apply bandwidth schedule (shared with copy targets)
+        let schedule =
+            BandwidthSchedule::parse(resolved.limit_upload.as_deref(), &resolved.bandwidth_windows)?;
+        let limiter = schedule
+            .is_limited()
+            .then(|| Arc::new(RateLimiter::new(schedule)));
+        repo.set_rate_limiter(limiter.clone());
 
         // Acquire lock (for local repos)
         let _lock = if let Some(repo_path) = repo.local_path() {
@@ -462,105 +739,260 @@ impl JobRunCommand {
             None
         };
 
+        // Append-only credentials run the backup but skip forget and prune
+        let capabilities = repo.capabilities().await;
+        if !resolved.dry_run {
+            capabilities.require(Access::Append, "backup")?;
+        }
+        let can_delete = capabilities.delete || resolved.dry_run;
+
         // Execute backup
-        let backup_result = self.run_backup(&repo, &resolved, cli).await;
+        let backup_result = self.run_backup(&repo, &resolved, out, report).await;
 
         let snapshot_id = match backup_result {
             Ok(id) => {
-                println!("Backup: OK");
-                println!("  Snapshot: {}", &id[..8]);
+                out.line("Backup: OK");
+                out.line(format!("  Snapshot: {}", id.short()));
+                report.snapshot_id = Some(id.clone());
                 Some(id)
             }
             Err(e) => {
-                println!("Backup: FAILED");
-                println!("  Error: {}", e);
+                out.line("Backup: FAILED");
+                out.line(format!("  Error: {}", e));
                 None
             }
         };
 
+        // Jobs without retention settings fall back to the policy stored in
+        // the repository
+        let stored_policy = if snapshot_id.is_some() && !resolved.has_retention_policy() {
+            repo.load_retention_policy().await.unwrap_or_else(|e| {
+                warn!("Failed to load repository retention policy: {}", e);
+                None
+            })
+        } else {
+            None
+        };
+
         // Execute forget if retention configured
-        if snapshot_id.is_some() && resolved.has_retention_policy() {
-            match self.run_forget(&repo, &resolved).await {
+        let forget = resolved.has_retention_policy() || stored_policy.is_some();
+        if snapshot_id.is_some() && forget && !can_delete {
+            out.line("Forget: SKIPPED (the storage credential cannot delete)");
+        } else if snapshot_id.is_some() && forget {
+            match self
+                .run_forget(&repo, &resolved, stored_policy.as_ref())
+                .await
+            {
                 Ok((kept, removed)) => {
-                    println!("Forget: OK");
-                    println!("  Kept: {}, Removed: {}", kept, removed);
+                    out.line("Forget: OK");
+                    if stored_policy.is_some() {
+                        out.line("  Policy: stored in repository");
+                    }
+                    out.line(format!("  Kept: {}, Removed: {}", kept, removed));
                 }
                 Err(e) => {
-                    println!("Forget: FAILED");
-                    println!("  Error: {}", e);
+                    out.line("Forget: FAILED");
+                    out.line(format!("  Error: {}", e));
                 }
             }
         }
 
         // Execute prune if enabled
-        if snapshot_id.is_some() && resolved.prune {
+        if snapshot_id.is_some() && resolved.prune && !can_delete {
+            out.line("Prune: SKIPPED (the storage credential cannot delete)");
+        } else if snapshot_id.is_some() && resolved.prune {
             match self.run_prune(&repo).await {
                 Ok((packs_removed, bytes_freed)) => {
-                    println!("Prune: OK");
+                    out.line("Prune: OK");
                     if packs_removed > 0 {
-                        println!("  Removed: {} packs ({})", packs_removed, HumanBytes(bytes_freed));
+                        out.line(format!(
+                            "  Removed: {} packs ({})",
+                            packs_removed,
+                            HumanBytes(bytes_freed)
+                        ));
                     } else {
-                        println!("  Nothing to prune");
+                        out.line("  Nothing to prune");
                     }
                 }
                 Err(e) => {
-                    println!("Prune: FAILED");
-                    println!("  Error: {}", e);
+                    out.line("Prune: FAILED");
+                    out.line(format!("  Error: {}", e));
+                }
+            }
+        }
+
+        // Copy the new snapshot to additional repositories; each target
+        // succeeds or fails on its own
+        let mut copy_failures = 0;
+        if let Some(ref id) = snapshot_id {
+            for target in &resolved.copy_to {
+                if resolved.dry_run {
+                    out.line(format!("Copy to {}: skipped (dry run)", target));
+                    continue;
+                }
+                let result = self
+                    .run_copy(&repo, id, target, &keys, proxy.as_ref(), limiter.clone())
+                    .await;
+                match result {
+                    Ok(stats) => {
+                        out.line(format!("Copy to {}: OK", target));
+                        out.line(format!(
+                            "  Chunks: {} copied, {} already present",
+                            stats.chunks_copied, stats.chunks_skipped
+                        ));
+                        report.copies.push(CopyReport {
+                            repository: target.clone(),
+                            status: "ok".to_string(),
+                            error: None,
+                        });
+                    }
+                    Err(e) => {
+                        out.line(format!("Copy to {}: FAILED", target));
+                        out.line(format!("  Error: {}", e));
+                        report.copies.push(CopyReport {
+                            repository: target.clone(),
+                            status: "failed".to_string(),
+                            error: Some(format!("{:#}", e)),
+                        });
+                        copy_failures += 1;
+                    }
                 }
             }
         }
 
         // Execute post-hook (always runs)
         if let Some(ref hook_cmd) = resolved.post_hook {
+            let status = if snapshot_id.is_some() && copy_failures == 0 {
+                "ok"
+            } else {
+                "failed"
+            };
             let hook_config = HookConfig {
                 command: hook_cmd.clone(),
                 timeout: resolved.post_hook_timeout,
                 shell: resolved.shell.clone(),
                 working_dir: resolved.working_directory.clone(),
+                env: vec![
+                    ("GHOSTSNAP_JOB".to_string(), resolved.name.clone()),
+                    ("GHOSTSNAP_STATUS".to_string(), status.to_string()),
+                    (
+                        "GHOSTSNAP_SNAPSHOT_ID".to_string(),
+                        snapshot_id
+                            .as_ref()
+                            .map(|id| id.to_string())
+                            .unwrap_or_default(),
+                    ),
+                ],
             };
 
-            let _ = execute_hook_with_output("Post-hook", &hook_config, cli.verbose).await;
+            out.line("  Post-hook: running...");
+            match execute_hook(&hook_config).await {
+                Ok(result) => out.lines(format_hook_result("Post-hook", &result, verbose)),
+                Err(e) => out.line(format!("  Post-hook: FAILED ({})", e)),
+            }
         }
 
         let total_duration = total_start.elapsed();
-        println!("{}", "─".repeat(50));
-        println!("Total duration: {}", HumanDuration(total_duration));
+        out.line("─".repeat(50));
+        out.line(format!("Total duration: {}", HumanDuration(total_duration)));
 
         if snapshot_id.is_none() {
             return Err(anyhow!("Backup failed"));
         }
+        if copy_failures > 0 {
+            return Err(anyhow!(
+                "Copy failed for {} of {} repositories",
+                copy_failures,
+                resolved.copy_to.len()
+            ));
+        }
 
         Ok(())
     }
 
+    /// Copies a snapshot into one `copy_to` repository, which must use the
+    /// same password (and keyfile, if it has one) as the job's main repository.
+    async fn run_copy(
+        &self,
+        repo: &Repository,
+        snapshot_id: &SnapshotID,
+        target: &str,
+        keys: &PasswordKey,
+        proxy: Option<&ProxyConfig>,
+        limiter: Option<Arc<RateLimiter>>,
+    ) -> Result<SnapshotCopyStats> {
+        let location =
+            RepositoryLocation::parse(target).map_err(|e| anyhow!("Invalid repository: {}", e))?;
+        let location = match proxy {
+            Some(proxy) => location.with_proxy(proxy.clone()),
+            None => location,
+        };
+
+        info!("Opening copy target: {}", target);
+        let mut dst = Repository::open_with_keys(location, keys).await?;
+        dst.set_rate_limiter(limiter);
+
+        let _lock = if let Some(repo_path) = dst.local_path() {
+            let lock_manager = LockManager::new(repo_path);
+            Some(lock_manager.acquire(LockType::Exclusive, "job").await?)
+        } else {
+            warn!("Repository locking not supported for remote repositories");
+            None
+        };
+
+        let stats = repo.copy_snapshot_to(&dst, snapshot_id).await?;
+        if let Err(e) = dst.refresh_stats_cache().await {
+            warn!("Failed to update stats cache: {}", e);
+        }
+
+        Ok(stats)
+    }
+
     async fn run_backup(
         &self,
         repo: &Repository,
         job: &ResolvedJob,
-        _cli: &crate::Cli,
-    ) -> Result<String> {
-        use ghostsnap_core::chunker::Chunker;
-        use ghostsnap_core::pack::PackManager;
-        use ghostsnap_core::snapshot::{Snapshot, Tree};
-        use ghostsnap_core::{ChunkRef, NodeType, TreeNode};
+        out: &mut JobOutput,
+        report: &mut JobReport,
+    ) -> Result<SnapshotID> {
+        use ghostsnap_core::snapshot::Tree;
+        use ghostsnap_core::{SourceWriter, TreeNode};
         use walkdir::WalkDir;
 
         if job.dry_run {
-            println!("  (dry run - skipping actual backup)");
-            return Ok("00000000-0000-0000-0000-000000000000".to_string());
+            out.line("  (dry run - skipping actual backup)");
+            return Ok("00000000-0000-0000-0000-000000000000".parse()?);
         }
 
-        let chunker = Chunker::new_default();
-        let mut pack_manager = PackManager::new(64 * 1024 * 1024);
+        crate::commands::warn_clock_skew(repo).await;
+        crate::commands::warn_storage_clock(repo).await;
+
+        let manifest_key = match &job.manifest {
+            Some(path) => crate::commands::prepare_manifest(path, job.manifest_key.as_deref())?,
+            None => None,
+        };
+        // Content hashes by path, for the manifest
+        let mut content_hashes = HashMap::new();
+
+        let mut writer = SourceWriter::new(repo);
+        if let Some(size) = job.pack_size {
+            writer = writer.with_pack_size(size);
+        }
         let mut tree = Tree::new();
 
         let mut files_new = 0u64;
         let mut files_unchanged = 0u64;
+        let mut entries_skipped = 0u64;
         let mut bytes_processed = 0u64;
-        let mut bytes_added = 0u64;
+        // Files and bytes found so far, checked against the job's limits
+        let mut files_seen = 0u64;
+        let mut bytes_seen = 0u64;
 
-        // Build glob-based exclude matcher (same as backup command)
-        let excludes = self.build_exclude_matcher(&job.exclude)?;
+        // Same include/exclude rules as the backup command
+        let mut exclude_patterns = repo.settings().excludes.clone();
+        exclude_patterns.extend(job.exclude.iter().cloned());
+        let filter = PathFilter::new(&exclude_patterns, &job.include, &job.exclude_if_present)?;
+        let read_limiter = crate::priority::read_ops_limiter(job.max_read_ops);
 
         for source_path in &job.paths {
             if !source_path.exists() {
@@ -571,26 +1003,32 @@ impl JobRunCommand {
                 continue;
             }
 
-            let mut walker = WalkDir::new(source_path).follow_links(false);
+            let mut walker = WalkDir::new(source_path).follow_links(job.follow_symlinks);
 
             // Honor one_file_system option
             if job.one_file_system {
                 walker = walker.same_file_system(true);
             }
 
-            for entry in walker.into_iter().filter_map(|e| e.ok()) {
-                let path = entry.path();
-                let relative = path.strip_prefix(source_path).unwrap_or(path);
-
-                // Check glob-based excludes
-                if self.should_exclude(path, &excludes) {
-                    debug!("Excluding (glob): {}", path.display());
-                    continue;
+            for entry in walker {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        crate::commands::warn_walk_error(&e);
+                        entries_skipped += 1;
+                        continue;
+                    }
+                };
+                if let Some(limiter) = &read_limiter {
+                    limiter.throttle(1).await;
                 }
+                let path = entry.path();
+                let relative = path
+                    .strip_prefix(job.base.as_deref().unwrap_or(source_path))
+                    .unwrap_or(path);
 
-                // Check exclude_if_present markers
-                if self.check_exclude_if_present(path, &job.exclude_if_present) {
-                    debug!("Excluding (marker file): {}", path.display());
+                if let Some(reason) = filter.skip_reason(path, entry.file_type().is_dir()) {
+                    debug!("Excluding ({}): {}", reason.as_str(), path.display());
                     continue;
                 }
 
@@ -612,44 +1050,62 @@ impl JobRunCommand {
                     .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64)
                     .unwrap_or(0);
 
+                let mut device = None;
                 let node_type = if metadata.is_file() {
                     NodeType::File
                 } else if metadata.is_dir() {
                     NodeType::Directory
                 } else if metadata.is_symlink() {
                     NodeType::Symlink
+                } else if let Some((node_type, number)) =
+                    crate::commands::special_file_type(&metadata)
+                {
+                    device = number;
+                    node_type
                 } else {
                     continue;
                 };
 
+                let link_target = if metadata.is_symlink() {
+                    match std::fs::read_link(path) {
+                        Ok(target) => Some(target.to_string_lossy().to_string()),
+                        Err(e) => {
+                            warn!("Cannot read symlink target for {}: {}", path.display(), e);
+                            continue;
+                        }
+                    }
+                } else {
+                    None
+                };
+
                 let mut chunks = Vec::new();
 
                 if metadata.is_file() {
-                    let data = std::fs::read(path)?;
-                    bytes_processed += data.len() as u64;
-
-                    let mut is_new = false;
-                    for chunk in chunker.chunk_data(&data) {
-                        let chunk_id = chunk.id();
-                        if !repo.has_chunk(&chunk_id).await? {
-                            is_new = true;
-                            bytes_added += chunk.data().len() as u64;
-                            if let Some(pack) = pack_manager.add_chunk(chunk_id, chunk.data())? {
-                                repo.save_pack(&pack).await?;
-                                for (cid, ce) in &pack.chunks {
-                                    repo.save_chunk_location(cid, &pack.header.pack_id, ce.offset, ce.length)
-                                        .await?;
-                                }
-                            }
-                        }
-                        chunks.push(ChunkRef {
-                            id: chunk_id,
-                            offset: 0,
-                            length: chunk.data().len() as u32,
-                        });
+                    // Jobs run unattended, so there is no one to ask: stop
+                    // before uploading more than expected
+                    files_seen += 1;
+                    bytes_seen += metadata.len();
+                    if let Some(exceeded) = job.limits.exceeded(files_seen, bytes_seen) {
+                        return Err(anyhow!(
+                            "Backup aborted: {}; raise max_total_size or max_file_count if this is expected",
+                            exceeded
+                        ));
                     }
 
-                    if is_new {
+                    if let Some(limiter) = &read_limiter {
+                        limiter.throttle(1).await;
+                    }
+                    let file = tokio::fs::File::open(path).await?;
+                    let (file_chunks, contents) = writer.store_reader(file).await?;
+                    if job.manifest.is_some() {
+                        content_hashes.insert(
+                            relative.to_string_lossy().to_string(),
+                            contents.content_hash,
+                        );
+                    }
+                    bytes_processed += contents.size;
+                    chunks = file_chunks;
+                    if contents.new_chunks > 0 {
                         files_new += 1;
                     } else {
                         files_unchanged += 1;
@@ -664,7 +1120,7 @@ impl JobRunCommand {
                     gid,
                     size: metadata.len(),
                     mtime,
-                    link_target: None,
+                    link_target,
                     subtree_id: None,
                     chunks,
                     xattr: None,
@@ -672,27 +1128,31 @@ impl JobRunCommand {
                     inode: None,
                     nlink: None,
                     hardlink_target: None,
+                    device,
                 });
             }
         }
 
         // Finish remaining pack
-        if let Some(pack) = pack_manager.finish_current_pack() {
-            repo.save_pack(&pack).await?;
-            for (cid, ce) in &pack.chunks {
-                repo.save_chunk_location(cid, &pack.header.pack_id, ce.offset, ce.length)
-                    .await?;
-            }
-        }
+        writer.flush().await?;
+        let bytes_added = writer.bytes_added();
 
         // Save tree and snapshot
         let tree_id = repo.save_tree(&tree).await?;
-        let mut snapshot = Snapshot::new(job.paths.clone(), tree_id);
+        let (user_names, group_names) = crate::idmap::owner_names(
+            tree.nodes.iter().map(|n| n.uid),
+            tree.nodes.iter().map(|n| n.gid),
+        );
+        let mut snapshot =
+            Snapshot::new(job.paths.clone(), tree_id).with_owner_names(user_names, group_names);
 
         // Apply tags
         if !job.tags.is_empty() {
             snapshot = snapshot.with_tags(job.tags.clone());
         }
+        snapshot = snapshot
+            .with_excludes(job.exclude.clone())
+            .with_includes(job.include.clone());
 
         // Apply hostname
         if let Some(ref hostname) = job.hostname {
@@ -702,20 +1162,58 @@ impl JobRunCommand {
         repo.save_snapshot(&snapshot).await?;
         repo.save_index().await?;
 
-        println!("  Files: {} new, {} unchanged", files_new, files_unchanged);
-        println!(
+        if let Err(e) = repo.refresh_stats_cache().await {
+            warn!("Failed to update stats cache: {}", e);
+        }
+        if let Err(e) = repo.refresh_refcounts().await {
+            warn!("Failed to update chunk reference counts: {}", e);
+        }
+
+        // Written last: the snapshot and index are complete even if this
+        // fails.
+        if let Some(path) = &job.manifest {
+            let manifest = BackupManifest::new(&snapshot, &tree, &content_hashes);
+            let written = manifest.write(path, manifest_key.as_ref()).map_err(|e| {
+                anyhow!(
+                    "Snapshot {} was saved, but writing the manifest failed: {}",
+                    snapshot.short_id(),
+                    e
+                )
+            })?;
+            out.line(format!("  Manifest: {}", written.display()));
+        }
+
+        report.files_new = files_new;
+        report.files_unchanged = files_unchanged;
+        report.entries_skipped = entries_skipped;
+        report.bytes_processed = bytes_processed;
+        report.bytes_added = bytes_added;
+
+        out.line(format!(
+            "  Files: {} new, {} unchanged",
+            files_new, files_unchanged
+        ));
+        if entries_skipped > 0 {
+            out.line(format!(
+                "  Skipped: {} unreadable entries or symlink loops",
+                entries_skipped
+            ));
+        }
+        out.line(format!(
             "  Size: {} processed, {} added",
             HumanBytes(bytes_processed),
             HumanBytes(bytes_added)
-        );
+        ));
 
         Ok(snapshot.id)
     }
 
-    async fn run_forget(&self, repo: &Repository, job: &ResolvedJob) -> Result<(usize, usize)> {
-        use chrono::Datelike;
-        use std::collections::HashSet;
-
+    async fn run_forget(
+        &self,
+        repo: &Repository,
+        job: &ResolvedJob,
+        policy: Option<&RetentionPolicy>,
+    ) -> Result<(usize, usize)> {
         let snapshot_ids = repo.list_snapshots().await?;
         let mut snapshots = Vec::new();
 
@@ -727,70 +1225,10 @@ impl JobRunCommand {
         // Sort by time, newest first
         snapshots.sort_by_key(|s| std::cmp::Reverse(s.time));
 
-        let mut keep_ids: HashSet<String> = HashSet::new();
-
-        // Keep last N
-        if let Some(n) = job.keep_last {
-            for snapshot in snapshots.iter().take(n as usize) {
-                keep_ids.insert(snapshot.id.clone());
-            }
-        }
-
-        // Keep daily
-        if let Some(n) = job.keep_daily {
-            let mut days_seen = HashSet::new();
-            for snapshot in &snapshots {
-                let day = snapshot.time.date_naive();
-                if days_seen.len() < n as usize && !days_seen.contains(&day) {
-                    days_seen.insert(day);
-                    keep_ids.insert(snapshot.id.clone());
-                }
-            }
-        }
-
-        // Keep weekly
-        if let Some(n) = job.keep_weekly {
-            let mut weeks_seen = HashSet::new();
-            for snapshot in &snapshots {
-                let week = snapshot.time.iso_week();
-                let week_key = (week.year(), week.week());
-                if weeks_seen.len() < n as usize && !weeks_seen.contains(&week_key) {
-                    weeks_seen.insert(week_key);
-                    keep_ids.insert(snapshot.id.clone());
-                }
-            }
-        }
-
-        // Keep monthly
-        if let Some(n) = job.keep_monthly {
-            let mut months_seen = HashSet::new();
-            for snapshot in &snapshots {
-                let month_key = (snapshot.time.year(), snapshot.time.month());
-                if months_seen.len() < n as usize && !months_seen.contains(&month_key) {
-                    months_seen.insert(month_key);
-                    keep_ids.insert(snapshot.id.clone());
-                }
-            }
-        }
-
-        // Keep yearly
-        if let Some(n) = job.keep_yearly {
-            let mut years_seen = HashSet::new();
-            for snapshot in &snapshots {
-                let year = snapshot.time.year();
-                if years_seen.len() < n as usize && !years_seen.contains(&year) {
-                    years_seen.insert(year);
-                    keep_ids.insert(snapshot.id.clone());
-                }
-            }
-        }
-
-        // If no policy, keep all
-        if !job.has_retention_policy() {
-            for snapshot in &snapshots {
-                keep_ids.insert(snapshot.id.clone());
-            }
-        }
+        let keep_ids = match policy {
+            Some(policy) => policy.keep(&snapshots, Utc::now()),
+            None => job_keep_ids(job, &snapshots),
+        };
 
         // Delete snapshots not in keep set
         let mut removed = 0;
@@ -801,26 +1239,20 @@ impl JobRunCommand {
             }
         }
 
+        if removed > 0 {
+            if let Err(e) = repo.refresh_stats_cache().await {
+                warn!("Failed to update stats cache: {}", e);
+            }
+            if let Err(e) = repo.refresh_refcounts().await {
+                warn!("Failed to update chunk reference counts: {}", e);
+            }
+        }
+
         Ok((keep_ids.len(), removed))
     }
 
     async fn run_prune(&self, repo: &Repository) -> Result<(usize, u64)> {
-        use std::collections::HashSet;
-
-        // Collect all referenced chunks
-        let mut referenced_chunks: HashSet<ghostsnap_core::ChunkID> = HashSet::new();
-
-        let snapshot_ids = repo.list_snapshots().await?;
-        for snapshot_id in &snapshot_ids {
-            let snapshot = repo.load_snapshot(snapshot_id).await?;
-            let tree = repo.load_tree(&snapshot.tree).await?;
-
-            for node in &tree.nodes {
-                for chunk_ref in &node.chunks {
-                    referenced_chunks.insert(chunk_ref.id);
-                }
-            }
-        }
+        let referenced_chunks = repo.collect_used_chunks().await?;
 
         // Find packs with no referenced chunks
         let all_packs = repo.list_packs().await?;
@@ -834,7 +1266,7 @@ impl JobRunCommand {
             // Check if any chunk in this pack is referenced
             let mut has_referenced = false;
             for (chunk_id, location) in index_guard.iter_chunks() {
-                if &location.pack_id == pack_id && referenced_chunks.contains(chunk_id) {
+                if &location.pack_id == pack_id && referenced_chunks.contains(&chunk_id) {
                     has_referenced = true;
                     break;
                 }
@@ -857,68 +1289,478 @@ impl JobRunCommand {
         // Save index
         repo.save_index().await?;
 
+        if let Err(e) = repo.refresh_stats_cache().await {
+            warn!("Failed to update stats cache: {}", e);
+        }
+
         Ok((packs_to_delete.len(), bytes_freed))
     }
+}
+
+// === Drill Command ===
+
+/// Restores each job's latest snapshot into a scratch directory and checks
+/// it: archives read to the end, database dumps are complete, the job's
+/// `drill_require` files exist and its `drill_command` succeeds.
+#[derive(Args)]
+struct JobDrillCommand {
+    /// Name of the job to drill.
+    name: Option<String>,
+
+    /// Drill all jobs.
+    #[arg(long)]
+    all: bool,
+
+    /// Restore into <DIR>/<job> and keep the files, instead of using a
+    /// temporary directory.
+    #[arg(long, value_name = "DIR")]
+    target: Option<PathBuf>,
+}
 
-    /// Builds a GlobSet from exclude patterns.
-    fn build_exclude_matcher(&self, patterns: &[String]) -> Result<GlobSet> {
-        let mut builder = GlobSetBuilder::new();
+impl JobDrillCommand {
+    async fn run(&self, config_path: &Option<PathBuf>, cli: &crate::Cli) -> Result<()> {
+        let (config, path) = load_config(config_path)?;
+        config.validate()?;
 
-        for pattern in patterns {
-            let glob = Glob::new(pattern)
-                .map_err(|e| anyhow!("Invalid exclude pattern '{}': {}", pattern, e))?;
-            builder.add(glob);
+        let job_names: Vec<String> = if self.all {
+            let mut names: Vec<String> = config.jobs.keys().cloned().collect();
+            names.sort();
+            names
+        } else {
+            vec![
+                self.name
+                    .clone()
+                    .ok_or_else(|| anyhow!("Job name required. Use --all to drill all jobs."))?,
+            ]
+        };
+        if job_names.is_empty() {
+            return Err(anyhow!("No jobs configured in {}", path.display()));
         }
 
-        builder
-            .build()
-            .map_err(|e| anyhow!("Failed to build exclude matcher: {}", e))
-    }
+        let mut results = Vec::new();
+        for name in &job_names {
+            println!("Job: {}", name);
+            println!("{}", "─".repeat(50));
+            let passed = match self.drill_job(&config, name, cli.verbose).await {
+                Ok(passed) => passed,
+                Err(e) => {
+                    println!("  Error: {:#}", e);
+                    false
+                }
+            };
+            println!("Drill: {}", if passed { "PASS" } else { "FAIL" });
+            println!();
+            results.push((name, passed));
+        }
 
-    /// Checks if a path matches any exclude pattern.
-    fn should_exclude(&self, path: &Path, excludes: &GlobSet) -> bool {
-        if excludes.is_empty() {
-            return false;
+        let failed: Vec<&str> = results
+            .iter()
+            .filter(|(_, passed)| !passed)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if results.len() > 1 {
+            for (name, passed) in &results {
+                println!("  {}  {}", if *passed { "PASS" } else { "FAIL" }, name);
+            }
+            println!();
+            println!(
+                "Drills: {} passed, {} failed",
+                results.len() - failed.len(),
+                failed.len()
+            );
         }
 
-        // Check path as-is
-        if excludes.is_match(path) {
-            return true;
+        if !failed.is_empty() {
+            return Err(anyhow!("Drill failed: {}", failed.join(", ")));
         }
+        Ok(())
+    }
 
-        // Also check just the file/dir name
-        if let Some(name) = path.file_name()
-            && excludes.is_match(name)
-        {
-            return true;
+    /// Restores and checks one job's latest snapshot. Returns whether every
+    /// check passed.
+    async fn drill_job(&self, config: &JobConfig, name: &str, verbose: bool) -> Result<bool> {
+        let job = config
+            .get_job(name)
+            .ok_or_else(|| anyhow!("Job '{}' not found", name))?;
+        let resolved = ResolvedJob::resolve(name, job, &config.defaults)?;
+        let password = resolved.resolve_password()?;
+        let (location, _) = job_location(&resolved)?;
+        let keys = crate::commands::key_provider(&password, resolved.keyfile.as_deref())?;
+        let repo = Repository::open_with_keys(location, &keys).await?;
+
+        // The job's own snapshots: same paths and tags, and host if set
+        let filter = SnapshotFilter {
+            hosts: resolved.hostname.iter().cloned().collect(),
+            tags: resolved.tags.clone(),
+            paths: resolved.paths.clone(),
+        };
+        let summaries = repo.snapshot_summaries(false).await?;
+        let snapshot = filter
+            .newest(summaries.iter().map(|summary| &summary.snapshot), None)
+            .ok_or_else(|| anyhow!("No snapshot found matching {}", filter.describe()))?;
+        println!(
+            "  Snapshot: {} ({})",
+            snapshot.short_id(),
+            snapshot.time.format("%Y-%m-%d %H:%M:%S")
+        );
+
+        // Restore into a scratch directory, removed when the drill ends
+        // unless --target was given
+        let scratch;
+        let dir = match &self.target {
+            Some(target) => {
+                let dir = target.join(name);
+                if dir.exists() {
+                    return Err(anyhow!("Drill directory already exists: {}", dir.display()));
+                }
+                dir
+            }
+            None => {
+                scratch = tempfile::Builder::new()
+                    .prefix("ghostsnap-drill-")
+                    .tempdir()?;
+                scratch.path().to_path_buf()
+            }
+        };
+
+        let mut checks =
+            crate::drill::restore_and_check(&repo, snapshot, &dir, &resolved.drill_require).await?;
+
+        if let Some(ref command) = resolved.drill_command {
+            let hook_config = HookConfig {
+                command: command.clone(),
+                timeout: resolved.drill_timeout,
+                shell: resolved.shell.clone(),
+                working_dir: Some(dir.clone()),
+                env: vec![
+                    ("GHOSTSNAP_JOB".to_string(), resolved.name.clone()),
+                    (
+                        "GHOSTSNAP_SNAPSHOT".to_string(),
+                        snapshot.id.as_str().to_string(),
+                    ),
+                    (
+                        "GHOSTSNAP_DRILL_DIR".to_string(),
+                        dir.to_string_lossy().to_string(),
+                    ),
+                ],
+            };
+            let result = execute_hook(&hook_config).await?;
+            for line in format_hook_result("Drill command", &result, verbose) {
+                println!("{}", line);
+            }
+            if !result.success {
+                checks.failures.push("Drill command failed".to_string());
+            }
         }
 
-        false
+        for failure in &checks.failures {
+            println!("  Failed: {}", failure);
+        }
+        if self.target.is_some() {
+            println!("  Restored files kept in {}", dir.display());
+        }
+        Ok(checks.passed())
     }
+}
 
-    /// Checks if directory contains any exclude-if-present marker files.
-    fn check_exclude_if_present(&self, path: &Path, markers: &[String]) -> bool {
-        if markers.is_empty() {
-            return false;
+/// Report lines of one job run. Printed as they are produced, or buffered and
+/// printed as one block when jobs run in parallel.
+struct JobOutput {
+    buffered: bool,
+    lines: Vec<String>,
+}
+
+impl JobOutput {
+    fn new(buffered: bool) -> Self {
+        Self {
+            buffered,
+            lines: Vec::new(),
         }
+    }
 
-        // Only check for directories
-        let dir = if path.is_dir() {
-            path
-        } else if let Some(parent) = path.parent() {
-            parent
+    fn line(&mut self, line: impl Into<String>) {
+        let line = line.into();
+        if self.buffered {
+            self.lines.push(line);
         } else {
-            return false;
-        };
+            println!("{}", line);
+        }
+    }
+
+    fn lines(&mut self, lines: Vec<String>) {
+        for line in lines {
+            self.line(line);
+        }
+    }
+
+    fn flush(&mut self) {
+        let mut stdout = std::io::stdout().lock();
+        for line in self.lines.drain(..) {
+            let _ = writeln!(stdout, "{}", line);
+        }
+    }
+}
+
+/// Machine-readable outcome of one job, written by `job run --report`.
+#[derive(Debug, Default, Serialize)]
+struct JobReport {
+    name: String,
+    repository: Option<String>,
+    /// "ok" or "failed"
+    status: String,
+    snapshot_id: Option<SnapshotID>,
+    files_new: u64,
+    files_unchanged: u64,
+    /// Symlink loops and entries that couldn't be read, left out
+    entries_skipped: u64,
+    bytes_processed: u64,
+    bytes_added: u64,
+    duration_secs: f64,
+    copies: Vec<CopyReport>,
+    error: Option<String>,
+}
+
+/// Outcome of copying a job's snapshot to one `copy_to` repository.
+#[derive(Debug, Serialize)]
+struct CopyReport {
+    repository: String,
+    status: String,
+    error: Option<String>,
+}
+
+impl JobReport {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn finish(&mut self, elapsed: Duration, result: &Result<()>) {
+        self.duration_secs = elapsed.as_secs_f64();
+        match result {
+            Ok(()) => self.status = "ok".to_string(),
+            Err(e) => {
+                self.status = "failed".to_string();
+                self.error = Some(format!("{:#}", e));
+            }
+        }
+    }
+}
+
+/// The JSON report for a `job run`.
+fn report_json(started_at: DateTime<Utc>, jobs: &[JobReport]) -> serde_json::Value {
+    let failed = jobs.iter().filter(|job| job.error.is_some()).count();
+    let finished_at = Utc::now();
+    serde_json::json!({
+        "started_at": started_at.to_rfc3339(),
+        "finished_at": finished_at.to_rfc3339(),
+        "duration_secs": (finished_at - started_at).num_milliseconds() as f64 / 1000.0,
+        "succeeded": jobs.len() - failed,
+        "failed": failed,
+        "jobs": jobs,
+    })
+}
+
+/// The `--report` path with `{date}` replaced by the local date the run
+/// started, e.g. `/var/log/ghostsnap/report-{date}.json`.
+fn report_path(path: &Path, started_at: DateTime<Utc>) -> PathBuf {
+    let date = started_at.with_timezone(&chrono::Local).format("%Y-%m-%d");
+    PathBuf::from(path.to_string_lossy().replace("{date}", &date.to_string()))
+}
+
+/// Writes the JSON report for a `job run`.
+fn write_report(path: &Path, report: &serde_json::Value) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create report directory: {}", parent.display()))?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(report)?)
+        .with_context(|| format!("Failed to write report: {}", path.display()))?;
+    Ok(())
+}
+
+/// Runs the `notify_command` after a `job run`. The command gets the JSON
+/// report as a file, and the overall status and a one-line-per-job summary
+/// in the environment, ready to mail or post to a webhook.
+async fn notify(
+    command: &str,
+    shell: &str,
+    report_path: Option<&Path>,
+    report: &serde_json::Value,
+    jobs: &[JobReport],
+) -> Result<()> {
+    // Without --report, the command reads a temporary copy
+    let temp;
+    let report_path = match report_path {
+        Some(path) => path,
+        None => {
+            temp = tempfile::Builder::new()
+                .prefix("ghostsnap-report-")
+                .suffix(".json")
+                .tempfile()?;
+            write_report(temp.path(), report)?;
+            temp.path()
+        }
+    };
+
+    let failed = jobs.iter().filter(|job| job.error.is_some()).count();
+    let summary: Vec<String> = jobs
+        .iter()
+        .map(|job| match &job.error {
+            Some(error) => format!("{}: failed: {}", job.name, truncate(error, 200)),
+            None => format!(
+                "{}: ok in {} ({} added)",
+                job.name,
+                HumanDuration(Duration::from_secs_f64(job.duration_secs)),
+                HumanBytes(job.bytes_added)
+            ),
+        })
+        .collect();
+
+    let hook_config = HookConfig {
+        command: command.to_string(),
+        timeout: Duration::from_secs(300),
+        shell: shell.to_string(),
+        working_dir: None,
+        env: vec![
+            (
+                "GHOSTSNAP_REPORT".to_string(),
+                report_path.to_string_lossy().to_string(),
+            ),
+            (
+                "GHOSTSNAP_REPORT_STATUS".to_string(),
+                if failed == 0 { "ok" } else { "failed" }.to_string(),
+            ),
+            ("GHOSTSNAP_REPORT_SUMMARY".to_string(), summary.join("\n")),
+        ],
+    };
+    let result = execute_hook(&hook_config).await?;
+    for line in format_hook_result("Notify", &result, false) {
+        println!("{}", line);
+    }
+    Ok(())
+}
 
-        for marker in markers {
-            if dir.join(marker).exists() {
-                return true;
+/// The job's repository location with its proxy applied, and the proxy for
+/// its `copy_to` repositories.
+fn job_location(job: &ResolvedJob) -> Result<(RepositoryLocation, Option<ProxyConfig>)> {
+    let location = RepositoryLocation::parse(&job.repository)
+        .map_err(|e| anyhow!("Invalid repository: {}", e))?;
+    let proxy = job.proxy.as_deref().map(ProxyConfig::parse).transpose()?;
+    let location = match &proxy {
+        Some(proxy) => location.with_proxy(proxy.clone()),
+        None => location,
+    };
+    Ok((location, proxy))
+}
+
+/// Splits jobs into groups that have no repository in common.
+fn group_by_repository(config: &JobConfig, job_names: Vec<String>) -> Vec<Vec<String>> {
+    let mut groups: Vec<(HashSet<String>, Vec<String>)> = Vec::new();
+
+    for name in job_names {
+        let mut repos = HashSet::new();
+        if let Some(job) = config.get_job(&name) {
+            if let Some(repo) = job
+                .repository
+                .as_ref()
+                .or(config.defaults.repository.as_ref())
+            {
+                repos.insert(repo.clone());
+            }
+            repos.extend(job.copy_to.iter().cloned());
+        }
+
+        let mut group = (repos, vec![name]);
+        let mut i = 0;
+        while i < groups.len() {
+            if groups[i].0.is_disjoint(&group.0) {
+                i += 1;
+            } else {
+                let (other_repos, mut other_names) = groups.remove(i);
+                group.0.extend(other_repos);
+                other_names.append(&mut group.1);
+                group.1 = other_names;
+            }
+        }
+        groups.push(group);
+    }
+
+    groups.into_iter().map(|(_, names)| names).collect()
+}
+
+/// Snapshots kept by the job's own retention settings; `snapshots` must be
+/// sorted newest first.
+fn job_keep_ids(job: &ResolvedJob, snapshots: &[Snapshot]) -> HashSet<SnapshotID> {
+    use chrono::Datelike;
+
+    let mut keep_ids: HashSet<SnapshotID> = HashSet::new();
+
+    // Keep last N
+    if let Some(n) = job.keep_last {
+        for snapshot in snapshots.iter().take(n as usize) {
+            keep_ids.insert(snapshot.id.clone());
+        }
+    }
+
+    // Keep daily
+    if let Some(n) = job.keep_daily {
+        let mut days_seen = HashSet::new();
+        for snapshot in snapshots {
+            let day = snapshot.time.date_naive();
+            if days_seen.len() < n as usize && !days_seen.contains(&day) {
+                days_seen.insert(day);
+                keep_ids.insert(snapshot.id.clone());
+            }
+        }
+    }
+
+    // Keep weekly
+    if let Some(n) = job.keep_weekly {
+        let mut weeks_seen = HashSet::new();
+        for snapshot in snapshots {
+            let week = snapshot.time.iso_week();
+            let week_key = (week.year(), week.week());
+            if weeks_seen.len() < n as usize && !weeks_seen.contains(&week_key) {
+                weeks_seen.insert(week_key);
+                keep_ids.insert(snapshot.id.clone());
+            }
+        }
+    }
+
+    // Keep monthly
+    if let Some(n) = job.keep_monthly {
+        let mut months_seen = HashSet::new();
+        for snapshot in snapshots {
+            let month_key = (snapshot.time.year(), snapshot.time.month());
+            if months_seen.len() < n as usize && !months_seen.contains(&month_key) {
+                months_seen.insert(month_key);
+                keep_ids.insert(snapshot.id.clone());
+            }
+        }
+    }
+
+    // Keep yearly
+    if let Some(n) = job.keep_yearly {
+        let mut years_seen = HashSet::new();
+        for snapshot in snapshots {
+            let year = snapshot.time.year();
+            if years_seen.len() < n as usize && !years_seen.contains(&year) {
+                years_seen.insert(year);
+                keep_ids.insert(snapshot.id.clone());
             }
         }
+    }
 
-        false
+    // If no policy, keep all
+    if !job.has_retention_policy() {
+        for snapshot in snapshots {
+            keep_ids.insert(snapshot.id.clone());
+        }
     }
+
+    keep_ids
 }
 
 fn truncate(s: &str, max_len: usize) -> String {
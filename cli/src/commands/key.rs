@@ -0,0 +1,243 @@
+//! Key command for listing keys and exporting and importing recovery material.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap key list                                # Keys, * marks the one used
+//! ghostsnap key export -o /escrow/web01-keys.json   # Config and sealed keys
+//! ghostsnap key export --recovery-code --qr         # Paper key, opens without password
+//! ghostsnap key import /escrow/web01-keys.json      # Restore lost config/keys
+//! ghostsnap key import --recovery-code              # Set a new password
+//! ```
+
+use anyhow::{Context, Result, anyhow};
+use clap::{Args, Subcommand};
+use ghostsnap_core::{KeyExport, RecoveryCode, Repository};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Args)]
+pub struct KeyCommand {
+    #[command(subcommand)]
+    subcommand: KeySubcommand,
+}
+
+#[derive(Subcommand)]
+enum KeySubcommand {
+    /// List the repository's keys.
+    List(KeyListCommand),
+
+    /// Export the repository config and sealed keys, or a recovery code.
+    Export(KeyExportCommand),
+
+    /// Restore exported keys, or add a key from a recovery code.
+    Import(KeyImportCommand),
+}
+
+impl KeyCommand {
+    pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        match &self.subcommand {
+            KeySubcommand::List(cmd) => cmd.run(cli).await,
+            KeySubcommand::Export(cmd) => cmd.run(cli).await,
+            KeySubcommand::Import(cmd) => cmd.run(cli).await,
+        }
+    }
+}
+
+#[derive(Args)]
+struct KeyListCommand {
+    #[arg(long, help = "Output in JSON format")]
+    json: bool,
+}
+
+impl KeyListCommand {
+    async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+        let password = crate::password::repository_password(cli)?;
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+        let keys = repo.list_keys().await?;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&keys)?);
+            return Ok(());
+        }
+
+        if keys.is_empty() {
+            println!("Repository is not encrypted and has no keys");
+            return Ok(());
+        }
+
+        println!(
+            "  {:<10} {:<20} {:<20} {:<12} {:<14} Keyfile",
+            "ID", "Created", "Host", "User", "KDF"
+        );
+        for key in &keys {
+            println!(
+                "{} {:<10} {:<20} {:<20} {:<12} {:<14} {}",
+                if key.current { "*" } else { " " },
+                key.name.chars().take(8).collect::<String>(),
+                key.created_at
+                    .map(|time| {
+                        time.with_timezone(&chrono::Local)
+                            .format("%Y-%m-%d %H:%M:%S")
+                            .to_string()
+                    })
+                    .unwrap_or_else(|| "-".to_string()),
+                key.hostname.as_deref().unwrap_or("-"),
+                key.username.as_deref().unwrap_or("-"),
+                key.kdf,
+                if key.keyfile { "yes" } else { "no" }
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct KeyExportCommand {
+    /// Write the export to this file instead of stdout (must not exist).
+    #[arg(long, short = 'o', conflicts_with = "recovery_code")]
+    output: Option<PathBuf>,
+
+    /// Print the data key as a recovery code instead. Anyone holding the
+    /// code can read the backups without the password.
+    #[arg(long)]
+    recovery_code: bool,
+
+    /// Also print the recovery code as a QR code.
+    #[arg(long, requires = "recovery_code")]
+    qr: bool,
+}
+
+impl KeyExportCommand {
+    async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+        let password = crate::password::repository_password(cli)?;
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+
+        if self.recovery_code {
+            let code = repo.recovery_code().await?;
+            println!("Recovery code for repository {}:", repo.config().id);
+            println!();
+            println!("    {}", code);
+            println!();
+            if self.qr {
+                println!("{}", render_qr(&code.to_string())?);
+            }
+            println!("The code opens the repository without the password or keyfile.");
+            println!(
+                "Store it offline, like the password itself; `ghostsnap key import --recovery-code` sets a new password with it."
+            );
+            return Ok(());
+        }
+
+        let export = repo.export_keys().await?;
+        let json = export.to_json()?;
+        match &self.output {
+            Some(path) => {
+                write_private(path, json.as_bytes())?;
+                eprintln!(
+                    "Exported config and {} key files of repository {} to {}",
+                    export.keys.len(),
+                    export.config.id,
+                    path.display()
+                );
+                eprintln!(
+                    "The keys are sealed: restoring from this file still needs the password{}",
+                    if cli.keyfile.is_some() {
+                        " and keyfile"
+                    } else {
+                        ""
+                    }
+                );
+            }
+            None => println!("{}", json),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct KeyImportCommand {
+    /// Key export written by `key export`.
+    #[arg(
+        required_unless_present = "recovery_code",
+        conflicts_with = "recovery_code"
+    )]
+    file: Option<PathBuf>,
+
+    /// Add a key from a recovery code, sealed with a new password. The code
+    /// is read from GHOSTSNAP_RECOVERY_CODE or prompted for.
+    #[arg(long)]
+    recovery_code: bool,
+}
+
+impl KeyImportCommand {
+    async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+        let repo_location = crate::commands::apply_proxy(cli, repo_location)?;
+
+        if let Some(path) = &self.file {
+            let data = std::fs::read(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let export = KeyExport::from_json(&data)?;
+            let written = Repository::import_keys(&repo_location, &export).await?;
+            println!(
+                "Imported {} of {} key files into repository {}",
+                written,
+                export.keys.len(),
+                export.config.id
+            );
+            if written < export.keys.len() {
+                println!("{} key files existed already", export.keys.len() - written);
+            }
+            return Ok(());
+        }
+
+        let code = crate::password::password_or_prompt(
+            std::env::var("GHOSTSNAP_RECOVERY_CODE").ok().as_deref(),
+            "Enter recovery code: ",
+        )?;
+        let code = RecoveryCode::parse(&code)?;
+        let password = crate::password::new_password(cli.password.as_deref(), true)?;
+        let keys = crate::commands::key_provider(&password, cli.keyfile.as_deref())?;
+
+        let key_name = Repository::add_key_from_recovery_code(&repo_location, &code, &keys).await?;
+        println!(
+            "Added key {}: the repository opens with the new password",
+            key_name
+        );
+        Ok(())
+    }
+}
+
+/// Writes `data` to a new file readable only by the current user.
+fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Renders `text` as a QR code for the terminal.
+fn render_qr(text: &str) -> Result<String> {
+    use qrcode::render::unicode::Dense1x2;
+
+    let code = qrcode::QrCode::new(text.as_bytes())
+        .map_err(|e| anyhow!("Failed to create QR code: {}", e))?;
+    Ok(code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build())
+}
@@ -0,0 +1,117 @@
+//! Locks command for inspecting repository locks.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap --repo /backup/repo locks list
+//! ghostsnap --repo /backup/repo locks list --json
+//! ```
+//!
+//! Listing needs no password: lock files are not encrypted.
+
+use anyhow::{Result, anyhow};
+use chrono::{Local, Utc};
+use clap::{Args, Subcommand};
+use ghostsnap_core::LockManager;
+use ghostsnap_core::storage::RepositoryLocation;
+
+#[derive(Args)]
+pub struct LocksCommand {
+    #[command(subcommand)]
+    subcommand: LocksSubcommand,
+}
+
+#[derive(Subcommand)]
+enum LocksSubcommand {
+    /// List held locks with their host, PID, operation and start time.
+    List(LocksListCommand),
+}
+
+impl LocksCommand {
+    pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+        let RepositoryLocation::Local(repo_path) = &repo_location else {
+            return Err(anyhow!(
+                "Locks are only kept for local repositories, not {}",
+                repo_location.display()
+            ));
+        };
+        let lock_manager = LockManager::new(repo_path);
+
+        match &self.subcommand {
+            LocksSubcommand::List(cmd) => cmd.run(&lock_manager).await,
+        }
+    }
+}
+
+#[derive(Args)]
+struct LocksListCommand {
+    #[arg(long, help = "Output in JSON format")]
+    json: bool,
+}
+
+impl LocksListCommand {
+    async fn run(&self, lock_manager: &LockManager) -> Result<()> {
+        let locks = lock_manager.list_locks().await?;
+
+        if self.json {
+            let output: Vec<_> = locks
+                .iter()
+                .map(|(path, info)| {
+                    serde_json::json!({
+                        "id": lock_id(path),
+                        "type": info.lock_type.as_str(),
+                        "hostname": info.hostname,
+                        "pid": info.pid,
+                        "operation": info.operation,
+                        "created_at": info.created_at.to_rfc3339(),
+                        "refreshed_at": info.last_refreshed().to_rfc3339(),
+                        "refresh_count": info.refresh_count,
+                        "stale": info.is_stale() && !info.is_process_alive(),
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&output)?);
+            return Ok(());
+        }
+
+        if locks.is_empty() {
+            println!("No locks held");
+            return Ok(());
+        }
+
+        println!(
+            "{:<10} {:<10} {:<20} {:>8} {:<16} {:<20} Age",
+            "ID", "Type", "Host", "PID", "Operation", "Started"
+        );
+        for (path, info) in &locks {
+            let age = (Utc::now() - info.created_at).to_std().unwrap_or_default();
+            let stale = if info.is_stale() && !info.is_process_alive() {
+                " (stale)"
+            } else {
+                ""
+            };
+            println!(
+                "{:<10} {:<10} {:<20} {:>8} {:<16} {:<20} {}{}",
+                lock_id(path).chars().take(8).collect::<String>(),
+                info.lock_type.as_str(),
+                info.hostname,
+                info.pid,
+                info.operation,
+                info.created_at
+                    .with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M:%S"),
+                indicatif::HumanDuration(age),
+                stale
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The lock file name without `.lock`.
+fn lock_id(path: &std::path::Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
@@ -1,17 +1,31 @@
 use anyhow::{Result, anyhow};
 use chrono::{DateTime, TimeZone, Utc};
-use clap::Args;
-use ghostsnap_core::{NodeType, Repository};
-use std::io::{self, Write};
+use clap::{Args, ValueEnum};
+use ghostsnap_core::snapshot::Tree;
+use ghostsnap_core::{NodeType, TreeNode};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Args)]
 pub struct LsCommand {
-    #[arg(help = "Snapshot ID (full or short prefix)")]
-    snapshot_id: String,
+    #[arg(
+        required_unless_present = "at",
+        help = "Snapshot ID (full or short prefix, or `latest`); with --at, the path within the snapshot"
+    )]
+    snapshot_id: Option<String>,
 
     #[arg(help = "Path within snapshot (optional)")]
     path: Option<String>,
 
+    #[arg(
+        long,
+        value_name = "TIME",
+        help = "List the newest snapshot taken at or before TIME, e.g. \"2024-12-01 03:00\""
+    )]
+    at: Option<String>,
+
+    #[command(flatten)]
+    filter: crate::commands::SnapshotFilterArgs,
+
     #[arg(short, long, help = "Long listing format")]
     long: bool,
 
@@ -20,64 +34,117 @@ pub struct LsCommand {
 
     #[arg(short, long, help = "Recursive listing")]
     recursive: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["long", "json"],
+        help = "Show a tree with cumulative directory sizes"
+    )]
+    tree: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "name",
+        help = "Sort entries by name or size"
+    )]
+    sort: SortOrder,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SortOrder {
+    Name,
+    /// Largest first; directories by cumulative size
+    Size,
 }
 
 impl LsCommand {
     pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
         let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
 
-        let password = cli
-            .password
-            .clone()
-            .or_else(|| {
-                print!("Enter repository password: ");
-                io::stdout().flush().ok()?;
-                rpassword::read_password().ok()
-            })
-            .ok_or_else(|| anyhow!("Password required"))?;
+        let password = crate::password::repository_password(cli)?;
 
-        let repo = Repository::open_at_location(repo_location, &password).await?;
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
 
-        // Resolve snapshot ID
-        let full_snapshot_id = self.resolve_snapshot_id(&repo, &self.snapshot_id).await?;
+        // Resolve snapshot ID; with --at the only positional argument is the path
+        let filter = self.filter.filter();
+        let (full_snapshot_id, path) = match (&self.at, &self.snapshot_id) {
+            (Some(_), _) if self.path.is_some() => {
+                return Err(anyhow!("A snapshot ID cannot be combined with --at"));
+            }
+            (Some(at), path) => (
+                crate::commands::snapshot_at(&repo, at, &filter).await?,
+                path.as_deref(),
+            ),
+            (None, Some(snapshot_id)) => (
+                crate::commands::resolve_snapshot(&repo, snapshot_id, &filter).await?,
+                self.path.as_deref(),
+            ),
+            (None, None) => return Err(anyhow!("Snapshot ID or --at required")),
+        };
         let snapshot = repo.load_snapshot(&full_snapshot_id).await?;
-        let tree = repo.load_tree(&snapshot.tree).await?;
+        // Names are relative to the backup sources, so a leading slash is
+        // dropped rather than matching nothing
+        let filter_path = path.unwrap_or("").trim_matches('/');
+
+        // Only the pages and subtrees holding names under the path are read
+        let mut pages = repo.tree_pages(&snapshot.tree, filter_path).await?;
+
+        // A plain listing of one directory by name is printed a page at a
+        // time (a directory's entries are in one tree object, whose pages
+        // are in name order), so listing a huge directory keeps one page in
+        // memory. Other listings need directory sizes or span subtrees, and
+        // collect the nodes first.
+        if !self.tree && !self.long && !self.json && !self.recursive && self.sort == SortOrder::Name
+        {
+            let mut found = false;
+            while let Some(page) = pages.next_page().await? {
+                found |= page
+                    .nodes
+                    .iter()
+                    .any(|node| is_under(&node.name, filter_path));
+                let mut nodes: Vec<_> = page
+                    .nodes
+                    .iter()
+                    .filter(|node| self.is_listed(filter_path, node))
+                    .collect();
+                sort_nodes(&mut nodes, self.sort, &|node: &TreeNode| node.size);
+                for node in nodes {
+                    print_name(node);
+                }
+            }
+            return check_found(found, filter_path);
+        }
+
+        let mut tree = Tree::new();
+        while let Some(page) = pages.next_page().await? {
+            tree.nodes.extend(
+                page.nodes
+                    .into_iter()
+                    .filter(|node| is_under(&node.name, filter_path)),
+            );
+        }
+        check_found(!tree.nodes.is_empty(), filter_path)?;
+        let sizes = tree.cumulative_sizes();
+        let size_of = |node: &TreeNode| match node.node_type {
+            NodeType::Directory => sizes.get(&node.name).copied().unwrap_or(0),
+            _ => node.size,
+        };
+
+        if self.tree {
+            let nodes: Vec<_> = tree.nodes.iter().collect();
+            print_tree(filter_path, &nodes, self.sort, &size_of);
+            return Ok(());
+        }
 
         // Filter nodes by path prefix
-        let filter_path = self.path.as_deref().unwrap_or("");
         let mut nodes: Vec<_> = tree
             .nodes
             .iter()
-            .filter(|node| {
-                if filter_path.is_empty() {
-                    // No path specified: show only top-level items by default
-                    // (or all items if recursive flag is set)
-                    if self.recursive {
-                        true
-                    } else {
-                        // Top-level items don't contain '/' in their name
-                        !node.name.contains('/')
-                    }
-                } else if self.recursive {
-                    node.name.starts_with(filter_path)
-                } else {
-                    // Non-recursive: only show direct children
-                    if node.name.starts_with(filter_path) {
-                        let remainder = node
-                            .name
-                            .strip_prefix(filter_path)
-                            .unwrap_or(&node.name)
-                            .trim_start_matches('/');
-                        !remainder.contains('/')
-                    } else {
-                        false
-                    }
-                }
-            })
+            .filter(|node| self.is_listed(filter_path, node))
             .collect();
 
-        // Sort by name
-        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+        sort_nodes(&mut nodes, self.sort, &size_of);
 
         if self.json {
             let entries: Vec<_> = nodes
@@ -85,17 +152,14 @@ impl LsCommand {
                 .map(|node| {
                     serde_json::json!({
                         "name": node.name,
-                        "type": match node.node_type {
-                            NodeType::File => "file",
-                            NodeType::Directory => "directory",
-                            NodeType::Symlink => "symlink",
-                        },
-                        "size": node.size,
+                        "type": node.node_type.as_str(),
+                        "size": size_of(node),
                         "mode": format!("{:o}", node.mode),
                         "uid": node.uid,
                         "gid": node.gid,
                         "mtime": node.mtime,
                         "link_target": node.link_target,
+                        "device": node.device,
                     })
                 })
                 .collect();
@@ -107,13 +171,16 @@ impl LsCommand {
                     NodeType::File => '-',
                     NodeType::Directory => 'd',
                     NodeType::Symlink => 'l',
+                    NodeType::CharDevice => 'c',
+                    NodeType::BlockDevice => 'b',
+                    NodeType::Fifo => 'p',
                 };
 
                 let mode_str = format_mode(node.mode);
-                let size_str = if matches!(node.node_type, NodeType::Directory) {
-                    "-".to_string()
-                } else {
-                    format_size(node.size)
+                // Like ls, devices show their numbers instead of a size
+                let size_str = match node.device {
+                    Some(device) => device.to_string(),
+                    None => format_size(size_of(node)),
                 };
 
                 let mtime: DateTime<Utc> = Utc
@@ -136,40 +203,139 @@ impl LsCommand {
         } else {
             // Simple listing
             for node in &nodes {
-                let suffix = match node.node_type {
-                    NodeType::Directory => "/",
-                    NodeType::Symlink => "@",
-                    NodeType::File => "",
-                };
-                println!("{}{}", node.name, suffix);
+                print_name(node);
             }
         }
 
         Ok(())
     }
 
-    async fn resolve_snapshot_id(&self, repo: &Repository, snapshot_id: &str) -> Result<String> {
-        if snapshot_id.len() >= 36 {
-            return Ok(snapshot_id.to_string());
+    /// Whether `node` is listed for `filter_path`: the entries of the
+    /// directory at the path (all below it with `--recursive`), or the path
+    /// itself if it is not a directory.
+    fn is_listed(&self, filter_path: &str, node: &TreeNode) -> bool {
+        if node.name == filter_path {
+            return !node.is_dir();
         }
+        let rest = if filter_path.is_empty() {
+            node.name.as_str()
+        } else {
+            match node
+                .name
+                .strip_prefix(filter_path)
+                .and_then(|rest| rest.strip_prefix('/'))
+            {
+                Some(rest) => rest,
+                None => return false,
+            }
+        };
+        self.recursive || !rest.contains('/')
+    }
+}
 
-        let all_snapshots = repo.list_snapshots().await?;
-        let matches: Vec<_> = all_snapshots
-            .iter()
-            .filter(|id| id.starts_with(snapshot_id))
-            .collect();
+/// Whether `name` is `path` or below it. Matches whole components, so
+/// `docs` doesn't match `docs-old`.
+fn is_under(name: &str, path: &str) -> bool {
+    path.is_empty()
+        || name
+            .strip_prefix(path)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Fails for a path that matched nothing, rather than listing nothing.
+fn check_found(found: bool, filter_path: &str) -> Result<()> {
+    if found || filter_path.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Path not found in snapshot: {}", filter_path))
+    }
+}
+
+/// Prints a node's name with a suffix marking its type, like `ls -F`.
+fn print_name(node: &TreeNode) {
+    let suffix = match node.node_type {
+        NodeType::Directory => "/",
+        NodeType::Symlink => "@",
+        NodeType::Fifo => "|",
+        NodeType::File | NodeType::CharDevice | NodeType::BlockDevice => "",
+    };
+    println!("{}{}", node.name, suffix);
+}
+
+fn sort_nodes(nodes: &mut [&TreeNode], sort: SortOrder, size_of: &impl Fn(&TreeNode) -> u64) {
+    match sort {
+        SortOrder::Name => nodes.sort_by(|a, b| a.name.cmp(&b.name)),
+        SortOrder::Size => nodes.sort_by(|a, b| {
+            size_of(b)
+                .cmp(&size_of(a))
+                .then_with(|| a.name.cmp(&b.name))
+        }),
+    }
+}
+
+/// Prints `nodes` below `root` (empty for the snapshot root) like `tree`,
+/// with the cumulative size of every directory.
+fn print_tree(
+    root: &str,
+    nodes: &[&TreeNode],
+    sort: SortOrder,
+    size_of: &impl Fn(&TreeNode) -> u64,
+) {
+    // Nodes whose parent has no node of its own are shown at the top level.
+    let names: HashSet<&str> = nodes.iter().map(|node| node.name.as_str()).collect();
+    let mut children: HashMap<&str, Vec<&TreeNode>> = HashMap::new();
+    for &node in nodes.iter().filter(|node| node.name != root) {
+        let parent = node
+            .name
+            .rsplit_once('/')
+            .map(|(parent, _)| parent)
+            .filter(|parent| names.contains(parent))
+            .unwrap_or(root);
+        children.entry(parent).or_default().push(node);
+    }
+    for entries in children.values_mut() {
+        sort_nodes(entries, sort, size_of);
+    }
+
+    let total: u64 = children
+        .get(root)
+        .map_or(0, |entries| entries.iter().map(|node| size_of(node)).sum());
+    let label = if root.is_empty() { "." } else { root };
+    println!("{} ({})", label, format_size(total));
+    print_children(root, "", &children, size_of);
+}
 
-        match matches.len() {
-            0 => Err(anyhow!(
-                "No snapshot found with ID starting with '{}'",
-                snapshot_id
-            )),
-            1 => Ok(matches[0].clone()),
-            _ => Err(anyhow!(
-                "Ambiguous snapshot ID '{}' - matches {} snapshots",
-                snapshot_id,
-                matches.len()
-            )),
+fn print_children(
+    parent: &str,
+    indent: &str,
+    children: &HashMap<&str, Vec<&TreeNode>>,
+    size_of: &impl Fn(&TreeNode) -> u64,
+) {
+    let Some(entries) = children.get(parent) else {
+        return;
+    };
+    for (i, node) in entries.iter().enumerate() {
+        let last = i + 1 == entries.len();
+        let name = node
+            .name
+            .strip_prefix(parent)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .unwrap_or(&node.name);
+        let label = match (&node.node_type, &node.link_target) {
+            (NodeType::Directory, _) => format!("{}/", name),
+            (NodeType::Symlink, Some(target)) => format!("{} -> {}", name, target),
+            _ => name.to_string(),
+        };
+        println!(
+            "{}{} {} ({})",
+            indent,
+            if last { "└──" } else { "├──" },
+            label,
+            format_size(size_of(node))
+        );
+        if node.is_dir() {
+            let indent = format!("{}{}", indent, if last { "    " } else { "│   " });
+            print_children(&node.name, &indent, children, size_of);
         }
     }
 }
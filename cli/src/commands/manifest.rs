@@ -0,0 +1,98 @@
+//! Manifest command for signing keys and manifest verification.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap manifest keygen /etc/ghostsnap/manifest.key
+//! ghostsnap backup /srv --manifest /catalog --manifest-key /etc/ghostsnap/manifest.key
+//! ghostsnap manifest verify /catalog/ghostsnap-<id>.json --public-key <hex>
+//! ```
+//!
+//! Neither subcommand touches a repository.
+
+use anyhow::{Result, anyhow};
+use clap::{Args, Subcommand};
+use ghostsnap_core::ManifestKey;
+use ghostsnap_core::manifest::{BackupManifest, signature_path, verify_signature};
+use std::path::{Path, PathBuf};
+
+/// Manifest command for managing manifest signing keys.
+#[derive(Args)]
+pub struct ManifestCommand {
+    #[command(subcommand)]
+    subcommand: ManifestSubcommand,
+}
+
+#[derive(Subcommand)]
+enum ManifestSubcommand {
+    /// Generate a signing key and print its public key.
+    Keygen {
+        /// Where to write the secret key (must not exist)
+        path: PathBuf,
+    },
+
+    /// Check a manifest's signature.
+    Verify {
+        /// Manifest file written by `backup --manifest`
+        manifest: PathBuf,
+
+        /// Public key, hex encoded or as a file containing it
+        #[arg(long)]
+        public_key: String,
+
+        /// Signature file (defaults to the manifest path plus `.sig`)
+        #[arg(long)]
+        signature: Option<PathBuf>,
+    },
+}
+
+impl ManifestCommand {
+    pub async fn run(&self, _cli: &crate::Cli) -> Result<()> {
+        match &self.subcommand {
+            ManifestSubcommand::Keygen { path } => {
+                let key = ManifestKey::generate();
+                key.save(path)
+                    .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+                println!("Secret key written to {}", path.display());
+                println!("Public key: {}", key.public_key_hex());
+                println!(
+                    "Give the public key to anyone verifying manifests; keep the secret key private"
+                );
+            }
+            ManifestSubcommand::Verify {
+                manifest,
+                public_key,
+                signature,
+            } => verify(manifest, public_key, signature.as_deref())?,
+        }
+
+        Ok(())
+    }
+}
+
+fn verify(manifest_path: &Path, public_key: &str, signature: Option<&Path>) -> Result<()> {
+    let data = std::fs::read(manifest_path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", manifest_path.display(), e))?;
+    let signature_path = signature
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| signature_path(manifest_path));
+    let signature = std::fs::read_to_string(&signature_path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", signature_path.display(), e))?;
+
+    let public_key = if Path::new(public_key).is_file() {
+        std::fs::read_to_string(public_key)?
+    } else {
+        public_key.to_string()
+    };
+
+    verify_signature(&data, &signature, &public_key)?;
+    let manifest: BackupManifest = serde_json::from_slice(&data)?;
+    println!(
+        "Signature OK: snapshot {} from {} at {} ({} entries)",
+        manifest.snapshot_id,
+        manifest.hostname,
+        manifest.time.format("%Y-%m-%d %H:%M:%S UTC"),
+        manifest.files.len()
+    );
+    Ok(())
+}
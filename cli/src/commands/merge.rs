@@ -0,0 +1,108 @@
+//! Merge command for consolidating snapshots.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap merge abc123 def456 --into consolidated
+//! ```
+
+use anyhow::{Result, anyhow};
+use clap::Args;
+use ghostsnap_core::LockType;
+use tracing::info;
+
+/// Merge command for combining several snapshots into one synthetic snapshot.
+#[derive(Args)]
+pub struct MergeCommand {
+    /// Snapshot IDs to merge (full or short prefix); at least two.
+    #[arg(required = true, num_args = 2..)]
+    snapshot_ids: Vec<String>,
+
+    /// Tag identifying the merged snapshot.
+    #[arg(long)]
+    into: Option<String>,
+
+    /// Hostname recorded in the merged snapshot.
+    #[arg(long)]
+    hostname: Option<String>,
+
+    /// Show what would be merged without writing anything.
+    #[arg(long, short = 'n')]
+    pub dry_run: bool,
+}
+
+impl MergeCommand {
+    pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+
+        let password = crate::password::repository_password(cli)?;
+
+        info!("Opening repository at: {}", repo_location.display());
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+
+        let _lock = if let Some(repo_path) = repo.local_path() {
+            let lock_manager = crate::commands::lock_manager(cli, repo_path)?;
+            Some(lock_manager.acquire(LockType::Exclusive, "merge").await?)
+        } else {
+            tracing::warn!("Repository locking not supported for remote repositories");
+            None
+        };
+
+        let mut snapshot_ids = Vec::with_capacity(self.snapshot_ids.len());
+        for id in &self.snapshot_ids {
+            let full_id = crate::commands::resolve_snapshot_prefix(&repo, id).await?;
+            if snapshot_ids.contains(&full_id) {
+                return Err(anyhow!("Snapshot {} given more than once", full_id.short()));
+            }
+            snapshot_ids.push(full_id);
+        }
+
+        if self.dry_run {
+            println!(
+                "Would merge {} snapshots (newest wins):",
+                snapshot_ids.len()
+            );
+            let mut snapshots = Vec::new();
+            for id in &snapshot_ids {
+                snapshots.push(repo.load_snapshot(id).await?);
+            }
+            snapshots.sort_by_key(|s| s.time);
+            for snapshot in &snapshots {
+                println!("  {}", snapshot.summary());
+            }
+            if snapshots.iter().any(|s| !s.has_same_paths(&snapshots[0])) {
+                return Err(anyhow!(
+                    "Cannot merge snapshots of different paths; only snapshots of the same paths can be merged"
+                ));
+            }
+            return Ok(());
+        }
+
+        let mut merged = repo.merge_snapshots(&snapshot_ids).await?;
+        if let Some(tag) = &self.into
+            && !merged.tags.contains(tag)
+        {
+            merged.tags.push(tag.clone());
+        }
+        if let Some(hostname) = &self.hostname {
+            merged.hostname = hostname.clone();
+        }
+
+        repo.save_snapshot(&merged).await?;
+        if let Err(e) = repo.refresh_stats_cache().await {
+            tracing::warn!("Failed to update stats cache: {}", e);
+        }
+
+        let tree = repo.load_tree(&merged.tree).await?;
+        println!("Merged {} snapshots", snapshot_ids.len());
+        println!("Snapshot: {}", merged.short_id());
+        println!(
+            "Files: {} | Dirs: {} | Paths: {}",
+            tree.file_count(),
+            tree.dir_count(),
+            merged.paths.len()
+        );
+
+        Ok(())
+    }
+}
@@ -1,22 +1,562 @@
+pub mod backend;
 pub mod backup;
+pub mod bench;
+pub mod bundle;
 pub mod check;
+pub mod config;
 pub mod copy;
 pub mod diff;
 pub mod dump;
 pub mod forget;
+pub mod grep;
+pub mod hestia;
+pub mod import;
 pub mod init;
 pub mod job;
+pub mod key;
+pub mod locks;
 pub mod ls;
+pub mod manifest;
+pub mod merge;
+pub mod policy;
 pub mod prune;
+pub mod restic;
 pub mod restore;
+pub mod scrub;
 pub mod snapshots;
 pub mod stats;
+pub mod telemetry;
+pub mod tui;
 
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use clap::Args;
 use ghostsnap_core::storage::RepositoryLocation;
+use ghostsnap_core::{
+    Access, DeviceNumber, LockManager, NodeType, PasswordKey, ProxyConfig, Repository,
+    SnapshotFilter, SnapshotID,
+};
+use std::path::{Path, PathBuf};
 
 pub fn parse_repository_location(repo: Option<&String>) -> Result<RepositoryLocation> {
     let repo =
         repo.ok_or_else(|| anyhow!("Repository path required (--repo or GHOSTSNAP_REPO)"))?;
     RepositoryLocation::parse(repo).map_err(|e| anyhow!(e.to_string()))
 }
+
+/// Parses a size such as `100M`, `1G` or `50GB` into bytes.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let upper = size.trim().to_uppercase();
+    let upper = upper.trim_end_matches('B');
+    let (num, multiplier) = if let Some(n) = upper.strip_suffix('T') {
+        (n, 1024 * 1024 * 1024 * 1024)
+    } else if let Some(n) = upper.strip_suffix('G') {
+        (n, 1024 * 1024 * 1024)
+    } else if let Some(n) = upper.strip_suffix('M') {
+        (n, 1024 * 1024)
+    } else if let Some(n) = upper.strip_suffix('K') {
+        (n, 1024)
+    } else {
+        (upper, 1)
+    };
+    let num: u64 = num
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Invalid size: {}", size))?;
+    Ok(num * multiplier)
+}
+
+/// Parses a pack size such as `32M`, which must be within the limits a
+/// repository accepts.
+pub fn parse_pack_size(size: &str) -> Result<u64> {
+    let bytes = parse_size(size)?;
+    ghostsnap_core::settings::check_pack_size(bytes)
+        .map_err(|e| anyhow!("Invalid pack size {}: {}", size, e))?;
+    Ok(bytes)
+}
+
+/// Limits on how much a single backup reads, to stop one that unexpectedly
+/// balloons (e.g. after a large disk was mounted below a backed-up path)
+/// before it is all uploaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackupLimits {
+    pub max_total_size: Option<u64>,
+    pub max_file_count: Option<u64>,
+}
+
+impl BackupLimits {
+    pub fn parse(max_total_size: Option<&str>, max_file_count: Option<u64>) -> Result<Self> {
+        Ok(Self {
+            max_total_size: max_total_size.map(parse_size).transpose()?,
+            max_file_count,
+        })
+    }
+
+    /// Describes the limits that `files` files totalling `bytes` exceed.
+    pub fn exceeded(&self, files: u64, bytes: u64) -> Option<String> {
+        let mut exceeded = Vec::new();
+        if let Some(max) = self.max_file_count
+            && files > max
+        {
+            exceeded.push(format!("{} files (limit {})", files, max));
+        }
+        if let Some(max) = self.max_total_size
+            && bytes > max
+        {
+            exceeded.push(format!(
+                "{} (limit {})",
+                indicatif::HumanBytes(bytes),
+                indicatif::HumanBytes(max)
+            ));
+        }
+        if exceeded.is_empty() {
+            None
+        } else {
+            Some(format!("backup exceeds {}", exceeded.join(" and ")))
+        }
+    }
+}
+
+/// Warns about an entry a backup walk couldn't get to, such as a symlink
+/// loop under `--follow-symlinks` or an unreadable directory. The entry, and
+/// anything below it, is left out of the snapshot.
+pub fn warn_walk_error(err: &walkdir::Error) {
+    match (err.path(), err.loop_ancestor()) {
+        (Some(path), Some(ancestor)) => tracing::warn!(
+            "Skipping symlink loop at {} (leads back to {})",
+            path.display(),
+            ancestor.display()
+        ),
+        (Some(path), None) => match err.io_error() {
+            Some(io) => tracing::warn!("Skipping {}: {}", path.display(), io),
+            None => tracing::warn!("Skipping {}: {}", path.display(), err),
+        },
+        (None, _) => tracing::warn!("Skipping unreadable entry: {}", err),
+    }
+}
+
+/// Loads the manifest signing key and checks that a manifest can be written
+/// to `path` (a file or a directory). Called before a backup starts, so that
+/// a bad key or destination fails the run before anything is saved.
+pub fn prepare_manifest(
+    path: &Path,
+    key: Option<&Path>,
+) -> Result<Option<ghostsnap_core::ManifestKey>> {
+    let key = key
+        .map(ghostsnap_core::ManifestKey::load)
+        .transpose()
+        .map_err(|e| anyhow!("Failed to load manifest key: {}", e))?;
+
+    let dir = if path.is_dir() {
+        path
+    } else {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        }
+    };
+    tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("Cannot write the manifest to {}", path.display()))?;
+    Ok(key)
+}
+
+/// Classifies a character device, block device or FIFO, which backups record
+/// as metadata only. Returns `None` for every other file type.
+pub fn special_file_type(metadata: &std::fs::Metadata) -> Option<(NodeType, Option<DeviceNumber>)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+        let file_type = metadata.file_type();
+        let device = Some(DeviceNumber::from_rdev(metadata.rdev()));
+        if file_type.is_char_device() {
+            Some((NodeType::CharDevice, device))
+        } else if file_type.is_block_device() {
+            Some((NodeType::BlockDevice, device))
+        } else if file_type.is_fifo() {
+            Some((NodeType::Fifo, None))
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Builds the key provider for `password`, adding the keyfile if one is given.
+pub fn key_provider(password: &str, keyfile: Option<&Path>) -> Result<PasswordKey> {
+    let keys = PasswordKey::new(password);
+    let Some(path) = keyfile else {
+        return Ok(keys);
+    };
+
+    let contents = std::fs::read(path)
+        .with_context(|| format!("Failed to read keyfile: {}", path.display()))?;
+    if contents.is_empty() {
+        return Err(anyhow!("Keyfile is empty: {}", path.display()));
+    }
+    Ok(keys.with_keyfile(contents))
+}
+
+/// Routes an S3 or Azure location through `--proxy`, if given. Without it
+/// the proxy comes from the environment.
+pub fn apply_proxy(cli: &crate::Cli, location: RepositoryLocation) -> Result<RepositoryLocation> {
+    match &cli.proxy {
+        Some(proxy) => Ok(location.with_proxy(ProxyConfig::parse(proxy)?)),
+        None => Ok(location),
+    }
+}
+
+/// Attempts at a prompted repository password before giving up.
+const PASSWORD_ATTEMPTS: usize = 3;
+
+/// Opens a repository with `password` and the `--keyfile`, if any.
+///
+/// A prompted password that opens no key file is asked for again, up to
+/// [`PASSWORD_ATTEMPTS`] times in all. Passwords from `--password` or
+/// `GHOSTSNAP_PASSWORD` fail at once, since asking again can't fix them.
+pub async fn open_repository(
+    cli: &crate::Cli,
+    location: RepositoryLocation,
+    password: &str,
+) -> Result<Repository> {
+    let (repo, _) = open_repository_with_password(cli, location, password).await?;
+    Ok(repo)
+}
+
+/// Like [`open_repository`], also returning the password that opened the
+/// repository, which differs from `password` after a retry.
+pub async fn open_repository_with_password(
+    cli: &crate::Cli,
+    location: RepositoryLocation,
+    password: &str,
+) -> Result<(Repository, String)> {
+    use ghostsnap_core::Error;
+    use std::io::IsTerminal;
+
+    let location = apply_proxy(cli, location)?;
+    let interactive = cli.password.is_none() && std::io::stdin().is_terminal();
+    let mut password = password.to_string();
+    let mut attempt = 1;
+    loop {
+        let keys = key_provider(&password, cli.keyfile.as_deref())?;
+        let err = match Repository::open_with_keys(location.clone(), &keys).await {
+            Ok(repo) => return Ok((repo, password)),
+            Err(err) => err,
+        };
+        if matches!(err, Error::InvalidPassword) && interactive && attempt < PASSWORD_ATTEMPTS {
+            eprintln!(
+                "Wrong password ({} of {} attempts), try again.",
+                attempt, PASSWORD_ATTEMPTS
+            );
+            password = crate::password::prompt_password("Enter repository password: ")?;
+            attempt += 1;
+            continue;
+        }
+
+        return Err(match err {
+            Error::InvalidPassword => anyhow!(
+                "{}: no key file opens with it{}. If the password is lost, \
+                 `ghostsnap key import --recovery-code` sets a new one from a recovery code.",
+                err,
+                if cli.keyfile.is_some() {
+                    " and the given keyfile"
+                } else {
+                    ""
+                }
+            ),
+            Error::KeyfileRequired => anyhow!("{} (use --keyfile or GHOSTSNAP_KEYFILE)", err),
+            Error::CorruptKeyFile { .. } => anyhow!(
+                "{}. No key file could be read, so the password was not checked; restore \
+                 keys/ from a `ghostsnap key export` or use `ghostsnap key import --recovery-code`.",
+                err
+            ),
+            _ if is_restic_location(&location).await => anyhow!(
+                "{} (this is a restic repository; use `ghostsnap restic` to read it)",
+                err
+            ),
+            _ => err.into(),
+        });
+    }
+}
+
+/// Returns whether `location` holds a restic repository. Errors count as no.
+async fn is_restic_location(location: &RepositoryLocation) -> bool {
+    match ghostsnap_core::storage::storage_for_location(location).await {
+        Ok(storage) => ghostsnap_core::restic::is_restic_repository(storage.as_ref())
+            .await
+            .unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// Returns whether the repository given by `--repo` is unencrypted, in which
+/// case commands must not prompt for a password. Errors are left for the
+/// command itself to report.
+pub async fn is_unencrypted_repository(cli: &crate::Cli) -> bool {
+    let Ok(location) = parse_repository_location(cli.repo.as_ref())
+        .and_then(|location| apply_proxy(cli, location))
+    else {
+        return false;
+    };
+    match Repository::read_config(&location).await {
+        Ok(config) => !config.encryption.is_encrypted(),
+        Err(_) => false,
+    }
+}
+
+/// Warns when this host's clock is behind the newest snapshot. New snapshots
+/// would then sort before existing ones and retention would forget the wrong
+/// ones. Failing to check is not an error.
+pub async fn warn_clock_skew(repo: &Repository) {
+    match repo.clock_skew().await {
+        Ok(Some(ahead)) => tracing::warn!(
+            "The newest snapshot is {} ahead of the system clock; the clock appears to be \
+             wrong. Retention policies rely on snapshot times, so fix the clock (or use \
+             `backup --time`) before continuing.",
+            indicatif::HumanDuration(ahead.to_std().unwrap_or_default())
+        ),
+        Ok(None) => {}
+        Err(e) => tracing::debug!("Failed to check the system clock: {}", e),
+    }
+}
+
+/// Warns when this host's clock disagrees with the storage's, judged by the
+/// time the storage stamps on a probe object. Lock expiry and snapshot times
+/// assume roughly synchronised clocks. Failing to check is not an error.
+pub async fn warn_storage_clock(repo: &Repository) {
+    match repo.storage_clock_offset().await {
+        Ok(Some(offset)) => tracing::warn!(
+            "The system clock is {} {} the repository storage's clock; check that NTP is \
+             running on this host.",
+            indicatif::HumanDuration(offset.abs().to_std().unwrap_or_default()),
+            if offset > chrono::Duration::zero() {
+                "behind"
+            } else {
+                "ahead of"
+            }
+        ),
+        Ok(None) => {}
+        Err(e) => tracing::debug!("Failed to compare the clock with the storage: {}", e),
+    }
+}
+
+/// Lock manager for the local repository at `repo_path`, waiting up to
+/// `--retry-lock` for conflicting locks to go away.
+pub fn lock_manager(cli: &crate::Cli, repo_path: &Path) -> Result<LockManager> {
+    let retry = match &cli.retry_lock {
+        Some(retry) => crate::config::parse_duration(retry)?,
+        None => std::time::Duration::ZERO,
+    };
+    Ok(LockManager::new(repo_path).with_retry(retry))
+}
+
+/// Probes the storage credential and fails before `operation` starts if it
+/// lacks the `access` needed, rather than partway through.
+pub async fn require_access(repo: &Repository, access: Access, operation: &str) -> Result<()> {
+    let capabilities = match repo.require_access(access, operation).await {
+        Ok(capabilities) => capabilities,
+        Err(e) => {
+            let hint = match access {
+                Access::Delete => {
+                    "append-only and read-only credentials can't remove data; run maintenance \
+                     with a credential that can delete"
+                }
+                Access::Append => "read-only credentials can restore and check, but not back up",
+                Access::Read => "the credential needs to list and read the repository",
+            };
+            return Err(anyhow!("{} ({})", e, hint));
+        }
+    };
+
+    tracing::debug!("Storage credential allows: {}", capabilities);
+    if access == Access::Append && capabilities.is_append_only() {
+        tracing::info!(
+            "The storage credential is append-only: {} works, but forget and prune need a \
+             credential that can delete",
+            operation
+        );
+    }
+    Ok(())
+}
+
+/// `--host`, `--tag` and `--path` flags selecting snapshots, shared by
+/// every command that picks snapshots so that they select the same ones.
+#[derive(Args, Debug, Clone, Default)]
+pub struct SnapshotFilterArgs {
+    #[arg(
+        long,
+        alias = "hostname",
+        help = "Only consider snapshots from this host (repeatable)"
+    )]
+    pub host: Vec<String>,
+
+    #[arg(long, help = "Only consider snapshots with this tag (repeatable)")]
+    pub tag: Vec<String>,
+
+    #[arg(
+        long = "path",
+        help = "Only consider snapshots that include this path (repeatable)"
+    )]
+    pub snapshot_paths: Vec<PathBuf>,
+}
+
+impl SnapshotFilterArgs {
+    pub fn filter(&self) -> SnapshotFilter {
+        SnapshotFilter {
+            hosts: self.host.clone(),
+            tags: self.tag.clone(),
+            paths: self.snapshot_paths.clone(),
+        }
+    }
+}
+
+/// " matching <filter>" for messages, or nothing for an empty filter.
+fn describe_filter(filter: &SnapshotFilter) -> String {
+    if filter.is_empty() {
+        String::new()
+    } else {
+        format!(" matching {}", filter.describe())
+    }
+}
+
+/// Resolves a full snapshot ID, a short prefix, or `latest` for the newest
+/// snapshot. Only snapshots matching `filter` are considered.
+pub async fn resolve_snapshot(
+    repo: &Repository,
+    snapshot_id: &str,
+    filter: &SnapshotFilter,
+) -> Result<SnapshotID> {
+    if filter.is_empty()
+        && let Ok(id) = snapshot_id.parse()
+    {
+        return Ok(id);
+    }
+
+    let summaries = repo.snapshot_summaries(false).await?;
+    let snapshots = summaries.iter().map(|summary| &summary.snapshot);
+    if snapshot_id == "latest" {
+        return filter
+            .newest(snapshots, None)
+            .map(|snapshot| snapshot.id.clone())
+            .ok_or_else(|| anyhow!("No snapshot found{}", describe_filter(filter)));
+    }
+
+    let matches: Vec<_> = snapshots
+        .filter(|snapshot| {
+            filter.matches(snapshot) && snapshot.id.as_str().starts_with(snapshot_id)
+        })
+        .collect();
+    match matches.len() {
+        0 => Err(anyhow!(
+            "No snapshot{} found with ID starting with '{}'",
+            describe_filter(filter),
+            snapshot_id
+        )),
+        1 => Ok(matches[0].id.clone()),
+        _ => Err(anyhow!(
+            "Ambiguous snapshot ID '{}' - matches {} snapshots",
+            snapshot_id,
+            matches.len()
+        )),
+    }
+}
+
+/// Resolves a full snapshot ID or a unique prefix of one from the snapshot
+/// listing, without loading any snapshots.
+pub async fn resolve_snapshot_prefix(repo: &Repository, snapshot_id: &str) -> Result<SnapshotID> {
+    if let Ok(id) = snapshot_id.parse() {
+        return Ok(id);
+    }
+
+    let all_snapshots = repo.list_snapshots().await?;
+    let mut matches: Vec<_> = all_snapshots
+        .into_iter()
+        .filter(|id| id.as_str().starts_with(snapshot_id))
+        .collect();
+
+    match matches.len() {
+        0 => Err(anyhow!(
+            "No snapshot found with ID starting with '{}'",
+            snapshot_id
+        )),
+        1 => Ok(matches.remove(0)),
+        _ => Err(anyhow!(
+            "Ambiguous snapshot ID '{}' - matches {} snapshots",
+            snapshot_id,
+            matches.len()
+        )),
+    }
+}
+
+/// Parses an `--at` time: RFC 3339, or `YYYY-MM-DD [HH:MM[:SS]]` in local
+/// time. A date alone means the end of that day.
+pub fn parse_at(input: &str) -> Result<DateTime<Utc>> {
+    let input = input.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let naive = [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(input, format).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(input, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(23, 59, 59))
+    })
+    .ok_or_else(|| {
+        anyhow!(
+            "Invalid time '{}': expected e.g. \"2024-12-01 03:00\", \"2024-12-01\" or RFC 3339",
+            input
+        )
+    })?;
+
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| anyhow!("'{}' does not exist in the local time zone", input))
+}
+
+/// Returns the ID of the newest snapshot taken at or before `at` that
+/// matches `filter`.
+pub async fn snapshot_at(
+    repo: &Repository,
+    at: &str,
+    filter: &SnapshotFilter,
+) -> Result<SnapshotID> {
+    let time = parse_at(at)?;
+    let summaries = repo.snapshot_summaries(false).await?;
+    let snapshot = filter
+        .newest(
+            summaries.iter().map(|summary| &summary.snapshot),
+            Some(time),
+        )
+        .ok_or_else(|| {
+            anyhow!(
+                "No snapshot{} at or before {}",
+                describe_filter(filter),
+                time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S %Z")
+            )
+        })?;
+
+    tracing::info!(
+        "--at {} selected snapshot {} from {}",
+        at,
+        snapshot.short_id(),
+        snapshot
+            .time
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S")
+    );
+    Ok(snapshot.id.clone())
+}
@@ -0,0 +1,216 @@
+//! Policy command for the retention policy stored in the repository.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap policy set --keep-daily 7 --keep-weekly 4     # Default rules
+//! ghostsnap policy set --host db01 --keep-daily 30        # Host override
+//! ghostsnap policy set --tag manual --keep-last 10        # Tag override
+//! ghostsnap policy show
+//! ghostsnap policy unset --host db01
+//! ghostsnap forget --use-policy
+//! ```
+
+use anyhow::{Result, anyhow};
+use clap::{Args, Subcommand};
+use ghostsnap_core::{
+    LockManager, LockType, PolicyScope, Repository, RepositoryLock, RetentionPolicy, RetentionRules,
+};
+
+/// Policy command for managing the repository retention policy.
+#[derive(Args)]
+pub struct PolicyCommand {
+    #[command(subcommand)]
+    subcommand: PolicySubcommand,
+}
+
+#[derive(Subcommand)]
+enum PolicySubcommand {
+    /// Set the default rules or a host or tag override.
+    Set(PolicySetCommand),
+
+    /// Show the stored policy.
+    Show,
+
+    /// Remove a host or tag override, or the whole policy.
+    Unset(PolicyUnsetCommand),
+}
+
+impl PolicyCommand {
+    pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+
+        let password = crate::password::repository_password(cli)?;
+
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+
+        match &self.subcommand {
+            PolicySubcommand::Set(cmd) => cmd.run(&repo).await,
+            PolicySubcommand::Show => show(&repo).await,
+            PolicySubcommand::Unset(cmd) => cmd.run(&repo).await,
+        }
+    }
+}
+
+/// Host or tag selected for an override.
+#[derive(Args)]
+struct ScopeArgs {
+    /// Override for snapshots from this host
+    #[arg(long, conflicts_with = "tag")]
+    host: Option<String>,
+
+    /// Override for snapshots with this tag (takes precedence over host overrides)
+    #[arg(long)]
+    tag: Option<String>,
+}
+
+impl ScopeArgs {
+    fn scope(&self) -> PolicyScope {
+        match (&self.host, &self.tag) {
+            (Some(host), _) => PolicyScope::Host(host.clone()),
+            (None, Some(tag)) => PolicyScope::Tag(tag.clone()),
+            (None, None) => PolicyScope::Default,
+        }
+    }
+}
+
+// === Set Command ===
+
+#[derive(Args)]
+struct PolicySetCommand {
+    #[command(flatten)]
+    scope: ScopeArgs,
+
+    /// Keep last N snapshots
+    #[arg(long)]
+    keep_last: Option<u32>,
+
+    /// Keep daily snapshots for N days
+    #[arg(long)]
+    keep_daily: Option<u32>,
+
+    /// Keep weekly snapshots for N weeks
+    #[arg(long)]
+    keep_weekly: Option<u32>,
+
+    /// Keep monthly snapshots for N months
+    #[arg(long)]
+    keep_monthly: Option<u32>,
+
+    /// Keep yearly snapshots for N years
+    #[arg(long)]
+    keep_yearly: Option<u32>,
+}
+
+impl PolicySetCommand {
+    async fn run(&self, repo: &Repository) -> Result<()> {
+        let rules = RetentionRules {
+            keep_last: self.keep_last,
+            keep_daily: self.keep_daily,
+            keep_weekly: self.keep_weekly,
+            keep_monthly: self.keep_monthly,
+            keep_yearly: self.keep_yearly,
+        };
+        if rules.is_empty() {
+            return Err(anyhow!(
+                "No rules given (use --keep-last, --keep-daily, ...; `policy unset` removes rules)"
+            ));
+        }
+
+        let _lock = lock(repo).await?;
+        let mut policy = repo.load_retention_policy().await?.unwrap_or_default();
+        let scope = self.scope.scope();
+        println!("Set {} rules: {}", scope, rules);
+        policy.set_rules(scope, rules);
+        repo.save_retention_policy(&policy).await?;
+
+        Ok(())
+    }
+}
+
+// === Show Command ===
+
+async fn show(repo: &Repository) -> Result<()> {
+    let Some(policy) = repo.load_retention_policy().await? else {
+        println!("No retention policy stored in this repository");
+        return Ok(());
+    };
+    print_policy(&policy);
+    Ok(())
+}
+
+fn print_policy(policy: &RetentionPolicy) {
+    println!(
+        "Retention policy (updated {}):",
+        policy.updated_at.format("%Y-%m-%d %H:%M:%S")
+    );
+    println!("  {}: {}", PolicyScope::Default, policy.default);
+    for (host, rules) in &policy.hosts {
+        println!("  {}: {}", PolicyScope::Host(host.clone()), rules);
+    }
+    for (tag, rules) in &policy.tags {
+        println!("  {}: {}", PolicyScope::Tag(tag.clone()), rules);
+    }
+}
+
+// === Unset Command ===
+
+#[derive(Args)]
+struct PolicyUnsetCommand {
+    #[command(flatten)]
+    scope: ScopeArgs,
+
+    /// Remove the whole policy, including all overrides
+    #[arg(long, conflicts_with_all = ["host", "tag"])]
+    all: bool,
+}
+
+impl PolicyUnsetCommand {
+    async fn run(&self, repo: &Repository) -> Result<()> {
+        let _lock = lock(repo).await?;
+
+        if self.all {
+            if repo.delete_retention_policy().await? {
+                println!("Removed retention policy");
+            } else {
+                println!("No retention policy stored in this repository");
+            }
+            return Ok(());
+        }
+
+        let scope = self.scope.scope();
+        let Some(mut policy) = repo.load_retention_policy().await? else {
+            println!("No retention policy stored in this repository");
+            return Ok(());
+        };
+        if policy.rules(&scope).is_none() {
+            return Err(anyhow!("Policy has no {} rules", scope));
+        }
+
+        policy.set_rules(scope.clone(), RetentionRules::default());
+        if policy.is_empty() {
+            repo.delete_retention_policy().await?;
+            println!(
+                "Removed {} rules; policy is now empty and was removed",
+                scope
+            );
+        } else {
+            repo.save_retention_policy(&policy).await?;
+            println!("Removed {} rules", scope);
+        }
+
+        Ok(())
+    }
+}
+
+/// Serializes policy changes with other writers on local repositories.
+async fn lock(repo: &Repository) -> Result<Option<RepositoryLock>> {
+    match repo.local_path() {
+        Some(path) => Ok(Some(
+            LockManager::new(path)
+                .acquire(LockType::Exclusive, "policy")
+                .await?,
+        )),
+        None => Ok(None),
+    }
+}
@@ -1,11 +1,13 @@
-use anyhow::{Result, anyhow};
+use anyhow::Result;
 use clap::Args;
-use ghostsnap_core::{ChunkID, LockManager, LockType, Repository};
-use indicatif::{ProgressBar, ProgressStyle};
+use ghostsnap_core::{Access, ChunkID, LockType, PackID};
 use std::collections::HashSet;
 use std::io::{self, Write};
 use tracing::info;
 
+/// Unused percentage above which a pack is repacked when `--max-unused` is not given.
+pub const DEFAULT_MAX_UNUSED: u32 = 50;
+
 #[derive(Args)]
 pub struct PruneCommand {
     #[arg(long, short = 'n', help = "Dry run - show what would be deleted")]
@@ -22,64 +24,55 @@ impl PruneCommand {
     pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
         let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
 
-        let password = cli
-            .password
-            .clone()
-            .or_else(|| {
-                print!("Enter repository password: ");
-                io::stdout().flush().ok()?;
-                rpassword::read_password().ok()
-            })
-            .ok_or_else(|| anyhow!("Password required"))?;
+        let password = crate::password::repository_password(cli)?;
 
-        let repo = Repository::open_at_location(repo_location, &password).await?;
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+        if !self.dry_run {
+            crate::commands::require_access(&repo, Access::Delete, "prune").await?;
+        }
 
-        // Acquire exclusive lock for prune operation
+        // Acquire exclusive lock for prune operation; a dry run only reads
+        let lock_type = if self.dry_run {
+            LockType::Shared
+        } else {
+            LockType::Exclusive
+        };
         let _lock = if let Some(repo_path) = repo.local_path() {
-            let lock_manager = LockManager::new(repo_path);
-            Some(lock_manager.acquire(LockType::Exclusive, "prune").await?)
+            let lock_manager = crate::commands::lock_manager(cli, repo_path)?;
+            Some(lock_manager.acquire(lock_type, "prune").await?)
         } else {
             tracing::warn!("Repository locking not supported for remote repositories");
             None
         };
 
+        // Under the exclusive lock, move objects of repositories created
+        // before sharding into the fan-out layout
+        if !self.dry_run {
+            let migrated = repo.migrate_layout().await?;
+            if migrated > 0 {
+                println!("Moved {} objects into the fan-out layout", migrated);
+            }
+        }
+
         println!("Analyzing repository...");
         println!();
 
-        // Step 1: Find all chunks referenced by snapshots
-        let snapshots = repo.list_snapshots().await?;
-        let mut referenced_chunks: HashSet<ChunkID> = HashSet::new();
-
+        // Step 1: Find all chunks referenced by snapshots. Only snapshots
+        // added or forgotten since the reference counts were last updated
+        // have their trees loaded.
+        println!("[1/4] Updating chunk reference counts...");
+        let refcounts = repo.refcounts().await?;
+        let referenced_chunks: HashSet<ChunkID> = refcounts.referenced_chunks();
         println!(
-            "[1/4] Scanning {} snapshots for referenced chunks...",
-            snapshots.len()
+            "  Found {} referenced chunks in {} snapshots",
+            referenced_chunks.len(),
+            refcounts.snapshots().len()
         );
-        let pb = ProgressBar::new(snapshots.len() as u64);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{bar:40} {pos}/{len}")
-                .unwrap(),
-        );
-
-        for snapshot_id in &snapshots {
-            if let Ok(snapshot) = repo.load_snapshot(snapshot_id).await
-                && let Ok(tree) = repo.load_tree(&snapshot.tree).await
-            {
-                for node in &tree.nodes {
-                    for chunk_ref in &node.chunks {
-                        referenced_chunks.insert(chunk_ref.id);
-                    }
-                }
-            }
-            pb.inc(1);
-        }
-        pb.finish_and_clear();
-        println!("  Found {} referenced chunks", referenced_chunks.len());
 
         // Step 2: Find all indexed chunks
         let index = repo.index();
         let index_guard = index.read().await;
-        let all_chunks: HashSet<_> = index_guard.iter_chunks().map(|(id, _)| *id).collect();
+        let all_chunks: HashSet<_> = index_guard.iter_chunk_ids().collect();
         drop(index_guard);
 
         println!("[2/4] Checking {} indexed chunks...", all_chunks.len());
@@ -89,17 +82,15 @@ impl PruneCommand {
             all_chunks.difference(&referenced_chunks).cloned().collect();
         println!("  Found {} orphaned chunks", orphaned_chunks.len());
 
-        if orphaned_chunks.is_empty() {
-            println!();
-            println!("No unused data to prune");
-            return Ok(());
-        }
-
-        // Step 4: Analyze packs containing orphaned chunks
+        // Step 4: Analyze packs containing orphaned chunks, or copies of
+        // chunks indexed elsewhere that a forgotten standalone snapshot wrote
         println!("[3/4] Analyzing pack files...");
 
+        // Packs of standalone snapshots are kept whole while they exist
+        let standalone_packs = repo.standalone_packs().await?;
+
         // Map pack_id -> (total_chunks, orphaned_chunks, size)
-        let mut pack_stats: std::collections::HashMap<String, (usize, usize, u64)> =
+        let mut pack_stats: std::collections::HashMap<PackID, (usize, usize, u64)> =
             std::collections::HashMap::new();
 
         let index = repo.index();
@@ -111,11 +102,37 @@ impl PruneCommand {
                 .entry(location.pack_id.clone())
                 .or_insert((0, 0, 0));
             entry.0 += 1;
-            if orphaned_chunks.contains(chunk_id) {
+            if orphaned_chunks.contains(&chunk_id) {
                 entry.1 += 1;
             }
         }
+        // Chunks stored in a pack beyond those the index places there are
+        // unused copies
+        for pack_id in index_guard.all_pack_ids() {
+            pack_stats.entry(pack_id).or_insert((0, 0, 0));
+        }
+        for (pack_id, (total, orphaned, _)) in pack_stats.iter_mut() {
+            let stored = index_guard
+                .get_pack(pack_id)
+                .map_or(0, |info| info.chunk_count as usize);
+            if stored > *total {
+                *orphaned += stored - *total;
+                *total = stored;
+            }
+        }
         drop(index_guard);
+        pack_stats.retain(|pack_id, (_, orphaned, _)| {
+            *orphaned > 0 && !standalone_packs.contains(pack_id)
+        });
+
+        if orphaned_chunks.is_empty() && pack_stats.is_empty() {
+            println!();
+            println!("No unused data to prune");
+            if self.dry_run {
+                println!("Dry run - no changes made");
+            }
+            return Ok(());
+        }
 
         // Get pack sizes
         for (pack_id, stats) in pack_stats.iter_mut() {
@@ -125,16 +142,12 @@ impl PruneCommand {
         }
 
         // Find packs to delete (100% orphaned) or repack (partially orphaned)
-        let max_unused_pct = self.max_unused.unwrap_or(50) as f64 / 100.0;
-        let mut packs_to_delete: Vec<String> = Vec::new();
-        let mut packs_to_repack: Vec<String> = Vec::new();
+        let max_unused_pct = self.max_unused.unwrap_or(DEFAULT_MAX_UNUSED) as f64 / 100.0;
+        let mut packs_to_delete: Vec<PackID> = Vec::new();
+        let mut packs_to_repack: Vec<PackID> = Vec::new();
         let mut space_to_reclaim = 0u64;
 
         for (pack_id, (total, orphaned, size)) in &pack_stats {
-            if *orphaned == 0 {
-                continue;
-            }
-
             let orphan_ratio = *orphaned as f64 / *total as f64;
 
             if orphan_ratio >= 1.0 {
@@ -157,7 +170,30 @@ impl PruneCommand {
         println!("  Packs to repack:    {}", packs_to_repack.len());
         println!("  Space to reclaim:   {}", format_size(space_to_reclaim));
 
+        let foreign = repo.foreign_objects().await?;
+        if !foreign.is_empty() {
+            println!(
+                "  Foreign objects:    {} (not written by ghostsnap, left untouched)",
+                foreign.len()
+            );
+            for path in &foreign {
+                info!("Ignoring foreign object: {}", path);
+            }
+        }
+
         if self.dry_run {
+            println!();
+            println!("Would delete:");
+            for pack_id in &packs_to_delete {
+                println!("  data/{}.pack", pack_id);
+            }
+            if !orphaned_chunks.is_empty() {
+                println!("Would rewrite:");
+                println!(
+                    "  index/main.idx ({} chunks removed)",
+                    orphaned_chunks.len()
+                );
+            }
             println!();
             println!("Dry run - no changes made");
             println!("Run without --dry-run to actually prune");
@@ -180,7 +216,7 @@ impl PruneCommand {
             println!(" done");
         }
 
-        // Remove orphaned chunks from index
+        // Remove orphaned chunks from index, and deleted packs with them
         print!("  Removing {} chunks from index...", orphaned_chunks.len());
         io::stdout().flush()?;
 
@@ -196,6 +232,10 @@ impl PruneCommand {
         repo.save_index().await?;
         println!(" done");
 
+        if let Err(e) = repo.refresh_stats_cache().await {
+            tracing::warn!("Failed to update stats cache: {}", e);
+        }
+
         // Note: Repacking would require reading chunks from old packs and writing new ones
         // This is a more complex operation that we'll note but not implement fully here
         if !packs_to_repack.is_empty() {
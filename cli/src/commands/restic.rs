@@ -0,0 +1,349 @@
+//! Read-only access to restic repositories.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap --repo /srv/restic-repo restic snapshots
+//! ghostsnap --repo /srv/restic-repo restic ls 4bba301e home/alice
+//! ghostsnap --repo /srv/restic-repo restic restore 4bba301e --target /tmp/restore
+//! ```
+//!
+//! The repository is never written to; back up into a ghostsnap repository
+//! to continue from there.
+
+use anyhow::{Result, anyhow};
+use clap::{Args, Subcommand};
+use ghostsnap_core::snapshot::Tree;
+use ghostsnap_core::{NodeType, ResticRepository, TreeNode};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// Restic command for browsing and restoring from restic repositories.
+#[derive(Args)]
+pub struct ResticCommand {
+    #[command(subcommand)]
+    subcommand: ResticSubcommand,
+}
+
+#[derive(Subcommand)]
+enum ResticSubcommand {
+    /// List the snapshots in the restic repository.
+    Snapshots {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List the contents of a snapshot.
+    Ls {
+        /// Snapshot ID (full or short prefix)
+        snapshot_id: String,
+
+        /// Only list entries below this path
+        path: Option<String>,
+    },
+
+    /// Restore files from a snapshot.
+    Restore {
+        /// Snapshot ID (full or short prefix)
+        snapshot_id: String,
+
+        /// Target directory for restore
+        #[arg(short = 't', long)]
+        target: PathBuf,
+
+        /// Specific paths to restore (optional)
+        paths: Vec<String>,
+
+        /// Overwrite existing files
+        #[arg(long)]
+        overwrite: bool,
+
+        /// Show what would be restored without writing anything
+        #[arg(long, short = 'n')]
+        dry_run: bool,
+    },
+}
+
+impl ResticCommand {
+    pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+        let repo_location = crate::commands::apply_proxy(cli, repo_location)?;
+
+        let password = crate::password::password_or_prompt(
+            cli.password.as_deref(),
+            "Enter restic repository password: ",
+        )?;
+
+        info!("Opening restic repository at: {}", repo_location.display());
+        let repo = ResticRepository::open(repo_location, &password).await?;
+        debug!("restic repository format version {}", repo.version());
+
+        match &self.subcommand {
+            ResticSubcommand::Snapshots { json } => list_snapshots(&repo, *json).await,
+            ResticSubcommand::Ls { snapshot_id, path } => {
+                list_files(&repo, snapshot_id, path.as_deref()).await
+            }
+            ResticSubcommand::Restore {
+                snapshot_id,
+                target,
+                paths,
+                overwrite,
+                dry_run,
+            } => {
+                let options = RestoreOptions {
+                    overwrite: *overwrite,
+                    dry_run: *dry_run,
+                };
+                restore(&repo, snapshot_id, target, paths, &options).await
+            }
+        }
+    }
+}
+
+async fn list_snapshots(repo: &ResticRepository, json: bool) -> Result<()> {
+    let snapshots = repo.list_snapshots().await?;
+
+    if json {
+        let entries: Vec<_> = snapshots
+            .iter()
+            .map(|s| {
+                serde_json::json!({
+                    "id": s.id,
+                    "time": s.time,
+                    "hostname": s.hostname,
+                    "username": s.username,
+                    "paths": s.paths,
+                    "tags": s.tags,
+                    "tree": s.tree,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    if snapshots.is_empty() {
+        println!("No snapshots found");
+        return Ok(());
+    }
+
+    println!(
+        "{:<10} {:<20} {:<15} {:<20} Paths",
+        "ID", "Time", "Host", "Tags"
+    );
+    println!("{}", "-".repeat(80));
+    for snapshot in &snapshots {
+        println!(
+            "{:<10} {:<20} {:<15} {:<20} {}",
+            snapshot.short_id(),
+            snapshot.time.format("%Y-%m-%d %H:%M:%S"),
+            snapshot.hostname,
+            snapshot.tags.join(","),
+            snapshot.paths.join(", ")
+        );
+    }
+    println!("{} snapshots", snapshots.len());
+    Ok(())
+}
+
+async fn list_files(repo: &ResticRepository, snapshot_id: &str, path: Option<&str>) -> Result<()> {
+    let snapshot = repo.load_snapshot(snapshot_id).await?;
+    let tree = repo.load_tree(&snapshot).await?;
+
+    let paths: Vec<String> = path.map(str::to_string).into_iter().collect();
+    for node in select_nodes(&tree, &paths) {
+        let suffix = match node.node_type {
+            NodeType::Directory => "/".to_string(),
+            NodeType::Symlink => format!(" -> {}", node.link_target.as_deref().unwrap_or("?")),
+            _ => String::new(),
+        };
+        println!("{}{}", node.name, suffix);
+    }
+    Ok(())
+}
+
+/// Nodes at or below any of `paths`, or every node when none are given.
+fn select_nodes<'a>(tree: &'a Tree, paths: &[String]) -> Vec<&'a TreeNode> {
+    tree.nodes
+        .iter()
+        .filter(|node| {
+            paths.is_empty()
+                || paths.iter().any(|p| {
+                    let p = p.trim_matches('/');
+                    node.name == p || node.name.starts_with(&format!("{}/", p))
+                })
+        })
+        .collect()
+}
+
+struct RestoreOptions {
+    overwrite: bool,
+    dry_run: bool,
+}
+
+async fn restore(
+    repo: &ResticRepository,
+    snapshot_id: &str,
+    target: &Path,
+    paths: &[String],
+    options: &RestoreOptions,
+) -> Result<()> {
+    let snapshot = repo.load_snapshot(snapshot_id).await?;
+    let tree = repo.load_tree(&snapshot).await?;
+
+    println!("Restoring restic snapshot: {}", snapshot.short_id());
+    println!("Created: {}", snapshot.time.format("%Y-%m-%d %H:%M:%S UTC"));
+    println!("Host: {}", snapshot.hostname);
+    println!("Target: {}", target.display());
+    if options.dry_run {
+        println!("DRY RUN - no files will be written");
+    }
+
+    // Tree nodes are sorted by path, so every directory precedes its contents
+    let nodes = select_nodes(&tree, paths);
+    if nodes.is_empty() {
+        println!("No files to restore");
+        return Ok(());
+    }
+
+    let total_bytes: u64 = nodes
+        .iter()
+        .filter(|n| n.node_type == NodeType::File)
+        .map(|n| n.size)
+        .sum();
+    let pb = ProgressBar::new(total_bytes);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}) {msg}")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+
+    let mut restored = 0;
+    let mut skipped = 0;
+    let mut unsupported = 0;
+    let mut failed = 0;
+    let mut directories = Vec::new();
+
+    for node in nodes {
+        pb.set_message(node.name.clone());
+        let dest = target.join(&node.name);
+
+        if dest.symlink_metadata().is_ok() && !options.overwrite && !node.is_dir() {
+            skipped += 1;
+            pb.inc(node.size);
+            continue;
+        }
+
+        if options.dry_run {
+            pb.println(format!("Would restore: {}", dest.display()));
+            pb.inc(node.size);
+            restored += 1;
+            continue;
+        }
+
+        let result = match node.node_type {
+            NodeType::Directory => {
+                directories.push((dest.clone(), node));
+                std::fs::create_dir_all(&dest).map_err(Into::into)
+            }
+            NodeType::File => restore_file(repo, node, &dest, &pb).await,
+            NodeType::Symlink => restore_symlink(node, &dest),
+            NodeType::CharDevice | NodeType::BlockDevice | NodeType::Fifo => {
+                debug!("Skipping {}: {}", node.node_type.as_str(), node.name);
+                unsupported += 1;
+                continue;
+            }
+        };
+
+        match result {
+            Ok(()) => restored += 1,
+            Err(e) => {
+                failed += 1;
+                warn!("Failed to restore {}: {}", node.name, e);
+            }
+        }
+    }
+
+    // Directory metadata last, deepest first, so that writing their contents
+    // does not change it again.
+    for (dest, node) in directories.iter().rev() {
+        if let Err(e) = apply_metadata(node, dest) {
+            warn!("Failed to restore metadata for {}: {}", dest.display(), e);
+        }
+    }
+
+    pb.finish_and_clear();
+    println!("Restore completed!");
+    println!("Restored: {} ({})", restored, HumanBytes(total_bytes));
+    if skipped > 0 {
+        println!("Skipped (existing): {}", skipped);
+    }
+    if unsupported > 0 {
+        println!("Skipped (devices/FIFOs): {}", unsupported);
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} entries failed to restore", failed));
+    }
+    Ok(())
+}
+
+async fn restore_file(
+    repo: &ResticRepository,
+    node: &TreeNode,
+    dest: &Path,
+    pb: &ProgressBar,
+) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::File::create(dest)?;
+    for chunk in &node.chunks {
+        let data = repo.load_blob(&chunk.id.to_hex()).await?;
+        file.write_all(&data)?;
+        pb.inc(data.len() as u64);
+    }
+    drop(file);
+    apply_metadata(node, dest)
+}
+
+fn restore_symlink(node: &TreeNode, dest: &Path) -> Result<()> {
+    let link_target = node
+        .link_target
+        .as_deref()
+        .ok_or_else(|| anyhow!("Symlink without target"))?;
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if dest.symlink_metadata().is_ok() {
+        std::fs::remove_file(dest)?;
+    }
+
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(link_target, dest)?;
+
+    #[cfg(not(unix))]
+    std::os::windows::fs::symlink_file(link_target, dest)?;
+
+    Ok(())
+}
+
+/// Applies the recorded modification time and permissions; the time first,
+/// as the permissions may not allow opening the file afterwards.
+fn apply_metadata(node: &TreeNode, dest: &Path) -> Result<()> {
+    if node.mtime > 0 {
+        let mtime = UNIX_EPOCH + Duration::from_secs(node.mtime as u64);
+        std::fs::File::open(dest)?.set_modified(mtime)?;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(dest, std::fs::Permissions::from_mode(node.mode))?;
+    }
+    Ok(())
+}
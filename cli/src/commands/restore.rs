@@ -1,9 +1,16 @@
+use crate::idmap::{IdMapper, IdRange};
 use anyhow::{Result, anyhow};
-use clap::Args;
-use ghostsnap_core::{NodeType, Repository, TreeNode};
+use clap::{Args, FromArgMatches};
+use ghostsnap_core::snapshot::{Snapshot, Tree};
+use ghostsnap_core::storage::{RepositoryLocation, storage_for_location};
+use ghostsnap_core::target::{plan_restore, restore_to};
+use ghostsnap_core::{
+    ChunkID, ChunkRef, LockType, NodeType, PackID, RehydratePriority, Repository, StorageTarget,
+    TreeNode,
+};
 use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
-use std::collections::HashMap;
-use std::io::{self, Write};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::SeekFrom;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 use tokio::fs;
@@ -11,10 +18,27 @@ use tracing::{debug, info, warn};
 
 #[derive(Args)]
 pub struct RestoreCommand {
-    #[arg(help = "Snapshot ID (full or short prefix)")]
-    snapshot_id: String,
+    #[arg(
+        required_unless_present = "at",
+        help = "Snapshot ID (full or short prefix, or `latest`); with --at, the first path to restore"
+    )]
+    snapshot_id: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "TIME",
+        help = "Restore the newest snapshot taken at or before TIME, e.g. \"2024-12-01 03:00\""
+    )]
+    at: Option<String>,
+
+    #[command(flatten)]
+    filter: crate::commands::SnapshotFilterArgs,
 
-    #[arg(short = 't', long, help = "Target directory for restore")]
+    #[arg(
+        short = 't',
+        long,
+        help = "Target directory, or a storage location (e.g. s3:bucket/prefix) to write the files to as objects"
+    )]
     target: String,
 
     #[arg(help = "Specific paths to restore (optional)")]
@@ -26,15 +50,47 @@ pub struct RestoreCommand {
     #[arg(long, help = "Don't restore ownership (uid/gid)")]
     no_ownership: bool,
 
+    #[arg(
+        long,
+        value_name = "OLD[-LAST]:NEW",
+        help = "Restore files owned by uid OLD (or OLD..=LAST) as NEW (repeatable)"
+    )]
+    uid_map: Vec<IdRange>,
+
+    #[arg(
+        long,
+        value_name = "OLD[-LAST]:NEW",
+        help = "Restore files owned by gid OLD (or OLD..=LAST) as NEW (repeatable)"
+    )]
+    gid_map: Vec<IdRange>,
+
+    #[arg(
+        long,
+        help = "Restore recorded uid/gid numbers instead of mapping owners by name"
+    )]
+    numeric_ids: bool,
+
     #[arg(long, help = "Overwrite existing files")]
     overwrite: bool,
 
+    #[arg(
+        long,
+        help = "Compare existing files chunk by chunk and only download and rewrite the ranges that differ"
+    )]
+    verify_existing: bool,
+
     #[arg(long, short = 'n', help = "Dry run - don't write any files")]
-    dry_run: bool,
+    pub dry_run: bool,
 
-    #[arg(long, help = "Don't restore extended attributes")]
+    #[arg(
+        long,
+        help = "Don't restore extended attributes (file capabilities are still restored)"
+    )]
     no_xattr: bool,
 
+    #[arg(long, help = "Don't restore file capabilities (security.capability)")]
+    no_capabilities: bool,
+
     #[arg(long, help = "Don't restore file timestamps (mtime)")]
     no_timestamps: bool,
 
@@ -49,31 +105,123 @@ pub struct RestoreCommand {
         help = "Don't restore hardlinks as hardlinks (create copies instead)"
     )]
     no_hardlinks: bool,
+
+    #[arg(
+        long,
+        short = 'i',
+        conflicts_with = "paths",
+        help = "Browse the snapshot and pick files to restore"
+    )]
+    interactive: bool,
+
+    #[arg(long, help = "Skip the free space, permission and conflict checks")]
+    no_preflight: bool,
+
+    #[arg(
+        long,
+        default_value = "standard",
+        help = "Priority for rehydrating archived packs (standard or high)"
+    )]
+    rehydrate_priority: RehydratePriority,
+}
+
+/// Maximum number of paths listed per item of the preflight and metadata
+/// reports.
+const PREFLIGHT_LIST_LIMIT: usize = 20;
+
+/// Outcome of the checks run before a restore writes anything.
+#[derive(Default)]
+struct Preflight {
+    /// Bytes that will be written, net of files being replaced
+    bytes_required: u64,
+    /// Free space on the target filesystem, if it could be determined
+    bytes_available: Option<u64>,
+    /// Existing files that will be replaced (`--overwrite`)
+    overwrites: Vec<PathBuf>,
+    /// Existing entries left alone because `--overwrite` was not given
+    skipped: usize,
+    /// Existing files that will be compared and patched in place
+    /// (`--verify-existing`)
+    patched: usize,
+    /// Conditions that would make the restore fail part way through
+    problems: Vec<String>,
+}
+
+/// Existing files brought in line with the snapshot by `--verify-existing`.
+#[derive(Default)]
+struct DeltaReport {
+    files: usize,
+    /// Files that already matched the snapshot
+    unchanged: usize,
+    bytes_downloaded: u64,
+    bytes_reused: u64,
+}
+
+/// Metadata that could not be applied, reported after the restore so that
+/// e.g. a non-root restore of system files doesn't silently lose it.
+#[derive(Default)]
+struct MetadataReport {
+    /// Entries left owned by the restoring user because it isn't root
+    ownership_skipped: usize,
+    /// Paths by the kind of metadata that failed, e.g. "File capabilities"
+    failures: BTreeMap<&'static str, Vec<PathBuf>>,
 }
 
 impl RestoreCommand {
+    /// A restore as given by command-line `args` (snapshot, target and
+    /// options), for commands that run a preset restore.
+    pub fn from_args<I, S>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<std::ffi::OsString> + Clone,
+    {
+        let command = Self::augment_args(clap::Command::new("restore").no_binary_name(true));
+        let matches = command.try_get_matches_from(args)?;
+        Ok(Self::from_arg_matches(&matches)?)
+    }
+
     pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
         let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
 
-        let password = cli
-            .password
-            .clone()
-            .or_else(|| {
-                print!("Enter repository password: ");
-                io::stdout().flush().ok()?;
-                rpassword::read_password().ok()
-            })
-            .ok_or_else(|| anyhow!("Password required"))?;
+        let password = crate::password::repository_password(cli)?;
 
         info!("Opening repository at: {}", repo_location.display());
-        let repo = Repository::open_at_location(repo_location, &password).await?;
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+
+        // Shared: prune must not delete packs while a long restore reads them
+        let _lock = if let Some(repo_path) = repo.local_path() {
+            let lock_manager = crate::commands::lock_manager(cli, repo_path)?;
+            Some(lock_manager.acquire(LockType::Shared, "restore").await?)
+        } else {
+            None
+        };
 
-        // Support short snapshot IDs
-        let full_snapshot_id = self.resolve_snapshot_id(&repo, &self.snapshot_id).await?;
+        // Support short snapshot IDs, `latest` and --at
+        let filter = self.filter.filter();
+        let full_snapshot_id = match (&self.at, &self.snapshot_id) {
+            (Some(at), _) => crate::commands::snapshot_at(&repo, at, &filter).await?,
+            (None, Some(snapshot_id)) => {
+                crate::commands::resolve_snapshot(&repo, snapshot_id, &filter).await?
+            }
+            (None, None) => return Err(anyhow!("Snapshot ID or --at required")),
+        };
+        if self.interactive && !self.restore_paths().is_empty() {
+            return Err(anyhow!(
+                "--interactive cannot be used with paths to restore"
+            ));
+        }
 
         info!("Loading snapshot: {}", full_snapshot_id);
         let snapshot = repo.load_snapshot(&full_snapshot_id).await?;
 
+        // Remote locations get the files as objects, e.g. in another bucket
+        let target_location = RepositoryLocation::parse(&self.target)?;
+        if !matches!(target_location, RepositoryLocation::Local(_)) {
+            return self
+                .restore_to_storage(&repo, &snapshot, target_location)
+                .await;
+        }
+
         let target_path = PathBuf::from(&self.target);
         if !target_path.exists() {
             if self.dry_run {
@@ -93,8 +241,38 @@ impl RestoreCommand {
             println!("DRY RUN - no files will be written");
         }
 
-        // Load the tree
-        let tree = repo.load_tree(&snapshot.tree).await?;
+        // Load the tree; of a paged tree, only the pages needed for the
+        // paths to restore
+        let mut tree = if self.interactive {
+            repo.load_tree(&snapshot.tree).await?
+        } else {
+            self.load_restore_tree(&repo, &snapshot.tree, &self.restore_paths())
+                .await?
+        };
+
+        if !self.no_ownership {
+            let remapped = self.map_owners(&snapshot, &mut tree);
+            if remapped > 0 {
+                println!("Ownership: {} entries remapped", remapped);
+            }
+        }
+
+        let selected_paths = if self.interactive {
+            let picker_tree = tree.clone();
+            let title = snapshot.short_id();
+            let picked =
+                tokio::task::spawn_blocking(move || crate::tui::picker::run(&picker_tree, &title))
+                    .await??;
+            match picked {
+                Some(paths) => paths,
+                None => {
+                    println!("Restore cancelled");
+                    return Ok(());
+                }
+            }
+        } else {
+            self.restore_paths()
+        };
 
         // Build a lookup map for finding original files (needed for hardlink restoration)
         let node_by_name: HashMap<String, &TreeNode> = tree
@@ -103,40 +281,14 @@ impl RestoreCommand {
             .map(|node| (node.name.clone(), node))
             .collect();
 
-        // Filter nodes to restore
-        let mut nodes_to_restore: Vec<_> = if self.paths.is_empty() {
-            tree.nodes.iter().collect()
-        } else {
-            tree.nodes
-                .iter()
-                .filter(|node| {
-                    self.paths.iter().any(|p| {
-                        let p = p.trim_end_matches('/');
-                        // Exact match or proper directory prefix (with path separator)
-                        node.name == p || node.name.starts_with(&format!("{}/", p))
-                    })
-                })
-                .collect()
-        };
-
+        // The selected entries, the directories leading to them, and
+        // directories first so parents are created before their contents
+        let nodes_to_restore = plan_restore(&tree, &selected_paths);
         if nodes_to_restore.is_empty() {
             println!("No files to restore");
             return Ok(());
         }
 
-        // Sort nodes: directories first (by depth), then files and symlinks
-        // This ensures parent directories are created before their contents
-        nodes_to_restore.sort_by(|a, b| {
-            let a_is_dir = matches!(a.node_type, NodeType::Directory);
-            let b_is_dir = matches!(b.node_type, NodeType::Directory);
-
-            match (a_is_dir, b_is_dir) {
-                (true, false) => std::cmp::Ordering::Less,
-                (false, true) => std::cmp::Ordering::Greater,
-                _ => a.name.cmp(&b.name),
-            }
-        });
-
         // Count by type
         let dir_count = nodes_to_restore
             .iter()
@@ -150,6 +302,7 @@ impl RestoreCommand {
             .iter()
             .filter(|n| n.node_type == NodeType::Symlink)
             .count();
+        let special_count = nodes_to_restore.iter().filter(|n| n.is_special()).count();
 
         // Count hardlinks
         let hardlink_count = nodes_to_restore
@@ -164,6 +317,32 @@ impl RestoreCommand {
         if hardlink_count > 0 {
             println!("  ({} hardlinks)", hardlink_count);
         }
+        if special_count > 0 {
+            println!("  ({} devices/FIFOs)", special_count);
+        }
+
+        if !self.no_preflight {
+            let preflight = self.preflight(&nodes_to_restore, &target_path);
+            preflight.print();
+            if !preflight.problems.is_empty() {
+                println!("Preflight failed:");
+                for problem in &preflight.problems {
+                    println!("  - {}", problem);
+                }
+                if self.dry_run {
+                    println!("(dry run - continuing anyway)");
+                } else {
+                    return Err(anyhow!(
+                        "Restore preflight failed with {} problem(s); nothing was written",
+                        preflight.problems.len()
+                    ));
+                }
+            }
+        }
+
+        // Archived packs can't be read until they are rehydrated, which takes
+        // hours; start that now rather than failing on the first file.
+        self.ensure_packs_readable(&repo, &nodes_to_restore).await?;
 
         // Calculate total bytes to restore
         let total_bytes: u64 = nodes_to_restore
@@ -188,6 +367,10 @@ impl RestoreCommand {
         let mut verify_failed_count = 0;
         let mut bytes_restored = 0u64;
         let mut hardlinks_restored = 0;
+        let mut devices_skipped = 0;
+
+        let mut metadata = MetadataReport::default();
+        let mut delta = DeltaReport::default();
 
         // Track directories for later timestamp restoration
         let mut directories: Vec<(PathBuf, &TreeNode)> = Vec::new();
@@ -195,13 +378,18 @@ impl RestoreCommand {
         // Track restored files for hardlink creation (path -> dest_path)
         let mut restored_files: HashMap<String, PathBuf> = HashMap::new();
 
+        // Restored symlinks, which nothing is written through
+        let mut restored_symlinks: HashSet<String> = HashSet::new();
+
         for node in &nodes_to_restore {
             pb.set_message(node.name.clone());
 
             let dest_path = target_path.join(&node.name);
+            let patch = self.patches_in_place(node)
+                && std::fs::symlink_metadata(&dest_path).is_ok_and(|meta| meta.is_file());
 
             // Check if file exists
-            if dest_path.exists() && !self.overwrite && !self.dry_run {
+            if dest_path.exists() && !self.overwrite && !self.dry_run && !patch {
                 skipped_count += 1;
                 debug!("Skipping existing: {}", node.name);
                 if node.node_type == NodeType::File {
@@ -211,6 +399,25 @@ impl RestoreCommand {
                 continue;
             }
 
+            // Device nodes can only be created by root
+            if matches!(node.node_type, NodeType::CharDevice | NodeType::BlockDevice)
+                && !self.dry_run
+                && !running_as_root()
+            {
+                devices_skipped += 1;
+                debug!("Skipping device node (requires root): {}", node.name);
+                continue;
+            }
+
+            // An entry below a symlink, restored or already in the target,
+            // would be written wherever the link points, possibly outside the
+            // target directory
+            if let Some(link) = symlink_ancestor(&target_path, &restored_symlinks, &node.name) {
+                failed_count += 1;
+                warn!("Not restoring {}: {} is a symlink", node.name, link);
+                continue;
+            }
+
             let result = match node.node_type {
                 NodeType::Directory => {
                     if self.dry_run {
@@ -218,17 +425,29 @@ impl RestoreCommand {
                         Ok(())
                     } else {
                         directories.push((dest_path.clone(), node));
-                        self.restore_directory(node, &dest_path).await
+                        self.restore_directory(node, &dest_path, &mut metadata)
+                            .await
                     }
                 }
                 NodeType::File => {
                     if self.dry_run {
+                        let mut result = Ok(());
                         if let Some(ref target) = node.hardlink_target {
                             println!(
                                 "Would create hardlink: {} -> {}",
                                 dest_path.display(),
                                 target
                             );
+                        } else if patch {
+                            match Self::differing_chunks(&repo, node, &dest_path).await {
+                                Ok(differing) => println!(
+                                    "Would patch file: {} ({} of {} chunks differ)",
+                                    dest_path.display(),
+                                    differing.len(),
+                                    node.chunks.len()
+                                ),
+                                Err(e) => result = Err(e),
+                            }
                         } else {
                             println!(
                                 "Would restore file: {} ({})",
@@ -238,7 +457,7 @@ impl RestoreCommand {
                         }
                         bytes_restored += node.size;
                         pb.set_position(bytes_restored);
-                        Ok(())
+                        result
                     } else if let Some(ref target) = node.hardlink_target {
                         // This is a hardlink - create it as a link to the original
                         if !self.no_hardlinks {
@@ -247,12 +466,14 @@ impl RestoreCommand {
                             } else {
                                 // Original file not found - restore as normal file
                                 warn!("Hardlink target {} not found, restoring as copy", target);
-                                self.restore_file(&repo, node, &dest_path).await
+                                self.restore_file(&repo, node, &dest_path, &mut metadata)
+                                    .await
                             }
                         } else {
                             // --no-hardlinks flag: restore as copy of original file
                             if let Some(original_node) = node_by_name.get(target) {
-                                self.restore_file(&repo, original_node, &dest_path).await
+                                self.restore_file(&repo, original_node, &dest_path, &mut metadata)
+                                    .await
                             } else {
                                 Err(anyhow!(
                                     "Hardlink target '{}' not found in snapshot tree",
@@ -262,7 +483,13 @@ impl RestoreCommand {
                         }
                     } else {
                         // Normal file
-                        let result = self.restore_file(&repo, node, &dest_path).await;
+                        let result = if patch {
+                            self.patch_file(&repo, node, &dest_path, &mut delta, &mut metadata)
+                                .await
+                        } else {
+                            self.restore_file(&repo, node, &dest_path, &mut metadata)
+                                .await
+                        };
                         if result.is_ok() {
                             // Track for potential hardlinks
                             restored_files.insert(node.name.clone(), dest_path.clone());
@@ -280,7 +507,19 @@ impl RestoreCommand {
                         );
                         Ok(())
                     } else {
-                        self.restore_symlink(node, &dest_path).await
+                        self.restore_symlink(node, &dest_path, &mut metadata).await
+                    }
+                }
+                NodeType::CharDevice | NodeType::BlockDevice | NodeType::Fifo => {
+                    if self.dry_run {
+                        println!(
+                            "Would create {}: {}",
+                            node.node_type.as_str(),
+                            dest_path.display()
+                        );
+                        Ok(())
+                    } else {
+                        self.restore_special(node, &dest_path, &mut metadata).await
                     }
                 }
             };
@@ -288,6 +527,9 @@ impl RestoreCommand {
             match result {
                 Ok(_) => {
                     restored_count += 1;
+                    if node.node_type == NodeType::Symlink {
+                        restored_symlinks.insert(node.name.clone());
+                    }
                     if node.hardlink_target.is_some() && !self.no_hardlinks {
                         hardlinks_restored += 1;
                     }
@@ -316,13 +558,14 @@ impl RestoreCommand {
             }
         }
 
-        // Restore directory timestamps after all contents are written
-        // (writing files inside would update the directory mtime)
-        if !self.dry_run && !self.no_timestamps {
+        // Apply directory permissions and timestamps after all contents are
+        // written, deepest first: writing files inside would update the
+        // directory mtime, and a read-only directory could not be filled.
+        if !self.dry_run {
             for (dir_path, node) in directories.iter().rev() {
-                if let Err(e) = self.set_timestamps(dir_path, node.mtime).await {
-                    debug!(
-                        "Failed to set directory timestamp for {}: {}",
+                if let Err(e) = self.finish_directory(node, dir_path, &mut metadata).await {
+                    warn!(
+                        "Failed to restore directory metadata for {}: {}",
                         dir_path.display(),
                         e
                     );
@@ -353,9 +596,24 @@ impl RestoreCommand {
         if hardlinks_restored > 0 {
             println!("Hardlinks: {}", hardlinks_restored);
         }
+        if delta.files > 0 {
+            println!(
+                "Patched in place: {} ({} unchanged; {} downloaded, {} reused)",
+                delta.files,
+                delta.unchanged,
+                HumanBytes(delta.bytes_downloaded),
+                HumanBytes(delta.bytes_reused)
+            );
+        }
         if skipped_count > 0 {
             println!("Skipped (existing): {}", skipped_count);
         }
+        if devices_skipped > 0 {
+            println!(
+                "Skipped (device nodes, not running as root): {}",
+                devices_skipped
+            );
+        }
         if failed_count > 0 {
             println!("Failed: {}", failed_count);
         }
@@ -366,70 +624,362 @@ impl RestoreCommand {
             );
         }
         println!("Location: {}", target_path.display());
+        if !metadata.is_empty() {
+            println!();
+            metadata.print();
+        }
 
         Ok(())
     }
 
-    async fn resolve_snapshot_id(&self, repo: &Repository, snapshot_id: &str) -> Result<String> {
-        if snapshot_id.len() >= 36 {
-            return Ok(snapshot_id.to_string());
+    /// Restores the selected files as objects in a storage location.
+    async fn restore_to_storage(
+        &self,
+        repo: &Repository,
+        snapshot: &Snapshot,
+        location: RepositoryLocation,
+    ) -> Result<()> {
+        if self.interactive {
+            return Err(anyhow!("--interactive can only restore to a directory"));
         }
 
-        let all_snapshots = repo.list_snapshots().await?;
-        let matches: Vec<_> = all_snapshots
+        let paths = self.restore_paths();
+        let tree = self.load_restore_tree(repo, &snapshot.tree, &paths).await?;
+        let nodes = plan_restore(&tree, &paths);
+        let files: Vec<&TreeNode> = nodes
             .iter()
-            .filter(|id| id.starts_with(snapshot_id))
+            .copied()
+            .filter(|n| n.node_type == NodeType::File)
             .collect();
 
-        match matches.len() {
-            0 => Err(anyhow!(
-                "No snapshot found with ID starting with '{}'",
-                snapshot_id
-            )),
-            1 => Ok(matches[0].clone()),
-            _ => Err(anyhow!(
-                "Ambiguous snapshot ID '{}' - matches {} snapshots",
-                snapshot_id,
-                matches.len()
+        println!(
+            "Restoring snapshot {} to {}",
+            snapshot.short_id(),
+            location.display()
+        );
+        if self.dry_run {
+            for node in &files {
+                println!(
+                    "Would write object: {} ({})",
+                    node.name,
+                    HumanBytes(node.size)
+                );
+            }
+            return Ok(());
+        }
+
+        self.ensure_packs_readable(repo, &files).await?;
+        let storage = storage_for_location(&location).await?;
+        let start_time = Instant::now();
+        let totals = restore_to(repo, &tree, &nodes, &mut StorageTarget::new(storage)).await?;
+
+        println!("Restore completed!");
+        println!(
+            "Restored: {} files ({} in {})",
+            files.len(),
+            HumanBytes(totals.bytes),
+            HumanDuration(start_time.elapsed())
+        );
+        let skipped = nodes.len() - files.len();
+        if skipped > 0 {
+            println!("Skipped (directories, symlinks, devices): {}", skipped);
+        }
+        println!("Location: {}", location.display());
+        Ok(())
+    }
+
+    /// Rewrites the owners in `tree` to the ids they should be restored
+    /// with, returning how many entries changed.
+    fn map_owners(&self, snapshot: &Snapshot, tree: &mut Tree) -> usize {
+        let mut mapper = IdMapper::new(self.uid_map.clone(), self.gid_map.clone());
+        if !self.numeric_ids {
+            mapper = mapper.with_system_names(&snapshot.user_names, &snapshot.group_names);
+        }
+
+        let mut remapped = 0;
+        for node in &mut tree.nodes {
+            let (uid, gid) = (mapper.map_uid(node.uid), mapper.map_gid(node.gid));
+            if (uid, gid) != (node.uid, node.gid) {
+                node.uid = uid;
+                node.gid = gid;
+                remapped += 1;
+            }
+        }
+        remapped
+    }
+
+    /// Paths to restore. With `--at` there is no snapshot ID, so the first
+    /// positional argument is a path too.
+    fn restore_paths(&self) -> Vec<String> {
+        match (&self.at, &self.snapshot_id) {
+            (Some(_), Some(first)) => std::iter::once(first.clone())
+                .chain(self.paths.iter().cloned())
+                .collect(),
+            _ => self.paths.clone(),
+        }
+    }
+
+    /// Loads the nodes needed to restore `paths`, or the whole tree if no
+    /// paths are given. Of a paged tree or one with subtrees, only the tree
+    /// objects holding the paths, the directories above them and the
+    /// originals of their hardlinks are read.
+    async fn load_restore_tree(
+        &self,
+        repo: &Repository,
+        tree_id: &ChunkID,
+        paths: &[String],
+    ) -> Result<Tree> {
+        if paths.is_empty() {
+            return Ok(repo.load_tree(tree_id).await?);
+        }
+        let mut tree = repo.load_tree_paths(tree_id, paths).await?;
+
+        // Hardlinks may point at files in tree objects that were not read
+        let names: HashSet<&str> = tree.nodes.iter().map(|node| node.name.as_str()).collect();
+        let targets: BTreeSet<String> = tree
+            .nodes
+            .iter()
+            .filter_map(|node| node.hardlink_target.clone())
+            .filter(|target| !names.contains(target.as_str()))
+            .collect();
+        if !targets.is_empty() {
+            let paths: Vec<String> = targets.iter().cloned().collect();
+            let originals = repo.load_tree_paths(tree_id, &paths).await?;
+            tree.nodes.extend(
+                originals
+                    .nodes
+                    .into_iter()
+                    .filter(|node| targets.contains(&node.name)),
+            );
+        }
+        Ok(tree)
+    }
+
+    /// Checks that the restore can complete before anything is written:
+    /// enough free space, a writable target, and no entry whose type
+    /// conflicts with what the snapshot wants to put there.
+    /// Starts rehydrating the archived packs holding the file data to
+    /// restore, and fails while any of them is still unreadable.
+    async fn ensure_packs_readable(&self, repo: &Repository, nodes: &[&TreeNode]) -> Result<()> {
+        let pack_ids: Vec<PackID> = {
+            let index = repo.index();
+            let index = index.read().await;
+            nodes
+                .iter()
+                .filter(|n| n.node_type == NodeType::File)
+                .flat_map(|n| &n.chunks)
+                .filter_map(|chunk| index.get_chunk(&chunk.id))
+                .map(|location| location.pack_id)
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect()
+        };
+
+        if self.dry_run {
+            let mut archived = 0;
+            for pack_id in &pack_ids {
+                match repo.pack_access_tier(pack_id).await? {
+                    Some(status) if !status.is_readable() => archived += 1,
+                    Some(_) => {}
+                    None => return Ok(()),
+                }
+            }
+            if archived > 0 {
+                println!(
+                    "Would rehydrate {} archived pack(s) before restoring",
+                    archived
+                );
+            }
+            return Ok(());
+        }
+
+        let report = repo
+            .rehydrate_packs(&pack_ids, self.rehydrate_priority)
+            .await?;
+        if report.is_ready() {
+            return Ok(());
+        }
+
+        if !report.requested.is_empty() {
+            println!(
+                "Started {} rehydration of {} archived pack(s)",
+                self.rehydrate_priority,
+                report.requested.len()
+            );
+        }
+        if !report.pending.is_empty() {
+            println!(
+                "{} archived pack(s) are already being rehydrated",
+                report.pending.len()
+            );
+        }
+        Err(anyhow!(
+            "{} pack(s) needed for this restore are archived; run the restore again once rehydration completes (check with `ghostsnap backend tier show`)",
+            report.requested.len() + report.pending.len()
+        ))
+    }
+
+    fn preflight(&self, nodes: &[&TreeNode], target_path: &Path) -> Preflight {
+        let mut report = Preflight::default();
+        let mut bytes_freed = 0u64;
+
+        for node in nodes {
+            let dest_path = target_path.join(&node.name);
+            // Hardlinks share the data of an earlier file
+            let size = if node.node_type == NodeType::File
+                && (node.hardlink_target.is_none() || self.no_hardlinks)
+            {
+                node.size
+            } else {
+                0
+            };
+
+            let existing = match std::fs::symlink_metadata(&dest_path) {
+                Ok(meta) => meta,
+                Err(_) => {
+                    report.bytes_required += size;
+                    continue;
+                }
+            };
+
+            match node.node_type {
+                NodeType::Directory if !existing.is_dir() => {
+                    report.problems.push(format!(
+                        "{} exists and is not a directory",
+                        dest_path.display()
+                    ));
+                }
+                NodeType::Directory => {}
+                _ if existing.is_dir() => {
+                    report.problems.push(format!(
+                        "{} is a directory but the snapshot has a {}",
+                        dest_path.display(),
+                        match node.node_type {
+                            NodeType::Symlink => "symlink",
+                            NodeType::CharDevice | NodeType::BlockDevice => "device node",
+                            NodeType::Fifo => "FIFO",
+                            _ => "file",
+                        }
+                    ));
+                }
+                _ if self.patches_in_place(node) && existing.is_file() => {
+                    // Only growth needs new space
+                    report.bytes_required += size.saturating_sub(existing.len());
+                    report.patched += 1;
+                }
+                _ if self.overwrite => {
+                    report.bytes_required += size;
+                    if existing.is_file() {
+                        bytes_freed += existing.len();
+                    }
+                    report.overwrites.push(dest_path);
+                }
+                _ => report.skipped += 1,
+            }
+        }
+        report.bytes_required = report.bytes_required.saturating_sub(bytes_freed);
+
+        // The target may not exist yet (dry run); check the closest parent
+        // that does.
+        let existing_dir = target_path.ancestors().find(|p| p.is_dir());
+        match existing_dir {
+            Some(dir) => {
+                if !is_writable(dir) {
+                    report
+                        .problems
+                        .push(format!("No write permission on {}", dir.display()));
+                }
+                report.bytes_available = available_space(dir);
+            }
+            None => report.problems.push(format!(
+                "No existing parent directory for {}",
+                target_path.display()
             )),
         }
+
+        if let Some(available) = report.bytes_available
+            && available < report.bytes_required
+        {
+            report.problems.push(format!(
+                "Not enough free space: {} required, {} available",
+                HumanBytes(report.bytes_required),
+                HumanBytes(available)
+            ));
+        }
+
+        report
     }
 
-    async fn restore_directory(&self, node: &TreeNode, dest_path: &Path) -> Result<()> {
-        // Create directory
+    async fn restore_directory(
+        &self,
+        node: &TreeNode,
+        dest_path: &Path,
+        report: &mut MetadataReport,
+    ) -> Result<()> {
+        // Create directory, replacing a symlink in its place rather than
+        // changing wherever it points
+        if std::fs::symlink_metadata(dest_path).is_ok_and(|meta| meta.is_symlink()) {
+            fs::remove_file(dest_path).await?;
+        }
         fs::create_dir_all(dest_path).await?;
 
-        // Set permissions
+        // Keep the directory writable until its contents are restored; the
+        // recorded mode is applied by finish_directory.
         if !self.no_permissions {
             #[cfg(unix)]
             {
                 use std::os::unix::fs::PermissionsExt;
-                let permissions = std::fs::Permissions::from_mode(node.mode);
+                let permissions = std::fs::Permissions::from_mode(node.mode | 0o700);
                 fs::set_permissions(dest_path, permissions).await?;
             }
         }
 
         // Set ownership (requires root)
         if !self.no_ownership {
-            self.set_ownership(dest_path, node.uid, node.gid).await?;
+            self.set_ownership(dest_path, node.uid, node.gid, report);
         }
 
         // Restore extended attributes
         if !self.no_xattr
             && let Some(ref xattrs) = node.xattr
         {
-            self.restore_xattrs(dest_path, xattrs).await?;
+            self.restore_xattrs(dest_path, xattrs, report);
         }
 
         debug!("Created directory: {}", dest_path.display());
         Ok(())
     }
 
+    /// Applies the recorded mode and mtime to a directory once its contents
+    /// have been restored.
+    async fn finish_directory(
+        &self,
+        node: &TreeNode,
+        dest_path: &Path,
+        report: &mut MetadataReport,
+    ) -> Result<()> {
+        if !self.no_permissions {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let permissions = std::fs::Permissions::from_mode(node.mode);
+                fs::set_permissions(dest_path, permissions).await?;
+            }
+        }
+
+        if !self.no_timestamps {
+            self.set_timestamps(dest_path, node.mtime, report);
+        }
+
+        Ok(())
+    }
+
     async fn restore_file(
         &self,
         repo: &Repository,
         node: &TreeNode,
         dest_path: &Path,
+        report: &mut MetadataReport,
     ) -> Result<()> {
         // Create parent directories if needed
         if let Some(parent) = dest_path.parent() {
@@ -444,9 +994,110 @@ impl RestoreCommand {
             file_data.extend_from_slice(&chunk_data);
         }
 
-        // Write file
+        // Write file, replacing a symlink in its place rather than writing
+        // to wherever it points
+        if std::fs::symlink_metadata(dest_path).is_ok_and(|meta| meta.is_symlink()) {
+            fs::remove_file(dest_path).await?;
+        }
         fs::write(dest_path, &file_data).await?;
 
+        self.finish_file(node, dest_path, report).await?;
+
+        debug!(
+            "Restored file: {} ({} bytes)",
+            dest_path.display(),
+            file_data.len()
+        );
+        Ok(())
+    }
+
+    /// Whether `--verify-existing` patches an existing file for `node`
+    /// rather than skipping or replacing it. Hardlinks are left to the
+    /// usual handling, since patching one would also change its original.
+    fn patches_in_place(&self, node: &TreeNode) -> bool {
+        self.verify_existing && node.node_type == NodeType::File && node.hardlink_target.is_none()
+    }
+
+    /// Offsets and references of the chunks of `node` whose data differs in
+    /// the existing file at `path`, including those past its end.
+    async fn differing_chunks<'a>(
+        repo: &Repository,
+        node: &'a TreeNode,
+        path: &Path,
+    ) -> Result<Vec<(u64, &'a ChunkRef)>> {
+        use tokio::io::AsyncReadExt;
+
+        let mut file = fs::File::open(path).await?;
+        let mut differing = Vec::new();
+        let mut offset = 0u64;
+        let mut data = Vec::new();
+        for chunk_ref in &node.chunks {
+            data.clear();
+            (&mut file)
+                .take(chunk_ref.length as u64)
+                .read_to_end(&mut data)
+                .await?;
+            if data.len() != chunk_ref.length as usize || repo.chunk_id(&data) != chunk_ref.id {
+                differing.push((offset, chunk_ref));
+            }
+            offset += chunk_ref.length as u64;
+        }
+        Ok(differing)
+    }
+
+    /// Brings an existing file in line with the snapshot, downloading only
+    /// the chunks that differ and writing them at their offsets.
+    async fn patch_file(
+        &self,
+        repo: &Repository,
+        node: &TreeNode,
+        dest_path: &Path,
+        delta: &mut DeltaReport,
+        report: &mut MetadataReport,
+    ) -> Result<()> {
+        use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+        let existing_size = fs::metadata(dest_path).await?.len();
+        let differing = Self::differing_chunks(repo, node, dest_path).await?;
+
+        let mut file = fs::OpenOptions::new().write(true).open(dest_path).await?;
+        let mut downloaded = 0u64;
+        for (offset, chunk_ref) in &differing {
+            let chunk_data = repo.load_chunk(&chunk_ref.id).await?;
+            file.seek(SeekFrom::Start(*offset)).await?;
+            file.write_all(&chunk_data).await?;
+            downloaded += chunk_data.len() as u64;
+        }
+        // Drops data past the end of the snapshot's version
+        file.set_len(node.size).await?;
+        file.flush().await?;
+        drop(file);
+
+        delta.files += 1;
+        if differing.is_empty() && existing_size == node.size {
+            delta.unchanged += 1;
+        }
+        delta.bytes_downloaded += downloaded;
+        delta.bytes_reused += node.size.saturating_sub(downloaded);
+
+        self.finish_file(node, dest_path, report).await?;
+
+        debug!(
+            "Patched file: {} ({} of {} chunks differed)",
+            dest_path.display(),
+            differing.len(),
+            node.chunks.len()
+        );
+        Ok(())
+    }
+
+    /// Punches holes and applies metadata once a file's data is in place.
+    async fn finish_file(
+        &self,
+        node: &TreeNode,
+        dest_path: &Path,
+        report: &mut MetadataReport,
+    ) -> Result<()> {
         // Punch holes for sparse files if requested
         if self.sparse
             && let Some(ref holes) = node.sparse_holes
@@ -472,30 +1123,36 @@ impl RestoreCommand {
 
         // Set ownership
         if !self.no_ownership {
-            self.set_ownership(dest_path, node.uid, node.gid).await?;
+            self.set_ownership(dest_path, node.uid, node.gid, report);
         }
 
         // Set timestamps
         if !self.no_timestamps {
-            self.set_timestamps(dest_path, node.mtime).await?;
+            self.set_timestamps(dest_path, node.mtime, report);
         }
 
         // Restore extended attributes
         if !self.no_xattr
             && let Some(ref xattrs) = node.xattr
         {
-            self.restore_xattrs(dest_path, xattrs).await?;
+            self.restore_xattrs(dest_path, xattrs, report);
         }
 
-        debug!(
-            "Restored file: {} ({} bytes)",
-            dest_path.display(),
-            file_data.len()
-        );
+        // Capabilities last: changing the owner clears them
+        if !self.no_capabilities
+            && let Some(capabilities) = node.capabilities()
+        {
+            self.restore_capabilities(dest_path, capabilities, report);
+        }
         Ok(())
     }
 
-    async fn restore_symlink(&self, node: &TreeNode, dest_path: &Path) -> Result<()> {
+    async fn restore_symlink(
+        &self,
+        node: &TreeNode,
+        dest_path: &Path,
+        report: &mut MetadataReport,
+    ) -> Result<()> {
         let link_target = node
             .link_target
             .as_ref()
@@ -527,16 +1184,12 @@ impl RestoreCommand {
             }
         }
 
-        // Set ownership on symlink (lchown)
+        // Set ownership and timestamps on the symlink itself
         if !self.no_ownership {
-            #[cfg(unix)]
-            {
-                use std::os::unix::ffi::OsStrExt;
-                let path_cstr = std::ffi::CString::new(dest_path.as_os_str().as_bytes())?;
-                unsafe {
-                    libc::lchown(path_cstr.as_ptr(), node.uid, node.gid);
-                }
-            }
+            self.set_ownership(dest_path, node.uid, node.gid, report);
+        }
+        if !self.no_timestamps {
+            self.set_timestamps(dest_path, node.mtime, report);
         }
 
         debug!(
@@ -547,42 +1200,99 @@ impl RestoreCommand {
         Ok(())
     }
 
-    async fn set_ownership(&self, path: &Path, uid: u32, gid: u32) -> Result<()> {
+    /// Recreates a device node or FIFO with its recorded metadata.
+    async fn restore_special(
+        &self,
+        node: &TreeNode,
+        dest_path: &Path,
+        report: &mut MetadataReport,
+    ) -> Result<()> {
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        // Remove existing if overwrite is set
+        if dest_path.symlink_metadata().is_ok() {
+            fs::remove_file(dest_path).await.ok();
+        }
+
+        make_special_file(node, dest_path)?;
+
+        // The node was created subject to the umask
+        if !self.no_permissions {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let permissions = std::fs::Permissions::from_mode(node.mode);
+                fs::set_permissions(dest_path, permissions).await?;
+            }
+        }
+
+        if !self.no_ownership {
+            self.set_ownership(dest_path, node.uid, node.gid, report);
+        }
+
+        if !self.no_timestamps {
+            self.set_timestamps(dest_path, node.mtime, report);
+        }
+
+        if !self.no_xattr
+            && let Some(ref xattrs) = node.xattr
+        {
+            self.restore_xattrs(dest_path, xattrs, report);
+        }
+
+        debug!(
+            "Created {}: {}",
+            node.node_type.as_str(),
+            dest_path.display()
+        );
+        Ok(())
+    }
+
+    /// Sets the owner of `path`, or of the symlink itself. Only root can
+    /// give files away; other users keep ownership and it is reported.
+    fn set_ownership(&self, path: &Path, uid: u32, gid: u32, report: &mut MetadataReport) {
         #[cfg(unix)]
         {
             use std::os::unix::ffi::OsStrExt;
 
-            // Only attempt if we're running as root
-            if unsafe { libc::geteuid() } != 0 {
-                return Ok(());
+            if !running_as_root() {
+                if (uid, gid) != unsafe { (libc::geteuid(), libc::getegid()) } {
+                    report.ownership_skipped += 1;
+                }
+                return;
             }
 
-            let path_cstr = std::ffi::CString::new(path.as_os_str().as_bytes())?;
-            let result = unsafe { libc::chown(path_cstr.as_ptr(), uid, gid) };
-
-            if result != 0 {
-                debug!(
-                    "Failed to set ownership on {}: {}",
-                    path.display(),
-                    std::io::Error::last_os_error()
-                );
+            let result = std::ffi::CString::new(path.as_os_str().as_bytes())
+                .map_err(std::io::Error::from)
+                .and_then(|path_cstr| {
+                    // lchown: the same as chown except on symlinks, whose
+                    // target must not change owner
+                    match unsafe { libc::lchown(path_cstr.as_ptr(), uid, gid) } {
+                        0 => Ok(()),
+                        _ => Err(std::io::Error::last_os_error()),
+                    }
+                });
+            if let Err(e) = result {
+                debug!("Failed to set ownership on {}: {}", path.display(), e);
+                report.failed("Ownership", path);
             }
         }
 
         #[cfg(not(unix))]
         {
-            let _ = (path, uid, gid);
+            let _ = (path, uid, gid, report);
         }
-
-        Ok(())
     }
 
-    async fn set_timestamps(&self, path: &Path, mtime: i64) -> Result<()> {
+    /// Sets the access and modification time of `path`, or of the symlink
+    /// itself.
+    fn set_timestamps(&self, path: &Path, mtime: i64, report: &mut MetadataReport) {
         #[cfg(unix)]
         {
             use std::os::unix::ffi::OsStrExt;
 
-            let path_cstr = std::ffi::CString::new(path.as_os_str().as_bytes())?;
             let times = [
                 libc::timespec {
                     tv_sec: mtime,
@@ -593,36 +1303,79 @@ impl RestoreCommand {
                     tv_nsec: 0,
                 }, // mtime
             ];
-
-            unsafe {
-                libc::utimensat(libc::AT_FDCWD, path_cstr.as_ptr(), times.as_ptr(), 0);
+            let result = std::ffi::CString::new(path.as_os_str().as_bytes())
+                .map_err(std::io::Error::from)
+                .and_then(|path_cstr| {
+                    match unsafe {
+                        libc::utimensat(
+                            libc::AT_FDCWD,
+                            path_cstr.as_ptr(),
+                            times.as_ptr(),
+                            libc::AT_SYMLINK_NOFOLLOW,
+                        )
+                    } {
+                        0 => Ok(()),
+                        _ => Err(std::io::Error::last_os_error()),
+                    }
+                });
+            if let Err(e) = result {
+                debug!("Failed to set timestamps on {}: {}", path.display(), e);
+                report.failed("Timestamps", path);
             }
         }
 
         #[cfg(not(unix))]
         {
-            let _ = (path, mtime);
+            let _ = (path, mtime, report);
         }
-
-        Ok(())
     }
 
-    async fn restore_xattrs(&self, path: &Path, xattrs: &HashMap<String, Vec<u8>>) -> Result<()> {
+    /// Restores extended attributes other than the file capabilities, which
+    /// [`Self::restore_capabilities`] applies separately.
+    fn restore_xattrs(
+        &self,
+        path: &Path,
+        xattrs: &BTreeMap<String, Vec<u8>>,
+        report: &mut MetadataReport,
+    ) {
         #[cfg(unix)]
         {
+            let mut failed = false;
             for (name, value) in xattrs {
+                if name == ghostsnap_core::CAPABILITY_XATTR {
+                    continue;
+                }
                 if let Err(e) = xattr::set(path, name, value) {
                     debug!("Failed to set xattr {} on {}: {}", name, path.display(), e);
+                    failed = true;
                 }
             }
+            if failed {
+                report.failed("Extended attributes", path);
+            }
         }
 
         #[cfg(not(unix))]
         {
-            let _ = (path, xattrs);
+            let _ = (path, xattrs, report);
         }
+    }
 
-        Ok(())
+    /// Applies recorded file capabilities. Setting them needs root (or
+    /// CAP_SETFCAP), and must come after the owner is set, which clears them.
+    fn restore_capabilities(&self, path: &Path, capabilities: &[u8], report: &mut MetadataReport) {
+        #[cfg(unix)]
+        {
+            if let Err(e) = xattr::set(path, ghostsnap_core::CAPABILITY_XATTR, capabilities) {
+                debug!("Failed to set capabilities on {}: {}", path.display(), e);
+                report.failed("File capabilities", path);
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = (path, capabilities, report);
+        }
     }
 
     fn punch_holes(&self, path: &Path, holes: &[(u64, u64)]) -> Result<()> {
@@ -744,3 +1497,192 @@ impl RestoreCommand {
         Ok(())
     }
 }
+
+impl Preflight {
+    fn print(&self) {
+        println!("Preflight:");
+        println!("  Required:  {}", HumanBytes(self.bytes_required));
+        match self.bytes_available {
+            Some(available) => println!("  Available: {}", HumanBytes(available)),
+            None => println!("  Available: unknown"),
+        }
+        if self.skipped > 0 {
+            println!(
+                "  Existing:  {} (skipped, use --overwrite to replace)",
+                self.skipped
+            );
+        }
+        if self.patched > 0 {
+            println!(
+                "  Existing:  {} (compared, differing ranges patched in place)",
+                self.patched
+            );
+        }
+        if !self.overwrites.is_empty() {
+            println!("  Overwrites: {}", self.overwrites.len());
+            for path in self.overwrites.iter().take(PREFLIGHT_LIST_LIMIT) {
+                println!("    {}", path.display());
+            }
+            if self.overwrites.len() > PREFLIGHT_LIST_LIMIT {
+                println!(
+                    "    ... and {} more",
+                    self.overwrites.len() - PREFLIGHT_LIST_LIMIT
+                );
+            }
+        }
+    }
+}
+
+impl MetadataReport {
+    fn failed(&mut self, kind: &'static str, path: &Path) {
+        self.failures
+            .entry(kind)
+            .or_default()
+            .push(path.to_path_buf());
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ownership_skipped == 0 && self.failures.is_empty()
+    }
+
+    fn print(&self) {
+        println!("Metadata not applied:");
+        if self.ownership_skipped > 0 {
+            println!(
+                "  Ownership: {} entries left owned by the restoring user",
+                self.ownership_skipped
+            );
+        }
+        for (kind, paths) in &self.failures {
+            println!("  {}: {}", kind, paths.len());
+            for path in paths.iter().take(PREFLIGHT_LIST_LIMIT) {
+                println!("    {}", path.display());
+            }
+            if paths.len() > PREFLIGHT_LIST_LIMIT {
+                println!("    ... and {} more", paths.len() - PREFLIGHT_LIST_LIMIT);
+            }
+        }
+        if !running_as_root() {
+            println!(
+                "  (not running as root: ownership, file capabilities and trusted/security \
+                 attributes need root)"
+            );
+        }
+    }
+}
+
+/// The ancestor of `name` that is a symlink, if any: either one restored
+/// earlier (`symlinks`, which a dry run never writes) or one already in
+/// `target`.
+fn symlink_ancestor<'a>(
+    target: &Path,
+    symlinks: &HashSet<String>,
+    name: &'a str,
+) -> Option<&'a str> {
+    Path::new(name)
+        .ancestors()
+        .skip(1)
+        .filter_map(Path::to_str)
+        .filter(|dir| !dir.is_empty())
+        .find(|dir| {
+            symlinks.contains(*dir)
+                || std::fs::symlink_metadata(target.join(dir)).is_ok_and(|meta| meta.is_symlink())
+        })
+}
+
+/// Returns whether the process runs as root.
+fn running_as_root() -> bool {
+    #[cfg(unix)]
+    {
+        unsafe { libc::geteuid() == 0 }
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// Creates the device node or FIFO `node` describes at `path`.
+fn make_special_file(node: &TreeNode, path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let path_cstr = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+        let mode = (node.mode & 0o7777) as libc::mode_t;
+        let result = match node.node_type {
+            NodeType::Fifo => unsafe { libc::mkfifo(path_cstr.as_ptr(), mode) },
+            _ => {
+                let kind = if node.node_type == NodeType::BlockDevice {
+                    libc::S_IFBLK
+                } else {
+                    libc::S_IFCHR
+                };
+                let device = node
+                    .device
+                    .ok_or_else(|| anyhow!("Device node {} has no device number", node.name))?;
+                unsafe {
+                    libc::mknod(
+                        path_cstr.as_ptr(),
+                        kind | mode,
+                        device.to_rdev() as libc::dev_t,
+                    )
+                }
+            }
+        };
+        if result != 0 {
+            return Err(anyhow!(
+                "Failed to create {}: {}",
+                path.display(),
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Err(anyhow!(
+            "Cannot create {} {} on this platform",
+            node.node_type.as_str(),
+            node.name
+        ))
+    }
+}
+
+/// Returns whether the current user may create entries in `dir`.
+fn is_writable(dir: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        match std::ffi::CString::new(dir.as_os_str().as_bytes()) {
+            Ok(path_cstr) => unsafe { libc::access(path_cstr.as_ptr(), libc::W_OK) == 0 },
+            Err(_) => false,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        std::fs::metadata(dir)
+            .map(|meta| !meta.permissions().readonly())
+            .unwrap_or(false)
+    }
+}
+
+/// Returns the space available to unprivileged users on the filesystem
+/// containing `dir`, or `None` where it cannot be determined.
+fn available_space(dir: &Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let path_cstr = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(path_cstr.as_ptr(), &mut stat) } != 0 {
+            return None;
+        }
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = dir;
+        None
+    }
+}
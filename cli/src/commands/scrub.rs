@@ -0,0 +1,149 @@
+//! Scrub command for incremental pack verification.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap scrub --budget 50GB                     # One pass
+//! ghostsnap scrub --budget 20GB --daemon --interval 6h \
+//!     --on-failure 'mail -s "ghostsnap scrub failed" ops@example.org'
+//! ```
+//!
+//! Each pass verifies the least recently verified packs until the budget is
+//! used up; see `ghostsnap_core::scrub`.
+
+use crate::hooks::{HookConfig, execute_hook, format_hook_result};
+use anyhow::{Result, anyhow};
+use clap::Args;
+use ghostsnap_core::{LockType, Repository, ScrubReport};
+use indicatif::HumanBytes;
+use std::time::Duration;
+use tracing::{info, warn};
+
+#[derive(Args)]
+pub struct ScrubCommand {
+    #[arg(
+        long,
+        default_value = "10G",
+        help = "Pack data to read per pass (e.g., 500M, 50GB)"
+    )]
+    budget: String,
+
+    #[arg(long, help = "Keep running, starting a pass every --interval")]
+    daemon: bool,
+
+    #[arg(
+        long,
+        default_value = "1h",
+        requires = "daemon",
+        help = "Time between passes in daemon mode (e.g., 30m, 6h)"
+    )]
+    interval: String,
+
+    #[arg(
+        long,
+        help = "Shell command to run when a pass finds corrupt packs (GHOSTSNAP_SCRUB_FAILED_PACKS lists them)"
+    )]
+    on_failure: Option<String>,
+}
+
+impl ScrubCommand {
+    pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+
+        let password = crate::password::repository_password(cli)?;
+
+        let budget = crate::commands::parse_size(&self.budget)?;
+        let interval = crate::config::parse_duration(&self.interval)?;
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
+
+        if !self.daemon {
+            let report = self.pass(cli, &repo, budget).await?;
+            if !report.is_clean() {
+                return Err(anyhow!(
+                    "{} packs failed verification",
+                    report.failures.len()
+                ));
+            }
+            return Ok(());
+        }
+
+        info!("Scrubbing every {:?}", interval);
+        loop {
+            // A failed pass (e.g. the repository is locked) is retried at the
+            // next interval instead of stopping the daemon.
+            if let Err(e) = self.pass(cli, &repo, budget).await {
+                warn!("Scrub pass failed: {}", e);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Runs one scrub pass, prints its report and alerts on failures.
+    async fn pass(&self, cli: &crate::Cli, repo: &Repository, budget: u64) -> Result<ScrubReport> {
+        // Shared: packs must not be pruned while they are being read
+        let _lock = if let Some(repo_path) = repo.local_path() {
+            let lock_manager = crate::commands::lock_manager(cli, repo_path)?;
+            Some(lock_manager.acquire(LockType::Shared, "scrub").await?)
+        } else {
+            None
+        };
+
+        let report = repo.scrub(budget).await?;
+        print_report(&report);
+
+        if !report.is_clean()
+            && let Some(command) = &self.on_failure
+        {
+            self.alert(repo, &report, command).await;
+        }
+        Ok(report)
+    }
+
+    async fn alert(&self, repo: &Repository, report: &ScrubReport, command: &str) {
+        let failed_packs: Vec<&str> = report.failures.iter().map(|f| f.pack_id.as_str()).collect();
+        let hook_config = HookConfig {
+            command: command.to_string(),
+            timeout: Duration::from_secs(300),
+            shell: "/bin/sh".to_string(),
+            working_dir: None,
+            env: vec![
+                ("GHOSTSNAP_REPO".to_string(), repo.location().display()),
+                (
+                    "GHOSTSNAP_SCRUB_FAILED_PACKS".to_string(),
+                    failed_packs.join(" "),
+                ),
+            ],
+        };
+        match execute_hook(&hook_config).await {
+            Ok(result) => {
+                for line in format_hook_result("Failure hook", &result, false) {
+                    println!("{}", line);
+                }
+            }
+            Err(e) => warn!("Failed to run failure hook: {}", e),
+        }
+    }
+}
+
+fn print_report(report: &ScrubReport) {
+    println!(
+        "Scrubbed {} of {} packs ({} chunks, {} read)",
+        report.packs_verified + report.failures.len(),
+        report.total_packs,
+        report.chunks_verified,
+        HumanBytes(report.bytes_read)
+    );
+    for failure in &report.failures {
+        println!("  CORRUPT pack {}: {}", failure.pack_id, failure.error);
+        for chunk_id in &failure.corrupt_chunks {
+            println!("    chunk {}", chunk_id.to_hex());
+        }
+    }
+    match report.oldest_verification {
+        Some(oldest) => println!(
+            "Every pack verified since {}",
+            oldest.format("%Y-%m-%d %H:%M:%S UTC")
+        ),
+        None => println!("Packs never verified: {}", report.never_verified),
+    }
+}
@@ -1,7 +1,8 @@
 use anyhow::{Result, anyhow};
 use clap::Args;
-use ghostsnap_core::{NodeType, Repository};
-use std::io::{self, Write};
+use ghostsnap_core::{SnapshotChainStats, SnapshotID};
+use indicatif::HumanBytes;
+use std::collections::HashMap;
 use tracing::info;
 
 #[derive(Args)]
@@ -9,64 +10,109 @@ pub struct SnapshotsCommand {
     #[arg(long, help = "Output format (table, json)")]
     format: Option<String>,
 
-    #[arg(long, help = "Filter by hostname")]
-    hostname: Option<String>,
-
-    #[arg(long, help = "Filter by tag")]
-    tag: Vec<String>,
+    #[command(flatten)]
+    filter: crate::commands::SnapshotFilterArgs,
 
     #[arg(long, help = "Show latest N snapshots")]
     latest: Option<usize>,
+
+    #[arg(long, help = "Show at most N snapshots (oldest first)")]
+    limit: Option<usize>,
+
+    #[arg(long, default_value_t = 0, help = "Skip the first N snapshots")]
+    offset: usize,
+
+    #[arg(long, help = "Rebuild the snapshot summary cache")]
+    no_cache: bool,
+
+    #[arg(
+        long,
+        help = "Show how much data each snapshot introduced vs. shares with earlier snapshots"
+    )]
+    chain: bool,
 }
 
 impl SnapshotsCommand {
     pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
         let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
 
-        let password = cli
-            .password
-            .clone()
-            .or_else(|| {
-                print!("Enter repository password: ");
-                io::stdout().flush().ok()?;
-                rpassword::read_password().ok()
-            })
-            .ok_or_else(|| anyhow!("Password required"))?;
+        let password = crate::password::repository_password(cli)?;
 
         info!("Opening repository at: {}", repo_location.display());
-        let repo = Repository::open_at_location(repo_location, &password).await?;
+        let repo = crate::commands::open_repository(cli, repo_location, &password).await?;
 
-        let snapshot_ids = repo.list_snapshots().await?;
+        // Summaries come from the snapshot cache, oldest first; only snapshots
+        // added since the last listing are read from storage.
+        let mut summaries = repo.snapshot_summaries(self.no_cache).await?;
         let format = self.format.as_deref().unwrap_or("table");
 
-        if snapshot_ids.is_empty() {
+        if summaries.is_empty() {
             println!("No snapshots found");
             return Ok(());
         }
 
-        let mut snapshots = Vec::new();
-        for snapshot_id in snapshot_ids {
-            if let Ok(snapshot) = repo.load_snapshot(&snapshot_id).await {
-                snapshots.push(snapshot);
-            }
-        }
-
         // Apply filters
-        if let Some(hostname_filter) = &self.hostname {
-            snapshots.retain(|s| s.hostname == *hostname_filter);
-        }
-
-        if !self.tag.is_empty() {
-            snapshots.retain(|s| s.tags.iter().any(|tag| self.tag.contains(tag)));
-        }
+        let filter = self.filter.filter();
+        summaries.retain(|s| filter.matches(&s.snapshot));
 
         // Apply latest limit
         if let Some(latest) = self.latest {
-            snapshots.sort_by_key(|s| std::cmp::Reverse(s.time));
-            snapshots.truncate(latest);
+            summaries.reverse();
+            summaries.truncate(latest);
         }
 
+        // Apply pagination
+        let total = summaries.len();
+        let summaries: Vec<_> = summaries
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect();
+        let snapshots: Vec<_> = summaries.iter().map(|s| &s.snapshot).collect();
+
+        // Chain stats are computed over all snapshots so that filtering does not
+        // change what counts as "earlier" data.
+        let chain: HashMap<SnapshotID, SnapshotChainStats> = if self.chain {
+            repo.snapshot_chain_stats()
+                .await?
+                .into_iter()
+                .map(|s| (s.snapshot_id.clone(), s))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
         match format {
+            "table" if self.chain => {
+                println!(
+                    "{:<12} {:<20} {:<15} {:<12} {:>12} {:>12}",
+                    "ID", "Date", "Host", "Type", "Own", "Referenced"
+                );
+                println!("{:-<88}", "");
+
+                for snapshot in &snapshots {
+                    let Some(stats) = chain.get(&snapshot.id) else {
+                        continue;
+                    };
+                    let kind = if stats.standalone {
+                        "standalone"
+                    } else if stats.is_full() {
+                        "full"
+                    } else {
+                        "incremental"
+                    };
+
+                    println!(
+                        "{:<12} {:<20} {:<15} {:<12} {:>12} {:>12}",
+                        snapshot.short_id(),
+                        snapshot.time.format("%Y-%m-%d %H:%M:%S"),
+                        snapshot.hostname,
+                        kind,
+                        HumanBytes(stats.self_contained_bytes).to_string(),
+                        HumanBytes(stats.referenced_bytes).to_string()
+                    );
+                }
+            }
             "table" => {
                 println!(
                     "{:<12} {:<20} {:<15} {:<6} {:<20} Paths",
@@ -74,7 +120,8 @@ impl SnapshotsCommand {
                 );
                 println!("{:-<100}", "");
 
-                for snapshot in snapshots {
+                for summary in &summaries {
+                    let snapshot = &summary.snapshot;
                     let tags_str = snapshot.tags.join(",");
                     let paths_str = snapshot
                         .paths
@@ -83,27 +130,29 @@ impl SnapshotsCommand {
                         .collect::<Vec<_>>()
                         .join(",");
 
-                    // Load tree to count actual files
-                    let file_count = if let Ok(tree) = repo.load_tree(&snapshot.tree).await {
-                        tree.nodes
-                            .iter()
-                            .filter(|n| n.node_type == NodeType::File)
-                            .count()
-                    } else {
-                        0
-                    };
-
                     println!(
                         "{:<12} {:<20} {:<15} {:<6} {:<20} {}",
                         snapshot.short_id(),
                         snapshot.time.format("%Y-%m-%d %H:%M:%S"),
                         snapshot.hostname,
-                        file_count,
+                        summary.file_count,
                         tags_str,
                         paths_str
                     );
                 }
             }
+            "json" if self.chain => {
+                let entries: Vec<_> = snapshots
+                    .iter()
+                    .map(|snapshot| {
+                        serde_json::json!({
+                            "snapshot": snapshot,
+                            "chain": chain.get(&snapshot.id),
+                        })
+                    })
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            }
             "json" => {
                 let json = serde_json::to_string_pretty(&snapshots)?;
                 println!("{}", json);
@@ -113,6 +162,20 @@ impl SnapshotsCommand {
             }
         }
 
+        if format == "table" && summaries.len() < total {
+            if summaries.is_empty() {
+                println!("No snapshots at offset {} ({} total)", self.offset, total);
+            } else {
+                println!();
+                println!(
+                    "Showing {}-{} of {} snapshots",
+                    self.offset + 1,
+                    self.offset + summaries.len(),
+                    total
+                );
+            }
+        }
+
         Ok(())
     }
 }
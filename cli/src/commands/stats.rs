@@ -1,60 +1,71 @@
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
 use clap::Args;
-use ghostsnap_core::Repository;
-use std::io::{self, Write};
+use ghostsnap_core::cost::{self, CostEstimate, CostInputs, PRICING_TABLES, Pricing};
+use ghostsnap_core::{EncryptionLayer, HostStats};
+use tracing::warn;
 
 #[derive(Args)]
 pub struct StatsCommand {
     #[arg(long, help = "Output in JSON format")]
     json: bool,
+
+    #[arg(long, help = "Rebuild the cached statistics from scratch")]
+    recompute: bool,
+
+    #[arg(
+        long,
+        help = "Break down stored data by host, including what each host alone references (reads every snapshot)"
+    )]
+    host: bool,
+
+    #[arg(
+        long,
+        help = "Estimate monthly storage cost and the cost of a typical backup and restore"
+    )]
+    cost: bool,
+
+    #[arg(
+        long,
+        value_name = "NAME|FILE",
+        default_value = "s3-standard",
+        help = "Pricing table for --cost: a built-in name (s3-standard, s3-standard-ia, s3-glacier-ir, s3-deep-archive, azure-hot, azure-cool, azure-archive, b2, wasabi) or a TOML file; repeat to compare"
+    )]
+    pricing: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        default_value = "1G",
+        help = "New data uploaded by the backup priced by --cost"
+    )]
+    backup_size: String,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        default_value = "10G",
+        help = "Data downloaded by the restore priced by --cost"
+    )]
+    restore_size: String,
 }
 
 impl StatsCommand {
     pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
         let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
 
-        let password = cli
-            .password
-            .clone()
-            .or_else(|| {
-                print!("Enter repository password: ");
-                io::stdout().flush().ok()?;
-                rpassword::read_password().ok()
-            })
-            .ok_or_else(|| anyhow!("Password required"))?;
-
-        let repo = Repository::open_at_location(repo_location.clone(), &password).await?;
+        let password = crate::password::repository_password(cli)?;
 
-        // Get snapshot count
-        let snapshots = repo.list_snapshots().await?;
-        let snapshot_count = snapshots.len();
+        let repo = crate::commands::open_repository(cli, repo_location.clone(), &password).await?;
 
-        // Get pack files and calculate sizes
-        let packs = repo.list_packs().await?;
-        let pack_count = packs.len();
+        let cache = repo.stats_cache(self.recompute).await?;
+        let snapshot_count = cache.snapshot_count();
+        let file_count = cache.file_count();
+        let pack_count = cache.pack_count();
+        let total_pack_size = cache.stored_size();
+        let total_original_size = cache.original_size();
 
-        let mut total_pack_size = 0u64;
-
-        for pack_id in &packs {
-            if let Ok(size) = repo.pack_size(pack_id).await {
-                total_pack_size += size;
-            }
-        }
-
-        // Get index stats
-        let index = repo.index();
-        let index_guard = index.read().await;
-        let chunk_count = index_guard.chunk_count();
-
-        // Calculate dedup ratio from snapshots
-        let mut total_original_size = 0u64;
-        for snapshot_id in &snapshots {
-            if let Ok(snapshot) = repo.load_snapshot(snapshot_id).await
-                && let Ok(tree) = repo.load_tree(&snapshot.tree).await
-            {
-                total_original_size += tree.total_size();
-            }
-        }
+        let chunk_count = repo.stats().await.chunk_count;
+        let index_size = repo.index_size().await?;
 
         let dedup_ratio = if total_pack_size > 0 {
             total_original_size as f64 / total_pack_size as f64
@@ -62,28 +73,90 @@ impl StatsCommand {
             1.0
         };
 
+        let layer = repo.encryption_layer();
+        let backend_encryption = repo.backend_encryption();
+
+        let hosts = if self.host {
+            Some(repo.host_stats().await?)
+        } else {
+            None
+        };
+
+        let costs = if self.cost {
+            let inputs = CostInputs {
+                stored_bytes: total_pack_size + index_size,
+                pack_size: repo.target_pack_size(),
+                backup_bytes: crate::commands::parse_size(&self.backup_size)?,
+                restore_bytes: crate::commands::parse_size(&self.restore_size)?,
+            };
+            let mut costs = Vec::new();
+            for name in &self.pricing {
+                let pricing = load_pricing(name)?;
+                costs.push((cost::estimate(&pricing, &inputs), pricing));
+            }
+            Some((inputs, costs))
+        } else {
+            None
+        };
+
         if self.json {
-            let stats = serde_json::json!({
+            let mut stats = serde_json::json!({
                 "repository": repo_location.display(),
                 "snapshots": snapshot_count,
+                "files": file_count,
                 "packs": pack_count,
                 "chunks": chunk_count,
                 "total_size_bytes": total_pack_size,
+                "index_size_bytes": index_size,
                 "original_size_bytes": total_original_size,
                 "dedup_ratio": dedup_ratio,
+                "encrypted": repo.encryption_mode().is_encrypted(),
+                "encryption_layer": layer.to_string(),
+                "backend_encryption": backend_encryption,
+                "hash": repo.hasher().algorithm().as_str(),
+                "crypto_profile": repo.config().profile.to_string(),
+                "updated_at": cache.updated_at.to_rfc3339(),
             });
+            if let Some(hosts) = &hosts {
+                stats["hosts"] = serde_json::to_value(hosts)?;
+            }
+            if let Some((inputs, costs)) = &costs {
+                stats["cost"] = serde_json::json!({
+                    "pack_size_bytes": inputs.pack_size,
+                    "backup_size_bytes": inputs.backup_bytes,
+                    "restore_size_bytes": inputs.restore_bytes,
+                    "estimates": costs.iter().map(|(estimate, _)| estimate).collect::<Vec<_>>(),
+                });
+            }
             println!("{}", serde_json::to_string_pretty(&stats)?);
         } else {
             println!("Repository Statistics");
             println!("=====================");
             println!();
             println!("Location:     {}", repo_location.display());
+            println!(
+                "Encryption:   {} (layer: {})",
+                repo.encryption_mode(),
+                layer
+            );
+            println!(
+                "Backend enc.: {}",
+                backend_encryption.as_deref().unwrap_or("not detected")
+            );
+            println!("Chunk IDs:    {}", repo.hasher().algorithm());
+            println!("Profile:      {}", repo.config().profile);
             println!("Snapshots:    {}", snapshot_count);
+            println!("Files:        {}", file_count);
+            println!(
+                "Updated:      {}",
+                cache.updated_at.format("%Y-%m-%d %H:%M:%S UTC")
+            );
             println!();
             println!("Storage:");
             println!("  Packs:      {}", pack_count);
             println!("  Chunks:     {}", chunk_count);
             println!("  Size:       {}", format_size(total_pack_size));
+            println!("  Index:      {}", format_size(index_size));
             println!();
             println!("Deduplication:");
             println!("  Original:   {}", format_size(total_original_size));
@@ -93,12 +166,133 @@ impl StatsCommand {
                 "  Saved:      {}",
                 format_size(total_original_size.saturating_sub(total_pack_size))
             );
+            if let Some(hosts) = &hosts {
+                print_hosts(hosts);
+            }
+            if let Some((inputs, costs)) = &costs {
+                print_costs(inputs, costs);
+            }
+        }
+
+        if !repo.encryption_mode().is_encrypted() {
+            if backend_encryption.is_some() {
+                warn!(
+                    "Repository-side encryption is OFF: data is only protected by backend encryption and anyone with storage access can read it"
+                );
+            } else {
+                warn!(
+                    "Repository-side encryption is OFF and no backend encryption was detected: backups may be stored in PLAINTEXT"
+                );
+            }
+        } else if layer == EncryptionLayer::Both && backend_encryption.is_none() {
+            warn!("Encryption layer is 'both' but no backend encryption was detected");
         }
 
         Ok(())
     }
 }
 
+/// Prints each host's share. Unique data is stored for that host alone and
+/// would be freed by removing its snapshots and pruning.
+fn print_hosts(hosts: &[HostStats]) {
+    println!();
+    println!("Hosts:");
+    println!(
+        "  {:<24} {:>9} {:>12} {:>12} {:>12}",
+        "Host", "Snapshots", "Logical", "Referenced", "Unique"
+    );
+    for host in hosts {
+        println!(
+            "  {:<24} {:>9} {:>12} {:>12} {:>12}",
+            host.hostname,
+            host.snapshots,
+            format_size(host.logical_size),
+            format_size(host.referenced_size),
+            format_size(host.unique_size)
+        );
+    }
+    println!();
+    println!("  Referenced: stored size of the chunks the host's snapshots use");
+    println!("  Unique:     stored size freed if the host's snapshots were removed and pruned");
+}
+
+/// A built-in pricing table, or one read from a TOML file.
+fn load_pricing(name: &str) -> Result<Pricing> {
+    if let Some(pricing) = Pricing::builtin(name) {
+        return Ok(pricing);
+    }
+    let path = std::path::Path::new(name);
+    if !path.is_file() {
+        return Err(anyhow!(
+            "Unknown pricing table '{}' (built-in: {}; or give a TOML file)",
+            name,
+            PRICING_TABLES.join(", ")
+        ));
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read pricing file {}", path.display()))?;
+    let mut pricing: Pricing = toml::from_str(&content)
+        .with_context(|| format!("Invalid pricing file {}", path.display()))?;
+    if pricing.name.is_empty() {
+        pricing.name = path.file_stem().map_or_else(
+            || name.to_string(),
+            |stem| stem.to_string_lossy().into_owned(),
+        );
+    }
+    Ok(pricing)
+}
+
+/// Prints the estimate of each pricing table, in US dollars.
+fn print_costs(inputs: &CostInputs, costs: &[(CostEstimate, Pricing)]) {
+    println!();
+    println!(
+        "Estimated cost (USD, {} packs; list prices, no free tiers):",
+        format_size(inputs.pack_size)
+    );
+    println!(
+        "  {:<18} {:>12} {:>18} {:>18}",
+        "Pricing",
+        "Storage/mo",
+        format!("Backup {}", format_size(inputs.backup_bytes)),
+        format!("Restore {}", format_size(inputs.restore_bytes))
+    );
+    for (estimate, pricing) in costs {
+        println!(
+            "  {:<18} {:>12} {:>18} {:>18}",
+            estimate.pricing,
+            format_dollars(estimate.storage_month),
+            format_dollars(estimate.backup.total()),
+            format_dollars(estimate.restore.total())
+        );
+        if pricing.min_storage_days > 0 {
+            println!(
+                "  {:<18} data deleted within {} days is billed for the full {} days",
+                "", pricing.min_storage_days, pricing.min_storage_days
+            );
+        }
+    }
+    if let Some((estimate, _)) = costs.first() {
+        println!();
+        println!(
+            "  Backup:  {} PUT requests (one per new pack, plus snapshot, tree and index)",
+            estimate.backup.requests
+        );
+        println!(
+            "  Restore: {} GET requests; whole packs are downloaded, so larger packs mean fewer \
+             requests but more data read for small restores",
+            estimate.restore.requests
+        );
+    }
+}
+
+fn format_dollars(amount: f64) -> String {
+    if amount > 0.0 && amount < 0.01 {
+        format!("${:.4}", amount)
+    } else {
+        format!("${:.2}", amount)
+    }
+}
+
 fn format_size(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
@@ -0,0 +1,92 @@
+//! Telemetry command for the opt-in usage counters.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap telemetry enable                            # Start counting
+//! ghostsnap telemetry show                              # Print what is stored
+//! ghostsnap telemetry submit --url https://example.org/ghostsnap
+//! ghostsnap telemetry disable --reset                   # Stop and clear
+//! ```
+
+use crate::telemetry::{Counters, TELEMETRY_ENV, telemetry_path};
+use anyhow::{Context, Result, anyhow};
+use clap::{Args, Subcommand};
+use std::path::Path;
+
+/// Telemetry command for managing the local usage counters.
+#[derive(Args)]
+pub struct TelemetryCommand {
+    #[command(subcommand)]
+    subcommand: TelemetrySubcommand,
+}
+
+#[derive(Subcommand)]
+enum TelemetrySubcommand {
+    /// Print the stored counters, exactly as `submit` would send them.
+    Show,
+
+    /// Start counting commands, backend types and repository sizes.
+    Enable,
+
+    /// Stop counting.
+    Disable {
+        /// Also clear the counters recorded so far
+        #[arg(long)]
+        reset: bool,
+    },
+
+    /// Send the stored counters to a collection endpoint.
+    Submit {
+        /// Endpoint that receives the counters as a JSON POST
+        #[arg(long, env = "GHOSTSNAP_TELEMETRY_URL")]
+        url: String,
+    },
+}
+
+impl TelemetryCommand {
+    pub async fn run(&self, _cli: &crate::Cli) -> Result<()> {
+        let path = telemetry_path().ok_or_else(|| anyhow!("No data directory for telemetry"))?;
+        let mut counters = Counters::load(&path)?;
+
+        match &self.subcommand {
+            TelemetrySubcommand::Show => show(&counters, &path)?,
+            TelemetrySubcommand::Enable => {
+                counters.enabled = true;
+                counters.save(&path)?;
+                println!("Telemetry enabled; counters are kept in {}", path.display());
+                println!("Nothing is sent unless you run `ghostsnap telemetry submit`");
+            }
+            TelemetrySubcommand::Disable { reset } => {
+                counters.enabled = false;
+                if *reset {
+                    counters.reset();
+                }
+                counters.save(&path)?;
+                println!("Telemetry disabled");
+            }
+            TelemetrySubcommand::Submit { url } => {
+                reqwest::Client::new()
+                    .post(url)
+                    .json(&counters)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .with_context(|| format!("Failed to submit telemetry to {}", url))?;
+                println!("Submitted telemetry counters to {}", url);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn show(counters: &Counters, path: &Path) -> Result<()> {
+    let state = if counters.is_enabled() { "on" } else { "off" };
+    println!("Telemetry: {} ({})", state, path.display());
+    if std::env::var_os(TELEMETRY_ENV).is_some() {
+        println!("(overridden by {})", TELEMETRY_ENV);
+    }
+    println!("{}", serde_json::to_string_pretty(counters)?);
+    Ok(())
+}
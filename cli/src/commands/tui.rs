@@ -0,0 +1,38 @@
+//! Interactive repository dashboard.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! ghostsnap --repo /backup/repo tui --refresh 10
+//! ```
+
+use anyhow::Result;
+use clap::Args;
+use std::time::Duration;
+use tracing::info;
+
+/// Dashboard command showing snapshots, statistics, activity and errors.
+#[derive(Args)]
+pub struct TuiCommand {
+    /// Seconds between automatic refreshes.
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    refresh: u64,
+}
+
+impl TuiCommand {
+    pub async fn run(&self, cli: &crate::Cli) -> Result<()> {
+        let repo_location = crate::commands::parse_repository_location(cli.repo.as_ref())?;
+
+        let password = crate::password::repository_password(cli)?;
+
+        info!("Opening repository at: {}", repo_location.display());
+        let repo = crate::commands::open_repository(cli, repo_location.clone(), &password).await?;
+
+        crate::tui::dashboard::run(
+            &repo,
+            &repo_location.display().to_string(),
+            Duration::from_secs(self.refresh),
+        )
+        .await
+    }
+}
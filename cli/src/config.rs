@@ -17,9 +17,17 @@
 //! keep_daily = 7
 //! keep_weekly = 4
 //! prune = true
+//!
+//! # Throttle uploads during business hours only
+//! limit_upload = "unlimited"
+//! bandwidth_windows = ["08:00-20:00=10M"]
 //! ```
 
+use crate::commands::BackupLimits;
+use crate::priority::IoClass;
 use anyhow::{Context, Result, anyhow};
+use ghostsnap_core::ratelimit::parse_rate;
+use ghostsnap_core::{BandwidthWindow, ProxyConfig, RepositoryLocation, Validator};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
@@ -53,8 +61,43 @@ pub struct JobDefaults {
     /// Path to file containing the password.
     pub password_file: Option<PathBuf>,
 
+    /// Keyfile required in addition to the password.
+    pub keyfile: Option<PathBuf>,
+
+    /// Proxy URL for S3 and Azure repositories; the environment is used when unset.
+    pub proxy: Option<String>,
+
     /// Default shell for hooks.
     pub shell: Option<String>,
+
+    /// Default upload bandwidth limit (e.g., "10M"); unlimited when unset.
+    pub limit_upload: Option<String>,
+
+    /// Time-of-day bandwidth windows (e.g., "08:00-20:00=10M").
+    #[serde(default)]
+    pub bandwidth_windows: Vec<String>,
+
+    /// CPU scheduling priority for `job run` (nice value, 19 = lowest).
+    pub nice: Option<i32>,
+
+    /// I/O scheduling class for `job run` ("best-effort" or "idle").
+    pub io_class: Option<IoClass>,
+
+    /// Default limit on files opened or stat'ed per second.
+    pub max_read_ops: Option<u32>,
+
+    /// Default limit on the total size of the files a job backs up (e.g., "500G").
+    pub max_total_size: Option<String>,
+
+    /// Default limit on the number of files a job backs up.
+    pub max_file_count: Option<u64>,
+
+    /// Default target size of new packs (e.g., "256M"); the repository's
+    /// setting or the backend's default when unset.
+    pub pack_size: Option<String>,
+
+    /// Command run after `job run` with the run's report, e.g. to mail it.
+    pub notify_command: Option<String>,
 }
 
 /// A single backup job definition.
@@ -69,6 +112,12 @@ pub struct Job {
     /// Path to file containing the password.
     pub password_file: Option<PathBuf>,
 
+    /// Keyfile required in addition to the password (overrides defaults).
+    pub keyfile: Option<PathBuf>,
+
+    /// Proxy URL for S3 and Azure repositories (overrides defaults).
+    pub proxy: Option<String>,
+
     /// Paths to back up.
     pub paths: Vec<String>,
 
@@ -76,6 +125,11 @@ pub struct Job {
     #[serde(default)]
     pub extra_paths: Vec<String>,
 
+    /// Directory containing every path that snapshot paths are named
+    /// relative to; without it, each path's contents are named relative to
+    /// that path.
+    pub base: Option<PathBuf>,
+
     /// Tags to apply to the snapshot.
     #[serde(default)]
     pub tags: Vec<String>,
@@ -84,10 +138,18 @@ pub struct Job {
     #[serde(default)]
     pub exclude: Vec<String>,
 
+    /// Files with additional exclude patterns, one per line.
+    #[serde(default)]
+    pub exclude_files: Vec<PathBuf>,
+
     /// Patterns that cause directories to be excluded if present.
     #[serde(default)]
     pub exclude_if_present: Vec<String>,
 
+    /// Patterns files must match to be backed up; excludes still apply.
+    #[serde(default)]
+    pub include: Vec<String>,
+
     /// Override the hostname in snapshot metadata.
     pub hostname: Option<String>,
 
@@ -95,6 +157,42 @@ pub struct Job {
     #[serde(default)]
     pub one_file_system: bool,
 
+    /// Back up symlink targets instead of the links themselves.
+    #[serde(default)]
+    pub follow_symlinks: bool,
+
+    /// Additional repositories that receive a copy of each new snapshot.
+    #[serde(default)]
+    pub copy_to: Vec<String>,
+
+    /// File or directory to write a manifest of each new snapshot to.
+    pub manifest: Option<PathBuf>,
+
+    /// Ed25519 key file to sign the manifest with.
+    pub manifest_key: Option<PathBuf>,
+
+    // --- Bandwidth ---
+    /// Upload bandwidth limit outside of any window (overrides defaults).
+    pub limit_upload: Option<String>,
+
+    /// Time-of-day bandwidth windows (overrides defaults when non-empty).
+    #[serde(default)]
+    pub bandwidth_windows: Vec<String>,
+
+    /// Limit on files opened or stat'ed per second (overrides defaults).
+    pub max_read_ops: Option<u32>,
+
+    // --- Safety limits ---
+    /// Abort when the files to back up total more than this (overrides defaults).
+    pub max_total_size: Option<String>,
+
+    /// Abort when there are more files than this to back up (overrides defaults).
+    pub max_file_count: Option<u64>,
+
+    // --- Packs ---
+    /// Target size of new packs (overrides defaults).
+    pub pack_size: Option<String>,
+
     // --- Hooks ---
     /// Command to run before backup.
     pub pre_hook: Option<String>,
@@ -149,12 +247,68 @@ pub struct Job {
     /// Dry run mode - don't actually backup.
     #[serde(default)]
     pub dry_run: bool,
+
+    // --- Restore drill ---
+    /// Files a `job drill` restore must contain (glob patterns).
+    #[serde(default)]
+    pub drill_require: Vec<String>,
+
+    /// Command run in the restored directory by `job drill`.
+    pub drill_command: Option<String>,
+
+    /// Timeout for the drill command (default: 30m).
+    pub drill_timeout: Option<String>,
 }
 
 fn default_true() -> bool {
     true
 }
 
+/// Validates the settings `[defaults]` and jobs have in common.
+fn validate_shared(
+    v: &mut Validator,
+    prefix: &str,
+    proxy: Option<&str>,
+    limit_upload: Option<&str>,
+    bandwidth_windows: &[String],
+    max_read_ops: Option<u32>,
+    max_total_size: Option<&str>,
+) {
+    if let Some(proxy) = proxy {
+        v.check_result(ProxyConfig::parse(proxy), format!("{}.proxy", prefix));
+    }
+    if let Some(rate) = limit_upload {
+        v.check_result(parse_rate(rate), format!("{}.limit_upload", prefix));
+    }
+    for (i, window) in bandwidth_windows.iter().enumerate() {
+        v.check_result(
+            BandwidthWindow::parse(window),
+            format!("{}.bandwidth_windows[{}]", prefix, i),
+        );
+    }
+    if let Some(ops) = max_read_ops {
+        v.check(
+            ops > 0,
+            format!("{}.max_read_ops", prefix),
+            "must be at least 1",
+        );
+    }
+    if let Some(size) = max_total_size
+        && let Err(e) = crate::commands::parse_size(size)
+    {
+        v.error(format!("{}.max_total_size", prefix), e);
+    }
+}
+
+/// Validates a `pack_size` setting of `[defaults]` or a job.
+fn validate_pack_size(v: &mut Validator, prefix: &str, pack_size: Option<&str>) {
+    if let Some(size) = pack_size
+        && let Err(e) = crate::commands::parse_pack_size(size)
+    {
+        v.error(format!("{}.pack_size", prefix), e);
+    }
+}
+
 impl JobConfig {
     /// Load configuration from a file.
     pub fn load(path: &Path) -> Result<Self> {
@@ -213,6 +367,121 @@ impl JobConfig {
         paths
     }
 
+    /// Checks every setting that can be checked without touching the
+    /// network or the paths to back up, reporting each bad field (e.g.
+    /// `jobs.web.pre_hook_timeout`).
+    pub fn validate(&self) -> ghostsnap_core::Result<()> {
+        let mut v = Validator::new();
+        let defaults = &self.defaults;
+
+        if let Some(repository) = &defaults.repository {
+            v.check_result(RepositoryLocation::parse(repository), "defaults.repository");
+        }
+        validate_shared(
+            &mut v,
+            "defaults",
+            defaults.proxy.as_deref(),
+            defaults.limit_upload.as_deref(),
+            &defaults.bandwidth_windows,
+            defaults.max_read_ops,
+            defaults.max_total_size.as_deref(),
+        );
+        validate_pack_size(&mut v, "defaults", defaults.pack_size.as_deref());
+        if let Some(nice) = defaults.nice {
+            v.check(
+                (-20..=19).contains(&nice),
+                "defaults.nice",
+                format!("{} is outside -20..19", nice),
+            );
+        }
+
+        let mut names: Vec<&String> = self.jobs.keys().collect();
+        names.sort();
+        for name in names {
+            let job = &self.jobs[name];
+            let field = |key: &str| format!("jobs.{}.{}", name, key);
+
+            match &job.repository {
+                Some(repository) => {
+                    v.check_result(RepositoryLocation::parse(repository), field("repository"));
+                }
+                None => v.check(
+                    defaults.repository.is_some(),
+                    field("repository"),
+                    "not set here or in [defaults]",
+                ),
+            }
+            for (i, target) in job.copy_to.iter().enumerate() {
+                v.check_result(
+                    RepositoryLocation::parse(target),
+                    field(&format!("copy_to[{}]", i)),
+                );
+            }
+            v.check(
+                job.password_env.is_some()
+                    || job.password_file.is_some()
+                    || defaults.password_env.is_some()
+                    || defaults.password_file.is_some(),
+                field("password_env"),
+                "no password source (set password_env or password_file here or in [defaults])",
+            );
+            v.check(
+                !job.paths.is_empty() || !job.extra_paths.is_empty(),
+                field("paths"),
+                "no paths to back up",
+            );
+            if let Some(base) = &job.base {
+                for path in job.paths.iter().chain(&job.extra_paths) {
+                    v.check(
+                        Path::new(path).starts_with(base),
+                        field("base"),
+                        format!("does not contain {}", path),
+                    );
+                }
+            }
+            v.check(
+                job.manifest_key.is_none() || job.manifest.is_some(),
+                field("manifest_key"),
+                "set without manifest",
+            );
+            validate_shared(
+                &mut v,
+                &format!("jobs.{}", name),
+                job.proxy.as_deref(),
+                job.limit_upload.as_deref(),
+                &job.bandwidth_windows,
+                job.max_read_ops,
+                job.max_total_size.as_deref(),
+            );
+            validate_pack_size(&mut v, &format!("jobs.{}", name), job.pack_size.as_deref());
+            for (key, timeout) in [
+                ("pre_hook_timeout", &job.pre_hook_timeout),
+                ("post_hook_timeout", &job.post_hook_timeout),
+                ("drill_timeout", &job.drill_timeout),
+            ] {
+                if let Some(timeout) = timeout
+                    && let Err(e) = parse_duration(timeout)
+                {
+                    v.error(field(key), e);
+                }
+            }
+            if let Some(shell) = &job.shell {
+                v.check(
+                    !shell.trim().is_empty(),
+                    field("shell"),
+                    "must not be empty",
+                );
+            }
+            for (i, pattern) in job.drill_require.iter().enumerate() {
+                if let Err(e) = globset::Glob::new(pattern) {
+                    v.error(field(&format!("drill_require[{}]", i)), e);
+                }
+            }
+        }
+
+        v.finish()
+    }
+
     /// Get a job by name.
     pub fn get_job(&self, name: &str) -> Option<&Job> {
         self.jobs.get(name)
@@ -226,12 +495,31 @@ pub struct ResolvedJob {
     pub repository: String,
     pub password_env: Option<String>,
     pub password_file: Option<PathBuf>,
+    pub keyfile: Option<PathBuf>,
+    pub proxy: Option<String>,
     pub paths: Vec<PathBuf>,
+    pub base: Option<PathBuf>,
     pub tags: Vec<String>,
     pub exclude: Vec<String>,
     pub exclude_if_present: Vec<String>,
+    pub include: Vec<String>,
     pub hostname: Option<String>,
     pub one_file_system: bool,
+    pub follow_symlinks: bool,
+    pub copy_to: Vec<String>,
+    pub manifest: Option<PathBuf>,
+    pub manifest_key: Option<PathBuf>,
+
+    // Bandwidth
+    pub limit_upload: Option<String>,
+    pub bandwidth_windows: Vec<String>,
+    pub max_read_ops: Option<u32>,
+
+    // Safety limits
+    pub limits: BackupLimits,
+
+    /// Target size of new packs; `None` leaves it to the repository
+    pub pack_size: Option<u64>,
 
     // Hooks
     pub pre_hook: Option<String>,
@@ -254,6 +542,11 @@ pub struct ResolvedJob {
     pub require_paths_exist: bool,
     pub stop_on_pre_hook_failure: bool,
     pub dry_run: bool,
+
+    // Restore drill
+    pub drill_require: Vec<String>,
+    pub drill_command: Option<String>,
+    pub drill_timeout: Duration,
 }
 
 impl ResolvedJob {
@@ -278,20 +571,61 @@ impl ResolvedJob {
             .or_else(|| defaults.shell.clone())
             .unwrap_or_else(|| "/bin/sh".to_string());
 
+        let limit_upload = job.limit_upload.clone().or_else(|| defaults.limit_upload.clone());
+        let bandwidth_windows = if job.bandwidth_windows.is_empty() {
+            defaults.bandwidth_windows.clone()
+        } else {
+            job.bandwidth_windows.clone()
+        };
+
+        let mut exclude = job.exclude.clone();
+        for file in &job.exclude_files {
+            exclude.extend(read_exclude_file(file).with_context(|| format!("Job '{}'", name))?);
+        }
+
+        let limits = BackupLimits::parse(
+            job.max_total_size
+                .as_deref()
+                .or(defaults.max_total_size.as_deref()),
+            job.max_file_count.or(defaults.max_file_count),
+        )
+        .with_context(|| format!("Job '{}'", name))?;
+        let pack_size = job
+            .pack_size
+            .as_deref()
+            .or(defaults.pack_size.as_deref())
+            .map(crate::commands::parse_pack_size)
+            .transpose()
+            .with_context(|| format!("Job '{}'", name))?;
+
         let pre_hook_timeout = parse_duration(&job.pre_hook_timeout.clone().unwrap_or_else(|| "5m".to_string()))?;
         let post_hook_timeout = parse_duration(&job.post_hook_timeout.clone().unwrap_or_else(|| "5m".to_string()))?;
+        let drill_timeout = parse_duration(job.drill_timeout.as_deref().unwrap_or("30m"))?;
 
         Ok(Self {
             name: name.to_string(),
             repository,
             password_env,
             password_file,
+            keyfile: job.keyfile.clone().or_else(|| defaults.keyfile.clone()),
+            proxy: job.proxy.clone().or_else(|| defaults.proxy.clone()),
             paths,
+            base: job.base.clone(),
             tags: job.tags.clone(),
-            exclude: job.exclude.clone(),
+            exclude,
             exclude_if_present: job.exclude_if_present.clone(),
+            include: job.include.clone(),
             hostname: job.hostname.clone(),
             one_file_system: job.one_file_system,
+            follow_symlinks: job.follow_symlinks,
+            copy_to: job.copy_to.clone(),
+            manifest: job.manifest.clone(),
+            manifest_key: job.manifest_key.clone(),
+            limit_upload,
+            bandwidth_windows,
+            max_read_ops: job.max_read_ops.or(defaults.max_read_ops),
+            limits,
+            pack_size,
             pre_hook: job.pre_hook.clone(),
             post_hook: job.post_hook.clone(),
             pre_hook_timeout,
@@ -308,6 +642,9 @@ impl ResolvedJob {
             require_paths_exist: job.require_paths_exist,
             stop_on_pre_hook_failure: job.stop_on_pre_hook_failure,
             dry_run: job.dry_run,
+            drill_require: job.drill_require.clone(),
+            drill_command: job.drill_command.clone(),
+            drill_timeout,
         })
     }
 
@@ -345,8 +682,22 @@ impl ResolvedJob {
     }
 }
 
+/// Reads exclude patterns from a file: one glob per line, blank lines and
+/// lines starting with `#` are ignored.
+pub fn read_exclude_file(path: &Path) -> Result<Vec<String>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read exclude file: {}", path.display()))?;
+
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
 /// Parse a duration string like "5m", "30s", "1h".
-fn parse_duration(s: &str) -> Result<Duration> {
+pub fn parse_duration(s: &str) -> Result<Duration> {
     let s = s.trim();
     if s.is_empty() {
         return Ok(Duration::from_secs(300)); // Default 5 minutes
@@ -414,26 +765,117 @@ mod tests {
         assert_eq!(job.keep_daily, Some(7));
     }
 
+    #[test]
+    fn test_validate_config() {
+        let toml = r#"
+            version = 1
+
+            [defaults]
+            repository = "s3:default-bucket/backups"
+            password_env = "BACKUP_PASSWORD"
+            bandwidth_windows = ["08:00-20:00=10M"]
+
+            [jobs.web]
+            paths = ["/var/www"]
+            pre_hook_timeout = "10m"
+        "#;
+        let config: JobConfig = toml::from_str(toml).unwrap();
+        config.validate().unwrap();
+
+        let toml = r#"
+            version = 1
+
+            [defaults]
+            limit_upload = "fast"
+
+            [jobs.web]
+            paths = []
+            repository = "s3:"
+            pre_hook_timeout = "5x"
+            bandwidth_windows = ["08:00-08:00=1M"]
+            pack_size = "100K"
+        "#;
+        let config: JobConfig = toml::from_str(toml).unwrap();
+        let Err(ghostsnap_core::Error::InvalidConfig(errors)) = config.validate() else {
+            panic!("invalid config accepted");
+        };
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "defaults.limit_upload",
+                "jobs.web.repository",
+                "jobs.web.password_env",
+                "jobs.web.paths",
+                "jobs.web.bandwidth_windows[0]",
+                "jobs.web.pack_size",
+                "jobs.web.pre_hook_timeout",
+            ]
+        );
+
+        let toml = r#"
+            version = 1
+
+            [jobs.system]
+            repository = "/backup"
+            password_env = "BACKUP_PASSWORD"
+            base = "/etc"
+            paths = ["/etc/nginx", "/usr/local/hestia/conf"]
+        "#;
+        let config: JobConfig = toml::from_str(toml).unwrap();
+        let Err(ghostsnap_core::Error::InvalidConfig(errors)) = config.validate() else {
+            panic!("path outside base accepted");
+        };
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "jobs.system.base");
+    }
+
     #[test]
     fn test_resolve_job() {
         let defaults = JobDefaults {
             repository: Some("s3:default/repo".to_string()),
             password_env: Some("DEFAULT_PASSWORD".to_string()),
             password_file: None,
+            keyfile: Some(PathBuf::from("/root/.ghostsnap.key")),
+            proxy: Some("http://proxy.corp:3128".to_string()),
             shell: None,
+            limit_upload: Some("50M".to_string()),
+            bandwidth_windows: vec!["08:00-20:00=10M".to_string()],
+            nice: Some(10),
+            io_class: Some(IoClass::Idle),
+            max_read_ops: Some(500),
+            max_total_size: Some("2T".to_string()),
+            max_file_count: None,
+            pack_size: Some("256M".to_string()),
+            notify_command: None,
         };
 
         let job = Job {
             repository: None,
             password_env: None,
             password_file: None,
+            keyfile: None,
+            proxy: None,
             paths: vec!["/data".to_string()],
             extra_paths: vec!["/staging".to_string()],
+            base: None,
             tags: vec!["test".to_string()],
             exclude: vec![],
+            exclude_files: vec![],
             exclude_if_present: vec![],
+            include: vec![],
             hostname: None,
             one_file_system: false,
+            follow_symlinks: false,
+            copy_to: vec![],
+            manifest: None,
+            manifest_key: None,
+            limit_upload: None,
+            bandwidth_windows: vec![],
+            max_read_ops: None,
+            max_total_size: None,
+            max_file_count: Some(1_000_000),
+            pack_size: None,
             pre_hook: None,
             post_hook: None,
             pre_hook_timeout: None,
@@ -450,6 +892,9 @@ mod tests {
             require_paths_exist: true,
             stop_on_pre_hook_failure: true,
             dry_run: false,
+            drill_require: vec![],
+            drill_command: None,
+            drill_timeout: None,
         };
 
         let resolved = ResolvedJob::resolve("test", &job, &defaults).unwrap();
@@ -457,5 +902,37 @@ mod tests {
         assert_eq!(resolved.password_env, Some("DEFAULT_PASSWORD".to_string()));
         assert_eq!(resolved.paths.len(), 2);
         assert!(resolved.has_retention_policy());
+        assert_eq!(resolved.limit_upload, Some("50M".to_string()));
+        assert_eq!(resolved.bandwidth_windows, vec!["08:00-20:00=10M"]);
+        assert_eq!(resolved.max_read_ops, Some(500));
+        assert_eq!(
+            resolved.limits,
+            BackupLimits {
+                max_total_size: Some(2 * 1024 * 1024 * 1024 * 1024),
+                max_file_count: Some(1_000_000),
+            }
+        );
+        assert_eq!(
+            resolved.keyfile,
+            Some(PathBuf::from("/root/.ghostsnap.key"))
+        );
+        assert_eq!(resolved.proxy.as_deref(), Some("http://proxy.corp:3128"));
+        assert_eq!(resolved.pack_size, Some(256 * 1024 * 1024));
+        assert_eq!(resolved.drill_timeout, Duration::from_secs(30 * 60));
+    }
+
+    #[test]
+    fn test_read_exclude_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("excludes.txt");
+        fs::write(
+            &path,
+            "# WordPress\n*/wp-content/cache/*\n\n  */var/cache/*  \n#*/tmp/*\n",
+        )
+        .unwrap();
+
+        let patterns = read_exclude_file(&path).unwrap();
+        assert_eq!(patterns, vec!["*/wp-content/cache/*", "*/var/cache/*"]);
+        assert!(read_exclude_file(&dir.path().join("missing.txt")).is_err());
     }
 }
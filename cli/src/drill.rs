@@ -0,0 +1,321 @@
+//! Sanity checks for `job drill` and `hestia verify` test restores.
+//!
+//! A drill restores a job's latest snapshot into a scratch directory and
+//! checks that what came back is usable:
+//!
+//! - Every tar archive (`.tar`, `.tar.gz`, `.tgz`, `.tar.zst`) reads to the
+//!   end, including archives nested inside other archives
+//! - Every MySQL, MariaDB or PostgreSQL dump (`.sql`, optionally gzip or
+//!   zstd compressed) ends with the completion line its dump tool writes
+//! - Every required pattern matches at least one restored file. Archive
+//!   members count as `<archive>/<member>`, so `*/public_html/index.php`
+//!   also finds a file inside a tarball.
+
+use anyhow::{Context, Result, anyhow};
+use flate2::read::GzDecoder;
+use ghostsnap_core::snapshot::Snapshot;
+use ghostsnap_core::storage::{RepositoryLocation, storage_for_location};
+use ghostsnap_core::target::{StorageTarget, plan_restore, restore_to};
+use ghostsnap_core::{NodeType, Repository};
+use globset::{Glob, GlobMatcher};
+use indicatif::{HumanBytes, HumanDuration};
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::Instant;
+use walkdir::WalkDir;
+
+/// Dump tools by the header they write, with the line they end a complete
+/// dump with.
+const DUMP_MARKERS: &[(&str, &str)] = &[
+    ("-- MySQL dump", "-- Dump completed"),
+    ("-- MariaDB dump", "-- Dump completed"),
+    (
+        "-- PostgreSQL database dump",
+        "-- PostgreSQL database dump complete",
+    ),
+];
+
+/// Bytes at the start of a dump searched for its header.
+const DUMP_HEAD: usize = 1024;
+
+/// Bytes at the end of a dump searched for its completion line.
+const DUMP_TAIL: usize = 4096;
+
+/// Outcome of checking one restored directory.
+#[derive(Debug, Default)]
+pub struct DrillChecks {
+    /// Restored files.
+    pub files: u64,
+    /// Tar archives read to the end, nested ones included.
+    pub archives: u64,
+    /// Database dumps found complete.
+    pub dumps: u64,
+    /// One line per failed check.
+    pub failures: Vec<String>,
+    required: Vec<(String, GlobMatcher, bool)>,
+}
+
+impl DrillChecks {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    fn see(&mut self, name: &str) {
+        for (_, matcher, found) in &mut self.required {
+            if !*found && matcher.is_match(name) {
+                *found = true;
+            }
+        }
+    }
+}
+
+/// Restores `snapshot` into `dir` and checks it as [`check_restore`] does,
+/// printing what was restored and checked.
+pub async fn restore_and_check(
+    repo: &Repository,
+    snapshot: &Snapshot,
+    dir: &Path,
+    require: &[String],
+) -> Result<DrillChecks> {
+    let start = Instant::now();
+    let tree = repo.load_tree(&snapshot.tree).await?;
+    let nodes = plan_restore(&tree, &[]);
+    let storage = storage_for_location(&RepositoryLocation::Local(dir.to_path_buf())).await?;
+    let totals = restore_to(repo, &tree, &nodes, &mut StorageTarget::new(storage)).await?;
+    let files = nodes
+        .iter()
+        .filter(|node| node.node_type == NodeType::File)
+        .count();
+    println!(
+        "  Restored: {} files ({} in {})",
+        files,
+        HumanBytes(totals.bytes),
+        HumanDuration(start.elapsed())
+    );
+
+    let require = require.to_vec();
+    let checks_dir = dir.to_path_buf();
+    let checks =
+        tokio::task::spawn_blocking(move || check_restore(&checks_dir, &require)).await??;
+    println!("  Archives: {} read", checks.archives);
+    println!("  Dumps: {} complete", checks.dumps);
+    Ok(checks)
+}
+
+/// Checks the archives and dumps under `dir`, and that each `require`
+/// pattern matches a file or archive member.
+pub fn check_restore(dir: &Path, require: &[String]) -> Result<DrillChecks> {
+    let mut checks = DrillChecks::default();
+    for pattern in require {
+        let matcher = Glob::new(pattern)
+            .with_context(|| format!("Invalid drill_require pattern '{}'", pattern))?
+            .compile_matcher();
+        checks.required.push((pattern.clone(), matcher, false));
+    }
+
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        checks.files += 1;
+
+        let name = entry
+            .path()
+            .strip_prefix(dir)
+            .unwrap_or(entry.path())
+            .to_string_lossy()
+            .into_owned();
+        let mut file = std::fs::File::open(entry.path())
+            .with_context(|| format!("Failed to open {}", entry.path().display()))?;
+        if let Err(e) = check_contents(&name, &mut file, &mut checks) {
+            checks.failures.push(format!("{}: {:#}", name, e));
+        }
+    }
+
+    for (pattern, _, found) in std::mem::take(&mut checks.required) {
+        if !found {
+            checks
+                .failures
+                .push(format!("No restored file matches '{}'", pattern));
+        }
+    }
+    Ok(checks)
+}
+
+fn is_archive(name: &str) -> bool {
+    [".tar", ".tar.gz", ".tgz", ".tar.zst"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+}
+
+fn is_dump(name: &str) -> bool {
+    [".sql", ".sql.gz", ".sql.zst"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+}
+
+/// Checks one file or archive member, named by its path from the restore
+/// directory. Files that are neither archives nor dumps are not read.
+fn check_contents(name: &str, reader: &mut dyn Read, checks: &mut DrillChecks) -> Result<()> {
+    checks.see(name);
+    if !is_archive(name) && !is_dump(name) {
+        return Ok(());
+    }
+
+    let mut reader: Box<dyn Read + '_> = if name.ends_with(".gz") || name.ends_with(".tgz") {
+        Box::new(GzDecoder::new(reader))
+    } else if name.ends_with(".zst") {
+        Box::new(zstd::stream::read::Decoder::new(reader)?)
+    } else {
+        Box::new(reader)
+    };
+
+    if is_archive(name) {
+        check_archive(name, &mut reader, checks)?;
+        checks.archives += 1;
+    } else if check_dump(&mut reader)? {
+        checks.dumps += 1;
+    }
+    Ok(())
+}
+
+/// Reads every member of a tar archive, checking nested archives and dumps
+/// on the way.
+fn check_archive(name: &str, reader: &mut dyn Read, checks: &mut DrillChecks) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let member = format!("{}/{}", name, path.trim_start_matches("./"));
+        let size = entry.size();
+        let is_file = entry.header().entry_type().is_file();
+
+        let mut entry = CountingReader {
+            inner: entry,
+            bytes: 0,
+        };
+        if is_file {
+            check_contents(&member, &mut entry, checks).with_context(|| path.clone())?;
+        }
+        io::copy(&mut entry, &mut io::sink())?;
+        if entry.bytes != size {
+            return Err(anyhow!(
+                "{} is truncated ({} of {} bytes)",
+                path,
+                entry.bytes,
+                size
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a dump is complete. Returns false for files that were not
+/// written by a known dump tool, since their end cannot be recognized.
+fn check_dump(reader: &mut dyn Read) -> Result<bool> {
+    let mut data = Vec::with_capacity(DUMP_HEAD);
+    (&mut *reader)
+        .take(DUMP_HEAD as u64)
+        .read_to_end(&mut data)?;
+    let head = String::from_utf8_lossy(&data);
+    let Some(&(_, marker)) = DUMP_MARKERS
+        .iter()
+        .find(|(header, _)| head.contains(header))
+    else {
+        return Ok(false);
+    };
+
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..n]);
+        if data.len() > DUMP_TAIL {
+            data.drain(..data.len() - DUMP_TAIL);
+        }
+    }
+
+    if String::from_utf8_lossy(&data).contains(marker) {
+        Ok(true)
+    } else {
+        Err(anyhow!("dump is incomplete: no \"{}\" line", marker))
+    }
+}
+
+/// Counts the bytes read through it.
+struct CountingReader<R> {
+    inner: R,
+    bytes: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    const DUMP: &str = "-- MySQL dump 10.13\n\nCREATE TABLE t (id int);\n\n-- Dump completed on 2026-10-16 02:00:00\n";
+
+    fn tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+        for (name, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *data).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn test_check_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let web = tarball(&[("public_html/index.php", &b"<?php echo 1;"[..])]);
+        let user = tarball(&[
+            ("./web/example.com.tar.gz", web.as_slice()),
+            ("./db/app.sql", DUMP.as_bytes()),
+        ]);
+        std::fs::write(dir.path().join("admin.tar.gz"), &user).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"not checked").unwrap();
+
+        let checks = check_restore(dir.path(), &["*/public_html/index.php".to_string()]).unwrap();
+        assert!(checks.passed(), "{:?}", checks.failures);
+        assert_eq!(checks.files, 2);
+        assert_eq!(checks.archives, 2);
+        assert_eq!(checks.dumps, 1);
+
+        let checks = check_restore(dir.path(), &["*/wp-config.php".to_string()]).unwrap();
+        assert_eq!(
+            checks.failures,
+            vec!["No restored file matches '*/wp-config.php'"]
+        );
+    }
+
+    #[test]
+    fn test_check_restore_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = tarball(&[("data.bin", &[7u8; 100_000][..])]);
+        std::fs::write(dir.path().join("cut.tar.gz"), &archive[..archive.len() / 2]).unwrap();
+        let partial = DUMP.replace("-- Dump completed", "INSERT INTO t");
+        std::fs::write(dir.path().join("app.sql"), partial).unwrap();
+        std::fs::write(dir.path().join("plain.sql"), "SELECT 1;\n").unwrap();
+
+        let checks = check_restore(dir.path(), &[]).unwrap();
+        assert_eq!(checks.failures.len(), 2, "{:?}", checks.failures);
+        assert!(checks.failures[0].starts_with("app.sql: dump is incomplete"));
+        assert!(checks.failures[1].starts_with("cut.tar.gz: "));
+        assert_eq!(checks.dumps, 0);
+    }
+}
@@ -0,0 +1,174 @@
+//! Include and exclude rules deciding which paths a backup reads.
+//!
+//! Shared by `backup` and job runs. Patterns are globs matched against the
+//! full path and against the file or directory name. For each entry:
+//!
+//! 1. Excludes win: an entry matching an exclude pattern, or inside a
+//!    directory holding an `exclude_if_present` marker, is skipped even if
+//!    it also matches an include
+//! 2. Without include patterns, everything else is backed up
+//! 3. With include patterns, files, symlinks and special files are backed
+//!    up only if they match one. Directories are always walked and recorded,
+//!    so that included files keep their parents and permissions
+//!
+//! To include a whole directory, match what is below it, e.g.
+//! `/home/*/public_html/**`.
+
+use anyhow::{Result, anyhow};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::path::Path;
+
+/// Why [`PathFilter::skip_reason`] left an entry out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    Excluded,
+    MarkerPresent,
+    NotIncluded,
+}
+
+impl SkipReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SkipReason::Excluded => "glob",
+            SkipReason::MarkerPresent => "marker file present",
+            SkipReason::NotIncluded => "no include pattern matches",
+        }
+    }
+}
+
+pub struct PathFilter {
+    excludes: GlobSet,
+    includes: GlobSet,
+    markers: Vec<String>,
+}
+
+impl PathFilter {
+    pub fn new(excludes: &[String], includes: &[String], markers: &[String]) -> Result<Self> {
+        Ok(Self {
+            excludes: build_matcher(excludes, "exclude")?,
+            includes: build_matcher(includes, "include")?,
+            markers: markers.to_vec(),
+        })
+    }
+
+    /// Returns why `path` should be skipped, or `None` to back it up.
+    pub fn skip_reason(&self, path: &Path, is_dir: bool) -> Option<SkipReason> {
+        if matches(&self.excludes, path) {
+            return Some(SkipReason::Excluded);
+        }
+        if self.marker_present(path) {
+            return Some(SkipReason::MarkerPresent);
+        }
+        if !is_dir && !self.includes.is_empty() && !matches(&self.includes, path) {
+            return Some(SkipReason::NotIncluded);
+        }
+        None
+    }
+
+    /// Checks if the directory, or the parent of a file, contains any
+    /// exclude-if-present marker file.
+    fn marker_present(&self, path: &Path) -> bool {
+        if self.markers.is_empty() {
+            return false;
+        }
+
+        let dir = if path.is_dir() {
+            path
+        } else if let Some(parent) = path.parent() {
+            parent
+        } else {
+            return false;
+        };
+
+        self.markers.iter().any(|marker| dir.join(marker).exists())
+    }
+}
+
+fn build_matcher(patterns: &[String], kind: &str) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|e| anyhow!("Invalid {} pattern '{}': {}", kind, pattern, e))?;
+        builder.add(glob);
+    }
+
+    builder
+        .build()
+        .map_err(|e| anyhow!("Failed to build {} matcher: {}", kind, e))
+}
+
+/// Matches the path as-is and just the file/dir name.
+fn matches(set: &GlobSet, path: &Path) -> bool {
+    if set.is_empty() {
+        return false;
+    }
+    set.is_match(path) || path.file_name().is_some_and(|name| set.is_match(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(list: &[&str]) -> Vec<String> {
+        list.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_excludes_only() {
+        let filter = PathFilter::new(&patterns(&["*.log", "cache"]), &[], &[]).unwrap();
+        let skip = |path: &str, is_dir| filter.skip_reason(Path::new(path), is_dir);
+
+        assert_eq!(skip("/home/a/app.log", false), Some(SkipReason::Excluded));
+        assert_eq!(skip("/home/a/cache", true), Some(SkipReason::Excluded));
+        assert_eq!(skip("/home/a/index.php", false), None);
+        assert_eq!(skip("/home/a", true), None);
+    }
+
+    #[test]
+    fn test_includes_and_precedence() {
+        let filter = PathFilter::new(
+            &patterns(&["*/vendor/*"]),
+            &patterns(&["*.php", "*.sql"]),
+            &[],
+        )
+        .unwrap();
+        let skip = |path: &str, is_dir| filter.skip_reason(Path::new(path), is_dir);
+
+        assert_eq!(skip("/home/a/index.php", false), None);
+        assert_eq!(skip("/home/a/dump.sql", false), None);
+        assert_eq!(
+            skip("/home/a/image.png", false),
+            Some(SkipReason::NotIncluded)
+        );
+        // Directories are walked even though they match no include
+        assert_eq!(skip("/home/a/src", true), None);
+        // Excludes win over includes
+        assert_eq!(
+            skip("/home/a/vendor/lib.php", false),
+            Some(SkipReason::Excluded)
+        );
+    }
+
+    #[test]
+    fn test_marker_and_invalid_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        let skipped = dir.path().join("skipped");
+        std::fs::create_dir(&skipped).unwrap();
+        std::fs::write(skipped.join(".nobackup"), b"").unwrap();
+        std::fs::write(skipped.join("data.sql"), b"").unwrap();
+
+        let filter =
+            PathFilter::new(&[], &patterns(&["*.sql"]), &patterns(&[".nobackup"])).unwrap();
+        assert_eq!(
+            filter.skip_reason(&skipped.join("data.sql"), false),
+            Some(SkipReason::MarkerPresent)
+        );
+        assert_eq!(filter.skip_reason(dir.path(), true), None);
+
+        let err = PathFilter::new(&[], &patterns(&["a[b"]), &[])
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("Invalid include pattern"));
+    }
+}
@@ -38,6 +38,9 @@ pub struct HookConfig {
 
     /// Working directory for the command.
     pub working_dir: Option<PathBuf>,
+
+    /// Extra environment variables for the command.
+    pub env: Vec<(String, String)>,
 }
 
 /// Result of a hook execution.
@@ -115,6 +118,8 @@ pub async fn execute_hook(config: &HookConfig) -> Result<HookResult> {
     let mut cmd = Command::new(&config.shell);
     cmd.arg("-c").arg(&config.command);
 
+    cmd.envs(config.env.iter().map(|(key, value)| (key, value)));
+
     // Set working directory if specified
     if let Some(ref dir) = config.working_dir {
         cmd.current_dir(dir);
@@ -232,50 +237,49 @@ pub async fn execute_hook(config: &HookConfig) -> Result<HookResult> {
     }
 }
 
-/// Execute a hook and print its output.
-pub async fn execute_hook_with_output(
-    name: &str,
-    config: &HookConfig,
-    verbose: bool,
-) -> Result<HookResult> {
-    println!("  {}: running...", name);
-
-    let result = execute_hook(config).await?;
+/// Formats a hook's outcome as report lines, including its output if
+/// verbose or on failure.
+pub fn format_hook_result(name: &str, result: &HookResult, verbose: bool) -> Vec<String> {
+    let mut lines = Vec::new();
 
     if result.success {
-        println!("  {}: OK ({:.1}s)", name, result.duration.as_secs_f64());
+        lines.push(format!(
+            "  {}: OK ({:.1}s)",
+            name,
+            result.duration.as_secs_f64()
+        ));
     } else if result.timed_out {
-        println!(
+        lines.push(format!(
             "  {}: TIMEOUT after {:.1}s",
             name,
             result.duration.as_secs_f64()
-        );
+        ));
     } else {
-        println!(
+        lines.push(format!(
             "  {}: FAILED (exit code {:?}, {:.1}s)",
             name,
             result.exit_code,
             result.duration.as_secs_f64()
-        );
+        ));
     }
 
-    // Print output if verbose or on failure
+    // Include output if verbose or on failure
     if verbose || !result.success {
         if !result.stdout.is_empty() {
-            println!("    stdout:");
+            lines.push("    stdout:".to_string());
             for line in result.stdout.lines() {
-                println!("      {}", line);
+                lines.push(format!("      {}", line));
             }
         }
         if !result.stderr.is_empty() {
-            println!("    stderr:");
+            lines.push("    stderr:".to_string());
             for line in result.stderr.lines() {
-                println!("      {}", line);
+                lines.push(format!("      {}", line));
             }
         }
     }
 
-    Ok(result)
+    lines
 }
 
 /// Truncate a command string for display.
@@ -301,6 +305,7 @@ mod tests {
             timeout: Duration::from_secs(10),
             shell: "/bin/sh".to_string(),
             working_dir: None,
+            env: vec![],
         };
 
         let result = execute_hook(&config).await.unwrap();
@@ -310,6 +315,21 @@ mod tests {
         assert!(!result.timed_out);
     }
 
+    #[tokio::test]
+    async fn test_hook_env() {
+        let config = HookConfig {
+            command: "echo \"$GHOSTSNAP_STATUS\"".to_string(),
+            timeout: Duration::from_secs(10),
+            shell: "/bin/sh".to_string(),
+            working_dir: None,
+            env: vec![("GHOSTSNAP_STATUS".to_string(), "ok".to_string())],
+        };
+
+        let result = execute_hook(&config).await.unwrap();
+        assert!(result.success);
+        assert_eq!(result.stdout.trim(), "ok");
+    }
+
     #[tokio::test]
     async fn test_failed_hook() {
         let config = HookConfig {
@@ -317,6 +337,7 @@ mod tests {
             timeout: Duration::from_secs(10),
             shell: "/bin/sh".to_string(),
             working_dir: None,
+            env: vec![],
         };
 
         let result = execute_hook(&config).await.unwrap();
@@ -332,6 +353,7 @@ mod tests {
             timeout: Duration::from_millis(100),
             shell: "/bin/sh".to_string(),
             working_dir: None,
+            env: vec![],
         };
 
         let result = execute_hook(&config).await.unwrap();
@@ -346,6 +368,7 @@ mod tests {
             timeout: Duration::from_secs(10),
             shell: "/bin/sh".to_string(),
             working_dir: Some(PathBuf::from("/tmp")),
+            env: vec![],
         };
 
         let result = execute_hook(&config).await.unwrap();
@@ -360,6 +383,7 @@ mod tests {
             timeout: Duration::from_secs(10),
             shell: "/bin/sh".to_string(),
             working_dir: None,
+            env: vec![],
         };
 
         let result = execute_hook(&config).await.unwrap();
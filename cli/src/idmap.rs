@@ -0,0 +1,237 @@
+//! uid/gid mapping for restores into containers or onto other systems.
+//!
+//! Backups record numeric owners plus the user and group names they had on
+//! the source system. On restore, each owner is resolved in this order:
+//!
+//! 1. An explicit `--uid-map`/`--gid-map` range covering the id
+//! 2. The recorded name, looked up in this system's `/etc/passwd` or
+//!    `/etc/group` (skipped with `--numeric-ids`)
+//! 3. The recorded id, unchanged
+
+use anyhow::{Result, anyhow};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::str::FromStr;
+
+/// System user database.
+const PASSWD_PATH: &str = "/etc/passwd";
+
+/// System group database.
+const GROUP_PATH: &str = "/etc/group";
+
+/// Maps `count` consecutive ids starting at `from` onto ids starting at `to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdRange {
+    pub from: u32,
+    pub to: u32,
+    pub count: u32,
+}
+
+impl IdRange {
+    fn map(&self, id: u32) -> Option<u32> {
+        let offset = id.checked_sub(self.from)?;
+        (offset < self.count).then(|| self.to.saturating_add(offset))
+    }
+}
+
+/// Parses `OLD:NEW` or `FIRST-LAST:NEW`, e.g. `1000:2000` or `0-65535:100000`.
+impl FromStr for IdRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "Invalid id mapping '{}' (expected OLD:NEW or FIRST-LAST:NEW)",
+                s
+            )
+        };
+        let (old, new) = s.split_once(':').ok_or_else(invalid)?;
+        let (first, last) = match old.split_once('-') {
+            Some((first, last)) => (first, last),
+            None => (old, old),
+        };
+        let first: u32 = first.trim().parse().map_err(|_| invalid())?;
+        let last: u32 = last.trim().parse().map_err(|_| invalid())?;
+        let to: u32 = new.trim().parse().map_err(|_| invalid())?;
+        if last < first {
+            return Err(invalid());
+        }
+        let count = last - first + 1;
+        if to.checked_add(count - 1).is_none() {
+            return Err(anyhow!("Id mapping '{}' runs past the largest id", s));
+        }
+        Ok(Self {
+            from: first,
+            to,
+            count,
+        })
+    }
+}
+
+/// Resolves recorded owners to the ids to restore with.
+#[derive(Debug, Default)]
+pub struct IdMapper {
+    uid_ranges: Vec<IdRange>,
+    gid_ranges: Vec<IdRange>,
+    /// Recorded uid -> local uid, for users whose name exists here
+    uids_by_name: HashMap<u32, u32>,
+    /// Recorded gid -> local gid, for groups whose name exists here
+    gids_by_name: HashMap<u32, u32>,
+}
+
+impl IdMapper {
+    pub fn new(uid_ranges: Vec<IdRange>, gid_ranges: Vec<IdRange>) -> Self {
+        Self {
+            uid_ranges,
+            gid_ranges,
+            ..Self::default()
+        }
+    }
+
+    /// Maps owners by name: `user_names` and `group_names` are the names
+    /// recorded in the snapshot, `local_users` and `local_groups` the ids of
+    /// names on the restoring system.
+    pub fn with_names(
+        mut self,
+        user_names: &BTreeMap<u32, String>,
+        group_names: &BTreeMap<u32, String>,
+        local_users: &BTreeMap<u32, String>,
+        local_groups: &BTreeMap<u32, String>,
+    ) -> Self {
+        self.uids_by_name = match_names(user_names, local_users);
+        self.gids_by_name = match_names(group_names, local_groups);
+        self
+    }
+
+    /// Maps owners by name against this system's `/etc/passwd` and
+    /// `/etc/group`.
+    pub fn with_system_names(
+        self,
+        user_names: &BTreeMap<u32, String>,
+        group_names: &BTreeMap<u32, String>,
+    ) -> Self {
+        let local_users = read_id_file(Path::new(PASSWD_PATH));
+        let local_groups = read_id_file(Path::new(GROUP_PATH));
+        self.with_names(user_names, group_names, &local_users, &local_groups)
+    }
+
+    pub fn map_uid(&self, uid: u32) -> u32 {
+        map_id(uid, &self.uid_ranges, &self.uids_by_name)
+    }
+
+    pub fn map_gid(&self, gid: u32) -> u32 {
+        map_id(gid, &self.gid_ranges, &self.gids_by_name)
+    }
+}
+
+fn map_id(id: u32, ranges: &[IdRange], by_name: &HashMap<u32, u32>) -> u32 {
+    ranges
+        .iter()
+        .find_map(|range| range.map(id))
+        .or_else(|| by_name.get(&id).copied())
+        .unwrap_or(id)
+}
+
+fn match_names(
+    recorded: &BTreeMap<u32, String>,
+    local: &BTreeMap<u32, String>,
+) -> HashMap<u32, u32> {
+    let local_ids: HashMap<&str, u32> = local
+        .iter()
+        .map(|(id, name)| (name.as_str(), *id))
+        .collect();
+    recorded
+        .iter()
+        .filter_map(|(id, name)| Some((*id, *local_ids.get(name.as_str())?)))
+        .collect()
+}
+
+/// Parses `/etc/passwd` or `/etc/group` content (`name:password:id:...`)
+/// into id -> name. When several names share an id, the first one wins.
+pub fn parse_id_file(contents: &str) -> BTreeMap<u32, String> {
+    let mut names = BTreeMap::new();
+    for line in contents.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split(':');
+        let (Some(name), Some(_), Some(id)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        if let Ok(id) = id.parse::<u32>() {
+            names.entry(id).or_insert_with(|| name.to_string());
+        }
+    }
+    names
+}
+
+/// Reads a passwd or group file; a missing or unreadable file yields no
+/// names.
+pub fn read_id_file(path: &Path) -> BTreeMap<u32, String> {
+    std::fs::read_to_string(path)
+        .map(|contents| parse_id_file(&contents))
+        .unwrap_or_default()
+}
+
+/// Names of the given uids and gids on this system, for recording in a
+/// snapshot. Ids without a name are left out.
+pub fn owner_names(
+    uids: impl IntoIterator<Item = u32>,
+    gids: impl IntoIterator<Item = u32>,
+) -> (BTreeMap<u32, String>, BTreeMap<u32, String>) {
+    (
+        names_of(uids, &read_id_file(Path::new(PASSWD_PATH))),
+        names_of(gids, &read_id_file(Path::new(GROUP_PATH))),
+    )
+}
+
+fn names_of(
+    ids: impl IntoIterator<Item = u32>,
+    names: &BTreeMap<u32, String>,
+) -> BTreeMap<u32, String> {
+    ids.into_iter()
+        .filter_map(|id| Some((id, names.get(&id)?.clone())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_id_range() {
+        let single: IdRange = "1000:2000".parse().unwrap();
+        assert_eq!(single.map(1000), Some(2000));
+        assert_eq!(single.map(1001), None);
+
+        let shifted: IdRange = "0-65535:100000".parse().unwrap();
+        assert_eq!(shifted.map(0), Some(100000));
+        assert_eq!(shifted.map(1000), Some(101000));
+        assert_eq!(shifted.map(65536), None);
+
+        assert!("1000".parse::<IdRange>().is_err());
+        assert!("5-1:10".parse::<IdRange>().is_err());
+        assert!("0-10:4294967290".parse::<IdRange>().is_err());
+    }
+
+    #[test]
+    fn test_mapping_precedence() {
+        let source_users = BTreeMap::from([(1000, "alice".to_string()), (1001, "bob".to_string())]);
+        let local_users = parse_id_file(
+            "# users\nroot:x:0:0:root:/root:/bin/sh\nalice:x:1500:1500::/home/alice:/bin/sh\n",
+        );
+
+        let mapper = IdMapper::new(vec!["0:100000".parse().unwrap()], Vec::new()).with_names(
+            &source_users,
+            &BTreeMap::new(),
+            &local_users,
+            &BTreeMap::new(),
+        );
+
+        assert_eq!(mapper.map_uid(0), 100000);
+        assert_eq!(mapper.map_uid(1000), 1500);
+        // bob has no account here; the recorded id is kept
+        assert_eq!(mapper.map_uid(1001), 1001);
+        assert_eq!(mapper.map_gid(1000), 1000);
+    }
+}
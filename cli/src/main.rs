@@ -1,16 +1,27 @@
 mod commands;
 mod config;
+mod drill;
+mod filter;
 mod hooks;
+mod idmap;
+mod password;
+mod priority;
+mod telemetry;
+mod tui;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use commands::{
-    backup::BackupCommand, check::CheckCommand, copy::CopyCommand, diff::DiffCommand,
-    dump::DumpCommand, forget::ForgetCommand, init::InitCommand, job::JobCommand, ls::LsCommand,
-    prune::PruneCommand, restore::RestoreCommand, snapshots::SnapshotsCommand,
-    stats::StatsCommand,
+    backend::BackendCommand, backup::BackupCommand, bench::BenchCommand, bundle::BundleCommand,
+    check::CheckCommand, config::ConfigCommand, copy::CopyCommand, diff::DiffCommand,
+    dump::DumpCommand, forget::ForgetCommand, grep::GrepCommand, hestia::HestiaCommand,
+    import::ImportCommand, init::InitCommand, job::JobCommand, key::KeyCommand,
+    locks::LocksCommand, ls::LsCommand, manifest::ManifestCommand, merge::MergeCommand,
+    policy::PolicyCommand, prune::PruneCommand, restic::ResticCommand, restore::RestoreCommand,
+    scrub::ScrubCommand, snapshots::SnapshotsCommand, stats::StatsCommand,
+    telemetry::TelemetryCommand, tui::TuiCommand,
 };
-use tracing::info;
+use tracing::{Instrument, error, info, info_span};
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
 #[derive(Parser)]
@@ -29,6 +40,55 @@ struct Cli {
     #[arg(long, env = "GHOSTSNAP_PASSWORD", help = "Repository password")]
     password: Option<String>,
 
+    #[arg(
+        long,
+        global = true,
+        env = "GHOSTSNAP_KEYFILE",
+        help = "Keyfile required in addition to the password"
+    )]
+    keyfile: Option<std::path::PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "GHOSTSNAP_PROXY",
+        help = "Proxy for S3 and Azure repositories (http://, socks5:// or socks5h:// URL)"
+    )]
+    proxy: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        alias = "dump-requests",
+        env = "GHOSTSNAP_DEBUG_BACKEND",
+        help = "Log every storage request (method, path, status, duration, retries; never data or credentials)"
+    )]
+    debug_backend: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Show what would be deleted or modified without changing the repository"
+    )]
+    dry_run: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "DURATION",
+        help = "Keep retrying for this long (e.g. 30m) when the repository is locked"
+    )]
+    retry_lock: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "TTL",
+        env = "GHOSTSNAP_METADATA_CACHE",
+        help = "Cache object listings and existence checks for this long (e.g. 5m) and merge duplicate requests, to save LIST/HEAD calls on object stores"
+    )]
+    metadata_cache: Option<String>,
+
     #[arg(short, long, help = "Enable verbose output")]
     verbose: bool,
 
@@ -62,6 +122,9 @@ enum Commands {
     #[command(about = "Apply retention policies to snapshots")]
     Forget(ForgetCommand),
 
+    #[command(about = "Manage the retention policy stored in the repository")]
+    Policy(PolicyCommand),
+
     #[command(about = "Remove unused data and reclaim space")]
     Prune(PruneCommand),
 
@@ -76,34 +139,250 @@ enum Commands {
 
     #[command(about = "Run config-driven backup jobs")]
     Job(JobCommand),
+
+    #[command(about = "Export or import portable snapshot bundles")]
+    Bundle(BundleCommand),
+
+    #[command(about = "Manage storage backend settings such as Azure access tiers")]
+    Backend(BackendCommand),
+
+    #[command(about = "Merge several snapshots into one (newest version wins)")]
+    Merge(MergeCommand),
+
+    #[command(about = "Open an interactive repository dashboard")]
+    Tui(TuiCommand),
+
+    #[command(about = "Back up HestiaCP system configuration and mail")]
+    Hestia(HestiaCommand),
+
+    #[command(about = "Manage opt-in anonymous usage counters")]
+    Telemetry(TelemetryCommand),
+
+    #[command(about = "Browse and restore from a restic repository (read-only)")]
+    Restic(ResticCommand),
+
+    #[command(about = "Import tar or duplicity archives as dated snapshots")]
+    Import(ImportCommand),
+
+    #[command(about = "Create manifest signing keys and verify backup manifests")]
+    Manifest(ManifestCommand),
+
+    #[command(about = "Search file contents inside a snapshot")]
+    Grep(GrepCommand),
+
+    #[command(about = "Verify a rotating subset of packs (one pass or as a daemon)")]
+    Scrub(ScrubCommand),
+
+    #[command(about = "Validate configuration files and edit repository settings")]
+    Config(ConfigCommand),
+
+    #[command(about = "Export or import key material for disaster recovery")]
+    Key(KeyCommand),
+
+    #[command(about = "List repository locks and who holds them")]
+    Locks(LocksCommand),
+
+    #[command(about = "Measure chunking, compression and encryption speed and recommend settings")]
+    Bench(BenchCommand),
+}
+
+impl Commands {
+    /// Command name as counted by telemetry.
+    fn name(&self) -> &'static str {
+        match self {
+            Commands::Init(_) => "init",
+            Commands::Backup(_) => "backup",
+            Commands::Snapshots(_) => "snapshots",
+            Commands::Restore(_) => "restore",
+            Commands::Stats(_) => "stats",
+            Commands::Check(_) => "check",
+            Commands::Ls(_) => "ls",
+            Commands::Forget(_) => "forget",
+            Commands::Policy(_) => "policy",
+            Commands::Prune(_) => "prune",
+            Commands::Diff(_) => "diff",
+            Commands::Dump(_) => "dump",
+            Commands::Copy(_) => "copy",
+            Commands::Job(_) => "job",
+            Commands::Bundle(_) => "bundle",
+            Commands::Backend(_) => "backend",
+            Commands::Merge(_) => "merge",
+            Commands::Tui(_) => "tui",
+            Commands::Hestia(_) => "hestia",
+            Commands::Telemetry(_) => "telemetry",
+            Commands::Restic(_) => "restic",
+            Commands::Import(_) => "import",
+            Commands::Manifest(_) => "manifest",
+            Commands::Grep(_) => "grep",
+            Commands::Scrub(_) => "scrub",
+            Commands::Config(_) => "config",
+            Commands::Key(_) => "key",
+            Commands::Locks(_) => "locks",
+            Commands::Bench(_) => "bench",
+        }
+    }
+
+    /// The command's own `--dry-run` flag, for commands that have one.
+    fn dry_run_flag(&mut self) -> Option<&mut bool> {
+        match self {
+            Commands::Backup(cmd) => Some(&mut cmd.dry_run),
+            Commands::Restore(cmd) => Some(&mut cmd.dry_run),
+            Commands::Forget(cmd) => Some(&mut cmd.dry_run),
+            Commands::Prune(cmd) => Some(&mut cmd.dry_run),
+            Commands::Copy(cmd) => Some(&mut cmd.dry_run),
+            Commands::Merge(cmd) => Some(&mut cmd.dry_run),
+            Commands::Import(cmd) => Some(&mut cmd.dry_run),
+            _ => None,
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cli = Cli::parse();
-
-    init_tracing(cli.verbose, cli.quiet);
-
-    info!("Starting Ghostsnap");
-
-    match cli.command {
-        Commands::Init(ref cmd) => cmd.run(&cli).await,
-        Commands::Backup(ref cmd) => cmd.run(&cli).await,
-        Commands::Snapshots(ref cmd) => cmd.run(&cli).await,
-        Commands::Restore(ref cmd) => cmd.run(&cli).await,
-        Commands::Stats(ref cmd) => cmd.run(&cli).await,
-        Commands::Check(ref cmd) => cmd.run(&cli).await,
-        Commands::Ls(ref cmd) => cmd.run(&cli).await,
-        Commands::Forget(ref cmd) => cmd.run(&cli).await,
-        Commands::Prune(ref cmd) => cmd.run(&cli).await,
-        Commands::Diff(ref cmd) => cmd.run(&cli).await,
-        Commands::Dump(ref cmd) => cmd.run(&cli).await,
-        Commands::Copy(ref cmd) => cmd.run(&cli).await,
-        Commands::Job(ref cmd) => cmd.run(&cli).await,
+    let mut cli = Cli::parse();
+
+    init_tracing(cli.verbose, cli.quiet, cli.debug_backend);
+    if cli.debug_backend {
+        ghostsnap_core::request_log::enable();
     }
+
+    // `--dry-run` before and after the subcommand mean the same. Storage is
+    // opened read-only either way, so commands without a dry run of their
+    // own fail before changing anything.
+    if let Some(flag) = cli.command.dry_run_flag() {
+        *flag |= cli.dry_run;
+        cli.dry_run = *flag;
+    }
+    if cli.dry_run {
+        ghostsnap_core::dry_run::enable();
+    }
+    if let Some(ttl) = &cli.metadata_cache {
+        ghostsnap_core::api_cache::enable(config::parse_duration(ttl)?);
+    }
+
+    // Every log line of this run carries the operation ID, and it is repeated
+    // in the final error message so reports can be matched against the logs.
+    let operation_id = ghostsnap_core::new_operation_id();
+    let span = info_span!("run", op = %operation_id);
+
+    let result = async {
+        info!("Starting Ghostsnap");
+
+        // Unencrypted repositories have no password; don't prompt for one.
+        let opens_repository = match &cli.command {
+            Commands::Init(_) => false,
+            Commands::Config(cmd) => cmd.opens_repository(),
+            _ => true,
+        };
+        if cli.password.is_none()
+            && opens_repository
+            && commands::is_unencrypted_repository(&cli).await
+        {
+            cli.password = Some(String::new());
+        }
+
+        match cli.command {
+            Commands::Init(ref cmd) => cmd.run(&cli).await,
+            Commands::Backup(ref cmd) => cmd.run(&cli).await,
+            Commands::Snapshots(ref cmd) => cmd.run(&cli).await,
+            Commands::Restore(ref cmd) => cmd.run(&cli).await,
+            Commands::Stats(ref cmd) => cmd.run(&cli).await,
+            Commands::Check(ref cmd) => cmd.run(&cli).await,
+            Commands::Ls(ref cmd) => cmd.run(&cli).await,
+            Commands::Forget(ref cmd) => cmd.run(&cli).await,
+            Commands::Policy(ref cmd) => cmd.run(&cli).await,
+            Commands::Prune(ref cmd) => cmd.run(&cli).await,
+            Commands::Diff(ref cmd) => cmd.run(&cli).await,
+            Commands::Dump(ref cmd) => cmd.run(&cli).await,
+            Commands::Copy(ref cmd) => cmd.run(&cli).await,
+            Commands::Job(ref cmd) => cmd.run(&cli).await,
+            Commands::Bundle(ref cmd) => cmd.run(&cli).await,
+            Commands::Backend(ref cmd) => cmd.run(&cli).await,
+            Commands::Merge(ref cmd) => cmd.run(&cli).await,
+            Commands::Tui(ref cmd) => cmd.run(&cli).await,
+            Commands::Hestia(ref cmd) => cmd.run(&cli).await,
+            Commands::Telemetry(ref cmd) => cmd.run(&cli).await,
+            Commands::Restic(ref cmd) => cmd.run(&cli).await,
+            Commands::Import(ref cmd) => cmd.run(&cli).await,
+            Commands::Manifest(ref cmd) => cmd.run(&cli).await,
+            Commands::Grep(ref cmd) => cmd.run(&cli).await,
+            Commands::Scrub(ref cmd) => cmd.run(&cli).await,
+            Commands::Config(ref cmd) => cmd.run(&cli).await,
+            Commands::Key(ref cmd) => cmd.run(&cli).await,
+            Commands::Locks(ref cmd) => cmd.run(&cli).await,
+            Commands::Bench(ref cmd) => cmd.run(&cli).await,
+        }
+    }
+    .instrument(span.clone())
+    .await;
+
+    if !cli.quiet {
+        print_retry_summary(cli.verbose);
+        if cli.metadata_cache.is_some() || cli.verbose {
+            print_api_summary(cli.verbose);
+        }
+    }
+
+    if result.is_ok() && !matches!(cli.command, Commands::Telemetry(_)) {
+        telemetry::record(cli.quiet, |counters| {
+            counters.record_command(cli.command.name())
+        });
+    }
+
+    result.map_err(|e| {
+        span.in_scope(|| error!("{:#}", e));
+        e.context(format!("Operation {} failed", operation_id))
+    })
 }
 
-fn init_tracing(verbose: bool, quiet: bool) {
+/// Reports the transient backend errors retried during the run on stderr, so
+/// flaky infrastructure gets noticed even when every operation succeeded.
+fn print_retry_summary(verbose: bool) {
+    let metrics = ghostsnap_core::metrics::retry_metrics();
+    let lines = metrics.summary();
+    if lines.is_empty() {
+        return;
+    }
+
+    for line in lines {
+        eprintln!("{}", line);
+    }
+    if verbose {
+        for (operation, retries) in &metrics.operations {
+            if retries.retries > 0 || retries.failures > 0 {
+                eprintln!(
+                    "  {}: {} operations, {} retries, {} failed ({:.1}%)",
+                    operation,
+                    retries.operations,
+                    retries.retries,
+                    retries.failures,
+                    retries.failure_rate() * 100.0
+                );
+            }
+        }
+    }
+}
+
+/// Reports the storage requests of the run on stderr, to show what a
+/// command costs on a per-request billed object store.
+fn print_api_summary(verbose: bool) {
+    let calls = ghostsnap_core::api_cache::api_calls();
+    let Some(summary) = calls.summary() else {
+        return;
+    };
+
+    eprintln!("{}", summary);
+    if verbose {
+        for (method, calls) in &calls.methods {
+            eprintln!(
+                "  {}: {} sent, {} answered from cache",
+                method, calls.requests, calls.cached
+            );
+        }
+    }
+}
+
+fn init_tracing(verbose: bool, quiet: bool, debug_backend: bool) {
     let level = if quiet {
         "warn"
     } else if verbose {
@@ -112,8 +391,16 @@ fn init_tracing(verbose: bool, quiet: bool) {
         "info"
     };
 
+    let mut filter = format!("ghostsnap={}", level);
+    if debug_backend {
+        // Request lines are logged at debug level; show them even when quiet.
+        filter.push_str(",ghostsnap_core::request_log=debug");
+    }
+
     let subscriber = FmtSubscriber::builder()
-        .with_env_filter(EnvFilter::new(format!("ghostsnap={}", level)))
+        .with_env_filter(EnvFilter::new(filter))
+        // Keep stdout for command output such as `--format json`
+        .with_writer(std::io::stderr)
         .finish();
 
     // Ignore errors: a global subscriber may already be set (e.g. when the CLI
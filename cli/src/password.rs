@@ -0,0 +1,249 @@
+//! Password prompts and strength feedback.
+//!
+//! Every command reads the repository password through
+//! [`repository_password`], so `--password` / `GHOSTSNAP_PASSWORD` and the
+//! prompt behave the same everywhere. `init` uses [`new_password`], which asks
+//! twice so that a typo can't lock anyone out of their backups, and rates the
+//! password with a zxcvbn-style estimate.
+
+use anyhow::{Result, anyhow};
+use std::io::{self, Write};
+use tracing::warn;
+
+/// Returns the password from `--password` / `GHOSTSNAP_PASSWORD`, prompting
+/// for it when unset.
+pub fn repository_password(cli: &crate::Cli) -> Result<String> {
+    password_or_prompt(cli.password.as_deref(), "Enter repository password: ")
+}
+
+/// Returns `given`, or prompts for a password with `prompt`.
+pub fn password_or_prompt(given: Option<&str>, prompt: &str) -> Result<String> {
+    match given {
+        Some(password) => Ok(password.to_string()),
+        None => prompt_password(prompt),
+    }
+}
+
+/// Reads a password from the terminal without echoing it.
+pub fn prompt_password(prompt: &str) -> Result<String> {
+    rpassword::prompt_password(prompt).map_err(|e| anyhow!("Password required: {}", e))
+}
+
+/// Prompts before entering a password twice.
+const NEW_PASSWORD_ATTEMPTS: usize = 3;
+
+/// Reads the password for a new repository.
+///
+/// A password given on the command line or in the environment is used as
+/// is, with a warning if it is weak. Otherwise the password is prompted for
+/// and, unless `confirm` is false, entered a second time; weak passwords are
+/// only accepted after the strength feedback has been shown and confirmed.
+pub fn new_password(given: Option<&str>, confirm: bool) -> Result<String> {
+    if let Some(password) = given {
+        let strength = Strength::estimate(password);
+        if strength.is_weak() {
+            warn!(
+                "The repository password is {} ({}/4): {}",
+                strength.label(),
+                strength.score,
+                strength.feedback.join("; ")
+            );
+        }
+        return Ok(password.to_string());
+    }
+
+    for _ in 0..NEW_PASSWORD_ATTEMPTS {
+        let password = prompt_password("Enter new repository password: ")?;
+        let strength = Strength::estimate(&password);
+        eprintln!(
+            "Password strength: {} ({}/4)",
+            strength.label(),
+            strength.score
+        );
+        for line in &strength.feedback {
+            eprintln!("  - {}", line);
+        }
+        if strength.is_weak() && confirm && !ask_yes_no("Use this password anyway?")? {
+            continue;
+        }
+
+        if !confirm {
+            return Ok(password);
+        }
+        if prompt_password("Confirm repository password: ")? == password {
+            return Ok(password);
+        }
+        eprintln!("Passwords do not match, try again.");
+    }
+    Err(anyhow!(
+        "No password set after {} attempts",
+        NEW_PASSWORD_ATTEMPTS
+    ))
+}
+
+pub fn ask_yes_no(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes"))
+}
+
+/// Passwords that are guessed first, compared case-insensitively, also
+/// after stripping trailing digits and symbols.
+const COMMON_PASSWORDS: &[&str] = &[
+    "password",
+    "passw0rd",
+    "123456",
+    "12345678",
+    "qwerty",
+    "qwertz",
+    "azerty",
+    "letmein",
+    "welcome",
+    "admin",
+    "administrator",
+    "root",
+    "changeme",
+    "secret",
+    "iloveyou",
+    "monkey",
+    "dragon",
+    "football",
+    "baseball",
+    "sunshine",
+    "princess",
+    "master",
+    "shadow",
+    "abc123",
+    "backup",
+    "backups",
+    "ghostsnap",
+    "default",
+];
+
+/// Estimated password strength, scored 0 (trivial) to 4 (strong) like zxcvbn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Strength {
+    pub score: u8,
+    /// Suggestions for a stronger password
+    pub feedback: Vec<String>,
+}
+
+impl Strength {
+    /// Estimates the strength from the character classes used and the
+    /// length, not counting characters that only repeat or continue a
+    /// sequence (`aaa`, `abc`, `321`). Common passwords score 0.
+    pub fn estimate(password: &str) -> Self {
+        let mut feedback = Vec::new();
+
+        let lower = password.to_lowercase();
+        let base = lower.trim_end_matches(|c: char| !c.is_alphabetic());
+        if password.is_empty()
+            || COMMON_PASSWORDS.contains(&lower.as_str())
+            || COMMON_PASSWORDS.contains(&base)
+        {
+            feedback.push("This is one of the first passwords an attacker tries".to_string());
+            return Self { score: 0, feedback };
+        }
+
+        let has = |f: fn(&char) -> bool| password.chars().any(|c| f(&c));
+        let classes = [
+            (has(char::is_ascii_lowercase), 26),
+            (has(char::is_ascii_uppercase), 26),
+            (has(char::is_ascii_digit), 10),
+            (has(char::is_ascii_punctuation), 33),
+            (has(|c| *c == ' '), 1),
+            (has(|c| !c.is_ascii()), 100),
+        ];
+        let pool: u32 = classes
+            .iter()
+            .filter(|(used, _)| *used)
+            .map(|(_, n)| n)
+            .sum();
+        let used_classes = classes.iter().filter(|(used, _)| *used).count();
+
+        let chars: Vec<char> = password.chars().collect();
+        let mut effective = 1;
+        let mut patterned = false;
+        for pair in chars.windows(2) {
+            let step = pair[1] as i64 - pair[0] as i64;
+            if matches!(step, -1..=1) {
+                patterned = true;
+            } else {
+                effective += 1;
+            }
+        }
+
+        let bits = effective as f64 * f64::from(pool).log2();
+        let score = match bits {
+            b if b < 25.0 => 0,
+            b if b < 40.0 => 1,
+            b if b < 55.0 => 2,
+            b if b < 70.0 => 3,
+            _ => 4,
+        };
+
+        if chars.len() < 12 {
+            feedback.push("Use at least 12 characters".to_string());
+        }
+        if used_classes == 1 && chars.len() < 20 {
+            feedback.push("Mix in upper case letters, digits or symbols".to_string());
+        }
+        if patterned && effective * 2 < chars.len() {
+            feedback
+                .push("Avoid repeated characters and sequences like 'aaa' or 'abc'".to_string());
+        }
+        if score < 3 {
+            feedback.push(
+                "A passphrase of four or more random words is strong and easy to type".to_string(),
+            );
+        }
+        Self { score, feedback }
+    }
+
+    /// Below 3: guessable offline with modest effort.
+    pub fn is_weak(&self) -> bool {
+        self.score < 3
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self.score {
+            0 => "very weak",
+            1 => "weak",
+            2 => "fair",
+            3 => "good",
+            _ => "strong",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strength_estimate() {
+        assert_eq!(Strength::estimate("").score, 0);
+        assert_eq!(Strength::estimate("Password123!").score, 0);
+        assert_eq!(Strength::estimate("ghostsnap").score, 0);
+        assert_eq!(Strength::estimate("123456").score, 0);
+        assert!(Strength::estimate("aaaaaaaaaaaaaaaa").is_weak());
+        assert!(Strength::estimate("abcdefghijklmnop").is_weak());
+        assert!(Strength::estimate("hunter2").is_weak());
+
+        let strong = Strength::estimate("correct horse battery staple");
+        assert_eq!(strong.score, 4);
+        assert!(strong.feedback.is_empty());
+        assert!(!Strength::estimate("k8#Qv!2mZr@x").is_weak());
+    }
+
+    #[test]
+    fn test_given_password_is_used_as_is() {
+        assert_eq!(new_password(Some("hunter2"), true).unwrap(), "hunter2");
+        assert_eq!(
+            password_or_prompt(Some("secret"), "unused: ").unwrap(),
+            "secret"
+        );
+    }
+}
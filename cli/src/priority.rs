@@ -0,0 +1,121 @@
+//! Low-priority operation for backups on busy servers.
+//!
+//! - `nice` lowers CPU scheduling priority with `setpriority(2)`.
+//! - `io_class` sets the I/O scheduling class with `ioprio_set(2)` (Linux only).
+//! - `max_read_ops` caps how many files are opened or stat'ed per second
+//!   while scanning and chunking.
+//!
+//! Priorities apply to the whole process. Unprivileged users can only lower
+//! them; negative nice values need root.
+
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use ghostsnap_core::{BandwidthSchedule, RateLimiter};
+use serde::Deserialize;
+
+/// I/O scheduling class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoClass {
+    /// Normal class at its lowest priority level
+    BestEffort,
+    /// Only use the disk when no other process needs it
+    Idle,
+}
+
+impl IoClass {
+    /// Value passed to `ioprio_set`: class in the top bits, level below.
+    fn ioprio(self) -> i32 {
+        const IOPRIO_CLASS_SHIFT: i32 = 13;
+        match self {
+            IoClass::BestEffort => (2 << IOPRIO_CLASS_SHIFT) | 7,
+            IoClass::Idle => 3 << IOPRIO_CLASS_SHIFT,
+        }
+    }
+}
+
+/// Applies the requested CPU and I/O priorities to the current process.
+pub fn lower_priority(nice: Option<i32>, io_class: Option<IoClass>) -> Result<()> {
+    if let Some(nice) = nice {
+        set_nice(nice)?;
+    }
+    if let Some(class) = io_class {
+        set_io_class(class)?;
+    }
+    Ok(())
+}
+
+/// Builds a limiter that allows `max_read_ops` filesystem operations per
+/// second; each operation is throttled as one unit.
+pub fn read_ops_limiter(max_read_ops: Option<u32>) -> Option<RateLimiter> {
+    max_read_ops.map(|ops| {
+        RateLimiter::new(BandwidthSchedule {
+            default_limit: Some(u64::from(ops)),
+            windows: Vec::new(),
+        })
+    })
+}
+
+#[cfg(unix)]
+fn set_nice(nice: i32) -> Result<()> {
+    let result = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) };
+    if result != 0 {
+        return Err(anyhow!(
+            "Failed to set nice value {}: {}",
+            nice,
+            std::io::Error::last_os_error()
+        ));
+    }
+    tracing::debug!("Set nice value to {}", nice);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_nice(_nice: i32) -> Result<()> {
+    tracing::warn!("--nice is not supported on this platform");
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_io_class(class: IoClass) -> Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    let result =
+        unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, class.ioprio()) };
+    if result != 0 {
+        return Err(anyhow!(
+            "Failed to set I/O class {:?}: {}",
+            class,
+            std::io::Error::last_os_error()
+        ));
+    }
+    tracing::debug!("Set I/O class to {:?}", class);
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_io_class(class: IoClass) -> Result<()> {
+    tracing::warn!("I/O class {:?} is only supported on Linux", class);
+    let _ = class.ioprio();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ioprio_values() {
+        assert_eq!(IoClass::Idle.ioprio(), 0x6000);
+        assert_eq!(IoClass::BestEffort.ioprio(), 0x4007);
+    }
+
+    #[test]
+    fn test_io_class_from_config() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            io_class: IoClass,
+        }
+        let parsed: Wrapper = toml::from_str("io_class = \"best-effort\"").unwrap();
+        assert_eq!(parsed.io_class, IoClass::BestEffort);
+    }
+}
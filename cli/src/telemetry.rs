@@ -0,0 +1,226 @@
+//! Opt-in, anonymous usage counters.
+//!
+//! Nothing is recorded until the user runs `ghostsnap telemetry enable` (or
+//! sets `GHOSTSNAP_TELEMETRY=1`). The counters are kept in a local JSON file
+//! and only leave the machine when the user runs `ghostsnap telemetry submit`,
+//! which posts that file as-is to the URL they give.
+//!
+//! The counters hold no identifiers, host names, paths or repository
+//! locations: only how often each command ran, which backend types backups
+//! went to, and repository sizes rounded to coarse buckets. Runs with
+//! `--quiet` are not counted.
+
+use anyhow::{Context, Result};
+use ghostsnap_core::storage::RepositoryLocation;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Telemetry file format version.
+const TELEMETRY_VERSION: u32 = 1;
+
+/// Environment variable that turns recording on (`1`) or off (`0`),
+/// overriding the setting in the telemetry file.
+pub const TELEMETRY_ENV: &str = "GHOSTSNAP_TELEMETRY";
+
+/// Environment variable overriding the location of the telemetry file.
+const TELEMETRY_FILE_ENV: &str = "GHOSTSNAP_TELEMETRY_FILE";
+
+/// Upper bounds of the repository size buckets, in bytes.
+const SIZE_BUCKETS: &[(u64, &str)] = &[
+    (1 << 30, "<1G"),
+    (10 << 30, "1G-10G"),
+    (100 << 30, "10G-100G"),
+    (1 << 40, "100G-1T"),
+    (10 << 40, "1T-10T"),
+];
+
+/// Locally stored usage counters.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Counters {
+    pub version: u32,
+    /// Whether recording is turned on
+    #[serde(default)]
+    pub enabled: bool,
+    /// Successful runs per command
+    #[serde(default)]
+    pub commands: BTreeMap<String, u64>,
+    /// Backups per backend type (local, s3, azure, rclone, sftp)
+    #[serde(default)]
+    pub backends: BTreeMap<String, u64>,
+    /// Backups per repository size bucket
+    #[serde(default)]
+    pub repository_sizes: BTreeMap<String, u64>,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            version: TELEMETRY_VERSION,
+            enabled: false,
+            commands: BTreeMap::new(),
+            backends: BTreeMap::new(),
+            repository_sizes: BTreeMap::new(),
+        }
+    }
+}
+
+impl Counters {
+    /// Loads the counters from `path`; a missing file yields empty counters
+    /// with recording off.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("Invalid telemetry file: {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => {
+                Err(e).with_context(|| format!("Failed to read telemetry file: {}", path.display()))
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write telemetry file: {}", path.display()))
+    }
+
+    /// Whether recording is on, taking [`TELEMETRY_ENV`] into account.
+    pub fn is_enabled(&self) -> bool {
+        match std::env::var(TELEMETRY_ENV).ok().as_deref() {
+            Some("1" | "true" | "on") => true,
+            Some("0" | "false" | "off") => false,
+            _ => self.enabled,
+        }
+    }
+
+    pub fn record_command(&mut self, command: &str) {
+        *self.commands.entry(command.to_string()).or_default() += 1;
+    }
+
+    pub fn record_backup(&mut self, location: &RepositoryLocation, repository_size: u64) {
+        *self
+            .backends
+            .entry(backend_type(location).to_string())
+            .or_default() += 1;
+        *self
+            .repository_sizes
+            .entry(size_bucket(repository_size).to_string())
+            .or_default() += 1;
+    }
+
+    /// Clears the counters, keeping the enabled setting.
+    pub fn reset(&mut self) {
+        *self = Self {
+            enabled: self.enabled,
+            ..Self::default()
+        };
+    }
+}
+
+/// Location of the telemetry file.
+pub fn telemetry_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var(TELEMETRY_FILE_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    directories::ProjectDirs::from("", "", "ghostsnap")
+        .map(|dirs| dirs.data_dir().join("telemetry.json"))
+}
+
+/// Returns whether this run should be counted.
+pub fn is_recording(quiet: bool) -> bool {
+    !quiet
+        && telemetry_path()
+            .and_then(|path| Counters::load(&path).ok())
+            .is_some_and(|counters| counters.is_enabled())
+}
+
+/// Applies `update` to the stored counters if recording is on. Telemetry never
+/// fails a command: errors are only logged at debug level.
+pub fn record(quiet: bool, update: impl FnOnce(&mut Counters)) {
+    if quiet {
+        return;
+    }
+    let Some(path) = telemetry_path() else {
+        return;
+    };
+    if let Err(e) = record_at(&path, update) {
+        debug!("Failed to update telemetry counters: {:#}", e);
+    }
+}
+
+fn record_at(path: &Path, update: impl FnOnce(&mut Counters)) -> Result<()> {
+    let mut counters = Counters::load(path)?;
+    if !counters.is_enabled() {
+        return Ok(());
+    }
+    update(&mut counters);
+    counters.save(path)
+}
+
+/// Backend type of a repository, without any part of its location.
+pub fn backend_type(location: &RepositoryLocation) -> &'static str {
+    match location {
+        RepositoryLocation::Local(_) => "local",
+        RepositoryLocation::S3(_) => "s3",
+        RepositoryLocation::Azure(_) => "azure",
+        RepositoryLocation::Rclone(_) => "rclone",
+        RepositoryLocation::Sftp(_) => "sftp",
+    }
+}
+
+/// Rounds a repository size to its bucket.
+pub fn size_bucket(bytes: u64) -> &'static str {
+    SIZE_BUCKETS
+        .iter()
+        .find(|(limit, _)| bytes < *limit)
+        .map_or(">10T", |(_, name)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_buckets() {
+        assert_eq!(size_bucket(0), "<1G");
+        assert_eq!(size_bucket(5 << 30), "1G-10G");
+        assert_eq!(size_bucket(1 << 40), "1T-10T");
+        assert_eq!(size_bucket(u64::MAX), ">10T");
+    }
+
+    #[test]
+    fn test_record_only_when_enabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("telemetry.json");
+
+        record_at(&path, |c| c.record_command("backup")).unwrap();
+        assert!(!path.exists());
+
+        let counters = Counters {
+            enabled: true,
+            ..Counters::default()
+        };
+        counters.save(&path).unwrap();
+
+        let location = RepositoryLocation::Local(PathBuf::from("/backup/repo"));
+        record_at(&path, |c| {
+            c.record_command("backup");
+            c.record_backup(&location, 3 << 30);
+        })
+        .unwrap();
+
+        let counters = Counters::load(&path).unwrap();
+        assert_eq!(counters.commands["backup"], 1);
+        assert_eq!(counters.backends["local"], 1);
+        assert_eq!(counters.repository_sizes["1G-10G"], 1);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("/backup/repo"));
+    }
+}
@@ -0,0 +1,335 @@
+//! Repository overview dashboard for `ghostsnap tui`.
+//!
+//! Shows the snapshot list, cached repository statistics, the operation
+//! currently holding the repository lock, and errors hit while refreshing.
+//! Data is reloaded every refresh interval or on demand with `r`.
+
+use anyhow::Result;
+use chrono::{DateTime, Local, Utc};
+use ghostsnap_core::{LockInfo, LockManager, Repository, Snapshot, StatsCache};
+use indicatif::HumanBytes;
+use ratatui::DefaultTerminal;
+use ratatui::Frame;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Row, Table, TableState};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Number of errors kept in the error pane.
+const MAX_ERRORS: usize = 50;
+
+/// How long to wait for a key press before checking the refresh timer.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Runs the dashboard until the user quits.
+pub async fn run(repo: &Repository, location: &str, refresh: Duration) -> Result<()> {
+    let mut errors = ErrorLog::default();
+    let data = DashboardData::load(repo, &mut errors).await;
+
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, repo, location, refresh, data, errors).await;
+    ratatui::restore();
+    result
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    repo: &Repository,
+    location: &str,
+    refresh: Duration,
+    mut data: DashboardData,
+    mut errors: ErrorLog,
+) -> Result<()> {
+    let mut table_state = TableState::default().with_selected(Some(0));
+    let mut last_refresh = Instant::now();
+
+    loop {
+        terminal.draw(|frame| draw(frame, location, &data, &errors, &mut table_state))?;
+
+        // Crossterm polling blocks the thread; keep it off the async workers.
+        let event = tokio::task::block_in_place(|| -> std::io::Result<Option<Event>> {
+            if event::poll(POLL_INTERVAL)? {
+                Ok(Some(event::read()?))
+            } else {
+                Ok(None)
+            }
+        })?;
+
+        let mut reload = last_refresh.elapsed() >= refresh;
+
+        if let Some(Event::Key(key)) = event
+            && key.kind == KeyEventKind::Press
+        {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Up | KeyCode::Char('k') => table_state.select_previous(),
+                KeyCode::Down | KeyCode::Char('j') => table_state.select_next(),
+                KeyCode::Home | KeyCode::Char('g') => table_state.select_first(),
+                KeyCode::End | KeyCode::Char('G') => table_state.select_last(),
+                KeyCode::Char('r') => reload = true,
+                KeyCode::Char('c') => errors.clear(),
+                _ => {}
+            }
+        }
+
+        if reload {
+            data = DashboardData::load(repo, &mut errors).await;
+            last_refresh = Instant::now();
+        }
+    }
+}
+
+/// Snapshot of repository state shown by the dashboard.
+struct DashboardData {
+    /// Newest first
+    snapshots: Vec<Snapshot>,
+    stats: Option<StatsCache>,
+    lock: Option<LockInfo>,
+    /// Whether lock information is available (local repositories only)
+    lock_supported: bool,
+    loaded_at: DateTime<Utc>,
+}
+
+impl DashboardData {
+    /// Loads everything the dashboard shows. Failures are recorded in
+    /// `errors` and leave the corresponding pane empty.
+    async fn load(repo: &Repository, errors: &mut ErrorLog) -> Self {
+        let mut snapshots = Vec::new();
+        match repo.list_snapshots().await {
+            Ok(ids) => {
+                for id in ids {
+                    match repo.load_snapshot(&id).await {
+                        Ok(snapshot) => snapshots.push(snapshot),
+                        Err(e) => errors.push(format!("Snapshot {}: {}", id, e)),
+                    }
+                }
+            }
+            Err(e) => errors.push(format!("Listing snapshots: {}", e)),
+        }
+        snapshots.sort_by_key(|s| std::cmp::Reverse(s.time));
+
+        let stats = match repo.stats_cache(false).await {
+            Ok(stats) => Some(stats),
+            Err(e) => {
+                errors.push(format!("Statistics: {}", e));
+                None
+            }
+        };
+
+        let lock = match repo.local_path() {
+            Some(path) => match LockManager::new(path).get_lock_info().await {
+                Ok(lock) => lock,
+                Err(e) => {
+                    errors.push(format!("Lock status: {}", e));
+                    None
+                }
+            },
+            None => None,
+        };
+
+        Self {
+            snapshots,
+            stats,
+            lock,
+            lock_supported: repo.local_path().is_some(),
+            loaded_at: Utc::now(),
+        }
+    }
+}
+
+/// Bounded, newest-first list of timestamped error messages.
+#[derive(Default)]
+struct ErrorLog {
+    entries: VecDeque<(DateTime<Utc>, String)>,
+}
+
+impl ErrorLog {
+    fn push(&mut self, message: String) {
+        self.entries.push_front((Utc::now(), message));
+        self.entries.truncate(MAX_ERRORS);
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+fn draw(
+    frame: &mut Frame,
+    location: &str,
+    data: &DashboardData,
+    errors: &ErrorLog,
+    table_state: &mut TableState,
+) {
+    let [header, main, error_area, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(8),
+        Constraint::Length(8),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [snapshot_area, side] =
+        Layout::horizontal([Constraint::Percentage(70), Constraint::Percentage(30)]).areas(main);
+    let [stats_area, activity_area] =
+        Layout::vertical([Constraint::Length(10), Constraint::Min(4)]).areas(side);
+
+    frame.render_widget(
+        Paragraph::new(format!(
+            "Ghostsnap  {}  (updated {})",
+            location,
+            data.loaded_at.with_timezone(&Local).format("%H:%M:%S")
+        ))
+        .bold(),
+        header,
+    );
+
+    draw_snapshots(frame, snapshot_area, data, table_state);
+    draw_stats(frame, stats_area, data);
+    draw_activity(frame, activity_area, data);
+    draw_errors(frame, error_area, errors);
+
+    frame.render_widget(
+        Line::from("↑↓ select  r refresh  c clear errors  q quit").dim(),
+        footer,
+    );
+}
+
+fn draw_snapshots(frame: &mut Frame, area: Rect, data: &DashboardData, state: &mut TableState) {
+    let rows = data.snapshots.iter().map(|s| {
+        Row::new(vec![
+            s.short_id(),
+            s.time
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+            s.hostname.clone(),
+            s.tags.join(","),
+            s.paths
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+        ])
+    });
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(8),
+            Constraint::Length(16),
+            Constraint::Length(16),
+            Constraint::Length(16),
+            Constraint::Min(10),
+        ],
+    )
+    .header(Row::new(vec!["ID", "Time", "Host", "Tags", "Paths"]).bold())
+    .block(Block::bordered().title(format!(" Snapshots ({}) ", data.snapshots.len())))
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+    frame.render_stateful_widget(table, area, state);
+}
+
+fn draw_stats(frame: &mut Frame, area: Rect, data: &DashboardData) {
+    let lines = match &data.stats {
+        Some(stats) => {
+            let stored = stats.stored_size();
+            let original = stats.original_size();
+            let ratio = if stored > 0 {
+                original as f64 / stored as f64
+            } else {
+                1.0
+            };
+            vec![
+                Line::from(format!("Snapshots:  {}", stats.snapshot_count())),
+                Line::from(format!("Packs:      {}", stats.pack_count())),
+                Line::from(format!("Stored:     {}", HumanBytes(stored))),
+                Line::from(format!("Original:   {}", HumanBytes(original))),
+                Line::from(format!("Dedup:      {:.2}x", ratio)),
+                Line::from(format!(
+                    "Saved:      {}",
+                    HumanBytes(original.saturating_sub(stored))
+                )),
+            ]
+        }
+        None => vec![Line::from("Unavailable").dim()],
+    };
+
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Repository ")),
+        area,
+    );
+}
+
+fn draw_activity(frame: &mut Frame, area: Rect, data: &DashboardData) {
+    let lines = match (&data.lock, data.lock_supported) {
+        (Some(lock), _) => {
+            let running = Utc::now().signed_duration_since(lock.created_at);
+            let mut lines = vec![
+                Line::from(format!("{} ({:?} lock)", lock.operation, lock.lock_type)),
+                Line::from(format!("{} pid {}", lock.hostname, lock.pid)),
+                Line::from(format!(
+                    "Running {}m {}s",
+                    running.num_minutes(),
+                    running.num_seconds() % 60
+                )),
+            ];
+            if lock.is_stale() {
+                lines.push(Line::from("Lock looks stale").fg(Color::Yellow));
+            }
+            lines
+        }
+        (None, true) => vec![Line::from("Idle").dim()],
+        (None, false) => vec![Line::from("Not available for remote repositories").dim()],
+    };
+
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Activity ")),
+        area,
+    );
+}
+
+fn draw_errors(frame: &mut Frame, area: Rect, errors: &ErrorLog) {
+    let items: Vec<ListItem> = errors
+        .entries
+        .iter()
+        .map(|(time, message)| {
+            ListItem::new(format!(
+                "{}  {}",
+                time.with_timezone(&Local).format("%H:%M:%S"),
+                message
+            ))
+            .fg(Color::Red)
+        })
+        .collect();
+
+    frame.render_widget(
+        List::new(items).block(Block::bordered().title(format!(" Errors ({}) ", errors.len()))),
+        area,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_log_is_bounded_newest_first() {
+        let mut log = ErrorLog::default();
+        for i in 0..MAX_ERRORS + 5 {
+            log.push(format!("error {}", i));
+        }
+
+        assert_eq!(log.len(), MAX_ERRORS);
+        assert_eq!(log.entries[0].1, format!("error {}", MAX_ERRORS + 4));
+
+        log.clear();
+        assert_eq!(log.len(), 0);
+    }
+}
@@ -0,0 +1,7 @@
+//! Terminal user interfaces built on ratatui.
+//!
+//! - [`dashboard`]: repository overview used by `ghostsnap tui`
+//! - [`picker`]: snapshot tree browser used by `restore --interactive`
+
+pub mod dashboard;
+pub mod picker;
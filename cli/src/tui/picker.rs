@@ -0,0 +1,393 @@
+//! Interactive snapshot tree browser for selecting paths to restore.
+//!
+//! ## Keys
+//!
+//! | Key | Action |
+//! |-----|--------|
+//! | `↑`/`k`, `↓`/`j` | Move the cursor |
+//! | `→`/`l`/`Enter` | Open directory |
+//! | `←`/`h`/`Backspace` | Go to parent directory |
+//! | `Space` | Mark or unmark the entry under the cursor |
+//! | `a` | Mark or unmark every entry in the current directory |
+//! | `r` | Restore the marked entries |
+//! | `q`/`Esc` | Cancel |
+
+use anyhow::Result;
+use ghostsnap_core::snapshot::Tree;
+use indicatif::HumanBytes;
+use ratatui::DefaultTerminal;
+use ratatui::Frame;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use std::collections::{BTreeSet, HashMap};
+
+/// Opens the picker for `tree` and returns the marked paths, or `None` if the
+/// user cancelled.
+pub fn run(tree: &Tree, title: &str) -> Result<Option<Vec<String>>> {
+    let mut browser = TreeBrowser::new(tree);
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut browser, title);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    browser: &mut TreeBrowser,
+    title: &str,
+) -> Result<Option<Vec<String>>> {
+    let mut list_state = ListState::default();
+
+    loop {
+        list_state.select(Some(browser.cursor));
+        terminal.draw(|frame| draw(frame, browser, &mut list_state, title))?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+            KeyCode::Up | KeyCode::Char('k') => browser.up(),
+            KeyCode::Down | KeyCode::Char('j') => browser.down(),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => browser.enter(),
+            KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h') => browser.leave(),
+            KeyCode::Char(' ') => browser.toggle(),
+            KeyCode::Char('a') => browser.toggle_all(),
+            KeyCode::Char('r') if !browser.marked.is_empty() => {
+                return Ok(Some(browser.selection()));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, browser: &TreeBrowser, list_state: &mut ListState, title: &str) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    frame.render_widget(
+        Paragraph::new(format!("Snapshot {}  /{}", title, browser.cwd)).bold(),
+        header,
+    );
+
+    let items: Vec<ListItem> = browser
+        .current()
+        .iter()
+        .map(|name| {
+            let entry = &browser.entries[name];
+            let mark = match browser.mark_state(name) {
+                Mark::Full => "[x]",
+                Mark::Partial => "[~]",
+                Mark::None => "[ ]",
+            };
+            let label = file_name(name);
+            let line = if entry.is_dir {
+                format!("{} {}/", mark, label)
+            } else {
+                format!("{} {}  {}", mark, label, HumanBytes(entry.size))
+            };
+            ListItem::new(line)
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(Block::bordered())
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, body, list_state);
+
+    frame.render_widget(
+        Line::from(format!(
+            "↑↓ move  → open  ← back  space mark  a all  r restore  q quit  |  {} selected",
+            browser.marked.len()
+        ))
+        .dim(),
+        footer,
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    None,
+    Partial,
+    Full,
+}
+
+#[derive(Debug)]
+struct Entry {
+    is_dir: bool,
+    size: u64,
+}
+
+/// Directory-by-directory view of a flat snapshot tree with a set of marks.
+struct TreeBrowser {
+    entries: HashMap<String, Entry>,
+    /// Parent path -> children, directories first
+    children: HashMap<String, Vec<String>>,
+    cwd: String,
+    cursor: usize,
+    /// Marked paths; never contains a path below another marked path
+    marked: BTreeSet<String>,
+}
+
+impl TreeBrowser {
+    fn new(tree: &Tree) -> Self {
+        let mut entries = HashMap::new();
+        for node in &tree.nodes {
+            if node.name.is_empty() {
+                continue;
+            }
+            entries.insert(
+                node.name.clone(),
+                Entry {
+                    is_dir: node.is_dir(),
+                    size: node.size,
+                },
+            );
+
+            // Trees without explicit directory nodes still need browsable parents.
+            let mut parent = parent_of(&node.name);
+            while !parent.is_empty() {
+                entries.entry(parent.to_string()).or_insert(Entry {
+                    is_dir: true,
+                    size: 0,
+                });
+                parent = parent_of(parent);
+            }
+        }
+
+        let mut children: HashMap<String, Vec<String>> = HashMap::new();
+        for name in entries.keys() {
+            children
+                .entry(parent_of(name).to_string())
+                .or_default()
+                .push(name.clone());
+        }
+        for list in children.values_mut() {
+            list.sort_by(|a, b| {
+                entries[b]
+                    .is_dir
+                    .cmp(&entries[a].is_dir)
+                    .then_with(|| a.cmp(b))
+            });
+        }
+
+        Self {
+            entries,
+            children,
+            cwd: String::new(),
+            cursor: 0,
+            marked: BTreeSet::new(),
+        }
+    }
+
+    fn current(&self) -> &[String] {
+        self.children
+            .get(&self.cwd)
+            .map(|c| c.as_slice())
+            .unwrap_or(&[])
+    }
+
+    fn selected(&self) -> Option<&String> {
+        self.current().get(self.cursor)
+    }
+
+    fn up(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    fn down(&mut self) {
+        if self.cursor + 1 < self.current().len() {
+            self.cursor += 1;
+        }
+    }
+
+    fn enter(&mut self) {
+        if let Some(name) = self.selected().cloned()
+            && self.entries[&name].is_dir
+        {
+            self.cwd = name;
+            self.cursor = 0;
+        }
+    }
+
+    fn leave(&mut self) {
+        if self.cwd.is_empty() {
+            return;
+        }
+        let previous = std::mem::take(&mut self.cwd);
+        self.cwd = parent_of(&previous).to_string();
+        self.cursor = self
+            .current()
+            .iter()
+            .position(|name| *name == previous)
+            .unwrap_or(0);
+    }
+
+    fn toggle(&mut self) {
+        if let Some(name) = self.selected().cloned() {
+            self.set_marked(&name, self.mark_state(&name) != Mark::Full);
+        }
+    }
+
+    fn toggle_all(&mut self) {
+        let names = self.current().to_vec();
+        let mark = names.iter().any(|n| self.mark_state(n) != Mark::Full);
+        for name in names {
+            self.set_marked(&name, mark);
+        }
+    }
+
+    fn set_marked(&mut self, name: &str, mark: bool) {
+        // Entries below a marked directory are implied; they cannot be
+        // changed individually.
+        if self.has_marked_ancestor(name) {
+            return;
+        }
+
+        let prefix = format!("{}/", name);
+        self.marked.retain(|m| !m.starts_with(&prefix));
+        if mark {
+            self.marked.insert(name.to_string());
+        } else {
+            self.marked.remove(name);
+        }
+    }
+
+    fn has_marked_ancestor(&self, name: &str) -> bool {
+        let mut parent = parent_of(name);
+        while !parent.is_empty() {
+            if self.marked.contains(parent) {
+                return true;
+            }
+            parent = parent_of(parent);
+        }
+        false
+    }
+
+    fn mark_state(&self, name: &str) -> Mark {
+        if self.marked.contains(name) || self.has_marked_ancestor(name) {
+            Mark::Full
+        } else {
+            let prefix = format!("{}/", name);
+            if self.marked.iter().any(|m| m.starts_with(&prefix)) {
+                Mark::Partial
+            } else {
+                Mark::None
+            }
+        }
+    }
+
+    fn selection(&self) -> Vec<String> {
+        self.marked.iter().cloned().collect()
+    }
+}
+
+fn parent_of(name: &str) -> &str {
+    name.rsplit_once('/')
+        .map(|(parent, _)| parent)
+        .unwrap_or("")
+}
+
+fn file_name(name: &str) -> &str {
+    name.rsplit_once('/').map(|(_, file)| file).unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ghostsnap_core::types::{NodeType, TreeNode};
+
+    fn node(name: &str, node_type: NodeType) -> TreeNode {
+        TreeNode {
+            name: name.to_string(),
+            node_type,
+            mode: 0o644,
+            uid: 0,
+            gid: 0,
+            size: 10,
+            mtime: 0,
+            link_target: None,
+            subtree_id: None,
+            chunks: Vec::new(),
+            xattr: None,
+            sparse_holes: None,
+            inode: None,
+            nlink: None,
+            hardlink_target: None,
+            device: None,
+        }
+    }
+
+    fn browser() -> TreeBrowser {
+        let mut tree = Tree::new();
+        tree.add_node(node("", NodeType::Directory));
+        tree.add_node(node("b.txt", NodeType::File));
+        tree.add_node(node("docs", NodeType::Directory));
+        tree.add_node(node("docs/a.md", NodeType::File));
+        tree.add_node(node("docs/b.md", NodeType::File));
+        // No explicit node for "src"
+        tree.add_node(node("src/main.rs", NodeType::File));
+        TreeBrowser::new(&tree)
+    }
+
+    #[test]
+    fn test_browser_navigation() {
+        let mut b = browser();
+        assert_eq!(b.current(), ["docs", "src", "b.txt"]);
+
+        b.enter();
+        assert_eq!(b.cwd, "docs");
+        assert_eq!(b.current(), ["docs/a.md", "docs/b.md"]);
+
+        b.down();
+        b.down();
+        assert_eq!(b.cursor, 1);
+
+        b.leave();
+        assert_eq!(b.cwd, "");
+        assert_eq!(b.selected().unwrap(), "docs");
+
+        // Files cannot be entered
+        b.down();
+        b.down();
+        b.enter();
+        assert_eq!(b.cwd, "");
+    }
+
+    #[test]
+    fn test_browser_marks() {
+        let mut b = browser();
+        b.enter();
+        b.toggle();
+        assert_eq!(b.mark_state("docs/a.md"), Mark::Full);
+        assert_eq!(b.mark_state("docs"), Mark::Partial);
+
+        // Marking the directory subsumes marks below it
+        b.leave();
+        b.toggle();
+        assert_eq!(b.selection(), ["docs"]);
+        assert_eq!(b.mark_state("docs/b.md"), Mark::Full);
+
+        // Entries under a marked directory cannot be unmarked on their own
+        b.enter();
+        b.toggle();
+        assert_eq!(b.selection(), ["docs"]);
+
+        b.leave();
+        b.toggle_all();
+        assert_eq!(b.selection(), ["b.txt", "docs", "src"]);
+        b.toggle_all();
+        assert!(b.selection().is_empty());
+    }
+}
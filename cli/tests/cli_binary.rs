@@ -6,7 +6,7 @@
 
 use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use tempfile::tempdir;
 
@@ -131,6 +131,32 @@ fn test_cli_init_local_repo() {
     assert!(repo_path.join("keys").is_dir(), "Keys dir should exist");
 }
 
+#[test]
+fn test_cli_init_unencrypted_repo() {
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("plain-repo");
+
+    // No password is given or prompted for
+    let (success, _stdout, stderr) = run_ghostsnap(&[
+        "init",
+        repo_path.to_str().unwrap(),
+        "--insecure-no-encryption",
+    ]);
+    assert!(success, "Init should succeed: {}", stderr);
+
+    let config = fs::read_to_string(repo_path.join("config")).unwrap();
+    assert!(
+        config.contains("\"encryption\": \"none\""),
+        "Config should record the encryption mode: {}",
+        config
+    );
+
+    let (success, stdout, stderr) =
+        run_ghostsnap(&["--repo", repo_path.to_str().unwrap(), "stats"]);
+    assert!(success, "Stats should not need a password: {}", stderr);
+    assert!(stdout.contains("Encryption:   none"), "{}", stdout);
+}
+
 #[test]
 fn test_cli_snapshots_command() {
     let temp = tempdir().unwrap();
@@ -191,6 +217,57 @@ fn test_cli_stats_command() {
     assert!(success, "Stats command should succeed: {}", stderr);
 }
 
+#[test]
+fn test_cli_stats_cost() {
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let repo = repo_path.to_str().unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&["init", repo], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+
+    let pricing_file = temp.path().join("my-class.toml");
+    fs::write(
+        &pricing_file,
+        "storage_gb_month = 0.01\nput_per_1000 = 0.004\nget_per_1000 = 0.0004\n",
+    )
+    .unwrap();
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
+            "stats",
+            "--cost",
+            "--pricing",
+            "s3-deep-archive",
+            "--pricing",
+            pricing_file.to_str().unwrap(),
+            "--restore-size",
+            "1G",
+            "--json",
+        ],
+        "test-password",
+    );
+    assert!(success, "Stats --cost should succeed: {}", stderr);
+    let stats: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let estimates = stats["cost"]["estimates"].as_array().unwrap();
+    assert_eq!(estimates[0]["pricing"], "s3-deep-archive");
+    assert_eq!(estimates[1]["pricing"], "my-class");
+    // 1 GiB in 16 MiB local packs, plus the config, index, snapshot and tree
+    assert_eq!(estimates[0]["restore"]["requests"], 68);
+
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "stats", "--cost", "--pricing", "s3-unknown"],
+        "test-password",
+    );
+    assert!(!success);
+    assert!(
+        stderr.contains("Unknown pricing table 's3-unknown'"),
+        "{}",
+        stderr
+    );
+}
+
 #[test]
 fn test_cli_backup_and_restore_workflow() {
     let temp = tempdir().unwrap();
@@ -275,133 +352,1244 @@ fn test_cli_backup_and_restore_workflow() {
     );
 }
 
-#[test]
-fn test_cli_forget_and_prune() {
+/// --verify-existing compares a restored file with the snapshot chunk by
+/// chunk and downloads only the chunks that differ; data appended since is
+/// cut off again.
+#[tokio::test]
+async fn test_cli_restore_verify_existing() {
+    use ghostsnap_core::Repository;
+    use ghostsnap_core::chunker::MIN_CHUNK_SIZE;
+    use indicatif::HumanBytes;
+
     let temp = tempdir().unwrap();
     let repo_path = temp.path().join("repo");
     let source_path = temp.path().join("source");
+    let restore_path = temp.path().join("restore");
     fs::create_dir_all(&source_path).unwrap();
+    let repo = repo_path.to_str().unwrap();
+    let target = restore_path.to_str().unwrap();
 
-    // Create test file
-    let test_file = source_path.join("data.txt");
-    let mut file = File::create(&test_file).unwrap();
-    file.write_all(b"Some data for backup").unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&["init", repo], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+    // Small chunks, so a few MiB make several of them
+    Repository::open(&repo_path, "test-password")
+        .await
+        .unwrap()
+        .set_chunk_size(Some(MIN_CHUNK_SIZE))
+        .await
+        .unwrap();
 
-    // Init and backup
-    let _ = run_ghostsnap_with_password(&["init", repo_path.to_str().unwrap()], "test-password");
-    let _ = run_ghostsnap_with_password(
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    let data: Vec<u8> = (0..4 * 1024 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    fs::write(source_path.join("data.bin"), &data).unwrap();
+
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "backup", source_path.to_str().unwrap()],
+        "test-password",
+    );
+    assert!(success, "Backup should succeed: {}", stderr);
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "restore", "latest", "--target", target],
+        "test-password",
+    );
+    assert!(success, "Restore should succeed: {}", stderr);
+
+    let chunks = {
+        let repo = Repository::open(&repo_path, "test-password").await.unwrap();
+        let snapshot_id = repo.list_snapshots().await.unwrap()[0].clone();
+        let snapshot = repo.load_snapshot(&snapshot_id).await.unwrap();
+        let tree = repo.load_tree(&snapshot.tree).await.unwrap();
+        let node = tree.nodes.iter().find(|n| n.name == "data.bin").unwrap();
+        node.chunks.clone()
+    };
+    assert!(chunks.len() > 2, "{} chunks", chunks.len());
+
+    // Damage the second chunk and append to the file
+    let restored = restore_path.join("data.bin");
+    let start = chunks[0].length as usize;
+    let length = chunks[1].length as usize;
+    let mut contents = fs::read(&restored).unwrap();
+    for byte in &mut contents[start..start + length] {
+        *byte = !*byte;
+    }
+    contents.extend_from_slice(b"appended after the backup");
+    fs::write(&restored, &contents).unwrap();
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
         &[
             "--repo",
-            repo_path.to_str().unwrap(),
-            "backup",
-            source_path.to_str().unwrap(),
+            repo,
+            "restore",
+            "latest",
+            "--target",
+            target,
+            "--verify-existing",
+            "--dry-run",
         ],
         "test-password",
     );
+    assert!(success, "Dry run should succeed: {}", stderr);
+    assert!(
+        stdout.contains(&format!(
+            "Would patch file: {} (1 of {} chunks differ)",
+            restored.display(),
+            chunks.len()
+        )),
+        "stdout: {}",
+        stdout
+    );
+    assert_eq!(fs::read(&restored).unwrap(), contents);
 
-    // Test forget: ghostsnap --repo /path forget --keep-last 1
-    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
         &[
             "--repo",
-            repo_path.to_str().unwrap(),
-            "forget",
-            "--keep-last",
-            "1",
+            repo,
+            "restore",
+            "latest",
+            "--target",
+            target,
+            "--verify-existing",
         ],
         "test-password",
     );
-    assert!(success, "Forget should succeed: {}", stderr);
+    assert!(success, "Restore should succeed: {}", stderr);
+    assert!(
+        stdout.contains(&format!(
+            "Patched in place: 1 (0 unchanged; {} downloaded, {} reused)",
+            HumanBytes(length as u64),
+            HumanBytes((data.len() - length) as u64)
+        )),
+        "stdout: {}",
+        stdout
+    );
+    assert_eq!(fs::read(&restored).unwrap(), data);
+}
+
+/// FIFOs are backed up as metadata-only entries and recreated on restore.
+#[cfg(unix)]
+#[test]
+fn test_cli_fifo_backup_restore() {
+    use std::os::unix::fs::FileTypeExt;
+
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let source_path = temp.path().join("source");
+    let restore_path = temp.path().join("restore");
+    fs::create_dir_all(&source_path).unwrap();
+    let status = Command::new("mkfifo")
+        .arg(source_path.join("pipe"))
+        .status()
+        .unwrap();
+    assert!(status.success(), "mkfifo should succeed");
+
+    let repo = repo_path.to_str().unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&["init", repo], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "backup", source_path.to_str().unwrap()],
+        "test-password",
+    );
+    assert!(success, "Backup should succeed: {}", stderr);
+    assert!(stdout.contains("Devices/FIFOs: 1"), "{}", stdout);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "snapshots", "--format", "json"],
+        "test-password",
+    );
+    assert!(success, "Snapshots should succeed: {}", stderr);
+    let snapshots: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let snapshot_id = snapshots[0]["id"].as_str().unwrap();
+
+    let (success, stdout, stderr) =
+        run_ghostsnap_with_password(&["--repo", repo, "ls", snapshot_id, "-l"], "test-password");
+    assert!(success, "ls should succeed: {}", stderr);
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line.starts_with('p') && line.ends_with("pipe")),
+        "{}",
+        stdout
+    );
 
-    // Test prune: ghostsnap --repo /path prune
     let (success, _stdout, stderr) = run_ghostsnap_with_password(
-        &["--repo", repo_path.to_str().unwrap(), "prune"],
+        &[
+            "--repo",
+            repo,
+            "restore",
+            snapshot_id,
+            "--target",
+            restore_path.to_str().unwrap(),
+        ],
         "test-password",
     );
-    assert!(success, "Prune should succeed: {}", stderr);
+    assert!(success, "Restore should succeed: {}", stderr);
+    let restored = fs::symlink_metadata(restore_path.join("pipe")).unwrap();
+    assert!(restored.file_type().is_fifo());
 }
 
+/// Symlinks are restored as links, dangling ones included, and restore
+/// never writes through a symlink already at the target.
+#[cfg(unix)]
 #[test]
-fn test_cli_copy_between_repos() {
+fn test_cli_symlink_backup_restore() {
+    use std::os::unix::fs::symlink;
+
     let temp = tempdir().unwrap();
-    let repo1_path = temp.path().join("repo1");
-    let repo2_path = temp.path().join("repo2");
+    let repo_path = temp.path().join("repo");
     let source_path = temp.path().join("source");
-    fs::create_dir_all(&source_path).unwrap();
+    let restore_path = temp.path().join("restore");
+    fs::create_dir_all(source_path.join("www")).unwrap();
+    fs::write(source_path.join("www/index.php"), b"<?php echo 1;").unwrap();
+    symlink("www", source_path.join("current")).unwrap();
+    symlink("/nonexistent/ghostsnap", source_path.join("dangling")).unwrap();
 
-    // Create test file
-    let test_file = source_path.join("copy-test.txt");
-    let mut file = File::create(&test_file).unwrap();
-    file.write_all(b"Data for copy test").unwrap();
+    let repo = repo_path.to_str().unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&["init", repo], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "backup", source_path.to_str().unwrap()],
+        "test-password",
+    );
+    assert!(success, "Backup should succeed: {}", stderr);
 
-    // Init both repos
-    let _ = run_ghostsnap_with_password(&["init", repo1_path.to_str().unwrap()], "test-password");
-    let _ = run_ghostsnap_with_password(&["init", repo2_path.to_str().unwrap()], "test-password");
+    // A symlink in place of a restored file must be replaced, not followed
+    let victim = temp.path().join("victim.txt");
+    fs::write(&victim, b"untouched").unwrap();
+    fs::create_dir_all(restore_path.join("www")).unwrap();
+    symlink(&victim, restore_path.join("www/index.php")).unwrap();
 
-    // Backup to repo1
-    let _ = run_ghostsnap_with_password(
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
         &[
             "--repo",
-            repo1_path.to_str().unwrap(),
+            repo,
+            "restore",
+            "latest",
+            "--target",
+            restore_path.to_str().unwrap(),
+            "--overwrite",
+        ],
+        "test-password",
+    );
+    assert!(success, "Restore should succeed: {}", stderr);
+
+    assert_eq!(
+        fs::read_link(restore_path.join("current")).unwrap(),
+        Path::new("www")
+    );
+    assert_eq!(
+        fs::read_link(restore_path.join("dangling")).unwrap(),
+        Path::new("/nonexistent/ghostsnap")
+    );
+    let index = restore_path.join("www/index.php");
+    assert!(fs::symlink_metadata(&index).unwrap().is_file());
+    assert_eq!(fs::read(&index).unwrap(), b"<?php echo 1;");
+    assert_eq!(fs::read(&victim).unwrap(), b"untouched");
+
+    // Nor is a symlinked directory in the target, like www -> /etc, followed:
+    // --overwrite replaces it, and without it nothing is written below it
+    let elsewhere = temp.path().join("elsewhere");
+    fs::create_dir_all(&elsewhere).unwrap();
+    for overwrite in [true, false] {
+        let target = temp.path().join(format!("linked-{}", overwrite));
+        fs::create_dir_all(&target).unwrap();
+        symlink(&elsewhere, target.join("www")).unwrap();
+
+        let mut args = vec![
+            "--repo",
+            repo,
+            "restore",
+            "latest",
+            "--target",
+            target.to_str().unwrap(),
+            "--no-preflight",
+        ];
+        if overwrite {
+            args.push("--overwrite");
+        }
+        let (success, stdout, stderr) = run_ghostsnap_with_password(&args, "test-password");
+        assert!(success, "Restore should succeed: {}", stderr);
+
+        let www = fs::symlink_metadata(target.join("www")).unwrap();
+        if overwrite {
+            assert!(www.is_dir());
+            assert_eq!(
+                fs::read(target.join("www/index.php")).unwrap(),
+                b"<?php echo 1;"
+            );
+        } else {
+            assert!(www.is_symlink());
+            assert!(stdout.contains("Failed: 1"), "stdout: {}", stdout);
+        }
+        assert_eq!(fs::read_dir(&elsewhere).unwrap().count(), 0);
+    }
+}
+
+/// With --follow-symlinks, linked directories are backed up as their
+/// contents, and symlink loops are reported and skipped instead of walked.
+#[cfg(unix)]
+#[test]
+fn test_cli_follow_symlinks_backup() {
+    use std::os::unix::fs::symlink;
+
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let source_path = temp.path().join("source");
+    let restore_path = temp.path().join("restore");
+    fs::create_dir_all(source_path.join("real")).unwrap();
+    fs::write(source_path.join("real/file.txt"), b"followed").unwrap();
+    symlink("real", source_path.join("linked")).unwrap();
+    symlink("..", source_path.join("real/loop")).unwrap();
+
+    let repo = repo_path.to_str().unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&["init", repo], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
             "backup",
             source_path.to_str().unwrap(),
+            "--follow-symlinks",
         ],
         "test-password",
     );
+    assert!(success, "Backup should succeed: {}", stderr);
+    // real/loop and linked/loop both lead back to the source
+    assert!(
+        stderr.contains("Skipping symlink loop at"),
+        "stderr: {}",
+        stderr
+    );
+    assert!(stdout.contains("Failed: 2"), "stdout: {}", stdout);
 
-    // Get snapshot ID - look for 8-char hex string
-    let (_, stdout, _) = run_ghostsnap_with_password(
-        &["--repo", repo1_path.to_str().unwrap(), "snapshots"],
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
+            "restore",
+            "latest",
+            "--target",
+            restore_path.to_str().unwrap(),
+        ],
         "test-password",
     );
-    let snapshot_id = stdout
-        .lines()
-        .find_map(|line| {
-            let trimmed = line.trim();
-            if trimmed.len() >= 8 {
-                let first_word = trimmed.split_whitespace().next()?;
-                if first_word.len() == 8 && first_word.chars().all(|c| c.is_ascii_hexdigit()) {
-                    return Some(first_word.to_string());
-                }
-            }
-            None
-        })
-        .expect("Should have snapshot");
+    assert!(success, "Restore should succeed: {}", stderr);
+
+    let linked = fs::symlink_metadata(restore_path.join("linked")).unwrap();
+    assert!(linked.is_dir());
+    assert_eq!(
+        fs::read(restore_path.join("linked/file.txt")).unwrap(),
+        b"followed"
+    );
+    assert_eq!(
+        fs::read(restore_path.join("real/file.txt")).unwrap(),
+        b"followed"
+    );
+    assert!(fs::symlink_metadata(restore_path.join("real/loop")).is_err());
+}
+
+/// Hardlinked files are stored once and restored as links to one inode.
+#[cfg(unix)]
+#[test]
+fn test_cli_hardlink_backup_restore() {
+    use std::os::unix::fs::MetadataExt;
+
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let source_path = temp.path().join("source");
+    let restore_path = temp.path().join("restore");
+    fs::create_dir_all(source_path.join("b")).unwrap();
+    fs::write(source_path.join("a.txt"), b"shared contents").unwrap();
+    fs::hard_link(source_path.join("a.txt"), source_path.join("b/c.txt")).unwrap();
+
+    let repo = repo_path.to_str().unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&["init", repo], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "backup", source_path.to_str().unwrap()],
+        "test-password",
+    );
+    assert!(success, "Backup should succeed: {}", stderr);
+    assert!(stdout.contains("Hardlinks: 1"), "{}", stdout);
 
-    // Copy: ghostsnap --repo /repo1 copy --repo2 /repo2 --password2 <password> <snapshot-id>
     let (success, _stdout, stderr) = run_ghostsnap_with_password(
         &[
             "--repo",
-            repo1_path.to_str().unwrap(),
-            "copy",
-            "--repo2",
-            repo2_path.to_str().unwrap(),
-            "--password2",
-            "test-password",
-            &snapshot_id,
+            repo,
+            "restore",
+            "latest",
+            "--target",
+            restore_path.to_str().unwrap(),
         ],
         "test-password",
     );
-    assert!(success, "Copy should succeed: {}", stderr);
+    assert!(success, "Restore should succeed: {}", stderr);
+    let a = fs::metadata(restore_path.join("a.txt")).unwrap();
+    let c = fs::metadata(restore_path.join("b/c.txt")).unwrap();
+    assert_eq!(a.ino(), c.ino());
+    assert_eq!(a.nlink(), 2);
+    assert_eq!(
+        fs::read(restore_path.join("b/c.txt")).unwrap(),
+        b"shared contents"
+    );
+}
 
-    // Verify snapshot exists in repo2
-    let (success, stdout, _) = run_ghostsnap_with_password(
-        &["--repo", repo2_path.to_str().unwrap(), "snapshots"],
+/// Local repositories default to packs large enough for a small backup;
+/// `--pack-size` cuts smaller ones and rejects sizes out of range.
+#[test]
+fn test_cli_backup_pack_size() {
+    let temp = tempdir().unwrap();
+    let source_path = temp.path().join("source");
+    fs::create_dir_all(&source_path).unwrap();
+    // Incompressible, so each file fills more than a 1M pack
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    for name in ["a.bin", "b.bin"] {
+        let data: Vec<u8> = (0..1536 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        fs::write(source_path.join(name), data).unwrap();
+    }
+
+    let count_packs = |repo: &Path| {
+        let mut packs = 0;
+        for shard in fs::read_dir(repo.join("data")).unwrap() {
+            let shard = shard.unwrap().path();
+            if shard.is_dir() {
+                packs += fs::read_dir(&shard)
+                    .unwrap()
+                    .filter(|entry| {
+                        let path = entry.as_ref().unwrap().path();
+                        path.extension().is_some_and(|ext| ext == "pack")
+                    })
+                    .count();
+            }
+        }
+        packs
+    };
+
+    for (name, pack_size, expected) in [("default", None, 1), ("small", Some("1M"), 2)] {
+        let repo_path = temp.path().join(name);
+        let repo = repo_path.to_str().unwrap();
+        let (success, _stdout, stderr) =
+            run_ghostsnap_with_password(&["init", repo], "test-password");
+        assert!(success, "Init should succeed: {}", stderr);
+
+        let mut args = vec!["--repo", repo, "backup", source_path.to_str().unwrap()];
+        if let Some(size) = pack_size {
+            args.extend(["--pack-size", size]);
+        }
+        let (success, _stdout, stderr) = run_ghostsnap_with_password(&args, "test-password");
+        assert!(success, "Backup should succeed: {}", stderr);
+        assert_eq!(count_packs(&repo_path), expected, "{} packs", name);
+    }
+
+    let repo = temp.path().join("default");
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo.to_str().unwrap(),
+            "backup",
+            source_path.to_str().unwrap(),
+            "--pack-size",
+            "64K",
+        ],
         "test-password",
     );
-    assert!(success, "Snapshots in repo2 should succeed");
-    assert!(
-        stdout.contains(&snapshot_id),
-        "Repo2 should contain copied snapshot"
-    );
+    assert!(!success);
+    assert!(stderr.contains("Invalid pack size 64K"), "{}", stderr);
 }
 
+/// Tarballs are imported as snapshots dated from their file names, with the
+/// decrypt command's output as the archive.
+#[cfg(unix)]
 #[test]
-fn test_cli_env_var_repo() {
+fn test_cli_import_tarball() {
     let temp = tempdir().unwrap();
     let repo_path = temp.path().join("repo");
+    let source_path = temp.path().join("site");
+    fs::create_dir_all(source_path.join("public")).unwrap();
+    fs::write(source_path.join("public/index.html"), b"<h1>2024</h1>").unwrap();
 
-    // Init repo first
-    let _ = run_ghostsnap_with_password(&["init", repo_path.to_str().unwrap()], "test-password");
+    let archive = temp.path().join("admin.2024-01-15_05-10-03.tar");
+    let status = Command::new("tar")
+        .arg("-cf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(temp.path())
+        .arg("site")
+        .status()
+        .unwrap();
+    assert!(status.success(), "tar should succeed");
+
+    let repo = repo_path.to_str().unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&["init", repo], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
+            "import",
+            "--decrypt-cmd",
+            "cat",
+            archive.to_str().unwrap(),
+        ],
+        "test-password",
+    );
+    assert!(success, "Import should succeed: {}", stderr);
+    assert!(stdout.contains("Imported 1 snapshots"), "{}", stdout);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "snapshots", "--format", "json"],
+        "test-password",
+    );
+    assert!(success, "Snapshots should succeed: {}", stderr);
+    let snapshots: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(snapshots[0]["time"], "2024-01-15T05:10:03Z");
+    let snapshot_id = snapshots[0]["id"].as_str().unwrap();
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
+            "dump",
+            snapshot_id,
+            "site/public/index.html",
+        ],
+        "test-password",
+    );
+    assert!(success, "Dump should succeed: {}", stderr);
+    assert_eq!(stdout, "<h1>2024</h1>");
+
+    // The archive imported already is only verified; both are deleted
+    let newer = temp.path().join("admin.2024-01-16_05-10-03.tar");
+    fs::copy(&archive, &newer).unwrap();
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
+            "import",
+            "--remove-archives",
+            archive.to_str().unwrap(),
+            newer.to_str().unwrap(),
+        ],
+        "test-password",
+    );
+    assert!(success, "Import should succeed: {}", stderr);
+    assert!(
+        stdout.contains("Already imported admin.2024-01-15_05-10-03.tar"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("Imported 1 snapshots"), "{}", stdout);
+    assert!(!archive.exists());
+    assert!(!newer.exists());
+}
+
+/// Writes a Hestia-style tarball `name` in `dir` holding `site/index.html`.
+#[cfg(unix)]
+fn hestia_tarball(dir: &Path, name: &str, contents: &str) -> PathBuf {
+    let site = dir.join("site");
+    fs::create_dir_all(&site).unwrap();
+    fs::write(site.join("index.html"), contents).unwrap();
+    let archive = dir.join(name);
+    let status = Command::new("tar")
+        .arg("-cf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(dir)
+        .arg("site")
+        .status()
+        .unwrap();
+    assert!(status.success(), "tar should succeed");
+    archive
+}
+
+/// Hestia names tarballs alike on every server: another host's snapshot of
+/// the same name and time does not make an archive count as imported.
+#[cfg(unix)]
+#[test]
+fn test_cli_import_remove_archives_other_host() {
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let repo = repo_path.to_str().unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&["init", repo], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+
+    let name = "admin.2024-01-15_05-10-03.tar";
+    let host_a = temp.path().join("a");
+    let host_b = temp.path().join("b");
+    let archive_a = hestia_tarball(&host_a, name, "server a");
+    let archive_b = hestia_tarball(&host_b, name, "server b");
+
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
+            "import",
+            "--hostname",
+            "server-a",
+            archive_a.to_str().unwrap(),
+        ],
+        "test-password",
+    );
+    assert!(success, "Import should succeed: {}", stderr);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
+            "import",
+            "--hostname",
+            "server-b",
+            "--remove-archives",
+            archive_b.to_str().unwrap(),
+        ],
+        "test-password",
+    );
+    assert!(success, "Import should succeed: {}", stderr);
+    assert!(!stdout.contains("Already imported"), "{}", stdout);
+    assert!(stdout.contains("Imported 1 snapshots"), "{}", stdout);
+    assert!(!archive_b.exists());
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
+            "snapshots",
+            "--host",
+            "server-b",
+            "--format",
+            "json",
+        ],
+        "test-password",
+    );
+    assert!(success, "Snapshots should succeed: {}", stderr);
+    let snapshots: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let snapshot_id = snapshots[0]["id"].as_str().unwrap();
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "dump", snapshot_id, "site/index.html"],
+        "test-password",
+    );
+    assert!(success, "Dump should succeed: {}", stderr);
+    assert_eq!(stdout, "server b");
+}
+
+/// An archive whose snapshot fails verification is kept.
+#[cfg(unix)]
+#[test]
+fn test_cli_import_remove_archives_verification_fails() {
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let repo = repo_path.to_str().unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&["init", repo], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+
+    let archive = hestia_tarball(temp.path(), "admin.2024-01-15_05-10-03.tar", "only copy");
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "import", archive.to_str().unwrap()],
+        "test-password",
+    );
+    assert!(success, "Import should succeed: {}", stderr);
+
+    // Lose the snapshot's data
+    for entry in walkdir::WalkDir::new(repo_path.join("data")) {
+        let path = entry.unwrap().into_path();
+        if path.extension().is_some_and(|ext| ext == "pack") {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
+            "import",
+            "--remove-archives",
+            archive.to_str().unwrap(),
+        ],
+        "test-password",
+    );
+    assert!(!success, "Import should fail");
+    assert!(stderr.contains("keeping the archive"), "{}", stderr);
+    assert!(archive.exists());
+}
+
+/// A signed manifest is written next to the backup and verifies with the
+/// public key alone; any change to it breaks the signature.
+#[test]
+fn test_cli_backup_signed_manifest() {
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let source_path = temp.path().join("source");
+    let catalog_path = temp.path().join("catalog");
+    let key_path = temp.path().join("manifest.key");
+    fs::create_dir_all(&source_path).unwrap();
+    fs::create_dir_all(&catalog_path).unwrap();
+    fs::write(source_path.join("report.txt"), b"quarterly numbers").unwrap();
+
+    let (success, stdout, stderr) =
+        run_ghostsnap(&["manifest", "keygen", key_path.to_str().unwrap()]);
+    assert!(success, "Keygen should succeed: {}", stderr);
+    let public_key = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("Public key: "))
+        .unwrap()
+        .to_string();
+
+    let repo = repo_path.to_str().unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&["init", repo], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
+            "backup",
+            source_path.to_str().unwrap(),
+            "--manifest",
+            catalog_path.to_str().unwrap(),
+            "--manifest-key",
+            key_path.to_str().unwrap(),
+        ],
+        "test-password",
+    );
+    assert!(success, "Backup should succeed: {}", stderr);
+    assert!(stdout.contains("(signed)"), "{}", stdout);
+
+    let manifest_path = fs::read_dir(&catalog_path)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .expect("manifest should be written");
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&fs::read(&manifest_path).unwrap()).unwrap();
+    let report = manifest["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|f| f["path"].as_str().unwrap().ends_with("report.txt"))
+        .unwrap();
+    assert_eq!(
+        report["blake3"],
+        blake3::hash(b"quarterly numbers").to_hex().as_str()
+    );
+
+    let manifest_arg = manifest_path.to_str().unwrap();
+    let (success, stdout, stderr) = run_ghostsnap(&[
+        "manifest",
+        "verify",
+        manifest_arg,
+        "--public-key",
+        &public_key,
+    ]);
+    assert!(success, "Verify should succeed: {}", stderr);
+    assert!(stdout.contains("Signature OK"), "{}", stdout);
+
+    let tampered = fs::read_to_string(&manifest_path)
+        .unwrap()
+        .replace("report.txt", "rep0rt.txt");
+    fs::write(&manifest_path, tampered).unwrap();
+    let (success, _stdout, _stderr) = run_ghostsnap(&[
+        "manifest",
+        "verify",
+        manifest_arg,
+        "--public-key",
+        &public_key,
+    ]);
+    assert!(!success, "Verify should reject a modified manifest");
+}
+
+/// A missing manifest key or an unwritable manifest destination fails the
+/// backup before a snapshot is saved.
+#[test]
+fn test_cli_backup_manifest_checked_first() {
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let source_path = temp.path().join("source");
+    fs::create_dir_all(&source_path).unwrap();
+    fs::write(source_path.join("report.txt"), b"quarterly numbers").unwrap();
+    let repo = repo_path.to_str().unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&["init", repo], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+
+    let missing_key = temp.path().join("missing.key");
+    let missing_dir = temp.path().join("no-such-dir/manifest.json");
+    for extra in [
+        vec![
+            "--manifest",
+            temp.path().to_str().unwrap(),
+            "--manifest-key",
+            missing_key.to_str().unwrap(),
+        ],
+        vec!["--manifest", missing_dir.to_str().unwrap()],
+    ] {
+        let mut args = vec!["--repo", repo, "backup", source_path.to_str().unwrap()];
+        args.extend(extra);
+        let (success, _stdout, stderr) = run_ghostsnap_with_password(&args, "test-password");
+        assert!(!success, "Backup should fail");
+        assert!(stderr.contains("manifest"), "{}", stderr);
+    }
+
+    let (success, stdout, stderr) =
+        run_ghostsnap_with_password(&["--repo", repo, "snapshots"], "test-password");
+    assert!(success, "{}", stderr);
+    assert!(stdout.contains("No snapshots found"), "{}", stdout);
+}
+
+/// grep streams file contents out of a snapshot and reports binary files
+/// without printing them.
+#[test]
+fn test_cli_grep() {
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let source_path = temp.path().join("source");
+    fs::create_dir_all(source_path.join("nginx")).unwrap();
+    fs::write(
+        source_path.join("nginx/site.conf"),
+        b"server {\n    listen 8080;\n    server_name example.org;\n}",
+    )
+    .unwrap();
+    fs::write(source_path.join("blob.bin"), b"\0\0listen 8080\0").unwrap();
+
+    let repo = repo_path.to_str().unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&["init", repo], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "backup", source_path.to_str().unwrap()],
+        "test-password",
+    );
+    assert!(success, "Backup should succeed: {}", stderr);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "snapshots", "--format", "json"],
+        "test-password",
+    );
+    assert!(success, "Snapshots should succeed: {}", stderr);
+    let snapshots: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let snapshot_id = snapshots[0]["id"].as_str().unwrap();
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "grep", "-n", snapshot_id, "listen \\d+"],
+        "test-password",
+    );
+    assert!(success, "Grep should succeed: {}", stderr);
+    assert!(
+        stdout
+            .lines()
+            .any(|l| l.ends_with("nginx/site.conf:2:    listen 8080;")),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout
+            .lines()
+            .any(|l| l.starts_with("Binary file") && l.contains("blob.bin")),
+        "{}",
+        stdout
+    );
+
+    // The last line has no trailing newline
+    let (success, stdout, _stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "grep", "-l", snapshot_id, "^}$"],
+        "test-password",
+    );
+    assert!(success);
+    assert!(stdout.trim().ends_with("nginx/site.conf"), "{}", stdout);
+
+    let (success, _stdout, _stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "grep", snapshot_id, "not present anywhere"],
+        "test-password",
+    );
+    assert!(!success, "Grep without matches should fail");
+}
+
+#[test]
+fn test_cli_forget_and_prune() {
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let source_path = temp.path().join("source");
+    fs::create_dir_all(&source_path).unwrap();
+
+    // Create test file
+    let test_file = source_path.join("data.txt");
+    let mut file = File::create(&test_file).unwrap();
+    file.write_all(b"Some data for backup").unwrap();
+
+    // Init and backup
+    let _ = run_ghostsnap_with_password(&["init", repo_path.to_str().unwrap()], "test-password");
+    let _ = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo_path.to_str().unwrap(),
+            "backup",
+            source_path.to_str().unwrap(),
+        ],
+        "test-password",
+    );
+
+    // Test forget: ghostsnap --repo /path forget --keep-last 1
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo_path.to_str().unwrap(),
+            "forget",
+            "--keep-last",
+            "1",
+        ],
+        "test-password",
+    );
+    assert!(success, "Forget should succeed: {}", stderr);
+
+    // Test prune: ghostsnap --repo /path prune
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo_path.to_str().unwrap(), "prune"],
+        "test-password",
+    );
+    assert!(success, "Prune should succeed: {}", stderr);
+}
+
+/// Counts the pack files under the repository's `data/` directory.
+fn count_packs(repo_path: &Path) -> usize {
+    fn walk(dir: &Path) -> usize {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .map(|path| {
+                if path.is_dir() {
+                    walk(&path)
+                } else {
+                    usize::from(path.extension().is_some_and(|ext| ext == "pack"))
+                }
+            })
+            .sum()
+    }
+    walk(&repo_path.join("data"))
+}
+
+/// A standalone backup writes its own copies of chunks the repository
+/// already has without moving the index off the originals; prune keeps them
+/// until the snapshot is forgotten and then deletes them.
+#[test]
+fn test_cli_standalone_forget_prune_check() {
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let source_path = temp.path().join("source");
+    let restore_path = temp.path().join("restore");
+    fs::create_dir_all(&source_path).unwrap();
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    fs::write(source_path.join("data.bin"), &data).unwrap();
+
+    let repo = repo_path.to_str().unwrap();
+    let source = source_path.to_str().unwrap();
+    let run = |args: &[&str]| {
+        let (success, stdout, stderr) = run_ghostsnap_with_password(args, "test-password");
+        assert!(success, "{:?} should succeed: {}", args, stderr);
+        stdout
+    };
+
+    run(&["init", repo]);
+    run(&["--repo", repo, "backup", source]);
+    let packs = count_packs(&repo_path);
+    // Older than the first snapshot, so that keep-last forgets it
+    run(&[
+        "--repo",
+        repo,
+        "backup",
+        source,
+        "--standalone",
+        "--time",
+        "2020-01-01T00:00:00Z",
+    ]);
+    assert!(count_packs(&repo_path) > packs);
+
+    let stdout = run(&["--repo", repo, "check", "--check-unused"]);
+    assert!(stdout.contains("Repository is healthy!"), "{}", stdout);
+    let stdout = run(&["--repo", repo, "prune"]);
+    assert!(stdout.contains("No unused data to prune"), "{}", stdout);
+    assert!(count_packs(&repo_path) > packs);
+
+    run(&["--repo", repo, "forget", "--keep-last", "1"]);
+    let stdout = run(&["--repo", repo, "prune"]);
+    assert!(stdout.contains("Prune completed!"), "{}", stdout);
+    assert_eq!(count_packs(&repo_path), packs);
+
+    let stdout = run(&["--repo", repo, "check", "--check-unused"]);
+    assert!(stdout.contains("Repository is healthy!"), "{}", stdout);
+    run(&[
+        "--repo",
+        repo,
+        "restore",
+        "latest",
+        "--target",
+        restore_path.to_str().unwrap(),
+    ]);
+    assert_eq!(fs::read(restore_path.join("data.bin")).unwrap(), data);
+}
+
+#[test]
+fn test_cli_global_dry_run() {
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let source_path = temp.path().join("source");
+    fs::create_dir_all(&source_path).unwrap();
+    fs::write(source_path.join("data.txt"), b"Some data for backup").unwrap();
+
+    let repo = repo_path.to_str().unwrap();
+    let _ = run_ghostsnap_with_password(&["init", repo], "test-password");
+    for _ in 0..2 {
+        let _ = run_ghostsnap_with_password(
+            &["--repo", repo, "backup", source_path.to_str().unwrap()],
+            "test-password",
+        );
+    }
+    let snapshot_count = || fs::read_dir(repo_path.join("snapshots")).unwrap().count();
+    assert_eq!(snapshot_count(), 2);
+
+    // --dry-run before the subcommand works like the command's own flag
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "--dry-run", "forget", "--keep-last", "1"],
+        "test-password",
+    );
+    assert!(success, "Dry-run forget should succeed: {}", stderr);
+    assert!(stdout.contains("Would delete:"), "stdout: {}", stdout);
+    assert!(stdout.contains("snapshots/"), "stdout: {}", stdout);
+    assert_eq!(snapshot_count(), 2, "Dry run must not delete snapshots");
+
+    let (success, stdout, stderr) =
+        run_ghostsnap_with_password(&["--repo", repo, "--dry-run", "prune"], "test-password");
+    assert!(success, "Dry-run prune should succeed: {}", stderr);
+    assert!(
+        stdout.contains("Dry run - no changes made"),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_cli_metadata_cache() {
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let source_path = temp.path().join("source");
+    fs::create_dir_all(&source_path).unwrap();
+    fs::write(source_path.join("data.txt"), b"Some data for backup").unwrap();
+
+    let repo = repo_path.to_str().unwrap();
+    let _ = run_ghostsnap_with_password(&["init", repo], "test-password");
+    let _ = run_ghostsnap_with_password(
+        &["--repo", repo, "backup", source_path.to_str().unwrap()],
+        "test-password",
+    );
+
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "--metadata-cache", "5m", "check"],
+        "test-password",
+    );
+    assert!(success, "Check should succeed: {}", stderr);
+    assert!(stderr.contains("API calls: "), "stderr: {}", stderr);
+    assert!(stderr.contains(" LIST"), "stderr: {}", stderr);
+
+    // Without the cache and --verbose there is no summary
+    let (success, _stdout, stderr) =
+        run_ghostsnap_with_password(&["--repo", repo, "snapshots"], "test-password");
+    assert!(success, "Snapshots should succeed: {}", stderr);
+    assert!(!stderr.contains("API calls: "), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_cli_copy_between_repos() {
+    let temp = tempdir().unwrap();
+    let repo1_path = temp.path().join("repo1");
+    let repo2_path = temp.path().join("repo2");
+    let source_path = temp.path().join("source");
+    fs::create_dir_all(&source_path).unwrap();
+
+    // Create test file
+    let test_file = source_path.join("copy-test.txt");
+    let mut file = File::create(&test_file).unwrap();
+    file.write_all(b"Data for copy test").unwrap();
+
+    // Init both repos
+    let _ = run_ghostsnap_with_password(&["init", repo1_path.to_str().unwrap()], "test-password");
+    let _ = run_ghostsnap_with_password(&["init", repo2_path.to_str().unwrap()], "test-password");
+
+    // Backup to repo1
+    let _ = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo1_path.to_str().unwrap(),
+            "backup",
+            source_path.to_str().unwrap(),
+        ],
+        "test-password",
+    );
+
+    // Get snapshot ID - look for 8-char hex string
+    let (_, stdout, _) = run_ghostsnap_with_password(
+        &["--repo", repo1_path.to_str().unwrap(), "snapshots"],
+        "test-password",
+    );
+    let snapshot_id = stdout
+        .lines()
+        .find_map(|line| {
+            let trimmed = line.trim();
+            if trimmed.len() >= 8 {
+                let first_word = trimmed.split_whitespace().next()?;
+                if first_word.len() == 8 && first_word.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Some(first_word.to_string());
+                }
+            }
+            None
+        })
+        .expect("Should have snapshot");
+
+    // Copy: ghostsnap --repo /repo1 copy --repo2 /repo2 --password2 <password> <snapshot-id>
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo1_path.to_str().unwrap(),
+            "copy",
+            "--repo2",
+            repo2_path.to_str().unwrap(),
+            "--password2",
+            "test-password",
+            &snapshot_id,
+        ],
+        "test-password",
+    );
+    assert!(success, "Copy should succeed: {}", stderr);
+
+    // Verify snapshot exists in repo2
+    let (success, stdout, _) = run_ghostsnap_with_password(
+        &["--repo", repo2_path.to_str().unwrap(), "snapshots"],
+        "test-password",
+    );
+    assert!(success, "Snapshots in repo2 should succeed");
+    assert!(
+        stdout.contains(&snapshot_id),
+        "Repo2 should contain copied snapshot"
+    );
+}
+
+/// Copying into a repository with another chunk ID algorithm stores the
+/// chunks under the IDs the destination computes itself.
+#[test]
+fn test_cli_copy_between_hash_algorithms() {
+    let temp = tempdir().unwrap();
+    let blake3_path = temp.path().join("blake3");
+    let sha256_path = temp.path().join("sha256");
+    let source_path = temp.path().join("source");
+    fs::create_dir_all(&source_path).unwrap();
+    let contents: Vec<u8> = (0..200_000u32).flat_map(|i| i.to_le_bytes()).collect();
+    fs::write(source_path.join("data.bin"), &contents).unwrap();
+    let blake3 = blake3_path.to_str().unwrap();
+    let sha256 = sha256_path.to_str().unwrap();
+
+    let (success, _, stderr) = run_ghostsnap_with_password(&["init", blake3], "test-password");
+    assert!(success, "{}", stderr);
+    let (success, _, stderr) =
+        run_ghostsnap_with_password(&["init", "--hash", "sha256", sha256], "test-password");
+    assert!(success, "{}", stderr);
+    let (success, _, stderr) = run_ghostsnap_with_password(
+        &["--repo", blake3, "backup", source_path.to_str().unwrap()],
+        "test-password",
+    );
+    assert!(success, "{}", stderr);
+
+    let (_, stdout, _) = run_ghostsnap_with_password(
+        &["--repo", blake3, "snapshots", "--format", "json"],
+        "test-password",
+    );
+    let snapshots: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let snapshot_id = snapshots[0]["id"].as_str().unwrap().to_string();
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            blake3,
+            "copy",
+            "--repo2",
+            sha256,
+            "--password2",
+            "test-password",
+            "--dry-run",
+            &snapshot_id,
+        ],
+        "test-password",
+    );
+    assert!(success, "{}", stderr);
+    assert!(stdout.contains("re-identified"), "{}", stdout);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            blake3,
+            "copy",
+            "--repo2",
+            sha256,
+            "--password2",
+            "test-password",
+            &snapshot_id,
+        ],
+        "test-password",
+    );
+    assert!(success, "Copy should succeed: {}", stderr);
+    assert!(
+        stdout.contains("chunks copied (0 already existed)"),
+        "{}",
+        stdout
+    );
+
+    let (success, stdout, stderr) =
+        run_ghostsnap_with_password(&["--repo", sha256, "check", "--read-data"], "test-password");
+    assert!(success, "check failed: {}{}", stdout, stderr);
+
+    let target = temp.path().join("restore");
+    let (success, _, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            sha256,
+            "restore",
+            &snapshot_id,
+            "--target",
+            target.to_str().unwrap(),
+        ],
+        "test-password",
+    );
+    assert!(success, "{}", stderr);
+    assert_eq!(fs::read(target.join("data.bin")).unwrap(), contents);
+
+    // Backing up the same data into the destination deduplicates against
+    // the copied chunks.
+    let packs = count_packs(&sha256_path);
+    let (success, _, stderr) = run_ghostsnap_with_password(
+        &["--repo", sha256, "backup", source_path.to_str().unwrap()],
+        "test-password",
+    );
+    assert!(success, "{}", stderr);
+    assert_eq!(count_packs(&sha256_path), packs);
+}
+
+#[test]
+fn test_cli_env_var_repo() {
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+
+    // Init repo first
+    let _ = run_ghostsnap_with_password(&["init", repo_path.to_str().unwrap()], "test-password");
 
     // Test using GHOSTSNAP_REPO env var instead of --repo flag
     let output = Command::new(ghostsnap_bin())
@@ -416,3 +1604,237 @@ fn test_cli_env_var_repo() {
         "Should work with GHOSTSNAP_REPO env var"
     );
 }
+
+/// The Hestia system snapshot keeps each service's configuration under its
+/// own path and skips services that aren't installed.
+#[test]
+fn test_cli_hestia_backup_system() {
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let root = temp.path().join("root");
+    for (path, contents) in [
+        ("usr/local/hestia/conf/hestia.conf", "BACKEND_PORT='8083'"),
+        ("etc/nginx/conf.d/default.conf", "server {}"),
+        ("etc/mysql/conf.d/mysqld.cnf", "[mysqld]"),
+    ] {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    let repo = repo_path.to_str().unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&["init", repo], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
+            "hestia",
+            "backup-system",
+            "--root",
+            root.to_str().unwrap(),
+        ],
+        "test-password",
+    );
+    assert!(success, "Hestia backup should succeed: {}", stderr);
+    assert!(!stdout.contains("apache2"), "{}", stdout);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &["--quiet", "--repo", repo, "snapshots", "--format", "json"],
+        "test-password",
+    );
+    assert!(success, "Snapshots should succeed: {}", stderr);
+    let snapshots: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(snapshots[0]["tags"], serde_json::json!(["hestia:system"]));
+    let snapshot_id = snapshots[0]["id"].as_str().unwrap();
+
+    for (path, contents) in [
+        ("etc/nginx/conf.d/default.conf", "server {}"),
+        ("etc/mysql/conf.d/mysqld.cnf", "[mysqld]"),
+        ("usr/local/hestia/conf/hestia.conf", "BACKEND_PORT='8083'"),
+    ] {
+        let (success, stdout, stderr) = run_ghostsnap_with_password(
+            &["--quiet", "--repo", repo, "dump", snapshot_id, path],
+            "test-password",
+        );
+        assert!(success, "Dump of {} should succeed: {}", path, stderr);
+        assert_eq!(stdout, contents);
+    }
+
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
+            "hestia",
+            "backup-system",
+            "--root",
+            temp.path().join("empty").to_str().unwrap(),
+        ],
+        "test-password",
+    );
+    assert!(!success);
+    assert!(
+        stderr.contains("No Hestia configuration found"),
+        "{}",
+        stderr
+    );
+}
+
+/// Mail backups read only newly delivered messages and leave out Dovecot's
+/// indexes; a single mailbox can be restored back into place.
+#[test]
+fn test_cli_hestia_mail() {
+    let temp = tempdir().unwrap();
+    let repo_path = temp.path().join("repo");
+    let home = temp.path().join("home");
+    let mailbox = home.join("alice/mail/example.com/info");
+    for (path, contents) in [
+        ("cur/1700000000.M1.host:2,S", "Subject: one"),
+        ("new/1700000001.M2.host", "Subject: two"),
+        ("tmp/1700000002.M3.host", "partial"),
+        ("dovecot.index.cache", "index"),
+    ] {
+        let path = mailbox.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    let repo = repo_path.to_str().unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&["init", repo], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+
+    let backup_mail = [
+        "--repo",
+        repo,
+        "hestia",
+        "backup-mail",
+        "alice",
+        "--home",
+        home.to_str().unwrap(),
+    ];
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(&backup_mail, "test-password");
+    assert!(success, "Mail backup should succeed: {}", stderr);
+    let (success, stdout, stderr) = run_ghostsnap_with_password(&backup_mail, "test-password");
+    assert!(success, "Second mail backup should succeed: {}", stderr);
+    assert!(stdout.contains("Unchanged (not read): 2"), "{}", stdout);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &["--quiet", "--repo", repo, "snapshots", "--format", "json"],
+        "test-password",
+    );
+    assert!(success, "Snapshots should succeed: {}", stderr);
+    let snapshots: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(
+        snapshots[0]["tags"],
+        serde_json::json!(["hestia:mail", "user:alice"])
+    );
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "hestia", "verify", "alice"],
+        "test-password",
+    );
+    assert!(success, "Verify should succeed: {}", stderr);
+    assert!(stdout.contains("hestia:mail: snapshot"), "{}", stdout);
+    assert!(stdout.contains("Drill: PASS"), "{}", stdout);
+
+    let (success, stdout, _stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "hestia", "verify", "alice", "bob"],
+        "test-password",
+    );
+    assert!(!success);
+    assert!(
+        stdout.contains("No Hestia snapshots tagged user:bob"),
+        "{}",
+        stdout
+    );
+
+    fs::remove_dir_all(&mailbox).unwrap();
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
+            "hestia",
+            "restore-mail",
+            "alice",
+            "info@example.com",
+            "--home",
+            home.to_str().unwrap(),
+        ],
+        "test-password",
+    );
+    assert!(success, "Mailbox restore should succeed: {}", stderr);
+    assert_eq!(
+        fs::read_to_string(mailbox.join("cur/1700000000.M1.host:2,S")).unwrap(),
+        "Subject: one"
+    );
+    assert_eq!(
+        fs::read_to_string(mailbox.join("new/1700000001.M2.host")).unwrap(),
+        "Subject: two"
+    );
+    assert!(mailbox.join("tmp").is_dir());
+    assert!(!mailbox.join("tmp/1700000002.M3.host").exists());
+    assert!(!mailbox.join("dovecot.index.cache").exists());
+}
+
+#[test]
+fn test_cli_restic_fixture() {
+    // Generated from restic's design document, see
+    // core/tests/fixtures/restic/generate.py
+    let repo = Path::new(env!("CARGO_MANIFEST_DIR")).join("../core/tests/fixtures/restic/v2");
+    let repo = repo.to_str().unwrap();
+
+    let (success, stdout, stderr) =
+        run_ghostsnap_with_password(&["--repo", repo, "restic", "snapshots"], "fixture-password");
+    assert!(success, "restic snapshots failed: {}", stderr);
+    assert!(stdout.contains("01624333"), "{}", stdout);
+    assert!(stdout.contains("2024-03-01 11:00:00"), "{}", stdout);
+    assert!(stdout.contains("fixture-host"), "{}", stdout);
+    assert!(stdout.contains("/home/alice"), "{}", stdout);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo, "restic", "ls", "01624333", "home/alice"],
+        "fixture-password",
+    );
+    assert!(success, "restic ls failed: {}", stderr);
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        vec![
+            "home/alice/",
+            "home/alice/big.bin",
+            "home/alice/docs/",
+            "home/alice/docs/note.md",
+            "home/alice/hello.txt",
+            "home/alice/link -> hello.txt",
+        ]
+    );
+
+    let target = tempdir().unwrap();
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "--repo",
+            repo,
+            "restic",
+            "restore",
+            "01624333",
+            "-t",
+            target.path().to_str().unwrap(),
+            "home/alice/big.bin",
+        ],
+        "fixture-password",
+    );
+    assert!(success, "restic restore failed: {}", stderr);
+    assert!(stdout.contains("Restored: 1"), "{}", stdout);
+    let expected: Vec<u8> = (0..5000usize)
+        .map(|i| ((i * 7 + i / 251) % 256) as u8)
+        .collect();
+    assert_eq!(
+        fs::read(target.path().join("home/alice/big.bin")).unwrap(),
+        expected
+    );
+    assert!(!target.path().join("home/alice/hello.txt").exists());
+
+    let (success, _stdout, _stderr) =
+        run_ghostsnap_with_password(&["--repo", repo, "restic", "snapshots"], "wrong-password");
+    assert!(!success);
+}
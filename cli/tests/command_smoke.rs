@@ -8,10 +8,9 @@ use std::io::Write;
 use std::path::Path;
 use tempfile::tempdir;
 
-use ghostsnap_core::chunker::Chunker;
 use ghostsnap_core::pack::PackManager;
 use ghostsnap_core::snapshot::{Snapshot, Tree};
-use ghostsnap_core::{ChunkRef, NodeType, Repository, TreeNode};
+use ghostsnap_core::{ChunkRef, NodeType, Repository, SnapshotID, TreeNode};
 
 /// Helper to create a test file with given contents.
 fn create_test_file<P: AsRef<Path>>(path: P, contents: &[u8]) {
@@ -23,10 +22,10 @@ fn create_test_file<P: AsRef<Path>>(path: P, contents: &[u8]) {
 }
 
 /// Performs a backup of a source directory to a repository.
-async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<String> {
+async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<SnapshotID> {
     use walkdir::WalkDir;
 
-    let chunker = Chunker::new_default();
+    let chunker = repo.chunker();
     let mut pack_manager = PackManager::new(64 * 1024 * 1024);
     let mut tree = Tree::new();
 
@@ -101,6 +100,7 @@ async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<String>
             inode: None,
             nlink: None,
             hardlink_target: None,
+            device: None,
         });
     }
 
@@ -5,11 +5,15 @@ use std::io::Write;
 use std::path::Path;
 use tempfile::tempdir;
 
-use ghostsnap_core::chunker::Chunker;
+use ghostsnap_core::layout::{MARKER_PATH, RepositoryMarker};
 use ghostsnap_core::pack::PackManager;
 use ghostsnap_core::snapshot::{Snapshot, Tree};
 use ghostsnap_core::storage::RepositoryLocation;
-use ghostsnap_core::{ChunkRef, NodeType, RepoTransport, Repository, S3RepoSse, TreeNode};
+use ghostsnap_core::{
+    ChunkRef, EncryptionLayer, EncryptionMode, KeyExport, NodeType, PasswordKey, PolicyScope,
+    RecoveryCode, RepoSettings, RepoTransport, Repository, RetentionPolicy, RetentionRules,
+    S3RepoSse, SnapshotID, TreeNode,
+};
 
 /// Helper to create a test file with given contents.
 fn create_test_file<P: AsRef<Path>>(path: P, contents: &[u8]) {
@@ -28,10 +32,10 @@ fn assert_files_equal<P1: AsRef<Path>, P2: AsRef<Path>>(path1: P1, path2: P2) {
 }
 
 /// Performs a backup of a source directory to a repository.
-async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<String> {
+async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<SnapshotID> {
     use walkdir::WalkDir;
 
-    let chunker = Chunker::new_default();
+    let chunker = repo.chunker();
     let mut pack_manager = PackManager::new(64 * 1024 * 1024);
     let mut tree = Tree::new();
 
@@ -114,6 +118,7 @@ async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<String>
             inode: None,
             nlink: None,
             hardlink_target: None,
+            device: None,
         });
     }
 
@@ -137,10 +142,10 @@ async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<String>
 /// Restores a snapshot to a target directory.
 async fn restore_snapshot(
     repo: &Repository,
-    snapshot_id: &str,
+    snapshot_id: &SnapshotID,
     target: &Path,
 ) -> anyhow::Result<()> {
-    let snapshot = repo.load_snapshot(&snapshot_id.to_string()).await?;
+    let snapshot = repo.load_snapshot(snapshot_id).await?;
     let tree = repo.load_tree(&snapshot.tree).await?;
 
     fs::create_dir_all(target)?;
@@ -179,6 +184,7 @@ async fn restore_snapshot(
                     std::os::unix::fs::symlink(target_path, &dest)?;
                 }
             }
+            NodeType::CharDevice | NodeType::BlockDevice | NodeType::Fifo => {}
         }
     }
 
@@ -229,6 +235,444 @@ async fn test_basic_backup_restore() {
     );
 }
 
+/// Tests an unencrypted repository: data is stored in the clear but still
+/// checksummed, and no password is needed to open it.
+#[tokio::test]
+async fn test_unencrypted_repository() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+    let restore_dir = tempdir().unwrap();
+    let location = RepositoryLocation::Local(repo_dir.path().to_path_buf());
+
+    let repo = Repository::init_with_encryption(
+        location.clone(),
+        &PasswordKey::new(""),
+        EncryptionMode::None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(repo.encryption_mode(), EncryptionMode::None);
+    assert!(!repo_dir.path().join("keys").exists());
+
+    let config = Repository::read_config(&location).await.unwrap();
+    assert!(!config.encryption.is_encrypted());
+    assert_eq!(config.encryption_layer(), EncryptionLayer::Backend);
+
+    create_test_file(
+        source_dir.path().join("hello.txt"),
+        b"plaintext marker 0123456789",
+    );
+    let snapshot_id = backup_dir(&repo, source_dir.path()).await.unwrap();
+    drop(repo);
+
+    // Any password opens it
+    let repo = Repository::open(repo_dir.path(), "ignored").await.unwrap();
+    restore_snapshot(&repo, &snapshot_id, restore_dir.path())
+        .await
+        .unwrap();
+    assert_files_equal(
+        source_dir.path().join("hello.txt"),
+        restore_dir.path().join("hello.txt"),
+    );
+
+    // File contents are readable from the pack without a key; chunks are
+    // only compressed
+    let pack_path = walkdir::WalkDir::new(repo_dir.path().join("data"))
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .find(|e| e.path().extension().is_some_and(|ext| ext == "pack"))
+        .unwrap()
+        .into_path();
+    let bytes = fs::read(pack_path).unwrap();
+    let encryptor = ghostsnap_core::crypto::Encryptor::plaintext();
+    let pack = ghostsnap_core::PackFile::from_encrypted_bytes(&bytes, &encryptor).unwrap();
+    assert!(
+        pack.chunk_ids().iter().any(|id| {
+            let chunk = pack.get_chunk(id).unwrap();
+            chunk.windows(16).any(|w| w == b"plaintext marker")
+        }),
+        "unencrypted pack should contain the file data"
+    );
+}
+
+/// Tests that flipping an encrypted repository's plaintext config to
+/// `encryption: none` doesn't get it opened (and written) unencrypted.
+#[tokio::test]
+async fn test_tampered_config_not_opened_unencrypted() {
+    let repo_dir = tempdir().unwrap();
+    Repository::init(repo_dir.path(), "password").await.unwrap();
+
+    let config_path = repo_dir.path().join("config");
+    let mut config: serde_json::Value =
+        serde_json::from_slice(&fs::read(&config_path).unwrap()).unwrap();
+    config["encryption"] = "none".into();
+    fs::write(&config_path, serde_json::to_vec_pretty(&config).unwrap()).unwrap();
+
+    let err = Repository::open(repo_dir.path(), "password")
+        .await
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("keys/"), "{}", err);
+}
+
+/// Tests that init writes a plaintext marker naming the repository, and that
+/// clones carry it along with the repository's settings and retention policy.
+#[tokio::test]
+async fn test_repository_marker() {
+    let repo_dir = tempdir().unwrap();
+    let clone_dir = tempdir().unwrap();
+    let mut repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    let mut settings = RepoSettings::new();
+    settings.excludes = vec!["*.tmp".to_string()];
+    repo.save_settings(settings).await.unwrap();
+    let mut policy = RetentionPolicy::new();
+    policy.set_rules(
+        PolicyScope::Default,
+        RetentionRules {
+            keep_daily: Some(7),
+            ..Default::default()
+        },
+    );
+    repo.save_retention_policy(&policy).await.unwrap();
+
+    let marker: RepositoryMarker =
+        serde_json::from_slice(&fs::read(repo_dir.path().join(MARKER_PATH)).unwrap()).unwrap();
+    assert_eq!(marker.format, "ghostsnap");
+    assert_eq!(marker.id, repo.config().id);
+
+    let clone_path = clone_dir.path().join("clone");
+    repo.clone_to(&clone_path).await.unwrap();
+    assert!(clone_path.join(MARKER_PATH).exists());
+
+    let clone = Repository::open(&clone_path, "test-password")
+        .await
+        .unwrap();
+    assert_eq!(clone.settings().excludes, vec!["*.tmp"]);
+    let cloned = clone.load_retention_policy().await.unwrap().unwrap();
+    assert_eq!(
+        cloned.rules(&PolicyScope::Default).unwrap().keep_daily,
+        Some(7)
+    );
+}
+
+/// Tests that exported keys restore a repository that lost its config and
+/// keys, and that a recovery code sets a new password.
+#[tokio::test]
+async fn test_key_export_and_recovery() {
+    let repo_dir = tempdir().unwrap();
+    let location = RepositoryLocation::Local(repo_dir.path().to_path_buf());
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    repo.flush_index().await.unwrap();
+
+    let export = KeyExport::from_json(
+        repo.export_keys()
+            .await
+            .unwrap()
+            .to_json()
+            .unwrap()
+            .as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(export.keys.len(), 1);
+    let code = RecoveryCode::parse(&repo.recovery_code().await.unwrap().to_string()).unwrap();
+    drop(repo);
+
+    fs::remove_file(repo_dir.path().join("config")).unwrap();
+    fs::remove_dir_all(repo_dir.path().join("keys")).unwrap();
+    assert!(
+        Repository::open(repo_dir.path(), "test-password")
+            .await
+            .is_err()
+    );
+
+    assert_eq!(
+        Repository::import_keys(&location, &export).await.unwrap(),
+        1
+    );
+    assert_eq!(
+        Repository::import_keys(&location, &export).await.unwrap(),
+        0
+    );
+    Repository::open(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    Repository::add_key_from_recovery_code(&location, &code, &PasswordKey::new("new-password"))
+        .await
+        .unwrap();
+    Repository::open(repo_dir.path(), "new-password")
+        .await
+        .unwrap();
+    Repository::open(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    assert!(Repository::open(repo_dir.path(), "wrong").await.is_err());
+
+    // A code from another repository is rejected before a key is written
+    let other_dir = tempdir().unwrap();
+    let other = Repository::init(other_dir.path(), "test-password")
+        .await
+        .unwrap();
+    let other_code = other.recovery_code().await.unwrap();
+    let err = Repository::add_key_from_recovery_code(
+        &location,
+        &other_code,
+        &PasswordKey::new("other-password"),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, ghostsnap_core::Error::InvalidRecoveryCode(_)));
+    assert_eq!(
+        fs::read_dir(repo_dir.path().join("keys")).unwrap().count(),
+        2
+    );
+
+    // The export only fits the repository it came from
+    let other_export = other.export_keys().await.unwrap();
+    assert!(
+        Repository::import_keys(&location, &other_export)
+            .await
+            .is_err()
+    );
+}
+
+/// Tests that every key file is tried, and that unreadable key files are
+/// reported as such rather than as a wrong password.
+#[tokio::test]
+async fn test_corrupt_key_files() {
+    use ghostsnap_core::Error;
+
+    let repo_dir = tempdir().unwrap();
+    Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    let keys_dir = repo_dir.path().join("keys");
+    let key_path = fs::read_dir(&keys_dir)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+
+    // A damaged key file next to a good one doesn't get in the way
+    let damaged = keys_dir.join("00000000-0000-4000-8000-000000000000");
+    fs::write(&damaged, b"{\"encrypted_key\": [1, 2").unwrap();
+    Repository::open(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    assert!(matches!(
+        Repository::open(repo_dir.path(), "wrong").await,
+        Err(Error::InvalidPassword)
+    ));
+
+    // With no readable key file, the password can't be the problem
+    fs::remove_file(&key_path).unwrap();
+    let err = Repository::open(repo_dir.path(), "test-password")
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::CorruptKeyFile { ref name, .. } if damaged.ends_with(name)));
+}
+
+/// Tests that each password opens its own key, which is reported, and that
+/// key files record who added them.
+#[tokio::test]
+async fn test_multiple_keys() {
+    let repo_dir = tempdir().unwrap();
+    let location = RepositoryLocation::Local(repo_dir.path().to_path_buf());
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    let first = repo.key_name().unwrap().to_string();
+    let code = repo.recovery_code().await.unwrap();
+    drop(repo);
+
+    let second =
+        Repository::add_key_from_recovery_code(&location, &code, &PasswordKey::new("new-password"))
+            .await
+            .unwrap();
+    assert_ne!(first, second);
+
+    for (password, name) in [("test-password", &first), ("new-password", &second)] {
+        let repo = Repository::open(repo_dir.path(), password).await.unwrap();
+        assert_eq!(repo.key_name(), Some(name.as_str()));
+
+        let keys = repo.list_keys().await.unwrap();
+        assert_eq!(keys.len(), 2);
+        let current: Vec<_> = keys.iter().filter(|key| key.current).collect();
+        assert_eq!(current.len(), 1);
+        assert_eq!(&current[0].name, name);
+        assert!(
+            keys.iter()
+                .all(|key| key.created_at.is_some() && key.hostname.is_some())
+        );
+    }
+}
+
+/// Tests that `init --from-repo` copies settings, and with shared keys the
+/// data key and retention policy.
+#[tokio::test]
+async fn test_init_from_repository() {
+    let source_dir = tempdir().unwrap();
+    let source_location = RepositoryLocation::Local(source_dir.path().to_path_buf());
+    let source = Repository::init_with_encryption_layer(
+        source_location.clone(),
+        &PasswordKey::new("source-password"),
+        EncryptionLayer::Both,
+    )
+    .await
+    .unwrap();
+    let mut policy = RetentionPolicy::new();
+    policy.set_rules(
+        PolicyScope::Default,
+        RetentionRules {
+            keep_daily: Some(7),
+            ..Default::default()
+        },
+    );
+    source.save_retention_policy(&policy).await.unwrap();
+
+    let target_dir = tempdir().unwrap();
+    let target = Repository::init_from(
+        RepositoryLocation::Local(target_dir.path().to_path_buf()),
+        &PasswordKey::new("target-password"),
+        &source_location,
+        None,
+        false,
+    )
+    .await
+    .unwrap();
+    assert_ne!(target.config().id, source.config().id);
+    assert_eq!(
+        target.config().chunker_polynomial,
+        source.config().chunker_polynomial
+    );
+    assert_eq!(
+        target.config().kdf_params.memory,
+        source.config().kdf_params.memory
+    );
+    assert_ne!(
+        target.config().kdf_params.salt,
+        source.config().kdf_params.salt
+    );
+    assert_eq!(target.encryption_layer(), EncryptionLayer::Both);
+    assert!(target.load_retention_policy().await.unwrap().is_none());
+    assert_ne!(
+        target.recovery_code().await.unwrap(),
+        source.recovery_code().await.unwrap()
+    );
+
+    // Shared keys: the source password opens the copy, which uses the same
+    // data key and policy
+    let shared_dir = tempdir().unwrap();
+    let shared_location = RepositoryLocation::Local(shared_dir.path().to_path_buf());
+    assert!(
+        Repository::init_from(
+            shared_location.clone(),
+            &PasswordKey::new("wrong-password"),
+            &source_location,
+            None,
+            true,
+        )
+        .await
+        .is_err()
+    );
+    assert!(!shared_dir.path().join("config").exists());
+
+    Repository::init_from(
+        shared_location,
+        &PasswordKey::new("source-password"),
+        &source_location,
+        None,
+        true,
+    )
+    .await
+    .unwrap();
+    let shared = Repository::open(shared_dir.path(), "source-password")
+        .await
+        .unwrap();
+    assert_eq!(
+        shared.recovery_code().await.unwrap(),
+        source.recovery_code().await.unwrap()
+    );
+    assert_eq!(
+        shared
+            .load_retention_policy()
+            .await
+            .unwrap()
+            .unwrap()
+            .rules(&PolicyScope::Default),
+        policy.rules(&PolicyScope::Default)
+    );
+}
+
+/// Tests that the encryption layer is recorded and that configs without one
+/// fall back to a layer matching their encryption mode.
+#[tokio::test]
+async fn test_encryption_layer() {
+    let repo_dir = tempdir().unwrap();
+    let location = RepositoryLocation::Local(repo_dir.path().to_path_buf());
+
+    let repo = Repository::init_with_encryption_layer(
+        location.clone(),
+        &PasswordKey::new("test-password"),
+        EncryptionLayer::Both,
+    )
+    .await
+    .unwrap();
+    assert!(repo.encryption_mode().is_encrypted());
+    drop(repo);
+
+    let repo = Repository::open(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    assert_eq!(repo.encryption_layer(), EncryptionLayer::Both);
+
+    let mut config = Repository::read_config(&location).await.unwrap();
+    config.encryption_layer = None;
+    assert_eq!(config.encryption_layer(), EncryptionLayer::Repo);
+    assert_eq!("backend".parse(), Ok(EncryptionLayer::Backend));
+    assert!("disk".parse::<EncryptionLayer>().is_err());
+}
+
+/// Tests that a repository sealed with a keyfile needs both the password and
+/// the keyfile to open.
+#[tokio::test]
+async fn test_keyfile_unlock() {
+    let repo_dir = tempdir().unwrap();
+    let location = RepositoryLocation::Local(repo_dir.path().to_path_buf());
+    let keyfile = ghostsnap_core::crypto::generate_keyfile();
+
+    Repository::init_with_encryption(
+        location.clone(),
+        &PasswordKey::new("test-password").with_keyfile(keyfile.clone()),
+        EncryptionMode::default(),
+    )
+    .await
+    .unwrap();
+
+    let err = Repository::open(repo_dir.path(), "test-password")
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, ghostsnap_core::Error::KeyfileRequired));
+
+    let wrong =
+        PasswordKey::new("test-password").with_keyfile(ghostsnap_core::crypto::generate_keyfile());
+    let err = Repository::open_with_keys(location.clone(), &wrong)
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, ghostsnap_core::Error::InvalidPassword));
+
+    let right = PasswordKey::new("test-password").with_keyfile(keyfile);
+    Repository::open_with_keys(location, &right).await.unwrap();
+}
+
 /// Tests backup and restore of symlinks.
 #[tokio::test]
 #[cfg(unix)]
@@ -329,6 +773,14 @@ async fn test_deduplication() {
         stats1.chunk_count, stats2.chunk_count,
         "No new chunks should be created - deduplication should work"
     );
+
+    // Batched lookup agrees with per-chunk lookup
+    let known = repo.chunker().chunk_data(&large_data)[0].id();
+    let unknown = ghostsnap_core::ChunkID::from_data(b"never backed up");
+    assert_eq!(
+        repo.has_chunks(&[known, unknown, known]).await.unwrap(),
+        vec![true, false, true]
+    );
 }
 
 /// Tests large file backup/restore.
@@ -452,305 +904,2166 @@ async fn test_multiple_snapshots() {
     );
 }
 
-/// Tests empty directory handling.
+/// Tests self-contained vs referenced data reporting across a snapshot chain.
 #[tokio::test]
-async fn test_empty_directory() {
+async fn test_snapshot_chain_stats() {
     let repo_dir = tempdir().unwrap();
     let source_dir = tempdir().unwrap();
-    let restore_dir = tempdir().unwrap();
 
-    // Initialize repository
     let repo = Repository::init(repo_dir.path(), "test-password")
         .await
         .unwrap();
 
-    // Create empty subdirectory
-    fs::create_dir_all(source_dir.path().join("empty_dir")).unwrap();
-    create_test_file(source_dir.path().join("file.txt"), b"Content");
+    create_test_file(
+        source_dir.path().join("base.txt"),
+        b"Unchanged base content",
+    );
+    let snapshot1 = backup_dir(&repo, source_dir.path()).await.unwrap();
 
-    // Backup and restore
-    let snapshot_id = backup_dir(&repo, source_dir.path()).await.unwrap();
-    restore_snapshot(&repo, &snapshot_id, restore_dir.path())
+    create_test_file(source_dir.path().join("new.txt"), b"Added later");
+    let snapshot2 = backup_dir(&repo, source_dir.path()).await.unwrap();
+
+    let chain = repo.snapshot_chain_stats().await.unwrap();
+    assert_eq!(chain.len(), 2);
+
+    assert_eq!(chain[0].snapshot_id, snapshot1);
+    assert!(chain[0].is_full());
+    assert_eq!(chain[0].self_contained_bytes, 22);
+
+    assert_eq!(chain[1].snapshot_id, snapshot2);
+    assert!(!chain[1].is_full());
+    assert_eq!(chain[1].self_contained_bytes, 11);
+    assert_eq!(chain[1].referenced_bytes, 22);
+}
+
+/// Tests exporting a snapshot bundle from one repository and importing it into another.
+#[tokio::test]
+async fn test_bundle_export_import() {
+    use ghostsnap_core::SnapshotBundle;
+
+    let src_repo_dir = tempdir().unwrap();
+    let dst_repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+    let restore_dir = tempdir().unwrap();
+
+    let src_repo = Repository::init(src_repo_dir.path(), "source-password")
+        .await
+        .unwrap();
+    let dst_repo = Repository::init(dst_repo_dir.path(), "vault-password")
         .await
         .unwrap();
 
-    // Verify empty directory exists
-    assert!(
-        restore_dir.path().join("empty_dir").is_dir(),
-        "Empty directory should be restored"
+    create_test_file(source_dir.path().join("report.txt"), b"Quarterly report");
+    create_test_file(source_dir.path().join("nested/data.bin"), &[7u8; 4096]);
+    let snapshot_id = backup_dir(&src_repo, source_dir.path()).await.unwrap();
+
+    let bundle = src_repo.export_bundle(&snapshot_id).await.unwrap();
+    let bytes = bundle.to_bytes("bundle-password").unwrap();
+
+    let imported = SnapshotBundle::from_bytes(&bytes, "bundle-password").unwrap();
+    let stats = dst_repo.import_bundle(&imported).await.unwrap();
+    assert_eq!(stats.snapshot_id, snapshot_id);
+    assert_eq!(stats.chunks_imported, bundle.chunks.len());
+
+    restore_snapshot(&dst_repo, &stats.snapshot_id, restore_dir.path())
+        .await
+        .unwrap();
+    assert_files_equal(
+        source_dir.path().join("report.txt"),
+        restore_dir.path().join("report.txt"),
+    );
+    assert_files_equal(
+        source_dir.path().join("nested/data.bin"),
+        restore_dir.path().join("nested/data.bin"),
     );
 }
 
-#[test]
-fn test_repository_location_parse_local() {
-    let location = RepositoryLocation::parse("/backup/repo").unwrap();
-    match location {
-        RepositoryLocation::Local(path) => {
-            assert_eq!(path, std::path::PathBuf::from("/backup/repo"))
-        }
-        RepositoryLocation::S3(_) => panic!("expected local repository location"),
-        RepositoryLocation::Azure(_) => panic!("expected local repository location"),
-        RepositoryLocation::Rclone(_) => panic!("expected local repository location"),
-        RepositoryLocation::Sftp(_) => panic!("expected local repository location"),
-    }
-}
+/// Tests that the stats cache is built once and then updated incrementally.
+#[tokio::test]
+async fn test_stats_cache() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
 
-#[test]
-fn test_repository_location_parse_s3() {
-    let location = RepositoryLocation::parse("s3:my-bucket/ghostsnap/main").unwrap();
-    match location {
-        RepositoryLocation::S3(s3) => {
-            assert_eq!(s3.bucket, "my-bucket");
-            assert_eq!(s3.prefix, "ghostsnap/main");
-        }
-        RepositoryLocation::Local(_) => panic!("expected s3 repository location"),
-        RepositoryLocation::Azure(_) => panic!("expected s3 repository location"),
-        RepositoryLocation::Rclone(_) => panic!("expected s3 repository location"),
-        RepositoryLocation::Sftp(_) => panic!("expected s3 repository location"),
-    }
-}
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
 
-#[test]
-fn test_repository_location_parse_sftp() {
-    let location = RepositoryLocation::parse("sftp:backup@nas.local:2222/srv/ghostsnap").unwrap();
-    match location {
+    // No cache exists until stats are requested; refreshing is a no-op.
+    repo.refresh_stats_cache().await.unwrap();
+    assert!(repo.load_stats_cache().await.unwrap().is_none());
+
+    create_test_file(source_dir.path().join("a.txt"), b"First file");
+    let snapshot1 = backup_dir(&repo, source_dir.path()).await.unwrap();
+
+    let cache = repo.stats_cache(false).await.unwrap();
+    assert_eq!(cache.snapshot_count(), 1);
+    assert_eq!(cache.original_size(), 10);
+    assert!(cache.stored_size() > 0);
+
+    create_test_file(source_dir.path().join("b.txt"), b"Second");
+    let snapshot2 = backup_dir(&repo, source_dir.path()).await.unwrap();
+    repo.refresh_stats_cache().await.unwrap();
+
+    let cache = repo.load_stats_cache().await.unwrap().unwrap();
+    assert_eq!(cache.snapshot_count(), 2);
+    assert_eq!(cache.snapshots[&snapshot2].file_count, 2);
+    assert_eq!(cache.file_count(), 1 + 2);
+    assert_eq!(cache.original_size(), 10 + 16);
+    assert!(repo.index_size().await.unwrap() > 0);
+
+    repo.delete_snapshot(&snapshot1).await.unwrap();
+    repo.refresh_stats_cache().await.unwrap();
+
+    let cache = repo.load_stats_cache().await.unwrap().unwrap();
+    assert_eq!(cache.snapshot_count(), 1);
+    assert_eq!(cache.original_size(), 16);
+
+    let recomputed = repo.stats_cache(true).await.unwrap();
+    assert_eq!(recomputed.snapshots, cache.snapshots);
+    assert_eq!(recomputed.packs, cache.packs);
+}
+
+/// Tests that the retention policy is stored encrypted in the repository.
+#[tokio::test]
+async fn test_retention_policy_storage() {
+    let repo_dir = tempdir().unwrap();
+
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    assert!(repo.load_retention_policy().await.unwrap().is_none());
+
+    let mut policy = RetentionPolicy::new();
+    policy.set_rules(
+        PolicyScope::Default,
+        RetentionRules {
+            keep_daily: Some(7),
+            ..Default::default()
+        },
+    );
+    policy.set_rules(
+        PolicyScope::Host("db01".to_string()),
+        RetentionRules {
+            keep_last: Some(30),
+            ..Default::default()
+        },
+    );
+    repo.save_retention_policy(&policy).await.unwrap();
+
+    let raw = fs::read(repo_dir.path().join("policy")).unwrap();
+    assert!(!raw.windows(4).any(|w| w == b"db01"));
+
+    let reopened = Repository::open(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    let loaded = reopened.load_retention_policy().await.unwrap().unwrap();
+    assert_eq!(loaded.default.keep_daily, Some(7));
+    assert_eq!(loaded.hosts["db01"].keep_last, Some(30));
+
+    assert!(reopened.delete_retention_policy().await.unwrap());
+    assert!(reopened.load_retention_policy().await.unwrap().is_none());
+    assert!(!reopened.delete_retention_policy().await.unwrap());
+}
+
+/// Tests that repository settings are stored encrypted and seen by every
+/// client that opens the repository.
+#[tokio::test]
+async fn test_repository_settings() {
+    let repo_dir = tempdir().unwrap();
+
+    let mut repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    assert_eq!(repo.settings().pack_size, None);
+    // Local repositories default to smaller packs than remote ones
+    assert_eq!(repo.target_pack_size(), 16 * 1024 * 1024);
+    assert!(repo.settings().excludes.is_empty());
+
+    let settings = RepoSettings {
+        compression_level: Some(1),
+        pack_size: Some(8 * 1024 * 1024),
+        excludes: vec!["*.secret-cache".to_string()],
+        ..repo.settings().clone()
+    };
+    repo.save_settings(settings).await.unwrap();
+
+    let invalid = RepoSettings {
+        compression_level: Some(10),
+        ..repo.settings().clone()
+    };
+    assert!(repo.save_settings(invalid).await.is_err());
+    assert_eq!(repo.settings().compression_level, Some(1));
+
+    let raw = fs::read(repo_dir.path().join("settings")).unwrap();
+    assert!(!raw.windows(12).any(|w| w == b"secret-cache"));
+
+    let reopened = Repository::open(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    assert_eq!(reopened.settings().compression_level, Some(1));
+    assert_eq!(reopened.target_pack_size(), 8 * 1024 * 1024);
+    assert_eq!(reopened.settings().excludes, ["*.secret-cache"]);
+}
+
+/// Tests the space forecast for forgetting snapshots.
+#[tokio::test]
+async fn test_forget_forecast() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    create_test_file(source_dir.path().join("a.txt"), b"Shared by both snapshots");
+    create_test_file(source_dir.path().join("b.txt"), b"Only in the first");
+    let snapshot1 = backup_dir(&repo, source_dir.path()).await.unwrap();
+
+    fs::remove_file(source_dir.path().join("b.txt")).unwrap();
+    create_test_file(source_dir.path().join("c.txt"), b"Only in the second");
+    let snapshot2 = backup_dir(&repo, source_dir.path()).await.unwrap();
+
+    // The first pack keeps a.txt alive, so it is half unused and gets repacked.
+    let forecast = repo
+        .forecast_forget(std::slice::from_ref(&snapshot1), 0.5)
+        .await
+        .unwrap();
+    assert_eq!(forecast.snapshots, 1);
+    assert_eq!(forecast.unique_chunks, 1);
+    assert!(forecast.unique_bytes > 0);
+    assert_eq!(forecast.shared_chunks, 1);
+    assert_eq!(forecast.packs_deleted, 0);
+    assert_eq!(forecast.packs_repacked, 1);
+    assert!(forecast.repack_read_bytes > forecast.repack_write_bytes);
+    assert!(forecast.reclaimed_bytes() > 0);
+
+    // Under a stricter threshold the pack is left alone.
+    let forecast = repo
+        .forecast_forget(std::slice::from_ref(&snapshot1), 0.9)
+        .await
+        .unwrap();
+    assert_eq!(forecast.packs_repacked, 0);
+    assert_eq!(forecast.packs_kept, 1);
+    assert_eq!(forecast.reclaimed_bytes(), 0);
+
+    // The second pack only holds c.txt and is deleted outright.
+    let forecast = repo
+        .forecast_forget(std::slice::from_ref(&snapshot2), 0.5)
+        .await
+        .unwrap();
+    assert_eq!(forecast.packs_deleted, 1);
+    assert_eq!(forecast.repack_io_bytes(), 0);
+    assert_eq!(forecast.reclaimed_bytes(), forecast.deleted_bytes);
+
+    // Nothing was removed
+    assert_eq!(repo.list_snapshots().await.unwrap().len(), 2);
+}
+
+/// Tests that an unchanged tree is stored once and shared between snapshots.
+#[tokio::test]
+async fn test_tree_deduplication() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    create_test_file(source_dir.path().join("file.txt"), b"Unchanged content");
+    let tree_objects = || {
+        walkdir::WalkDir::new(repo_dir.path().join("data"))
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter(|e| e.path().extension().is_none_or(|ext| ext != "pack"))
+            .count()
+    };
+
+    let snapshot1 = backup_dir(&repo, source_dir.path()).await.unwrap();
+    let snapshot2 = backup_dir(&repo, source_dir.path()).await.unwrap();
+    let tree1 = repo.load_snapshot(&snapshot1).await.unwrap().tree;
+    let tree2 = repo.load_snapshot(&snapshot2).await.unwrap().tree;
+    assert_eq!(tree1, tree2);
+    assert_eq!(tree_objects(), 1);
+
+    create_test_file(source_dir.path().join("new.txt"), b"New file");
+    let snapshot3 = backup_dir(&repo, source_dir.path()).await.unwrap();
+    let tree3 = repo.load_snapshot(&snapshot3).await.unwrap().tree;
+    assert_ne!(tree1, tree3);
+    assert_eq!(tree_objects(), 2);
+}
+
+/// Tests that the snapshot summary cache follows new and forgotten snapshots.
+#[tokio::test]
+async fn test_snapshot_summary_cache() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    create_test_file(source_dir.path().join("a.txt"), b"First file");
+    let snapshot1 = backup_dir(&repo, source_dir.path()).await.unwrap();
+    create_test_file(source_dir.path().join("b.txt"), b"Second file");
+    let snapshot2 = backup_dir(&repo, source_dir.path()).await.unwrap();
+
+    let summaries = repo.snapshot_summaries(false).await.unwrap();
+    assert_eq!(summaries.len(), 2);
+    assert_eq!(summaries[0].snapshot.id, snapshot1);
+    assert_eq!(summaries[0].file_count, 1);
+    assert_eq!(summaries[1].snapshot.id, snapshot2);
+    assert_eq!(summaries[1].file_count, 2);
+    assert!(repo_dir.path().join("index/snapshots.cache").exists());
+
+    repo.delete_snapshot(&snapshot1).await.unwrap();
+    let summaries = repo.snapshot_summaries(false).await.unwrap();
+    assert_eq!(summaries.len(), 1);
+    assert_eq!(summaries[0].snapshot.id, snapshot2);
+
+    let rebuilt = repo.snapshot_summaries(true).await.unwrap();
+    assert_eq!(rebuilt.len(), 1);
+    assert_eq!(rebuilt[0].file_count, 2);
+}
+
+/// Tests detection of index entries that disagree with pack contents.
+#[tokio::test]
+async fn test_index_cross_check() {
+    use ghostsnap_core::{ChunkID, PackFile, PackID};
+
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    create_test_file(source_dir.path().join("file.txt"), b"Cross-check content");
+    backup_dir(&repo, source_dir.path()).await.unwrap();
+
+    let report = repo.cross_check_index().await.unwrap();
+    assert!(report.is_consistent());
+    assert_eq!(report.packs_checked, 1);
+
+    let stored_id = ChunkID::from_data(b"Cross-check content");
+
+    // A second pack holding a copy of the stored chunk and an unindexed one
+    let orphan_id = ChunkID::from_data(b"Never indexed");
+    let extra_pack = PackID::generate();
+    let mut pack = PackFile::new(extra_pack.clone());
+    pack.add_chunk(stored_id, b"Cross-check content").unwrap();
+    pack.add_chunk(orphan_id, b"Never indexed").unwrap();
+    repo.save_pack(&pack).await.unwrap();
+
+    // Corrupt the offset of the indexed entry
+    let mut location = repo.load_chunk_location(&stored_id).await.unwrap();
+    location.offset += 7;
+    repo.save_chunk_location(
+        &stored_id,
+        &location.pack_id,
+        location.offset,
+        location.length,
+    )
+    .await
+    .unwrap();
+
+    let report = repo.cross_check_index().await.unwrap();
+    assert!(!report.is_consistent());
+    assert_eq!(report.packs_checked, 2);
+    assert_eq!(report.unindexed_chunks, vec![(orphan_id, extra_pack)]);
+    assert_eq!(report.duplicate_chunks.len(), 1);
+    assert_eq!(report.duplicate_chunks[0].0, stored_id);
+
+    assert_eq!(report.mismatched_entries.len(), 1);
+    let mismatch = &report.mismatched_entries[0];
+    assert_eq!(mismatch.chunk_id, stored_id);
+    let actual = mismatch.actual.as_ref().unwrap();
+    assert_eq!(actual.pack_id, location.pack_id);
+    assert_eq!(actual.offset, location.offset - 7);
+}
+
+/// Tests the integrity check on a healthy repository and after a pack is
+/// lost and a snapshot damaged.
+#[tokio::test]
+async fn test_check_repository() {
+    use ghostsnap_core::check::{check_repository, check_snapshot};
+    use ghostsnap_core::{CheckOptions, CheckProblem, CheckStep, ChunkID};
+
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    create_test_file(source_dir.path().join("file.txt"), b"Checked content");
+    let snapshot_id = backup_dir(&repo, source_dir.path()).await.unwrap();
+    let snapshot = repo.load_snapshot(&snapshot_id).await.unwrap();
+
+    let options = CheckOptions {
+        read_data: true,
+        ..CheckOptions::default()
+    };
+    let report = check_repository(&repo, &options, &mut ()).await.unwrap();
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert_eq!((report.snapshots, report.trees, report.packs), (1, 1, 1));
+    assert!(
+        report
+            .referenced_chunks
+            .contains(&ChunkID::from_data(b"Checked content"))
+    );
+
+    // Losing the only pack loses every chunk of the snapshot
+    let pack_id = repo.list_packs().await.unwrap().remove(0);
+    fs::remove_file(
+        repo_dir
+            .path()
+            .join("data")
+            .join(&pack_id.as_str()[..2])
+            .join(format!("{}.pack", pack_id)),
+    )
+    .unwrap();
+    let report = check_snapshot(&repo, &snapshot, &CheckOptions::default(), &mut ())
+        .await
+        .unwrap();
+    assert_eq!(report.problems, vec![CheckProblem::MissingPack { pack_id }]);
+
+    fs::write(
+        repo_dir.path().join("snapshots").join(snapshot_id.as_str()),
+        b"garbage",
+    )
+    .unwrap();
+    let report = check_repository(&repo, &CheckOptions::default(), &mut ())
+        .await
+        .unwrap();
+    assert_eq!(report.problems_in(CheckStep::Snapshots), 1);
+    assert_eq!(report.problems_in(CheckStep::IndexedPacks), 1);
+    assert_eq!(report.packs, 0);
+}
+
+/// Tests merging partial snapshots into one, with the newest version winning.
+#[tokio::test]
+async fn test_merge_snapshots() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+    let restore_dir = tempdir().unwrap();
+
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    create_test_file(source_dir.path().join("shared.txt"), b"Old version");
+    create_test_file(source_dir.path().join("only-old.txt"), b"Only in first");
+    let first = backup_dir(&repo, source_dir.path()).await.unwrap();
+
+    fs::remove_file(source_dir.path().join("only-old.txt")).unwrap();
+    create_test_file(source_dir.path().join("shared.txt"), b"New version");
+    create_test_file(source_dir.path().join("only-new.txt"), b"Only in second");
+    let second = backup_dir(&repo, source_dir.path()).await.unwrap();
+
+    // Argument order does not matter; snapshot time decides which version wins.
+    let merged = repo
+        .merge_snapshots(&[second.clone(), first.clone()])
+        .await
+        .unwrap();
+    assert_eq!(merged.merged_from, vec![first, second]);
+    repo.save_snapshot(&merged).await.unwrap();
+
+    restore_snapshot(&repo, &merged.id, restore_dir.path())
+        .await
+        .unwrap();
+    assert_eq!(
+        fs::read(restore_dir.path().join("shared.txt")).unwrap(),
+        b"New version"
+    );
+    assert_eq!(
+        fs::read(restore_dir.path().join("only-old.txt")).unwrap(),
+        b"Only in first"
+    );
+    assert_eq!(
+        fs::read(restore_dir.path().join("only-new.txt")).unwrap(),
+        b"Only in second"
+    );
+}
+
+/// Snapshots of different roots are not merged: their node names would
+/// collide (`index.html` of one root replacing the other's).
+#[tokio::test]
+async fn test_merge_snapshots_different_roots() {
+    let repo_dir = tempdir().unwrap();
+    let etc_dir = tempdir().unwrap();
+    let www_dir = tempdir().unwrap();
+
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    create_test_file(etc_dir.path().join("index.html"), b"etc");
+    create_test_file(www_dir.path().join("index.html"), b"www");
+    let etc = backup_dir(&repo, etc_dir.path()).await.unwrap();
+    let www = backup_dir(&repo, www_dir.path()).await.unwrap();
+
+    let err = repo.merge_snapshots(&[etc, www]).await.unwrap_err();
+    assert!(
+        err.to_string().contains("different paths"),
+        "unexpected error: {}",
+        err
+    );
+    assert_eq!(repo.list_snapshots().await.unwrap().len(), 2);
+}
+
+/// Tests copying a snapshot into a second repository.
+#[tokio::test]
+async fn test_copy_snapshot_to() {
+    let src_dir = tempdir().unwrap();
+    let dst_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+    let restore_dir = tempdir().unwrap();
+
+    let src = Repository::init(src_dir.path(), "test-password")
+        .await
+        .unwrap();
+    let dst = Repository::init(dst_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    create_test_file(source_dir.path().join("a.txt"), b"Copied content");
+    let snapshot_id = backup_dir(&src, source_dir.path()).await.unwrap();
+
+    let stats = src.copy_snapshot_to(&dst, &snapshot_id).await.unwrap();
+    assert_eq!(stats.snapshot_id, snapshot_id);
+    assert!(stats.chunks_copied > 0);
+    assert_eq!(stats.chunks_skipped, 0);
+
+    // A second copy finds every chunk already present.
+    let stats = src.copy_snapshot_to(&dst, &snapshot_id).await.unwrap();
+    assert_eq!(stats.chunks_copied, 0);
+
+    restore_snapshot(&dst, &snapshot_id, restore_dir.path())
+        .await
+        .unwrap();
+    assert_files_equal(
+        source_dir.path().join("a.txt"),
+        restore_dir.path().join("a.txt"),
+    );
+}
+
+/// Tests that chunk IDs follow the repository's hash algorithm, and that
+/// copies and bundles carry snapshots between repositories hashing
+/// differently.
+#[tokio::test]
+async fn test_hash_algorithms() {
+    use ghostsnap_core::{HashAlgorithm, SnapshotBundle};
+
+    let sha256_dir = tempdir().unwrap();
+    let keyed_dir = tempdir().unwrap();
+    let blake3_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+    let keys = PasswordKey::new("test-password");
+
+    // A keyed hash needs a key, and so encryption
+    let plain_dir = tempdir().unwrap();
+    assert!(
+        Repository::init_with_hash(
+            RepositoryLocation::Local(plain_dir.path().to_path_buf()),
+            &keys,
+            EncryptionLayer::Backend,
+            HashAlgorithm::KeyedBlake3,
+        )
+        .await
+        .is_err()
+    );
+
+    let sha256 = Repository::init_with_hash(
+        RepositoryLocation::Local(sha256_dir.path().to_path_buf()),
+        &keys,
+        EncryptionLayer::Repo,
+        HashAlgorithm::Sha256,
+    )
+    .await
+    .unwrap();
+    let keyed = Repository::init_with_hash(
+        RepositoryLocation::Local(keyed_dir.path().to_path_buf()),
+        &keys,
+        EncryptionLayer::Repo,
+        HashAlgorithm::KeyedBlake3,
+    )
+    .await
+    .unwrap();
+    let blake3 = Repository::init(blake3_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    let contents = b"Hashed content".to_vec();
+    create_test_file(source_dir.path().join("a.txt"), &contents);
+    let snapshot_id = backup_dir(&sha256, source_dir.path()).await.unwrap();
+
+    // The algorithm is read back from the config
+    let sha256 = Repository::open(sha256_dir.path(), "test-password")
+        .await
+        .unwrap();
+    assert_eq!(sha256.hasher().algorithm(), HashAlgorithm::Sha256);
+    let snapshot = sha256.load_snapshot(&snapshot_id).await.unwrap();
+    let tree = sha256.load_tree(&snapshot.tree).await.unwrap();
+    let file = tree.find_node("a.txt").unwrap();
+    let chunk = &file.chunks[0];
+    assert_eq!(chunk.id, sha256.chunk_id(&contents));
+    assert_ne!(chunk.id, blake3.chunk_id(&contents));
+    assert_ne!(keyed.chunk_id(&contents), blake3.chunk_id(&contents));
+
+    // Copying re-identifies chunks with the destination's algorithm
+    let stats = sha256
+        .copy_snapshot_to(&blake3, &snapshot_id)
+        .await
+        .unwrap();
+    assert!(stats.chunks_copied > 0);
+    let snapshot = blake3.load_snapshot(&snapshot_id).await.unwrap();
+    let tree = blake3.load_tree(&snapshot.tree).await.unwrap();
+    let file = tree.find_node("a.txt").unwrap();
+    assert_eq!(file.chunks[0].id, blake3.chunk_id(&contents));
+    let restore_dir = tempdir().unwrap();
+    restore_snapshot(&blake3, &snapshot_id, restore_dir.path())
+        .await
+        .unwrap();
+    assert_files_equal(
+        source_dir.path().join("a.txt"),
+        restore_dir.path().join("a.txt"),
+    );
+
+    // So does importing a bundle
+    let bundle = sha256.export_bundle(&snapshot_id).await.unwrap();
+    assert_eq!(bundle.hash, HashAlgorithm::Sha256);
+    let bytes = bundle.to_bytes("bundle-password").unwrap();
+    let imported = SnapshotBundle::from_bytes(&bytes, "bundle-password").unwrap();
+    keyed.import_bundle(&imported).await.unwrap();
+    let restore_dir = tempdir().unwrap();
+    restore_snapshot(&keyed, &snapshot_id, restore_dir.path())
+        .await
+        .unwrap();
+    assert_files_equal(
+        source_dir.path().join("a.txt"),
+        restore_dir.path().join("a.txt"),
+    );
+}
+
+/// Tests that a FIPS repository uses AES-256-GCM, PBKDF2 and SHA-256, and
+/// that opening it enforces them.
+#[tokio::test]
+async fn test_fips_profile() {
+    use ghostsnap_core::{CryptoProfile, HashAlgorithm, KDF_PBKDF2_SHA256};
+
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+    let restore_dir = tempdir().unwrap();
+    let location = RepositoryLocation::Local(repo_dir.path().to_path_buf());
+    let keys = PasswordKey::new("test-password");
+
+    // Only the profile's own hash is accepted
+    let other_dir = tempdir().unwrap();
+    assert!(
+        Repository::init_with_profile(
+            RepositoryLocation::Local(other_dir.path().to_path_buf()),
+            &keys,
+            EncryptionLayer::Repo,
+            HashAlgorithm::Blake3,
+            CryptoProfile::Fips,
+        )
+        .await
+        .is_err()
+    );
+
+    let profile = CryptoProfile::Fips;
+    let repo = Repository::init_with_profile(
+        location,
+        &keys,
+        EncryptionLayer::Repo,
+        profile.hash(),
+        profile,
+    )
+    .await
+    .unwrap();
+    create_test_file(source_dir.path().join("a.txt"), b"FIPS content");
+    let snapshot_id = backup_dir(&repo, source_dir.path()).await.unwrap();
+    drop(repo);
+
+    let repo = Repository::open(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    assert_eq!(repo.encryption_mode(), EncryptionMode::Aes256Gcm);
+    assert_eq!(repo.config().profile, CryptoProfile::Fips);
+    assert_eq!(repo.config().kdf_params.algorithm, KDF_PBKDF2_SHA256);
+    assert_eq!(repo.hasher().algorithm(), HashAlgorithm::Sha256);
+    restore_snapshot(&repo, &snapshot_id, restore_dir.path())
+        .await
+        .unwrap();
+    assert_files_equal(
+        source_dir.path().join("a.txt"),
+        restore_dir.path().join("a.txt"),
+    );
+    assert!(
+        Repository::open(repo_dir.path(), "wrong-password")
+            .await
+            .is_err()
+    );
+
+    // A config weakened behind the profile's back is refused
+    let config_path = repo_dir.path().join("config");
+    let original = fs::read(&config_path).unwrap();
+    let mut config: serde_json::Value = serde_json::from_slice(&original).unwrap();
+    config["encryption"] = "chacha20-poly1305".into();
+    fs::write(&config_path, serde_json::to_vec(&config).unwrap()).unwrap();
+    assert!(
+        Repository::open(repo_dir.path(), "test-password")
+            .await
+            .is_err()
+    );
+}
+
+/// Tests empty directory handling.
+#[tokio::test]
+async fn test_empty_directory() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+    let restore_dir = tempdir().unwrap();
+
+    // Initialize repository
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    // Create empty subdirectory
+    fs::create_dir_all(source_dir.path().join("empty_dir")).unwrap();
+    create_test_file(source_dir.path().join("file.txt"), b"Content");
+
+    // Backup and restore
+    let snapshot_id = backup_dir(&repo, source_dir.path()).await.unwrap();
+    restore_snapshot(&repo, &snapshot_id, restore_dir.path())
+        .await
+        .unwrap();
+
+    // Verify empty directory exists
+    assert!(
+        restore_dir.path().join("empty_dir").is_dir(),
+        "Empty directory should be restored"
+    );
+}
+
+#[test]
+fn test_repository_location_parse_local() {
+    let location = RepositoryLocation::parse("/backup/repo").unwrap();
+    match location {
+        RepositoryLocation::Local(path) => {
+            assert_eq!(path, std::path::PathBuf::from("/backup/repo"))
+        }
+        RepositoryLocation::S3(_) => panic!("expected local repository location"),
+        RepositoryLocation::Azure(_) => panic!("expected local repository location"),
+        RepositoryLocation::Rclone(_) => panic!("expected local repository location"),
+        RepositoryLocation::Sftp(_) => panic!("expected local repository location"),
+    }
+}
+
+#[test]
+fn test_repository_location_parse_s3() {
+    let location = RepositoryLocation::parse("s3:my-bucket/ghostsnap/main").unwrap();
+    match location {
+        RepositoryLocation::S3(s3) => {
+            assert_eq!(s3.bucket, "my-bucket");
+            assert_eq!(s3.prefix, "ghostsnap/main");
+        }
+        RepositoryLocation::Local(_) => panic!("expected s3 repository location"),
+        RepositoryLocation::Azure(_) => panic!("expected s3 repository location"),
+        RepositoryLocation::Rclone(_) => panic!("expected s3 repository location"),
+        RepositoryLocation::Sftp(_) => panic!("expected s3 repository location"),
+    }
+}
+
+#[test]
+fn test_repository_location_parse_sftp() {
+    let location = RepositoryLocation::parse("sftp:backup@nas.local:2222/srv/ghostsnap").unwrap();
+    match location {
         RepositoryLocation::Sftp(sftp) => {
             assert_eq!(sftp.user, "backup");
             assert_eq!(sftp.host, "nas.local");
             assert_eq!(sftp.port, 2222);
             assert_eq!(sftp.path, "srv/ghostsnap");
         }
-        _ => panic!("expected sftp repository location"),
+        _ => panic!("expected sftp repository location"),
+    }
+}
+
+#[test]
+fn test_repository_location_parse_sftp_defaults() {
+    // No user, no port, no path: defaults to port 22 and empty user/path.
+    let location = RepositoryLocation::parse("sftp://example.com").unwrap();
+    match location {
+        RepositoryLocation::Sftp(sftp) => {
+            assert_eq!(sftp.host, "example.com");
+            assert_eq!(sftp.port, 22);
+            assert!(sftp.user.is_empty());
+            assert!(sftp.path.is_empty());
+        }
+        _ => panic!("expected sftp repository location"),
+    }
+}
+
+#[tokio::test]
+async fn test_open_init_at_location_local() {
+    let repo_dir = tempdir().unwrap();
+    let location = RepositoryLocation::Local(repo_dir.path().join("repo"));
+
+    let repo = Repository::init_at_location(location.clone(), "test-password")
+        .await
+        .unwrap();
+    assert_eq!(repo.location().display(), location.display());
+
+    let reopened = Repository::open_at_location(location.clone(), "test-password")
+        .await
+        .unwrap();
+    assert_eq!(reopened.location().display(), location.display());
+}
+
+#[tokio::test]
+async fn test_s3_transport_config_persists_in_repo_config() {
+    let repo_dir = tempdir().unwrap();
+    let mut repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    let mut location = ghostsnap_core::storage::S3Location::new(
+        "example-bucket".to_string(),
+        "ghostsnap/backups".to_string(),
+    );
+    location.endpoint = Some("https://s3.example.com".to_string());
+    location.region = Some("us-east-2".to_string());
+
+    repo.set_s3_transport_config(
+        &location,
+        Some(S3RepoSse {
+            mode: "kms".to_string(),
+            kms_key_id: Some("alias/ghostsnap".to_string()),
+        }),
+    )
+    .await
+    .unwrap();
+
+    let reopened = Repository::open(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    match reopened.config().transport.as_ref() {
+        Some(RepoTransport::S3(config)) => {
+            assert_eq!(config.bucket, "example-bucket");
+            assert_eq!(config.prefix, "ghostsnap/backups");
+            assert_eq!(config.endpoint.as_deref(), Some("https://s3.example.com"));
+            assert_eq!(config.region.as_deref(), Some("us-east-2"));
+            assert_eq!(config.sse.as_ref().map(|s| s.mode.as_str()), Some("kms"));
+            assert_eq!(
+                config.sse.as_ref().and_then(|s| s.kms_key_id.as_deref()),
+                Some("alias/ghostsnap")
+            );
+        }
+        _ => panic!("expected persisted S3 transport config"),
+    }
+}
+
+#[test]
+fn test_azure_repository_location_parse() {
+    // Azure is now supported
+    let azure = RepositoryLocation::parse("azure:myaccount/mycontainer/prefix").unwrap();
+    match azure {
+        RepositoryLocation::Azure(az) => {
+            assert_eq!(az.account_name, "myaccount");
+            assert_eq!(az.container, "mycontainer");
+            assert_eq!(az.prefix, "prefix");
+        }
+        _ => panic!("expected azure repository location"),
+    }
+}
+
+#[test]
+fn test_b2_minio_parse_as_s3() {
+    // Backblaze B2 and MinIO are S3-compatible and map to an S3 location.
+    let b2 = RepositoryLocation::parse("b2:bucket/repo").unwrap();
+    match b2 {
+        RepositoryLocation::S3(s3) => {
+            assert_eq!(s3.bucket, "bucket");
+            assert_eq!(s3.prefix, "repo");
+        }
+        _ => panic!("expected b2 to map to an s3 repository location"),
+    }
+
+    let minio = RepositoryLocation::parse("minio://data/ghostsnap").unwrap();
+    match minio {
+        RepositoryLocation::S3(s3) => {
+            assert_eq!(s3.bucket, "data");
+            assert_eq!(s3.prefix, "ghostsnap");
+        }
+        _ => panic!("expected minio to map to an s3 repository location"),
+    }
+}
+
+#[test]
+fn test_rclone_repository_location_parse() {
+    let location = RepositoryLocation::parse("rclone:myremote/backups/ghostsnap").unwrap();
+    match location {
+        RepositoryLocation::Rclone(rclone) => {
+            assert_eq!(rclone.remote, "myremote");
+            assert_eq!(rclone.path, "backups/ghostsnap");
+        }
+        _ => panic!("expected rclone repository location"),
+    }
+
+    // Test with just remote, no path
+    let location2 = RepositoryLocation::parse("rclone:gdrive").unwrap();
+    match location2 {
+        RepositoryLocation::Rclone(rclone) => {
+            assert_eq!(rclone.remote, "gdrive");
+            assert_eq!(rclone.path, "");
+        }
+        _ => panic!("expected rclone repository location"),
+    }
+}
+
+#[test]
+fn test_s3_location_env_overrides() {
+    use ghostsnap_core::storage::S3Location;
+
+    // SAFETY: This test modifies env vars but runs in a single thread.
+    // Using serial_test or similar would be needed for parallel safety.
+    unsafe {
+        // Set test env vars
+        std::env::set_var("AWS_ENDPOINT_URL", "https://test.example.com");
+        std::env::set_var("AWS_REGION", "us-west-2");
+    }
+
+    let location = S3Location::new("test-bucket".to_string(), "prefix".to_string());
+    assert!(location.endpoint.is_none());
+    assert!(location.region.is_none());
+
+    let location = location.with_env_overrides();
+    assert_eq!(
+        location.endpoint,
+        Some("https://test.example.com".to_string())
+    );
+    assert_eq!(location.region, Some("us-west-2".to_string()));
+
+    // SAFETY: Cleaning up env vars set by this test
+    unsafe {
+        std::env::remove_var("AWS_ENDPOINT_URL");
+        std::env::remove_var("AWS_REGION");
+    }
+
+    // Test that explicit values are not overwritten
+    // SAFETY: Setting temp env var for this test
+    unsafe {
+        std::env::set_var("AWS_ENDPOINT_URL", "https://should-not-use.com");
+    }
+
+    let location = S3Location {
+        bucket: "bucket".to_string(),
+        prefix: "prefix".to_string(),
+        endpoint: Some("https://explicit.example.com".to_string()),
+        region: None,
+        sse: None,
+        storage_classes: Default::default(),
+        checksum: Default::default(),
+        credentials: Default::default(),
+        tls: Default::default(),
+        proxy: None,
+    };
+
+    let location = location.with_env_overrides();
+    assert_eq!(
+        location.endpoint,
+        Some("https://explicit.example.com".to_string())
+    );
+
+    // SAFETY: Cleanup
+    unsafe {
+        std::env::remove_var("AWS_ENDPOINT_URL");
+    }
+}
+
+#[tokio::test]
+async fn test_s3_sse_config_persists_and_resolves() {
+    use ghostsnap_core::storage::S3Location;
+
+    let repo_dir = tempdir().unwrap();
+    let mut repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    let mut location = S3Location::new("sse-test-bucket".to_string(), "backups".to_string());
+    location.endpoint = Some("https://s3.example.com".to_string());
+
+    // Set SSE config with AES256
+    repo.set_s3_transport_config(
+        &location,
+        Some(S3RepoSse {
+            mode: "aes256".to_string(),
+            kms_key_id: None,
+        }),
+    )
+    .await
+    .unwrap();
+
+    // Reopen and verify SSE is persisted
+    let reopened = Repository::open(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    match reopened.config().transport.as_ref() {
+        Some(RepoTransport::S3(config)) => {
+            assert_eq!(config.sse.as_ref().map(|s| s.mode.as_str()), Some("aes256"));
+            assert!(
+                config
+                    .sse
+                    .as_ref()
+                    .and_then(|s| s.kms_key_id.as_ref())
+                    .is_none()
+            );
+        }
+        _ => panic!("expected persisted S3 transport config with SSE"),
+    }
+}
+
+#[tokio::test]
+async fn test_s3_storage_classes_persist() {
+    use ghostsnap_core::storage::S3Location;
+
+    let repo_dir = tempdir().unwrap();
+    let mut repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    let mut location = S3Location::new("class-test-bucket".to_string(), "backups".to_string());
+    location.storage_classes.set("metadata=standard").unwrap();
+    location.storage_classes.set("data=STANDARD_IA").unwrap();
+    assert!(location.storage_classes.set("metadata=GLACIER").is_err());
+    assert!(location.storage_classes.set("packs=STANDARD").is_err());
+
+    repo.set_s3_transport_config(&location, None).await.unwrap();
+
+    let reopened = Repository::open(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    let Some(RepoTransport::S3(config)) = reopened.config().transport.as_ref() else {
+        panic!("expected persisted S3 transport config");
+    };
+    let classes = &config.storage_classes;
+    assert_eq!(classes.for_path("data/abc.pack"), Some("STANDARD_IA"));
+    assert_eq!(classes.for_path("data/0123abcd"), Some("STANDARD"));
+    assert_eq!(classes.for_path("snapshots/abc"), Some("STANDARD"));
+    assert_eq!(classes.for_path("index/main.idx"), Some("STANDARD"));
+    assert!(!classes.data_is_archived());
+}
+
+#[tokio::test]
+async fn test_s3_checksum_persist() {
+    use ghostsnap_core::S3Checksum;
+    use ghostsnap_core::storage::{S3Location, s3_sha256_checksum, verify_s3_sha256};
+
+    assert_eq!("SHA256".parse::<S3Checksum>().unwrap(), S3Checksum::Sha256);
+    assert!("md5".parse::<S3Checksum>().is_err());
+
+    // Known digest of the empty input, base64 encoded as S3 returns it.
+    assert_eq!(
+        s3_sha256_checksum(b""),
+        "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+    );
+    let data = b"pack data";
+    let checksum = s3_sha256_checksum(data);
+    assert!(verify_s3_sha256("data/a.pack", data, Some(&checksum)).is_ok());
+    assert!(verify_s3_sha256("data/a.pack", b"corrupted", Some(&checksum)).is_err());
+    assert!(verify_s3_sha256("data/a.pack", data, None).is_ok());
+
+    let repo_dir = tempdir().unwrap();
+    let mut repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    let mut location = S3Location::new("checksum-bucket".to_string(), String::new());
+    location.checksum = S3Checksum::Sha256;
+    repo.set_s3_transport_config(&location, None).await.unwrap();
+
+    let reopened = Repository::open(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    let config = reopened.s3_transport().expect("persisted S3 transport");
+    assert_eq!(config.checksum, S3Checksum::Sha256);
+}
+
+#[test]
+fn test_s3_credentials() {
+    use ghostsnap_core::storage::{S3AssumeRole, S3Credentials, S3StaticKeys, S3WebIdentity};
+
+    let keys = S3StaticKeys {
+        access_key_id: "AKIDEXAMPLE".to_string(),
+        secret_access_key: "super-secret".to_string(),
+        session_token: Some("token".to_string()),
+    };
+    let debug = format!("{:?}", keys);
+    assert!(debug.contains("AKIDEXAMPLE"));
+    assert!(!debug.contains("super-secret"));
+    assert!(!debug.contains("\"token\""));
+
+    let role = S3AssumeRole {
+        role_arn: "arn:aws:iam::123456789012:role/backup".to_string(),
+        external_id: Some("ext-1".to_string()),
+        session_name: None,
+    };
+    let web_identity = S3WebIdentity {
+        role_arn: "arn:aws:iam::123456789012:role/irsa".to_string(),
+        token_file: "/var/run/secrets/token".into(),
+        session_name: None,
+    };
+
+    assert!(S3Credentials::default().is_default());
+    assert!(S3Credentials::anonymous().validate().is_ok());
+    let chained = S3Credentials {
+        static_keys: Some(keys.clone()),
+        assume_role: Some(role.clone()),
+        ..S3Credentials::default()
+    };
+    assert!(chained.validate().is_ok());
+    let irsa = S3Credentials {
+        web_identity: Some(web_identity.clone()),
+        assume_role: Some(role.clone()),
+        ..S3Credentials::default()
+    };
+    assert!(irsa.validate().is_ok());
+
+    // Conflicting or incomplete settings
+    let anonymous_role = S3Credentials {
+        assume_role: Some(role),
+        ..S3Credentials::anonymous()
+    };
+    assert!(anonymous_role.validate().is_err());
+    let two_bases = S3Credentials {
+        static_keys: Some(keys.clone()),
+        web_identity: Some(web_identity),
+        ..S3Credentials::default()
+    };
+    assert!(two_bases.validate().is_err());
+    let missing_secret = S3Credentials {
+        static_keys: Some(S3StaticKeys {
+            secret_access_key: String::new(),
+            ..keys
+        }),
+        ..S3Credentials::default()
+    };
+    assert!(missing_secret.validate().is_err());
+
+    // SAFETY: These variables are only read by this test.
+    unsafe {
+        std::env::set_var(
+            "GHOSTSNAP_S3_ROLE_ARN",
+            "arn:aws:iam::123456789012:role/env",
+        );
+        std::env::set_var("GHOSTSNAP_S3_EXTERNAL_ID", "ext-env");
+        std::env::set_var("GHOSTSNAP_S3_ACCESS_KEY_ID", "AKIDENV");
+    }
+    let from_env = S3Credentials::from_env();
+    // SAFETY: Cleanup
+    unsafe {
+        std::env::remove_var("GHOSTSNAP_S3_ROLE_ARN");
+        std::env::remove_var("GHOSTSNAP_S3_EXTERNAL_ID");
+        std::env::remove_var("GHOSTSNAP_S3_ACCESS_KEY_ID");
+    }
+    let role = from_env.assume_role.as_ref().unwrap();
+    assert_eq!(role.role_arn, "arn:aws:iam::123456789012:role/env");
+    assert_eq!(role.external_id.as_deref(), Some("ext-env"));
+    // An access key without its secret is kept and reported by validate().
+    assert!(from_env.static_keys.is_some());
+    assert!(from_env.validate().is_err());
+}
+
+#[test]
+fn test_s3_tls_settings() {
+    use ghostsnap_core::S3Tls;
+    use ghostsnap_core::storage::s3_http_client;
+
+    assert!(s3_http_client(&S3Tls::default(), None).unwrap().is_none());
+
+    let dir = tempdir().unwrap();
+    let missing = S3Tls {
+        ca_bundle: Some(dir.path().join("missing.pem")),
+        ..S3Tls::default()
+    };
+    assert!(s3_http_client(&missing, None).is_err());
+
+    let empty_bundle = dir.path().join("empty.pem");
+    fs::write(&empty_bundle, "").unwrap();
+    let empty = S3Tls {
+        ca_bundle: Some(empty_bundle),
+        ..S3Tls::default()
+    };
+    assert!(s3_http_client(&empty, None).is_err());
+
+    let cert_without_key = S3Tls {
+        client_cert: Some(dir.path().join("client.pem")),
+        ..S3Tls::default()
+    };
+    assert!(s3_http_client(&cert_without_key, None).is_err());
+
+    // Paths are persisted in MinIO backend configs.
+    let tls = S3Tls {
+        ca_bundle: Some("/etc/ssl/private-ca.pem".into()),
+        accept_invalid_hostnames: true,
+        ..S3Tls::default()
+    };
+    let json = serde_json::to_string(&tls).unwrap();
+    assert!(!json.contains("client_cert"));
+    assert_eq!(serde_json::from_str::<S3Tls>(&json).unwrap(), tls);
+}
+
+#[tokio::test]
+async fn test_azure_access_tiers() {
+    use ghostsnap_core::{AccessTier, AzureAccessTiers, RehydratePriority};
+
+    let mut tiers = AzureAccessTiers::default();
+    tiers.set("metadata=hot").unwrap();
+    tiers.set("data=Archive").unwrap();
+    assert!(tiers.set("metadata=archive").is_err());
+    assert!(tiers.set("data=frozen").is_err());
+    assert!(tiers.set("packs=cool").is_err());
+    assert_eq!(tiers.for_path("data/abc.pack"), Some(AccessTier::Archive));
+    assert_eq!(tiers.for_path("data/0123abcd"), Some(AccessTier::Hot));
+    assert_eq!(tiers.for_path("snapshots/abc"), Some(AccessTier::Hot));
+    assert!(tiers.data_is_archived());
+
+    // Local storage has no tiers: nothing to rehydrate, and rules are refused.
+    let repo_dir = tempdir().unwrap();
+    let mut repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    let pack_id = ghostsnap_core::PackID::generate();
+    assert!(repo.pack_access_tier(&pack_id).await.unwrap().is_none());
+    let report = repo
+        .rehydrate_packs(&[pack_id], RehydratePriority::High)
+        .await
+        .unwrap();
+    assert!(report.is_ready());
+    assert!(repo.set_azure_access_tiers(tiers).await.is_err());
+}
+
+/// Tests that objects not written by ghostsnap are ignored by listings and
+/// maintenance, and reported as foreign.
+#[tokio::test]
+async fn test_foreign_objects_ignored() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    create_test_file(source_dir.path().join("hello.txt"), b"Hello, World!");
+    let snapshot_id = backup_dir(&repo, source_dir.path()).await.unwrap();
+
+    create_test_file(repo_dir.path().join("README.txt"), b"not a backup");
+    create_test_file(repo_dir.path().join("snapshots/notes.txt"), b"notes");
+    create_test_file(repo_dir.path().join("data/old.pack"), b"not a pack");
+    create_test_file(repo_dir.path().join("keys/id_ed25519"), b"ssh key");
+
+    let repo = Repository::open(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    assert_eq!(repo.list_snapshots().await.unwrap(), vec![snapshot_id]);
+    let packs = repo.list_packs().await.unwrap();
+    assert!(!packs.is_empty());
+    assert!(packs.iter().all(|id| id.as_str() != "old"));
+
+    let stats = repo.verify(true).await.unwrap();
+    assert_eq!(stats.corrupt_packs, 0);
+    assert_eq!(stats.corrupt_snapshots, 0);
+
+    assert_eq!(
+        repo.foreign_objects().await.unwrap(),
+        vec![
+            "README.txt",
+            "data/old.pack",
+            "keys/id_ed25519",
+            "snapshots/notes.txt"
+        ]
+    );
+}
+
+/// Tests that data objects are spread over shard directories and that a
+/// repository in the flat layout is readable and migrated only on request.
+#[tokio::test]
+async fn test_fan_out_layout() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+    let restore_dir = tempdir().unwrap();
+
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    create_test_file(source_dir.path().join("hello.txt"), b"Hello, World!");
+    let snapshot_id = backup_dir(&repo, source_dir.path()).await.unwrap();
+    let packs = repo.list_packs().await.unwrap();
+
+    let data_dir = repo_dir.path().join("data");
+    let objects: Vec<_> = walkdir::WalkDir::new(&data_dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect();
+    assert!(!objects.is_empty());
+    for path in &objects {
+        let name = path.file_name().unwrap().to_string_lossy();
+        let shard = path.parent().unwrap();
+        assert_eq!(shard.parent().unwrap(), data_dir);
+        assert_eq!(shard.file_name().unwrap().to_string_lossy(), name[..2]);
+    }
+
+    // Flatten the layout as an older version would have written it
+    for path in &objects {
+        fs::rename(path, data_dir.join(path.file_name().unwrap())).unwrap();
+    }
+
+    let repo = Repository::open(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    let tree = repo.load_snapshot(&snapshot_id).await.unwrap().tree;
+    assert!(repo.load_tree(&tree).await.is_ok());
+    let mut listed = repo.list_packs().await.unwrap();
+    listed.sort();
+    let mut expected = packs.clone();
+    expected.sort();
+    assert_eq!(listed, expected);
+    for path in &objects {
+        assert!(!path.exists(), "{} was migrated by listing", path.display());
+    }
+
+    assert_eq!(repo.migrate_layout().await.unwrap(), objects.len());
+    for path in &objects {
+        assert!(path.exists(), "{} was not migrated", path.display());
+    }
+    let mut listed = repo.list_packs().await.unwrap();
+    listed.sort();
+    assert_eq!(listed, expected);
+    assert_eq!(repo.migrate_layout().await.unwrap(), 0);
+
+    restore_snapshot(&repo, &snapshot_id, restore_dir.path())
+        .await
+        .unwrap();
+    assert_files_equal(
+        source_dir.path().join("hello.txt"),
+        restore_dir.path().join("hello.txt"),
+    );
+}
+
+/// Tests snapshot time overrides and detection of a clock that is behind the
+/// newest snapshot.
+#[tokio::test]
+async fn test_snapshot_time_and_clock_skew() {
+    use std::path::PathBuf;
+
+    let repo_dir = tempdir().unwrap();
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    let tree = repo.save_tree(&Tree::new()).await.unwrap();
+    assert!(repo.clock_skew().await.unwrap().is_none());
+
+    let historical = chrono::DateTime::parse_from_rfc3339("2021-06-01T02:00:00Z")
+        .unwrap()
+        .with_timezone(&chrono::Utc);
+    let snapshot = Snapshot::new(vec![PathBuf::from("/data")], tree).with_time(historical);
+    repo.save_snapshot(&snapshot).await.unwrap();
+    assert_eq!(
+        repo.load_snapshot(&snapshot.id).await.unwrap().time,
+        historical
+    );
+    assert!(repo.clock_skew().await.unwrap().is_none());
+
+    let future = chrono::Utc::now() + chrono::Duration::days(2);
+    let snapshot = Snapshot::new(vec![PathBuf::from("/data")], tree).with_time(future);
+    repo.save_snapshot(&snapshot).await.unwrap();
+    let ahead = repo.clock_skew().await.unwrap().unwrap();
+    assert!(ahead > chrono::Duration::days(1));
+
+    // Local storage stamps objects with this host's clock
+    assert!(repo.storage_clock_offset().await.unwrap().is_none());
+    assert!(
+        !repo_dir
+            .path()
+            .join(ghostsnap_core::capability::PROBE_PATH)
+            .exists()
+    );
+
+    use chrono::Duration;
+    let (before, after) = (historical, historical + Duration::seconds(2));
+    let offset = |stamped| ghostsnap_core::repository::clock_offset(stamped, before, after);
+    assert_eq!(offset(before + Duration::seconds(1)), Duration::zero());
+    assert_eq!(offset(after + Duration::hours(1)), Duration::hours(1));
+    assert_eq!(offset(before - Duration::hours(1)), -Duration::hours(1));
+}
+
+/// Tests cumulative directory sizes used by `ls --tree`.
+#[tokio::test]
+async fn test_tree_cumulative_sizes() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    fs::create_dir_all(source_dir.path().join("docs/reports")).unwrap();
+    create_test_file(source_dir.path().join("docs/reports/q1.txt"), &[1u8; 300]);
+    create_test_file(source_dir.path().join("docs/readme.txt"), &[2u8; 50]);
+    create_test_file(source_dir.path().join("top.txt"), &[3u8; 7]);
+    fs::create_dir_all(source_dir.path().join("empty")).unwrap();
+
+    let snapshot_id = backup_dir(&repo, source_dir.path()).await.unwrap();
+    let snapshot = repo.load_snapshot(&snapshot_id).await.unwrap();
+    let tree = repo.load_tree(&snapshot.tree).await.unwrap();
+
+    let sizes = tree.cumulative_sizes();
+    assert_eq!(sizes["docs"], 350);
+    assert_eq!(sizes["docs/reports"], 300);
+    assert_eq!(sizes["empty"], 0);
+    assert!(!sizes.contains_key("top.txt"));
+}
+
+/// Tests that scrubbing rotates through packs within its budget and reports
+/// a pack whose data was damaged.
+#[tokio::test]
+async fn test_scrub_rotation_and_corruption() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    create_test_file(source_dir.path().join("first.txt"), &[1u8; 4096]);
+    backup_dir(&repo, source_dir.path()).await.unwrap();
+    create_test_file(source_dir.path().join("second.txt"), &[2u8; 4096]);
+    backup_dir(&repo, source_dir.path()).await.unwrap();
+    let packs = repo.list_packs().await.unwrap();
+    assert_eq!(packs.len(), 2);
+
+    // A one-byte budget still verifies one pack per pass
+    let first = repo.scrub(1).await.unwrap();
+    assert_eq!(first.packs_verified, 1);
+    assert_eq!(first.never_verified, 1);
+    assert!(first.oldest_verification.is_none());
+    let second = repo.scrub(1).await.unwrap();
+    assert_eq!(second.packs_verified, 1);
+    assert_eq!(second.never_verified, 0);
+    assert!(second.oldest_verification.is_some());
+    let state = repo.load_scrub_state().await.unwrap();
+    assert!(packs.iter().all(|id| state.packs[id].ok));
+
+    let damaged = walkdir::WalkDir::new(repo_dir.path().join("data"))
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .find(|e| e.file_name().to_string_lossy() == format!("{}.pack", packs[0]))
+        .unwrap()
+        .into_path();
+    let mut data = fs::read(&damaged).unwrap();
+    let middle = data.len() / 2;
+    data[middle] ^= 0xff;
+    fs::write(&damaged, data).unwrap();
+
+    let report = repo.scrub(u64::MAX).await.unwrap();
+    assert_eq!(report.packs_verified, 1);
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(report.failures[0].pack_id, packs[0]);
+    let state = repo.load_scrub_state().await.unwrap();
+    assert!(!state.packs[&packs[0]].ok);
+}
+
+/// Tests that a replica missing a recently written pack is reported out of
+/// sync.
+#[tokio::test]
+async fn test_replication_status_detects_missing_pack() {
+    let repo_dir = tempdir().unwrap();
+    let replica_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+    create_test_file(source_dir.path().join("first.txt"), &[1u8; 4096]);
+    backup_dir(&repo, source_dir.path()).await.unwrap();
+    create_test_file(source_dir.path().join("second.txt"), &[2u8; 4096]);
+    backup_dir(&repo, source_dir.path()).await.unwrap();
+
+    // Sampling covers both snapshots, their trees and their packs
+    let paths = repo.recent_objects(5, 50).await.unwrap();
+    assert_eq!(
+        paths.iter().filter(|p| p.starts_with("snapshots/")).count(),
+        2
+    );
+    assert_eq!(paths.iter().filter(|p| p.ends_with(".pack")).count(), 2);
+
+    // Replicate everything, then lose one pack on the replica
+    for entry in walkdir::WalkDir::new(repo_dir.path())
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let target = replica_dir
+            .path()
+            .join(entry.path().strip_prefix(repo_dir.path()).unwrap());
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target).unwrap();
+        } else {
+            fs::copy(entry.path(), &target).unwrap();
+        }
+    }
+    let replica = ghostsnap_core::storage::storage_for_location(&RepositoryLocation::Local(
+        replica_dir.path().to_path_buf(),
+    ))
+    .await
+    .unwrap();
+
+    let report = ghostsnap_core::check_replica(&repo, replica.as_ref(), &paths)
+        .await
+        .unwrap();
+    assert!(report.is_in_sync());
+
+    let lost = paths.iter().find(|p| p.ends_with(".pack")).unwrap();
+    replica.delete(lost).await.unwrap();
+    let report = ghostsnap_core::check_replica(&repo, replica.as_ref(), &paths)
+        .await
+        .unwrap();
+    let out_of_sync: Vec<_> = report.out_of_sync().collect();
+    assert_eq!(out_of_sync.len(), 1);
+    assert_eq!(&out_of_sync[0].path, lost);
+    assert_eq!(
+        out_of_sync[0].status,
+        ghostsnap_core::ReplicaObjectStatus::Missing
+    );
+    assert!(report.lag(chrono::Utc::now()).is_some());
+}
+
+/// Tests that a tree with a huge directory is stored as pages and can be read
+/// a page at a time.
+#[tokio::test]
+async fn test_paged_tree() {
+    use ghostsnap_core::snapshot::TREE_PAGE_NODES;
+
+    let repo_dir = tempdir().unwrap();
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    let node = |name: String| TreeNode {
+        name,
+        node_type: NodeType::File,
+        mode: 0o644,
+        uid: 0,
+        gid: 0,
+        size: 1,
+        mtime: 1_700_000_000,
+        link_target: None,
+        subtree_id: None,
+        chunks: Vec::new(),
+        xattr: None,
+        sparse_holes: None,
+        inode: None,
+        nlink: None,
+        hardlink_target: None,
+        device: None,
+    };
+    let mut tree = Tree::new();
+    tree.add_node(node("small.txt".to_string()));
+    for i in 0..TREE_PAGE_NODES * 3 {
+        tree.add_node(node(format!("huge/{:06}", i)));
     }
+
+    let tree_id = repo.save_tree(&tree).await.unwrap();
+    let root = repo.load_tree_root(&tree_id).await.unwrap();
+    assert!(root.is_paged());
+    assert!(root.nodes.is_empty());
+    assert_eq!(
+        root.pages.iter().map(|page| page.nodes).sum::<usize>(),
+        tree.nodes.len()
+    );
+
+    let loaded = repo.load_tree(&tree_id).await.unwrap();
+    assert_eq!(loaded.nodes.len(), tree.nodes.len());
+    assert!(loaded.nodes.windows(2).all(|w| w[0].name < w[1].name));
+
+    // Saving the same tree again reuses the stored pages
+    assert_eq!(repo.save_tree(&tree).await.unwrap(), tree_id);
+
+    // Only the page holding "small.txt" is read for it
+    let mut pages = repo.tree_pages(&tree_id, "small.txt").await.unwrap();
+    let page = pages.next_page().await.unwrap().unwrap();
+    assert!(page.find_node("small.txt").is_some());
+    assert!(pages.next_page().await.unwrap().is_none());
+
+    // Small trees are not paged
+    let small_id = repo.save_tree(&Tree::new()).await.unwrap();
+    assert!(!repo.load_tree_root(&small_id).await.unwrap().is_paged());
 }
 
-#[test]
-fn test_repository_location_parse_sftp_defaults() {
-    // No user, no port, no path: defaults to port 22 and empty user/path.
-    let location = RepositoryLocation::parse("sftp://example.com").unwrap();
-    match location {
-        RepositoryLocation::Sftp(sftp) => {
-            assert_eq!(sftp.host, "example.com");
-            assert_eq!(sftp.port, 22);
-            assert!(sftp.user.is_empty());
-            assert!(sftp.path.is_empty());
+/// Tests that large directories are stored as subtrees, read back into one
+/// tree, and only loaded when a path below them is wanted.
+#[tokio::test]
+async fn test_tree_subtrees() {
+    let repo_dir = tempdir().unwrap();
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    let node = |name: &str, node_type: NodeType, mtime: i64| TreeNode {
+        name: name.to_string(),
+        node_type,
+        mode: 0o644,
+        uid: 0,
+        gid: 0,
+        size: 1,
+        mtime,
+        link_target: None,
+        subtree_id: None,
+        chunks: Vec::new(),
+        xattr: None,
+        sparse_holes: None,
+        inode: None,
+        nlink: None,
+        hardlink_target: None,
+        device: None,
+    };
+    let site_tree = |mtime: i64| {
+        let mut tree = Tree::new();
+        tree.add_node(node("home", NodeType::Directory, 0));
+        for site in ["alpha", "beta"] {
+            let dir = format!("home/{}", site);
+            tree.add_node(node(&dir, NodeType::Directory, 0));
+            for i in 0..5 {
+                let mtime = if site == "alpha" { mtime } else { 0 };
+                tree.add_node(node(&format!("{}/{}.php", dir, i), NodeType::File, mtime));
+            }
         }
-        _ => panic!("expected sftp repository location"),
+        tree.add_node(node("top.txt", NodeType::File, 0));
+        tree
+    };
+    let tree = site_tree(0);
+
+    let tree_id = repo.save_tree_with(&tree, 5).await.unwrap();
+    let root = repo.load_tree_root(&tree_id).await.unwrap();
+    assert_eq!(root.nodes.len(), 4);
+    for name in ["home/alpha", "home/beta"] {
+        assert!(root.find_node(name).unwrap().subtree_id.is_some());
     }
+    assert!(root.find_node("top.txt").unwrap().subtree_id.is_none());
+
+    let loaded = repo.load_tree(&tree_id).await.unwrap();
+    let names = |tree: &Tree| -> Vec<String> {
+        let mut names: Vec<String> = tree.nodes.iter().map(|n| n.name.clone()).collect();
+        names.sort();
+        names
+    };
+    assert_eq!(names(&loaded), names(&tree));
+    assert!(loaded.nodes.iter().all(|n| n.subtree_id.is_none()));
+    assert_eq!(repo.save_tree_with(&tree, 5).await.unwrap(), tree_id);
+
+    // Only the subtree holding the wanted path is read
+    let wanted = repo
+        .load_tree_paths(&tree_id, &["home/beta/3.php".to_string()])
+        .await
+        .unwrap();
+    assert!(wanted.find_node("home/beta/3.php").is_some());
+    assert!(wanted.find_node("home/alpha/3.php").is_none());
+
+    // A change in one directory leaves the other directory's subtree as is
+    let objects = repo.tree_objects(&tree_id).await.unwrap();
+    assert_eq!(objects.len(), 3);
+    let changed_id = repo.save_tree_with(&site_tree(1), 5).await.unwrap();
+    let changed = repo.tree_objects(&changed_id).await.unwrap();
+    assert_eq!(changed.iter().filter(|id| objects.contains(id)).count(), 1);
 }
 
+/// Tests pack verification for `check --read-data` and the read counter
+/// behind its progress bars.
 #[tokio::test]
-async fn test_open_init_at_location_local() {
+async fn test_verify_pack_counts_bytes_read() {
     let repo_dir = tempdir().unwrap();
-    let location = RepositoryLocation::Local(repo_dir.path().join("repo"));
+    let source_dir = tempdir().unwrap();
 
-    let repo = Repository::init_at_location(location.clone(), "test-password")
+    let repo = Repository::init(repo_dir.path(), "test-password")
         .await
         .unwrap();
-    assert_eq!(repo.location().display(), location.display());
+    create_test_file(source_dir.path().join("data.bin"), &[9u8; 4096]);
+    backup_dir(&repo, source_dir.path()).await.unwrap();
 
-    let reopened = Repository::open_at_location(location.clone(), "test-password")
-        .await
-        .unwrap();
-    assert_eq!(reopened.location().display(), location.display());
+    let packs = repo.list_packs().await.unwrap();
+    assert!(!packs.is_empty());
+    let before = repo.bytes_read();
+    let read = repo.verify_pack(&packs[0]).await.unwrap();
+    assert!(read > 0);
+    assert_eq!(repo.bytes_read(), before + read);
+
+    assert!(
+        repo.verify_pack(&ghostsnap_core::PackID::generate())
+            .await
+            .is_err()
+    );
 }
 
+/// Tests the chunk verification behind `check --snapshot --read-data`.
 #[tokio::test]
-async fn test_s3_transport_config_persists_in_repo_config() {
+async fn test_verify_pack_chunks() {
     let repo_dir = tempdir().unwrap();
-    let mut repo = Repository::init(repo_dir.path(), "test-password")
+    let source_dir = tempdir().unwrap();
+
+    let repo = Repository::init(repo_dir.path(), "test-password")
         .await
         .unwrap();
+    create_test_file(source_dir.path().join("a.txt"), b"first file");
+    create_test_file(source_dir.path().join("b.txt"), b"second file");
+    let snapshot_id = backup_dir(&repo, source_dir.path()).await.unwrap();
 
-    let mut location = ghostsnap_core::storage::S3Location::new(
-        "example-bucket".to_string(),
-        "ghostsnap/backups".to_string(),
-    );
-    location.endpoint = Some("https://s3.example.com".to_string());
-    location.region = Some("us-east-2".to_string());
+    let snapshot = repo.load_snapshot(&snapshot_id).await.unwrap();
+    let tree = repo.load_tree(&snapshot.tree).await.unwrap();
+    let chunk_ids: Vec<_> = tree
+        .nodes
+        .iter()
+        .flat_map(|node| &node.chunks)
+        .map(|chunk_ref| chunk_ref.id)
+        .collect();
+    assert!(!chunk_ids.is_empty());
+    let pack_id = repo
+        .load_chunk_location(&chunk_ids[0])
+        .await
+        .unwrap()
+        .pack_id;
 
-    repo.set_s3_transport_config(
-        &location,
-        Some(S3RepoSse {
-            mode: "kms".to_string(),
-            kms_key_id: Some("alias/ghostsnap".to_string()),
-        }),
-    )
-    .await
-    .unwrap();
+    let bad = repo.verify_pack_chunks(&pack_id, &chunk_ids).await.unwrap();
+    assert!(bad.is_empty());
 
-    let reopened = Repository::open(repo_dir.path(), "test-password")
+    let unknown = ghostsnap_core::ChunkID::from_data(b"not stored");
+    let bad = repo
+        .verify_pack_chunks(&pack_id, &[chunk_ids[0], unknown])
         .await
         .unwrap();
+    assert_eq!(bad, vec![unknown]);
+}
 
-    match reopened.config().transport.as_ref() {
-        Some(RepoTransport::S3(config)) => {
-            assert_eq!(config.bucket, "example-bucket");
-            assert_eq!(config.prefix, "ghostsnap/backups");
-            assert_eq!(config.endpoint.as_deref(), Some("https://s3.example.com"));
-            assert_eq!(config.region.as_deref(), Some("us-east-2"));
-            assert_eq!(config.sse.as_ref().map(|s| s.mode.as_str()), Some("kms"));
-            assert_eq!(
-                config.sse.as_ref().and_then(|s| s.kms_key_id.as_deref()),
-                Some("alias/ghostsnap")
-            );
-        }
-        _ => panic!("expected persisted S3 transport config"),
-    }
+/// Tests that a pack ID already in the repository is never written again.
+#[tokio::test]
+async fn test_save_pack_refuses_overwrite() {
+    let repo_dir = tempdir().unwrap();
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
+
+    let pack_id = ghostsnap_core::PackID::generate();
+    let chunk_id = ghostsnap_core::ChunkID::from_data(b"original");
+    let mut pack = ghostsnap_core::PackFile::new(pack_id.clone());
+    pack.add_chunk(chunk_id, b"original").unwrap();
+    repo.save_pack(&pack).await.unwrap();
+
+    let mut other = ghostsnap_core::PackFile::new(pack_id.clone());
+    other
+        .add_chunk(ghostsnap_core::ChunkID::from_data(b"other"), b"other")
+        .unwrap();
+    let err = repo.save_pack(&other).await.unwrap_err();
+    assert!(matches!(err, ghostsnap_core::Error::PackExists { .. }));
+
+    let stored = repo.load_pack(&pack_id).await.unwrap();
+    assert!(stored.chunks.contains_key(&chunk_id));
 }
 
-#[test]
-fn test_azure_repository_location_parse() {
-    // Azure is now supported
-    let azure = RepositoryLocation::parse("azure:myaccount/mycontainer/prefix").unwrap();
-    match azure {
-        RepositoryLocation::Azure(az) => {
-            assert_eq!(az.account_name, "myaccount");
-            assert_eq!(az.container, "mycontainer");
-            assert_eq!(az.prefix, "prefix");
-        }
-        _ => panic!("expected azure repository location"),
-    }
+/// In-memory entries for exercising the source pipeline.
+struct MemorySource {
+    entries: std::vec::IntoIter<(TreeNode, Option<Vec<u8>>)>,
+    current: Option<Vec<u8>>,
 }
 
-#[test]
-fn test_b2_minio_parse_as_s3() {
-    // Backblaze B2 and MinIO are S3-compatible and map to an S3 location.
-    let b2 = RepositoryLocation::parse("b2:bucket/repo").unwrap();
-    match b2 {
-        RepositoryLocation::S3(s3) => {
-            assert_eq!(s3.bucket, "bucket");
-            assert_eq!(s3.prefix, "repo");
-        }
-        _ => panic!("expected b2 to map to an s3 repository location"),
+impl ghostsnap_core::BackupSource for MemorySource {
+    fn next_entry(&mut self) -> ghostsnap_core::Result<Option<TreeNode>> {
+        Ok(self.entries.next().map(|(node, data)| {
+            self.current = data;
+            node
+        }))
     }
 
-    let minio = RepositoryLocation::parse("minio://data/ghostsnap").unwrap();
-    match minio {
-        RepositoryLocation::S3(s3) => {
-            assert_eq!(s3.bucket, "data");
-            assert_eq!(s3.prefix, "ghostsnap");
+    fn open_entry(&mut self) -> ghostsnap_core::Result<Box<dyn std::io::Read + '_>> {
+        match &self.current {
+            Some(data) => Ok(Box::new(data.as_slice())),
+            None => Err(std::io::Error::other("unreadable").into()),
         }
-        _ => panic!("expected minio to map to an s3 repository location"),
     }
 }
 
-#[test]
-fn test_rclone_repository_location_parse() {
-    let location = RepositoryLocation::parse("rclone:myremote/backups/ghostsnap").unwrap();
-    match location {
-        RepositoryLocation::Rclone(rclone) => {
-            assert_eq!(rclone.remote, "myremote");
-            assert_eq!(rclone.path, "backups/ghostsnap");
+/// Counts what the writer reports and skips unreadable entries.
+#[derive(Default)]
+struct CountingObserver {
+    new_chunks: u64,
+    dedup_chunks: u64,
+    unchanged: Vec<String>,
+    failed: Vec<String>,
+}
+
+impl ghostsnap_core::SourceObserver for CountingObserver {
+    fn added(&mut self, _node: &TreeNode, contents: Option<&ghostsnap_core::StoredContents>) {
+        if let Some(contents) = contents {
+            self.new_chunks += contents.new_chunks;
+            self.dedup_chunks += contents.dedup_chunks;
         }
-        _ => panic!("expected rclone repository location"),
     }
 
-    // Test with just remote, no path
-    let location2 = RepositoryLocation::parse("rclone:gdrive").unwrap();
-    match location2 {
-        RepositoryLocation::Rclone(rclone) => {
-            assert_eq!(rclone.remote, "gdrive");
-            assert_eq!(rclone.path, "");
-        }
-        _ => panic!("expected rclone repository location"),
+    fn unchanged(&mut self, node: &TreeNode) {
+        self.unchanged.push(node.name.clone());
     }
-}
 
-#[test]
-fn test_s3_location_env_overrides() {
-    use ghostsnap_core::storage::S3Location;
+    fn failed(
+        &mut self,
+        node: &TreeNode,
+        _error: ghostsnap_core::Error,
+    ) -> ghostsnap_core::Result<()> {
+        self.failed.push(node.name.clone());
+        Ok(())
+    }
+}
 
-    // SAFETY: This test modifies env vars but runs in a single thread.
-    // Using serial_test or similar would be needed for parallel safety.
-    unsafe {
-        // Set test env vars
-        std::env::set_var("AWS_ENDPOINT_URL", "https://test.example.com");
-        std::env::set_var("AWS_REGION", "us-west-2");
+fn source_node(name: &str, node_type: NodeType, hardlink_target: Option<&str>) -> TreeNode {
+    TreeNode {
+        name: name.to_string(),
+        node_type,
+        mode: 0o644,
+        uid: 0,
+        gid: 0,
+        size: 0,
+        mtime: 0,
+        link_target: None,
+        subtree_id: None,
+        chunks: Vec::new(),
+        xattr: None,
+        sparse_holes: None,
+        inode: None,
+        nlink: None,
+        hardlink_target: hardlink_target.map(String::from),
+        device: None,
     }
+}
 
-    let location = S3Location::new("test-bucket".to_string(), "prefix".to_string());
-    assert!(location.endpoint.is_none());
-    assert!(location.region.is_none());
+/// Tests that the source writer stores file contents once and deduplicates
+/// across sources.
+#[tokio::test]
+async fn test_source_writer() {
+    let repo_dir = tempdir().unwrap();
+    let repo = Repository::init(repo_dir.path(), "test-password")
+        .await
+        .unwrap();
 
-    let location = location.with_env_overrides();
+    let source = || MemorySource {
+        entries: vec![
+            (source_node("dir", NodeType::Directory, None), None),
+            (
+                source_node("dir/a", NodeType::File, None),
+                Some(b"same contents".to_vec()),
+            ),
+            (
+                source_node("dir/b", NodeType::File, None),
+                Some(b"same contents".to_vec()),
+            ),
+            (source_node("dir/link", NodeType::File, Some("dir/a")), None),
+            (source_node("dir/broken", NodeType::File, None), None),
+        ]
+        .into_iter(),
+        current: None,
+    };
+
+    let mut writer = ghostsnap_core::SourceWriter::new(&repo);
+    let mut tree = Tree::new();
+    let mut observer = CountingObserver::default();
+    writer
+        .add_source(&mut source(), &mut tree, &mut observer)
+        .await
+        .unwrap();
+    writer.flush().await.unwrap();
+
+    assert_eq!((observer.new_chunks, observer.dedup_chunks), (1, 1));
+    assert_eq!(observer.failed, vec!["dir/broken".to_string()]);
+    assert_eq!(writer.bytes_added(), 13);
+    let names: Vec<_> = tree.nodes.iter().map(|n| n.name.as_str()).collect();
+    assert_eq!(names, vec!["dir", "dir/a", "dir/b", "dir/link"]);
+    assert_eq!(tree.nodes[1].size, 13);
+    assert_eq!(tree.nodes[1].chunks[0].id, tree.nodes[2].chunks[0].id);
+    assert!(tree.nodes[3].chunks.is_empty());
+    let chunk_id = tree.nodes[1].chunks[0].id;
     assert_eq!(
-        location.endpoint,
-        Some("https://test.example.com".to_string())
+        repo.load_chunk(&chunk_id).await.unwrap().as_ref(),
+        b"same contents"
     );
-    assert_eq!(location.region, Some("us-west-2".to_string()));
 
-    // SAFETY: Cleaning up env vars set by this test
-    unsafe {
-        std::env::remove_var("AWS_ENDPOINT_URL");
-        std::env::remove_var("AWS_REGION");
+    // A second source deduplicates against the saved packs; without an
+    // observer that skips them, unreadable entries abort
+    let mut writer = ghostsnap_core::SourceWriter::new(&repo);
+    let mut observer = CountingObserver::default();
+    let mut source = source();
+    let entries: Vec<_> = source.entries.by_ref().take(4).collect();
+    source.entries = entries.into_iter();
+    writer
+        .add_source(&mut source, &mut Tree::new(), &mut observer)
+        .await
+        .unwrap();
+    assert_eq!((observer.new_chunks, observer.dedup_chunks), (0, 2));
+    assert_eq!(writer.bytes_added(), 0);
+
+    let result = ghostsnap_core::SourceWriter::new(&repo)
+        .add_source(
+            &mut MemorySource {
+                entries: vec![(source_node("broken", NodeType::File, None), None)].into_iter(),
+                current: None,
+            },
+            &mut Tree::new(),
+            &mut (),
+        )
+        .await;
+    assert!(result.is_err());
+}
+
+/// Tests that reading several files at once builds the same tree, in the
+/// same order, as reading them one at a time.
+#[tokio::test]
+async fn test_parallel_source_writer() {
+    let source_dir = tempdir().unwrap();
+    let mut entries = Vec::new();
+    let mut state = 3u64;
+    for i in 0..24 {
+        let name = format!("file{:02}", i);
+        let path = source_dir.path().join(&name);
+        // Every third file repeats the one before it
+        if i % 3 != 2 {
+            let data: Vec<u8> = (0..64 * 1024 + i * 1000)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    (state >> 56) as u8
+                })
+                .collect();
+            fs::write(&path, data).unwrap();
+        } else {
+            let previous = source_dir.path().join(format!("file{:02}", i - 1));
+            fs::copy(previous, &path).unwrap();
+        }
+        entries.push((path, source_node(&name, NodeType::File, None)));
     }
+    entries.insert(
+        5,
+        (
+            source_dir.path().join("missing"),
+            source_node("missing", NodeType::File, None),
+        ),
+    );
+    entries.insert(
+        0,
+        (
+            source_dir.path().to_path_buf(),
+            source_node("", NodeType::Directory, None),
+        ),
+    );
 
-    // Test that explicit values are not overwritten
-    // SAFETY: Setting temp env var for this test
-    unsafe {
-        std::env::set_var("AWS_ENDPOINT_URL", "https://should-not-use.com");
+    let mut trees = Vec::new();
+    for jobs in [1, 4] {
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(repo_dir.path(), "test-password")
+            .await
+            .unwrap();
+        let mut writer = ghostsnap_core::SourceWriter::new(&repo).with_jobs(jobs);
+        let mut tree = Tree::new();
+        let mut observer = CountingObserver::default();
+        writer
+            .add_source(
+                &mut ghostsnap_core::FsSource::new(entries.clone()),
+                &mut tree,
+                &mut observer,
+            )
+            .await
+            .unwrap();
+        writer.flush().await.unwrap();
+
+        assert_eq!(observer.failed, vec!["missing".to_string()]);
+        assert_eq!((observer.new_chunks, observer.dedup_chunks), (16, 8));
+        let last = tree.nodes.last().unwrap();
+        let data = repo.load_chunk(&last.chunks[0].id).await.unwrap();
+        assert_eq!(
+            data.as_ref(),
+            fs::read(source_dir.path().join(&last.name)).unwrap()
+        );
+        trees.push(tree);
     }
 
-    let location = S3Location {
-        bucket: "bucket".to_string(),
-        prefix: "prefix".to_string(),
-        endpoint: Some("https://explicit.example.com".to_string()),
-        region: None,
-        sse: None,
+    let summary = |tree: &Tree| -> Vec<_> {
+        tree.nodes
+            .iter()
+            .map(|node| {
+                let chunks: Vec<_> = node.chunks.iter().map(|chunk| chunk.id).collect();
+                (node.name.clone(), node.size, chunks)
+            })
+            .collect()
     };
+    assert_eq!(trees[0].nodes.len(), 25);
+    assert_eq!(summary(&trees[0]), summary(&trees[1]));
+}
 
-    let location = location.with_env_overrides();
-    assert_eq!(
-        location.endpoint,
-        Some("https://explicit.example.com".to_string())
-    );
-
-    // SAFETY: Cleanup
-    unsafe {
-        std::env::remove_var("AWS_ENDPOINT_URL");
+/// Tests that when the original of a hardlink group can't be read, the next
+/// link is stored in its place and the others point at it.
+#[tokio::test]
+async fn test_source_writer_hardlink_original_left_out() {
+    let source_dir = tempdir().unwrap();
+    let linked = source_dir.path().join("linked");
+    fs::write(&linked, b"hardlinked contents").unwrap();
+    let entries = vec![
+        (
+            source_dir.path().join("missing"),
+            source_node("original", NodeType::File, None),
+        ),
+        (
+            linked.clone(),
+            source_node("link1", NodeType::File, Some("original")),
+        ),
+        (
+            linked,
+            source_node("link2", NodeType::File, Some("original")),
+        ),
+    ];
+
+    for jobs in [1, 4] {
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(repo_dir.path(), "test-password")
+            .await
+            .unwrap();
+        let mut writer = ghostsnap_core::SourceWriter::new(&repo).with_jobs(jobs);
+        let mut tree = Tree::new();
+        let mut observer = CountingObserver::default();
+        writer
+            .add_source(
+                &mut ghostsnap_core::FsSource::new(entries.clone()),
+                &mut tree,
+                &mut observer,
+            )
+            .await
+            .unwrap();
+        writer.flush().await.unwrap();
+
+        assert_eq!(observer.failed, vec!["original".to_string()]);
+        let names: Vec<_> = tree.nodes.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(names, vec!["link1", "link2"]);
+        assert!(tree.nodes[0].hardlink_target.is_none());
+        assert_eq!(tree.nodes[0].size, 19);
+        let data = repo.load_chunk(&tree.nodes[0].chunks[0].id).await.unwrap();
+        assert_eq!(data.as_ref(), b"hardlinked contents");
+        assert_eq!(tree.nodes[1].hardlink_target.as_deref(), Some("link1"));
     }
 }
 
+/// Tests that files unchanged since the parent tree keep its chunks without
+/// being read, and that any difference in size, mtime or inode, or a change
+/// in the second the parent was taken, makes them read again.
 #[tokio::test]
-async fn test_s3_sse_config_persists_and_resolves() {
-    use ghostsnap_core::storage::S3Location;
+async fn test_source_writer_parent() {
+    let source_dir = tempdir().unwrap();
+    let file = |name: &str, contents: &[u8], mtime: i64, inode: Option<u64>| {
+        let path = source_dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        let mut node = source_node(name, NodeType::File, None);
+        node.size = contents.len() as u64;
+        node.mtime = mtime;
+        node.inode = inode;
+        (path, node)
+    };
+    let parent_time = chrono::Utc::now();
+    let now = parent_time.timestamp();
 
     let repo_dir = tempdir().unwrap();
-    let mut repo = Repository::init(repo_dir.path(), "test-password")
+    let repo = Repository::init(repo_dir.path(), "test-password")
         .await
         .unwrap();
+    let names = ["same", "resized", "touched", "moved", "recent", "no-inode"];
+    let first: Vec<_> = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let mtime = if *name == "recent" { now } else { 1000 };
+            let inode = (*name != "no-inode").then_some(i as u64 + 1);
+            file(name, name.as_bytes(), mtime, inode)
+        })
+        .collect();
+    let mut parent = Tree::new();
+    let mut writer = ghostsnap_core::SourceWriter::new(&repo);
+    writer
+        .add_source(
+            &mut ghostsnap_core::FsSource::new(first.clone()),
+            &mut parent,
+            &mut (),
+        )
+        .await
+        .unwrap();
+    writer.flush().await.unwrap();
+
+    let mut second = first.clone();
+    // Reading the unchanged file would fail
+    second[0].0 = source_dir.path().join("gone");
+    second[1] = file("resized", b"resized!", 1000, Some(2));
+    second[2].1.mtime = 2000;
+    second[3].1.inode = Some(40);
+
+    for jobs in [1, 4] {
+        let mut writer = ghostsnap_core::SourceWriter::new(&repo)
+            .with_jobs(jobs)
+            .with_parent(&parent, parent_time);
+        let mut tree = Tree::new();
+        let mut observer = CountingObserver::default();
+        writer
+            .add_source(
+                &mut ghostsnap_core::FsSource::new(second.clone()),
+                &mut tree,
+                &mut observer,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(observer.unchanged, vec!["same".to_string()]);
+        assert!(observer.failed.is_empty());
+        assert_eq!(tree.nodes[0].chunks[0].id, parent.nodes[0].chunks[0].id);
+        assert_eq!(tree.nodes[1].size, 8);
+        assert_eq!(tree.nodes.len(), names.len());
+    }
+}
 
-    let mut location = S3Location::new("sse-test-bucket".to_string(), "backups".to_string());
-    location.endpoint = Some("https://s3.example.com".to_string());
+/// Tests restoring a snapshot's files as objects in another storage
+/// location.
+#[tokio::test]
+async fn test_restore_to_storage_target() {
+    use ghostsnap_core::target::{plan_restore, restore_to};
 
-    // Set SSE config with AES256
-    repo.set_s3_transport_config(
-        &location,
-        Some(S3RepoSse {
-            mode: "aes256".to_string(),
-            kms_key_id: None,
-        }),
-    )
-    .await
-    .unwrap();
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+    let bucket_dir = tempdir().unwrap();
 
-    // Reopen and verify SSE is persisted
-    let reopened = Repository::open(repo_dir.path(), "test-password")
+    let repo = Repository::init(repo_dir.path(), "test-password")
         .await
         .unwrap();
+    create_test_file(source_dir.path().join("site/index.php"), b"<?php echo 1;");
+    create_test_file(source_dir.path().join("site/uploads/a.jpg"), &[7u8; 4096]);
+    create_test_file(source_dir.path().join("notes.txt"), b"not selected");
 
-    match reopened.config().transport.as_ref() {
-        Some(RepoTransport::S3(config)) => {
-            assert_eq!(config.sse.as_ref().map(|s| s.mode.as_str()), Some("aes256"));
-            assert!(
-                config
-                    .sse
-                    .as_ref()
-                    .and_then(|s| s.kms_key_id.as_ref())
-                    .is_none()
-            );
-        }
-        _ => panic!("expected persisted S3 transport config with SSE"),
-    }
+    let snapshot_id = backup_dir(&repo, source_dir.path()).await.unwrap();
+    let snapshot = repo.load_snapshot(&snapshot_id).await.unwrap();
+    let tree = repo.load_tree(&snapshot.tree).await.unwrap();
+
+    let nodes = plan_restore(&tree, &["site".to_string()]);
+    let storage = ghostsnap_core::storage::local_storage(bucket_dir.path());
+    let mut target = ghostsnap_core::StorageTarget::new(storage);
+    let totals = restore_to(&repo, &tree, &nodes, &mut target).await.unwrap();
+
+    assert_eq!(totals.bytes, 13 + 4096);
+    assert_files_equal(
+        source_dir.path().join("site/index.php"),
+        bucket_dir.path().join("site/index.php"),
+    );
+    assert_files_equal(
+        source_dir.path().join("site/uploads/a.jpg"),
+        bucket_dir.path().join("site/uploads/a.jpg"),
+    );
+    assert!(!bucket_dir.path().join("notes.txt").exists());
 }
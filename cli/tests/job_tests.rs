@@ -1139,3 +1139,277 @@ tags = ["job-b"]
         stdout
     );
 }
+
+#[test]
+fn test_job_drill() {
+    let temp = tempdir().unwrap();
+    let config_path = temp.path().join("jobs.toml");
+    let repo_path = temp.path().join("repo");
+    let source_path = temp.path().join("source");
+    let password_file = temp.path().join("password");
+
+    fs::create_dir_all(source_path.join("site")).unwrap();
+    fs::write(&password_file, "test-password").unwrap();
+    fs::write(source_path.join("site/index.php"), "<?php echo 'ok';").unwrap();
+    fs::write(
+        source_path.join("app.sql"),
+        "-- MySQL dump 10.13\nCREATE TABLE t (id int);\n-- Dump completed on 2026-10-16\n",
+    )
+    .unwrap();
+
+    let paths_str = format!("\"{}\"", source_path.to_str().unwrap());
+    let config = format!(
+        r#"version = 1
+
+[defaults]
+password_file = "{}"
+repository = "{}"
+
+[jobs.web]
+paths = [{}]
+tags = ["web"]
+drill_require = ["*/index.php"]
+drill_command = "grep -q ok site/index.php && test -d \"$GHOSTSNAP_DRILL_DIR\""
+
+[jobs.wordpress]
+paths = [{}]
+tags = ["wordpress"]
+drill_require = ["*/wp-config.php"]
+"#,
+        password_file.to_str().unwrap(),
+        repo_path.to_str().unwrap(),
+        paths_str,
+        paths_str
+    );
+    fs::write(&config_path, &config).unwrap();
+
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &["init", repo_path.to_str().unwrap()],
+        "test-password",
+    );
+    assert!(success, "Init should succeed: {}", stderr);
+
+    let config_arg = config_path.to_str().unwrap();
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &["job", "--config", config_arg, "run", "--all"],
+        "test-password",
+    );
+    assert!(success, "job run --all should succeed: {}\n{}", stderr, stdout);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &["job", "--config", config_arg, "drill", "web"],
+        "test-password",
+    );
+    assert!(success, "drill should pass: {}\n{}", stderr, stdout);
+    assert!(stdout.contains("Dumps: 1 complete"), "{}", stdout);
+    assert!(stdout.contains("Drill: PASS"), "{}", stdout);
+
+    let (success, stdout, _stderr) = run_ghostsnap_with_password(
+        &["job", "--config", config_arg, "drill", "--all"],
+        "test-password",
+    );
+    assert!(!success, "drill --all should fail: {}", stdout);
+    assert!(stdout.contains("PASS  web"), "{}", stdout);
+    assert!(stdout.contains("FAIL  wordpress"), "{}", stdout);
+    assert!(
+        stdout.contains("No restored file matches '*/wp-config.php'"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn test_job_run_report_and_notify() {
+    let temp = tempdir().unwrap();
+    let config_path = temp.path().join("jobs.toml");
+    let repo_path = temp.path().join("repo");
+    let source_path = temp.path().join("source");
+    let password_file = temp.path().join("password");
+    let reports_dir = temp.path().join("reports");
+    let notified = temp.path().join("notified.txt");
+
+    fs::create_dir_all(&source_path).unwrap();
+    fs::write(&password_file, "test-password").unwrap();
+    fs::write(source_path.join("test.txt"), "Report test").unwrap();
+
+    let config = format!(
+        r#"version = 1
+
+[defaults]
+password_file = "{}"
+notify_command = '''printf '%s\n%s\n' "$GHOSTSNAP_REPORT_STATUS" "$GHOSTSNAP_REPORT_SUMMARY" > {} && test -s "$GHOSTSNAP_REPORT"'''
+
+[jobs.test-job]
+repository = "{}"
+paths = ["{}"]
+"#,
+        password_file.to_str().unwrap(),
+        notified.to_str().unwrap(),
+        repo_path.to_str().unwrap(),
+        source_path.to_str().unwrap()
+    );
+    fs::write(&config_path, &config).unwrap();
+
+    let (success, _stdout, stderr) = run_ghostsnap_with_password(
+        &["init", repo_path.to_str().unwrap()],
+        "test-password",
+    );
+    assert!(success, "Init should succeed: {}", stderr);
+
+    let report_arg = reports_dir.join("report-{date}.json");
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "job",
+            "--config",
+            config_path.to_str().unwrap(),
+            "run",
+            "test-job",
+            "--report",
+            report_arg.to_str().unwrap(),
+        ],
+        "test-password",
+    );
+    assert!(success, "job run should succeed: {}\n{}", stderr, stdout);
+    assert!(stdout.contains("Notify: OK"), "{}", stdout);
+
+    let reports: Vec<_> = fs::read_dir(&reports_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(reports.len(), 1);
+    assert!(
+        reports[0].starts_with("report-20") && !reports[0].contains("{date}"),
+        "{:?}",
+        reports
+    );
+    let report = fs::read_to_string(reports_dir.join(&reports[0])).unwrap();
+    assert!(report.contains("\"succeeded\": 1"), "{}", report);
+
+    let notified = fs::read_to_string(&notified).unwrap();
+    assert!(notified.starts_with("ok\ntest-job: ok in "), "{}", notified);
+}
+
+/// Jobs write a manifest of each new snapshot, like `backup --manifest`.
+#[test]
+fn test_job_run_manifest() {
+    let temp = tempdir().unwrap();
+    let config_path = temp.path().join("jobs.toml");
+    let repo_path = temp.path().join("repo");
+    let source_path = temp.path().join("source");
+    let catalog_path = temp.path().join("catalog");
+    let password_file = temp.path().join("password");
+    fs::create_dir_all(&source_path).unwrap();
+    fs::create_dir_all(&catalog_path).unwrap();
+    fs::write(&password_file, "test-password").unwrap();
+    fs::write(source_path.join("report.txt"), b"quarterly numbers").unwrap();
+
+    let config = format!(
+        r#"version = 1
+
+[jobs.catalogued]
+repository = "{}"
+password_file = "{}"
+paths = ["{}"]
+manifest = "{}"
+"#,
+        repo_path.display(),
+        password_file.display(),
+        source_path.display(),
+        catalog_path.display()
+    );
+    fs::write(&config_path, config).unwrap();
+
+    let (success, _stdout, stderr) =
+        run_ghostsnap_with_password(&["init", repo_path.to_str().unwrap()], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "job",
+            "--config",
+            config_path.to_str().unwrap(),
+            "run",
+            "catalogued",
+        ],
+        "test-password",
+    );
+    assert!(success, "job run should succeed: {}\n{}", stderr, stdout);
+    assert!(stdout.contains("Manifest:"), "{}", stdout);
+
+    let manifest_path = fs::read_dir(&catalog_path)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .expect("manifest should be written");
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&fs::read(manifest_path).unwrap()).unwrap();
+    let report = manifest["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|f| f["path"] == "report.txt")
+        .unwrap();
+    assert_eq!(
+        report["blake3"],
+        blake3::hash(b"quarterly numbers").to_hex().as_str()
+    );
+}
+
+/// With `base`, snapshot paths are relative to it instead of to each path,
+/// so paths from different directories don't collide.
+#[test]
+fn test_job_run_base() {
+    let temp = tempdir().unwrap();
+    let config_path = temp.path().join("jobs.toml");
+    let repo_path = temp.path().join("repo");
+    let root = temp.path().join("root");
+    let password_file = temp.path().join("password");
+    fs::create_dir_all(root.join("etc/nginx")).unwrap();
+    fs::create_dir_all(root.join("usr/local/hestia/conf")).unwrap();
+    fs::write(&password_file, "test-password").unwrap();
+    fs::write(root.join("etc/nginx/main.conf"), b"nginx").unwrap();
+    fs::write(root.join("usr/local/hestia/conf/main.conf"), b"hestia").unwrap();
+
+    let config = format!(
+        r#"version = 1
+
+[jobs.system]
+repository = "{repo}"
+password_file = "{password}"
+base = "{root}"
+paths = ["{root}/etc/nginx", "{root}/usr/local/hestia/conf"]
+"#,
+        repo = repo_path.display(),
+        password = password_file.display(),
+        root = root.display(),
+    );
+    fs::write(&config_path, config).unwrap();
+
+    let (success, _stdout, stderr) =
+        run_ghostsnap_with_password(&["init", repo_path.to_str().unwrap()], "test-password");
+    assert!(success, "Init should succeed: {}", stderr);
+
+    let (success, stdout, stderr) = run_ghostsnap_with_password(
+        &[
+            "job",
+            "--config",
+            config_path.to_str().unwrap(),
+            "run",
+            "system",
+        ],
+        "test-password",
+    );
+    assert!(success, "job run should succeed: {}\n{}", stderr, stdout);
+
+    let (success, ls_stdout, stderr) = run_ghostsnap_with_password(
+        &["--repo", repo_path.to_str().unwrap(), "ls", "latest", "-r"],
+        "test-password",
+    );
+    assert!(success, "ls should succeed: {}", stderr);
+    assert!(ls_stdout.contains("etc/nginx/main.conf"), "{}", ls_stdout);
+    assert!(
+        ls_stdout.contains("usr/local/hestia/conf/main.conf"),
+        "{}",
+        ls_stdout
+    );
+}
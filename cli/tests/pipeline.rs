@@ -0,0 +1,386 @@
+//! End-to-end tests of the backup pipeline over fault-injecting storage.
+//!
+//! Repositories here run on a `MockBackend` wrapping local storage, which
+//! adds latency and fails, throttles or cuts off requests on demand. Each
+//! test goes through backup, damage, check, repair and restore, so that a
+//! regression anywhere in the storage path shows up as a failed restore or
+//! an undetected corruption.
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tempfile::tempdir;
+
+use ghostsnap_backends::{Fault, MockBackend, Operation};
+use ghostsnap_core::pack::PackManager;
+use ghostsnap_core::snapshot::{Snapshot, Tree};
+use ghostsnap_core::{
+    ChunkRef, FsSource, NodeType, PasswordKey, Repository, SnapshotID, SourceObserver,
+    SourceWriter, TreeNode,
+};
+
+const PASSWORD: &str = "test-password";
+
+/// Writes a few files, one of them spanning several chunks.
+fn create_source(dir: &Path) {
+    fs::write(dir.join("small.txt"), b"small file").unwrap();
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    fs::write(dir.join("nested/notes.txt"), b"nested file contents").unwrap();
+
+    let mut state = 7u64;
+    let large: Vec<u8> = (0..6 * 1024 * 1024)
+        .map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (state >> 56) as u8
+        })
+        .collect();
+    fs::write(dir.join("large.bin"), large).unwrap();
+}
+
+/// Backs up the regular files below `source`.
+async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<SnapshotID> {
+    let chunker = repo.chunker();
+    let mut pack_manager = PackManager::new(1024 * 1024);
+    let mut tree = Tree::new();
+
+    for entry in walkdir::WalkDir::new(source).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let data = fs::read(entry.path())?;
+        let mut chunks = Vec::new();
+        for chunk in chunker.chunk_data(&data) {
+            let chunk_id = chunk.id();
+            if !repo.has_chunk(&chunk_id).await?
+                && let Some(pack) = pack_manager.add_chunk(chunk_id, chunk.data())?
+            {
+                save_pack(repo, &pack).await?;
+            }
+            chunks.push(ChunkRef {
+                id: chunk_id,
+                offset: 0,
+                length: chunk.data().len() as u32,
+            });
+        }
+
+        let name = entry.path().strip_prefix(source)?;
+        tree.add_node(TreeNode {
+            name: name.to_string_lossy().to_string(),
+            node_type: NodeType::File,
+            mode: 0o644,
+            uid: 0,
+            gid: 0,
+            size: data.len() as u64,
+            mtime: 0,
+            link_target: None,
+            subtree_id: None,
+            chunks,
+            xattr: None,
+            sparse_holes: None,
+            inode: None,
+            nlink: None,
+            hardlink_target: None,
+            device: None,
+        });
+    }
+    if let Some(pack) = pack_manager.finish_current_pack() {
+        save_pack(repo, &pack).await?;
+    }
+
+    let tree_id = repo.save_tree(&tree).await?;
+    let snapshot = Snapshot::new(vec![source.to_path_buf()], tree_id);
+    repo.save_snapshot(&snapshot).await?;
+    repo.save_index().await?;
+    Ok(snapshot.id)
+}
+
+async fn save_pack(repo: &Repository, pack: &ghostsnap_core::PackFile) -> anyhow::Result<()> {
+    repo.save_pack(pack).await?;
+    for (chunk_id, entry) in &pack.chunks {
+        repo.save_chunk_location(chunk_id, &pack.header.pack_id, entry.offset, entry.length)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Leaves out files that can't be read and carries on, like `backup`.
+struct SkipUnreadable;
+
+impl SourceObserver for SkipUnreadable {
+    fn failed(
+        &mut self,
+        _node: &TreeNode,
+        _error: ghostsnap_core::Error,
+    ) -> ghostsnap_core::Result<()> {
+        Ok(())
+    }
+}
+
+/// Backs up the regular files below `source` through a [`SourceWriter`]
+/// reading `jobs` files at once.
+async fn source_backup(
+    repo: &Repository,
+    source: &Path,
+    jobs: usize,
+) -> anyhow::Result<SnapshotID> {
+    let mut entries = Vec::new();
+    for entry in walkdir::WalkDir::new(source).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let name = entry.path().strip_prefix(source)?;
+        entries.push((
+            entry.path().to_path_buf(),
+            TreeNode {
+                name: name.to_string_lossy().to_string(),
+                node_type: NodeType::File,
+                mode: 0o644,
+                uid: 0,
+                gid: 0,
+                size: entry.metadata()?.len(),
+                mtime: 0,
+                link_target: None,
+                subtree_id: None,
+                chunks: Vec::new(),
+                xattr: None,
+                sparse_holes: None,
+                inode: None,
+                nlink: None,
+                hardlink_target: None,
+                device: None,
+            },
+        ));
+    }
+
+    let mut writer = SourceWriter::new(repo)
+        .with_pack_size(1024 * 1024)
+        .with_jobs(jobs);
+    let mut tree = Tree::new();
+    writer
+        .add_source(&mut FsSource::new(entries), &mut tree, &mut SkipUnreadable)
+        .await?;
+    writer.flush().await?;
+
+    let tree_id = repo.save_tree(&tree).await?;
+    let snapshot = Snapshot::new(vec![source.to_path_buf()], tree_id);
+    repo.save_snapshot(&snapshot).await?;
+    repo.save_index().await?;
+    Ok(snapshot.id)
+}
+
+/// Restores a snapshot's files below `target`.
+async fn restore_snapshot(
+    repo: &Repository,
+    snapshot_id: &SnapshotID,
+    target: &Path,
+) -> anyhow::Result<()> {
+    let snapshot = repo.load_snapshot(snapshot_id).await?;
+    let tree = repo.load_tree(&snapshot.tree).await?;
+    for node in &tree.nodes {
+        let mut data = Vec::new();
+        for chunk_ref in &node.chunks {
+            data.extend_from_slice(&repo.load_chunk(&chunk_ref.id).await?);
+        }
+        let dest = target.join(&node.name);
+        fs::create_dir_all(dest.parent().unwrap())?;
+        fs::write(dest, data)?;
+    }
+    Ok(())
+}
+
+fn assert_restored(source: &Path, target: &Path) {
+    for name in ["small.txt", "nested/notes.txt", "large.bin"] {
+        assert_eq!(
+            fs::read(source.join(name)).unwrap(),
+            fs::read(target.join(name)).unwrap(),
+            "{} differs after restore",
+            name
+        );
+    }
+}
+
+/// Reopens the repository, so that nothing is served from the pack cache.
+async fn reopen(mock: &MockBackend) -> Repository {
+    Repository::open_with_storage(mock.storage(), &PasswordKey::new(PASSWORD))
+        .await
+        .unwrap()
+}
+
+/// Tests that a corrupted pack is detected, removed, re-uploaded by the next
+/// backup, and that the snapshot then restores intact.
+#[tokio::test]
+async fn test_backup_corrupt_check_repair_restore() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+    let restore_dir = tempdir().unwrap();
+    create_source(source_dir.path());
+
+    let mock = MockBackend::local(repo_dir.path()).with_latency(Duration::from_millis(1));
+    let repo = Repository::init_with_storage(mock.storage(), &PasswordKey::new(PASSWORD))
+        .await
+        .unwrap();
+    let first = backup_dir(&repo, source_dir.path()).await.unwrap();
+    assert!(mock.calls(Operation::Write) > 0);
+
+    let packs = repo.list_packs().await.unwrap();
+    assert!(packs.len() > 1);
+    let damaged = packs[0].clone();
+    mock.corrupt(&format!("data/{}.pack", damaged), 4096)
+        .await
+        .unwrap();
+
+    // check finds the damage and restore fails
+    let repo = reopen(&mock).await;
+    let stats = repo.verify(true).await.unwrap();
+    assert_eq!(stats.corrupt_packs, 1);
+    assert_eq!(stats.valid_packs, packs.len() - 1);
+    let report = repo.cross_check_index().await.unwrap();
+    assert_eq!(report.unreadable_packs.len(), 1);
+    assert_eq!(report.unreadable_packs[0].0, damaged);
+    assert!(
+        restore_snapshot(&repo, &first, restore_dir.path())
+            .await
+            .is_err()
+    );
+
+    // Repair: drop the pack, then back up the same data again
+    assert!(repo.remove_damaged_pack(&damaged).await.unwrap() > 0);
+    let second = backup_dir(&repo, source_dir.path()).await.unwrap();
+
+    let repo = reopen(&mock).await;
+    let stats = repo.verify(true).await.unwrap();
+    assert_eq!(stats.corrupt_packs, 0);
+    assert!(repo.cross_check_index().await.unwrap().is_consistent());
+    restore_snapshot(&repo, &second, restore_dir.path())
+        .await
+        .unwrap();
+    assert_restored(source_dir.path(), restore_dir.path());
+
+    // The first snapshot references the same chunks and is whole again
+    let first_dir = tempdir().unwrap();
+    restore_snapshot(&repo, &first, first_dir.path())
+        .await
+        .unwrap();
+    assert_restored(source_dir.path(), first_dir.path());
+}
+
+/// Tests that a pack cut off midway by a failed upload is caught by check
+/// and cleaned up, and that the retried backup restores intact.
+#[tokio::test]
+async fn test_partial_write_is_detected_and_repaired() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+    let restore_dir = tempdir().unwrap();
+    create_source(source_dir.path());
+
+    let mock = MockBackend::local(repo_dir.path());
+    let repo = Repository::init_with_storage(mock.storage(), &PasswordKey::new(PASSWORD))
+        .await
+        .unwrap();
+
+    mock.inject(Fault::partial_write().with_prefix("data/"));
+    assert!(backup_dir(&repo, source_dir.path()).await.is_err());
+    assert_eq!(mock.faults_injected(), 1);
+
+    // The truncated pack is stored but never made it into the index
+    let repo = reopen(&mock).await;
+    let packs = repo.list_packs().await.unwrap();
+    assert_eq!(packs.len(), 1);
+    assert_eq!(repo.verify(true).await.unwrap().corrupt_packs, 1);
+    let report = repo.cross_check_index().await.unwrap();
+    assert_eq!(report.unreadable_packs.len(), 1);
+
+    assert_eq!(repo.remove_damaged_pack(&packs[0]).await.unwrap(), 0);
+    let snapshot_id = backup_dir(&repo, source_dir.path()).await.unwrap();
+
+    let repo = reopen(&mock).await;
+    assert!(repo.cross_check_index().await.unwrap().is_consistent());
+    restore_snapshot(&repo, &snapshot_id, restore_dir.path())
+        .await
+        .unwrap();
+    assert_restored(source_dir.path(), restore_dir.path());
+}
+
+/// Tests that transient failures and throttling fail the operation cleanly,
+/// and that running it again succeeds without leftovers.
+#[tokio::test]
+async fn test_transient_faults_and_throttling() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+    let restore_dir = tempdir().unwrap();
+    create_source(source_dir.path());
+
+    let mock = MockBackend::local(repo_dir.path()).with_latency(Duration::from_millis(1));
+    let repo = Repository::init_with_storage(mock.storage(), &PasswordKey::new(PASSWORD))
+        .await
+        .unwrap();
+
+    // A failed snapshot upload leaves no snapshot behind
+    mock.inject(Fault::transient(Operation::Write).with_prefix("snapshots/"));
+    let err = backup_dir(&repo, source_dir.path()).await.unwrap_err();
+    let err = err.downcast::<ghostsnap_core::Error>().unwrap();
+    assert_eq!(err.http_status(), Some(500));
+    assert!(repo.list_snapshots().await.unwrap().is_empty());
+
+    let snapshot_id = backup_dir(&repo, source_dir.path()).await.unwrap();
+    assert_eq!(
+        repo.list_snapshots().await.unwrap(),
+        vec![snapshot_id.clone()]
+    );
+
+    // Throttled reads surface the requested delay
+    let repo = reopen(&mock).await;
+    mock.inject(Fault::throttle(Operation::Read, Duration::from_millis(20)).with_prefix("data/"));
+    let err = restore_snapshot(&repo, &snapshot_id, restore_dir.path())
+        .await
+        .unwrap_err()
+        .downcast::<ghostsnap_core::Error>()
+        .unwrap();
+    assert!(err.is_throttling());
+    assert_eq!(err.retry_after(), Some(Duration::from_millis(20)));
+
+    restore_snapshot(&repo, &snapshot_id, restore_dir.path())
+        .await
+        .unwrap();
+    assert_restored(source_dir.path(), restore_dir.path());
+    assert_eq!(repo.verify(true).await.unwrap().corrupt_packs, 0);
+    assert_eq!(mock.faults_injected(), 2);
+}
+
+/// Tests that a failed pack upload aborts a backup instead of leaving the
+/// file out like an unreadable one, which would save a snapshot referencing
+/// chunks that were never stored.
+#[tokio::test]
+async fn test_failed_pack_upload_aborts_backup() {
+    let repo_dir = tempdir().unwrap();
+    let source_dir = tempdir().unwrap();
+    let restore_dir = tempdir().unwrap();
+    create_source(source_dir.path());
+
+    let mock = MockBackend::local(repo_dir.path());
+    let repo = Repository::init_with_storage(mock.storage(), &PasswordKey::new(PASSWORD))
+        .await
+        .unwrap();
+
+    for jobs in [1, 4] {
+        mock.inject(Fault::transient(Operation::Write).with_prefix("data/"));
+        let err = source_backup(&repo, source_dir.path(), jobs)
+            .await
+            .unwrap_err()
+            .downcast::<ghostsnap_core::Error>()
+            .unwrap();
+        assert_eq!(err.http_status(), Some(500), "jobs {}", jobs);
+        assert!(repo.list_snapshots().await.unwrap().is_empty());
+    }
+    assert_eq!(mock.faults_injected(), 2);
+
+    let repo = reopen(&mock).await;
+    let snapshot_id = source_backup(&repo, source_dir.path(), 1).await.unwrap();
+    let repo = reopen(&mock).await;
+    restore_snapshot(&repo, &snapshot_id, restore_dir.path())
+        .await
+        .unwrap();
+    assert_restored(source_dir.path(), restore_dir.path());
+}
@@ -32,11 +32,10 @@ use std::io::Write;
 use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use ghostsnap_core::chunker::Chunker;
 use ghostsnap_core::pack::PackManager;
 use ghostsnap_core::snapshot::{Snapshot, Tree};
 use ghostsnap_core::storage::{RcloneLocation, RepositoryLocation};
-use ghostsnap_core::{ChunkRef, NodeType, RepoTransport, Repository, TreeNode};
+use ghostsnap_core::{ChunkRef, NodeType, RepoTransport, Repository, SnapshotID, TreeNode};
 use tempfile::tempdir;
 
 fn create_test_file<P: AsRef<Path>>(path: P, contents: &[u8]) {
@@ -47,10 +46,10 @@ fn create_test_file<P: AsRef<Path>>(path: P, contents: &[u8]) {
     file.write_all(contents).unwrap();
 }
 
-async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<String> {
+async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<SnapshotID> {
     use walkdir::WalkDir;
 
-    let chunker = Chunker::new_default();
+    let chunker = repo.chunker();
     let mut pack_manager = PackManager::new(64 * 1024 * 1024);
     let mut tree = Tree::new();
 
@@ -125,6 +124,7 @@ async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<String>
             inode: None,
             nlink: None,
             hardlink_target: None,
+            device: None,
         });
     }
 
@@ -148,9 +148,9 @@ async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<String>
 async fn copy_snapshot(
     src_repo: &Repository,
     dst_repo: &Repository,
-    snapshot_id: &str,
+    snapshot_id: &SnapshotID,
 ) -> anyhow::Result<()> {
-    let snapshot = src_repo.load_snapshot(&snapshot_id.to_string()).await?;
+    let snapshot = src_repo.load_snapshot(snapshot_id).await?;
     let tree = src_repo.load_tree(&snapshot.tree).await?;
 
     let mut chunks_needed = std::collections::HashSet::new();
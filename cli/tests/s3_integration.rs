@@ -4,11 +4,10 @@ use std::io::Write;
 use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use ghostsnap_core::chunker::Chunker;
 use ghostsnap_core::pack::PackManager;
 use ghostsnap_core::snapshot::{Snapshot, Tree};
 use ghostsnap_core::storage::{RepositoryLocation, S3Location};
-use ghostsnap_core::{ChunkRef, NodeType, RepoTransport, Repository, TreeNode};
+use ghostsnap_core::{ChunkRef, NodeType, RepoTransport, Repository, SnapshotID, TreeNode};
 use tempfile::tempdir;
 
 fn create_test_file<P: AsRef<Path>>(path: P, contents: &[u8]) {
@@ -19,10 +18,10 @@ fn create_test_file<P: AsRef<Path>>(path: P, contents: &[u8]) {
     file.write_all(contents).unwrap();
 }
 
-async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<String> {
+async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<SnapshotID> {
     use walkdir::WalkDir;
 
-    let chunker = Chunker::new_default();
+    let chunker = repo.chunker();
     let mut pack_manager = PackManager::new(64 * 1024 * 1024);
     let mut tree = Tree::new();
 
@@ -97,6 +96,7 @@ async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<String>
             inode: None,
             nlink: None,
             hardlink_target: None,
+            device: None,
         });
     }
 
@@ -119,9 +119,9 @@ async fn backup_dir(repo: &Repository, source: &Path) -> anyhow::Result<String>
 async fn copy_snapshot(
     src_repo: &Repository,
     dst_repo: &Repository,
-    snapshot_id: &str,
+    snapshot_id: &SnapshotID,
 ) -> anyhow::Result<()> {
-    let snapshot = src_repo.load_snapshot(&snapshot_id.to_string()).await?;
+    let snapshot = src_repo.load_snapshot(snapshot_id).await?;
     let tree = src_repo.load_tree(&snapshot.tree).await?;
 
     let mut chunks_needed = std::collections::HashSet::new();
@@ -193,7 +193,7 @@ fn s3_test_config() -> Option<(RepositoryLocation, String)> {
     location.endpoint = env::var("GHOSTSNAP_TEST_S3_ENDPOINT").ok();
     location.region = env::var("GHOSTSNAP_TEST_S3_REGION").ok();
 
-    Some((RepositoryLocation::S3(location), password))
+    Some((RepositoryLocation::S3(Box::new(location)), password))
 }
 
 #[tokio::test]
@@ -352,7 +352,7 @@ async fn test_s3_reopen_with_endpoint_env_var_opt_in() {
         std::env::set_var("AWS_ENDPOINT_URL", &endpoint);
     }
 
-    let minimal_location = RepositoryLocation::S3(S3Location::new(bucket, prefix));
+    let minimal_location = RepositoryLocation::S3(Box::new(S3Location::new(bucket, prefix)));
     let reopened = Repository::open_at_location(minimal_location, &password)
         .await
         .expect("Should be able to reopen using AWS_ENDPOINT_URL env var");
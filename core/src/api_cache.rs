@@ -0,0 +1,398 @@
+//! Metadata caching for object stores (`--metadata-cache`).
+//!
+//! `snapshots`, `check` and `prune` list prefixes and probe objects in
+//! bursts of thousands of LIST and HEAD requests, and object stores bill
+//! every one of them. While the cache is on, storage is wrapped in a
+//! [`CachedStorage`] that:
+//!
+//! - keeps each listing for the cache lifetime and answers `exists` for
+//!   objects in a listed prefix from it
+//! - keeps `exists` and `metadata` answers for the same lifetime
+//! - merges concurrent identical LIST, HEAD and STAT requests into one
+//! - applies its own writes and deletes to the cached answers
+//!
+//! Objects written by other processes show up once the cached answer
+//! expires. Reads of object contents are never cached.
+//!
+//! Every request that reaches the backend, and every one answered from the
+//! cache instead, is counted per method; [`api_calls`] returns the counts
+//! of the run.
+
+use crate::storage::{ObjectMetadata, RepositoryLocation, RepositoryStorage, TierStatus};
+use crate::{AccessTier, ChunkID, RehydratePriority, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Cache lifetime in milliseconds; zero when the cache is off.
+static TTL_MS: AtomicU64 = AtomicU64::new(0);
+
+static CALLS: Mutex<ApiCallCounts> = Mutex::new(ApiCallCounts {
+    methods: BTreeMap::new(),
+});
+
+/// Turns on the metadata cache, with answers kept for `ttl`, for storage
+/// opened from now on.
+pub fn enable(ttl: Duration) {
+    TTL_MS.store(ttl.as_millis().max(1) as u64, Ordering::Relaxed);
+}
+
+/// Returns the cache lifetime, or `None` when the cache is off.
+pub fn ttl() -> Option<Duration> {
+    match TTL_MS.load(Ordering::Relaxed) {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    }
+}
+
+/// Request counts of one method (`LIST`, `HEAD`, ...).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ApiCalls {
+    /// Requests sent to the backend
+    pub requests: u64,
+    /// Requests answered from the cache or merged into another request
+    pub cached: u64,
+}
+
+/// Request counts per method, as returned by [`api_calls`].
+#[derive(Debug, Clone, Default)]
+pub struct ApiCallCounts {
+    pub methods: BTreeMap<&'static str, ApiCalls>,
+}
+
+impl ApiCallCounts {
+    /// Requests sent to the backend, over all methods.
+    pub fn requests(&self) -> u64 {
+        self.methods.values().map(|calls| calls.requests).sum()
+    }
+
+    /// Requests saved by the cache, over all methods.
+    pub fn cached(&self) -> u64 {
+        self.methods.values().map(|calls| calls.cached).sum()
+    }
+
+    /// One line such as "API calls: 2 LIST, 14 HEAD, 30 GET (812 answered
+    /// from cache)", or `None` when no request was made.
+    pub fn summary(&self) -> Option<String> {
+        if self.requests() == 0 && self.cached() == 0 {
+            return None;
+        }
+        let methods: Vec<String> = self
+            .methods
+            .iter()
+            .filter(|(_, calls)| calls.requests > 0)
+            .map(|(method, calls)| format!("{} {}", calls.requests, method))
+            .collect();
+        let mut line = format!(
+            "API calls: {}",
+            if methods.is_empty() {
+                "none".to_string()
+            } else {
+                methods.join(", ")
+            }
+        );
+        if self.cached() > 0 {
+            line.push_str(&format!(" ({} answered from cache)", self.cached()));
+        }
+        Some(line)
+    }
+
+    fn record(&mut self, method: &'static str, cached: bool) {
+        let calls = self.methods.entry(method).or_default();
+        if cached {
+            calls.cached += 1;
+        } else {
+            calls.requests += 1;
+        }
+    }
+}
+
+/// Request counts of all storage opened during this run.
+pub fn api_calls() -> ApiCallCounts {
+    CALLS.lock().unwrap().clone()
+}
+
+/// A cached answer, filled by the first request for its key. Later requests
+/// for the key wait on the lock while that request is in flight.
+type Slot<T> = Arc<tokio::sync::Mutex<Option<(Instant, T)>>>;
+
+/// Storage wrapper that counts requests and, with a lifetime set, caches
+/// listings and object metadata of the storage it wraps.
+pub struct CachedStorage {
+    inner: Box<dyn RepositoryStorage>,
+    ttl: Option<Duration>,
+    calls: Mutex<ApiCallCounts>,
+    listings: Mutex<HashMap<String, Slot<Vec<String>>>>,
+    exists: Mutex<HashMap<String, Slot<bool>>>,
+    metadata: Mutex<HashMap<String, Slot<ObjectMetadata>>>,
+}
+
+impl CachedStorage {
+    /// Wraps `inner`, caching answers for `ttl`; `None` only counts requests.
+    pub fn new(inner: Box<dyn RepositoryStorage>, ttl: Option<Duration>) -> Self {
+        Self {
+            inner,
+            ttl,
+            calls: Mutex::new(ApiCallCounts::default()),
+            listings: Mutex::new(HashMap::new()),
+            exists: Mutex::new(HashMap::new()),
+            metadata: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Request counts of this storage.
+    pub fn calls(&self) -> ApiCallCounts {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn record(&self, method: &'static str, cached: bool) {
+        self.calls.lock().unwrap().record(method, cached);
+        CALLS.lock().unwrap().record(method, cached);
+    }
+
+    /// Sends a request that is never cached.
+    async fn request<T, F>(&self, method: &'static str, operation: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        self.record(method, false);
+        operation.await
+    }
+
+    /// Answers from the slot for `key` while it is fresh, and otherwise
+    /// sends the request and stores its answer.
+    async fn cached<T, F>(
+        &self,
+        method: &'static str,
+        slots: &Mutex<HashMap<String, Slot<T>>>,
+        key: &str,
+        operation: F,
+    ) -> Result<T>
+    where
+        T: Clone,
+        F: Future<Output = Result<T>>,
+    {
+        let Some(ttl) = self.ttl else {
+            return self.request(method, operation).await;
+        };
+        let slot = slots
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        let mut cached = slot.lock().await;
+        if let Some((fetched, value)) = cached.as_ref()
+            && fetched.elapsed() < ttl
+        {
+            self.record(method, true);
+            return Ok(value.clone());
+        }
+
+        self.record(method, false);
+        let value = operation.await?;
+        *cached = Some((Instant::now(), value.clone()));
+        Ok(value)
+    }
+
+    /// Whether `path` is in a fresh cached listing of its prefix, or `None`
+    /// when the prefix has no such listing.
+    async fn listed(&self, path: &str) -> Option<bool> {
+        let ttl = self.ttl?;
+        let (prefix, name) = split_path(path);
+        let slot = self.listings.lock().unwrap().get(prefix).cloned()?;
+        let cached = slot.lock().await;
+        cached
+            .as_ref()
+            .filter(|(fetched, _)| fetched.elapsed() < ttl)
+            .map(|(_, names)| names.binary_search_by(|n| n.as_str().cmp(name)).is_ok())
+    }
+
+    /// Applies a write (`present`) or delete of `path` to the cached answers.
+    async fn update(&self, path: &str, present: bool) {
+        if self.ttl.is_none() {
+            return;
+        }
+        self.exists.lock().unwrap().remove(path);
+        self.metadata.lock().unwrap().remove(path);
+
+        let (prefix, name) = split_path(path);
+        let slot = self.listings.lock().unwrap().get(prefix).cloned();
+        if let Some(slot) = slot
+            && let Some((_, names)) = slot.lock().await.as_mut()
+        {
+            match (names.binary_search_by(|n| n.as_str().cmp(name)), present) {
+                (Err(i), true) => names.insert(i, name.to_string()),
+                (Ok(i), false) => {
+                    names.remove(i);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Splits an object path into the prefix it is listed under and its name.
+fn split_path(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+#[async_trait]
+impl RepositoryStorage for CachedStorage {
+    fn location(&self) -> &RepositoryLocation {
+        self.inner.location()
+    }
+
+    async fn init(&self) -> Result<()> {
+        self.request("INIT", self.inner.init()).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool> {
+        if let Some(listed) = self.listed(path).await {
+            self.record("HEAD", true);
+            return Ok(listed);
+        }
+        self.cached("HEAD", &self.exists, path, self.inner.exists(path))
+            .await
+    }
+
+    async fn read(&self, path: &str) -> Result<Bytes> {
+        self.request("GET", self.inner.read(path)).await
+    }
+
+    async fn write(&self, path: &str, data: Bytes) -> Result<()> {
+        self.request("PUT", self.inner.write(path, data)).await?;
+        self.update(path, true).await;
+        Ok(())
+    }
+
+    async fn delete(&self, path: &str) -> Result<()> {
+        let result = self.request("DELETE", self.inner.delete(path)).await;
+        // A failed delete may still have removed the object
+        self.update(path, false).await;
+        result
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        self.cached("LIST", &self.listings, prefix, async {
+            let mut names = self.inner.list(prefix).await?;
+            names.sort();
+            Ok(names)
+        })
+        .await
+    }
+
+    async fn metadata(&self, path: &str) -> Result<ObjectMetadata> {
+        self.cached("STAT", &self.metadata, path, self.inner.metadata(path))
+            .await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        self.request("COPY", self.inner.copy(from, to)).await?;
+        self.update(to, true).await;
+        Ok(())
+    }
+
+    async fn has_chunks(&self, chunk_ids: &[ChunkID]) -> Result<Option<Vec<bool>>> {
+        self.request("HAS_CHUNKS", self.inner.has_chunks(chunk_ids))
+            .await
+    }
+
+    async fn migrate_layout(&self) -> Result<usize> {
+        self.request("MIGRATE", self.inner.migrate_layout()).await
+    }
+
+    async fn access_tier(&self, path: &str) -> Result<Option<TierStatus>> {
+        self.request("GET_TIER", self.inner.access_tier(path)).await
+    }
+
+    async fn set_access_tier(
+        &self,
+        path: &str,
+        tier: AccessTier,
+        priority: RehydratePriority,
+    ) -> Result<()> {
+        self.request("SET_TIER", self.inner.set_access_tier(path, tier, priority))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::local_storage;
+
+    /// Requests sent and answered from the cache for `method`.
+    fn counts(storage: &CachedStorage, method: &str) -> (u64, u64) {
+        let calls = storage.calls().methods.get(method).copied();
+        calls.map_or((0, 0), |calls| (calls.requests, calls.cached))
+    }
+
+    #[tokio::test]
+    async fn test_cached_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let other = local_storage(dir.path());
+        let storage = CachedStorage::new(local_storage(dir.path()), Some(Duration::from_secs(60)));
+        storage.init().await.unwrap();
+        storage
+            .write("snapshots/a", Bytes::from_static(b"a"))
+            .await
+            .unwrap();
+
+        assert_eq!(storage.list("snapshots").await.unwrap(), ["a"]);
+        assert!(storage.exists("snapshots/a").await.unwrap());
+        assert!(!storage.exists("snapshots/b").await.unwrap());
+        assert_eq!(counts(&storage, "LIST"), (1, 0));
+        assert_eq!(counts(&storage, "HEAD"), (0, 2));
+
+        // Other writers show up only once the listing expires...
+        other
+            .write("snapshots/c", Bytes::from_static(b"c"))
+            .await
+            .unwrap();
+        assert_eq!(storage.list("snapshots").await.unwrap(), ["a"]);
+        assert_eq!(counts(&storage, "LIST"), (1, 1));
+
+        // ...while the storage's own writes and deletes apply at once
+        storage
+            .write("snapshots/b", Bytes::from_static(b"b"))
+            .await
+            .unwrap();
+        storage.delete("snapshots/a").await.unwrap();
+        assert_eq!(storage.list("snapshots").await.unwrap(), ["b"]);
+        assert!(!storage.exists("snapshots/a").await.unwrap());
+
+        // Concurrent requests for the same object are sent once
+        let (a, b) = tokio::join!(
+            storage.metadata("snapshots/c"),
+            storage.metadata("snapshots/c")
+        );
+        assert_eq!(a.unwrap().size, b.unwrap().size);
+        assert_eq!(counts(&storage, "STAT"), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_counting_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = CachedStorage::new(local_storage(dir.path()), None);
+        storage.init().await.unwrap();
+        storage
+            .write("keys/k", Bytes::from_static(b"k"))
+            .await
+            .unwrap();
+        storage.list("keys").await.unwrap();
+        storage.list("keys").await.unwrap();
+        assert!(storage.exists("keys/k").await.unwrap());
+
+        assert_eq!(counts(&storage, "LIST"), (2, 0));
+        assert_eq!(counts(&storage, "HEAD"), (1, 0));
+        assert_eq!(
+            storage.calls().summary().unwrap(),
+            "API calls: 1 HEAD, 1 INIT, 2 LIST, 1 PUT"
+        );
+    }
+}
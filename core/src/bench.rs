@@ -0,0 +1,369 @@
+//! Measuring chunking, hashing, compression and encryption throughput.
+//!
+//! `ghostsnap bench` runs [`run`] on generated data and turns the
+//! [`BenchReport`] into a [`Recommendation`] for the repository's chunk size
+//! and compression level. Numbers are single-threaded, as backups chunk,
+//! hash, compress and encrypt one file at a time.
+
+use crate::Result;
+use crate::chunker::{Chunker, MAX_CHUNK_SIZE};
+use crate::crypto::Encryptor;
+use aes::cipher::{KeyInit, KeyIvInit, StreamCipher, generic_array::GenericArray};
+use poly1305::Poly1305;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Average chunk sizes measured, smallest first.
+pub const CHUNK_SIZES: [u32; 3] = [1024 * 1024, 2 * 1024 * 1024, MAX_CHUNK_SIZE];
+
+/// zlib levels measured; packs are compressed with zlib.
+pub const ZLIB_LEVELS: [u32; 4] = [1, 3, 6, 9];
+
+/// zstd levels measured for comparison.
+pub const ZSTD_LEVELS: [i32; 3] = [1, 3, 9];
+
+/// Compression throughput to aim for unless the caller asks for another:
+/// roughly what a fast uplink or a spinning disk delivers.
+pub const DEFAULT_TARGET_MB_PER_SEC: f64 = 100.0;
+
+/// The cipher repositories encrypt with.
+pub const REPOSITORY_CIPHER: &str = "ChaCha20-Poly1305";
+
+/// Bytes processed in some time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Throughput {
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    /// Times `f` processing `bytes`.
+    fn measure(bytes: usize, f: impl FnOnce() -> Result<()>) -> Result<Self> {
+        let start = Instant::now();
+        f()?;
+        Ok(Self {
+            bytes: bytes as u64,
+            elapsed: start.elapsed(),
+        })
+    }
+
+    /// Megabytes (10^6 bytes) per second.
+    pub fn mb_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64().max(1e-9);
+        self.bytes as f64 / 1e6 / secs
+    }
+}
+
+/// One compression algorithm at one level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionResult {
+    /// "zlib" or "zstd"
+    pub algorithm: &'static str,
+    pub level: i32,
+    pub throughput: Throughput,
+    /// Compressed size divided by input size
+    pub ratio: f64,
+}
+
+/// One cipher's encryption throughput.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CipherResult {
+    pub name: &'static str,
+    pub throughput: Throughput,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchReport {
+    /// Chunking throughput per average chunk size, as in [`CHUNK_SIZES`]
+    pub chunking: Vec<(u32, Throughput)>,
+    /// BLAKE3 hashing, which chunk IDs use
+    pub hashing: Throughput,
+    pub compression: Vec<CompressionResult>,
+    pub ciphers: Vec<CipherResult>,
+}
+
+/// Settings suggested by a [`BenchReport`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recommendation {
+    pub chunk_size: u32,
+    pub compression_level: u32,
+    pub cipher: &'static str,
+    /// One line per setting explaining the choice
+    pub reasons: Vec<String>,
+}
+
+/// Generates `len` bytes that compress about as well as typical backup
+/// data: alternating blocks of random bytes and repeated text.
+pub fn sample_data(len: usize) -> Vec<u8> {
+    const BLOCK: usize = 64 * 1024;
+    let text = b"ghostsnap benchmark sample: the quick brown fox jumps over the lazy dog\n";
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    let mut data = Vec::with_capacity(len);
+    while data.len() < len {
+        let block = BLOCK.min(len - data.len());
+        if (data.len() / BLOCK).is_multiple_of(2) {
+            data.extend((0..block).map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            }));
+        } else {
+            data.extend(text.iter().cycle().take(block));
+        }
+    }
+    data
+}
+
+/// Runs every measurement over `size` bytes of [`sample_data`].
+pub fn run(size: usize) -> Result<BenchReport> {
+    let data = sample_data(size);
+
+    let mut chunking = Vec::new();
+    for avg_size in CHUNK_SIZES {
+        let chunker = Chunker::new(avg_size);
+        let throughput = Throughput::measure(data.len(), || {
+            std::hint::black_box(chunker.chunk_data(&data));
+            Ok(())
+        })?;
+        chunking.push((avg_size, throughput));
+    }
+
+    let hashing = Throughput::measure(data.len(), || {
+        std::hint::black_box(blake3::hash(&data));
+        Ok(())
+    })?;
+
+    let mut compression = Vec::new();
+    for level in ZLIB_LEVELS {
+        let mut compressed = Vec::new();
+        let throughput = Throughput::measure(data.len(), || {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::new(level));
+            encoder.write_all(&data)?;
+            compressed = encoder.finish()?;
+            Ok(())
+        })?;
+        compression.push(CompressionResult {
+            algorithm: "zlib",
+            level: level as i32,
+            ratio: compressed.len() as f64 / data.len() as f64,
+            throughput,
+        });
+    }
+    for level in ZSTD_LEVELS {
+        let mut compressed = Vec::new();
+        let throughput = Throughput::measure(data.len(), || {
+            compressed = zstd::stream::encode_all(data.as_slice(), level)?;
+            Ok(())
+        })?;
+        compression.push(CompressionResult {
+            algorithm: "zstd",
+            level,
+            ratio: compressed.len() as f64 / data.len() as f64,
+            throughput,
+        });
+    }
+
+    let key = [7u8; 32];
+    let encryptor = Encryptor::new(&key)?;
+    let chacha = Throughput::measure(data.len(), || {
+        std::hint::black_box(encryptor.encrypt(&data)?);
+        Ok(())
+    })?;
+    // AES-256-CTR with Poly1305, the construction restic uses
+    let aes = Throughput::measure(data.len(), || {
+        let mut buffer = data.clone();
+        ctr::Ctr128BE::<aes::Aes256>::new(&key.into(), GenericArray::from_slice(&[0u8; 16]))
+            .apply_keystream(&mut buffer);
+        let tag = Poly1305::new(&key.into()).compute_unpadded(&buffer);
+        std::hint::black_box(tag);
+        Ok(())
+    })?;
+
+    Ok(BenchReport {
+        chunking,
+        hashing,
+        compression,
+        ciphers: vec![
+            CipherResult {
+                name: REPOSITORY_CIPHER,
+                throughput: chacha,
+            },
+            CipherResult {
+                name: "AES-256-CTR + Poly1305",
+                throughput: aes,
+            },
+        ],
+    })
+}
+
+impl BenchReport {
+    /// Suggests settings for data arriving at `target_mb_per_sec`:
+    ///
+    /// - the smallest chunk size that chunks within 10% of the fastest, as
+    ///   smaller chunks deduplicate better
+    /// - the highest zlib level that still compresses at the target, or
+    ///   level 1 if none does
+    /// - always [`REPOSITORY_CIPHER`], the only cipher repositories support;
+    ///   the reason notes when AES would have been faster
+    pub fn recommend(&self, target_mb_per_sec: f64) -> Recommendation {
+        let mut reasons = Vec::new();
+
+        let fastest = self
+            .chunking
+            .iter()
+            .map(|(_, throughput)| throughput.mb_per_sec())
+            .fold(0.0, f64::max);
+        let (chunk_size, chunk_speed) = self
+            .chunking
+            .iter()
+            .map(|(size, throughput)| (*size, throughput.mb_per_sec()))
+            .find(|(_, speed)| *speed >= fastest * 0.9)
+            .unwrap_or((MAX_CHUNK_SIZE, fastest));
+        reasons.push(format!(
+            "chunk size {} KiB: smallest within 10% of the fastest chunking speed ({:.0} MB/s)",
+            chunk_size / 1024,
+            chunk_speed
+        ));
+
+        let zlib: Vec<&CompressionResult> = self
+            .compression
+            .iter()
+            .filter(|result| result.algorithm == "zlib")
+            .collect();
+        let compression_level = match zlib
+            .iter()
+            .filter(|result| result.throughput.mb_per_sec() >= target_mb_per_sec)
+            .max_by_key(|result| result.level)
+        {
+            Some(result) => {
+                reasons.push(format!(
+                    "compression level {}: highest zlib level at or above {:.0} MB/s ({:.0} MB/s, {:.0}% of input size)",
+                    result.level,
+                    target_mb_per_sec,
+                    result.throughput.mb_per_sec(),
+                    result.ratio * 100.0
+                ));
+                result.level as u32
+            }
+            None => {
+                reasons.push(format!(
+                    "compression level 1: no zlib level reaches {:.0} MB/s, so use the fastest",
+                    target_mb_per_sec
+                ));
+                1
+            }
+        };
+
+        let speed = |name: &str| {
+            self.ciphers
+                .iter()
+                .find(|cipher| cipher.name == name)
+                .map(|cipher| cipher.throughput.mb_per_sec())
+        };
+        let chacha = speed(REPOSITORY_CIPHER).unwrap_or(0.0);
+        let fastest_other = self
+            .ciphers
+            .iter()
+            .filter(|cipher| cipher.name != REPOSITORY_CIPHER)
+            .max_by(|a, b| {
+                a.throughput
+                    .mb_per_sec()
+                    .total_cmp(&b.throughput.mb_per_sec())
+            });
+        match fastest_other {
+            Some(other) if other.throughput.mb_per_sec() > chacha * 1.5 => reasons.push(format!(
+                "cipher {}: {} is faster here ({:.0} vs {:.0} MB/s, likely hardware AES), \
+                 but repositories only support {}",
+                REPOSITORY_CIPHER,
+                other.name,
+                other.throughput.mb_per_sec(),
+                chacha,
+                REPOSITORY_CIPHER
+            )),
+            _ => reasons.push(format!(
+                "cipher {}: {:.0} MB/s, as fast as the alternatives",
+                REPOSITORY_CIPHER, chacha
+            )),
+        }
+
+        Recommendation {
+            chunk_size,
+            compression_level,
+            cipher: REPOSITORY_CIPHER,
+            reasons,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throughput(mb_per_sec: f64) -> Throughput {
+        Throughput {
+            bytes: (mb_per_sec * 1e6) as u64,
+            elapsed: Duration::from_secs(1),
+        }
+    }
+
+    fn zlib(level: i32, mb_per_sec: f64) -> CompressionResult {
+        CompressionResult {
+            algorithm: "zlib",
+            level,
+            throughput: throughput(mb_per_sec),
+            ratio: 0.5,
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let report = run(256 * 1024).unwrap();
+        assert_eq!(report.chunking.len(), CHUNK_SIZES.len());
+        assert_eq!(
+            report.compression.len(),
+            ZLIB_LEVELS.len() + ZSTD_LEVELS.len()
+        );
+        assert_eq!(report.ciphers.len(), 2);
+        assert!(report.hashing.mb_per_sec() > 0.0);
+        // Half of the sample is repeated text
+        assert!(report.compression.iter().all(|result| result.ratio < 0.75));
+
+        let recommendation = report.recommend(DEFAULT_TARGET_MB_PER_SEC);
+        assert!(CHUNK_SIZES.contains(&recommendation.chunk_size));
+        assert_eq!(recommendation.reasons.len(), 3);
+    }
+
+    #[test]
+    fn test_recommend() {
+        let report = BenchReport {
+            chunking: vec![
+                (1024 * 1024, throughput(700.0)),
+                (2 * 1024 * 1024, throughput(950.0)),
+                (4 * 1024 * 1024, throughput(1000.0)),
+            ],
+            hashing: throughput(3000.0),
+            compression: vec![zlib(1, 250.0), zlib(3, 180.0), zlib(6, 60.0), zlib(9, 20.0)],
+            ciphers: vec![
+                CipherResult {
+                    name: REPOSITORY_CIPHER,
+                    throughput: throughput(800.0),
+                },
+                CipherResult {
+                    name: "AES-256-CTR + Poly1305",
+                    throughput: throughput(3000.0),
+                },
+            ],
+        };
+
+        let recommendation = report.recommend(100.0);
+        assert_eq!(recommendation.chunk_size, 2 * 1024 * 1024);
+        assert_eq!(recommendation.compression_level, 3);
+        assert_eq!(recommendation.cipher, REPOSITORY_CIPHER);
+        assert!(recommendation.reasons[2].contains("hardware AES"));
+
+        assert_eq!(report.recommend(50.0).compression_level, 6);
+        assert_eq!(report.recommend(500.0).compression_level, 1);
+    }
+}
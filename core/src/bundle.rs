@@ -0,0 +1,252 @@
+//! Portable snapshot bundles.
+//!
+//! A bundle is a single self-contained file holding one snapshot, its tree and
+//! every chunk the tree references. Bundles are encrypted with a key derived
+//! from a bundle password, independent of any repository key, so they can be
+//! carried to an offline vault and imported into a different repository.
+//!
+//! # Layout
+//!
+//! ```text
+//! [8 bytes magic "GSBUNDLE"][u32 header len][header JSON][encrypted payload]
+//! ```
+//!
+//! The payload is a zlib-compressed postcard document, encrypted with
+//! ChaCha20-Poly1305 like every other repository object. Bundles exported
+//! from a repository with the FIPS profile use its primitives instead:
+//! PBKDF2-HMAC-SHA256 and AES-256-GCM.
+
+use crate::crypto::{Encryptor, MasterKey};
+use crate::hash::{ChunkHasher, HashAlgorithm};
+use crate::snapshot::{Snapshot, Tree};
+use crate::types::{
+    ChunkID, CryptoProfile, EncryptionLayer, KDF_PBKDF2_SHA256, KdfParams, SnapshotID,
+};
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+/// Magic bytes at the start of every bundle file.
+pub const BUNDLE_MAGIC: &[u8; 8] = b"GSBUNDLE";
+
+/// Bundle format version for schema evolution.
+const BUNDLE_VERSION: u32 = 1;
+
+/// Unencrypted bundle header, needed to derive the bundle key.
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleHeader {
+    version: u32,
+    kdf_params: KdfParams,
+    /// Algorithm of the chunk IDs; bundles written before it was recorded
+    /// use BLAKE3
+    #[serde(default, skip_serializing_if = "HashAlgorithm::is_default")]
+    hash: HashAlgorithm,
+    /// Primitives of the bundle key and encryption
+    #[serde(default, skip_serializing_if = "CryptoProfile::is_default")]
+    profile: CryptoProfile,
+}
+
+/// Encrypted bundle contents.
+///
+/// Snapshot and tree are stored as JSON because their optional fields are
+/// skipped during serialization, which postcard cannot round-trip.
+#[derive(Debug, Serialize, Deserialize)]
+struct BundlePayload {
+    snapshot: Vec<u8>,
+    tree: Vec<u8>,
+    chunks: Vec<BundleChunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleChunk {
+    id: ChunkID,
+    data: Vec<u8>,
+}
+
+/// A snapshot together with all data required to restore it.
+#[derive(Debug, Clone)]
+pub struct SnapshotBundle {
+    pub snapshot: Snapshot,
+    pub tree: Tree,
+    pub chunks: Vec<(ChunkID, Vec<u8>)>,
+    /// Algorithm of the exporting repository's chunk IDs
+    pub hash: HashAlgorithm,
+    /// Crypto profile of the exporting repository, which the bundle is
+    /// encrypted with
+    pub profile: CryptoProfile,
+}
+
+impl SnapshotBundle {
+    /// Total size of the chunk data carried by the bundle.
+    pub fn data_size(&self) -> u64 {
+        self.chunks.iter().map(|(_, data)| data.len() as u64).sum()
+    }
+
+    /// Serializes and encrypts the bundle with a key derived from `password`.
+    pub fn to_bytes(&self, password: &str) -> Result<Vec<u8>> {
+        let kdf_params = self.profile.kdf_params();
+        let key = MasterKey::derive_from_password(password, &kdf_params.salt, &kdf_params)?;
+        let encryptor = Self::encryptor(self.profile, &key)?;
+
+        let payload = BundlePayload {
+            snapshot: serde_json::to_vec(&self.snapshot)?,
+            tree: serde_json::to_vec(&self.tree)?,
+            chunks: self
+                .chunks
+                .iter()
+                .map(|(id, data)| BundleChunk {
+                    id: *id,
+                    data: data.clone(),
+                })
+                .collect(),
+        };
+
+        let payload_bytes = postcard::to_allocvec(&payload)
+            .map_err(|e| Error::Other(format!("Failed to serialize bundle: {}", e)))?;
+
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&payload_bytes)?;
+        let compressed = encoder.finish()?;
+
+        let encrypted = encryptor.encrypt(&compressed)?;
+
+        let header = serde_json::to_vec(&BundleHeader {
+            version: BUNDLE_VERSION,
+            kdf_params,
+            hash: self.hash,
+            profile: self.profile,
+        })?;
+
+        let mut result = Vec::with_capacity(12 + header.len() + encrypted.len());
+        result.extend_from_slice(BUNDLE_MAGIC);
+        result.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        result.extend_from_slice(&header);
+        result.extend_from_slice(&encrypted);
+        Ok(result)
+    }
+
+    /// Decrypts and deserializes a bundle produced by [`SnapshotBundle::to_bytes`].
+    pub fn from_bytes(data: &[u8], password: &str) -> Result<Self> {
+        if data.len() < 12 || &data[..8] != BUNDLE_MAGIC {
+            return Err(Error::Other("Not a ghostsnap bundle".to_string()));
+        }
+
+        let header_len = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+        if data.len() < 12 + header_len {
+            return Err(Error::Other("Truncated bundle header".to_string()));
+        }
+
+        let header: BundleHeader = serde_json::from_slice(&data[12..12 + header_len])?;
+        if header.version != BUNDLE_VERSION {
+            return Err(Error::InvalidFormatVersion {
+                version: header.version,
+            });
+        }
+        if header.profile == CryptoProfile::Fips && header.kdf_params.algorithm != KDF_PBKDF2_SHA256
+        {
+            return Err(Error::Encryption(format!(
+                "Bundle key uses {}, which the fips profile does not allow",
+                header.kdf_params.algorithm
+            )));
+        }
+
+        let key =
+            MasterKey::derive_from_password(password, &header.kdf_params.salt, &header.kdf_params)?;
+        let encryptor = Self::encryptor(header.profile, &key)?;
+
+        let compressed = encryptor
+            .decrypt(&data[12 + header_len..])
+            .map_err(|_| Error::InvalidPassword)?;
+
+        let mut payload_bytes = Vec::new();
+        flate2::read::ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut payload_bytes)?;
+
+        let payload: BundlePayload = postcard::from_bytes(&payload_bytes)
+            .map_err(|e| Error::Other(format!("Failed to deserialize bundle: {}", e)))?;
+
+        let tree: Tree = serde_json::from_slice(&payload.tree)?;
+        let chunks: Vec<(ChunkID, Vec<u8>)> = payload
+            .chunks
+            .into_iter()
+            .map(|chunk| (chunk.id, chunk.data))
+            .collect();
+
+        // Reject bundles whose chunk data does not match the advertised IDs.
+        // Keyed IDs need the exporting repository's key; the payload is
+        // authenticated by the bundle key instead.
+        if !header.hash.is_keyed() {
+            let hasher = ChunkHasher::new(header.hash, None)?;
+            for (id, data) in &chunks {
+                if hasher.hash(data) != *id {
+                    return Err(Error::Other(format!(
+                        "Bundle chunk {} failed integrity check",
+                        id.short_string()
+                    )));
+                }
+            }
+        }
+
+        Ok(Self {
+            snapshot: serde_json::from_slice(&payload.snapshot)?,
+            tree,
+            chunks,
+            hash: header.hash,
+            profile: header.profile,
+        })
+    }
+
+    fn encryptor(profile: CryptoProfile, key: &MasterKey) -> Result<Encryptor> {
+        Encryptor::with_mode(profile.mode(EncryptionLayer::Repo), key.as_bytes())
+    }
+}
+
+/// Bundle import statistics.
+#[derive(Debug)]
+pub struct BundleImportStats {
+    /// ID of the snapshot created in the target repository
+    pub snapshot_id: SnapshotID,
+    /// Chunks written to the target repository
+    pub chunks_imported: usize,
+    /// Chunks already present in the target repository
+    pub chunks_skipped: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_bundle_roundtrip() {
+        let data = b"bundle chunk data".to_vec();
+        let chunk_id = ChunkID::from_data(&data);
+        let bundle = SnapshotBundle {
+            snapshot: Snapshot::new(vec![PathBuf::from("/data")], chunk_id),
+            tree: Tree::new(),
+            chunks: vec![(chunk_id, data.clone())],
+            hash: HashAlgorithm::Blake3,
+            profile: CryptoProfile::Standard,
+        };
+
+        let bytes = bundle.to_bytes("bundle-password").unwrap();
+        assert_eq!(&bytes[..8], BUNDLE_MAGIC);
+
+        let restored = SnapshotBundle::from_bytes(&bytes, "bundle-password").unwrap();
+        assert_eq!(restored.snapshot.id, bundle.snapshot.id);
+        assert_eq!(restored.chunks, vec![(chunk_id, data)]);
+
+        assert!(matches!(
+            SnapshotBundle::from_bytes(&bytes, "wrong-password"),
+            Err(Error::InvalidPassword)
+        ));
+
+        // IDs are checked with the algorithm the bundle records
+        let sha256 = SnapshotBundle {
+            hash: HashAlgorithm::Sha256,
+            ..bundle
+        };
+        let bytes = sha256.to_bytes("bundle-password").unwrap();
+        assert!(SnapshotBundle::from_bytes(&bytes, "bundle-password").is_err());
+    }
+}
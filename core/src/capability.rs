@@ -0,0 +1,226 @@
+//! Detecting what the storage credential is allowed to do.
+//!
+//! Backups can run with append-only credentials (write but no delete), and
+//! a host that only restores needs nothing beyond read and list. Rather than
+//! failing halfway through a prune on the first rejected delete, commands
+//! probe the credential up front with [`probe`] and check the result against
+//! the [`Access`] they need.
+
+use crate::storage::RepositoryStorage;
+use crate::{Error, Result};
+use bytes::Bytes;
+use std::fmt;
+
+/// Object written and deleted again to probe write and delete access. It is
+/// a lock object so that checks never report it as foreign.
+pub const PROBE_PATH: &str = "locks/capability-probe.lock";
+
+/// What the storage credential allows. Requests that fail for reasons other
+/// than a denied permission, such as a missing object, count as allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub list: bool,
+    pub read: bool,
+    pub write: bool,
+    pub delete: bool,
+}
+
+/// The access an operation needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// List and read objects, e.g. restore and check
+    Read,
+    /// Also write new objects, e.g. backup
+    Append,
+    /// Also delete objects, e.g. forget and prune
+    Delete,
+}
+
+impl Capabilities {
+    pub const ALL: Self = Self {
+        list: true,
+        read: true,
+        write: true,
+        delete: true,
+    };
+
+    /// Write without delete, as with an append-only or object-locked bucket.
+    pub fn is_append_only(&self) -> bool {
+        self.write && !self.delete
+    }
+
+    /// Permissions `access` needs that the credential lacks.
+    pub fn missing(&self, access: Access) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        if !self.list {
+            missing.push("list");
+        }
+        if !self.read {
+            missing.push("read");
+        }
+        if access != Access::Read && !self.write {
+            missing.push("write");
+        }
+        if access == Access::Delete && !self.delete {
+            missing.push("delete");
+        }
+        missing
+    }
+
+    /// Fails with [`Error::InsufficientPermissions`] unless the credential
+    /// allows `access`. `operation` names what is refused, e.g. "prune".
+    pub fn require(&self, access: Access, operation: &str) -> Result<()> {
+        let missing = self.missing(access);
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(Error::InsufficientPermissions {
+            operation: operation.to_string(),
+            missing: missing.join(", "),
+        })
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let allowed: Vec<&str> = [
+            (self.list, "list"),
+            (self.read, "read"),
+            (self.write, "write"),
+            (self.delete, "delete"),
+        ]
+        .into_iter()
+        .filter_map(|(allowed, name)| allowed.then_some(name))
+        .collect();
+        if allowed.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&allowed.join(", "))
+        }
+    }
+}
+
+/// Probes what `storage` allows with four small requests: listing `keys/`,
+/// reading `config`, and writing and deleting [`PROBE_PATH`].
+pub async fn probe(storage: &dyn RepositoryStorage) -> Capabilities {
+    let list = allowed(storage.list("keys").await.map(drop));
+    let read = allowed(storage.read("config").await.map(drop));
+    let write = allowed(
+        storage
+            .write(PROBE_PATH, Bytes::from_static(b"capability probe"))
+            .await,
+    );
+    // Without write access this deletes an object that does not exist, which
+    // storage still refuses when deletes are denied.
+    let delete = allowed(storage.delete(PROBE_PATH).await);
+
+    Capabilities {
+        list,
+        read,
+        write,
+        delete,
+    }
+}
+
+fn allowed(result: Result<()>) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(e) if e.is_permission_denied() => false,
+        Err(e) => {
+            tracing::debug!("Capability probe request failed: {}", e);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{ObjectMetadata, RepositoryLocation, local_storage};
+    use async_trait::async_trait;
+
+    /// Local storage that rejects writes and/or deletes with HTTP 403.
+    struct Restricted {
+        inner: Box<dyn RepositoryStorage>,
+        write: bool,
+        delete: bool,
+    }
+
+    fn denied() -> Error {
+        Error::BackendStatus {
+            status: 403,
+            message: "AccessDenied".to_string(),
+            retry_after: None,
+        }
+    }
+
+    #[async_trait]
+    impl RepositoryStorage for Restricted {
+        fn location(&self) -> &RepositoryLocation {
+            self.inner.location()
+        }
+        async fn init(&self) -> Result<()> {
+            self.inner.init().await
+        }
+        async fn exists(&self, path: &str) -> Result<bool> {
+            self.inner.exists(path).await
+        }
+        async fn read(&self, path: &str) -> Result<Bytes> {
+            self.inner.read(path).await
+        }
+        async fn write(&self, path: &str, data: Bytes) -> Result<()> {
+            if !self.write {
+                return Err(denied());
+            }
+            self.inner.write(path, data).await
+        }
+        async fn delete(&self, path: &str) -> Result<()> {
+            if !self.delete {
+                return Err(denied());
+            }
+            self.inner.delete(path).await
+        }
+        async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+            self.inner.list(prefix).await
+        }
+        async fn metadata(&self, path: &str) -> Result<ObjectMetadata> {
+            self.inner.metadata(path).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = local_storage(dir.path());
+        storage.init().await.unwrap();
+
+        // A missing config or keys/ prefix is not a denied permission
+        assert_eq!(probe(storage.as_ref()).await, Capabilities::ALL);
+        assert!(!storage.exists(PROBE_PATH).await.unwrap());
+
+        let append_only = Restricted {
+            inner: local_storage(dir.path()),
+            write: true,
+            delete: false,
+        };
+        let capabilities = probe(&append_only).await;
+        assert!(capabilities.is_append_only());
+        assert!(capabilities.require(Access::Append, "backup").is_ok());
+        let err = capabilities.require(Access::Delete, "prune").unwrap_err();
+        assert!(matches!(err, Error::InsufficientPermissions { .. }));
+        assert!(err.to_string().contains("cannot delete"));
+
+        let read_only = Restricted {
+            inner: local_storage(dir.path()),
+            write: false,
+            delete: false,
+        };
+        let capabilities = probe(&read_only).await;
+        assert_eq!(capabilities.to_string(), "list, read");
+        assert!(capabilities.require(Access::Read, "restore").is_ok());
+        assert_eq!(
+            capabilities.missing(Access::Delete),
+            vec!["write", "delete"]
+        );
+    }
+}
@@ -0,0 +1,567 @@
+//! Repository integrity checks.
+//!
+//! [`check_repository`] verifies that every snapshot can be restored:
+//!
+//! 1. The config is valid and names the same repository as the marker
+//! 2. Every snapshot loads
+//! 3. Every snapshot's tree loads, with all pages of a paged tree
+//! 4. Every chunk the trees reference is in the index
+//! 5. Every pack the index places chunks in exists
+//! 6. Every pack is intact. With [`CheckOptions::read_data`] each pack is
+//!    decrypted and its header, chunk table and data checksum verified;
+//!    otherwise it only has to be non-empty, which is all a listing shows
+//!
+//! [`check_snapshot`] checks only what one snapshot needs. Problems do not
+//! stop a check: they are collected in the [`CheckReport`] so that one run
+//! lists all of them.
+
+use crate::repository::Repository;
+use crate::snapshot::{Snapshot, Tree};
+use crate::types::{ChunkID, PackID, SnapshotID};
+use crate::{Error, Result};
+use futures::stream::{self, StreamExt};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::future::Future;
+
+/// What a check reads.
+#[derive(Debug, Clone, Copy)]
+pub struct CheckOptions {
+    /// Decrypt every pack and verify its contents
+    pub read_data: bool,
+    /// Objects to verify at once
+    pub parallel: usize,
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        Self {
+            read_data: false,
+            parallel: 8,
+        }
+    }
+}
+
+/// Stage of a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStep {
+    Config,
+    Snapshots,
+    Trees,
+    Chunks,
+    IndexedPacks,
+    Packs,
+}
+
+impl CheckStep {
+    /// What the objects checked in this step are called.
+    pub fn noun(self) -> &'static str {
+        match self {
+            CheckStep::Config => "config",
+            CheckStep::Snapshots => "snapshots",
+            CheckStep::Trees => "tree objects",
+            CheckStep::Chunks => "chunk references",
+            CheckStep::IndexedPacks => "index pack references",
+            CheckStep::Packs => "pack files",
+        }
+    }
+}
+
+/// Notified as a check progresses.
+pub trait CheckObserver {
+    /// Called when a step starts, with the number of objects it checks.
+    fn step(&mut self, _step: CheckStep, _objects: usize) {}
+
+    /// Called as objects of the current step are checked.
+    fn checked(&mut self, _objects: usize) {}
+
+    /// Called for every problem, as it is found.
+    fn problem(&mut self, _problem: &CheckProblem) {}
+}
+
+impl CheckObserver for () {}
+
+/// A missing or damaged object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckProblem {
+    /// The config is invalid or belongs to another repository
+    Config(String),
+    /// A snapshot cannot be loaded
+    Snapshot {
+        snapshot_id: SnapshotID,
+        error: String,
+    },
+    /// A tree, or a page of one, cannot be loaded
+    Tree { tree_id: ChunkID, error: String },
+    /// A chunk referenced by a tree is not in the index
+    MissingChunk { chunk_id: ChunkID },
+    /// The index places chunks in a pack that does not exist
+    MissingPack { pack_id: PackID },
+    /// A pack cannot be read or is damaged
+    Pack { pack_id: PackID, error: String },
+    /// A chunk is missing from its pack or does not match its hash
+    DamagedChunk { chunk_id: ChunkID, pack_id: PackID },
+}
+
+impl CheckProblem {
+    /// The step that finds this kind of problem.
+    pub fn step(&self) -> CheckStep {
+        match self {
+            CheckProblem::Config(_) => CheckStep::Config,
+            CheckProblem::Snapshot { .. } => CheckStep::Snapshots,
+            CheckProblem::Tree { .. } => CheckStep::Trees,
+            CheckProblem::MissingChunk { .. } => CheckStep::Chunks,
+            CheckProblem::MissingPack { .. } => CheckStep::IndexedPacks,
+            CheckProblem::Pack { .. } | CheckProblem::DamagedChunk { .. } => CheckStep::Packs,
+        }
+    }
+}
+
+impl fmt::Display for CheckProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckProblem::Config(error) => write!(f, "Invalid config: {}", error),
+            CheckProblem::Snapshot { snapshot_id, error } => {
+                write!(f, "Cannot load snapshot {}: {}", snapshot_id, error)
+            }
+            CheckProblem::Tree { tree_id, error } => {
+                write!(f, "Cannot load tree {}: {}", tree_id.short_string(), error)
+            }
+            CheckProblem::MissingChunk { chunk_id } => write!(
+                f,
+                "Chunk {} referenced but not in index",
+                chunk_id.short_string()
+            ),
+            CheckProblem::MissingPack { pack_id } => {
+                write!(f, "Pack {} referenced in index but does not exist", pack_id)
+            }
+            CheckProblem::Pack { pack_id, error } => {
+                write!(f, "Pack {} is damaged: {}", pack_id, error)
+            }
+            CheckProblem::DamagedChunk { chunk_id, pack_id } => write!(
+                f,
+                "Chunk {} in pack {} is missing or does not match its hash",
+                chunk_id.short_string(),
+                pack_id
+            ),
+        }
+    }
+}
+
+/// What a check looked at and the problems it found.
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub snapshots: usize,
+    pub trees: usize,
+    /// Distinct chunks referenced by the trees that loaded
+    pub referenced_chunks: HashSet<ChunkID>,
+    /// Packs the index (or, for one snapshot, its chunks) refer to
+    pub indexed_packs: usize,
+    pub packs: usize,
+    pub problems: Vec<CheckProblem>,
+}
+
+impl CheckReport {
+    /// True if no problem was found.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Number of problems found by `step`.
+    pub fn problems_in(&self, step: CheckStep) -> usize {
+        self.problems.iter().filter(|p| p.step() == step).count()
+    }
+
+    fn add(&mut self, observer: &mut dyn CheckObserver, problem: CheckProblem) {
+        observer.problem(&problem);
+        self.problems.push(problem);
+    }
+}
+
+/// Checks the whole repository.
+pub async fn check_repository(
+    repo: &Repository,
+    options: &CheckOptions,
+    observer: &mut dyn CheckObserver,
+) -> Result<CheckReport> {
+    let mut report = CheckReport::default();
+    let parallel = options.parallel.max(1);
+
+    observer.step(CheckStep::Config, 1);
+    if let Some(problem) = check_config(repo).await {
+        report.add(observer, problem);
+    }
+    observer.checked(1);
+
+    let snapshot_ids = repo.list_snapshots().await?;
+    observer.step(CheckStep::Snapshots, snapshot_ids.len());
+    let loaded = verify_all(&snapshot_ids, parallel, observer, |snapshot_id| {
+        repo.load_snapshot(snapshot_id)
+    })
+    .await;
+    let mut tree_ids = HashSet::new();
+    for (snapshot_id, result) in snapshot_ids.iter().zip(loaded) {
+        match result {
+            Ok(snapshot) => {
+                tree_ids.insert(snapshot.tree);
+            }
+            Err(e) => report.add(
+                observer,
+                CheckProblem::Snapshot {
+                    snapshot_id: snapshot_id.clone(),
+                    error: e.to_string(),
+                },
+            ),
+        }
+    }
+    report.snapshots = snapshot_ids.len();
+
+    // Snapshots of unchanged data share trees; each is loaded once
+    let tree_ids: Vec<ChunkID> = tree_ids.into_iter().collect();
+    observer.step(CheckStep::Trees, tree_ids.len());
+    let loaded = verify_all(&tree_ids, parallel, observer, |tree_id| {
+        repo.load_tree(tree_id)
+    })
+    .await;
+    for (tree_id, result) in tree_ids.iter().zip(loaded) {
+        match result {
+            Ok(tree) => report.referenced_chunks.extend(chunk_ids(&tree)),
+            Err(e) => report.add(
+                observer,
+                CheckProblem::Tree {
+                    tree_id: *tree_id,
+                    error: e.to_string(),
+                },
+            ),
+        }
+    }
+    report.trees = tree_ids.len();
+
+    let chunk_ids = std::mem::take(&mut report.referenced_chunks);
+    check_chunks(repo, &chunk_ids, &mut report, observer).await;
+    report.referenced_chunks = chunk_ids;
+
+    let packs = repo.list_packs().await?;
+    let existing: HashSet<&PackID> = packs.iter().collect();
+    let indexed: HashSet<PackID> = repo
+        .index()
+        .read()
+        .await
+        .iter_chunks()
+        .map(|(_, location)| location.pack_id)
+        .collect();
+    observer.step(CheckStep::IndexedPacks, indexed.len());
+    let mut missing: Vec<&PackID> = indexed
+        .iter()
+        .filter(|pack_id| !existing.contains(pack_id))
+        .collect();
+    missing.sort();
+    for pack_id in missing {
+        report.add(
+            observer,
+            CheckProblem::MissingPack {
+                pack_id: pack_id.clone(),
+            },
+        );
+    }
+    observer.checked(indexed.len());
+    report.indexed_packs = indexed.len();
+
+    observer.step(CheckStep::Packs, packs.len());
+    let verified = verify_all(&packs, parallel, observer, |pack_id| {
+        verify_pack(repo, pack_id, options.read_data)
+    })
+    .await;
+    for (pack_id, result) in packs.iter().zip(verified) {
+        if let Err(e) = result {
+            report.add(
+                observer,
+                CheckProblem::Pack {
+                    pack_id: pack_id.clone(),
+                    error: e.to_string(),
+                },
+            );
+        }
+    }
+    report.packs = packs.len();
+
+    Ok(report)
+}
+
+/// Checks only what `snapshot` needs to be restored: its tree, the index
+/// entries of its chunks and the packs holding them. With
+/// [`CheckOptions::read_data`], the snapshot's chunks are read back and
+/// checked against their hashes.
+pub async fn check_snapshot(
+    repo: &Repository,
+    snapshot: &Snapshot,
+    options: &CheckOptions,
+    observer: &mut dyn CheckObserver,
+) -> Result<CheckReport> {
+    let mut report = CheckReport {
+        snapshots: 1,
+        ..CheckReport::default()
+    };
+    let parallel = options.parallel.max(1);
+
+    let root = match repo.load_tree_root(&snapshot.tree).await {
+        Ok(root) => root,
+        Err(e) => {
+            observer.step(CheckStep::Trees, 1);
+            report.add(
+                observer,
+                CheckProblem::Tree {
+                    tree_id: snapshot.tree,
+                    error: e.to_string(),
+                },
+            );
+            return Ok(report);
+        }
+    };
+    observer.step(CheckStep::Trees, 1 + root.pages.len());
+    observer.checked(1);
+    report.trees = 1 + root.pages.len();
+    let mut tree = if root.is_paged() {
+        let loaded = verify_all(&root.pages, parallel, observer, |page| {
+            repo.load_tree_root(&page.tree)
+        })
+        .await;
+        let mut tree = Tree::new();
+        for (page, result) in root.pages.iter().zip(loaded) {
+            match result {
+                Ok(page_tree) => tree.nodes.extend(page_tree.nodes),
+                Err(e) => report.add(
+                    observer,
+                    CheckProblem::Tree {
+                        tree_id: page.tree,
+                        error: e.to_string(),
+                    },
+                ),
+            }
+        }
+        tree
+    } else {
+        root
+    };
+
+    // Subtrees are found as the tree objects above them load, so the step's
+    // count covers only the root and its pages
+    let mut subtrees = subtree_ids(&tree);
+    while !subtrees.is_empty() {
+        let loaded = verify_all(&subtrees, parallel, observer, |tree_id| {
+            load_level(repo, tree_id)
+        })
+        .await;
+        let mut next = Vec::new();
+        for (tree_id, result) in subtrees.iter().zip(loaded) {
+            match result {
+                Ok(level) => {
+                    next.extend(subtree_ids(&level));
+                    tree.nodes.extend(level.nodes);
+                }
+                Err(e) => report.add(
+                    observer,
+                    CheckProblem::Tree {
+                        tree_id: *tree_id,
+                        error: e.to_string(),
+                    },
+                ),
+            }
+        }
+        report.trees += subtrees.len();
+        subtrees = next;
+    }
+    report.referenced_chunks = chunk_ids(&tree);
+
+    let chunk_ids = std::mem::take(&mut report.referenced_chunks);
+    let by_pack = check_chunks(repo, &chunk_ids, &mut report, observer).await;
+    report.referenced_chunks = chunk_ids;
+
+    let packs: Vec<(PackID, Vec<ChunkID>)> = by_pack.into_iter().collect();
+    report.indexed_packs = packs.len();
+    observer.step(CheckStep::Packs, packs.len());
+    if options.read_data {
+        let verified = verify_all(&packs, parallel, observer, |(pack_id, chunks)| {
+            repo.verify_pack_chunks(pack_id, chunks)
+        })
+        .await;
+        for ((pack_id, _), result) in packs.iter().zip(verified) {
+            match result {
+                Ok(damaged) => {
+                    for chunk_id in damaged {
+                        report.add(
+                            observer,
+                            CheckProblem::DamagedChunk {
+                                chunk_id,
+                                pack_id: pack_id.clone(),
+                            },
+                        );
+                    }
+                }
+                Err(e) => report.add(
+                    observer,
+                    CheckProblem::Pack {
+                        pack_id: pack_id.clone(),
+                        error: e.to_string(),
+                    },
+                ),
+            }
+        }
+    } else {
+        let existing = verify_all(&packs, parallel, observer, |(pack_id, _)| {
+            repo.pack_exists(pack_id)
+        })
+        .await;
+        for ((pack_id, _), result) in packs.iter().zip(existing) {
+            let problem = match result {
+                Ok(true) => continue,
+                Ok(false) => CheckProblem::MissingPack {
+                    pack_id: pack_id.clone(),
+                },
+                Err(e) => CheckProblem::Pack {
+                    pack_id: pack_id.clone(),
+                    error: e.to_string(),
+                },
+            };
+            report.add(observer, problem);
+        }
+    }
+    report.packs = packs.len();
+
+    Ok(report)
+}
+
+/// Validates the config and compares its repository ID with the marker's.
+async fn check_config(repo: &Repository) -> Option<CheckProblem> {
+    if let Err(e) = repo.config().validate() {
+        return Some(CheckProblem::Config(e.to_string()));
+    }
+    match repo.marker().await {
+        Ok(Some(marker)) if marker.id != repo.config().id => Some(CheckProblem::Config(format!(
+            "config is for repository {}, but the marker names {}",
+            repo.config().id,
+            marker.id
+        ))),
+        Ok(_) => None,
+        Err(e) => Some(CheckProblem::Config(format!("cannot read marker: {}", e))),
+    }
+}
+
+/// Looks `chunk_ids` up in the index, reporting those that are missing, and
+/// returns the rest grouped by pack.
+async fn check_chunks(
+    repo: &Repository,
+    chunk_ids: &HashSet<ChunkID>,
+    report: &mut CheckReport,
+    observer: &mut dyn CheckObserver,
+) -> BTreeMap<PackID, Vec<ChunkID>> {
+    observer.step(CheckStep::Chunks, chunk_ids.len());
+    let mut by_pack: BTreeMap<PackID, Vec<ChunkID>> = BTreeMap::new();
+    let mut missing = Vec::new();
+    {
+        let index = repo.index();
+        let index = index.read().await;
+        for chunk_id in chunk_ids {
+            match index.get_chunk(chunk_id) {
+                Some(location) => by_pack.entry(location.pack_id).or_default().push(*chunk_id),
+                None => missing.push(*chunk_id),
+            }
+        }
+    }
+    observer.checked(chunk_ids.len());
+
+    missing.sort_by_key(ChunkID::to_hex);
+    for chunk_id in missing {
+        report.add(observer, CheckProblem::MissingChunk { chunk_id });
+    }
+    by_pack
+}
+
+/// Decrypts the pack with `read_data`; otherwise only rejects empty pack
+/// files, which interrupted uploads leave on some backends.
+async fn verify_pack(repo: &Repository, pack_id: &PackID, read_data: bool) -> Result<()> {
+    if read_data {
+        repo.verify_pack(pack_id).await?;
+    } else if repo.pack_size(pack_id).await? == 0 {
+        return Err(Error::Other("Pack file is empty".to_string()));
+    }
+    Ok(())
+}
+
+fn chunk_ids(tree: &Tree) -> HashSet<ChunkID> {
+    tree.nodes
+        .iter()
+        .flat_map(|node| &node.chunks)
+        .map(|chunk_ref| chunk_ref.id)
+        .collect()
+}
+
+/// Subtrees referenced by the directories of a tree object.
+fn subtree_ids(tree: &Tree) -> Vec<ChunkID> {
+    tree.nodes
+        .iter()
+        .filter_map(|node| node.subtree_id)
+        .collect()
+}
+
+/// Loads a subtree with its pages, leaving the subtrees below it unread.
+async fn load_level(repo: &Repository, tree_id: &ChunkID) -> Result<Tree> {
+    let mut level = repo.load_tree_root(tree_id).await?;
+    for page in std::mem::take(&mut level.pages) {
+        level
+            .nodes
+            .extend(repo.load_tree_root(&page.tree).await?.nodes);
+    }
+    Ok(level)
+}
+
+/// Runs `verify` on every item, up to `parallel` at a time, telling the
+/// observer as each finishes. Results are returned in the order of `items`.
+async fn verify_all<'a, T, R, F, Fut>(
+    items: &'a [T],
+    parallel: usize,
+    observer: &mut dyn CheckObserver,
+    verify: F,
+) -> Vec<R>
+where
+    F: Fn(&'a T) -> Fut,
+    Fut: Future<Output = R>,
+{
+    stream::iter(items)
+        .map(verify)
+        .buffered(parallel)
+        .inspect(|_| observer.checked(1))
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_problem_steps() {
+        let chunk_id = ChunkID::from_data(b"chunk");
+        let pack_id = PackID::generate();
+        let report = CheckReport {
+            problems: vec![
+                CheckProblem::MissingChunk { chunk_id },
+                CheckProblem::MissingPack {
+                    pack_id: pack_id.clone(),
+                },
+                CheckProblem::DamagedChunk { chunk_id, pack_id },
+            ],
+            ..CheckReport::default()
+        };
+        assert!(!report.is_ok());
+        assert_eq!(report.problems_in(CheckStep::Chunks), 1);
+        assert_eq!(report.problems_in(CheckStep::IndexedPacks), 1);
+        assert_eq!(report.problems_in(CheckStep::Packs), 1);
+        assert_eq!(report.problems_in(CheckStep::Config), 0);
+        assert!(
+            report.problems[0]
+                .to_string()
+                .contains("referenced but not in index")
+        );
+    }
+}
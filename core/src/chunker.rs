@@ -1,11 +1,27 @@
-use crate::Result;
-use fastcdc::v2020::FastCDC;
+use crate::hash::ChunkHasher;
+use crate::{ChunkID, RepoConfig, Result};
+use fastcdc::v2020::{FastCDC, Normalization};
 use std::io::Read;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Average chunk size unless the repository sets one.
+pub const DEFAULT_CHUNK_SIZE: u32 = 4 * 1024 * 1024;
+
+/// Smallest average chunk size a repository can set. Smaller chunks
+/// deduplicate better but grow the index.
+pub const MIN_CHUNK_SIZE: u32 = 256 * 1024;
+
+/// Largest average chunk size; FastCDC caps chunks at 16 MiB, four times this.
+pub const MAX_CHUNK_SIZE: u32 = 4 * 1024 * 1024;
 
 pub struct Chunker {
     min_size: u32,
     avg_size: u32,
     max_size: u32,
+    /// Mixed into the gear hash so that boundaries differ per repository;
+    /// `None` keeps FastCDC's stock gear table
+    seed: Option<u64>,
+    hasher: ChunkHasher,
 }
 
 impl Chunker {
@@ -14,41 +30,157 @@ impl Chunker {
             min_size: avg_size / 4,
             avg_size,
             max_size: avg_size * 4,
+            seed: None,
+            hasher: ChunkHasher::default(),
         }
     }
 
     pub fn new_default() -> Self {
-        Self::new(4 * 1024 * 1024)
+        Self::new(DEFAULT_CHUNK_SIZE)
+    }
+
+    /// The chunker with the repository's chunk size and gear seed, see
+    /// [`RepoConfig::chunker_seed`].
+    pub fn for_config(config: &RepoConfig) -> Self {
+        let chunker = Self::new(config.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE));
+        match config.chunker_seed() {
+            Some(seed) => chunker.with_seed(seed),
+            None => chunker,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Computes chunk IDs with `hasher` instead of plain BLAKE3.
+    pub fn with_hasher(mut self, hasher: ChunkHasher) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    fn fastcdc<'d>(&self, data: &'d [u8]) -> FastCDC<'d> {
+        match self.seed {
+            Some(seed) => FastCDC::with_level_and_seed(
+                data,
+                self.min_size,
+                self.avg_size,
+                self.max_size,
+                Normalization::Level1,
+                seed,
+            ),
+            None => FastCDC::new(data, self.min_size, self.avg_size, self.max_size),
+        }
     }
 
     pub fn chunk_data(&self, data: &[u8]) -> Vec<Chunk> {
-        let chunker = FastCDC::new(data, self.min_size, self.avg_size, self.max_size);
-        chunker
-            .map(|chunk| Chunk {
-                offset: chunk.offset,
-                length: chunk.length,
-                data: data[chunk.offset..chunk.offset + chunk.length].to_vec(),
+        self.fastcdc(data)
+            .map(|chunk| {
+                let data = &data[chunk.offset..chunk.offset + chunk.length];
+                Chunk {
+                    offset: chunk.offset,
+                    length: chunk.length,
+                    id: self.hasher.hash(data),
+                    data: data.to_vec(),
+                }
             })
             .collect()
     }
 
+    /// Reads all of `reader` into memory and chunks it. Use
+    /// [`chunk_stream`](Self::chunk_stream) for large inputs.
     pub fn chunk_reader<R: Read>(&self, mut reader: R) -> Result<Vec<Chunk>> {
         let mut buffer = Vec::new();
         reader.read_to_end(&mut buffer)?;
         Ok(self.chunk_data(&buffer))
     }
+
+    /// Chunks `reader` as it is read, holding at most twice the maximum
+    /// chunk size in memory. Boundaries are the same as those of
+    /// [`chunk_data`](Self::chunk_data) on the whole input.
+    pub fn chunk_stream<R: AsyncRead + Unpin>(&self, reader: R) -> ChunkStream<'_, R> {
+        ChunkStream {
+            chunker: self,
+            reader,
+            buffer: Vec::new(),
+            start: 0,
+            offset: 0,
+            eof: false,
+        }
+    }
+}
+
+/// Chunks read from an [`AsyncRead`], see [`Chunker::chunk_stream`].
+pub struct ChunkStream<'a, R> {
+    chunker: &'a Chunker,
+    reader: R,
+    buffer: Vec<u8>,
+    /// Start of the data not chunked yet
+    start: usize,
+    /// Stream offset of `buffer[start]`
+    offset: usize,
+    eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> ChunkStream<'_, R> {
+    /// Returns the next chunk, or `None` at the end of the input.
+    pub async fn next_chunk(&mut self) -> Result<Option<Chunk>> {
+        // A cut point depends on at most `max_size` bytes, so with that much
+        // buffered the next chunk ends where it would in the whole input
+        let max_size = self.chunker.max_size as usize;
+        if !self.eof && self.buffer.len() - self.start < max_size {
+            self.fill(2 * max_size).await?;
+        }
+
+        let pending = &self.buffer[self.start..];
+        let Some(cut) = self.chunker.fastcdc(pending).next() else {
+            return Ok(None);
+        };
+        let data = &pending[..cut.length];
+        let chunk = Chunk {
+            offset: self.offset,
+            length: cut.length,
+            id: self.chunker.hasher.hash(data),
+            data: data.to_vec(),
+        };
+        self.start += cut.length;
+        self.offset += cut.length;
+        Ok(Some(chunk))
+    }
+
+    /// Drops the chunked data and reads until `capacity` bytes are buffered
+    /// or the input ends.
+    async fn fill(&mut self, capacity: usize) -> Result<()> {
+        self.buffer.drain(..self.start);
+        self.start = 0;
+
+        let mut len = self.buffer.len();
+        self.buffer.resize(capacity, 0);
+        while len < capacity {
+            let read = self.reader.read(&mut self.buffer[len..]).await?;
+            if read == 0 {
+                self.eof = true;
+                break;
+            }
+            len += read;
+        }
+        self.buffer.truncate(len);
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Chunk {
     pub offset: usize,
     pub length: usize,
+    pub id: ChunkID,
     pub data: Vec<u8>,
 }
 
 impl Chunk {
-    pub fn id(&self) -> crate::ChunkID {
-        crate::ChunkID::from(blake3::hash(&self.data))
+    pub fn id(&self) -> ChunkID {
+        self.id
     }
 
     pub fn data(&self) -> &[u8] {
@@ -70,5 +202,101 @@ mod tests {
 
         let total_size: usize = chunks.iter().map(|c| c.length).sum();
         assert_eq!(total_size, data.len());
+        assert_eq!(chunks[0].id(), ChunkID::from_data(chunks[0].data()));
+
+        let sha256 = ChunkHasher::new(crate::hash::HashAlgorithm::Sha256, None).unwrap();
+        let chunks = Chunker::new(1024)
+            .with_hasher(sha256.clone())
+            .chunk_data(&data);
+        assert_eq!(chunks[0].id(), sha256.hash(chunks[0].data()));
+    }
+
+    #[test]
+    fn test_seeded_boundaries() {
+        let mut state = 1u64;
+        let data: Vec<u8> = (0..256 * 1024)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 56) as u8
+            })
+            .collect();
+        let lengths = |chunker: Chunker| -> Vec<usize> {
+            chunker.chunk_data(&data).iter().map(|c| c.length).collect()
+        };
+
+        let stock = lengths(Chunker::new(4096));
+        let seeded = lengths(Chunker::new(4096).with_seed(0x1234_5678_9abc_def0));
+        assert_eq!(seeded.iter().sum::<usize>(), data.len());
+        assert_ne!(stock, seeded);
+        assert_eq!(
+            seeded,
+            lengths(Chunker::new(4096).with_seed(0x1234_5678_9abc_def0))
+        );
+
+        // Repositories with the legacy polynomial keep their boundaries
+        let legacy = RepoConfig {
+            chunker_polynomial: crate::LEGACY_CHUNKER_POLYNOMIAL,
+            ..RepoConfig::default()
+        };
+        assert_eq!(legacy.chunker_seed(), None);
+        assert!(RepoConfig::default().chunker_seed().is_some());
+        assert!(Chunker::for_config(&legacy).seed.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_chunk_stream() {
+        let mut state = 7u64;
+        let data: Vec<u8> = (0..100_000)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 56) as u8
+            })
+            .collect();
+
+        let chunker = Chunker::new(1024).with_seed(42);
+        let expected = chunker.chunk_data(&data);
+        assert!(expected.len() > 10);
+
+        let mut stream = chunker.chunk_stream(&data[..]);
+        let mut chunks = Vec::new();
+        while let Some(chunk) = stream.next_chunk().await.unwrap() {
+            assert!(stream.buffer.len() <= 2 * chunker.max_size as usize);
+            chunks.push(chunk);
+        }
+        assert_eq!(chunks.len(), expected.len());
+        for (chunk, expected) in chunks.iter().zip(&expected) {
+            assert_eq!(chunk.offset, expected.offset);
+            assert_eq!(chunk.length, expected.length);
+            assert_eq!(chunk.id(), expected.id());
+        }
+
+        let mut empty = chunker.chunk_stream(&[][..]);
+        assert!(empty.next_chunk().await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_configured_chunk_size() {
+        let config = RepoConfig {
+            chunk_size: Some(1024 * 1024),
+            ..RepoConfig::default()
+        };
+        let chunker = Chunker::for_config(&config);
+        assert_eq!(chunker.avg_size, 1024 * 1024);
+        assert_eq!(chunker.max_size, 4 * 1024 * 1024);
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            Chunker::for_config(&RepoConfig::default()).avg_size,
+            DEFAULT_CHUNK_SIZE
+        );
+
+        let invalid = RepoConfig {
+            chunk_size: Some(1024),
+            compression_level: Some(12),
+            ..RepoConfig::default()
+        };
+        match invalid.validate() {
+            Err(crate::Error::InvalidConfig(errors)) => assert_eq!(errors.len(), 2),
+            other => panic!("expected invalid config, got {:?}", other),
+        }
     }
 }
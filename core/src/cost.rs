@@ -0,0 +1,196 @@
+//! Cost estimates for cloud repositories (`stats --cost`).
+//!
+//! A [`Pricing`] table holds what a storage class charges for storage,
+//! requests and transfer. [`estimate`] applies it to the repository's
+//! current size and to the requests a typical backup and restore send:
+//!
+//! - a backup uploads one PUT per new pack, plus the snapshot, tree and
+//!   index objects
+//! - a restore downloads every pack holding restored data in full, one GET
+//!   per pack, so larger packs mean fewer requests but more data read for
+//!   a small restore
+//!
+//! The built-in tables are public list prices of the cheapest region and
+//! ignore free tiers and volume discounts; custom tables can be loaded from
+//! TOML with the same field names.
+
+use serde::{Deserialize, Serialize};
+
+/// Bytes per GB as storage providers bill them.
+const GB: f64 = 1_000_000_000.0;
+
+/// Objects other than packs written by a backup: snapshot, tree and index.
+const BACKUP_OBJECTS: u64 = 3;
+
+/// Objects other than packs read by a restore: config, index, snapshot and
+/// tree.
+const RESTORE_OBJECTS: u64 = 4;
+
+/// What a storage class charges, in US dollars.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pricing {
+    #[serde(default)]
+    pub name: String,
+    /// Storage per GB and month
+    pub storage_gb_month: f64,
+    /// PUT, COPY and LIST requests per 1,000
+    #[serde(default)]
+    pub put_per_1000: f64,
+    /// GET and HEAD requests per 1,000
+    #[serde(default)]
+    pub get_per_1000: f64,
+    /// Data transfer out per GB
+    #[serde(default)]
+    pub egress_gb: f64,
+    /// Retrieval fee per GB read, for infrequent-access and archive classes
+    #[serde(default)]
+    pub retrieval_gb: f64,
+    /// Days an object is billed for even if deleted earlier
+    #[serde(default)]
+    pub min_storage_days: u32,
+}
+
+/// Names of the built-in pricing tables.
+pub const PRICING_TABLES: &[&str] = &[
+    "s3-standard",
+    "s3-standard-ia",
+    "s3-glacier-ir",
+    "s3-deep-archive",
+    "azure-hot",
+    "azure-cool",
+    "azure-archive",
+    "b2",
+    "wasabi",
+];
+
+impl Pricing {
+    /// The built-in table called `name`, one of [`PRICING_TABLES`].
+    pub fn builtin(name: &str) -> Option<Self> {
+        // (storage, put, get, egress, retrieval, minimum days)
+        let (storage, put, get, egress, retrieval, min_days) = match name {
+            "s3-standard" => (0.023, 0.005, 0.0004, 0.09, 0.0, 0),
+            "s3-standard-ia" => (0.0125, 0.01, 0.001, 0.09, 0.01, 30),
+            "s3-glacier-ir" => (0.004, 0.02, 0.01, 0.09, 0.03, 90),
+            "s3-deep-archive" => (0.00099, 0.05, 0.1, 0.09, 0.02, 180),
+            "azure-hot" => (0.018, 0.005, 0.0004, 0.087, 0.0, 0),
+            "azure-cool" => (0.01, 0.01, 0.001, 0.087, 0.01, 30),
+            "azure-archive" => (0.00099, 0.011, 0.55, 0.087, 0.022, 180),
+            "b2" => (0.006, 0.0, 0.0004, 0.01, 0.0, 0),
+            "wasabi" => (0.00699, 0.0, 0.0, 0.0, 0.0, 90),
+            _ => return None,
+        };
+        Some(Self {
+            name: name.to_string(),
+            storage_gb_month: storage,
+            put_per_1000: put,
+            get_per_1000: get,
+            egress_gb: egress,
+            retrieval_gb: retrieval,
+            min_storage_days: min_days,
+        })
+    }
+}
+
+/// Repository figures and operation sizes an estimate is made for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostInputs {
+    /// Bytes stored: packs and index
+    pub stored_bytes: u64,
+    /// Size of new packs
+    pub pack_size: u64,
+    /// New data a backup uploads, after deduplication and compression
+    pub backup_bytes: u64,
+    /// Data a restore downloads
+    pub restore_bytes: u64,
+}
+
+/// Requests and fees of one operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct OperationCost {
+    pub requests: u64,
+    pub request_cost: f64,
+    pub transfer_cost: f64,
+}
+
+impl OperationCost {
+    pub fn total(&self) -> f64 {
+        self.request_cost + self.transfer_cost
+    }
+}
+
+/// Estimated costs for one pricing table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CostEstimate {
+    pub pricing: String,
+    /// Storing the repository for a month
+    pub storage_month: f64,
+    /// Uploading `backup_bytes` of new data, without the storage it adds
+    pub backup: OperationCost,
+    /// Downloading `restore_bytes`
+    pub restore: OperationCost,
+}
+
+/// Estimates what the repository and its typical operations cost under
+/// `pricing`.
+pub fn estimate(pricing: &Pricing, inputs: &CostInputs) -> CostEstimate {
+    let packs = |bytes: u64| bytes.div_ceil(inputs.pack_size.max(1));
+
+    let backup_requests = packs(inputs.backup_bytes) + BACKUP_OBJECTS;
+    let backup = OperationCost {
+        requests: backup_requests,
+        request_cost: backup_requests as f64 / 1000.0 * pricing.put_per_1000,
+        transfer_cost: 0.0,
+    };
+
+    // Whole packs are read, so a restore downloads at least one pack
+    let restore_packs = packs(inputs.restore_bytes);
+    let downloaded = (restore_packs * inputs.pack_size).max(inputs.restore_bytes) as f64 / GB;
+    let restore_requests = restore_packs + RESTORE_OBJECTS;
+    let restore = OperationCost {
+        requests: restore_requests,
+        request_cost: restore_requests as f64 / 1000.0 * pricing.get_per_1000,
+        transfer_cost: downloaded * (pricing.egress_gb + pricing.retrieval_gb),
+    };
+
+    CostEstimate {
+        pricing: pricing.name.clone(),
+        storage_month: inputs.stored_bytes as f64 / GB * pricing.storage_gb_month,
+        backup,
+        restore,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_tables() {
+        for name in PRICING_TABLES {
+            let pricing = Pricing::builtin(name).unwrap();
+            assert_eq!(pricing.name, *name);
+            assert!(pricing.storage_gb_month > 0.0);
+        }
+        assert!(Pricing::builtin("s3-unknown").is_none());
+    }
+
+    #[test]
+    fn test_estimate() {
+        let pricing = Pricing::builtin("s3-standard").unwrap();
+        let inputs = CostInputs {
+            stored_bytes: 100_000_000_000,
+            pack_size: 64_000_000,
+            backup_bytes: 640_000_000,
+            restore_bytes: 1_000_000_000,
+        };
+        let estimate = estimate(&pricing, &inputs);
+
+        assert!((estimate.storage_month - 2.3).abs() < 1e-9);
+        assert_eq!(estimate.backup.requests, 13);
+        assert!((estimate.backup.request_cost - 0.000065).abs() < 1e-12);
+        // 16 packs of 64 MB are downloaded for 1 GB
+        assert_eq!(estimate.restore.requests, 20);
+        assert!((estimate.restore.transfer_cost - 1.024 * 0.09).abs() < 1e-9);
+    }
+}
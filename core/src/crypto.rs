@@ -1,22 +1,46 @@
-use crate::{Error, Result};
+use crate::{EncryptionMode, Error, KDF_ARGON2ID, KDF_PBKDF2_SHA256, Result};
+use aes_gcm::Aes256Gcm;
 use argon2::password_hash::{SaltString, rand_core::OsRng};
 use argon2::{Argon2, PasswordHasher};
 use chacha20poly1305::{
     ChaCha20Poly1305, Key, Nonce,
     aead::{Aead, AeadCore, KeyInit, OsRng as AeadOsRng},
 };
+use hmac::{Hmac, Mac};
 use rand::RngCore;
+use sha2::Sha256;
 
 pub struct MasterKey {
     key: Vec<u8>,
 }
 
 impl MasterKey {
+    /// Derives a key with the algorithm named in `params`.
     pub fn derive_from_password(
         password: &str,
         salt: &[u8],
         params: &crate::KdfParams,
     ) -> Result<Self> {
+        match params.algorithm.as_str() {
+            KDF_ARGON2ID => Self::derive_argon2id(password, salt, params),
+            KDF_PBKDF2_SHA256 => {
+                let mut key = vec![0u8; 32];
+                pbkdf2::pbkdf2_hmac::<Sha256>(
+                    password.as_bytes(),
+                    salt,
+                    params.iterations,
+                    &mut key,
+                );
+                Ok(Self { key })
+            }
+            other => Err(Error::Encryption(format!(
+                "Unsupported key derivation '{}'",
+                other
+            ))),
+        }
+    }
+
+    fn derive_argon2id(password: &str, salt: &[u8], params: &crate::KdfParams) -> Result<Self> {
         let argon2 = Argon2::new(
             argon2::Algorithm::Argon2id,
             argon2::Version::V0x13,
@@ -46,29 +70,196 @@ impl MasterKey {
     pub fn as_bytes(&self) -> &[u8] {
         &self.key
     }
+
+    /// Mixes keyfile contents into this key, so that the result can only be
+    /// reproduced with both the password and the keyfile.
+    pub fn combine_with_keyfile(&self, keyfile: &[u8]) -> Result<Self> {
+        let key: &[u8; 32] = self
+            .key
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::Encryption("Key must be 32 bytes".to_string()))?;
+        Ok(Self {
+            key: blake3::keyed_hash(key, keyfile).as_bytes().to_vec(),
+        })
+    }
+
+    /// Like [`MasterKey::combine_with_keyfile`], with HMAC-SHA256 instead of
+    /// keyed BLAKE3 for keys derived under the FIPS profile.
+    pub fn combine_with_keyfile_hmac(&self, keyfile: &[u8]) -> Result<Self> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.key)
+            .map_err(|e| Error::Encryption(e.to_string()))?;
+        mac.update(keyfile);
+        Ok(Self {
+            key: mac.finalize().into_bytes().to_vec(),
+        })
+    }
+}
+
+/// Size of keyfiles generated by [`generate_keyfile`].
+pub const KEYFILE_LEN: usize = 64;
+
+/// Generates random contents for a new keyfile.
+pub fn generate_keyfile() -> Vec<u8> {
+    let mut contents = vec![0u8; KEYFILE_LEN];
+    OsRng.fill_bytes(&mut contents);
+    contents
 }
 
+/// Supplies the secrets that unlock a repository's data key.
+pub trait KeyProvider: Send + Sync {
+    /// Derives the key-encryption key for a data key sealed with `params`.
+    /// `keyfile` is true when that data key was sealed with a keyfile as a
+    /// second factor.
+    fn derive_kek(&self, params: &crate::KdfParams, keyfile: bool) -> Result<MasterKey>;
+
+    /// Whether data keys sealed through this provider require a keyfile.
+    fn has_keyfile(&self) -> bool;
+
+    /// Whether the caller supplied any secret at all, which unencrypted
+    /// repositories ignore.
+    fn has_secret(&self) -> bool;
+}
+
+/// A password, optionally combined with a keyfile.
+///
+/// The keyfile is only used for data keys that were sealed with one, so the
+/// same provider can open repositories with and without a keyfile.
+pub struct PasswordKey {
+    password: String,
+    keyfile: Option<Vec<u8>>,
+}
+
+impl PasswordKey {
+    pub fn new(password: impl Into<String>) -> Self {
+        Self {
+            password: password.into(),
+            keyfile: None,
+        }
+    }
+
+    pub fn with_keyfile(mut self, contents: Vec<u8>) -> Self {
+        self.keyfile = Some(contents);
+        self
+    }
+}
+
+impl KeyProvider for PasswordKey {
+    fn derive_kek(&self, params: &crate::KdfParams, keyfile: bool) -> Result<MasterKey> {
+        let kek = MasterKey::derive_from_password(&self.password, &params.salt, params)?;
+        if !keyfile {
+            return Ok(kek);
+        }
+        let contents = self.keyfile.as_ref().ok_or(Error::KeyfileRequired)?;
+        if params.algorithm == KDF_PBKDF2_SHA256 {
+            kek.combine_with_keyfile_hmac(contents)
+        } else {
+            kek.combine_with_keyfile(contents)
+        }
+    }
+
+    fn has_keyfile(&self) -> bool {
+        self.keyfile.is_some()
+    }
+
+    fn has_secret(&self) -> bool {
+        !self.password.is_empty() || self.keyfile.is_some()
+    }
+}
+
+/// Length of the BLAKE3 checksum prefixed to objects in unencrypted repositories.
+const CHECKSUM_LEN: usize = 32;
+
+/// BLAKE3 key derivation context of the keyed chunk ID hash.
+const CHUNK_ID_KEY_CONTEXT: &str = "ghostsnap chunk ID key v1";
+
+/// AEAD of an encrypted repository. Both take a 96-bit nonce, so sealed
+/// objects have the same layout. AES-256-GCM keeps its expanded key
+/// schedule, so it is boxed to keep the enum small.
+enum Cipher {
+    ChaCha20Poly1305(ChaCha20Poly1305),
+    Aes256Gcm(Box<Aes256Gcm>),
+}
+
+/// Seals and opens repository objects according to the repository's
+/// [`EncryptionMode`].
+///
+/// Encrypted repositories use ChaCha20-Poly1305, or AES-256-GCM under the
+/// FIPS profile, with a random nonce. In unencrypted repositories objects
+/// are stored as plaintext prefixed with their BLAKE3 hash, so corruption is
+/// still detected on read.
 pub struct Encryptor {
-    cipher: ChaCha20Poly1305,
+    /// `None` for unencrypted repositories
+    cipher: Option<Cipher>,
+    /// Key of the keyed chunk ID hash, derived from the encryption key
+    chunk_id_key: Option<[u8; 32]>,
 }
 
 impl Encryptor {
+    /// Creates a ChaCha20-Poly1305 encryptor.
     pub fn new(key: &[u8]) -> Result<Self> {
+        Self::with_mode(EncryptionMode::Chacha20Poly1305, key)
+    }
+
+    /// Creates an encryptor for `mode`; `key` is ignored for
+    /// [`EncryptionMode::None`].
+    pub fn with_mode(mode: EncryptionMode, key: &[u8]) -> Result<Self> {
+        if mode == EncryptionMode::None {
+            return Ok(Self::plaintext());
+        }
         if key.len() != 32 {
             return Err(Error::Encryption("Key must be 32 bytes".to_string()));
         }
 
+        let chunk_id_key = blake3::derive_key(CHUNK_ID_KEY_CONTEXT, key);
         let key = Key::from_slice(key);
-        let cipher = ChaCha20Poly1305::new(key);
-        Ok(Self { cipher })
+        let cipher = match mode {
+            EncryptionMode::Aes256Gcm => Cipher::Aes256Gcm(Box::new(Aes256Gcm::new(key))),
+            _ => Cipher::ChaCha20Poly1305(ChaCha20Poly1305::new(key)),
+        };
+        Ok(Self {
+            cipher: Some(cipher),
+            chunk_id_key: Some(chunk_id_key),
+        })
+    }
+
+    /// Creates an encryptor for unencrypted repositories: objects are only
+    /// checksummed.
+    pub fn plaintext() -> Self {
+        Self {
+            cipher: None,
+            chunk_id_key: None,
+        }
+    }
+
+    /// Key for [`HashAlgorithm::KeyedBlake3`](crate::hash::HashAlgorithm)
+    /// chunk IDs; `None` for unencrypted repositories.
+    pub fn chunk_id_key(&self) -> Option<&[u8; 32]> {
+        self.chunk_id_key.as_ref()
+    }
+
+    pub fn mode(&self) -> EncryptionMode {
+        match self.cipher {
+            Some(Cipher::ChaCha20Poly1305(_)) => EncryptionMode::Chacha20Poly1305,
+            Some(Cipher::Aes256Gcm(_)) => EncryptionMode::Aes256Gcm,
+            None => EncryptionMode::None,
+        }
     }
 
     pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let Some(cipher) = &self.cipher else {
+            let mut result = Vec::with_capacity(CHECKSUM_LEN + plaintext.len());
+            result.extend_from_slice(blake3::hash(plaintext).as_bytes());
+            result.extend_from_slice(plaintext);
+            return Ok(result);
+        };
+
         let nonce = ChaCha20Poly1305::generate_nonce(&mut AeadOsRng);
-        let ciphertext = self
-            .cipher
-            .encrypt(&nonce, plaintext)
-            .map_err(|e| Error::Encryption(e.to_string()))?;
+        let ciphertext = match cipher {
+            Cipher::ChaCha20Poly1305(cipher) => cipher.encrypt(&nonce, plaintext),
+            Cipher::Aes256Gcm(cipher) => cipher.encrypt(&nonce, plaintext),
+        }
+        .map_err(|e| Error::Encryption(e.to_string()))?;
 
         let mut result = nonce.to_vec();
         result.extend_from_slice(&ciphertext);
@@ -76,6 +267,17 @@ impl Encryptor {
     }
 
     pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let Some(cipher) = &self.cipher else {
+            if ciphertext.len() < CHECKSUM_LEN {
+                return Err(Error::Encryption("Object too short".to_string()));
+            }
+            let (checksum, plaintext) = ciphertext.split_at(CHECKSUM_LEN);
+            if blake3::hash(plaintext).as_bytes() != checksum {
+                return Err(Error::Encryption("Checksum mismatch".to_string()));
+            }
+            return Ok(plaintext.to_vec());
+        };
+
         if ciphertext.len() < 12 {
             return Err(Error::Encryption("Ciphertext too short".to_string()));
         }
@@ -83,9 +285,11 @@ impl Encryptor {
         let (nonce_bytes, encrypted) = ciphertext.split_at(12);
         let nonce = Nonce::from_slice(nonce_bytes);
 
-        self.cipher
-            .decrypt(nonce, encrypted)
-            .map_err(|e| Error::Encryption(e.to_string()))
+        match cipher {
+            Cipher::ChaCha20Poly1305(cipher) => cipher.decrypt(nonce, encrypted),
+            Cipher::Aes256Gcm(cipher) => cipher.decrypt(nonce, encrypted),
+        }
+        .map_err(|e| Error::Encryption(e.to_string()))
     }
 }
 
@@ -108,4 +312,99 @@ mod tests {
 
         assert_eq!(plaintext.to_vec(), decrypted);
     }
+
+    #[test]
+    fn test_aes256_gcm() {
+        let key = MasterKey::generate();
+        let aes = Encryptor::with_mode(EncryptionMode::Aes256Gcm, key.as_bytes()).unwrap();
+        assert_eq!(aes.mode(), EncryptionMode::Aes256Gcm);
+
+        let ciphertext = aes.encrypt(b"Hello, Ghostsnap!").unwrap();
+        assert_eq!(aes.decrypt(&ciphertext).unwrap(), b"Hello, Ghostsnap!");
+        let chacha = Encryptor::new(key.as_bytes()).unwrap();
+        assert!(chacha.decrypt(&ciphertext).is_err());
+    }
+
+    #[test]
+    fn test_pbkdf2() {
+        // RFC 7914, section 11
+        let params = crate::KdfParams {
+            iterations: 1,
+            salt: b"salt".to_vec(),
+            ..crate::KdfParams::pbkdf2_sha256()
+        };
+        let key = MasterKey::derive_from_password("passwd", &params.salt, &params).unwrap();
+        assert_eq!(
+            hex::encode(key.as_bytes()),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc"
+        );
+
+        let unknown = crate::KdfParams {
+            algorithm: "md5".to_string(),
+            ..params.clone()
+        };
+        assert!(MasterKey::derive_from_password("passwd", &params.salt, &unknown).is_err());
+
+        // The keyfile is mixed in with HMAC-SHA256
+        let with_keyfile = PasswordKey::new("passwd").with_keyfile(b"keyfile".to_vec());
+        assert_eq!(
+            with_keyfile.derive_kek(&params, true).unwrap().as_bytes(),
+            key.combine_with_keyfile_hmac(b"keyfile")
+                .unwrap()
+                .as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_keyfile_changes_kek() {
+        let params = crate::KdfParams::default();
+        let keyfile = generate_keyfile();
+
+        let password_only = PasswordKey::new("secret");
+        let with_keyfile = PasswordKey::new("secret").with_keyfile(keyfile.clone());
+        let other_keyfile = PasswordKey::new("secret").with_keyfile(generate_keyfile());
+
+        let kek = with_keyfile.derive_kek(&params, true).unwrap();
+        assert_eq!(
+            kek.as_bytes(),
+            PasswordKey::new("secret")
+                .with_keyfile(keyfile)
+                .derive_kek(&params, true)
+                .unwrap()
+                .as_bytes()
+        );
+        assert_ne!(
+            kek.as_bytes(),
+            other_keyfile.derive_kek(&params, true).unwrap().as_bytes()
+        );
+        assert_ne!(
+            kek.as_bytes(),
+            password_only.derive_kek(&params, false).unwrap().as_bytes()
+        );
+        assert!(matches!(
+            password_only.derive_kek(&params, true),
+            Err(Error::KeyfileRequired)
+        ));
+
+        // Keys sealed without a keyfile ignore it
+        assert_eq!(
+            with_keyfile.derive_kek(&params, false).unwrap().as_bytes(),
+            password_only.derive_kek(&params, false).unwrap().as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_plaintext_mode_detects_corruption() {
+        let encryptor = Encryptor::plaintext();
+        assert_eq!(encryptor.mode(), EncryptionMode::None);
+
+        let sealed = encryptor.encrypt(b"Hello, Ghostsnap!").unwrap();
+        assert!(sealed.ends_with(b"Hello, Ghostsnap!"));
+        assert_eq!(encryptor.decrypt(&sealed).unwrap(), b"Hello, Ghostsnap!");
+
+        let mut corrupted = sealed.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(encryptor.decrypt(&corrupted).is_err());
+        assert!(encryptor.decrypt(&sealed[..8]).is_err());
+    }
 }
@@ -0,0 +1,378 @@
+//! Comparing snapshot trees.
+//!
+//! [`diff_trees`] reports what changed between two trees, path by path. A path
+//! that disappears from the old tree and reappears elsewhere in the new one
+//! with the same content is reported as a rename instead of a removal plus an
+//! addition: a file matches when its chunk list is identical, and a directory
+//! matches when everything below it does. A renamed directory is reported once,
+//! without its contents.
+//!
+//! Renamed data deduplicates, so [`TreeDiff::new_bytes`] counts only chunks the
+//! old tree did not reference.
+
+use crate::snapshot::Tree;
+use crate::types::{ChunkID, DeviceNumber, NodeType, TreeNode};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// How a single path differs between two trees.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    Modified {
+        old_size: u64,
+        new_size: u64,
+    },
+    TypeChanged {
+        old_type: NodeType,
+        new_type: NodeType,
+    },
+    /// Only mode, ownership or mtime changed
+    MetadataChanged,
+    /// The path holds what `from` held in the old tree
+    Renamed {
+        from: String,
+    },
+}
+
+/// Differences between two trees.
+#[derive(Debug, Clone, Default)]
+pub struct TreeDiff {
+    /// Changes ordered by path (the new path for renames)
+    pub changes: Vec<(String, Change)>,
+    /// Size of the distinct chunks the new tree references and the old one
+    /// does not
+    pub new_bytes: u64,
+}
+
+impl TreeDiff {
+    /// Number of changes matching `predicate`.
+    pub fn count(&self, predicate: impl Fn(&Change) -> bool) -> usize {
+        self.changes
+            .iter()
+            .filter(|(_, change)| predicate(change))
+            .count()
+    }
+}
+
+/// Compares `old` with `new`. Metadata-only changes are reported when
+/// `metadata` is set.
+pub fn diff_trees(old: &Tree, new: &Tree, metadata: bool) -> TreeDiff {
+    let old_nodes: BTreeMap<&str, &TreeNode> =
+        old.nodes.iter().map(|n| (n.name.as_str(), n)).collect();
+    let new_nodes: BTreeMap<&str, &TreeNode> =
+        new.nodes.iter().map(|n| (n.name.as_str(), n)).collect();
+
+    let removed: Vec<&str> = old_nodes
+        .keys()
+        .filter(|path| !new_nodes.contains_key(*path))
+        .copied()
+        .collect();
+    let added: Vec<&str> = new_nodes
+        .keys()
+        .filter(|path| !old_nodes.contains_key(*path))
+        .copied()
+        .collect();
+
+    let mut changes = Vec::new();
+    let renames = detect_renames(&old_nodes, &new_nodes, &removed, &added);
+    for (from, to) in &renames {
+        changes.push((
+            to.to_string(),
+            Change::Renamed {
+                from: from.to_string(),
+            },
+        ));
+    }
+
+    for path in removed {
+        if !renames.iter().any(|(from, _)| is_within(path, from)) {
+            changes.push((path.to_string(), Change::Removed));
+        }
+    }
+    for path in added {
+        if !renames.iter().any(|(_, to)| is_within(path, to)) {
+            changes.push((path.to_string(), Change::Added));
+        }
+    }
+
+    for (path, old_node) in &old_nodes {
+        let Some(new_node) = new_nodes.get(path) else {
+            continue;
+        };
+        if let Some(change) = compare_nodes(old_node, new_node, metadata) {
+            changes.push((path.to_string(), change));
+        }
+    }
+
+    changes.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let old_chunks: HashSet<ChunkID> = old
+        .nodes
+        .iter()
+        .flat_map(|n| n.chunks.iter().map(|c| c.id))
+        .collect();
+    let mut counted = HashSet::new();
+    let new_bytes = new
+        .nodes
+        .iter()
+        .flat_map(|n| &n.chunks)
+        .filter(|c| !old_chunks.contains(&c.id) && counted.insert(c.id))
+        .map(|c| c.length as u64)
+        .sum();
+
+    TreeDiff { changes, new_bytes }
+}
+
+fn compare_nodes(old: &TreeNode, new: &TreeNode, metadata: bool) -> Option<Change> {
+    if old.node_type != new.node_type {
+        return Some(Change::TypeChanged {
+            old_type: old.node_type.clone(),
+            new_type: new.node_type.clone(),
+        });
+    }
+
+    let old_chunks = old.chunks.iter().map(|c| c.id);
+    if !old_chunks.eq(new.chunks.iter().map(|c| c.id))
+        || old.link_target != new.link_target
+        || old.device != new.device
+    {
+        return Some(Change::Modified {
+            old_size: old.size,
+            new_size: new.size,
+        });
+    }
+
+    if metadata
+        && (old.mode != new.mode
+            || old.uid != new.uid
+            || old.gid != new.gid
+            || old.mtime != new.mtime)
+    {
+        return Some(Change::MetadataChanged);
+    }
+    None
+}
+
+/// What a node holds, independent of its name and metadata.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Content {
+    File(Vec<ChunkID>),
+    Directory,
+    Symlink(Option<String>),
+    Special(NodeType, Option<DeviceNumber>),
+}
+
+impl Content {
+    fn of(node: &TreeNode) -> Self {
+        match node.node_type {
+            NodeType::File => Content::File(node.chunks.iter().map(|c| c.id).collect()),
+            NodeType::Directory => Content::Directory,
+            NodeType::Symlink => Content::Symlink(node.link_target.clone()),
+            NodeType::CharDevice | NodeType::BlockDevice | NodeType::Fifo => {
+                Content::Special(node.node_type.clone(), node.device)
+            }
+        }
+    }
+
+    /// Empty files all look alike, so they never identify a rename.
+    fn is_distinctive(&self) -> bool {
+        matches!(self, Content::File(chunks) if !chunks.is_empty())
+    }
+}
+
+/// Everything below a directory, by path relative to it.
+type DirContent = Vec<(String, Content)>;
+
+fn dir_content(nodes: &BTreeMap<&str, &TreeNode>, dir: &str) -> DirContent {
+    let prefix = format!("{}/", dir);
+    nodes
+        .range(prefix.as_str()..)
+        .take_while(|(path, _)| path.starts_with(&prefix))
+        .map(|(path, node)| (path[prefix.len()..].to_string(), Content::of(node)))
+        .collect()
+}
+
+/// Pairs removed paths with added paths holding the same content, returning
+/// `(from, to)` pairs. Directories are matched first, topmost first, so that
+/// a renamed directory is reported instead of each entry below it.
+fn detect_renames<'a>(
+    old_nodes: &BTreeMap<&str, &TreeNode>,
+    new_nodes: &BTreeMap<&'a str, &TreeNode>,
+    removed: &[&'a str],
+    added: &[&'a str],
+) -> Vec<(&'a str, &'a str)> {
+    let mut renames: Vec<(&str, &str)> = Vec::new();
+    let claimed = |renames: &[(&str, &str)], path: &str, new_side: bool| {
+        renames.iter().any(|(from, to)| {
+            let renamed = if new_side { to } else { from };
+            is_within(path, renamed)
+        })
+    };
+
+    let mut added_dirs: HashMap<DirContent, Vec<&str>> = HashMap::new();
+    for &path in added {
+        if new_nodes[path].is_dir() {
+            let content = dir_content(new_nodes, path);
+            if content.iter().any(|(_, c)| c.is_distinctive()) {
+                added_dirs.entry(content).or_default().push(path);
+            }
+        }
+    }
+    for &path in removed {
+        if !old_nodes[path].is_dir() || claimed(&renames, path, false) {
+            continue;
+        }
+        let Some(candidates) = added_dirs.get(&dir_content(old_nodes, path)) else {
+            continue;
+        };
+        let target = pick_candidate(path, candidates, |c| !claimed(&renames, c, true));
+        if let Some(target) = target {
+            renames.push((path, target));
+        }
+    }
+
+    let mut added_files: HashMap<Content, Vec<&str>> = HashMap::new();
+    for &path in added {
+        let content = Content::of(new_nodes[path]);
+        if content.is_distinctive() && !claimed(&renames, path, true) {
+            added_files.entry(content).or_default().push(path);
+        }
+    }
+    for &path in removed {
+        let content = Content::of(old_nodes[path]);
+        if !content.is_distinctive() || claimed(&renames, path, false) {
+            continue;
+        }
+        let Some(candidates) = added_files.get(&content) else {
+            continue;
+        };
+        let target = pick_candidate(path, candidates, |c| {
+            !renames.iter().any(|(_, to)| *to == c)
+        });
+        if let Some(target) = target {
+            renames.push((path, target));
+        }
+    }
+
+    renames
+}
+
+/// Picks the available candidate for `path`, preferring one with the same
+/// file name.
+fn pick_candidate<'a>(
+    path: &str,
+    candidates: &[&'a str],
+    available: impl Fn(&str) -> bool,
+) -> Option<&'a str> {
+    let mut available = candidates.iter().copied().filter(|c| available(c));
+    let first = available.next()?;
+    let name = file_name(path);
+    Some(
+        std::iter::once(first)
+            .chain(available)
+            .find(|c| file_name(c) == name)
+            .unwrap_or(first),
+    )
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Returns whether `path` is `dir` or lies below it.
+fn is_within(path: &str, dir: &str) -> bool {
+    path == dir
+        || path
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str, node_type: NodeType, data: &[&[u8]]) -> TreeNode {
+        TreeNode {
+            node_type,
+            ..TreeNode::test_file(name, data)
+        }
+    }
+
+    fn tree(nodes: Vec<TreeNode>) -> Tree {
+        Tree {
+            nodes,
+            pages: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_directory_rename_reported_once() {
+        let old = tree(vec![
+            node("photos", NodeType::Directory, &[]),
+            node("photos/a.jpg", NodeType::File, &[b"aaaa"]),
+            node("photos/b.jpg", NodeType::File, &[b"bbbb"]),
+            node("notes.txt", NodeType::File, &[b"old"]),
+        ]);
+        let new = tree(vec![
+            node("pictures", NodeType::Directory, &[]),
+            node("pictures/a.jpg", NodeType::File, &[b"aaaa"]),
+            node("pictures/b.jpg", NodeType::File, &[b"bbbb"]),
+            node("notes.txt", NodeType::File, &[b"new!"]),
+        ]);
+
+        let diff = diff_trees(&old, &new, false);
+        assert_eq!(
+            diff.changes,
+            vec![
+                (
+                    "notes.txt".to_string(),
+                    Change::Modified {
+                        old_size: 3,
+                        new_size: 4
+                    }
+                ),
+                (
+                    "pictures".to_string(),
+                    Change::Renamed {
+                        from: "photos".to_string()
+                    }
+                ),
+            ]
+        );
+        assert_eq!(diff.new_bytes, 4);
+    }
+
+    #[test]
+    fn test_file_renames_and_real_changes() {
+        let old = tree(vec![
+            node("a/report.pdf", NodeType::File, &[b"report", b"pages"]),
+            node("a/gone.txt", NodeType::File, &[b"gone"]),
+            node("a/empty", NodeType::File, &[]),
+        ]);
+        let new = tree(vec![
+            node("b/report.pdf", NodeType::File, &[b"report", b"pages"]),
+            node("b/fresh.txt", NodeType::File, &[b"fresh"]),
+            node("b/empty", NodeType::File, &[]),
+        ]);
+
+        let diff = diff_trees(&old, &new, false);
+        assert_eq!(
+            diff.changes,
+            vec![
+                ("a/empty".to_string(), Change::Removed),
+                ("a/gone.txt".to_string(), Change::Removed),
+                ("b/empty".to_string(), Change::Added),
+                ("b/fresh.txt".to_string(), Change::Added),
+                (
+                    "b/report.pdf".to_string(),
+                    Change::Renamed {
+                        from: "a/report.pdf".to_string()
+                    }
+                ),
+            ]
+        );
+        assert_eq!(diff.count(|c| matches!(c, Change::Renamed { .. })), 1);
+        assert_eq!(diff.new_bytes, 5);
+    }
+}
@@ -0,0 +1,138 @@
+//! Read-only storage for `--dry-run`.
+//!
+//! Commands with a dry run print what they would delete or change and skip
+//! doing it. As a safety net, storage opened while dry-run mode is on is
+//! wrapped in [`DryRunStorage`], which passes reads through and rejects every
+//! call that would modify the repository with [`Error::DryRun`], so a code
+//! path that forgets to check the flag fails instead of deleting data.
+
+use crate::storage::{ObjectMetadata, RepositoryLocation, RepositoryStorage, TierStatus};
+use crate::{AccessTier, ChunkID, Error, RehydratePriority, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns on dry-run mode for storage opened from now on.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether dry-run mode is on.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Storage wrapper that rejects every modification of the storage it wraps.
+pub struct DryRunStorage {
+    inner: Box<dyn RepositoryStorage>,
+}
+
+impl DryRunStorage {
+    pub fn new(inner: Box<dyn RepositoryStorage>) -> Self {
+        Self { inner }
+    }
+
+    fn reject(operation: &str, path: &str) -> Error {
+        tracing::debug!(operation, path, "Dry run: rejected storage modification");
+        Error::DryRun {
+            operation: operation.to_string(),
+            path: path.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl RepositoryStorage for DryRunStorage {
+    fn location(&self) -> &RepositoryLocation {
+        self.inner.location()
+    }
+
+    async fn init(&self) -> Result<()> {
+        Err(Self::reject("initialize", &self.inner.location().display()))
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool> {
+        self.inner.exists(path).await
+    }
+
+    async fn read(&self, path: &str) -> Result<Bytes> {
+        self.inner.read(path).await
+    }
+
+    async fn write(&self, path: &str, _data: Bytes) -> Result<()> {
+        Err(Self::reject("write", path))
+    }
+
+    async fn delete(&self, path: &str) -> Result<()> {
+        Err(Self::reject("delete", path))
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        self.inner.list(prefix).await
+    }
+
+    async fn metadata(&self, path: &str) -> Result<ObjectMetadata> {
+        self.inner.metadata(path).await
+    }
+
+    async fn copy(&self, _from: &str, to: &str) -> Result<()> {
+        Err(Self::reject("write", to))
+    }
+
+    async fn has_chunks(&self, chunk_ids: &[ChunkID]) -> Result<Option<Vec<bool>>> {
+        self.inner.has_chunks(chunk_ids).await
+    }
+
+    async fn migrate_layout(&self) -> Result<usize> {
+        Err(Self::reject(
+            "migrate the layout of",
+            &self.inner.location().display(),
+        ))
+    }
+
+    async fn access_tier(&self, path: &str) -> Result<Option<TierStatus>> {
+        self.inner.access_tier(path).await
+    }
+
+    async fn set_access_tier(
+        &self,
+        path: &str,
+        _tier: AccessTier,
+        _priority: RehydratePriority,
+    ) -> Result<()> {
+        Err(Self::reject("change the access tier of", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::local_storage;
+
+    #[tokio::test]
+    async fn test_dry_run_storage_rejects_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let inner = local_storage(dir.path());
+        inner.init().await.unwrap();
+        inner
+            .write("config", Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+
+        let storage = DryRunStorage::new(inner);
+        assert_eq!(storage.read("config").await.unwrap(), "data");
+        assert!(storage.metadata("config").await.is_ok());
+
+        let err = storage.delete("config").await.unwrap_err();
+        assert!(matches!(err, Error::DryRun { .. }));
+        assert_eq!(err.to_string(), "Dry run: not allowed to delete config");
+        assert!(storage.write("new", Bytes::new()).await.is_err());
+        assert!(storage.copy("config", "copy").await.is_err());
+        assert!(storage.migrate_layout().await.is_err());
+
+        assert!(storage.exists("config").await.unwrap());
+        assert!(!storage.exists("new").await.unwrap());
+    }
+}
@@ -1,3 +1,4 @@
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -23,23 +24,186 @@ pub enum Error {
     #[error("Pack file corrupted: {id}")]
     CorruptedPack { id: String },
 
+    /// A pack with the same ID is stored already; packs are never replaced.
+    #[error("Pack already exists: {id}")]
+    PackExists { id: String },
+
     #[error("Snapshot not found: {id}")]
     SnapshotNotFound { id: String },
 
+    /// A snapshot or pack ID that is not a lowercase hyphenated UUID.
+    #[error("Invalid {kind} ID: {id}")]
+    InvalidId { kind: &'static str, id: String },
+
     #[error("Invalid password")]
     InvalidPassword,
 
+    #[error("Repository key requires a keyfile")]
+    KeyfileRequired,
+
+    /// No key file could be read, so the password was never checked.
+    #[error("Key file {name} is corrupt: {reason}")]
+    CorruptKeyFile { name: String, reason: String },
+
+    #[error("Invalid recovery code: {0}")]
+    InvalidRecoveryCode(String),
+
     #[error("Backend error: {0}")]
     Backend(String),
 
+    /// A backend request answered with an HTTP error status.
+    #[error("Backend error: {message} (HTTP {status})")]
+    BackendStatus {
+        status: u16,
+        message: String,
+        /// Delay requested by the service's `Retry-After` header
+        retry_after: Option<Duration>,
+    },
+
+    /// The backend failed repeatedly and requests are not sent until
+    /// `retry_in` has passed.
+    #[error(
+        "Backend unavailable after {failures} consecutive failures, not retrying for {}s: {last_error}",
+        .retry_in.as_secs()
+    )]
+    BackendUnavailable {
+        failures: u32,
+        retry_in: Duration,
+        last_error: String,
+    },
+
+    /// A modification rejected because the run is a dry run.
+    #[error("Dry run: not allowed to {operation} {path}")]
+    DryRun { operation: String, path: String },
+
+    /// Configuration values that failed validation, one entry per field.
+    #[error(
+        "Invalid configuration: {}",
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+    )]
+    InvalidConfig(Vec<crate::validation::FieldError>),
+
     #[error("Chunk not found: {id}")]
     ChunkNotFound { id: String },
 
     #[error("Lock conflict: {0}")]
     LockConflict(String),
 
+    /// The storage credential lacks permissions an operation needs.
+    #[error("The storage credential cannot {missing}, which {operation} needs")]
+    InsufficientPermissions {
+        /// The refused operation, e.g. "prune"
+        operation: String,
+        /// Missing permissions, e.g. "delete"
+        missing: String,
+    },
+
     #[error("{0}")]
     Other(String),
+
+    /// Wraps another error with the operation and object it occurred on.
+    #[error("Failed to {operation} {object}: {inner}")]
+    Context {
+        /// What was being attempted, e.g. "load pack"
+        operation: String,
+        /// The object involved: a snapshot, pack or chunk ID, or a backend path
+        object: String,
+        inner: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Wraps this error with the operation and object it occurred on.
+    pub fn context(self, operation: impl Into<String>, object: impl Into<String>) -> Self {
+        Error::Context {
+            operation: operation.into(),
+            object: object.into(),
+            inner: Box::new(self),
+        }
+    }
+
+    /// Returns the innermost error, skipping any context layers.
+    pub fn root_cause(&self) -> &Error {
+        match self {
+            Error::Context { inner, .. } => inner.root_cause(),
+            other => other,
+        }
+    }
+
+    /// HTTP status of a failed backend request, if known.
+    pub fn http_status(&self) -> Option<u16> {
+        match self.root_cause() {
+            Error::BackendStatus { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Delay the backend asked for before the request is retried.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self.root_cause() {
+            Error::BackendStatus { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Whether the backend rejected the request because it is being sent
+    /// too many (429, or 503 as S3 "SlowDown" and Azure "ServerBusy").
+    pub fn is_throttling(&self) -> bool {
+        match self.root_cause() {
+            Error::BackendStatus { status, .. } => matches!(status, 429 | 503),
+            Error::Backend(msg) => {
+                msg.contains("SlowDown")
+                    || msg.contains("ServerBusy")
+                    || msg.contains("throttl")
+                    || msg.contains("rate limit")
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the backend refused the request for lack of permission (HTTP
+    /// 401 or 403, or a local permission error).
+    pub fn is_permission_denied(&self) -> bool {
+        match self.root_cause() {
+            Error::Io(e) => e.kind() == std::io::ErrorKind::PermissionDenied,
+            Error::BackendStatus { status, .. } => matches!(status, 401 | 403),
+            Error::Backend(msg) => {
+                msg.contains("AccessDenied")
+                    || msg.contains("AuthorizationPermissionMismatch")
+                    || msg.contains("Forbidden")
+                    || msg.contains("Permission denied")
+                    || msg.contains("permission denied")
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parses a `Retry-After` header value: either delay seconds or an HTTP
+/// date. A date in the past yields a zero delay.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (at.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Adds operation context to fallible results.
+pub trait ErrorContext<T> {
+    /// Wraps the error, if any, with the operation and object it occurred on.
+    fn op_context(self, operation: &str, object: impl std::fmt::Display) -> Result<T>;
+}
+
+impl<T> ErrorContext<T> for Result<T> {
+    fn op_context(self, operation: &str, object: impl std::fmt::Display) -> Result<T> {
+        self.map_err(|e| e.context(operation, object.to_string()))
+    }
 }
 
 impl From<russh::Error> for Error {
@@ -49,3 +213,76 @@ impl From<russh::Error> for Error {
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+/// Generates a short random ID identifying a single run in logs and error
+/// output, so reports can be correlated with log lines.
+pub fn new_operation_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()[..12].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_context() {
+        let err: Result<()> = Err(Error::ChunkNotFound {
+            id: "abc".to_string(),
+        });
+        let err = err
+            .op_context("load chunk", "abc")
+            .op_context("restore", "snapshot 1234")
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Failed to restore snapshot 1234: Failed to load chunk abc: Chunk not found: abc"
+        );
+        assert!(matches!(err.root_cause(), Error::ChunkNotFound { .. }));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+
+        let err = Error::BackendStatus {
+            status: 503,
+            message: "Failed to write data/ab".to_string(),
+            retry_after: Some(Duration::from_secs(2)),
+        }
+        .context("save", "pack ab");
+        assert_eq!(err.http_status(), Some(503));
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(2)));
+        assert!(err.is_throttling());
+        assert!(!Error::Backend("Access Denied".to_string()).is_throttling());
+    }
+
+    #[test]
+    fn test_permission_denied() {
+        let forbidden = Error::BackendStatus {
+            status: 403,
+            message: "Failed to delete data/ab.pack".to_string(),
+            retry_after: None,
+        }
+        .context("delete", "pack ab");
+        assert!(forbidden.is_permission_denied());
+        assert!(Error::Io(std::io::ErrorKind::PermissionDenied.into()).is_permission_denied());
+        assert!(!Error::Io(std::io::ErrorKind::NotFound.into()).is_permission_denied());
+        assert!(
+            Error::Backend("Failed to write: AuthorizationPermissionMismatch".to_string())
+                .is_permission_denied()
+        );
+    }
+
+    #[test]
+    fn test_operation_id() {
+        let a = new_operation_id();
+        assert_eq!(a.len(), 12);
+        assert_ne!(a, new_operation_id());
+    }
+}
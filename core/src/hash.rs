@@ -0,0 +1,162 @@
+//! Hash algorithms behind chunk IDs.
+//!
+//! Chunks, and trees stored like chunks, are addressed by a 32-byte hash of
+//! their plaintext. Each repository records in its config which algorithm
+//! computes it, so that one client reads repositories that use different
+//! ones, and copies snapshots between them:
+//!
+//! - `blake3`, the default and the only algorithm of older repositories
+//! - `keyed-blake3`, BLAKE3 keyed with a key derived from the data key, so
+//!   that chunk IDs don't reveal whether a repository holds known contents
+//! - `sha256`, for environments that only allow FIPS-approved algorithms
+
+use crate::types::ChunkID;
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+/// Algorithm computing the chunk IDs of a repository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashAlgorithm {
+    #[default]
+    Blake3,
+    KeyedBlake3,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::KeyedBlake3 => "keyed-blake3",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == HashAlgorithm::default()
+    }
+
+    /// Whether the algorithm needs a key, and so an encrypted repository.
+    pub fn is_keyed(&self) -> bool {
+        *self == HashAlgorithm::KeyedBlake3
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            "keyed-blake3" => Ok(HashAlgorithm::KeyedBlake3),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            _ => Err(Error::Other(format!(
+                "Unknown hash algorithm '{}' (expected blake3, keyed-blake3 or sha256)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Computes chunk IDs with a repository's algorithm and key.
+#[derive(Clone, Default)]
+pub struct ChunkHasher {
+    algorithm: HashAlgorithm,
+    /// Zero unless the algorithm is keyed
+    key: [u8; 32],
+}
+
+impl ChunkHasher {
+    /// Fails for a keyed algorithm without a key.
+    pub fn new(algorithm: HashAlgorithm, key: Option<&[u8; 32]>) -> Result<Self> {
+        let key = match (algorithm.is_keyed(), key) {
+            (true, Some(key)) => *key,
+            (true, None) => {
+                return Err(Error::Other(format!(
+                    "The {} hash needs an encrypted repository",
+                    algorithm
+                )));
+            }
+            (false, _) => [0; 32],
+        };
+        Ok(Self { algorithm, key })
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn hash(&self, data: &[u8]) -> ChunkID {
+        match self.algorithm {
+            HashAlgorithm::Blake3 => ChunkID::from(blake3::hash(data)),
+            HashAlgorithm::KeyedBlake3 => ChunkID::from(blake3::keyed_hash(&self.key, data)),
+            HashAlgorithm::Sha256 => ChunkID::from(<[u8; 32]>::from(Sha256::digest(data))),
+        }
+    }
+
+    /// Whether both hashers give the same data the same ID, so that chunk
+    /// IDs carry over between their repositories.
+    pub fn is_compatible(&self, other: &ChunkHasher) -> bool {
+        self.algorithm == other.algorithm && self.key == other.key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_algorithms() {
+        let blake3 = ChunkHasher::default();
+        assert_eq!(blake3.algorithm(), HashAlgorithm::Blake3);
+        assert_eq!(blake3.hash(b"data"), ChunkID::from_data(b"data"));
+
+        let sha256 = ChunkHasher::new(HashAlgorithm::Sha256, None).unwrap();
+        assert_eq!(
+            sha256.hash(b"abc").to_hex(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        assert!(ChunkHasher::new(HashAlgorithm::KeyedBlake3, None).is_err());
+        let keyed = ChunkHasher::new(HashAlgorithm::KeyedBlake3, Some(&[1; 32])).unwrap();
+        let other_key = ChunkHasher::new(HashAlgorithm::KeyedBlake3, Some(&[2; 32])).unwrap();
+        assert_ne!(keyed.hash(b"data"), blake3.hash(b"data"));
+        assert_ne!(keyed.hash(b"data"), other_key.hash(b"data"));
+
+        assert!(keyed.is_compatible(&keyed.clone()));
+        assert!(!keyed.is_compatible(&other_key));
+        assert!(!blake3.is_compatible(&sha256));
+        // A key given for an unkeyed algorithm is ignored
+        let blake3_with_key = ChunkHasher::new(HashAlgorithm::Blake3, Some(&[1; 32])).unwrap();
+        assert!(blake3.is_compatible(&blake3_with_key));
+    }
+
+    #[test]
+    fn test_parse() {
+        for algorithm in [
+            HashAlgorithm::Blake3,
+            HashAlgorithm::KeyedBlake3,
+            HashAlgorithm::Sha256,
+        ] {
+            assert_eq!(
+                algorithm.as_str().parse::<HashAlgorithm>().unwrap(),
+                algorithm
+            );
+            assert_eq!(
+                serde_json::to_string(&algorithm).unwrap(),
+                format!("\"{}\"", algorithm)
+            );
+        }
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+    }
+}
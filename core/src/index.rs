@@ -1,12 +1,14 @@
 use crate::crypto::Encryptor;
+use crate::packed_index::PackedIndex;
 use crate::{ChunkID, ChunkMetadata, Error, PackID, Result};
 use bloomfilter::Bloom;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use tokio::fs;
 
-/// Current index format version for schema evolution
+/// Last postcard index format version; newer indexes use the packed layout
+/// ([`crate::packed_index::PACKED_INDEX_VERSION`]).
 const INDEX_VERSION: u32 = 2;
 
 /// Bloom filter parameters - tuned for 1M chunks with 0.1% false positive rate
@@ -14,7 +16,7 @@ const BLOOM_ITEMS_COUNT: usize = 1_000_000;
 const BLOOM_FP_RATE: f64 = 0.001;
 
 /// Location of a chunk within a pack file.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ChunkLocation {
     pub pack_id: PackID,
     pub offset: u64,
@@ -37,7 +39,8 @@ struct IndexHeader {
     pack_count: u64,
 }
 
-/// Serializable index data (without bloom filter).
+/// Postcard index data (format version 2 and earlier), still read so older
+/// repositories open; it is rewritten in the packed layout on the next save.
 #[derive(Debug, Serialize, Deserialize)]
 struct IndexData {
     header: IndexHeader,
@@ -49,16 +52,22 @@ struct IndexData {
 ///
 /// The index maintains:
 /// - A bloom filter for O(1) chunk existence checks
-/// - A HashMap mapping chunk IDs to their pack locations
+/// - The packed, sorted chunk records loaded from disk (see [`PackedIndex`])
+/// - A HashMap of chunks added or moved since the index was loaded
 /// - Pack file metadata for statistics
 ///
-/// The bloom filter eliminates most disk reads for chunks that don't exist,
+/// The bloom filter eliminates most lookups for chunks that don't exist,
 /// which is critical for deduplication during backup.
 pub struct Index {
     /// Bloom filter for fast negative lookups
     bloom: Bloom<ChunkID>,
-    /// Chunk ID to location mapping
+    /// Immutable chunk records loaded from disk
+    base: Option<PackedIndex>,
+    /// Chunks added since the base was loaded, including new locations for
+    /// chunks that are also in the base
     chunks: HashMap<ChunkID, ChunkLocation>,
+    /// Base records that were removed or replaced by an entry in `chunks`
+    shadowed: HashSet<ChunkID>,
     /// Pack metadata
     packs: HashMap<PackID, PackInfo>,
     /// Track if index has unsaved changes
@@ -76,7 +85,9 @@ impl Index {
     pub fn new() -> Self {
         Self {
             bloom: Bloom::new_for_fp_rate(BLOOM_ITEMS_COUNT, BLOOM_FP_RATE),
+            base: None,
             chunks: HashMap::new(),
+            shadowed: HashSet::new(),
             packs: HashMap::new(),
             dirty: false,
         }
@@ -87,15 +98,42 @@ impl Index {
         let bloom_size = chunk_capacity.max(BLOOM_ITEMS_COUNT);
         Self {
             bloom: Bloom::new_for_fp_rate(bloom_size, BLOOM_FP_RATE),
+            base: None,
             chunks: HashMap::with_capacity(chunk_capacity),
+            shadowed: HashSet::new(),
             packs: HashMap::new(),
             dirty: false,
         }
     }
 
+    /// Creates an index backed by packed records.
+    pub fn from_packed(base: PackedIndex) -> Self {
+        let bloom_size = (base.len() * 2).max(BLOOM_ITEMS_COUNT);
+        let mut bloom = Bloom::new_for_fp_rate(bloom_size, BLOOM_FP_RATE);
+        for id in base.chunk_ids() {
+            bloom.set(&id);
+        }
+
+        Self {
+            bloom,
+            packs: base.packs().clone(),
+            base: Some(base),
+            chunks: HashMap::new(),
+            shadowed: HashSet::new(),
+            dirty: false,
+        }
+    }
+
+    fn base_contains(&self, id: &ChunkID) -> bool {
+        self.base.as_ref().is_some_and(|base| base.contains(id))
+    }
+
     /// Adds a chunk to the index.
     pub fn add_chunk(&mut self, chunk_id: ChunkID, location: ChunkLocation) {
         self.bloom.set(&chunk_id);
+        if self.base_contains(&chunk_id) {
+            self.shadowed.insert(chunk_id);
+        }
         self.chunks.insert(chunk_id, location);
         self.dirty = true;
     }
@@ -128,16 +166,22 @@ impl Index {
         if !self.bloom.check(id) {
             return false;
         }
-        // Bloom says maybe -> check HashMap
-        self.chunks.contains_key(id)
+        // Bloom says maybe -> check the overlay, then the packed records
+        self.chunks.contains_key(id) || (!self.shadowed.contains(id) && self.base_contains(id))
     }
 
     /// Gets chunk location if it exists.
-    pub fn get_chunk(&self, id: &ChunkID) -> Option<&ChunkLocation> {
+    pub fn get_chunk(&self, id: &ChunkID) -> Option<ChunkLocation> {
         if !self.bloom.check(id) {
             return None;
         }
-        self.chunks.get(id)
+        if let Some(location) = self.chunks.get(id) {
+            return Some(location.clone());
+        }
+        if self.shadowed.contains(id) {
+            return None;
+        }
+        self.base.as_ref().and_then(|base| base.get(id))
     }
 
     /// Gets pack information.
@@ -147,7 +191,8 @@ impl Index {
 
     /// Returns the number of chunks in the index.
     pub fn chunk_count(&self) -> usize {
-        self.chunks.len()
+        let base = self.base.as_ref().map_or(0, |base| base.len());
+        base - self.shadowed.len() + self.chunks.len()
     }
 
     /// Returns the number of packs in the index.
@@ -167,17 +212,34 @@ impl Index {
 
     /// Merges another index into this one.
     pub fn merge(&mut self, other: Index) {
-        for (id, loc) in other.chunks {
-            self.bloom.set(&id);
-            self.chunks.insert(id, loc);
+        for (id, loc) in other.iter_chunks() {
+            self.add_chunk(id, loc);
         }
         self.packs.extend(other.packs);
         self.dirty = true;
     }
 
     /// Iterates over all chunks.
-    pub fn iter_chunks(&self) -> impl Iterator<Item = (&ChunkID, &ChunkLocation)> {
-        self.chunks.iter()
+    pub fn iter_chunks(&self) -> impl Iterator<Item = (ChunkID, ChunkLocation)> + '_ {
+        let base = self
+            .base
+            .iter()
+            .flat_map(|base| base.iter())
+            .filter(|(id, _)| !self.shadowed.contains(id));
+        self.chunks
+            .iter()
+            .map(|(id, loc)| (*id, loc.clone()))
+            .chain(base)
+    }
+
+    /// Iterates over all chunk IDs without cloning their locations.
+    pub fn iter_chunk_ids(&self) -> impl Iterator<Item = ChunkID> + '_ {
+        let base = self
+            .base
+            .iter()
+            .flat_map(|base| base.chunk_ids())
+            .filter(|id| !self.shadowed.contains(id));
+        self.chunks.keys().copied().chain(base)
     }
 
     /// Iterates over all packs.
@@ -190,7 +252,13 @@ impl Index {
         // Note: Can't remove from bloom filter, but that's okay -
         // it just means slightly more false positives after pruning.
         self.dirty = true;
-        self.chunks.remove(id)
+        if let Some(location) = self.chunks.remove(id) {
+            return Some(location);
+        }
+        if self.base_contains(id) && self.shadowed.insert(*id) {
+            return self.base.as_ref().and_then(|base| base.get(id));
+        }
+        None
     }
 
     /// Removes a pack from the index.
@@ -201,13 +269,17 @@ impl Index {
 
     /// Compacts the index by removing chunks not in the given set of used chunk IDs.
     /// Returns the number of chunks removed.
-    pub fn compact(&mut self, used_chunks: &std::collections::HashSet<ChunkID>) -> usize {
-        let original_count = self.chunks.len();
+    pub fn compact(&mut self, used_chunks: &HashSet<ChunkID>) -> usize {
+        let original_count = self.chunk_count();
 
         // Remove unused chunks
         self.chunks.retain(|id, _| used_chunks.contains(id));
+        if let Some(base) = &self.base {
+            self.shadowed
+                .extend(base.chunk_ids().filter(|id| !used_chunks.contains(id)));
+        }
 
-        let removed_count = original_count - self.chunks.len();
+        let removed_count = original_count - self.chunk_count();
 
         if removed_count > 0 {
             // Rebuild bloom filter with remaining chunks
@@ -220,16 +292,17 @@ impl Index {
 
     /// Rebuilds the bloom filter from the current chunk set.
     fn rebuild_bloom(&mut self) {
-        let bloom_size = (self.chunks.len() * 2).max(BLOOM_ITEMS_COUNT);
-        self.bloom = Bloom::new_for_fp_rate(bloom_size, BLOOM_FP_RATE);
-        for id in self.chunks.keys() {
-            self.bloom.set(id);
+        let bloom_size = (self.chunk_count() * 2).max(BLOOM_ITEMS_COUNT);
+        let mut bloom = Bloom::new_for_fp_rate(bloom_size, BLOOM_FP_RATE);
+        for id in self.iter_chunk_ids() {
+            bloom.set(&id);
         }
+        self.bloom = bloom;
     }
 
     /// Returns all chunk IDs in the index.
-    pub fn all_chunk_ids(&self) -> std::collections::HashSet<ChunkID> {
-        self.chunks.keys().cloned().collect()
+    pub fn all_chunk_ids(&self) -> HashSet<ChunkID> {
+        self.iter_chunk_ids().collect()
     }
 
     /// Returns all pack IDs in the index.
@@ -239,10 +312,9 @@ impl Index {
 
     /// Returns chunks belonging to a specific pack.
     pub fn chunks_in_pack(&self, pack_id: &PackID) -> Vec<ChunkID> {
-        self.chunks
-            .iter()
+        self.iter_chunks()
             .filter(|(_, loc)| loc.pack_id == *pack_id)
-            .map(|(id, _)| *id)
+            .map(|(id, _)| id)
             .collect()
     }
 
@@ -263,24 +335,10 @@ impl Index {
         Ok(())
     }
 
+    /// Serializes the index in the packed layout and encrypts it.
     pub fn to_encrypted_bytes(&self, encryptor: &Encryptor) -> Result<Vec<u8>> {
-        let data = IndexData {
-            header: IndexHeader {
-                version: INDEX_VERSION,
-                chunk_count: self.chunks.len() as u64,
-                pack_count: self.packs.len() as u64,
-            },
-            chunks: self.chunks.clone(),
-            packs: self.packs.clone(),
-        };
-
-        // Serialize with postcard (compact binary format)
-        let serialized = postcard::to_allocvec(&data)
-            .map_err(|e| Error::Other(format!("Index serialization failed: {}", e)))?;
-
-        // Encrypt
-        let encrypted = encryptor.encrypt(&serialized)?;
-        Ok(encrypted)
+        let packed = PackedIndex::encode(self.iter_chunks(), &self.packs)?;
+        encryptor.encrypt(&packed)
     }
 
     /// Loads the index from an encrypted binary file.
@@ -289,9 +347,14 @@ impl Index {
         Self::from_encrypted_bytes(&encrypted, encryptor)
     }
 
+    /// Decrypts an index in the packed layout or the older postcard format.
     pub fn from_encrypted_bytes(encrypted: &[u8], encryptor: &Encryptor) -> Result<Self> {
         let serialized = encryptor.decrypt(encrypted)?;
 
+        if PackedIndex::is_packed(&serialized) {
+            return Ok(Self::from_packed(PackedIndex::from_vec(serialized)?));
+        }
+
         let data: IndexData = postcard::from_bytes(&serialized)
             .map_err(|e| Error::Other(format!("Index deserialization failed: {}", e)))?;
 
@@ -311,7 +374,9 @@ impl Index {
 
         Ok(Self {
             bloom,
+            base: None,
             chunks: data.chunks,
+            shadowed: HashSet::new(),
             packs: data.packs,
             dirty: false,
         })
@@ -409,7 +474,7 @@ impl ShardedIndex {
         let mut sharded = Self::new();
 
         // Distribute chunks to shards
-        for (chunk_id, location) in index.chunks {
+        for (chunk_id, location) in index.iter_chunks() {
             let shard_idx = chunk_id.as_bytes()[0] as usize;
             sharded.shards[shard_idx].add_chunk(chunk_id, location);
         }
@@ -451,7 +516,7 @@ impl ShardedIndex {
     }
 
     /// Gets chunk location if it exists.
-    pub fn get_chunk(&self, id: &ChunkID) -> Option<&ChunkLocation> {
+    pub fn get_chunk(&self, id: &ChunkID) -> Option<ChunkLocation> {
         let shard_idx = Self::shard_index(id);
         self.shards[shard_idx].get_chunk(id)
     }
@@ -656,7 +721,7 @@ mod tests {
         let mut index = Index::new();
         let chunk_id = ChunkID::from_data(b"test data");
         let location = ChunkLocation {
-            pack_id: "pack-123".to_string(),
+            pack_id: PackID::generate(),
             offset: 0,
             length: 100,
         };
@@ -673,6 +738,7 @@ mod tests {
     fn test_bloom_filter_no_false_negatives() {
         let mut index = Index::new();
         let mut chunk_ids = Vec::new();
+        let pack_id = PackID::generate();
 
         // Add 1000 chunks
         for i in 0..1000 {
@@ -681,7 +747,7 @@ mod tests {
             index.add_chunk(
                 chunk_id,
                 ChunkLocation {
-                    pack_id: "pack".to_string(),
+                    pack_id: pack_id.clone(),
                     offset: i as u64,
                     length: 100,
                 },
@@ -705,7 +771,7 @@ mod tests {
         index1.add_chunk(
             chunk1,
             ChunkLocation {
-                pack_id: "pack1".to_string(),
+                pack_id: PackID::generate(),
                 offset: 0,
                 length: 100,
             },
@@ -714,7 +780,7 @@ mod tests {
         index2.add_chunk(
             chunk2,
             ChunkLocation {
-                pack_id: "pack2".to_string(),
+                pack_id: PackID::generate(),
                 offset: 0,
                 length: 200,
             },
@@ -726,4 +792,46 @@ mod tests {
         assert!(index1.has_chunk(&chunk2));
         assert_eq!(index1.chunk_count(), 2);
     }
+
+    #[test]
+    fn test_packed_base_overlay() {
+        let encryptor = Encryptor::new(&[7u8; 32]).unwrap();
+        let (pack1, pack2) = (PackID::generate(), PackID::generate());
+        let location = |pack: &PackID, offset: u64| ChunkLocation {
+            pack_id: pack.clone(),
+            offset,
+            length: 10,
+        };
+
+        let mut index = Index::new();
+        let ids: Vec<_> = (0..4)
+            .map(|i| ChunkID::from_data(format!("chunk-{}", i).as_bytes()))
+            .collect();
+        for (i, id) in ids.iter().enumerate() {
+            index.add_chunk(*id, location(&pack1, i as u64 * 10));
+        }
+        let bytes = index.to_encrypted_bytes(&encryptor).unwrap();
+        let mut index = Index::from_encrypted_bytes(&bytes, &encryptor).unwrap();
+        assert_eq!(index.chunk_count(), 4);
+        assert_eq!(index.get_chunk(&ids[2]), Some(location(&pack1, 20)));
+
+        // Moving, removing and adding chunks goes through the overlay
+        index.add_chunk(ids[0], location(&pack2, 0));
+        index.remove_chunk(&ids[1]);
+        let extra = ChunkID::from_data(b"extra");
+        index.add_chunk(extra, location(&pack2, 10));
+        assert_eq!(index.chunk_count(), 4);
+        assert_eq!(index.get_chunk(&ids[0]), Some(location(&pack2, 0)));
+        assert!(!index.has_chunk(&ids[1]));
+
+        let used: HashSet<_> = [ids[0], ids[3], extra].into_iter().collect();
+        assert_eq!(index.compact(&used), 1);
+        assert_eq!(index.all_chunk_ids(), used);
+
+        let bytes = index.to_encrypted_bytes(&encryptor).unwrap();
+        let reloaded = Index::from_encrypted_bytes(&bytes, &encryptor).unwrap();
+        assert_eq!(reloaded.chunk_count(), 3);
+        assert_eq!(reloaded.get_chunk(&ids[0]), Some(location(&pack2, 0)));
+        assert_eq!(reloaded.get_chunk(&extra), Some(location(&pack2, 10)));
+    }
 }
@@ -0,0 +1,166 @@
+//! Names of the objects ghostsnap stores in a repository.
+//!
+//! Every repository object lives under one of the typed prefixes below, or is
+//! one of the few root objects (`config`, `marker`, `policy`, `settings`).
+//! Buckets and shares are often used for more than one thing, so listings
+//! must not assume that everything they return was written by ghostsnap:
+//! operations that list a prefix only act on names that [`classify`]
+//! recognizes, and maintenance commands report the rest as foreign objects
+//! without touching them.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Prefixes holding repository objects.
+pub const REPOSITORY_PREFIXES: &[&str] = &["data", "index", "keys", "locks", "snapshots"];
+
+/// Objects stored at the repository root.
+const ROOT_OBJECTS: &[&str] = &["config", "marker", "policy", "settings"];
+
+/// Path of the [`RepositoryMarker`].
+pub const MARKER_PATH: &str = "marker";
+
+/// Plaintext object written first by `init`. It identifies the location as a
+/// ghostsnap repository to anyone browsing the storage, and writing and
+/// reading it back proves that the backend works before keys and config are
+/// stored. Repositories created before it existed have no marker.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepositoryMarker {
+    /// Always `ghostsnap`
+    pub format: String,
+    pub version: u32,
+    /// Repository ID, as in the config
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl RepositoryMarker {
+    pub fn new(id: &str) -> Self {
+        Self {
+            format: "ghostsnap".to_string(),
+            version: 1,
+            id: id.to_string(),
+            created_at: Utc::now(),
+        }
+    }
+}
+
+/// Kind of a recognized repository object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Config,
+    Marker,
+    Policy,
+    Settings,
+    Key,
+    Pack,
+    Tree,
+    Index,
+    Snapshot,
+    Lock,
+}
+
+/// Classifies the object at `path` (relative to the repository root).
+///
+/// Returns `None` for objects that ghostsnap did not write.
+pub fn classify(path: &str) -> Option<ObjectKind> {
+    let Some((prefix, name)) = path.split_once('/') else {
+        return match path {
+            "config" => Some(ObjectKind::Config),
+            MARKER_PATH => Some(ObjectKind::Marker),
+            "policy" => Some(ObjectKind::Policy),
+            "settings" => Some(ObjectKind::Settings),
+            _ => None,
+        };
+    };
+    match prefix {
+        "keys" if is_uuid(name) => Some(ObjectKind::Key),
+        "snapshots" if is_uuid(name) => Some(ObjectKind::Snapshot),
+        "data" => match name.strip_suffix(".pack") {
+            Some(pack_id) if is_uuid(pack_id) => Some(ObjectKind::Pack),
+            Some(_) => None,
+            None => is_object_hash(name).then_some(ObjectKind::Tree),
+        },
+        // Binary and cache files, reference counts, plus per-chunk files of
+        // the legacy layout
+        "index"
+            if name.ends_with(".idx")
+                || name.ends_with(".cache")
+                || name == "refcounts"
+                || is_object_hash(name) =>
+        {
+            Some(ObjectKind::Index)
+        }
+        "locks" if name.ends_with(".lock") => Some(ObjectKind::Lock),
+        _ => None,
+    }
+}
+
+/// Returns whether `name`, listed directly under `prefix` (empty for the
+/// repository root), is a repository object or one of the typed prefixes.
+pub fn is_repository_entry(prefix: &str, name: &str) -> bool {
+    if prefix.is_empty() {
+        return ROOT_OBJECTS.contains(&name) || REPOSITORY_PREFIXES.contains(&name);
+    }
+    classify(&format!("{}/{}", prefix, name)).is_some()
+}
+
+/// Snapshot, pack and key IDs are UUIDs.
+fn is_uuid(name: &str) -> bool {
+    Uuid::parse_str(name).is_ok_and(|uuid| uuid.hyphenated().to_string() == name)
+}
+
+/// Trees are named by the hex BLAKE3 hash of their contents.
+fn is_object_hash(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "3fa2c1d4-5b6e-4f70-8a9b-0c1d2e3f4a5b";
+
+    #[test]
+    fn test_classify_repository_objects() {
+        assert_eq!(classify("config"), Some(ObjectKind::Config));
+        assert_eq!(classify("marker"), Some(ObjectKind::Marker));
+        assert_eq!(classify("policy"), Some(ObjectKind::Policy));
+        assert_eq!(classify("settings"), Some(ObjectKind::Settings));
+        assert_eq!(classify(&format!("keys/{}", ID)), Some(ObjectKind::Key));
+        assert_eq!(
+            classify(&format!("snapshots/{}", ID)),
+            Some(ObjectKind::Snapshot)
+        );
+        assert_eq!(
+            classify(&format!("data/{}.pack", ID)),
+            Some(ObjectKind::Pack)
+        );
+        assert_eq!(
+            classify(&format!("data/{}", "ab".repeat(32))),
+            Some(ObjectKind::Tree)
+        );
+        assert_eq!(classify("index/main.idx"), Some(ObjectKind::Index));
+        assert_eq!(classify("index/snapshots.cache"), Some(ObjectKind::Index));
+        assert_eq!(classify("index/refcounts"), Some(ObjectKind::Index));
+        assert_eq!(classify("locks/repo.lock"), Some(ObjectKind::Lock));
+    }
+
+    #[test]
+    fn test_classify_foreign_objects() {
+        assert_eq!(classify("README.txt"), None);
+        assert_eq!(classify("snapshots/backup-2024.tar.gz"), None);
+        assert_eq!(classify(&format!("snapshots/{}", ID.to_uppercase())), None);
+        assert_eq!(classify("data/notes.pack"), None);
+        assert_eq!(classify(&format!("data/{}", "AB".repeat(32))), None);
+        assert_eq!(classify("keys/id_rsa"), None);
+        assert_eq!(classify("index/.DS_Store"), None);
+        assert_eq!(classify("photos/cat.jpg"), None);
+
+        assert!(is_repository_entry("", "data"));
+        assert!(is_repository_entry("", "config"));
+        assert!(!is_repository_entry("", "photos"));
+        assert!(!is_repository_entry("data", "Thumbs.db"));
+    }
+}
@@ -26,22 +26,79 @@
 //! }
 //! ```
 
+pub mod api_cache;
+pub mod bench;
+pub mod bundle;
+pub mod capability;
+pub mod check;
 pub mod chunker;
+pub mod cost;
 pub mod crypto;
+pub mod diff;
+pub mod dry_run;
 pub mod error;
+pub mod hash;
 pub mod index;
+pub mod layout;
 pub mod lock;
+pub mod manifest;
+pub mod metrics;
 pub mod pack;
+pub mod packed_index;
+pub mod policy;
+pub mod proxy;
+pub mod ratelimit;
+pub mod recovery;
+pub mod refcount;
+pub mod replication;
 pub mod repository;
+pub mod request_log;
+pub mod restic;
+pub mod scrub;
+pub mod settings;
 pub mod snapshot;
+pub mod snapshot_cache;
+pub mod snapshot_filter;
+pub mod source;
+pub mod stats;
 pub mod storage;
+pub mod target;
 pub mod types;
+pub mod validation;
 
-pub use error::{Error, Result};
+pub use bundle::{BundleImportStats, SnapshotBundle};
+pub use capability::{Access, Capabilities};
+pub use check::{CheckObserver, CheckOptions, CheckProblem, CheckReport, CheckStep};
+pub use crypto::{KeyProvider, PasswordKey};
+pub use diff::{Change, TreeDiff, diff_trees};
+pub use error::{Error, ErrorContext, Result, new_operation_id, parse_retry_after};
+pub use hash::{ChunkHasher, HashAlgorithm};
 pub use index::{ChunkLocation, Index, PackInfo, ShardStats, ShardedIndex, should_use_sharding};
 pub use lock::{LockInfo, LockManager, LockType, RepositoryLock};
+pub use manifest::{BackupManifest, ManifestEntry, ManifestKey};
 pub use pack::{PackFile, PackManager, RepackStats, Repacker};
-pub use repository::{CacheStats, CloneStats, CompactStats, RepoStats, Repository, VerifyStats};
+pub use policy::{PolicyScope, RetentionPolicy, RetentionRules};
+pub use proxy::ProxyConfig;
+pub use ratelimit::{BandwidthSchedule, BandwidthWindow, RateLimiter};
+pub use recovery::{KeyExport, RecoveryCode};
+pub use refcount::RefCounts;
+pub use replication::{ReplicaObjectStatus, ReplicationReport, check_replica};
+pub use repository::{
+    CacheStats, CloneStats, CompactStats, ForgetForecast, IndexCrossCheck, IndexMismatch, KeyInfo,
+    RehydrationReport, RepoStats, Repository, SnapshotChainStats, SnapshotCopyStats, TreePages,
+    VerifyStats,
+};
+pub use restic::{ResticRepository, ResticSnapshot};
+pub use scrub::{ScrubFailure, ScrubReport, ScrubState};
+pub use settings::RepoSettings;
 pub use snapshot::Snapshot;
-pub use storage::{AzureLocation, RcloneLocation, RepositoryLocation, S3Location, SftpLocation};
+pub use snapshot_cache::{SnapshotCache, SnapshotSummary};
+pub use snapshot_filter::SnapshotFilter;
+pub use source::{BackupSource, FsSource, SourceObserver, SourceWriter, StoredContents};
+pub use stats::{HostAttribution, HostStats, SnapshotStatsEntry, StatsCache};
+pub use storage::{
+    AzureLocation, RcloneLocation, RepositoryLocation, S3Location, SftpLocation, TierStatus,
+};
+pub use target::{RestoreTarget, RestoreTotals, StorageTarget, TarTarget};
 pub use types::*;
+pub use validation::{FieldError, Validator};
@@ -1,14 +1,37 @@
+//! Repository locks.
+//!
+//! Hosts sharing a repository rarely agree on the time to the second, and
+//! sometimes not even to the hour, so a lock's age is never judged by its
+//! timestamps alone. The holder rewrites its lock every [`REFRESH_INTERVAL`]
+//! with a higher refresh count; a lock whose count has not moved for the
+//! stale timeout, timed by the observer's own monotonic clock, is stale no
+//! matter what either clock says. Timestamps are only trusted with a
+//! generous allowance for skew.
+
+use crate::repository::{CLOCK_SKEW_TOLERANCE, clock_offset};
 use crate::{Error, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tokio::fs;
+use tokio::task::JoinHandle;
 
-/// Lock file name
-const LOCK_FILE: &str = "repo.lock";
+/// How often a held lock is refreshed (5 minutes)
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
 
-/// Stale lock timeout in seconds (15 minutes)
-const STALE_TIMEOUT_SECS: i64 = 15 * 60;
+/// How long a lock may go without a refresh before it is stale (15 minutes,
+/// three missed refreshes)
+const STALE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Allowance for the holder's clock differing from ours when a lock's
+/// timestamps are all there is to go by (1 hour)
+const LOCK_SKEW_TOLERANCE: Duration = Duration::from_secs(60 * 60);
+
+/// How often a waiting [`LockManager::acquire`] looks at the locks again
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
 
 /// Lock type for different operations
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -19,6 +42,15 @@ pub enum LockType {
     Shared,
 }
 
+impl LockType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LockType::Exclusive => "exclusive",
+            LockType::Shared => "shared",
+        }
+    }
+}
+
 /// Lock file content
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LockInfo {
@@ -27,6 +59,12 @@ pub struct LockInfo {
     pub pid: u32,
     pub created_at: DateTime<Utc>,
     pub operation: String,
+    /// When the holder last refreshed the lock, by its own clock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refreshed_at: Option<DateTime<Utc>>,
+    /// Number of refreshes so far; only advances while the holder is alive
+    #[serde(default)]
+    pub refresh_count: u64,
 }
 
 impl LockInfo {
@@ -39,9 +77,21 @@ impl LockInfo {
             pid: std::process::id(),
             created_at: Utc::now(),
             operation: operation.to_string(),
+            refreshed_at: None,
+            refresh_count: 0,
         }
     }
 
+    /// When the holder last wrote the lock, by the holder's clock
+    pub fn last_refreshed(&self) -> DateTime<Utc> {
+        self.refreshed_at.unwrap_or(self.created_at)
+    }
+
+    fn refresh(&mut self) {
+        self.refresh_count += 1;
+        self.refreshed_at = Some(Utc::now());
+    }
+
     /// Check if this lock is from the current process
     pub fn is_current_process(&self) -> bool {
         self.pid == std::process::id() && self.is_local_host()
@@ -56,10 +106,13 @@ impl LockInfo {
         }
     }
 
-    /// Check if the lock is stale (old and likely abandoned)
+    /// Check if the lock is stale judging by its timestamps alone: not
+    /// refreshed for longer than the stale timeout plus an allowance for the
+    /// holder's clock being behind ours
     pub fn is_stale(&self) -> bool {
-        let age = Utc::now().signed_duration_since(self.created_at);
-        age.num_seconds() > STALE_TIMEOUT_SECS
+        let age = Utc::now().signed_duration_since(self.last_refreshed());
+        age.to_std()
+            .is_ok_and(|age| age > STALE_TIMEOUT + LOCK_SKEW_TOLERANCE)
     }
 
     /// Check if the process holding the lock is still running
@@ -81,62 +134,159 @@ impl LockInfo {
     }
 }
 
-/// Repository lock manager
+/// Repository lock manager.
+///
+/// Every lock is its own file in `locks/`, so several shared locks (e.g.
+/// restores) can be held at once while an exclusive lock (e.g. prune) waits
+/// for all of them to go away.
 pub struct LockManager {
     locks_dir: PathBuf,
+    retry: Duration,
+    /// Refresh count of every lock seen, and when it was first seen at that
+    /// count by our monotonic clock
+    observed: Mutex<HashMap<PathBuf, (u64, Instant)>>,
 }
 
 impl LockManager {
     pub fn new<P: AsRef<Path>>(repo_path: P) -> Self {
         Self {
             locks_dir: repo_path.as_ref().join("locks"),
+            retry: Duration::ZERO,
+            observed: Mutex::new(HashMap::new()),
         }
     }
 
+    /// Keeps retrying a conflicting lock for up to `retry` before giving up.
+    /// A lock left behind by a crashed host is removed once its refresh
+    /// count has stood still for the stale timeout, so waiting that long
+    /// gets past it whatever that host's clock said.
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = retry;
+        self
+    }
+
     /// Acquire a lock on the repository
     pub async fn acquire(&self, lock_type: LockType, operation: &str) -> Result<RepositoryLock> {
-        let lock_path = self.locks_dir.join(LOCK_FILE);
-
-        // Check for existing lock
-        if lock_path.exists() {
-            let existing = self.read_lock(&lock_path).await?;
-
-            // If it's our own lock, allow re-entry
-            if existing.is_current_process() {
-                return Ok(RepositoryLock {
-                    path: lock_path,
-                    owned: false, // Don't delete on drop - we're re-entering
-                });
+        let deadline = Instant::now() + self.retry;
+        loop {
+            match self.acquire_once(lock_type, operation).await {
+                Err(Error::LockConflict(msg)) if Instant::now() < deadline => {
+                    tracing::info!("{}; retrying", msg);
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    tokio::time::sleep(RETRY_INTERVAL.min(remaining)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn acquire_once(&self, lock_type: LockType, operation: &str) -> Result<RepositoryLock> {
+        // If this process already holds a lock, allow re-entry
+        let existing = self.list_locks().await?;
+        if let Some((path, _)) = existing.iter().find(|(_, info)| info.is_current_process()) {
+            return Ok(RepositoryLock {
+                path: path.clone(),
+                owned: false, // Don't delete on drop - we're re-entering
+                refresher: None,
+            });
+        }
+        self.check_conflicts(lock_type, existing).await?;
+
+        // Create new lock, then check again: another process may have
+        // checked at the same time and written its lock in between
+        let lock_path = self
+            .locks_dir
+            .join(format!("{}.lock", uuid::Uuid::new_v4()));
+        let lock_info = LockInfo::new(lock_type, operation);
+        let before = Utc::now();
+        self.write_lock(&lock_path, &lock_info).await?;
+        warn_clock_skew(&lock_path, before, Utc::now()).await;
+        let lock = RepositoryLock {
+            path: lock_path.clone(),
+            owned: true,
+            refresher: Some(self.spawn_refresher(lock_path.clone(), lock_info)),
+        };
+
+        let others = self
+            .list_locks()
+            .await?
+            .into_iter()
+            .filter(|(path, _)| *path != lock_path)
+            .collect();
+        self.check_conflicts(lock_type, others).await?;
+        Ok(lock)
+    }
+
+    /// Fails with [`Error::LockConflict`] if one of `locks` is incompatible
+    /// with a new lock of `lock_type`, removing stale locks along the way.
+    async fn check_conflicts(
+        &self,
+        lock_type: LockType,
+        locks: Vec<(PathBuf, LockInfo)>,
+    ) -> Result<()> {
+        for (path, existing) in locks {
+            if lock_type == LockType::Shared && existing.lock_type == LockType::Shared {
+                continue;
             }
 
             // Check if the lock is stale
-            if existing.is_stale() && !existing.is_process_alive() {
+            if self.is_stale(&path, &existing) && !existing.is_process_alive() {
                 tracing::warn!(
-                    "Removing stale lock from {} (PID {}, created {})",
+                    "Removing stale lock from {} (PID {}, created {}, {} refreshes)",
                     existing.hostname,
                     existing.pid,
-                    existing.created_at
+                    existing.created_at,
+                    existing.refresh_count
                 );
-                fs::remove_file(&lock_path).await.ok();
-            } else {
-                // Lock is held by another process
-                return Err(Error::LockConflict(format!(
-                    "Repository locked by {} (PID {}, operation: {}, since {})",
-                    existing.hostname,
-                    existing.pid,
-                    existing.operation,
-                    existing.created_at.format("%Y-%m-%d %H:%M:%S UTC")
-                )));
+                fs::remove_file(&path).await.ok();
+                continue;
             }
+
+            // Lock is held by another process
+            return Err(Error::LockConflict(format!(
+                "Repository locked by {} (PID {}, operation: {}, {} lock since {})",
+                existing.hostname,
+                existing.pid,
+                existing.operation,
+                existing.lock_type.as_str(),
+                existing.created_at.format("%Y-%m-%d %H:%M:%S UTC")
+            )));
         }
+        Ok(())
+    }
 
-        // Create new lock
-        let lock_info = LockInfo::new(lock_type, operation);
-        self.write_lock(&lock_path, &lock_info).await?;
+    /// Whether the lock at `path` is stale: its refresh count has not moved
+    /// for the stale timeout since this manager first saw it, or its
+    /// timestamps say so even allowing for clock skew.
+    fn is_stale(&self, path: &Path, info: &LockInfo) -> bool {
+        let now = Instant::now();
+        let mut observed = self.observed.lock().unwrap();
+        let (count, since) = observed
+            .entry(path.to_path_buf())
+            .or_insert((info.refresh_count, now));
+        if *count != info.refresh_count {
+            *count = info.refresh_count;
+            *since = now;
+        }
+        now.duration_since(*since) > STALE_TIMEOUT || info.is_stale()
+    }
 
-        Ok(RepositoryLock {
-            path: lock_path,
-            owned: true,
+    /// Rewrites a held lock every [`REFRESH_INTERVAL`] with a higher refresh
+    /// count, until the lock is released or removed.
+    fn spawn_refresher(&self, path: PathBuf, mut info: LockInfo) -> JoinHandle<()> {
+        let locks_dir = self.locks_dir.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(REFRESH_INTERVAL).await;
+                if !path.exists() {
+                    tracing::warn!("Lock {} was removed while held", path.display());
+                    return;
+                }
+                info.refresh();
+                if let Err(e) = write_lock_file(&locks_dir, &path, &info).await {
+                    tracing::warn!("Failed to refresh lock {}: {}", path.display(), e);
+                }
+            }
         })
     }
 
@@ -155,25 +305,45 @@ impl LockManager {
 
     /// Check if the repository is currently locked
     pub async fn is_locked(&self) -> Result<bool> {
-        let lock_path = self.locks_dir.join(LOCK_FILE);
-        Ok(lock_path.exists())
+        Ok(!self.list_locks().await?.is_empty())
     }
 
-    /// Get information about the current lock
+    /// Get information about the current lock, preferring an exclusive one
+    /// if several are held
     pub async fn get_lock_info(&self) -> Result<Option<LockInfo>> {
-        let lock_path = self.locks_dir.join(LOCK_FILE);
-        if lock_path.exists() {
-            Ok(Some(self.read_lock(&lock_path).await?))
-        } else {
-            Ok(None)
+        let mut locks = self.list_locks().await?;
+        locks.sort_by_key(|(_, info)| info.lock_type != LockType::Exclusive);
+        Ok(locks.into_iter().next().map(|(_, info)| info))
+    }
+
+    /// All locks currently held, oldest first. Files that aren't valid locks,
+    /// such as the capability probe, are skipped.
+    pub async fn list_locks(&self) -> Result<Vec<(PathBuf, LockInfo)>> {
+        let mut entries = match fs::read_dir(&self.locks_dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut locks = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "lock") {
+                continue;
+            }
+            match self.read_lock(&path).await {
+                Ok(info) => locks.push((path, info)),
+                Err(e) => tracing::debug!("Ignoring {}: {}", path.display(), e),
+            }
         }
+        locks.sort_by_key(|(_, info)| info.created_at);
+        Ok(locks)
     }
 
-    /// Force remove a lock (use with caution)
+    /// Force remove all locks (use with caution)
     pub async fn force_unlock(&self) -> Result<()> {
-        let lock_path = self.locks_dir.join(LOCK_FILE);
-        if lock_path.exists() {
-            fs::remove_file(&lock_path).await?;
+        for (path, _) in self.list_locks().await? {
+            fs::remove_file(&path).await?;
         }
         Ok(())
     }
@@ -185,27 +355,60 @@ impl LockManager {
     }
 
     async fn write_lock(&self, path: &Path, info: &LockInfo) -> Result<()> {
-        // Ensure locks directory exists
-        fs::create_dir_all(&self.locks_dir).await?;
-
-        // Write atomically via temp file
-        let temp_path = path.with_extension("lock.tmp");
-        let content = serde_json::to_string_pretty(info)?;
-        fs::write(&temp_path, &content).await?;
-        fs::rename(&temp_path, path).await?;
-        Ok(())
+        write_lock_file(&self.locks_dir, path, info).await
+    }
+}
+
+async fn write_lock_file(locks_dir: &Path, path: &Path, info: &LockInfo) -> Result<()> {
+    // Ensure locks directory exists
+    fs::create_dir_all(locks_dir).await?;
+
+    // Write atomically via temp file
+    let temp_path = path.with_extension("lock.tmp");
+    let content = serde_json::to_string_pretty(info)?;
+    fs::write(&temp_path, &content).await?;
+    fs::rename(&temp_path, path).await?;
+    Ok(())
+}
+
+/// Warns when the modification time the filesystem gave a lock written
+/// between `before` and `after` disagrees with this host's clock, as happens
+/// with a network filesystem whose server keeps different time.
+async fn warn_clock_skew(path: &Path, before: DateTime<Utc>, after: DateTime<Utc>) {
+    let Ok(modified) = fs::metadata(path).await.and_then(|m| m.modified()) else {
+        return;
+    };
+    let offset = clock_offset(modified.into(), before, after);
+    if offset.abs() > CLOCK_SKEW_TOLERANCE {
+        let direction = if offset > chrono::Duration::zero() {
+            "ahead of"
+        } else {
+            "behind"
+        };
+        tracing::warn!(
+            "The repository filesystem's clock is {}s {} this host's; check that NTP is running \
+             on both",
+            offset.num_seconds().abs(),
+            direction
+        );
     }
 }
 
 /// RAII lock handle that releases the lock on drop
+#[derive(Debug)]
 pub struct RepositoryLock {
     path: PathBuf,
     owned: bool,
+    /// Task refreshing the lock while it is held
+    refresher: Option<JoinHandle<()>>,
 }
 
 impl RepositoryLock {
     /// Explicitly release the lock
-    pub async fn release(self) -> Result<()> {
+    pub async fn release(mut self) -> Result<()> {
+        if let Some(refresher) = self.refresher.take() {
+            refresher.abort();
+        }
         if self.owned && self.path.exists() {
             fs::remove_file(&self.path).await?;
         }
@@ -217,6 +420,9 @@ impl RepositoryLock {
 
 impl Drop for RepositoryLock {
     fn drop(&mut self) {
+        if let Some(refresher) = self.refresher.take() {
+            refresher.abort();
+        }
         if self.owned && self.path.exists() {
             // Best-effort removal in drop (can't await)
             let _ = std::fs::remove_file(&self.path);
@@ -260,4 +466,109 @@ mod tests {
         // Second acquisition should succeed (same process)
         let _lock2 = manager.acquire(LockType::Exclusive, "test2").await.unwrap();
     }
+
+    #[test]
+    fn test_stale_timestamps() {
+        // Locks written before refreshes existed count from their creation
+        let mut info: LockInfo = serde_json::from_str(
+            r#"{"lock_type":"Exclusive","hostname":"other-host","pid":1,
+                "created_at":"2020-01-01T00:00:00Z","operation":"backup"}"#,
+        )
+        .unwrap();
+        assert_eq!(info.refresh_count, 0);
+        assert!(info.is_stale());
+
+        // A holder whose clock is behind by less than the allowance
+        info.created_at = Utc::now() - chrono::Duration::minutes(50);
+        assert!(!info.is_stale());
+
+        info.created_at = Utc::now() - chrono::Duration::hours(3);
+        assert!(info.is_stale());
+        info.refresh();
+        assert!(!info.is_stale());
+        assert_eq!(info.refresh_count, 1);
+    }
+
+    #[test]
+    fn test_stale_refresh_count() {
+        let dir = tempdir().unwrap();
+        let manager = LockManager::new(dir.path());
+        let path = manager.locks_dir.join("a.lock");
+
+        // From a host whose clock is a day ahead, the timestamps look fresh
+        let mut info = LockInfo::new(LockType::Exclusive, "backup");
+        info.created_at = Utc::now() + chrono::Duration::days(1);
+        assert!(!manager.is_stale(&path, &info));
+
+        // ...but a refresh count that stood still for the stale timeout does not
+        let Some(first_seen) = Instant::now().checked_sub(STALE_TIMEOUT + Duration::from_secs(1))
+        else {
+            return;
+        };
+        manager
+            .observed
+            .lock()
+            .unwrap()
+            .insert(path.clone(), (0, first_seen));
+        assert!(manager.is_stale(&path, &info));
+
+        info.refresh();
+        assert!(!manager.is_stale(&path, &info));
+    }
+
+    /// Writes a lock held by another, live process.
+    async fn foreign_lock(manager: &LockManager, name: &str, lock_type: LockType) {
+        let mut info = LockInfo::new(lock_type, "restore");
+        info.hostname = "other-host".to_string();
+        info.pid = 1;
+        manager
+            .write_lock(&manager.locks_dir.join(name), &info)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shared_locks() {
+        let dir = tempdir().unwrap();
+        let manager = LockManager::new(dir.path());
+        foreign_lock(&manager, "a.lock", LockType::Shared).await;
+        foreign_lock(&manager, "b.lock", LockType::Shared).await;
+        // The capability probe and temp files are not locks
+        std::fs::write(dir.path().join("locks/capability-probe.lock"), b"probe").unwrap();
+        std::fs::write(dir.path().join("locks/c.lock.tmp"), b"{}").unwrap();
+        assert_eq!(manager.list_locks().await.unwrap().len(), 2);
+
+        // Shared locks coexist, an exclusive lock waits for all of them
+        let lock = manager.acquire(LockType::Shared, "check").await.unwrap();
+        assert_eq!(manager.list_locks().await.unwrap().len(), 3);
+        lock.release().await.unwrap();
+
+        let err = manager
+            .acquire(LockType::Exclusive, "prune")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::LockConflict(_)));
+        assert!(err.to_string().contains("other-host"));
+        assert!(err.to_string().contains("restore"));
+        // The failed attempt leaves no lock behind
+        assert_eq!(manager.list_locks().await.unwrap().len(), 2);
+
+        // Retrying gives up once the retry time is over
+        let retrying = LockManager::new(dir.path()).with_retry(Duration::from_millis(50));
+        let started = Instant::now();
+        let err = retrying
+            .acquire(LockType::Exclusive, "prune")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::LockConflict(_)));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        manager.force_unlock().await.unwrap();
+        let lock = manager.acquire(LockType::Exclusive, "prune").await.unwrap();
+        let info = manager.get_lock_info().await.unwrap().unwrap();
+        assert_eq!(info.operation, "prune");
+        assert!(info.is_current_process());
+        drop(lock);
+        assert!(!manager.is_locked().await.unwrap());
+    }
 }
@@ -0,0 +1,294 @@
+//! Signed backup manifests for external cataloging.
+//!
+//! A [`BackupManifest`] lists what a snapshot contains — every path with its
+//! type, size and, for regular files, the BLAKE3 hash of its contents — as
+//! plain JSON. It is written outside the repository, next to a detached
+//! Ed25519 signature over the exact file bytes, so audit and catalog systems
+//! can index and trust backup contents holding only the public key, without
+//! repository credentials.
+//!
+//! The signature file (`<manifest>.sig`) holds the hex-encoded signature; it
+//! verifies with any Ed25519 implementation.
+
+use crate::error::{Error, Result};
+use crate::snapshot::{Snapshot, Tree};
+use crate::types::{NodeType, SnapshotID};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Manifest format version.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Extension appended to a manifest's file name for its signature.
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+/// Contents of a snapshot, for consumers outside the repository.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub version: u32,
+    pub snapshot_id: SnapshotID,
+    pub time: DateTime<Utc>,
+    pub hostname: String,
+    pub username: String,
+    pub paths: Vec<PathBuf>,
+    pub tags: Vec<String>,
+    /// Sum of the sizes of all regular files
+    pub total_size: u64,
+    pub files: Vec<ManifestEntry>,
+}
+
+/// A single path in a [`BackupManifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    #[serde(rename = "type")]
+    pub node_type: String,
+    pub size: u64,
+    pub mode: u32,
+    pub mtime: i64,
+    /// BLAKE3 hash of the file contents, hex encoded; regular files only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<String>,
+}
+
+impl BackupManifest {
+    /// Builds the manifest of `snapshot`. `content_hashes` holds the BLAKE3
+    /// hash of each regular file by path, as computed while reading it;
+    /// hardlinks take the hash of the file they point to.
+    pub fn new(
+        snapshot: &Snapshot,
+        tree: &Tree,
+        content_hashes: &HashMap<String, blake3::Hash>,
+    ) -> Self {
+        let files: Vec<ManifestEntry> = tree
+            .nodes
+            .iter()
+            .map(|node| {
+                let blake3 = (node.node_type == NodeType::File)
+                    .then(|| {
+                        let source = node.hardlink_target.as_deref().unwrap_or(&node.name);
+                        content_hashes.get(source).map(|h| h.to_hex().to_string())
+                    })
+                    .flatten();
+                ManifestEntry {
+                    path: node.name.clone(),
+                    node_type: node.node_type.as_str().to_string(),
+                    size: node.size,
+                    mode: node.mode,
+                    mtime: node.mtime,
+                    blake3,
+                    link_target: node.link_target.clone(),
+                }
+            })
+            .collect();
+        let total_size = tree
+            .nodes
+            .iter()
+            .filter(|n| n.node_type == NodeType::File)
+            .map(|n| n.size)
+            .sum();
+
+        Self {
+            version: MANIFEST_VERSION,
+            snapshot_id: snapshot.id.clone(),
+            time: snapshot.time,
+            hostname: snapshot.hostname.clone(),
+            username: snapshot.username.clone(),
+            paths: snapshot.paths.clone(),
+            tags: snapshot.tags.clone(),
+            total_size,
+            files,
+        }
+    }
+
+    /// File name the manifest is written under inside a directory.
+    pub fn file_name(&self) -> String {
+        format!("ghostsnap-{}.json", self.snapshot_id)
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Writes the manifest to `path` and, with a key, its signature to
+    /// `path.sig`. A directory `path` receives the manifest under
+    /// [`file_name`](Self::file_name). Returns the manifest's path.
+    pub fn write(&self, path: &Path, key: Option<&ManifestKey>) -> Result<PathBuf> {
+        let path = if path.is_dir() {
+            path.join(self.file_name())
+        } else {
+            path.to_path_buf()
+        };
+        let json = self.to_json()?;
+        std::fs::write(&path, &json)?;
+        if let Some(key) = key {
+            std::fs::write(signature_path(&path), format!("{}\n", key.sign(&json)))?;
+        }
+        Ok(path)
+    }
+}
+
+/// Path of the detached signature for the manifest at `path`.
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(SIGNATURE_EXTENSION);
+    path.with_file_name(name)
+}
+
+/// Ed25519 key pair signing manifests.
+pub struct ManifestKey {
+    signing_key: SigningKey,
+}
+
+impl ManifestKey {
+    pub fn generate() -> Self {
+        Self {
+            signing_key: SigningKey::generate(&mut rand::rngs::OsRng),
+        }
+    }
+
+    /// Parses a hex-encoded 32-byte secret key.
+    pub fn from_hex(secret: &str) -> Result<Self> {
+        let bytes: [u8; 32] = decode_hex_key(secret, "manifest signing key")?;
+        Ok(Self {
+            signing_key: SigningKey::from_bytes(&bytes),
+        })
+    }
+
+    /// Reads a secret key file written by [`save`](Self::save).
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_hex(&std::fs::read_to_string(path)?)
+    }
+
+    /// Writes the hex-encoded secret key to `path`, readable by the owner
+    /// only.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = format!("{}\n", hex::encode(self.signing_key.to_bytes()));
+
+        #[cfg(unix)]
+        {
+            use std::io::Write;
+            use std::os::unix::fs::OpenOptionsExt;
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(path)?
+                .write_all(contents.as_bytes())?;
+        }
+
+        #[cfg(not(unix))]
+        {
+            if path.exists() {
+                return Err(Error::Other(format!("{} already exists", path.display())));
+            }
+            std::fs::write(path, contents)?;
+        }
+
+        Ok(())
+    }
+
+    /// Hex-encoded public key, handed to whoever verifies manifests.
+    pub fn public_key_hex(&self) -> String {
+        hex::encode(self.signing_key.verifying_key().to_bytes())
+    }
+
+    /// Hex-encoded signature over `data`.
+    pub fn sign(&self, data: &[u8]) -> String {
+        hex::encode(self.signing_key.sign(data).to_bytes())
+    }
+}
+
+/// Checks a hex-encoded Ed25519 `signature` over the manifest bytes `data`
+/// against a hex-encoded `public_key`.
+pub fn verify_signature(data: &[u8], signature: &str, public_key: &str) -> Result<()> {
+    let key_bytes: [u8; 32] = decode_hex_key(public_key, "manifest public key")?;
+    let public_key = VerifyingKey::from_bytes(&key_bytes)
+        .map_err(|e| Error::Other(format!("Invalid manifest public key: {}", e)))?;
+    let signature_bytes: [u8; 64] = decode_hex_key(signature, "manifest signature")?;
+    public_key
+        .verify(data, &Signature::from_bytes(&signature_bytes))
+        .map_err(|_| Error::Other("Manifest signature does not match".to_string()))
+}
+
+fn decode_hex_key<const N: usize>(input: &str, what: &str) -> Result<[u8; N]> {
+    hex::decode(input.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            Error::Other(format!(
+                "Invalid {} (expected {} hex characters)",
+                what,
+                N * 2
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ChunkID, TreeNode};
+
+    fn node(name: &str, node_type: NodeType, size: u64) -> TreeNode {
+        TreeNode {
+            node_type,
+            size,
+            mtime: 1_700_000_000,
+            ..TreeNode::test_file(name, &[])
+        }
+    }
+
+    #[test]
+    fn test_manifest_hashes_and_signature() {
+        let mut link = node("docs/copy.txt", NodeType::File, 5);
+        link.hardlink_target = Some("docs/a.txt".to_string());
+        let tree = Tree {
+            nodes: vec![
+                node("docs", NodeType::Directory, 0),
+                node("docs/a.txt", NodeType::File, 5),
+                link,
+            ],
+            pages: Vec::new(),
+        };
+        let snapshot = Snapshot::new(vec![PathBuf::from("/srv")], ChunkID::from_data(b"tree"));
+        let hash = blake3::hash(b"hello");
+        let hashes = HashMap::from([("docs/a.txt".to_string(), hash)]);
+
+        let manifest = BackupManifest::new(&snapshot, &tree, &hashes);
+        assert_eq!(manifest.total_size, 10);
+        assert_eq!(manifest.files[0].blake3, None);
+        assert_eq!(manifest.files[1].blake3, Some(hash.to_hex().to_string()));
+        assert_eq!(manifest.files[2].blake3, manifest.files[1].blake3);
+
+        let key = ManifestKey::generate();
+        let json = manifest.to_json().unwrap();
+        let signature = key.sign(&json);
+        verify_signature(&json, &signature, &key.public_key_hex()).unwrap();
+
+        let mut tampered = json.clone();
+        tampered[10] ^= 1;
+        assert!(verify_signature(&tampered, &signature, &key.public_key_hex()).is_err());
+        let other = ManifestKey::generate();
+        assert!(verify_signature(&json, &signature, &other.public_key_hex()).is_err());
+
+        let restored =
+            ManifestKey::from_hex(&format!("{}\n", hex::encode(key.signing_key.to_bytes())))
+                .unwrap();
+        assert_eq!(restored.public_key_hex(), key.public_key_hex());
+        assert!(ManifestKey::from_hex("abcd").is_err());
+    }
+
+    #[test]
+    fn test_signature_path() {
+        assert_eq!(
+            signature_path(Path::new("/catalog/ghostsnap-abc.json")),
+            PathBuf::from("/catalog/ghostsnap-abc.json.sig")
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Process-wide backend metrics.
+//!
+//! Transient backend errors are retried in two places: the AWS SDK retries
+//! S3 requests internally, and `retry_with_backoff` in `ghostsnap-backends`
+//! retries whole operations. Both record every retry here, per operation
+//! (`s3_put`, `b2_read`, ...), so a run can report what flaky infrastructure
+//! cost it instead of the retries going unnoticed.
+
+use aws_sdk_s3::config::interceptors::{
+    BeforeTransmitInterceptorContextRef, FinalizerInterceptorContextRef,
+};
+use aws_sdk_s3::config::{ConfigBag, Intercept, RuntimeComponents};
+use aws_smithy_runtime_api::box_error::BoxError;
+use aws_smithy_runtime_api::client::retries::RequestAttempts;
+use aws_smithy_types::config_bag::{Storable, StoreReplace};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static RETRIES: Mutex<BTreeMap<String, OperationRetries>> = Mutex::new(BTreeMap::new());
+
+/// Retry counters of one kind of operation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OperationRetries {
+    /// Operations started
+    pub operations: u64,
+    /// Attempts repeated after a transient error
+    pub retries: u64,
+    /// Retries caused by the backend throttling requests
+    pub throttled: u64,
+    /// Operations that still failed with a transient error after retrying
+    pub failures: u64,
+    /// Time spent waiting between attempts
+    pub backoff: Duration,
+}
+
+impl OperationRetries {
+    /// Share of operations that failed, from 0.0 to 1.0.
+    pub fn failure_rate(&self) -> f64 {
+        if self.operations == 0 {
+            return 0.0;
+        }
+        self.failures as f64 / self.operations as f64
+    }
+
+    fn add(&mut self, other: &OperationRetries) {
+        self.operations += other.operations;
+        self.retries += other.retries;
+        self.throttled += other.throttled;
+        self.failures += other.failures;
+        self.backoff += other.backoff;
+    }
+}
+
+fn update(operation: &str, f: impl FnOnce(&mut OperationRetries)) {
+    let mut retries = RETRIES.lock().unwrap();
+    f(retries.entry(operation.to_string()).or_default());
+}
+
+/// Records the start of an operation.
+pub fn record_operation(operation: &str) {
+    update(operation, |r| r.operations += 1);
+}
+
+/// Records a retry of `operation` after waiting `backoff`.
+pub fn record_retry(operation: &str, backoff: Duration, throttled: bool) {
+    update(operation, |r| {
+        r.retries += 1;
+        r.backoff += backoff;
+        if throttled {
+            r.throttled += 1;
+        }
+    });
+}
+
+/// Records an operation that failed after its last attempt.
+pub fn record_failure(operation: &str) {
+    update(operation, |r| r.failures += 1);
+}
+
+/// Current retry counters of all operations.
+pub fn retry_metrics() -> RetryMetrics {
+    RetryMetrics {
+        operations: RETRIES.lock().unwrap().clone(),
+    }
+}
+
+/// Retry counters per operation, as returned by [`retry_metrics`].
+#[derive(Debug, Clone, Default)]
+pub struct RetryMetrics {
+    pub operations: BTreeMap<String, OperationRetries>,
+}
+
+impl RetryMetrics {
+    /// Counters per backend, keyed by the operation name prefix (`s3`, `b2`).
+    pub fn by_backend(&self) -> BTreeMap<&str, OperationRetries> {
+        let mut backends: BTreeMap<&str, OperationRetries> = BTreeMap::new();
+        for (operation, retries) in &self.operations {
+            let backend = operation.split('_').next().unwrap_or(operation);
+            backends.entry(backend).or_default().add(retries);
+        }
+        backends
+    }
+
+    /// One line per backend that needed retries, e.g. "12 transient S3
+    /// errors retried (3 throttled, 4.2s backoff)". Empty when nothing was
+    /// retried or failed.
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (backend, retries) in self.by_backend() {
+            let name = backend_display_name(backend);
+            if retries.retries > 0 {
+                let mut line = format!(
+                    "{} transient {} {} retried (",
+                    retries.retries,
+                    name,
+                    plural(retries.retries, "error", "errors")
+                );
+                if retries.throttled > 0 {
+                    line.push_str(&format!("{} throttled, ", retries.throttled));
+                }
+                line.push_str(&format!("{:.1}s backoff)", retries.backoff.as_secs_f64()));
+                lines.push(line);
+            }
+            if retries.failures > 0 {
+                lines.push(format!(
+                    "{} of {} {} {} failed after retrying",
+                    retries.failures,
+                    retries.operations,
+                    name,
+                    plural(retries.operations, "operation", "operations")
+                ));
+            }
+        }
+        lines
+    }
+}
+
+fn backend_display_name(backend: &str) -> &str {
+    match backend {
+        "s3" => "S3",
+        "b2" => "B2",
+        "azure" => "Azure",
+        "minio" => "MinIO",
+        "sftp" => "SFTP",
+        other => other,
+    }
+}
+
+fn plural<'a>(n: u64, one: &'a str, many: &'a str) -> &'a str {
+    if n == 1 { one } else { many }
+}
+
+/// Outcome of the previous HTTP attempt, kept in the request's config bag.
+#[derive(Debug, Clone, Copy)]
+struct PreviousAttempt {
+    ended: Instant,
+    throttled: bool,
+}
+
+impl Storable for PreviousAttempt {
+    type Storer = StoreReplace<Self>;
+}
+
+/// Operation name for an S3 request, e.g. `s3_put`.
+fn s3_operation(method: &str) -> String {
+    format!("s3_{}", method.to_ascii_lowercase())
+}
+
+fn is_transient_status(status: u16) -> bool {
+    status == 429 || status >= 500
+}
+
+/// S3 client interceptor recording the SDK's own retries.
+#[derive(Debug)]
+pub(crate) struct S3RetryMetrics;
+
+impl Intercept for S3RetryMetrics {
+    fn name(&self) -> &'static str {
+        "S3RetryMetrics"
+    }
+
+    fn read_before_attempt(
+        &self,
+        context: &BeforeTransmitInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> std::result::Result<(), BoxError> {
+        let operation = s3_operation(context.request().method());
+        match cfg.load::<PreviousAttempt>() {
+            Some(previous) => {
+                record_retry(&operation, previous.ended.elapsed(), previous.throttled)
+            }
+            None => record_operation(&operation),
+        }
+        Ok(())
+    }
+
+    fn read_after_attempt(
+        &self,
+        context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> std::result::Result<(), BoxError> {
+        let status = context.response().map(|r| r.status().as_u16());
+        cfg.interceptor_state().store_put(PreviousAttempt {
+            ended: Instant::now(),
+            throttled: matches!(status, Some(429 | 503)),
+        });
+        Ok(())
+    }
+
+    fn read_after_execution(
+        &self,
+        context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> std::result::Result<(), BoxError> {
+        let Some(request) = context.request() else {
+            return Ok(());
+        };
+        let retried = cfg
+            .load::<RequestAttempts>()
+            .is_some_and(|attempts| attempts.attempts() > 1);
+        let transient = context
+            .response()
+            .is_none_or(|r| is_transient_status(r.status().as_u16()));
+        if retried && transient {
+            record_failure(&s3_operation(request.method()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_per_backend() {
+        let mut metrics = RetryMetrics::default();
+        metrics.operations.insert(
+            "s3_put".to_string(),
+            OperationRetries {
+                operations: 100,
+                retries: 10,
+                throttled: 3,
+                failures: 1,
+                backoff: Duration::from_millis(4200),
+            },
+        );
+        metrics.operations.insert(
+            "s3_get".to_string(),
+            OperationRetries {
+                operations: 20,
+                retries: 2,
+                ..Default::default()
+            },
+        );
+        metrics.operations.insert(
+            "local_read".to_string(),
+            OperationRetries {
+                operations: 5,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            metrics.summary(),
+            vec![
+                "12 transient S3 errors retried (3 throttled, 4.2s backoff)".to_string(),
+                "1 of 120 S3 operations failed after retrying".to_string(),
+            ]
+        );
+        assert_eq!(metrics.operations["s3_put"].failure_rate(), 0.01);
+        assert!(RetryMetrics::default().summary().is_empty());
+    }
+
+    #[test]
+    fn test_record() {
+        record_operation("test_record_op");
+        record_operation("test_record_op");
+        record_retry("test_record_op", Duration::from_millis(5), true);
+        record_failure("test_record_op");
+
+        let metrics = retry_metrics();
+        let retries = &metrics.operations["test_record_op"];
+        assert_eq!(retries.operations, 2);
+        assert_eq!(retries.retries, 1);
+        assert_eq!(retries.throttled, 1);
+        assert_eq!(retries.failures, 1);
+        assert_eq!(retries.failure_rate(), 0.5);
+    }
+}
@@ -34,6 +34,10 @@ pub struct PackFile {
     pub header: PackHeader,
     pub chunks: HashMap<ChunkID, PackedChunk>,
     pub data: Vec<u8>,
+    /// zlib level for chunks added from now on; `None` for zlib's default.
+    /// Not stored, zlib streams decompress the same at any level.
+    #[serde(skip)]
+    pub compression_level: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,9 +62,15 @@ impl PackFile {
             },
             chunks: HashMap::new(),
             data: Vec::new(),
+            compression_level: None,
         }
     }
 
+    pub fn with_compression_level(mut self, level: u32) -> Self {
+        self.compression_level = Some(level);
+        self
+    }
+
     pub fn add_chunk(&mut self, id: ChunkID, data: &[u8]) -> Result<()> {
         // Compress the chunk data
         let compressed = self.compress_data(data)?;
@@ -140,9 +150,41 @@ impl PackFile {
         }
     }
 
+    /// Checks that the header and chunk table describe this pack: the header
+    /// names `pack_id`, the pack it was read as, its size matches the data
+    /// section and every chunk lies within the data.
+    pub fn check_header(&self, pack_id: &PackID) -> Result<()> {
+        if self.header.pack_id != *pack_id {
+            return Err(Error::Other(format!(
+                "Pack header names pack {}",
+                self.header.pack_id
+            )));
+        }
+        if self.header.compressed_size != self.data.len() as u64 {
+            return Err(Error::Other(format!(
+                "Pack header records {} bytes of data, found {}",
+                self.header.compressed_size,
+                self.data.len()
+            )));
+        }
+        if let Some(chunk) = self
+            .chunks
+            .values()
+            .find(|c| c.offset + c.length as u64 > self.data.len() as u64)
+        {
+            return Err(Error::Other(format!(
+                "Chunk {} extends beyond pack data",
+                chunk.id.short_string()
+            )));
+        }
+        Ok(())
+    }
+
     fn compress_data(&self, data: &[u8]) -> Result<Vec<u8>> {
-        let mut encoder =
-            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        let level = self
+            .compression_level
+            .map_or_else(flate2::Compression::default, flate2::Compression::new);
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), level);
         encoder
             .write_all(data)
             .map_err(|e| Error::Other(e.to_string()))?;
@@ -247,12 +289,13 @@ impl PackFile {
             header,
             chunks,
             data: decrypted_data,
+            compression_level: None,
         };
 
         // Verify checksum if present
         if !pack.verify_checksum()? {
             return Err(Error::CorruptedPack {
-                id: pack.header.pack_id.clone(),
+                id: pack.header.pack_id.to_string(),
             });
         }
 
@@ -264,7 +307,7 @@ impl PackFile {
 pub struct PackManager {
     current_pack: Option<PackFile>,
     max_pack_size: u64,
-    pack_counter: u64,
+    compression_level: Option<u32>,
 }
 
 impl PackManager {
@@ -272,10 +315,16 @@ impl PackManager {
         Self {
             current_pack: None,
             max_pack_size,
-            pack_counter: 0,
+            compression_level: None,
         }
     }
 
+    /// Compresses chunks at zlib `level` instead of the default.
+    pub fn with_compression_level(mut self, level: u32) -> Self {
+        self.compression_level = Some(level);
+        self
+    }
+
     pub fn add_chunk(&mut self, chunk_id: ChunkID, data: &[u8]) -> Result<Option<PackFile>> {
         // Check if we need a new pack
         if self.current_pack.is_none()
@@ -309,10 +358,11 @@ impl PackManager {
     }
 
     fn start_new_pack(&mut self) -> Result<()> {
-        // Use UUID for globally unique pack IDs to avoid collisions across backups
-        let pack_id = uuid::Uuid::new_v4().to_string();
-        self.pack_counter += 1;
-        self.current_pack = Some(PackFile::new(pack_id));
+        // Random UUIDs keep pack IDs unique across runs and hosts writing to
+        // the same repository
+        let mut pack = PackFile::new(PackID::generate());
+        pack.compression_level = self.compression_level;
+        self.current_pack = Some(pack);
         Ok(())
     }
 }
@@ -352,7 +402,7 @@ impl Repacker {
             return Ok(None);
         }
 
-        let mut new_pack = PackFile::new(uuid::Uuid::new_v4().to_string());
+        let mut new_pack = PackFile::new(PackID::generate());
 
         for chunk_id in chunk_ids {
             if let Some(chunk_entry) = source_pack.chunks.get(chunk_id) {
@@ -377,7 +427,7 @@ impl Repacker {
     }
 
     /// Identifies packs that are candidates for repacking (too small or too fragmented).
-    pub fn find_repack_candidates(&self, pack_infos: &[(String, u64)]) -> Vec<String> {
+    pub fn find_repack_candidates(&self, pack_infos: &[(PackID, u64)]) -> Vec<PackID> {
         pack_infos
             .iter()
             .filter(|(_, size)| *size < self.min_pack_size)
@@ -392,7 +442,7 @@ mod tests {
 
     #[test]
     fn test_pack_checksum() {
-        let mut pack = PackFile::new("test-pack".to_string());
+        let mut pack = PackFile::new(PackID::generate());
         pack.add_chunk(ChunkID::from_data(b"chunk1"), b"hello world")
             .unwrap();
         pack.add_chunk(ChunkID::from_data(b"chunk2"), b"goodbye world")
@@ -412,9 +462,60 @@ mod tests {
         assert!(!pack.verify_checksum().unwrap());
     }
 
+    #[test]
+    fn test_check_header() {
+        let pack_id = PackID::generate();
+        let mut pack = PackFile::new(pack_id.clone());
+        pack.add_chunk(ChunkID::from_data(b"chunk"), b"chunk")
+            .unwrap();
+        assert!(pack.check_header(&pack_id).is_ok());
+        assert!(pack.check_header(&PackID::generate()).is_err());
+
+        pack.data.pop();
+        assert!(pack.check_header(&pack_id).is_err());
+    }
+
+    #[test]
+    fn test_pack_manager_ids() {
+        let ids: Vec<PackID> = (0..2)
+            .map(|_| {
+                let mut manager = PackManager::new(1024);
+                manager.add_chunk(ChunkID::from_data(b"a"), b"a").unwrap();
+                manager.finish_current_pack().unwrap().header.pack_id
+            })
+            .collect();
+
+        // Managers in separate runs must not hand out the same ID
+        assert_ne!(ids[0], ids[1]);
+        for id in &ids {
+            assert_eq!(
+                crate::layout::classify(&format!("data/{}.pack", id)),
+                Some(crate::layout::ObjectKind::Pack)
+            );
+        }
+    }
+
+    #[test]
+    fn test_compression_level() {
+        let data = b"compressible ".repeat(1000);
+        let id = ChunkID::from_data(&data);
+        let sizes: Vec<u64> = [0, 9]
+            .into_iter()
+            .map(|level| {
+                let mut manager = PackManager::new(1024 * 1024).with_compression_level(level);
+                manager.add_chunk(id, &data).unwrap();
+                let pack = manager.finish_current_pack().unwrap();
+                assert_eq!(pack.get_chunk(&id).unwrap(), data);
+                pack.header.compressed_size
+            })
+            .collect();
+        assert!(sizes[0] > data.len() as u64);
+        assert!(sizes[1] < sizes[0] / 10);
+    }
+
     #[test]
     fn test_repacker_extract_chunks() {
-        let mut source = PackFile::new("source".to_string());
+        let mut source = PackFile::new(PackID::generate());
         let chunk1 = ChunkID::from_data(b"chunk1");
         let chunk2 = ChunkID::from_data(b"chunk2");
         let chunk3 = ChunkID::from_data(b"chunk3");
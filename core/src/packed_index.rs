@@ -0,0 +1,316 @@
+//! Compact binary layout for the master chunk index.
+//!
+//! The postcard-encoded index stores every chunk ID as a 64-character hex
+//! string and repeats the pack ID string in every entry, and it has to be
+//! decoded into a `HashMap` before the first lookup. At millions of chunks
+//! that dominates repository open time and memory.
+//!
+//! The packed layout instead stores fixed-size records sorted by chunk ID, so
+//! lookups are a binary search directly over the bytes and no per-chunk
+//! allocation happens when the index is opened.
+//!
+//! ```text
+//! offset  size  field
+//! 0       8     magic "GSNPIDX\0"
+//! 8       4     version (3)
+//! 12      4     pack count
+//! 16      8     chunk count
+//! 24      48×n  chunk records, sorted by chunk ID:
+//!                 chunk ID [32] | offset u64 | length u32 | pack ordinal u32
+//! ...           pack table, one entry per pack ordinal:
+//!                 size u64 | chunk count u32 | flags u8 | ID length u16 | ID
+//! ```
+//!
+//! All integers are little-endian. Flag bit 0 is set when the pack has
+//! [`PackInfo`] (size and chunk count are meaningful).
+
+use crate::index::{ChunkLocation, PackInfo};
+use crate::{ChunkID, Error, PackID, Result};
+use std::collections::HashMap;
+
+/// Identifies a packed index buffer.
+const MAGIC: &[u8; 8] = b"GSNPIDX\0";
+
+/// Packed layout version, continuing the postcard index versions.
+pub const PACKED_INDEX_VERSION: u32 = 3;
+
+const HEADER_LEN: usize = 24;
+const RECORD_LEN: usize = 48;
+const FLAG_HAS_INFO: u8 = 1;
+
+/// Read-only chunk index over the packed binary layout.
+pub struct PackedIndex {
+    data: Vec<u8>,
+    chunk_count: usize,
+    /// Pack ID for each pack ordinal
+    pack_ids: Vec<PackID>,
+    packs: HashMap<PackID, PackInfo>,
+}
+
+impl PackedIndex {
+    /// True if `data` starts with the packed index magic.
+    pub fn is_packed(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    /// Parses a packed index held in memory.
+    pub fn from_vec(data: Vec<u8>) -> Result<Self> {
+        if data.len() < HEADER_LEN || !Self::is_packed(&data) {
+            return Err(Error::Other("Not a packed index".to_string()));
+        }
+
+        let version = read_u32(&data, 8);
+        if version > PACKED_INDEX_VERSION {
+            return Err(Error::Other(format!(
+                "Index version {} is newer than supported version {}",
+                version, PACKED_INDEX_VERSION
+            )));
+        }
+
+        let pack_count = read_u32(&data, 12) as usize;
+        let chunk_count = usize::try_from(read_u64(&data, 16))
+            .map_err(|_| Error::Other("Packed index chunk count overflows".to_string()))?;
+        let mut pos = chunk_count
+            .checked_mul(RECORD_LEN)
+            .and_then(|len| len.checked_add(HEADER_LEN))
+            .filter(|&end| end <= data.len())
+            .ok_or_else(|| Error::Other("Packed index is truncated".to_string()))?;
+
+        let truncated = || Error::Other("Packed index pack table is truncated".to_string());
+        let mut pack_ids = Vec::with_capacity(pack_count);
+        let mut packs = HashMap::new();
+        for _ in 0..pack_count {
+            let entry = data.get(pos..pos + 15).ok_or_else(truncated)?;
+            let size = read_u64(entry, 0);
+            let chunks = read_u32(entry, 8);
+            let flags = entry[12];
+            let id_len = u16::from_le_bytes([entry[13], entry[14]]) as usize;
+            pos += 15;
+
+            let id_bytes = data.get(pos..pos + id_len).ok_or_else(truncated)?;
+            let id: PackID = std::str::from_utf8(id_bytes)
+                .map_err(|_| Error::Other("Packed index pack ID is not UTF-8".to_string()))?
+                .parse()?;
+            pos += id_len;
+
+            if flags & FLAG_HAS_INFO != 0 {
+                packs.insert(
+                    id.clone(),
+                    PackInfo {
+                        id: id.clone(),
+                        size,
+                        chunk_count: chunks,
+                    },
+                );
+            }
+            pack_ids.push(id);
+        }
+
+        let index = Self {
+            data,
+            chunk_count,
+            pack_ids,
+            packs,
+        };
+        if let Some(i) = (0..chunk_count).find(|&i| index.pack_ordinal(i) >= pack_count) {
+            return Err(Error::Other(format!(
+                "Packed index record {} references unknown pack",
+                i
+            )));
+        }
+        Ok(index)
+    }
+
+    /// Encodes chunk locations and pack information into the packed layout.
+    pub fn encode<I>(chunks: I, packs: &HashMap<PackID, PackInfo>) -> Result<Vec<u8>>
+    where
+        I: IntoIterator<Item = (ChunkID, ChunkLocation)>,
+    {
+        let mut records: Vec<(ChunkID, ChunkLocation)> = chunks.into_iter().collect();
+        records.sort_unstable_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+
+        // Ordinals for every pack referenced by a chunk or known by info
+        let mut pack_ids: Vec<&PackID> = packs.keys().collect();
+        for (_, location) in &records {
+            if !packs.contains_key(&location.pack_id) {
+                pack_ids.push(&location.pack_id);
+            }
+        }
+        pack_ids.sort_unstable();
+        pack_ids.dedup();
+        let ordinals: HashMap<&PackID, u32> = pack_ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (*id, i as u32))
+            .collect();
+
+        let mut out = Vec::with_capacity(HEADER_LEN + records.len() * RECORD_LEN);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&PACKED_INDEX_VERSION.to_le_bytes());
+        out.extend_from_slice(&(pack_ids.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(records.len() as u64).to_le_bytes());
+
+        for (id, location) in &records {
+            out.extend_from_slice(id.as_bytes());
+            out.extend_from_slice(&location.offset.to_le_bytes());
+            out.extend_from_slice(&location.length.to_le_bytes());
+            out.extend_from_slice(&ordinals[&location.pack_id].to_le_bytes());
+        }
+
+        for id in pack_ids {
+            let id_len = u16::try_from(id.as_str().len())
+                .map_err(|_| Error::Other(format!("Pack ID too long: {}", id)))?;
+            let (size, chunk_count, flags) = match packs.get(id) {
+                Some(info) => (info.size, info.chunk_count, FLAG_HAS_INFO),
+                None => (0, 0, 0),
+            };
+            out.extend_from_slice(&size.to_le_bytes());
+            out.extend_from_slice(&chunk_count.to_le_bytes());
+            out.push(flags);
+            out.extend_from_slice(&id_len.to_le_bytes());
+            out.extend_from_slice(id.as_str().as_bytes());
+        }
+
+        Ok(out)
+    }
+
+    /// Number of chunk records.
+    pub fn len(&self) -> usize {
+        self.chunk_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunk_count == 0
+    }
+
+    /// Pack information stored in the pack table.
+    pub fn packs(&self) -> &HashMap<PackID, PackInfo> {
+        &self.packs
+    }
+
+    /// Looks up a chunk by binary search over the sorted records.
+    pub fn get(&self, id: &ChunkID) -> Option<ChunkLocation> {
+        self.position(id).map(|i| self.location(i))
+    }
+
+    pub fn contains(&self, id: &ChunkID) -> bool {
+        self.position(id).is_some()
+    }
+
+    /// Iterates over all records in chunk ID order.
+    pub fn iter(&self) -> impl Iterator<Item = (ChunkID, ChunkLocation)> + '_ {
+        (0..self.chunk_count).map(|i| (self.chunk_id(i), self.location(i)))
+    }
+
+    /// Iterates over all chunk IDs without decoding their locations.
+    pub fn chunk_ids(&self) -> impl Iterator<Item = ChunkID> + '_ {
+        (0..self.chunk_count).map(|i| self.chunk_id(i))
+    }
+
+    fn position(&self, id: &ChunkID) -> Option<usize> {
+        let target: &[u8] = id.as_bytes();
+        let (mut lo, mut hi) = (0, self.chunk_count);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.record(mid)[..32].cmp(target) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    fn record(&self, i: usize) -> &[u8] {
+        let start = HEADER_LEN + i * RECORD_LEN;
+        &self.data[start..start + RECORD_LEN]
+    }
+
+    fn chunk_id(&self, i: usize) -> ChunkID {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&self.record(i)[..32]);
+        ChunkID::new(blake3::Hash::from(bytes))
+    }
+
+    fn pack_ordinal(&self, i: usize) -> usize {
+        read_u32(self.record(i), 44) as usize
+    }
+
+    fn location(&self, i: usize) -> ChunkLocation {
+        let record = self.record(i);
+        ChunkLocation {
+            pack_id: self.pack_ids[self.pack_ordinal(i)].clone(),
+            offset: read_u64(record, 32),
+            length: read_u32(record, 40),
+        }
+    }
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(pack: &PackID, offset: u64) -> ChunkLocation {
+        ChunkLocation {
+            pack_id: pack.clone(),
+            offset,
+            length: 100,
+        }
+    }
+
+    #[test]
+    fn test_packed_index_roundtrip() {
+        let (pack_a, pack_b) = (PackID::generate(), PackID::generate());
+        let chunks: Vec<_> = (0..500u64)
+            .map(|i| {
+                let id = ChunkID::from_data(format!("chunk-{}", i).as_bytes());
+                let pack = if i % 2 == 0 { &pack_a } else { &pack_b };
+                (id, location(pack, i))
+            })
+            .collect();
+        let mut packs = HashMap::new();
+        packs.insert(
+            pack_a.clone(),
+            PackInfo {
+                id: pack_a.clone(),
+                size: 4096,
+                chunk_count: 250,
+            },
+        );
+
+        let data = PackedIndex::encode(chunks.clone(), &packs).unwrap();
+        assert_eq!(&data[..8], MAGIC);
+        let index = PackedIndex::from_vec(data).unwrap();
+
+        assert_eq!(index.len(), 500);
+        for (id, loc) in &chunks {
+            assert_eq!(index.get(id).as_ref(), Some(loc));
+        }
+        assert!(!index.contains(&ChunkID::from_data(b"missing")));
+
+        // Only packs with info are reported; others still resolve for chunks
+        assert_eq!(index.packs().len(), 1);
+        assert_eq!(index.packs()[&pack_a].size, 4096);
+
+        let ids: Vec<_> = index.chunk_ids().collect();
+        assert!(ids.windows(2).all(|w| w[0].as_bytes() < w[1].as_bytes()));
+    }
+
+    #[test]
+    fn test_packed_index_rejects_truncated_data() {
+        let chunks = vec![(ChunkID::from_data(b"a"), location(&PackID::generate(), 0))];
+        let data = PackedIndex::encode(chunks, &HashMap::new()).unwrap();
+
+        assert!(PackedIndex::from_vec(data[..data.len() - 1].to_vec()).is_err());
+        assert!(PackedIndex::from_vec(data[..HEADER_LEN + 10].to_vec()).is_err());
+        assert!(PackedIndex::from_vec(b"not an index".to_vec()).is_err());
+    }
+}
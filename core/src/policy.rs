@@ -0,0 +1,365 @@
+//! Retention policy stored in the repository.
+//!
+//! Instead of passing `--keep-*` flags on every `forget` run, the policy can be
+//! saved once with `ghostsnap policy set` and applied with
+//! `forget --use-policy` or by jobs that configure no retention of their own.
+//!
+//! A policy has a default set of [`RetentionRules`] plus optional overrides
+//! for individual hosts and tags. Each snapshot is governed by exactly one
+//! rule set: a matching tag override wins over a host override, which wins
+//! over the default. Snapshots governed by the same rule set are grouped and
+//! the rules are applied to each group separately.
+
+use crate::crypto::Encryptor;
+use crate::snapshot::Snapshot;
+use crate::types::SnapshotID;
+use crate::{Error, Result};
+use chrono::{DateTime, Datelike, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+/// Storage path of the encrypted retention policy.
+pub const POLICY_PATH: &str = "policy";
+
+/// Policy format version for schema evolution.
+const POLICY_VERSION: u32 = 1;
+
+/// Maps a snapshot time to the key of its daily, weekly, ... bucket.
+type BucketKey = fn(&DateTime<Utc>) -> String;
+
+/// How many snapshots to keep in each time bucket.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionRules {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_last: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_daily: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_weekly: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_monthly: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_yearly: Option<u32>,
+}
+
+impl RetentionRules {
+    /// True if no rule is set; such a rule set keeps every snapshot.
+    pub fn is_empty(&self) -> bool {
+        self.keep_last.is_none()
+            && self.keep_daily.is_none()
+            && self.keep_weekly.is_none()
+            && self.keep_monthly.is_none()
+            && self.keep_yearly.is_none()
+    }
+
+    /// Returns the IDs of the snapshots to keep.
+    ///
+    /// `snapshots` must be sorted newest first. Daily, weekly, monthly and
+    /// yearly rules keep the newest snapshot of each bucket that falls within
+    /// the last N days, weeks, months or years.
+    pub fn keep(&self, snapshots: &[&Snapshot], now: DateTime<Utc>) -> HashSet<SnapshotID> {
+        let mut keep = HashSet::new();
+
+        if self.is_empty() {
+            keep.extend(snapshots.iter().map(|s| s.id.clone()));
+            return keep;
+        }
+
+        if let Some(n) = self.keep_last {
+            keep.extend(snapshots.iter().take(n as usize).map(|s| s.id.clone()));
+        }
+
+        let buckets: [(Option<u32>, i64, BucketKey); 4] = [
+            (self.keep_daily, 1, |t| t.format("%Y-%m-%d").to_string()),
+            (self.keep_weekly, 7, |t| {
+                format!("{}-W{:02}", t.year(), t.iso_week().week())
+            }),
+            // Months and years are approximated for the cutoff
+            (self.keep_monthly, 31, |t| t.format("%Y-%m").to_string()),
+            (self.keep_yearly, 365, |t| t.format("%Y").to_string()),
+        ];
+
+        for (count, days_per_bucket, bucket_key) in buckets {
+            let Some(n) = count else {
+                continue;
+            };
+            let cutoff = now - Duration::days(n as i64 * days_per_bucket);
+            let mut newest: HashMap<String, &Snapshot> = HashMap::new();
+            for s in snapshots.iter().filter(|s| s.time >= cutoff) {
+                newest.entry(bucket_key(&s.time)).or_insert(*s);
+            }
+            keep.extend(newest.values().map(|s| s.id.clone()));
+        }
+
+        keep
+    }
+}
+
+impl fmt::Display for RetentionRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "keep all");
+        }
+
+        let rules = [
+            ("last", self.keep_last),
+            ("daily", self.keep_daily),
+            ("weekly", self.keep_weekly),
+            ("monthly", self.keep_monthly),
+            ("yearly", self.keep_yearly),
+        ];
+        let parts: Vec<String> = rules
+            .iter()
+            .filter_map(|(name, n)| n.map(|n| format!("{} {}", name, n)))
+            .collect();
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// Which part of a [`RetentionPolicy`] governs a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PolicyScope {
+    Default,
+    Host(String),
+    Tag(String),
+}
+
+impl fmt::Display for PolicyScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyScope::Default => write!(f, "default"),
+            PolicyScope::Host(host) => write!(f, "host {}", host),
+            PolicyScope::Tag(tag) => write!(f, "tag {}", tag),
+        }
+    }
+}
+
+/// Encrypted retention policy with per-host and per-tag overrides.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    pub version: u32,
+    pub updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub default: RetentionRules,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hosts: BTreeMap<String, RetentionRules>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, RetentionRules>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RetentionPolicy {
+    pub fn new() -> Self {
+        Self {
+            version: POLICY_VERSION,
+            updated_at: Utc::now(),
+            default: RetentionRules::default(),
+            hosts: BTreeMap::new(),
+            tags: BTreeMap::new(),
+        }
+    }
+
+    /// Rules for `scope`, if any are set.
+    pub fn rules(&self, scope: &PolicyScope) -> Option<&RetentionRules> {
+        match scope {
+            PolicyScope::Default => Some(&self.default),
+            PolicyScope::Host(host) => self.hosts.get(host),
+            PolicyScope::Tag(tag) => self.tags.get(tag),
+        }
+    }
+
+    /// Replaces the rules for `scope`. Empty rules remove a host or tag override.
+    pub fn set_rules(&mut self, scope: PolicyScope, rules: RetentionRules) {
+        match scope {
+            PolicyScope::Default => self.default = rules,
+            PolicyScope::Host(host) if rules.is_empty() => {
+                self.hosts.remove(&host);
+            }
+            PolicyScope::Host(host) => {
+                self.hosts.insert(host, rules);
+            }
+            PolicyScope::Tag(tag) if rules.is_empty() => {
+                self.tags.remove(&tag);
+            }
+            PolicyScope::Tag(tag) => {
+                self.tags.insert(tag, rules);
+            }
+        }
+        self.updated_at = Utc::now();
+    }
+
+    /// True if neither the default nor any override sets a rule.
+    pub fn is_empty(&self) -> bool {
+        self.default.is_empty() && self.hosts.is_empty() && self.tags.is_empty()
+    }
+
+    /// Scope whose rules govern `snapshot`.
+    ///
+    /// Tag overrides are checked in name order, so a snapshot carrying several
+    /// overridden tags is governed by the alphabetically first one.
+    pub fn scope_for(&self, snapshot: &Snapshot) -> PolicyScope {
+        if let Some(tag) = self.tags.keys().find(|t| snapshot.tags.contains(t)) {
+            return PolicyScope::Tag(tag.clone());
+        }
+        if self.hosts.contains_key(&snapshot.hostname) {
+            return PolicyScope::Host(snapshot.hostname.clone());
+        }
+        PolicyScope::Default
+    }
+
+    /// Returns the IDs of the snapshots to keep under this policy.
+    ///
+    /// Snapshots are grouped by the scope that governs them and each group is
+    /// sorted newest first before its rules are applied.
+    pub fn keep(&self, snapshots: &[Snapshot], now: DateTime<Utc>) -> HashSet<SnapshotID> {
+        let mut groups: BTreeMap<PolicyScope, Vec<&Snapshot>> = BTreeMap::new();
+        for snapshot in snapshots {
+            groups
+                .entry(self.scope_for(snapshot))
+                .or_default()
+                .push(snapshot);
+        }
+
+        let mut keep = HashSet::new();
+        for (scope, mut group) in groups {
+            group.sort_by_key(|s| std::cmp::Reverse(s.time));
+            let rules = self.rules(&scope).unwrap_or(&self.default);
+            keep.extend(rules.keep(&group, now));
+        }
+        keep
+    }
+
+    pub fn serialize(&self, encryptor: &Encryptor) -> Result<Vec<u8>> {
+        let json_data = serde_json::to_vec(self)
+            .map_err(|e| Error::Other(format!("Failed to serialize retention policy: {}", e)))?;
+        encryptor.encrypt(&json_data)
+    }
+
+    pub fn deserialize(data: &[u8], encryptor: &Encryptor) -> Result<Self> {
+        let decrypted_data = encryptor.decrypt(data)?;
+        let policy: Self = serde_json::from_slice(&decrypted_data)
+            .map_err(|e| Error::Other(format!("Failed to deserialize retention policy: {}", e)))?;
+        if policy.version != POLICY_VERSION {
+            return Err(Error::InvalidFormatVersion {
+                version: policy.version,
+            });
+        }
+        Ok(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChunkID;
+    use chrono::TimeZone;
+    use std::path::PathBuf;
+
+    fn fixed_now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap()
+    }
+
+    fn snapshot(host: &str, tags: &[&str], days_ago: i64, now: DateTime<Utc>) -> Snapshot {
+        let mut snapshot = Snapshot::new(vec![PathBuf::from("/data")], ChunkID::from_data(b"t"))
+            .with_tags(tags.iter().map(|t| t.to_string()).collect());
+        snapshot.hostname = host.to_string();
+        snapshot.time = now - Duration::days(days_ago);
+        snapshot
+    }
+
+    #[test]
+    fn test_overrides_take_precedence() {
+        let now = fixed_now();
+        let mut policy = RetentionPolicy::new();
+        policy.set_rules(
+            PolicyScope::Default,
+            RetentionRules {
+                keep_last: Some(1),
+                ..Default::default()
+            },
+        );
+        policy.set_rules(
+            PolicyScope::Host("db".to_string()),
+            RetentionRules {
+                keep_last: Some(2),
+                ..Default::default()
+            },
+        );
+        policy.set_rules(
+            PolicyScope::Tag("manual".to_string()),
+            RetentionRules::default(),
+        );
+
+        let snapshots = vec![
+            snapshot("web", &[], 0, now),
+            snapshot("web", &[], 1, now),
+            snapshot("db", &[], 0, now),
+            snapshot("db", &[], 1, now),
+            snapshot("db", &[], 2, now),
+        ];
+        assert_eq!(
+            policy.scope_for(&snapshots[2]),
+            PolicyScope::Host("db".into())
+        );
+        // Empty rules do not create an override
+        assert!(policy.tags.is_empty());
+
+        let keep = policy.keep(&snapshots, now);
+        let expected: HashSet<_> = [0, 2, 3].iter().map(|&i| snapshots[i].id.clone()).collect();
+        assert_eq!(keep, expected);
+
+        // A tag override wins over the host override
+        policy.set_rules(
+            PolicyScope::Tag("manual".to_string()),
+            RetentionRules {
+                keep_daily: Some(30),
+                ..Default::default()
+            },
+        );
+        let tagged = snapshot("db", &["manual"], 3, now);
+        assert_eq!(policy.scope_for(&tagged), PolicyScope::Tag("manual".into()));
+    }
+
+    #[test]
+    fn test_daily_keeps_newest_per_day() {
+        let now = fixed_now();
+        let snapshots = [
+            snapshot("h", &[], 0, now),
+            snapshot("h", &[], 0, now - Duration::hours(1)),
+            snapshot("h", &[], 10, now),
+        ];
+        let refs: Vec<&Snapshot> = snapshots.iter().collect();
+        let rules = RetentionRules {
+            keep_daily: Some(7),
+            ..Default::default()
+        };
+
+        let keep = rules.keep(&refs, now);
+        assert_eq!(keep.len(), 1);
+        assert!(keep.contains(&snapshots[0].id));
+    }
+
+    #[test]
+    fn test_policy_roundtrip() {
+        let encryptor = Encryptor::new(&[7u8; 32]).unwrap();
+        let mut policy = RetentionPolicy::new();
+        policy.set_rules(
+            PolicyScope::Tag("hourly".to_string()),
+            RetentionRules {
+                keep_last: Some(24),
+                ..Default::default()
+            },
+        );
+
+        let data = policy.serialize(&encryptor).unwrap();
+        let restored = RetentionPolicy::deserialize(&data, &encryptor).unwrap();
+        assert_eq!(restored.tags["hourly"].keep_last, Some(24));
+        assert!(restored.default.is_empty());
+    }
+}
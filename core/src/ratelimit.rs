@@ -0,0 +1,245 @@
+//! Time-of-day aware upload bandwidth limiting.
+//!
+//! A [`BandwidthSchedule`] maps local time windows to upload limits, e.g.
+//! 10 MiB/s during business hours and unlimited overnight:
+//!
+//! ```
+//! use ghostsnap_core::ratelimit::BandwidthSchedule;
+//!
+//! let schedule = BandwidthSchedule::parse(None, &["08:00-20:00=10M".to_string()]).unwrap();
+//! assert!(schedule.is_limited());
+//! ```
+//!
+//! Windows may wrap past midnight (`22:00-06:00=50M`). When no window matches,
+//! the default limit applies (unlimited unless one is configured).
+
+use crate::{Error, Result};
+use chrono::{Local, NaiveTime};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// A bandwidth limit active between two local times.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BandwidthWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    /// Limit in bytes per second; `None` means unlimited
+    pub limit: Option<u64>,
+}
+
+impl BandwidthWindow {
+    /// Parses a window of the form `HH:MM-HH:MM=RATE`, e.g. `08:00-20:00=10M`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (range, rate) = spec.split_once('=').ok_or_else(|| {
+            Error::Other(format!(
+                "Invalid bandwidth window '{}': expected HH:MM-HH:MM=RATE",
+                spec
+            ))
+        })?;
+        let (start, end) = range.split_once('-').ok_or_else(|| {
+            Error::Other(format!(
+                "Invalid bandwidth window '{}': expected HH:MM-HH:MM=RATE",
+                spec
+            ))
+        })?;
+
+        let parse_time = |s: &str| {
+            NaiveTime::parse_from_str(s.trim(), "%H:%M").map_err(|e| {
+                Error::Other(format!("Invalid time '{}' in bandwidth window: {}", s, e))
+            })
+        };
+
+        let start = parse_time(start)?;
+        let end = parse_time(end)?;
+        if start == end {
+            return Err(Error::Other(format!(
+                "Invalid bandwidth window '{}': start and end are equal",
+                spec
+            )));
+        }
+
+        Ok(Self {
+            start,
+            end,
+            limit: parse_rate(rate)?,
+        })
+    }
+
+    /// Returns true if `time` falls inside this window (end exclusive).
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            time >= self.start && time < self.end
+        } else {
+            // Window wraps past midnight
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// Upload limits by time of day.
+#[derive(Debug, Clone, Default)]
+pub struct BandwidthSchedule {
+    /// Limit applied outside every window; `None` means unlimited
+    pub default_limit: Option<u64>,
+    pub windows: Vec<BandwidthWindow>,
+}
+
+impl BandwidthSchedule {
+    /// Builds a schedule from an optional default rate and window specs.
+    pub fn parse(default_rate: Option<&str>, windows: &[String]) -> Result<Self> {
+        Ok(Self {
+            default_limit: match default_rate {
+                Some(rate) => parse_rate(rate)?,
+                None => None,
+            },
+            windows: windows
+                .iter()
+                .map(|w| BandwidthWindow::parse(w))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Returns true if any limit is configured.
+    pub fn is_limited(&self) -> bool {
+        self.default_limit.is_some() || self.windows.iter().any(|w| w.limit.is_some())
+    }
+
+    /// Returns the limit in bytes per second at `time`. The first matching window wins.
+    pub fn limit_at(&self, time: NaiveTime) -> Option<u64> {
+        self.windows
+            .iter()
+            .find(|w| w.contains(time))
+            .map(|w| w.limit)
+            .unwrap_or(self.default_limit)
+    }
+
+    /// Returns the limit currently in effect, based on local time.
+    pub fn current_limit(&self) -> Option<u64> {
+        self.limit_at(Local::now().time())
+    }
+}
+
+/// Parses a rate such as `10M`, `512K`, `1G` (bytes per second).
+///
+/// `0`, `unlimited` and `none` yield `None`.
+pub fn parse_rate(rate: &str) -> Result<Option<u64>> {
+    let rate = rate.trim().to_uppercase();
+    if rate == "0" || rate == "UNLIMITED" || rate == "NONE" {
+        return Ok(None);
+    }
+
+    let rate = rate.trim_end_matches("/S").trim_end_matches('B');
+    let (num, multiplier) = if let Some(n) = rate.strip_suffix('G') {
+        (n, 1024 * 1024 * 1024)
+    } else if let Some(n) = rate.strip_suffix('M') {
+        (n, 1024 * 1024)
+    } else if let Some(n) = rate.strip_suffix('K') {
+        (n, 1024)
+    } else {
+        (rate, 1)
+    };
+
+    let invalid = || Error::Other(format!("Invalid bandwidth rate: {}", rate));
+    let num: u64 = num.trim().parse().map_err(|_| invalid())?;
+    num.checked_mul(multiplier).map(Some).ok_or_else(invalid)
+}
+
+/// Throttles uploads (or downloads) according to a [`BandwidthSchedule`].
+///
+/// The limit is re-evaluated on every call, so long-running backups pick up
+/// the new rate as soon as a window opens or closes.
+#[derive(Debug)]
+pub struct RateLimiter {
+    schedule: BandwidthSchedule,
+    state: Mutex<LimiterState>,
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    period_start: Instant,
+    bytes: u64,
+}
+
+impl RateLimiter {
+    pub fn new(schedule: BandwidthSchedule) -> Self {
+        Self {
+            schedule,
+            state: Mutex::new(LimiterState {
+                period_start: Instant::now(),
+                bytes: 0,
+            }),
+        }
+    }
+
+    pub fn schedule(&self) -> &BandwidthSchedule {
+        &self.schedule
+    }
+
+    /// Waits as long as needed for `bytes` to stay within the current limit.
+    pub async fn throttle(&self, bytes: usize) {
+        let Some(limit) = self.schedule.current_limit() else {
+            return;
+        };
+        if limit == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().await;
+        if state.period_start.elapsed() >= Duration::from_secs(1) {
+            state.period_start = Instant::now();
+            state.bytes = 0;
+        }
+
+        state.bytes += bytes as u64;
+        let expected = Duration::from_secs_f64(state.bytes as f64 / limit as f64);
+        let elapsed = state.period_start.elapsed();
+        if expected > elapsed {
+            tokio::time::sleep(expected - elapsed).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("10M").unwrap(), Some(10 * 1024 * 1024));
+        assert_eq!(parse_rate("512k").unwrap(), Some(512 * 1024));
+        assert_eq!(parse_rate("1GB/s").unwrap(), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_rate("unlimited").unwrap(), None);
+        assert!(parse_rate("fast").is_err());
+        let err = parse_rate("99999999999G").unwrap_err();
+        assert!(
+            err.to_string().contains("Invalid bandwidth rate"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_schedule_windows() {
+        let schedule = BandwidthSchedule::parse(
+            Some("50M"),
+            &[
+                "08:00-20:00=10M".to_string(),
+                "22:00-06:00=unlimited".to_string(),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(schedule.limit_at(time(12, 0)), Some(10 * 1024 * 1024));
+        assert_eq!(schedule.limit_at(time(20, 0)), Some(50 * 1024 * 1024));
+        assert_eq!(schedule.limit_at(time(23, 30)), None);
+        assert_eq!(schedule.limit_at(time(3, 0)), None);
+        assert_eq!(schedule.limit_at(time(7, 0)), Some(50 * 1024 * 1024));
+
+        assert!(BandwidthWindow::parse("08:00=10M").is_err());
+        assert!(BandwidthWindow::parse("08:00-08:00=10M").is_err());
+    }
+}
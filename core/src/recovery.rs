@@ -0,0 +1,246 @@
+//! Recovery material for key escrow.
+//!
+//! Losing the password, or the `keys/` objects, makes every backup in a
+//! repository unreadable. Two kinds of recovery material can be exported from
+//! an open repository and kept in escrow:
+//!
+//! - A [`KeyExport`] holds the repository config and the key files as stored.
+//!   The data key in it stays sealed with the password (and keyfile), so it is
+//!   no more sensitive than the repository itself. Importing it restores a
+//!   repository whose config or keys were lost.
+//! - A [`RecoveryCode`] is the unsealed data key, written as a paper key. It
+//!   opens the repository without any password and must be stored like one;
+//!   using it seals the data key with a new password.
+
+use crate::{Error, RepoConfig, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Value of [`KeyExport::format`].
+pub const KEY_EXPORT_FORMAT: &str = "ghostsnap-key-export";
+
+/// The config and sealed key files of a repository, as written by
+/// `key export`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyExport {
+    /// Always [`KEY_EXPORT_FORMAT`]
+    pub format: String,
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    /// Repository config, restored if the repository lost it
+    pub config: RepoConfig,
+    /// Key files by object name under `keys/`
+    pub keys: BTreeMap<String, String>,
+}
+
+impl KeyExport {
+    pub fn new(config: RepoConfig, keys: BTreeMap<String, String>) -> Self {
+        Self {
+            format: KEY_EXPORT_FORMAT.to_string(),
+            version: 1,
+            created_at: Utc::now(),
+            config,
+            keys,
+        }
+    }
+
+    /// Parses an export, rejecting other files and unsafe key names.
+    pub fn from_json(data: &[u8]) -> Result<Self> {
+        let export: Self = serde_json::from_slice(data)?;
+        if export.format != KEY_EXPORT_FORMAT {
+            return Err(Error::Other(format!(
+                "Not a ghostsnap key export (format '{}')",
+                export.format
+            )));
+        }
+        if export.version != 1 {
+            return Err(Error::InvalidFormatVersion {
+                version: export.version,
+            });
+        }
+        if let Some(name) = export
+            .keys
+            .keys()
+            .find(|name| name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.'))
+        {
+            return Err(Error::Other(format!(
+                "Invalid key name in key export: '{}'",
+                name
+            )));
+        }
+        Ok(export)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Crockford base32: no I, L, O or U, so codes survive being copied by hand.
+const CODE_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Length of the data key in a recovery code.
+const KEY_LEN: usize = 32;
+
+/// Bytes of BLAKE3 checksum appended to the key, to catch typos.
+const CODE_CHECKSUM_LEN: usize = 2;
+
+/// Characters per dash-separated group.
+const CODE_GROUP_LEN: usize = 5;
+
+/// A repository data key as a paper key: 55 base32 characters in groups of
+/// five, including a checksum.
+#[derive(Clone, PartialEq, Eq)]
+pub struct RecoveryCode {
+    key: [u8; KEY_LEN],
+}
+
+impl RecoveryCode {
+    pub fn from_key(key: &[u8]) -> Result<Self> {
+        let key = key
+            .try_into()
+            .map_err(|_| Error::Encryption("Key must be 32 bytes".to_string()))?;
+        Ok(Self { key })
+    }
+
+    /// Parses a code. Case, dashes and spaces don't matter, and the letters
+    /// O, I and L are read as the digits they resemble.
+    pub fn parse(code: &str) -> Result<Self> {
+        let mut bytes = Vec::with_capacity(KEY_LEN + CODE_CHECKSUM_LEN);
+        let mut buffer = 0u32;
+        let mut bits = 0;
+        for c in code.chars().filter(|c| !matches!(c, '-' | ' ')) {
+            let c = match c.to_ascii_uppercase() {
+                'O' => '0',
+                'I' | 'L' => '1',
+                c => c,
+            };
+            let value = CODE_ALPHABET
+                .iter()
+                .position(|&a| a as char == c)
+                .ok_or_else(|| {
+                    Error::InvalidRecoveryCode(format!("unexpected character '{}'", c))
+                })?;
+            buffer = (buffer << 5) | value as u32;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                bytes.push((buffer >> bits) as u8);
+                buffer &= (1 << bits) - 1;
+            }
+        }
+
+        if bytes.len() != KEY_LEN + CODE_CHECKSUM_LEN || bits >= 5 {
+            return Err(Error::InvalidRecoveryCode(
+                "wrong length, a character is missing or extra".to_string(),
+            ));
+        }
+        let (key, checksum) = bytes.split_at(KEY_LEN);
+        if checksum != &blake3::hash(key).as_bytes()[..CODE_CHECKSUM_LEN] {
+            return Err(Error::InvalidRecoveryCode(
+                "checksum mismatch, check for typos".to_string(),
+            ));
+        }
+        Self::from_key(key)
+    }
+
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+}
+
+impl fmt::Display for RecoveryCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = self.key.to_vec();
+        bytes.extend_from_slice(&blake3::hash(&self.key).as_bytes()[..CODE_CHECKSUM_LEN]);
+
+        let mut chars = Vec::new();
+        let mut buffer = 0u32;
+        let mut bits = 0;
+        for byte in bytes {
+            buffer = (buffer << 8) | u32::from(byte);
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                chars.push(CODE_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+            }
+            buffer &= (1 << bits) - 1;
+        }
+        if bits > 0 {
+            chars.push(CODE_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+        }
+
+        let groups: Vec<String> = chars
+            .chunks(CODE_GROUP_LEN)
+            .map(|group| group.iter().collect())
+            .collect();
+        f.write_str(&groups.join("-"))
+    }
+}
+
+/// Never prints the key.
+impl fmt::Debug for RecoveryCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RecoveryCode(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovery_code_roundtrip() {
+        let key: Vec<u8> = (0..32).collect();
+        let code = RecoveryCode::from_key(&key).unwrap();
+        let text = code.to_string();
+        assert_eq!(text.len(), 55 + 10);
+        assert!(text.split('-').all(|group| group.len() == 5));
+
+        assert_eq!(RecoveryCode::parse(&text).unwrap(), code);
+        assert_eq!(
+            RecoveryCode::parse(&text.to_lowercase().replace('-', " ")).unwrap(),
+            code
+        );
+        assert_eq!(
+            RecoveryCode::parse(&text.replace('0', "o").replace('1', "l")).unwrap(),
+            code
+        );
+        assert_eq!(format!("{:?}", code), "RecoveryCode(..)");
+    }
+
+    #[test]
+    fn test_recovery_code_typos() {
+        let text = RecoveryCode::from_key(&[7u8; 32]).unwrap().to_string();
+
+        let mut typo: Vec<char> = text.chars().collect();
+        typo[3] = if typo[3] == 'A' { 'B' } else { 'A' };
+        let typo: String = typo.into_iter().collect();
+        assert!(matches!(
+            RecoveryCode::parse(&typo),
+            Err(Error::InvalidRecoveryCode(_))
+        ));
+        assert!(RecoveryCode::parse(&text[..text.len() - 1]).is_err());
+        assert!(RecoveryCode::parse(&format!("{}0", text)).is_err());
+        assert!(RecoveryCode::parse("not-a-code!").is_err());
+    }
+
+    #[test]
+    fn test_key_export_json() {
+        let mut keys = BTreeMap::new();
+        keys.insert("key-one".to_string(), "{}".to_string());
+        let export = KeyExport::new(RepoConfig::default(), keys);
+        let json = export.to_json().unwrap();
+
+        let parsed = KeyExport::from_json(json.as_bytes()).unwrap();
+        assert_eq!(parsed.config.id, export.config.id);
+        assert_eq!(parsed.keys, export.keys);
+
+        let renamed = json.replace("key-one", "../config");
+        assert!(KeyExport::from_json(renamed.as_bytes()).is_err());
+        let other = json.replace(KEY_EXPORT_FORMAT, "something-else");
+        assert!(KeyExport::from_json(other.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+//! Incrementally maintained chunk reference counts.
+//!
+//! Finding the chunks still in use used to mean loading the tree of every
+//! snapshot, which dominates `prune` on large repositories. [`RefCounts`]
+//! records, for every chunk, how many snapshots reference it, together with
+//! the snapshots that have been counted and their trees. It is stored
+//! encrypted at `index/refcounts` and brought up to date by loading only the
+//! trees of snapshots added or removed since it was written: trees are never
+//! deleted, so a forgotten snapshot's chunks can still be subtracted.
+//!
+//! The counts are a compact binary layout, since they hold an entry for
+//! every chunk in the repository:
+//!
+//! ```text
+//! offset  size  field
+//! 0       8     magic "GSNPREF\0"
+//! 8       4     version (1)
+//! 12      4     snapshot count
+//! 16      8     chunk count
+//! 24      ...   snapshots: tree ID [32] | ID length u16 | ID
+//! ...     36×n  chunk records, sorted by chunk ID: chunk ID [32] | count u32
+//! ```
+//!
+//! All integers are little-endian.
+
+use crate::crypto::Encryptor;
+use crate::snapshot::Tree;
+use crate::types::{ChunkID, SnapshotID};
+use crate::{Error, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Storage path of the encrypted reference counts.
+pub const REFCOUNTS_PATH: &str = "index/refcounts";
+
+const MAGIC: &[u8; 8] = b"GSNPREF\0";
+const REFCOUNTS_VERSION: u32 = 1;
+const HEADER_LEN: usize = 24;
+const RECORD_LEN: usize = 36;
+
+/// Number of counted snapshots referencing each chunk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefCounts {
+    /// Counted snapshots and their trees
+    snapshots: BTreeMap<SnapshotID, ChunkID>,
+    counts: HashMap<ChunkID, u32>,
+}
+
+impl RefCounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counted snapshots and their trees.
+    pub fn snapshots(&self) -> &BTreeMap<SnapshotID, ChunkID> {
+        &self.snapshots
+    }
+
+    /// Counts the distinct chunks of `tree` for `snapshot_id`. Returns false
+    /// if the snapshot was already counted.
+    pub fn add_snapshot(
+        &mut self,
+        snapshot_id: &SnapshotID,
+        tree_id: ChunkID,
+        tree: &Tree,
+    ) -> bool {
+        if self.snapshots.contains_key(snapshot_id) {
+            return false;
+        }
+        for chunk_id in distinct_chunks(tree) {
+            *self.counts.entry(chunk_id).or_default() += 1;
+        }
+        self.snapshots.insert(snapshot_id.clone(), tree_id);
+        true
+    }
+
+    /// Subtracts the chunks of a counted snapshot, given its tree. Returns
+    /// false if the snapshot was not counted.
+    pub fn remove_snapshot(&mut self, snapshot_id: &SnapshotID, tree: &Tree) -> bool {
+        if self.snapshots.remove(snapshot_id).is_none() {
+            return false;
+        }
+        for chunk_id in distinct_chunks(tree) {
+            if let Some(count) = self.counts.get_mut(&chunk_id) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(&chunk_id);
+                }
+            }
+        }
+        true
+    }
+
+    /// Number of counted snapshots referencing `chunk_id`.
+    pub fn count(&self, chunk_id: &ChunkID) -> u32 {
+        self.counts.get(chunk_id).copied().unwrap_or(0)
+    }
+
+    /// Number of distinct chunks referenced by any counted snapshot.
+    pub fn chunk_count(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Chunks referenced by at least one counted snapshot.
+    pub fn referenced_chunks(&self) -> HashSet<ChunkID> {
+        self.counts.keys().copied().collect()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut chunks: Vec<(&ChunkID, &u32)> = self.counts.iter().collect();
+        chunks.sort_unstable_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+
+        let mut buf =
+            Vec::with_capacity(HEADER_LEN + self.snapshots.len() * 70 + chunks.len() * RECORD_LEN);
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&REFCOUNTS_VERSION.to_le_bytes());
+        buf.extend_from_slice(&(self.snapshots.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&(chunks.len() as u64).to_le_bytes());
+        for (snapshot_id, tree_id) in &self.snapshots {
+            buf.extend_from_slice(tree_id.as_bytes());
+            buf.extend_from_slice(&(snapshot_id.as_str().len() as u16).to_le_bytes());
+            buf.extend_from_slice(snapshot_id.as_str().as_bytes());
+        }
+        for (chunk_id, count) in chunks {
+            buf.extend_from_slice(chunk_id.as_bytes());
+            buf.extend_from_slice(&count.to_le_bytes());
+        }
+        buf
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let corrupt = |what: &str| Error::Other(format!("Corrupt reference counts: {}", what));
+
+        if data.len() < HEADER_LEN || &data[..8] != MAGIC {
+            return Err(corrupt("bad header"));
+        }
+        let version = u32::from_le_bytes(data[8..12].try_into().unwrap());
+        if version != REFCOUNTS_VERSION {
+            return Err(Error::InvalidFormatVersion { version });
+        }
+        let snapshot_count = u32::from_le_bytes(data[12..16].try_into().unwrap()) as usize;
+        let chunk_count = u64::from_le_bytes(data[16..24].try_into().unwrap()) as usize;
+
+        let mut pos = HEADER_LEN;
+        let mut next = |len: usize| take(data, &mut pos, len).ok_or_else(|| corrupt("truncated"));
+
+        let mut snapshots = BTreeMap::new();
+        for _ in 0..snapshot_count {
+            let tree_id = read_chunk_id(next(32)?);
+            let len = u16::from_le_bytes(next(2)?.try_into().unwrap()) as usize;
+            let snapshot_id: SnapshotID = std::str::from_utf8(next(len)?)
+                .map_err(|_| corrupt("snapshot ID is not UTF-8"))?
+                .parse()?;
+            snapshots.insert(snapshot_id, tree_id);
+        }
+
+        let mut counts = HashMap::with_capacity(chunk_count);
+        for _ in 0..chunk_count {
+            let chunk_id = read_chunk_id(next(32)?);
+            let count = u32::from_le_bytes(next(4)?.try_into().unwrap());
+            counts.insert(chunk_id, count);
+        }
+        if pos != data.len() {
+            return Err(corrupt("trailing data"));
+        }
+
+        Ok(Self { snapshots, counts })
+    }
+
+    pub fn serialize(&self, encryptor: &Encryptor) -> Result<Vec<u8>> {
+        encryptor.encrypt(&self.to_bytes())
+    }
+
+    pub fn deserialize(data: &[u8], encryptor: &Encryptor) -> Result<Self> {
+        Self::from_bytes(&encryptor.decrypt(data)?)
+    }
+}
+
+fn distinct_chunks(tree: &Tree) -> HashSet<ChunkID> {
+    tree.nodes
+        .iter()
+        .flat_map(|node| &node.chunks)
+        .map(|chunk_ref| chunk_ref.id)
+        .collect()
+}
+
+/// Returns the next `len` bytes at `pos` and advances past them.
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Option<&'a [u8]> {
+    let bytes = data.get(*pos..*pos + len)?;
+    *pos += len;
+    Some(bytes)
+}
+
+fn read_chunk_id(bytes: &[u8]) -> ChunkID {
+    let array: [u8; 32] = bytes.try_into().unwrap();
+    ChunkID::from(blake3::Hash::from(array))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TreeNode;
+
+    fn tree(chunks: &[&[u8]]) -> Tree {
+        let mut tree = Tree::new();
+        tree.add_node(TreeNode::test_file("file", chunks));
+        tree
+    }
+
+    #[test]
+    fn test_add_and_remove_snapshots() {
+        let first = tree(&[b"a", b"b", b"a"]);
+        let second = tree(&[b"b", b"c"]);
+        let a = ChunkID::from_data(b"a");
+        let b = ChunkID::from_data(b"b");
+        let c = ChunkID::from_data(b"c");
+
+        let (s1, s2) = (SnapshotID::generate(), SnapshotID::generate());
+
+        let mut refcounts = RefCounts::new();
+        assert!(refcounts.add_snapshot(&s1, ChunkID::from_data(b"t1"), &first));
+        assert!(refcounts.add_snapshot(&s2, ChunkID::from_data(b"t2"), &second));
+        assert!(!refcounts.add_snapshot(&s2, ChunkID::from_data(b"t2"), &second));
+
+        // Chunks repeated within a snapshot count once
+        assert_eq!(refcounts.count(&a), 1);
+        assert_eq!(refcounts.count(&b), 2);
+        assert_eq!(refcounts.chunk_count(), 3);
+
+        assert!(refcounts.remove_snapshot(&s1, &first));
+        assert!(!refcounts.remove_snapshot(&s1, &first));
+        assert_eq!(refcounts.count(&a), 0);
+        assert_eq!(refcounts.count(&b), 1);
+        assert_eq!(refcounts.referenced_chunks(), HashSet::from([b, c]));
+        assert_eq!(refcounts.snapshots().len(), 1);
+    }
+
+    #[test]
+    fn test_refcounts_roundtrip() {
+        let encryptor = Encryptor::new(&[7u8; 32]).unwrap();
+        let mut refcounts = RefCounts::new();
+        refcounts.add_snapshot(
+            &SnapshotID::generate(),
+            ChunkID::from_data(b"tree"),
+            &tree(&[b"x", b"y"]),
+        );
+
+        let data = refcounts.serialize(&encryptor).unwrap();
+        assert_eq!(
+            RefCounts::deserialize(&data, &encryptor).unwrap(),
+            refcounts
+        );
+
+        let bytes = refcounts.to_bytes();
+        assert!(RefCounts::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(RefCounts::from_bytes(&[0u8; HEADER_LEN]).is_err());
+    }
+}
@@ -1,19 +1,39 @@
+use crate::bundle::{BundleImportStats, SnapshotBundle};
+use crate::capability::{self, Access, Capabilities};
+use crate::chunker::Chunker;
+use crate::hash::{ChunkHasher, HashAlgorithm};
 use crate::index::{ChunkLocation, Index, PackInfo};
+use crate::layout::{self, MARKER_PATH, REPOSITORY_PREFIXES, RepositoryMarker};
 use crate::pack::{PackFile, PackManager, RepackStats, Repacker};
-use crate::snapshot::{Snapshot, Tree};
-use crate::storage::{RepositoryLocation, RepositoryStorage, S3Location, storage_for_location};
+use crate::policy::{POLICY_PATH, RetentionPolicy};
+use crate::ratelimit::RateLimiter;
+use crate::recovery::{KeyExport, RecoveryCode};
+use crate::scrub::{SCRUB_STATE_PATH, ScrubFailure, ScrubReport, ScrubState};
+use crate::settings::{RepoSettings, SETTINGS_PATH, default_pack_size};
+use crate::snapshot::{SUBTREE_MIN_NODES, Snapshot, TREE_PAGE_NODES, Tree, TreePage};
+use crate::snapshot_cache::{SNAPSHOT_CACHE_PATH, SnapshotCache, SnapshotSummary};
+use crate::refcount::{REFCOUNTS_PATH, RefCounts};
+use crate::stats::{HostAttribution, HostStats, STATS_CACHE_PATH, SnapshotStatsEntry, StatsCache};
+use crate::storage::{
+    ObjectMetadata, RepositoryLocation, RepositoryStorage, S3Location, TierStatus, fan_out,
+    storage_for_location,
+};
 use crate::{ChunkID, PackID, SnapshotID};
 use crate::{
-    AzureRepoTransport, Error, RcloneRepoTransport, RepoConfig, RepoTransport, Result, S3RepoSse,
-    S3RepoTransport, SftpRepoTransport, crypto::{Encryptor, MasterKey},
+    AccessTier, AzureAccessTiers, AzureRepoTransport, CryptoProfile, EncryptionLayer,
+    EncryptionMode, Error, ErrorContext, KDF_PBKDF2_SHA256, RcloneRepoTransport, RehydratePriority,
+    RepoConfig, RepoTransport, Result, S3RepoSse, S3RepoTransport, SftpRepoTransport,
+    crypto::{Encryptor, KeyProvider, MasterKey, PasswordKey},
 };
 use bytes::Bytes;
 use lru::LruCache;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::str;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::fs;
 use tokio::sync::RwLock;
 
@@ -23,6 +43,28 @@ const DEFAULT_PACK_CACHE_SIZE: usize = 128 * 1024 * 1024;
 /// Maximum number of packs to cache.
 const DEFAULT_PACK_CACHE_COUNT: usize = 32;
 
+/// How far the newest snapshot, or the storage's clock, may be ahead of or
+/// behind the local clock before the local clock is considered wrong (hosts
+/// sharing a repository drift a little).
+pub const CLOCK_SKEW_TOLERANCE: chrono::Duration = chrono::Duration::minutes(5);
+
+/// How far `stamped`, a time another clock gave an object written between
+/// `before` and `after` by ours, lies outside that window: positive if the
+/// other clock is ahead, negative if it is behind, zero if they agree.
+pub fn clock_offset(
+    stamped: chrono::DateTime<chrono::Utc>,
+    before: chrono::DateTime<chrono::Utc>,
+    after: chrono::DateTime<chrono::Utc>,
+) -> chrono::Duration {
+    if stamped > after {
+        stamped - after
+    } else if stamped < before {
+        stamped - before
+    } else {
+        chrono::Duration::zero()
+    }
+}
+
 /// The main repository structure for Ghostsnap backups.
 ///
 /// A repository manages all backup data including snapshots, pack files, indices, and encryption keys.
@@ -33,6 +75,8 @@ const DEFAULT_PACK_CACHE_COUNT: usize = 32;
 /// ```text
 /// repository/
 /// ├── config          # Repository configuration
+/// ├── policy          # Encrypted retention policy (optional)
+/// ├── settings        # Encrypted settings shared by all clients (optional)
 /// ├── keys/           # Encrypted data keys
 /// ├── data/           # Pack files and tree objects
 /// ├── index/          # Chunk location index (consolidated)
@@ -45,9 +89,16 @@ pub struct Repository {
     display_path: PathBuf,
     storage: Box<dyn RepositoryStorage>,
     config: RepoConfig,
+    /// Shared settings, read when the repository is opened
+    settings: RepoSettings,
     #[allow(dead_code)]
     master_key: Option<MasterKey>,
+    /// Name of the key file that unlocked the repository; `None` if it is
+    /// unencrypted
+    key_name: Option<String>,
     encryptor: Option<Encryptor>,
+    /// Computes chunk and tree IDs with the configured algorithm
+    hasher: ChunkHasher,
     /// In-memory chunk index with bloom filter
     index: Arc<RwLock<Index>>,
     /// LRU cache for pack files
@@ -56,6 +107,12 @@ pub struct Repository {
     pack_cache_size: Arc<RwLock<usize>>,
     /// Maximum cache size in bytes
     max_cache_size: usize,
+    /// Optional upload bandwidth limiter for pack and tree writes
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Optional download bandwidth limiter for snapshot, tree and pack reads
+    download_limiter: Option<Arc<RateLimiter>>,
+    /// Bytes of snapshots, trees and packs read from storage
+    bytes_read: AtomicU64,
 }
 
 impl Repository {
@@ -72,7 +129,169 @@ impl Repository {
     }
 
     pub async fn init_at_location(location: RepositoryLocation, password: &str) -> Result<Self> {
+        Self::init_with_encryption(
+            location,
+            &PasswordKey::new(password),
+            EncryptionMode::default(),
+        )
+        .await
+    }
+
+    /// Initializes a repository with the given encryption mode, sealing the
+    /// data key with the secrets from `keys`.
+    ///
+    /// With [`EncryptionMode::None`] no key file is written and `keys` is
+    /// ignored; objects are stored as checksummed plaintext. Only use this on
+    /// storage that is already encrypted and access-controlled.
+    pub async fn init_with_encryption(
+        location: RepositoryLocation,
+        keys: &dyn KeyProvider,
+        encryption: EncryptionMode,
+    ) -> Result<Self> {
+        Self::init_with_encryption_layer(location, keys, EncryptionLayer::for_mode(encryption))
+            .await
+    }
+
+    /// Initializes a repository that encrypts data at the given layer.
+    ///
+    /// [`EncryptionLayer::Backend`] stores checksummed plaintext like
+    /// [`EncryptionMode::None`]; the layer is recorded in the config so that
+    /// `stats` can report whether the backend is actually encrypting.
+    pub async fn init_with_encryption_layer(
+        location: RepositoryLocation,
+        keys: &dyn KeyProvider,
+        layer: EncryptionLayer,
+    ) -> Result<Self> {
+        Self::init_with_hash(location, keys, layer, HashAlgorithm::default()).await
+    }
+
+    /// Initializes a repository that encrypts data at the given layer and
+    /// computes chunk IDs with `hash`. Keyed hashes need an encrypted layer.
+    pub async fn init_with_hash(
+        location: RepositoryLocation,
+        keys: &dyn KeyProvider,
+        layer: EncryptionLayer,
+        hash: HashAlgorithm,
+    ) -> Result<Self> {
+        Self::init_with_profile(location, keys, layer, hash, CryptoProfile::Standard).await
+    }
+
+    /// Initializes a repository restricted to the primitives of `profile`:
+    /// its cipher and KDF, and `hash`, which must be the profile's own
+    /// ([`CryptoProfile::hash`]) under [`CryptoProfile::Fips`].
+    pub async fn init_with_profile(
+        location: RepositoryLocation,
+        keys: &dyn KeyProvider,
+        layer: EncryptionLayer,
+        hash: HashAlgorithm,
+        profile: CryptoProfile,
+    ) -> Result<Self> {
+        let config = RepoConfig {
+            transport: Some(Self::transport_from_location(&location)),
+            kdf_params: profile.kdf_params(),
+            encryption: profile.mode(layer),
+            encryption_layer: Some(layer),
+            hash,
+            profile,
+            ..RepoConfig::default()
+        };
+        Self::init_with_config(location, keys, config, None).await
+    }
+
+    /// Initializes a repository with the settings of the one at `source`: its
+    /// chunker polynomial and chunk size, hash algorithm, crypto profile,
+    /// compression level, KDF and, unless `layer` is given, encryption layer. Backend
+    /// settings come from `location` as with any init.
+    ///
+    /// With `share_keys`, the source's key files, retention policy and
+    /// settings are
+    /// copied instead of generating a data key, so the same password opens
+    /// both repositories and encrypted objects can be copied between them
+    /// as they are.
+    pub async fn init_from(
+        location: RepositoryLocation,
+        keys: &dyn KeyProvider,
+        source: &RepositoryLocation,
+        layer: Option<EncryptionLayer>,
+        share_keys: bool,
+    ) -> Result<Self> {
+        let source_config = Self::read_config(source).await?;
+        let layer = layer.unwrap_or_else(|| source_config.encryption_layer());
+        let config = RepoConfig {
+            transport: Some(Self::transport_from_location(&location)),
+            chunker_polynomial: source_config.chunker_polynomial,
+            kdf_params: crate::KdfParams {
+                salt: crate::KdfParams::default().salt,
+                ..source_config.kdf_params.clone()
+            },
+            encryption: source_config.profile.mode(layer),
+            encryption_layer: Some(layer),
+            chunk_size: source_config.chunk_size,
+            compression_level: source_config.compression_level,
+            hash: source_config.hash,
+            profile: source_config.profile,
+            ..RepoConfig::default()
+        };
+        if !share_keys {
+            return Self::init_with_config(location, keys, config, None).await;
+        }
+
+        if !layer.mode().is_encrypted() || !source_config.encryption.is_encrypted() {
+            return Err(Error::Encryption(
+                "Keys can only be shared between encrypted repositories".to_string(),
+            ));
+        }
+        let source_storage =
+            storage_for_location(&Self::resolve_location(source.clone(), &source_config)).await?;
+        let shared_keys = read_key_files(source_storage.as_ref()).await?;
+        let mut repo = Self::init_with_config(location, keys, config, Some(&shared_keys)).await?;
+
+        for path in [POLICY_PATH, SETTINGS_PATH] {
+            if source_storage.exists(path).await? {
+                let data = source_storage.read(path).await?;
+                repo.storage.write(path, data).await?;
+            }
+        }
+        repo.settings = Self::read_settings(repo.storage.as_ref(), repo.encryptor()?).await?;
+        Ok(repo)
+    }
+
+    /// Initializes an encrypted repository in `storage` instead of storage
+    /// opened from a location, e.g. a wrapper that injects faults in tests.
+    pub async fn init_with_storage(
+        storage: Box<dyn RepositoryStorage>,
+        keys: &dyn KeyProvider,
+    ) -> Result<Self> {
+        let location = storage.location().clone();
+        let config = RepoConfig {
+            transport: Some(Self::transport_from_location(&location)),
+            ..RepoConfig::default()
+        };
+        Self::init_in_storage(location, storage, keys, config, None).await
+    }
+
+    /// Creates the repository described by `config`. The data key is sealed
+    /// with `keys`, or taken from `shared_keys` (key files of another
+    /// repository) if given.
+    async fn init_with_config(
+        location: RepositoryLocation,
+        keys: &dyn KeyProvider,
+        config: RepoConfig,
+        shared_keys: Option<&BTreeMap<String, String>>,
+    ) -> Result<Self> {
         let storage = storage_for_location(&location).await?;
+        Self::init_in_storage(location, storage, keys, config, shared_keys).await
+    }
+
+    async fn init_in_storage(
+        location: RepositoryLocation,
+        storage: Box<dyn RepositoryStorage>,
+        keys: &dyn KeyProvider,
+        config: RepoConfig,
+        shared_keys: Option<&BTreeMap<String, String>>,
+    ) -> Result<Self> {
+        let encryption = config.encryption;
+        config.validate()?;
 
         if storage.exists("config").await? {
             return Err(Error::RepositoryExists {
@@ -80,38 +299,58 @@ impl Repository {
             });
         }
 
+        // Shared keys must open with `keys` before anything is written
+        let shared = shared_keys
+            .map(|shared_keys| Self::open_key_files(shared_keys, keys, &config))
+            .transpose()?;
+
         storage.init().await?;
 
-        let config = RepoConfig {
-            transport: Some(Self::transport_from_location(&location)),
-            ..RepoConfig::default()
-        };
+        // The marker goes first: a backend that drops or mangles writes
+        // fails here, before a key or config that would look valid is left
+        let marker = serde_json::to_string_pretty(&RepositoryMarker::new(&config.id))?;
+        write_verified(storage.as_ref(), MARKER_PATH, Bytes::from(marker)).await?;
+
+        let (key_name, master_key, encryptor) = if let Some((name, key, encryptor)) = shared {
+            for (key_name, data) in shared_keys.into_iter().flatten() {
+                storage
+                    .write(&format!("keys/{}", key_name), Bytes::from(data.clone()))
+                    .await?;
+            }
+            (Some(name), Some(key), encryptor)
+        } else if encryption.is_encrypted() {
+            let master_key = keys.derive_kek(&config.kdf_params, keys.has_keyfile())?;
+
+            let data_key = MasterKey::generate();
+            let encryptor = Encryptor::with_mode(encryption, data_key.as_bytes())?;
 
-        let master_key =
-            MasterKey::derive_from_password(password, &config.kdf_params.salt, &config.kdf_params)?;
+            let key_encryptor = Encryptor::with_mode(encryption, master_key.as_bytes())?;
+            let encrypted_data_key = key_encryptor.encrypt(data_key.as_bytes())?;
 
-        let data_key = MasterKey::generate();
-        let encryptor = Encryptor::new(data_key.as_bytes())?;
+            let key_file = KeyFile::new(
+                encrypted_data_key,
+                config.kdf_params.clone(),
+                keys.has_keyfile(),
+            );
 
-        let key_encryptor = Encryptor::new(master_key.as_bytes())?;
-        let encrypted_data_key = key_encryptor.encrypt(data_key.as_bytes())?;
+            let key_json = serde_json::to_string_pretty(&key_file)?;
+            let key_id = uuid::Uuid::new_v4().to_string();
+            storage
+                .write(&format!("keys/{}", key_id), Bytes::from(key_json))
+                .await?;
 
-        let key_file = KeyFile {
-            encrypted_key: encrypted_data_key,
-            kdf_params: config.kdf_params.clone(),
+            (Some(key_id), Some(master_key), encryptor)
+        } else {
+            (None, None, Encryptor::plaintext())
         };
 
         let config_json = serde_json::to_string_pretty(&config)?;
-        storage.write("config", Bytes::from(config_json)).await?;
-
-        let key_json = serde_json::to_string_pretty(&key_file)?;
-        let key_id = uuid::Uuid::new_v4().to_string();
-        storage
-            .write(&format!("keys/{}", key_id), Bytes::from(key_json))
-            .await?;
+        write_verified(storage.as_ref(), "config", Bytes::from(config_json)).await?;
 
         // Create empty index
         let index = Index::new();
+        let settings = RepoSettings::new();
+        let hasher = ChunkHasher::new(config.hash, encryptor.chunk_id_key())?;
 
         let display_path = PathBuf::from(location.display());
         Ok(Self {
@@ -119,14 +358,20 @@ impl Repository {
             display_path,
             storage,
             config,
-            master_key: Some(master_key),
+            settings,
+            master_key,
+            key_name,
             encryptor: Some(encryptor),
+            hasher,
             index: Arc::new(RwLock::new(index)),
             pack_cache: Arc::new(RwLock::new(LruCache::new(
                 NonZeroUsize::new(DEFAULT_PACK_CACHE_COUNT).unwrap(),
             ))),
             pack_cache_size: Arc::new(RwLock::new(0)),
             max_cache_size: DEFAULT_PACK_CACHE_SIZE,
+            rate_limiter: None,
+            download_limiter: None,
+            bytes_read: AtomicU64::new(0),
         })
     }
 
@@ -144,54 +389,58 @@ impl Repository {
     }
 
     pub async fn open_at_location(location: RepositoryLocation, password: &str) -> Result<Self> {
-        let bootstrap_storage = storage_for_location(&location).await?;
+        Self::open_with_keys(location, &PasswordKey::new(password)).await
+    }
+
+    /// Opens an existing repository, unlocking it with the secrets from `keys`.
+    pub async fn open_with_keys(
+        location: RepositoryLocation,
+        keys: &dyn KeyProvider,
+    ) -> Result<Self> {
+        let config = Self::read_config(&location).await?;
+        let resolved_location = Self::resolve_location(location, &config);
+        let storage = storage_for_location(&resolved_location).await?;
+        Self::open_in_storage(resolved_location, storage, config, keys).await
+    }
 
-        if !bootstrap_storage.exists("config").await? {
+    /// Opens the repository in `storage` instead of storage opened from a
+    /// location, e.g. a wrapper that injects faults in tests.
+    pub async fn open_with_storage(
+        storage: Box<dyn RepositoryStorage>,
+        keys: &dyn KeyProvider,
+    ) -> Result<Self> {
+        let location = storage.location().clone();
+        if !storage.exists("config").await? {
             return Err(Error::RepositoryNotFound {
                 path: location.display(),
             });
         }
+        let config = parse_config(&storage.read("config").await?)?;
+        Self::open_in_storage(location, storage, config, keys).await
+    }
 
-        let config_bytes = bootstrap_storage.read("config").await?;
-        let config_data = str::from_utf8(&config_bytes)
-            .map_err(|e| Error::Other(format!("Invalid repository config encoding: {}", e)))?;
-        let config: RepoConfig = serde_json::from_str(config_data)?;
-
+    async fn open_in_storage(
+        resolved_location: RepositoryLocation,
+        storage: Box<dyn RepositoryStorage>,
+        config: RepoConfig,
+        keys: &dyn KeyProvider,
+    ) -> Result<Self> {
         if config.version != 1 {
             return Err(Error::InvalidFormatVersion {
                 version: config.version,
             });
         }
+        config.validate()?;
 
-        let resolved_location = Self::resolve_location(location, &config);
-        let storage = storage_for_location(&resolved_location).await?;
-
-        let mut key_file = None;
-
-        for key_name in storage.list("keys").await? {
-            let key_data = storage.read(&format!("keys/{}", key_name)).await?;
-            let key_data = str::from_utf8(&key_data)
-                .map_err(|e| Error::Other(format!("Invalid key file encoding: {}", e)))?;
-            if let Ok(kf) = serde_json::from_str::<KeyFile>(key_data) {
-                key_file = Some(kf);
-                break;
-            }
-        }
-
-        let key_file = key_file.ok_or(Error::InvalidPassword)?;
-
-        let master_key = MasterKey::derive_from_password(
-            password,
-            &key_file.kdf_params.salt,
-            &key_file.kdf_params,
-        )?;
-
-        let key_encryptor = Encryptor::new(master_key.as_bytes())?;
-        let data_key = key_encryptor
-            .decrypt(&key_file.encrypted_key)
-            .map_err(|_| Error::InvalidPassword)?;
-
-        let encryptor = Encryptor::new(&data_key)?;
+        let (key_name, master_key, encryptor) = if config.encryption.is_encrypted() {
+            let (key_name, master_key, encryptor) =
+                Self::unlock(storage.as_ref(), keys, &config).await?;
+            tracing::debug!("Unlocked with key {}", key_name);
+            (Some(key_name), Some(master_key), encryptor)
+        } else {
+            Self::check_unencrypted(storage.as_ref(), keys).await?;
+            (None, None, Encryptor::plaintext())
+        };
 
         // Load index (with migration from legacy format if needed)
         let local_path = match &resolved_location {
@@ -204,6 +453,8 @@ impl Repository {
         let index =
             Self::load_or_migrate_index(storage.as_ref(), local_path.as_deref(), &encryptor)
                 .await?;
+        let settings = Self::read_settings(storage.as_ref(), &encryptor).await?;
+        let hasher = ChunkHasher::new(config.hash, encryptor.chunk_id_key())?;
         let display_path = PathBuf::from(resolved_location.display());
 
         Ok(Self {
@@ -211,17 +462,282 @@ impl Repository {
             display_path,
             storage,
             config,
-            master_key: Some(master_key),
+            settings,
+            master_key,
+            key_name,
             encryptor: Some(encryptor),
+            hasher,
             index: Arc::new(RwLock::new(index)),
             pack_cache: Arc::new(RwLock::new(LruCache::new(
                 NonZeroUsize::new(DEFAULT_PACK_CACHE_COUNT).unwrap(),
             ))),
             pack_cache_size: Arc::new(RwLock::new(0)),
             max_cache_size: DEFAULT_PACK_CACHE_SIZE,
+            rate_limiter: None,
+            download_limiter: None,
+            bytes_read: AtomicU64::new(0),
+        })
+    }
+
+    /// Refuses to open a repository as unencrypted on the word of its
+    /// plaintext config alone. Anyone with write access to the storage could
+    /// flip `encryption` to `none` so that new backups are written in the
+    /// clear; key files mean the config was not written by
+    /// `init --insecure-no-encryption`. A supplied password is ignored, since
+    /// jobs and copy targets always pass one.
+    async fn check_unencrypted(
+        storage: &dyn RepositoryStorage,
+        keys: &dyn KeyProvider,
+    ) -> Result<()> {
+        let key_files = list_objects(storage, "keys").await?;
+        if !key_files.is_empty() {
+            return Err(Error::Encryption(format!(
+                "Repository config says it is unencrypted, but keys/ holds {} key file(s); \
+                 refusing to open it without encryption",
+                key_files.len()
+            )));
+        }
+        if keys.has_secret() {
+            tracing::debug!("Repository is not encrypted; ignoring password");
+        }
+        Ok(())
+    }
+
+    /// Reads the repository config without unlocking the repository, e.g. to
+    /// find out whether a password is needed.
+    pub async fn read_config(location: &RepositoryLocation) -> Result<RepoConfig> {
+        let storage = storage_for_location(location).await?;
+
+        if !storage.exists("config").await? {
+            return Err(Error::RepositoryNotFound {
+                path: location.display(),
+            });
+        }
+
+        parse_config(&storage.read("config").await?)
+    }
+
+    /// Derives the master key from `keys` and decrypts the data key.
+    ///
+    /// Every key file is tried: the secrets may only open a key added later,
+    /// e.g. with a recovery code.
+    async fn unlock(
+        storage: &dyn RepositoryStorage,
+        keys: &dyn KeyProvider,
+        config: &RepoConfig,
+    ) -> Result<(String, MasterKey, Encryptor)> {
+        let mut key_files = BTreeMap::new();
+        for key_name in list_objects(storage, "keys").await? {
+            let data = storage.read(&format!("keys/{}", key_name)).await?;
+            key_files.insert(key_name, data);
+        }
+        Self::open_key_files(&key_files, keys, config)
+    }
+
+    /// Opens the data key in the first of `key_files` that the secrets from
+    /// `keys` open, returning that key file's name. Key files are tried in
+    /// name order, so the same one wins every time.
+    ///
+    /// Fails with [`Error::InvalidPassword`] if the secrets were tried on at
+    /// least one key file, and with [`Error::CorruptKeyFile`] if no key file
+    /// was readable, so that damage isn't mistaken for a wrong password.
+    fn open_key_files(
+        key_files: &BTreeMap<String, impl AsRef<[u8]>>,
+        keys: &dyn KeyProvider,
+        config: &RepoConfig,
+    ) -> Result<(String, MasterKey, Encryptor)> {
+        let mut keyfile_required = false;
+        let mut rejected = false;
+        let mut corrupt = None;
+
+        for (name, data) in key_files {
+            match Self::try_key_file(data.as_ref(), keys, config) {
+                Ok(KeyAttempt::Opened(opened)) => {
+                    let (master_key, encryptor) = *opened;
+                    return Ok((name.clone(), master_key, encryptor));
+                }
+                Ok(KeyAttempt::Rejected) => rejected = true,
+                Ok(KeyAttempt::Corrupt(reason)) => {
+                    tracing::warn!("Skipping key file {}: {}", name, reason);
+                    corrupt.get_or_insert_with(|| (name.clone(), reason));
+                }
+                Err(Error::KeyfileRequired) => keyfile_required = true,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(match corrupt {
+            _ if keyfile_required => Error::KeyfileRequired,
+            _ if rejected => Error::InvalidPassword,
+            Some((name, reason)) => Error::CorruptKeyFile { name, reason },
+            None => Error::Encryption("Repository has no key files".to_string()),
         })
     }
 
+    /// Tries the secrets from `keys` on one key file. Under the FIPS profile,
+    /// key files derived with another KDF are refused.
+    fn try_key_file(
+        key_data: &[u8],
+        keys: &dyn KeyProvider,
+        config: &RepoConfig,
+    ) -> Result<KeyAttempt> {
+        let Ok(key_data) = str::from_utf8(key_data) else {
+            return Ok(KeyAttempt::Corrupt("not UTF-8".to_string()));
+        };
+        let key_file = match serde_json::from_str::<KeyFile>(key_data) {
+            Ok(key_file) => key_file,
+            Err(e) => return Ok(KeyAttempt::Corrupt(format!("not a key file: {}", e))),
+        };
+        if config.profile == CryptoProfile::Fips
+            && key_file.kdf_params.algorithm != KDF_PBKDF2_SHA256
+        {
+            return Err(Error::Encryption(format!(
+                "Key file uses {}, which the fips profile does not allow",
+                key_file.kdf_params.algorithm
+            )));
+        }
+        if key_file.encrypted_key.len() < SEALED_KEY_LEN {
+            return Ok(KeyAttempt::Corrupt("sealed key is truncated".to_string()));
+        }
+
+        let mode = config.encryption;
+        let master_key = match keys.derive_kek(&key_file.kdf_params, key_file.keyfile) {
+            Ok(master_key) => master_key,
+            Err(Error::Encryption(reason)) => return Ok(KeyAttempt::Corrupt(reason)),
+            Err(e) => return Err(e),
+        };
+        let key_encryptor = Encryptor::with_mode(mode, master_key.as_bytes())?;
+        // An authentication failure can't tell a wrong password from a
+        // damaged sealed key; only the former is likely
+        let Ok(data_key) = key_encryptor.decrypt(&key_file.encrypted_key) else {
+            return Ok(KeyAttempt::Rejected);
+        };
+        match Encryptor::with_mode(mode, &data_key) {
+            Ok(encryptor) => Ok(KeyAttempt::Opened(Box::new((master_key, encryptor)))),
+            Err(_) => Ok(KeyAttempt::Corrupt(format!(
+                "data key is {} bytes",
+                data_key.len()
+            ))),
+        }
+    }
+
+    /// Exports the config and the sealed key files for escrow.
+    pub async fn export_keys(&self) -> Result<KeyExport> {
+        let keys = read_key_files(self.storage.as_ref()).await?;
+        Ok(KeyExport::new(self.config.clone(), keys))
+    }
+
+    /// Returns the unsealed data key as a [`RecoveryCode`].
+    pub async fn recovery_code(&self) -> Result<RecoveryCode> {
+        let master_key = self.master_key.as_ref().ok_or_else(|| {
+            Error::Encryption("Repository is not encrypted and has no key".to_string())
+        })?;
+        let key_encryptor = Encryptor::with_mode(self.config.encryption, master_key.as_bytes())?;
+
+        for key_name in list_objects(self.storage.as_ref(), "keys").await? {
+            let data = self.storage.read(&format!("keys/{}", key_name)).await?;
+            let Ok(key_file) = serde_json::from_slice::<KeyFile>(&data) else {
+                continue;
+            };
+            if let Ok(data_key) = key_encryptor.decrypt(&key_file.encrypted_key) {
+                return RecoveryCode::from_key(&data_key);
+            }
+        }
+        Err(Error::InvalidPassword)
+    }
+
+    /// Restores the config and key files of `export` at `location`.
+    ///
+    /// A missing config is written from the export; an existing one must
+    /// belong to the same repository. Key files that exist are kept. Returns
+    /// the number of key files written.
+    pub async fn import_keys(location: &RepositoryLocation, export: &KeyExport) -> Result<usize> {
+        let storage = storage_for_location(location).await?;
+
+        if storage.exists("config").await? {
+            let config = Self::read_config(location).await?;
+            if config.id != export.config.id {
+                return Err(Error::Other(format!(
+                    "Key export belongs to repository {}, not {}",
+                    export.config.id, config.id
+                )));
+            }
+        } else {
+            storage.init().await?;
+            let config_json = serde_json::to_string_pretty(&export.config)?;
+            write_verified(storage.as_ref(), "config", Bytes::from(config_json)).await?;
+        }
+
+        let mut written = 0;
+        for (key_name, data) in &export.keys {
+            let path = format!("keys/{}", key_name);
+            if storage.exists(&path).await? {
+                continue;
+            }
+            write_verified(storage.as_ref(), &path, Bytes::from(data.clone())).await?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// Seals the data key from `code` with the secrets from `keys` and stores
+    /// it as a new key file, returning its name.
+    ///
+    /// Nothing is written unless the code decrypts the repository's index or
+    /// a snapshot. An empty repository can't be checked.
+    pub async fn add_key_from_recovery_code(
+        location: &RepositoryLocation,
+        code: &RecoveryCode,
+        keys: &dyn KeyProvider,
+    ) -> Result<String> {
+        let config = Self::read_config(location).await?;
+        if !config.encryption.is_encrypted() {
+            return Err(Error::Encryption(
+                "Repository is not encrypted and has no key".to_string(),
+            ));
+        }
+        let location = Self::resolve_location(location.clone(), &config);
+        let storage = storage_for_location(&location).await?;
+
+        let encryptor = Encryptor::with_mode(config.encryption, code.key())?;
+        let sample = if storage.exists("index/main.idx").await? {
+            Some("index/main.idx".to_string())
+        } else {
+            list_objects(storage.as_ref(), "snapshots")
+                .await?
+                .first()
+                .map(|id| format!("snapshots/{}", id))
+        };
+        if let Some(path) = sample {
+            let data = storage.read(&path).await?;
+            encryptor
+                .decrypt(&data)
+                .map_err(|_| Error::InvalidRecoveryCode(format!("it does not decrypt {}", path)))?;
+        }
+
+        // Same cost as the repository's other keys, fresh salt
+        let kdf_params = crate::KdfParams {
+            salt: crate::KdfParams::default().salt,
+            ..config.kdf_params
+        };
+        let master_key = keys.derive_kek(&kdf_params, keys.has_keyfile())?;
+        let key_file = KeyFile::new(
+            Encryptor::with_mode(config.encryption, master_key.as_bytes())?.encrypt(code.key())?,
+            kdf_params,
+            keys.has_keyfile(),
+        );
+
+        let key_name = uuid::Uuid::new_v4().to_string();
+        let key_json = serde_json::to_string_pretty(&key_file)?;
+        write_verified(
+            storage.as_ref(),
+            &format!("keys/{}", key_name),
+            Bytes::from(key_json),
+        )
+        .await?;
+        Ok(key_name)
+    }
+
     /// Loads the consolidated index or migrates from legacy format.
     async fn load_or_migrate_index(
         storage: &dyn RepositoryStorage,
@@ -294,6 +810,19 @@ impl Repository {
         }
     }
 
+    /// Probes what the storage credential allows. See [`capability::probe`].
+    pub async fn capabilities(&self) -> Capabilities {
+        capability::probe(self.storage.as_ref()).await
+    }
+
+    /// Probes the storage credential and fails early if it lacks the
+    /// `access` that `operation` needs.
+    pub async fn require_access(&self, access: Access, operation: &str) -> Result<Capabilities> {
+        let capabilities = self.capabilities().await;
+        capabilities.require(access, operation)?;
+        Ok(capabilities)
+    }
+
     fn transport_from_location(location: &RepositoryLocation) -> RepoTransport {
         match location {
             RepositoryLocation::Local(_) => RepoTransport::Local,
@@ -303,11 +832,14 @@ impl Repository {
                 endpoint: s3.endpoint.clone(),
                 region: s3.region.clone(),
                 sse: s3.sse.clone(),
+                storage_classes: s3.storage_classes.clone(),
+                checksum: s3.checksum,
             }),
             RepositoryLocation::Azure(azure) => RepoTransport::Azure(AzureRepoTransport {
                 account_name: azure.account_name.clone(),
                 container: azure.container.clone(),
                 prefix: azure.prefix.clone(),
+                access_tiers: azure.access_tiers.clone(),
             }),
             RepositoryLocation::Rclone(rclone) => RepoTransport::Rclone(RcloneRepoTransport {
                 remote: rclone.remote.clone(),
@@ -342,6 +874,12 @@ impl Repository {
                 if location.sse.is_none() {
                     location.sse = stored.sse.clone();
                 }
+                if location.storage_classes.is_empty() {
+                    location.storage_classes = stored.storage_classes.clone();
+                }
+                if location.checksum.is_default() {
+                    location.checksum = stored.checksum;
+                }
                 RepositoryLocation::S3(location)
             }
             (RepositoryLocation::S3(location), _) => RepositoryLocation::S3(location),
@@ -355,6 +893,9 @@ impl Repository {
                 if location.prefix.is_empty() {
                     location.prefix = stored.prefix.clone();
                 }
+                if location.access_tiers.is_empty() {
+                    location.access_tiers = stored.access_tiers.clone();
+                }
                 RepositoryLocation::Azure(location)
             }
             (RepositoryLocation::Azure(location), _) => RepositoryLocation::Azure(location),
@@ -402,17 +943,97 @@ impl Repository {
             endpoint: location.endpoint.clone(),
             region: location.region.clone(),
             sse,
+            storage_classes: location.storage_classes.clone(),
+            checksum: location.checksum,
         }));
 
-        let config_json = serde_json::to_string_pretty(&self.config)?;
+        self.write_config().await
+    }
+
+    pub fn azure_transport(&self) -> Option<&AzureRepoTransport> {
+        match self.config.transport.as_ref() {
+            Some(RepoTransport::Azure(config)) => Some(config),
+            _ => None,
+        }
+    }
+
+    /// Stores the access tier rules applied to blobs written from now on.
+    ///
+    /// Takes effect the next time the repository is opened.
+    pub async fn set_azure_access_tiers(&mut self, access_tiers: AzureAccessTiers) -> Result<()> {
+        match self.config.transport.as_mut() {
+            Some(RepoTransport::Azure(config)) => config.access_tiers = access_tiers,
+            _ => {
+                return Err(Error::Backend(format!(
+                    "Access tiers are only supported for Azure repositories, not {}",
+                    self.location.display()
+                )));
+            }
+        }
+
+        self.write_config().await
+    }
+
+    /// Access tier of a pack file; `None` when the storage has no tiering.
+    pub async fn pack_access_tier(&self, pack_id: &PackID) -> Result<Option<TierStatus>> {
         self.storage
-            .write("config", Bytes::from(config_json))
-            .await?;
-        Ok(())
+            .access_tier(&format!("data/{}.pack", pack_id))
+            .await
+    }
+
+    /// Moves a pack file to another access tier.
+    pub async fn set_pack_access_tier(
+        &self,
+        pack_id: &PackID,
+        tier: AccessTier,
+        priority: RehydratePriority,
+    ) -> Result<()> {
+        self.storage
+            .set_access_tier(&format!("data/{}.pack", pack_id), tier, priority)
+            .await
+    }
+
+    /// Makes sure the given packs can be read, starting a rehydration for
+    /// every archived pack that is not already rehydrating.
+    ///
+    /// Packs are rehydrated to the configured data tier when that is online,
+    /// otherwise to Cool. Returns an empty report when the storage has no
+    /// tiering.
+    pub async fn rehydrate_packs(
+        &self,
+        pack_ids: &[PackID],
+        priority: RehydratePriority,
+    ) -> Result<RehydrationReport> {
+        let target = self
+            .azure_transport()
+            .and_then(|config| config.access_tiers.data)
+            .filter(|tier| tier.is_online())
+            .unwrap_or(AccessTier::Cool);
+
+        let mut report = RehydrationReport::default();
+        for pack_id in pack_ids {
+            let Some(status) = self.pack_access_tier(pack_id).await? else {
+                return Ok(RehydrationReport::default());
+            };
+            if status.is_readable() {
+                continue;
+            }
+            if status.rehydrating_to.is_some() {
+                report.pending.push(pack_id.clone());
+            } else {
+                self.set_pack_access_tier(pack_id, target, priority).await?;
+                report.requested.push(pack_id.clone());
+            }
+        }
+        Ok(report)
     }
 
     pub async fn object_size(&self, path: &str) -> Result<u64> {
-        Ok(self.storage.metadata(path).await?.size)
+        Ok(self.object_metadata(path).await?.size)
+    }
+
+    pub async fn object_metadata(&self, path: &str) -> Result<ObjectMetadata> {
+        self.storage.metadata(path).await
     }
 
     pub async fn pack_size(&self, pack_id: &PackID) -> Result<u64> {
@@ -423,16 +1044,166 @@ impl Repository {
         self.storage.exists(&format!("data/{}.pack", pack_id)).await
     }
 
+    /// Chunker for new backups, with this repository's boundaries and
+    /// chunk IDs.
+    pub fn chunker(&self) -> Chunker {
+        Chunker::for_config(&self.config).with_hasher(self.hasher.clone())
+    }
+
+    /// Computes chunk and tree IDs with this repository's algorithm.
+    pub fn hasher(&self) -> &ChunkHasher {
+        &self.hasher
+    }
+
+    /// The ID `data` is stored under in this repository.
+    pub fn chunk_id(&self, data: &[u8]) -> ChunkID {
+        self.hasher.hash(data)
+    }
+
+    /// Pack manager for new packs, compressing at the level from the
+    /// settings or, if unset, the config.
+    pub fn pack_manager(&self, max_pack_size: u64) -> PackManager {
+        let manager = PackManager::new(max_pack_size);
+        let level = self.settings.compression_level;
+        match level.or(self.config.compression_level) {
+            Some(level) => manager.with_compression_level(level),
+            None => manager,
+        }
+    }
+
+    /// Sets the average chunk size of new backups, or the default for `None`.
+    ///
+    /// Chunks of existing snapshots keep their size, so data backed up
+    /// before the change no longer deduplicates against new backups.
+    pub async fn set_chunk_size(&mut self, chunk_size: Option<u32>) -> Result<()> {
+        let config = RepoConfig {
+            chunk_size,
+            ..self.config.clone()
+        };
+        config.validate()?;
+        self.config = config;
+        self.write_config().await
+    }
+
+    /// Sets the zlib level of new packs, or the default for `None`.
+    pub async fn set_compression_level(&mut self, level: Option<u32>) -> Result<()> {
+        let config = RepoConfig {
+            compression_level: level,
+            ..self.config.clone()
+        };
+        config.validate()?;
+        self.config = config;
+        self.write_config().await
+    }
+
+    async fn write_config(&self) -> Result<()> {
+        let config_json = serde_json::to_string_pretty(&self.config)?;
+        self.storage.write("config", Bytes::from(config_json)).await
+    }
+
     pub fn config(&self) -> &RepoConfig {
         &self.config
     }
 
+    pub fn encryption_mode(&self) -> EncryptionMode {
+        self.config.encryption
+    }
+
+    pub fn encryption_layer(&self) -> EncryptionLayer {
+        self.config.encryption_layer()
+    }
+
+    /// Describes the at-rest encryption the backend provides, if it can be
+    /// detected: S3 server-side encryption configured for the repository,
+    /// Azure Storage encryption (always on), or a local path on a dm-crypt
+    /// volume. `None` means no backend encryption was found, not that there
+    /// is none.
+    pub fn backend_encryption(&self) -> Option<String> {
+        match &self.location {
+            RepositoryLocation::S3(s3) => {
+                let stored = match &self.config.transport {
+                    Some(RepoTransport::S3(transport)) => transport.sse.as_ref(),
+                    _ => None,
+                };
+                s3.sse
+                    .as_ref()
+                    .or(stored)
+                    .map(|sse| format!("S3 server-side encryption ({})", sse.mode))
+            }
+            RepositoryLocation::Azure(_) => Some("Azure Storage Service Encryption".to_string()),
+            RepositoryLocation::Local(path) => local_volume_encryption(path),
+            RepositoryLocation::Rclone(_) | RepositoryLocation::Sftp(_) => None,
+        }
+    }
+
     pub fn encryptor(&self) -> Result<&Encryptor> {
         self.encryptor
             .as_ref()
             .ok_or_else(|| Error::Other("Repository not unlocked".to_string()))
     }
 
+    /// Name of the key file that unlocked the repository, or `None` if it
+    /// isn't encrypted.
+    pub fn key_name(&self) -> Option<&str> {
+        self.key_name.as_deref()
+    }
+
+    /// Describes every key file, in name order. Key files that can't be
+    /// parsed are skipped with a warning.
+    pub async fn list_keys(&self) -> Result<Vec<KeyInfo>> {
+        let mut keys = Vec::new();
+        for (name, data) in read_key_files(self.storage.as_ref()).await? {
+            let key_file: KeyFile = match serde_json::from_str(&data) {
+                Ok(key_file) => key_file,
+                Err(e) => {
+                    tracing::warn!("Skipping corrupt key file {}: {}", name, e);
+                    continue;
+                }
+            };
+            keys.push(KeyInfo {
+                current: self.key_name.as_deref() == Some(name.as_str()),
+                name,
+                created_at: key_file.created_at,
+                hostname: key_file.hostname,
+                username: key_file.username,
+                kdf: key_file.kdf_params.algorithm,
+                keyfile: key_file.keyfile,
+            });
+        }
+        Ok(keys)
+    }
+
+    /// Sets the limiter used to throttle pack and tree uploads.
+    pub fn set_rate_limiter(&mut self, limiter: Option<Arc<RateLimiter>>) {
+        self.rate_limiter = limiter;
+    }
+
+    async fn throttle_upload(&self, bytes: usize) {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.throttle(bytes).await;
+        }
+    }
+
+    /// Sets the limiter used to throttle snapshot, tree and pack reads.
+    pub fn set_download_limiter(&mut self, limiter: Option<Arc<RateLimiter>>) {
+        self.download_limiter = limiter;
+    }
+
+    /// Counts `bytes` just read and, when limited, waits until reading them
+    /// fits the download limit.
+    async fn throttle_download(&self, bytes: usize) {
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+        if let Some(limiter) = &self.download_limiter {
+            limiter.throttle(bytes).await;
+        }
+    }
+
+    /// Bytes of snapshots, trees and packs read from storage since the
+    /// repository was opened (pack cache hits excluded).
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
     /// Returns a clone of the index Arc for shared access.
     pub fn index(&self) -> Arc<RwLock<Index>> {
         Arc::clone(&self.index)
@@ -471,7 +1242,8 @@ impl Repository {
         let data = snapshot.serialize(encryptor)?;
         self.storage
             .write(&format!("snapshots/{}", snapshot.id), data)
-            .await?;
+            .await
+            .op_context("save snapshot", &snapshot.id)?;
         Ok(())
     }
 
@@ -480,12 +1252,19 @@ impl Repository {
         let data = self
             .storage
             .read(&format!("snapshots/{}", snapshot_id))
-            .await?;
-        Snapshot::deserialize(&data, encryptor)
+            .await
+            .op_context("read snapshot", snapshot_id)?;
+        self.throttle_download(data.len()).await;
+        Snapshot::deserialize(&data, encryptor).op_context("decode snapshot", snapshot_id)
     }
 
     pub async fn list_snapshots(&self) -> Result<Vec<SnapshotID>> {
-        let mut snapshot_ids = self.storage.list("snapshots").await?;
+        // Foreign objects are filtered out, so every name is a snapshot ID
+        let mut snapshot_ids: Vec<SnapshotID> = list_objects(self.storage.as_ref(), "snapshots")
+            .await?
+            .iter()
+            .filter_map(|name| name.parse().ok())
+            .collect();
         snapshot_ids.sort();
         Ok(snapshot_ids)
     }
@@ -494,35 +1273,168 @@ impl Repository {
     pub async fn delete_snapshot(&self, snapshot_id: &SnapshotID) -> Result<()> {
         self.storage
             .delete(&format!("snapshots/{}", snapshot_id))
-            .await?;
+            .await
+            .op_context("delete snapshot", snapshot_id)?;
         Ok(())
     }
 
+    /// Saves a tree under its content-addressed ID.
+    ///
+    /// The ID is the hash of the plaintext tree, so a tree that is identical
+    /// to one already stored is not written or uploaded again. Directories
+    /// with at least [`SUBTREE_MIN_NODES`] nodes below them are saved first,
+    /// as subtrees, so an unchanged large directory is deduplicated on its
+    /// own. Tree objects with more than [`TREE_PAGE_NODES`] nodes are stored
+    /// as pages, each deduplicated the same way.
     pub async fn save_tree(&self, tree: &Tree) -> Result<ChunkID> {
-        let encryptor = self.encryptor()?;
-        let data = tree.serialize(encryptor)?;
-        let tree_id = ChunkID::from_data(&data);
-        self.storage
-            .write(&format!("data/{}", tree_id.to_hex()), data)
-            .await?;
-        Ok(tree_id)
+        self.save_tree_with(tree, SUBTREE_MIN_NODES).await
     }
 
-    pub async fn load_tree(&self, tree_id: &ChunkID) -> Result<Tree> {
-        let encryptor = self.encryptor()?;
-        let data = self
-            .storage
-            .read(&format!("data/{}", tree_id.to_hex()))
-            .await?;
-        Tree::deserialize(&data, encryptor)
+    /// [`Self::save_tree`], splitting off subtrees of `min_nodes` nodes.
+    pub async fn save_tree_with(&self, tree: &Tree, min_nodes: usize) -> Result<ChunkID> {
+        let mut levels = tree.split_subtrees(min_nodes);
+        let subtrees = levels.len() - 1;
+        let mut root_id = None;
+        for i in 0..levels.len() {
+            let id = self.save_tree_level(&levels[i].tree).await?;
+            match levels[i].parent {
+                Some((level, node)) => levels[level].tree.nodes[node].subtree_id = Some(id),
+                None => root_id = Some(id),
+            }
+        }
+        if subtrees > 0 {
+            tracing::debug!(
+                "Stored tree of {} nodes with {} subtrees",
+                tree.nodes.len(),
+                subtrees
+            );
+        }
+        root_id.ok_or_else(|| Error::Other("Tree has no root level".to_string()))
+    }
+
+    /// Saves one tree object, as pages if it is large.
+    async fn save_tree_level(&self, tree: &Tree) -> Result<ChunkID> {
+        if tree.nodes.len() <= TREE_PAGE_NODES {
+            return self.save_tree_object(tree).await;
+        }
+
+        let mut root = Tree::new();
+        for page in tree.split_pages(TREE_PAGE_NODES) {
+            let (Some(first), Some(last)) = (page.nodes.first(), page.nodes.last()) else {
+                continue;
+            };
+            root.pages.push(TreePage {
+                first: first.name.clone(),
+                last: last.name.clone(),
+                nodes: page.nodes.len(),
+                tree: self.save_tree_object(&page).await?,
+            });
+        }
+        tracing::debug!(
+            "Stored tree of {} nodes as {} pages",
+            tree.nodes.len(),
+            root.pages.len()
+        );
+        self.save_tree_object(&root).await
+    }
+
+    async fn save_tree_object(&self, tree: &Tree) -> Result<ChunkID> {
+        let encryptor = self.encryptor()?;
+        let plain = tree.to_bytes()?;
+        let tree_id = self.chunk_id(&plain);
+        let path = format!("data/{}", tree_id.to_hex());
+        if self
+            .storage
+            .exists(&path)
+            .await
+            .op_context("check tree", tree_id)?
+        {
+            tracing::debug!("Tree {} already stored", tree_id.short_string());
+            return Ok(tree_id);
+        }
+
+        let data = Bytes::from(encryptor.encrypt(&plain)?);
+        self.throttle_upload(data.len()).await;
+        self.storage
+            .write(&path, data)
+            .await
+            .op_context("save tree", tree_id)?;
+        Ok(tree_id)
+    }
+
+    /// Loads a tree with all its nodes, reading every page and subtree.
+    pub async fn load_tree(&self, tree_id: &ChunkID) -> Result<Tree> {
+        self.load_tree_paths(tree_id, &[]).await
+    }
+
+    /// Loads the nodes at or below `paths` and the directories leading to
+    /// them, or the whole tree if no paths are given. Only the pages and
+    /// subtrees that may hold such nodes are read, so the result may hold
+    /// other nodes as well.
+    pub async fn load_tree_paths(&self, tree_id: &ChunkID, paths: &[String]) -> Result<Tree> {
+        let mut pages = TreePages::new(self, tree_id, paths);
+        let mut tree = Tree::new();
+        while let Some(page) = pages.next_page().await? {
+            tree.nodes.extend(page.nodes);
+        }
+        Ok(tree)
+    }
+
+    /// Every tree object a tree is stored as: the root, its pages and its
+    /// subtrees.
+    pub async fn tree_objects(&self, tree_id: &ChunkID) -> Result<Vec<ChunkID>> {
+        let mut objects = Vec::new();
+        let mut pending = vec![*tree_id];
+        while let Some(id) = pending.pop() {
+            let tree = self.load_tree_root(&id).await?;
+            objects.push(id);
+            pending.extend(tree.pages.iter().map(|page| page.tree));
+            pending.extend(tree.nodes.iter().filter_map(|node| node.subtree_id));
+        }
+        Ok(objects)
+    }
+
+    /// Loads a tree object as stored: for a paged tree, only the list of
+    /// pages, and for a directory stored as a subtree, only its node.
+    pub async fn load_tree_root(&self, tree_id: &ChunkID) -> Result<Tree> {
+        let encryptor = self.encryptor()?;
+        let data = self
+            .storage
+            .read(&format!("data/{}", tree_id.to_hex()))
+            .await
+            .op_context("read tree", tree_id)?;
+        self.throttle_download(data.len()).await;
+        Tree::deserialize(&data, encryptor).op_context("decode tree", tree_id)
+    }
+
+    /// Reads a tree one tree object at a time, skipping pages and subtrees
+    /// that hold no node at or below `prefix` or directory leading to it.
+    /// Pages of one tree object are read in name order.
+    pub async fn tree_pages(&self, tree_id: &ChunkID, prefix: &str) -> Result<TreePages<'_>> {
+        let paths: &[String] = if prefix.is_empty() {
+            &[]
+        } else {
+            &[prefix.to_string()]
+        };
+        Ok(TreePages::new(self, tree_id, paths))
     }
 
     pub async fn save_pack(&self, pack: &PackFile) -> Result<()> {
+        let path = format!("data/{}.pack", pack.header.pack_id);
+        // Replacing a pack would lose every chunk the index places in it
+        if self.storage.exists(&path).await? {
+            return Err(Error::PackExists {
+                id: pack.header.pack_id.to_string(),
+            });
+        }
+
         let encryptor = self.encryptor()?;
         let bytes = pack.to_encrypted_bytes(encryptor)?;
+        self.throttle_upload(bytes.len()).await;
         self.storage
-            .write(&format!("data/{}.pack", pack.header.pack_id), bytes.into())
-            .await?;
+            .write(&path, bytes.into())
+            .await
+            .op_context("save pack", &pack.header.pack_id)?;
 
         // Invalidate cache entry if it exists
         {
@@ -544,6 +1456,52 @@ impl Repository {
         Ok(())
     }
 
+    /// Reads and decrypts a pack to verify it, bypassing the pack cache, and
+    /// checks its header (see [`PackFile::check_header`]). Returns the
+    /// number of bytes read.
+    pub async fn verify_pack(&self, pack_id: &PackID) -> Result<u64> {
+        let encryptor = self.encryptor()?;
+        let data = self
+            .storage
+            .read(&format!("data/{}.pack", pack_id))
+            .await
+            .op_context("read pack", pack_id)?;
+        self.throttle_download(data.len()).await;
+        let pack =
+            PackFile::from_encrypted_bytes(&data, encryptor).op_context("decode pack", pack_id)?;
+        pack.check_header(pack_id)
+            .op_context("check pack header", pack_id)?;
+        Ok(data.len() as u64)
+    }
+
+    /// Reads a pack, bypassing the pack cache, and returns those of
+    /// `chunk_ids` that it does not hold or whose data does not match their
+    /// hash.
+    pub async fn verify_pack_chunks(
+        &self,
+        pack_id: &PackID,
+        chunk_ids: &[ChunkID],
+    ) -> Result<Vec<ChunkID>> {
+        let encryptor = self.encryptor()?;
+        let data = self
+            .storage
+            .read(&format!("data/{}.pack", pack_id))
+            .await
+            .op_context("read pack", pack_id)?;
+        self.throttle_download(data.len()).await;
+        let pack =
+            PackFile::from_encrypted_bytes(&data, encryptor).op_context("decode pack", pack_id)?;
+        Ok(chunk_ids
+            .iter()
+            .filter(|id| {
+                !pack
+                    .get_chunk(id)
+                    .is_ok_and(|data| self.chunk_id(&data) == **id)
+            })
+            .copied()
+            .collect())
+    }
+
     /// Loads a pack file, using the LRU cache if available.
     pub async fn load_pack(&self, pack_id: &PackID) -> Result<Arc<PackFile>> {
         // Check cache first
@@ -558,8 +1516,14 @@ impl Repository {
         // Cache miss - load from disk
         tracing::debug!("Pack cache miss: {}", pack_id);
         let encryptor = self.encryptor()?;
-        let data = self.storage.read(&format!("data/{}.pack", pack_id)).await?;
-        let pack = PackFile::from_encrypted_bytes(&data, encryptor)?;
+        let data = self
+            .storage
+            .read(&format!("data/{}.pack", pack_id))
+            .await
+            .op_context("read pack", pack_id)?;
+        self.throttle_download(data.len()).await;
+        let pack =
+            PackFile::from_encrypted_bytes(&data, encryptor).op_context("decode pack", pack_id)?;
         let pack_size = pack.size();
         let pack = Arc::new(pack);
 
@@ -583,14 +1547,26 @@ impl Repository {
         Ok(pack)
     }
 
+    /// Moves objects of repositories created before sharding into the
+    /// fan-out layout, returning how many were moved. Callers hold the
+    /// exclusive lock; in dry-run mode nothing is moved.
+    pub async fn migrate_layout(&self) -> Result<usize> {
+        if crate::dry_run::is_enabled() {
+            return Ok(0);
+        }
+        self.storage.migrate_layout().await
+    }
+
     /// Lists all pack files in the repository.
     pub async fn list_packs(&self) -> Result<Vec<PackID>> {
-        let entries = self.storage.list("data").await?;
+        let entries = list_objects(self.storage.as_ref(), "data").await?;
         let mut pack_ids = Vec::new();
 
         for name in entries {
-            if name.ends_with(".pack") {
-                pack_ids.push(name.trim_end_matches(".pack").to_string());
+            if let Some(pack_id) = name.strip_suffix(".pack")
+                && let Ok(pack_id) = pack_id.parse()
+            {
+                pack_ids.push(pack_id);
             }
         }
 
@@ -610,7 +1586,8 @@ impl Repository {
 
         self.storage
             .delete(&format!("data/{}.pack", pack_id))
-            .await?;
+            .await
+            .op_context("delete pack", pack_id)?;
 
         // Remove from index
         let mut index = self.index.write().await;
@@ -619,6 +1596,55 @@ impl Repository {
         Ok(())
     }
 
+    /// Deletes a damaged pack and drops the chunks the index places in it,
+    /// so that the next backup of the same data uploads them again. Returns
+    /// the number of chunks dropped; snapshots using them can't be restored
+    /// completely until then.
+    pub async fn remove_damaged_pack(&self, pack_id: &PackID) -> Result<usize> {
+        let chunk_ids = self.index.read().await.chunks_in_pack(pack_id);
+        self.delete_pack(pack_id).await?;
+        {
+            let mut index = self.index.write().await;
+            for chunk_id in &chunk_ids {
+                index.remove_chunk(chunk_id);
+            }
+        }
+        self.save_index().await?;
+        Ok(chunk_ids.len())
+    }
+
+    /// Reads the repository marker. `None` for repositories created before
+    /// the marker existed.
+    pub async fn marker(&self) -> Result<Option<RepositoryMarker>> {
+        if !self.storage.exists(MARKER_PATH).await? {
+            return Ok(None);
+        }
+        let data = self.storage.read(MARKER_PATH).await?;
+        Ok(Some(serde_json::from_slice(&data)?))
+    }
+
+    /// Lists objects at the repository root and under its typed prefixes
+    /// that ghostsnap did not write (see [`layout::classify`]).
+    ///
+    /// Repository operations ignore these objects; maintenance commands
+    /// report them so they can be moved elsewhere.
+    pub async fn foreign_objects(&self) -> Result<Vec<String>> {
+        let mut foreign = Vec::new();
+        for prefix in std::iter::once("").chain(REPOSITORY_PREFIXES.iter().copied()) {
+            for name in self.storage.list(prefix).await? {
+                if !layout::is_repository_entry(prefix, &name) {
+                    foreign.push(if prefix.is_empty() {
+                        name
+                    } else {
+                        format!("{}/{}", prefix, name)
+                    });
+                }
+            }
+        }
+        foreign.sort();
+        Ok(foreign)
+    }
+
     /// Checks if a chunk exists using the in-memory index with bloom filter.
     /// This is O(1) for chunks that don't exist (bloom filter) and O(1) amortized
     /// for chunks that do exist (HashMap lookup).
@@ -627,6 +1653,36 @@ impl Repository {
         Ok(index.has_chunk(chunk_id))
     }
 
+    /// Checks many chunks at once, returning one flag per input ID.
+    ///
+    /// Storage that keeps its own chunk index answers in one round trip;
+    /// chunks saved by this process but not yet known to the storage still
+    /// count from the in-memory index. Other storage is answered from the
+    /// in-memory index alone, under a single lock, so callers deduplicating a
+    /// whole file pay for one lock acquisition instead of one per chunk.
+    pub async fn has_chunks(&self, chunk_ids: &[ChunkID]) -> Result<Vec<bool>> {
+        let remote = self.storage.has_chunks(chunk_ids).await?;
+        if let Some(flags) = &remote
+            && flags.len() != chunk_ids.len()
+        {
+            return Err(Error::Backend(format!(
+                "Storage answered {} chunk lookups for {} chunks",
+                flags.len(),
+                chunk_ids.len()
+            )));
+        }
+
+        let index = self.index.read().await;
+        Ok(match remote {
+            Some(flags) => chunk_ids
+                .iter()
+                .zip(flags)
+                .map(|(id, known)| known || index.has_chunk(id))
+                .collect(),
+            None => chunk_ids.iter().map(|id| index.has_chunk(id)).collect(),
+        })
+    }
+
     /// Adds a chunk location to the index.
     pub async fn save_chunk_location(
         &self,
@@ -652,59 +1708,960 @@ impl Repository {
         let index = self.index.read().await;
         index
             .get_chunk(chunk_id)
-            .cloned()
             .ok_or_else(|| Error::ChunkNotFound {
                 id: chunk_id.to_hex(),
             })
     }
 
-    /// Loads a chunk's data by looking up its location and reading from the pack.
-    pub async fn load_chunk(&self, chunk_id: &ChunkID) -> Result<Bytes> {
-        let location = self.load_chunk_location(chunk_id).await?;
-        let pack = self.load_pack(&location.pack_id).await?;
-        pack.get_chunk(chunk_id)
+    /// Loads a chunk's data by looking up its location and reading from the pack.
+    pub async fn load_chunk(&self, chunk_id: &ChunkID) -> Result<Bytes> {
+        let location = self.load_chunk_location(chunk_id).await?;
+        let pack = self.load_pack(&location.pack_id).await?;
+        pack.get_chunk(chunk_id).op_context(
+            "extract chunk",
+            format!("{} from pack {}", chunk_id, location.pack_id),
+        )
+    }
+
+    /// Returns repository statistics.
+    pub async fn stats(&self) -> RepoStats {
+        let index = self.index.read().await;
+        RepoStats {
+            chunk_count: index.chunk_count(),
+            pack_count: index.pack_count(),
+        }
+    }
+
+    /// Stored size of the index objects: the chunk index and the caches
+    /// kept next to it.
+    pub async fn index_size(&self) -> Result<u64> {
+        let mut size = 0;
+        for name in list_objects(self.storage.as_ref(), "index").await? {
+            size += self
+                .storage
+                .metadata(&format!("index/{}", name))
+                .await?
+                .size;
+        }
+        Ok(size)
+    }
+
+    /// Returns pack cache statistics.
+    pub async fn cache_stats(&self) -> CacheStats {
+        let cache = self.pack_cache.read().await;
+        let cache_size = self.pack_cache_size.read().await;
+
+        CacheStats {
+            pack_count: cache.len(),
+            total_size: *cache_size,
+            max_size: self.max_cache_size,
+        }
+    }
+
+    /// Loads the cached statistics object, if one has been written.
+    ///
+    /// An unreadable cache is treated as missing so it gets rebuilt.
+    pub async fn load_stats_cache(&self) -> Result<Option<StatsCache>> {
+        if !self.storage.exists(STATS_CACHE_PATH).await? {
+            return Ok(None);
+        }
+
+        let data = self.storage.read(STATS_CACHE_PATH).await?;
+        match StatsCache::deserialize(&data, self.encryptor()?) {
+            Ok(cache) => Ok(Some(cache)),
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable stats cache: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn save_stats_cache(&self, cache: &StatsCache) -> Result<()> {
+        let data = cache.serialize(self.encryptor()?)?;
+        self.storage.write(STATS_CACHE_PATH, data.into()).await?;
+        Ok(())
+    }
+
+    /// Loads the stored chunk reference counts, if they have been written.
+    ///
+    /// Unreadable counts are treated as missing so they get rebuilt.
+    pub async fn load_refcounts(&self) -> Result<Option<RefCounts>> {
+        if !self.storage.exists(REFCOUNTS_PATH).await? {
+            return Ok(None);
+        }
+
+        let data = self.storage.read(REFCOUNTS_PATH).await?;
+        match RefCounts::deserialize(&data, self.encryptor()?) {
+            Ok(refcounts) => Ok(Some(refcounts)),
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable chunk reference counts: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn save_refcounts(&self, refcounts: &RefCounts) -> Result<()> {
+        let data = refcounts.serialize(self.encryptor()?)?;
+        self.storage.write(REFCOUNTS_PATH, data.into()).await?;
+        Ok(())
+    }
+
+    /// Returns chunk reference counts covering exactly the snapshots in
+    /// storage, creating them on first use.
+    pub async fn refcounts(&self) -> Result<RefCounts> {
+        let existing = self.load_refcounts().await?;
+        let created = existing.is_none();
+        let mut refcounts = existing.unwrap_or_default();
+        let changed = self.sync_refcounts(&mut refcounts).await?;
+        if created || changed {
+            match self.save_refcounts(&refcounts).await {
+                // Read-only in a dry run; the counts are updated next time
+                Err(Error::DryRun { .. }) => {}
+                result => result?,
+            }
+        }
+        Ok(refcounts)
+    }
+
+    /// Brings the stored reference counts up to date after snapshots were
+    /// added or forgotten. Does nothing until they have been created by
+    /// `prune`.
+    pub async fn refresh_refcounts(&self) -> Result<()> {
+        if let Some(mut refcounts) = self.load_refcounts().await?
+            && self.sync_refcounts(&mut refcounts).await?
+        {
+            self.save_refcounts(&refcounts).await?;
+        }
+        Ok(())
+    }
+
+    /// Reconciles the reference counts with the snapshots in storage.
+    ///
+    /// Only the trees of snapshots added or removed since the counts were
+    /// written are loaded. If a removed snapshot's tree can't be read, its
+    /// chunks can't be subtracted and the counts are rebuilt from scratch.
+    /// Returns true if the counts were modified.
+    async fn sync_refcounts(&self, refcounts: &mut RefCounts) -> Result<bool> {
+        use std::collections::HashSet;
+
+        let snapshot_ids = self.list_snapshots().await?;
+        let live: HashSet<&SnapshotID> = snapshot_ids.iter().collect();
+        let mut changed = false;
+
+        let removed: Vec<(SnapshotID, ChunkID)> = refcounts
+            .snapshots()
+            .iter()
+            .filter(|(id, _)| !live.contains(id))
+            .map(|(id, tree_id)| (id.clone(), *tree_id))
+            .collect();
+        for (snapshot_id, tree_id) in removed {
+            changed = true;
+            match self.load_tree(&tree_id).await {
+                Ok(tree) => {
+                    refcounts.remove_snapshot(&snapshot_id, &tree);
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Rebuilding chunk reference counts: tree of forgotten snapshot {} is unreadable: {}",
+                        snapshot_id,
+                        e
+                    );
+                    *refcounts = RefCounts::new();
+                    break;
+                }
+            }
+        }
+
+        for snapshot_id in &snapshot_ids {
+            if refcounts.snapshots().contains_key(snapshot_id) {
+                continue;
+            }
+            let snapshot = self.load_snapshot(snapshot_id).await?;
+            let tree = self.load_tree(&snapshot.tree).await?;
+            refcounts.add_snapshot(snapshot_id, snapshot.tree, &tree);
+            changed = true;
+        }
+
+        Ok(changed)
+    }
+
+    /// Returns a summary of every snapshot, oldest first, from the snapshot
+    /// cache.
+    ///
+    /// Only snapshots that are new since the cache was last written are read;
+    /// forgotten snapshots are dropped. With `recompute`, the cache is rebuilt
+    /// from scratch. Failing to write the updated cache (e.g. on read-only
+    /// storage) is logged and does not fail the listing.
+    pub async fn snapshot_summaries(&self, recompute: bool) -> Result<Vec<SnapshotSummary>> {
+        use std::collections::HashSet;
+
+        let existing = if recompute {
+            None
+        } else {
+            self.load_snapshot_cache().await?
+        };
+        let created = existing.is_none();
+        let mut cache = existing.unwrap_or_default();
+
+        let snapshot_ids = self.list_snapshots().await?;
+        let live: HashSet<&SnapshotID> = snapshot_ids.iter().collect();
+        let before = cache.snapshots.len();
+        cache.snapshots.retain(|id, _| live.contains(id));
+        let mut changed = before != cache.snapshots.len();
+
+        for snapshot_id in &snapshot_ids {
+            if cache.snapshots.contains_key(snapshot_id) {
+                continue;
+            }
+            match self.snapshot_summary(snapshot_id).await {
+                Ok(summary) => {
+                    cache.snapshots.insert(snapshot_id.clone(), summary);
+                    changed = true;
+                }
+                Err(e) => tracing::warn!("Skipping snapshot {}: {}", snapshot_id, e),
+            }
+        }
+
+        if created || changed {
+            cache.updated_at = chrono::Utc::now();
+            let saved = match cache.serialize(self.encryptor()?) {
+                Ok(data) => self.storage.write(SNAPSHOT_CACHE_PATH, data.into()).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = saved {
+                tracing::warn!("Failed to update snapshot cache: {}", e);
+            }
+        }
+
+        Ok(cache.sorted())
+    }
+
+    /// Checks this host's clock against the newest snapshot.
+    ///
+    /// Returns how far the newest snapshot lies in the future, if that is
+    /// more than [`CLOCK_SKEW_TOLERANCE`]. A clock that is behind makes new
+    /// snapshots sort before existing ones, which breaks retention policies.
+    pub async fn clock_skew(&self) -> Result<Option<chrono::Duration>> {
+        let now = chrono::Utc::now();
+        let newest = self
+            .snapshot_summaries(false)
+            .await?
+            .into_iter()
+            .map(|summary| summary.snapshot.time)
+            .max();
+        Ok(newest
+            .map(|time| time - now)
+            .filter(|ahead| *ahead > CLOCK_SKEW_TOLERANCE))
+    }
+
+    /// Checks this host's clock against the storage's: writes a probe object
+    /// and compares the modification time the storage reports for it.
+    ///
+    /// Returns the storage's clock minus this host's, if they differ by
+    /// more than [`CLOCK_SKEW_TOLERANCE`]. Needs write access.
+    pub async fn storage_clock_offset(&self) -> Result<Option<chrono::Duration>> {
+        let before = chrono::Utc::now();
+        self.storage
+            .write(capability::PROBE_PATH, Bytes::from_static(b"clock probe"))
+            .await?;
+        let after = chrono::Utc::now();
+        let metadata = self.storage.metadata(capability::PROBE_PATH).await;
+        // Append-only credentials can't delete it; the probe is a lock object,
+        // so leaving it behind is harmless
+        self.storage.delete(capability::PROBE_PATH).await.ok();
+        let offset = clock_offset(metadata?.modified_at, before, after);
+        Ok((offset.abs() > CLOCK_SKEW_TOLERANCE).then_some(offset))
+    }
+
+    /// Loads the snapshot summary cache. An unreadable cache is treated as
+    /// missing so it gets rebuilt.
+    async fn load_snapshot_cache(&self) -> Result<Option<SnapshotCache>> {
+        if !self.storage.exists(SNAPSHOT_CACHE_PATH).await? {
+            return Ok(None);
+        }
+
+        let data = self.storage.read(SNAPSHOT_CACHE_PATH).await?;
+        match SnapshotCache::deserialize(&data, self.encryptor()?) {
+            Ok(cache) => Ok(Some(cache)),
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable snapshot cache: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn snapshot_summary(&self, snapshot_id: &SnapshotID) -> Result<SnapshotSummary> {
+        let snapshot = self.load_snapshot(snapshot_id).await?;
+        let tree = self.load_tree(&snapshot.tree).await?;
+        Ok(SnapshotSummary {
+            snapshot,
+            file_count: tree.file_count() as u64,
+        })
+    }
+
+    /// Settings shared by all clients, as read when the repository was opened.
+    pub fn settings(&self) -> &RepoSettings {
+        &self.settings
+    }
+
+    /// Target size of new packs: the `pack_size` setting or, if unset, the
+    /// default for the backend (see [`default_pack_size`]).
+    pub fn target_pack_size(&self) -> u64 {
+        self.settings
+            .pack_size
+            .unwrap_or_else(|| default_pack_size(&self.location))
+    }
+
+    /// Validates and stores `settings`, replacing the previous ones.
+    pub async fn save_settings(&mut self, mut settings: RepoSettings) -> Result<()> {
+        settings.validate()?;
+        settings.updated_at = chrono::Utc::now();
+        let data = settings.serialize(self.encryptor()?)?;
+        self.storage.write(SETTINGS_PATH, data.into()).await?;
+        self.settings = settings;
+        Ok(())
+    }
+
+    /// Reads the stored settings, or the defaults if none were saved.
+    async fn read_settings(
+        storage: &dyn RepositoryStorage,
+        encryptor: &Encryptor,
+    ) -> Result<RepoSettings> {
+        if !storage.exists(SETTINGS_PATH).await? {
+            return Ok(RepoSettings::new());
+        }
+        RepoSettings::deserialize(&storage.read(SETTINGS_PATH).await?, encryptor)
+    }
+
+    /// Loads the retention policy saved with [`save_retention_policy`], if any.
+    ///
+    /// [`save_retention_policy`]: Self::save_retention_policy
+    pub async fn load_retention_policy(&self) -> Result<Option<RetentionPolicy>> {
+        if !self.storage.exists(POLICY_PATH).await? {
+            return Ok(None);
+        }
+
+        let data = self.storage.read(POLICY_PATH).await?;
+        RetentionPolicy::deserialize(&data, self.encryptor()?).map(Some)
+    }
+
+    /// Stores `policy` in the repository, replacing any previous policy.
+    pub async fn save_retention_policy(&self, policy: &RetentionPolicy) -> Result<()> {
+        let data = policy.serialize(self.encryptor()?)?;
+        self.storage.write(POLICY_PATH, data.into()).await?;
+        Ok(())
+    }
+
+    /// Removes the stored retention policy. Returns false if there was none.
+    pub async fn delete_retention_policy(&self) -> Result<bool> {
+        if !self.storage.exists(POLICY_PATH).await? {
+            return Ok(false);
+        }
+        self.storage.delete(POLICY_PATH).await?;
+        Ok(true)
+    }
+
+    /// Returns up-to-date repository statistics, creating the cache on first use.
+    ///
+    /// With `recompute`, the existing cache is discarded and every snapshot
+    /// and pack is walked again.
+    pub async fn stats_cache(&self, recompute: bool) -> Result<StatsCache> {
+        let existing = if recompute {
+            None
+        } else {
+            self.load_stats_cache().await?
+        };
+
+        let created = existing.is_none();
+        let mut cache = existing.unwrap_or_default();
+        let changed = self.sync_stats_cache(&mut cache).await?;
+        if created || changed {
+            match self.save_stats_cache(&cache).await {
+                // Read-only in a dry run; the cache is rebuilt next time
+                Err(Error::DryRun { .. }) => {}
+                result => result?,
+            }
+        }
+
+        Ok(cache)
+    }
+
+    /// Brings the stats cache up to date after snapshots or packs were added
+    /// or removed. Does nothing until the cache has been created by `stats`.
+    pub async fn refresh_stats_cache(&self) -> Result<()> {
+        if let Some(mut cache) = self.load_stats_cache().await?
+            && self.sync_stats_cache(&mut cache).await?
+        {
+            self.save_stats_cache(&cache).await?;
+        }
+        Ok(())
+    }
+
+    /// Reconciles the cache with the snapshots and packs currently in storage.
+    ///
+    /// Only new snapshots have their trees loaded and only new packs are sized,
+    /// so the cost is proportional to what changed. Returns true if the cache
+    /// was modified.
+    async fn sync_stats_cache(&self, cache: &mut StatsCache) -> Result<bool> {
+        use std::collections::HashSet;
+
+        let snapshot_ids = self.list_snapshots().await?;
+        let pack_ids = self.list_packs().await?;
+
+        let live_snapshots: HashSet<&SnapshotID> = snapshot_ids.iter().collect();
+        let live_packs: HashSet<&PackID> = pack_ids.iter().collect();
+
+        let before = (cache.snapshots.len(), cache.packs.len());
+        cache.snapshots.retain(|id, _| live_snapshots.contains(id));
+        cache.packs.retain(|id, _| live_packs.contains(id));
+        let mut changed = before != (cache.snapshots.len(), cache.packs.len());
+
+        for snapshot_id in &snapshot_ids {
+            if cache.snapshots.contains_key(snapshot_id) {
+                continue;
+            }
+            match self.snapshot_stats_entry(snapshot_id).await {
+                Ok(entry) => {
+                    cache.snapshots.insert(snapshot_id.clone(), entry);
+                    changed = true;
+                }
+                Err(e) => tracing::warn!("Skipping snapshot {} in stats: {}", snapshot_id, e),
+            }
+        }
+
+        for pack_id in &pack_ids {
+            if cache.packs.contains_key(pack_id) {
+                continue;
+            }
+            match self.pack_size(pack_id).await {
+                Ok(size) => {
+                    cache.packs.insert(pack_id.clone(), size);
+                    changed = true;
+                }
+                Err(e) => tracing::warn!("Skipping pack {} in stats: {}", pack_id, e),
+            }
+        }
+
+        if changed {
+            cache.updated_at = chrono::Utc::now();
+        }
+        Ok(changed)
+    }
+
+    async fn snapshot_stats_entry(&self, snapshot_id: &SnapshotID) -> Result<SnapshotStatsEntry> {
+        let snapshot = self.load_snapshot(snapshot_id).await?;
+        let tree = self.load_tree(&snapshot.tree).await?;
+        Ok(SnapshotStatsEntry {
+            hostname: snapshot.hostname,
+            file_count: tree.file_count() as u64,
+            original_size: tree.total_size(),
+        })
+    }
+
+    /// Attributes stored chunks to the hosts whose snapshots reference them,
+    /// loading every snapshot tree. Fails rather than skip an unreadable
+    /// snapshot, which would make the chunks it shares look unique.
+    pub async fn host_stats(&self) -> Result<Vec<HostStats>> {
+        let mut attribution = HostAttribution::new();
+        for snapshot_id in self.list_snapshots().await? {
+            let snapshot = self.load_snapshot(&snapshot_id).await?;
+            let tree = self.load_tree(&snapshot.tree).await?;
+            attribution.add_snapshot(&snapshot.hostname, &tree);
+        }
+
+        let index = self.index.read().await;
+        Ok(attribution.finish(|id| index.get_chunk(id).map(|location| location.length as u64)))
+    }
+
+    /// Paths of recently written objects, for checking replication: the
+    /// newest `snapshots` snapshots and their trees, plus up to `max_packs`
+    /// of the packs they reference that the snapshot before them does not.
+    /// All of these objects are immutable.
+    pub async fn recent_objects(&self, snapshots: usize, max_packs: usize) -> Result<Vec<String>> {
+        let summaries = self.snapshot_summaries(false).await?;
+        let (older, recent) = summaries.split_at(summaries.len().saturating_sub(snapshots));
+
+        let mut paths = Vec::new();
+        let mut packs = std::collections::BTreeSet::new();
+        for summary in recent.iter().rev() {
+            let snapshot = &summary.snapshot;
+            paths.push(format!("snapshots/{}", snapshot.id));
+            for tree_id in self.tree_objects(&snapshot.tree).await? {
+                paths.push(format!("data/{}", tree_id.to_hex()));
+            }
+            packs.extend(self.tree_packs(&snapshot.tree).await?);
+        }
+        if let Some(previous) = older.last() {
+            for pack_id in self.tree_packs(&previous.snapshot.tree).await? {
+                packs.remove(&pack_id);
+            }
+        }
+
+        paths.extend(
+            packs
+                .into_iter()
+                .take(max_packs)
+                .map(|pack_id| format!("data/{}.pack", pack_id)),
+        );
+        Ok(paths)
+    }
+
+    /// Checks that a snapshot can be restored as stored, without reading
+    /// pack contents: the snapshot and its tree load, every chunk the tree
+    /// references is indexed, and the packs holding them exist.
+    pub async fn verify_snapshot_present(&self, snapshot_id: &SnapshotID) -> Result<()> {
+        let snapshot = self.load_snapshot(snapshot_id).await?;
+        let tree = self.load_tree(&snapshot.tree).await?;
+        let mut packs = std::collections::BTreeSet::new();
+        {
+            let index = self.index.read().await;
+            for chunk in tree.nodes.iter().flat_map(|node| &node.chunks) {
+                let location = index
+                    .get_chunk(&chunk.id)
+                    .ok_or_else(|| Error::ChunkNotFound {
+                        id: chunk.id.to_hex(),
+                    })?;
+                packs.insert(location.pack_id);
+            }
+        }
+        for pack_id in packs {
+            if !self.pack_exists(&pack_id).await? {
+                return Err(Error::Other(format!(
+                    "Pack {} of snapshot {} is missing",
+                    pack_id, snapshot_id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Packs holding the chunks referenced by a tree.
+    async fn tree_packs(&self, tree_id: &ChunkID) -> Result<std::collections::BTreeSet<PackID>> {
+        let tree = self.load_tree(tree_id).await?;
+        let index = self.index.read().await;
+        Ok(tree
+            .nodes
+            .iter()
+            .flat_map(|node| &node.chunks)
+            .filter_map(|chunk| index.get_chunk(&chunk.id))
+            .map(|location| location.pack_id)
+            .collect())
+    }
+
+    /// Collects all chunk IDs referenced by all snapshots in the repository,
+    /// from the incrementally maintained reference counts.
+    pub async fn collect_used_chunks(&self) -> Result<std::collections::HashSet<ChunkID>> {
+        Ok(self.refcounts().await?.referenced_chunks())
+    }
+
+    /// Reports, for every snapshot in chronological order, how much of its data
+    /// was introduced by that snapshot versus shared with earlier snapshots.
+    ///
+    /// Snapshots created with `--standalone` re-upload all of their chunks and
+    /// are therefore always reported as fully self-contained.
+    pub async fn snapshot_chain_stats(&self) -> Result<Vec<SnapshotChainStats>> {
+        use std::collections::{HashMap, HashSet};
+
+        let mut snapshots = Vec::new();
+        for snapshot_id in self.list_snapshots().await? {
+            snapshots.push(self.load_snapshot(&snapshot_id).await?);
+        }
+        snapshots.sort_by_key(|s| s.time);
+
+        let mut seen: HashSet<ChunkID> = HashSet::new();
+        let mut results = Vec::with_capacity(snapshots.len());
+
+        for snapshot in snapshots {
+            let tree = self.load_tree(&snapshot.tree).await?;
+
+            // Deduplicate within the snapshot first so repeated chunks are counted once.
+            let mut chunks: HashMap<ChunkID, u64> = HashMap::new();
+            for node in &tree.nodes {
+                for chunk_ref in &node.chunks {
+                    chunks.insert(chunk_ref.id, chunk_ref.length as u64);
+                }
+            }
+
+            let mut stats = SnapshotChainStats {
+                snapshot_id: snapshot.id.clone(),
+                parent: snapshot.parent.clone(),
+                standalone: snapshot.standalone,
+                self_contained_chunks: 0,
+                self_contained_bytes: 0,
+                referenced_chunks: 0,
+                referenced_bytes: 0,
+            };
+
+            for (chunk_id, length) in chunks {
+                if !snapshot.standalone && seen.contains(&chunk_id) {
+                    stats.referenced_chunks += 1;
+                    stats.referenced_bytes += length;
+                } else {
+                    stats.self_contained_chunks += 1;
+                    stats.self_contained_bytes += length;
+                }
+                seen.insert(chunk_id);
+            }
+
+            results.push(stats);
+        }
+
+        Ok(results)
+    }
+
+    /// Estimates what forgetting `forget_ids` and then pruning would reclaim,
+    /// without changing anything.
+    ///
+    /// Chunks are only reclaimable once no remaining snapshot references them.
+    /// Packs left without live chunks are deleted; packs whose share of unused
+    /// chunks reaches `max_unused` (0.0-1.0, the `prune --max-unused` ratio)
+    /// are repacked, which reads the whole pack and rewrites its live chunks.
+    pub async fn forecast_forget(
+        &self,
+        forget_ids: &[SnapshotID],
+        max_unused: f64,
+    ) -> Result<ForgetForecast> {
+        use std::collections::{HashMap, HashSet};
+
+        let doomed: HashSet<&SnapshotID> = forget_ids.iter().collect();
+        let mut kept_chunks: HashSet<ChunkID> = HashSet::new();
+        let mut doomed_chunks: HashSet<ChunkID> = HashSet::new();
+
+        for snapshot_id in self.list_snapshots().await? {
+            let snapshot = self.load_snapshot(&snapshot_id).await?;
+            let tree = self.load_tree(&snapshot.tree).await?;
+            let target = if doomed.contains(&snapshot_id) {
+                &mut doomed_chunks
+            } else {
+                &mut kept_chunks
+            };
+            for node in &tree.nodes {
+                target.extend(node.chunks.iter().map(|c| c.id));
+            }
+        }
+
+        let mut forecast = ForgetForecast {
+            snapshots: doomed.len(),
+            ..Default::default()
+        };
+
+        // Pack ID -> (total chunks, unused chunks, live bytes)
+        let mut packs: HashMap<PackID, (usize, usize, u64)> = HashMap::new();
+        {
+            let index = self.index.read().await;
+            for (chunk_id, location) in index.iter_chunks() {
+                let length = u64::from(location.length);
+                let entry = packs.entry(location.pack_id.clone()).or_default();
+                entry.0 += 1;
+
+                if kept_chunks.contains(&chunk_id) {
+                    entry.2 += length;
+                    if doomed_chunks.contains(&chunk_id) {
+                        forecast.shared_chunks += 1;
+                    }
+                    continue;
+                }
+
+                entry.1 += 1;
+                if doomed_chunks.contains(&chunk_id) {
+                    forecast.unique_chunks += 1;
+                    forecast.unique_bytes += length;
+                } else {
+                    forecast.unused_chunks += 1;
+                    forecast.unused_bytes += length;
+                }
+            }
+        }
+
+        for (pack_id, (total, unused, live_bytes)) in packs {
+            if unused == 0 {
+                continue;
+            }
+
+            let size = self.pack_size(&pack_id).await?;
+            if unused == total {
+                forecast.packs_deleted += 1;
+                forecast.deleted_bytes += size;
+            } else if unused as f64 / total as f64 >= max_unused {
+                forecast.packs_repacked += 1;
+                forecast.repack_read_bytes += size;
+                forecast.repack_write_bytes += live_bytes;
+            } else {
+                forecast.packs_kept += 1;
+                forecast.kept_unused_bytes += size.saturating_sub(live_bytes);
+            }
+        }
+
+        Ok(forecast)
     }
 
-    /// Returns repository statistics.
-    pub async fn stats(&self) -> RepoStats {
-        let index = self.index.read().await;
-        RepoStats {
-            chunk_count: index.chunk_count(),
-            pack_count: index.pack_count(),
+    /// Writes the union of several snapshots' trees and returns an unsaved
+    /// snapshot referencing it.
+    ///
+    /// Sources are applied oldest first, so for paths present in more than
+    /// one snapshot the version from the newest snapshot wins. No chunk data
+    /// is copied; the merged tree references the existing chunks.
+    ///
+    /// All sources must back up the same paths: node names are relative to
+    /// them, so trees of different roots would mix unrelated files.
+    pub async fn merge_snapshots(&self, snapshot_ids: &[SnapshotID]) -> Result<Snapshot> {
+        if snapshot_ids.len() < 2 {
+            return Err(Error::Other(
+                "At least two snapshots are required for a merge".to_string(),
+            ));
         }
-    }
 
-    /// Returns pack cache statistics.
-    pub async fn cache_stats(&self) -> CacheStats {
-        let cache = self.pack_cache.read().await;
-        let cache_size = self.pack_cache_size.read().await;
+        let mut sources = Vec::with_capacity(snapshot_ids.len());
+        for snapshot_id in snapshot_ids {
+            let snapshot = self.load_snapshot(snapshot_id).await?;
+            let tree = self.load_tree(&snapshot.tree).await?;
+            sources.push((snapshot, tree));
+        }
+        sources.sort_by_key(|(snapshot, _)| snapshot.time);
+
+        let first = &sources[0].0;
+        if let Some((other, _)) = sources.iter().find(|(s, _)| !s.has_same_paths(first)) {
+            return Err(Error::Other(format!(
+                "Cannot merge snapshots of different paths: {} has {} but {} has {}",
+                first.short_id(),
+                display_paths(&first.paths),
+                other.short_id(),
+                display_paths(&other.paths)
+            )));
+        }
 
-        CacheStats {
-            pack_count: cache.len(),
-            total_size: *cache_size,
-            max_size: self.max_cache_size,
+        let tree = Tree::merge(sources.iter().map(|(_, tree)| tree));
+        let tree_id = self.save_tree(&tree).await?;
+
+        let paths = first.paths.clone();
+        let mut tags = Vec::new();
+        for (snapshot, _) in &sources {
+            for tag in &snapshot.tags {
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
+                }
+            }
         }
+
+        let mut merged = Snapshot::new(paths, tree_id).with_tags(tags);
+        let newest = &sources[sources.len() - 1].0;
+        if sources.iter().all(|(s, _)| s.hostname == newest.hostname) {
+            merged.hostname = newest.hostname.clone();
+        }
+        merged.merged_from = sources.iter().map(|(s, _)| s.id.clone()).collect();
+        for (snapshot, _) in &sources {
+            merged.user_names.extend(snapshot.user_names.clone());
+            merged.group_names.extend(snapshot.group_names.clone());
+        }
+
+        Ok(merged)
     }
 
-    /// Collects all chunk IDs referenced by all snapshots in the repository.
-    pub async fn collect_used_chunks(&self) -> Result<std::collections::HashSet<ChunkID>> {
+    /// Collects a snapshot, its tree and every chunk it references into a
+    /// portable bundle.
+    pub async fn export_bundle(&self, snapshot_id: &SnapshotID) -> Result<SnapshotBundle> {
         use std::collections::HashSet;
 
-        let mut used_chunks = HashSet::new();
-        let snapshot_ids = self.list_snapshots().await?;
+        let snapshot = self.load_snapshot(snapshot_id).await?;
+        let tree = self.load_tree(&snapshot.tree).await?;
 
-        for snapshot_id in snapshot_ids {
-            let snapshot = self.load_snapshot(&snapshot_id).await?;
-            let tree = self.load_tree(&snapshot.tree).await?;
+        let mut seen = HashSet::new();
+        let mut chunks = Vec::new();
+        for node in &tree.nodes {
+            for chunk_ref in &node.chunks {
+                if seen.insert(chunk_ref.id) {
+                    let data = self.load_chunk(&chunk_ref.id).await?;
+                    chunks.push((chunk_ref.id, data.to_vec()));
+                }
+            }
+        }
 
-            for node in &tree.nodes {
-                for chunk_ref in &node.chunks {
-                    used_chunks.insert(chunk_ref.id);
+        Ok(SnapshotBundle {
+            snapshot,
+            tree,
+            chunks,
+            hash: self.hasher.algorithm(),
+            profile: self.config.profile,
+        })
+    }
+
+    /// Imports a bundle produced by [`Repository::export_bundle`], writing any
+    /// chunks not already present and recreating the snapshot.
+    ///
+    /// Chunks get this repository's IDs, which differ from the bundle's if
+    /// it came from a repository with another hash algorithm or key.
+    pub async fn import_bundle(&self, bundle: &SnapshotBundle) -> Result<BundleImportStats> {
+        use std::collections::HashMap;
+
+        let mut pack_manager = self.pack_manager(self.target_pack_size());
+        let mut chunks_imported = 0;
+        let mut chunks_skipped = 0;
+        let mut new_ids = HashMap::new();
+
+        for (bundle_id, data) in &bundle.chunks {
+            let chunk_id = self.chunk_id(data);
+            if chunk_id != *bundle_id {
+                new_ids.insert(*bundle_id, chunk_id);
+            }
+            if self.has_chunk(&chunk_id).await? {
+                chunks_skipped += 1;
+                continue;
+            }
+
+            if let Some(pack) = pack_manager.add_chunk(chunk_id, data)? {
+                self.save_pack_with_locations(&pack).await?;
+            }
+            chunks_imported += 1;
+        }
+
+        if let Some(pack) = pack_manager.finish_current_pack() {
+            self.save_pack_with_locations(&pack).await?;
+        }
+
+        // Trees are stored under their content ID; an identical tree that is
+        // already in this repository is reused.
+        let tree_id = if new_ids.is_empty() {
+            self.save_tree(&bundle.tree).await?
+        } else {
+            let mut tree = bundle.tree.clone();
+            tree.replace_chunk_ids(&new_ids);
+            self.save_tree(&tree).await?
+        };
+        let mut snapshot = bundle.snapshot.clone();
+        snapshot.tree = tree_id;
+        // The parent snapshot is not carried in the bundle.
+        snapshot.parent = None;
+        // Standalone packs stay in the exporting repository
+        snapshot.packs.clear();
+
+        self.save_snapshot(&snapshot).await?;
+        self.save_index().await?;
+
+        Ok(BundleImportStats {
+            snapshot_id: snapshot.id,
+            chunks_imported,
+            chunks_skipped,
+        })
+    }
+
+    /// Copies a snapshot into another repository, uploading only the chunks
+    /// the destination does not already have.
+    ///
+    /// If the destination hashes chunks differently (another algorithm or
+    /// key), every chunk is read to compute its ID there.
+    ///
+    /// The snapshot keeps its ID; the parent reference is dropped because the
+    /// parent may not exist in the destination.
+    pub async fn copy_snapshot_to(
+        &self,
+        dst: &Repository,
+        snapshot_id: &SnapshotID,
+    ) -> Result<SnapshotCopyStats> {
+        use std::collections::{HashMap, HashSet};
+
+        let snapshot = self.load_snapshot(snapshot_id).await?;
+        let mut tree = self.load_tree(&snapshot.tree).await?;
+
+        let same_ids = self.hasher.is_compatible(&dst.hasher);
+        let mut pack_manager = dst.pack_manager(dst.target_pack_size());
+        let mut seen = HashSet::new();
+        let mut new_ids = HashMap::new();
+        let mut chunks_copied = 0;
+        let mut chunks_skipped = 0;
+
+        for node in &tree.nodes {
+            for chunk_ref in &node.chunks {
+                if !seen.insert(chunk_ref.id) {
+                    continue;
                 }
+                if same_ids && dst.has_chunk(&chunk_ref.id).await? {
+                    chunks_skipped += 1;
+                    continue;
+                }
+
+                let data = self.load_chunk(&chunk_ref.id).await?;
+                let chunk_id = if same_ids {
+                    chunk_ref.id
+                } else {
+                    let chunk_id = dst.chunk_id(&data);
+                    new_ids.insert(chunk_ref.id, chunk_id);
+                    if dst.has_chunk(&chunk_id).await? {
+                        chunks_skipped += 1;
+                        continue;
+                    }
+                    chunk_id
+                };
+                if let Some(pack) = pack_manager.add_chunk(chunk_id, &data)? {
+                    dst.save_pack_with_locations(&pack).await?;
+                }
+                chunks_copied += 1;
+            }
+        }
+
+        if let Some(pack) = pack_manager.finish_current_pack() {
+            dst.save_pack_with_locations(&pack).await?;
+        }
+        tree.replace_chunk_ids(&new_ids);
+
+        let mut copied = snapshot.clone();
+        copied.tree = dst.save_tree(&tree).await?;
+        copied.parent = None;
+        copied.packs.clear();
+
+        dst.save_snapshot(&copied).await?;
+        dst.save_index().await?;
+
+        Ok(SnapshotCopyStats {
+            snapshot_id: copied.id,
+            chunks_copied,
+            chunks_skipped,
+        })
+    }
+
+    /// Saves a pack and records the location of each of its chunks.
+    pub(crate) async fn save_pack_with_locations(&self, pack: &PackFile) -> Result<()> {
+        self.save_pack(pack).await?;
+        for (chunk_id, chunk_entry) in &pack.chunks {
+            self.save_chunk_location(
+                chunk_id,
+                &pack.header.pack_id,
+                chunk_entry.offset,
+                chunk_entry.length,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Saves a pack written by a standalone backup. Only chunks the index
+    /// doesn't have yet are located in it; chunks indexed elsewhere keep
+    /// their location, and the copies here are accounted for through
+    /// [`Snapshot::packs`].
+    pub(crate) async fn save_standalone_pack(&self, pack: &PackFile) -> Result<()> {
+        self.save_pack(pack).await?;
+        for (chunk_id, chunk_entry) in &pack.chunks {
+            if !self.has_chunk(chunk_id).await? {
+                self.save_chunk_location(
+                    chunk_id,
+                    &pack.header.pack_id,
+                    chunk_entry.offset,
+                    chunk_entry.length,
+                )
+                .await?;
             }
         }
+        Ok(())
+    }
 
-        Ok(used_chunks)
+    /// Packs written by standalone backups whose snapshots still exist.
+    /// They are kept whole, even where the index places their chunks in
+    /// other packs.
+    pub async fn standalone_packs(&self) -> Result<std::collections::HashSet<PackID>> {
+        let mut packs = std::collections::HashSet::new();
+        for snapshot_id in self.list_snapshots().await? {
+            packs.extend(self.load_snapshot(&snapshot_id).await?.packs);
+        }
+        Ok(packs)
     }
 
     /// Compacts the index by removing unreferenced chunks.
@@ -719,11 +2676,15 @@ impl Repository {
     /// Identifies packs that contain no referenced chunks.
     pub async fn find_unused_packs(&self) -> Result<Vec<PackID>> {
         let used_chunks = self.collect_used_chunks().await?;
+        let standalone_packs = self.standalone_packs().await?;
         let index = self.index.read().await;
 
         let mut unused_packs = Vec::new();
 
         for pack_id in index.all_pack_ids() {
+            if standalone_packs.contains(&pack_id) {
+                continue;
+            }
             let pack_chunks = index.chunks_in_pack(&pack_id);
             let has_used_chunks = pack_chunks.iter().any(|id| used_chunks.contains(id));
 
@@ -770,11 +2731,13 @@ impl Repository {
         let used_chunks = self.collect_used_chunks().await?;
         let repacker = Repacker::new(max_pack_size);
 
-        // Collect pack information
+        // Collect pack information; packs of standalone snapshots are kept
+        // as they are
         let pack_ids = self.list_packs().await?;
+        let standalone_packs = self.standalone_packs().await?;
         let mut pack_infos = Vec::new();
 
-        for pack_id in &pack_ids {
+        for pack_id in pack_ids.iter().filter(|id| !standalone_packs.contains(*id)) {
             let index = self.index.read().await;
             if let Some(info) = index.get_pack(pack_id) {
                 pack_infos.push((pack_id.clone(), info.size));
@@ -812,7 +2775,7 @@ impl Repository {
         stats.chunks_copied = chunks_to_repack.len();
 
         // Create new packs with the used chunks
-        let mut pack_manager = PackManager::new(max_pack_size);
+        let mut pack_manager = self.pack_manager(max_pack_size);
         let mut new_packs = Vec::new();
 
         for (chunk_id, data) in chunks_to_repack {
@@ -904,15 +2867,24 @@ impl Repository {
         fs::write(target_path.join("config"), &config_data).await?;
         stats.files_copied += 1;
 
+        // Copy the marker, the shared settings and the retention policy
+        for path in [MARKER_PATH, SETTINGS_PATH, POLICY_PATH] {
+            if self.storage.exists(path).await? {
+                let data = self.storage.read(path).await?;
+                fs::write(target_path.join(path), &data).await?;
+                stats.files_copied += 1;
+            }
+        }
+
         // Copy keys
-        for key_name in self.storage.list("keys").await? {
+        for key_name in list_objects(self.storage.as_ref(), "keys").await? {
             let data = self.storage.read(&format!("keys/{}", key_name)).await?;
             fs::write(target_path.join("keys").join(&key_name), &data).await?;
             stats.files_copied += 1;
         }
 
         // Copy index
-        for index_name in self.storage.list("index").await? {
+        for index_name in list_objects(self.storage.as_ref(), "index").await? {
             let data = self.storage.read(&format!("index/{}", index_name)).await?;
             let size = data.len() as u64;
             fs::write(target_path.join("index").join(&index_name), &data).await?;
@@ -921,10 +2893,15 @@ impl Repository {
         }
 
         // Copy data (packs and trees)
-        for data_name in self.storage.list("data").await? {
+        for data_name in list_objects(self.storage.as_ref(), "data").await? {
             let data = self.storage.read(&format!("data/{}", data_name)).await?;
             let size = data.len() as u64;
-            fs::write(target_path.join("data").join(&data_name), &data).await?;
+            let path = format!("data/{}", data_name);
+            let object_path = target_path.join(fan_out(&path).unwrap_or(path));
+            if let Some(parent) = object_path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::write(object_path, &data).await?;
             stats.files_copied += 1;
             stats.bytes_copied += size;
 
@@ -934,7 +2911,7 @@ impl Repository {
         }
 
         // Copy snapshots
-        for snapshot_name in self.storage.list("snapshots").await? {
+        for snapshot_name in list_objects(self.storage.as_ref(), "snapshots").await? {
             let data = self
                 .storage
                 .read(&format!("snapshots/{}", snapshot_name))
@@ -1002,6 +2979,337 @@ impl Repository {
 
         Ok(stats)
     }
+
+    /// Loads the pack scrub state, or an empty one if none has been written.
+    ///
+    /// An unreadable state is treated as missing, so scrubbing starts over.
+    pub async fn load_scrub_state(&self) -> Result<ScrubState> {
+        if !self.storage.exists(SCRUB_STATE_PATH).await? {
+            return Ok(ScrubState::new());
+        }
+
+        let data = self.storage.read(SCRUB_STATE_PATH).await?;
+        match ScrubState::deserialize(&data, self.encryptor()?) {
+            Ok(state) => Ok(state),
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable scrub state: {}", e);
+                Ok(ScrubState::new())
+            }
+        }
+    }
+
+    async fn save_scrub_state(&self, state: &ScrubState) -> Result<()> {
+        let data = state.serialize(self.encryptor()?)?;
+        self.storage.write(SCRUB_STATE_PATH, data.into()).await?;
+        Ok(())
+    }
+
+    /// Verifies packs totalling up to `budget` bytes, least recently verified
+    /// first, and records when each was verified. See [`crate::scrub`].
+    ///
+    /// Corrupt packs are reported, not repaired.
+    pub async fn scrub(&self, budget: u64) -> Result<ScrubReport> {
+        let packs = self.stats_cache(false).await?.packs;
+        let mut state = self.load_scrub_state().await?;
+        state.retain_packs(&packs);
+
+        let mut report = ScrubReport {
+            total_packs: packs.len(),
+            ..ScrubReport::default()
+        };
+        for pack_id in state.select(&packs, budget) {
+            let result = self.scrub_pack(&pack_id, &mut report.bytes_read).await;
+            state.record(&pack_id, result.is_ok());
+            match result {
+                Ok(chunks) => {
+                    report.packs_verified += 1;
+                    report.chunks_verified += chunks;
+                }
+                Err(failure) => {
+                    tracing::warn!("Pack {} failed scrubbing: {}", pack_id, failure.error);
+                    report.failures.push(failure);
+                }
+            }
+        }
+
+        report.never_verified = packs
+            .keys()
+            .filter(|id| !state.packs.contains_key(*id))
+            .count();
+        report.oldest_verification = state.oldest_verification(&packs);
+        self.save_scrub_state(&state).await?;
+        Ok(report)
+    }
+
+    /// Re-reads a pack from storage, bypassing the pack cache, and checks
+    /// every chunk against its ID and the pack checksum. Returns the number
+    /// of chunks verified; the bytes read are added to `bytes_read`.
+    async fn scrub_pack(
+        &self,
+        pack_id: &PackID,
+        bytes_read: &mut u64,
+    ) -> std::result::Result<usize, ScrubFailure> {
+        let failure = |corrupt_chunks: Vec<ChunkID>, error: String| ScrubFailure {
+            pack_id: pack_id.clone(),
+            corrupt_chunks,
+            error,
+        };
+
+        let data = self
+            .storage
+            .read(&format!("data/{}.pack", pack_id))
+            .await
+            .map_err(|e| failure(Vec::new(), format!("read failed: {}", e)))?;
+        *bytes_read += data.len() as u64;
+        let pack = self
+            .encryptor()
+            .and_then(|encryptor| PackFile::from_encrypted_bytes(&data, encryptor))
+            .map_err(|e| failure(Vec::new(), format!("decode failed: {}", e)))?;
+
+        let mut corrupt_chunks: Vec<ChunkID> = pack
+            .chunks
+            .keys()
+            .filter(|id| {
+                !pack
+                    .get_chunk(id)
+                    .is_ok_and(|data| self.chunk_id(&data) == **id)
+            })
+            .copied()
+            .collect();
+        if !corrupt_chunks.is_empty() {
+            corrupt_chunks.sort_by_key(ChunkID::to_hex);
+            let error = format!(
+                "{} of {} chunks do not match their hash",
+                corrupt_chunks.len(),
+                pack.chunks.len()
+            );
+            return Err(failure(corrupt_chunks, error));
+        }
+        if !pack.verify_checksum().unwrap_or(false) {
+            return Err(failure(Vec::new(), "pack checksum mismatch".to_string()));
+        }
+        Ok(pack.chunks.len())
+    }
+
+    /// Reads every pack header and compares it against the index.
+    ///
+    /// Detects chunks stored in packs but missing from the index, index
+    /// entries whose pack, offset or length disagree with the pack contents,
+    /// and chunks stored in more than one pack. Copies in the packs of
+    /// standalone snapshots are expected and not reported as duplicates.
+    pub async fn cross_check_index(&self) -> Result<IndexCrossCheck> {
+        use std::collections::HashMap;
+
+        let standalone_packs = self.standalone_packs().await?;
+        let mut report = IndexCrossCheck::default();
+        let mut stored: HashMap<ChunkID, Vec<ChunkLocation>> = HashMap::new();
+
+        for pack_id in self.list_packs().await? {
+            match self.load_pack(&pack_id).await {
+                Ok(pack) => {
+                    report.packs_checked += 1;
+                    for (chunk_id, entry) in &pack.chunks {
+                        stored.entry(*chunk_id).or_default().push(ChunkLocation {
+                            pack_id: pack_id.clone(),
+                            offset: entry.offset,
+                            length: entry.length,
+                        });
+                    }
+                }
+                Err(e) => report.unreadable_packs.push((pack_id, e.to_string())),
+            }
+        }
+
+        let unreadable: std::collections::HashSet<&PackID> =
+            report.unreadable_packs.iter().map(|(id, _)| id).collect();
+
+        let index = self.index.read().await;
+        for (chunk_id, locations) in &stored {
+            match index.get_chunk(chunk_id) {
+                None => report
+                    .unindexed_chunks
+                    .push((*chunk_id, locations[0].pack_id.clone())),
+                Some(indexed) => {
+                    if !locations.contains(&indexed) {
+                        let actual = locations
+                            .iter()
+                            .find(|l| l.pack_id == indexed.pack_id)
+                            .or_else(|| locations.first())
+                            .cloned();
+                        report.mismatched_entries.push(IndexMismatch {
+                            chunk_id: *chunk_id,
+                            indexed,
+                            actual,
+                        });
+                    }
+                }
+            }
+
+            let copies: Vec<PackID> = locations
+                .iter()
+                .map(|l| l.pack_id.clone())
+                .filter(|pack_id| !standalone_packs.contains(pack_id))
+                .collect();
+            if copies.len() > 1 {
+                report.duplicate_chunks.push((*chunk_id, copies));
+            }
+        }
+
+        // Index entries for chunks that no readable pack contains at all
+        for (chunk_id, indexed) in index.iter_chunks() {
+            if !stored.contains_key(&chunk_id) && !unreadable.contains(&indexed.pack_id) {
+                report.mismatched_entries.push(IndexMismatch {
+                    chunk_id,
+                    indexed,
+                    actual: None,
+                });
+            }
+        }
+
+        report.stored_chunks = stored.len();
+        Ok(report)
+    }
+}
+
+/// Result of comparing pack contents against the index.
+#[derive(Debug, Default)]
+pub struct IndexCrossCheck {
+    pub packs_checked: usize,
+    /// Distinct chunks found in readable packs
+    pub stored_chunks: usize,
+    /// Packs that could not be read, with the error
+    pub unreadable_packs: Vec<(PackID, String)>,
+    /// Chunks stored in a pack but absent from the index
+    pub unindexed_chunks: Vec<(ChunkID, PackID)>,
+    /// Index entries that disagree with the pack contents
+    pub mismatched_entries: Vec<IndexMismatch>,
+    /// Chunks stored in more than one pack
+    pub duplicate_chunks: Vec<(ChunkID, Vec<PackID>)>,
+}
+
+impl IndexCrossCheck {
+    /// Returns true if the index and the packs agree.
+    pub fn is_consistent(&self) -> bool {
+        self.unreadable_packs.is_empty()
+            && self.unindexed_chunks.is_empty()
+            && self.mismatched_entries.is_empty()
+            && self.duplicate_chunks.is_empty()
+    }
+}
+
+/// An index entry whose location does not match the pack contents.
+#[derive(Debug, Clone)]
+pub struct IndexMismatch {
+    pub chunk_id: ChunkID,
+    /// Location recorded in the index
+    pub indexed: ChunkLocation,
+    /// Where the chunk was actually found, if anywhere
+    pub actual: Option<ChunkLocation>,
+}
+
+/// Tree objects of a tree read one at a time, with node names expanded to
+/// full paths; see [`Repository::tree_pages`].
+pub struct TreePages<'a> {
+    repo: &'a Repository,
+    /// Paths the nodes are wanted for; all nodes when empty
+    paths: Vec<String>,
+    /// Tree objects still to read, with the directory each holds nodes of
+    pending: VecDeque<(String, ChunkID)>,
+}
+
+impl<'a> TreePages<'a> {
+    fn new(repo: &'a Repository, tree_id: &ChunkID, paths: &[String]) -> Self {
+        Self {
+            repo,
+            paths: paths
+                .iter()
+                .map(|path| path.trim_matches('/').to_string())
+                .collect(),
+            pending: VecDeque::from([(String::new(), *tree_id)]),
+        }
+    }
+
+    /// Loads the next page, or returns `None` after the last one.
+    pub async fn next_page(&mut self) -> Result<Option<Tree>> {
+        while let Some((dir, tree_id)) = self.pending.pop_front() {
+            let mut tree = self.repo.load_tree_root(&tree_id).await?;
+
+            // Pages come next, in order, before any subtree
+            if tree.is_paged() {
+                for page in tree.pages.iter().rev() {
+                    if self.wants_page(&dir, page) {
+                        self.pending.push_front((dir.clone(), page.tree));
+                    }
+                }
+                continue;
+            }
+
+            for node in &mut tree.nodes {
+                if !dir.is_empty() {
+                    node.name = format!("{}/{}", dir, node.name);
+                }
+                if let Some(subtree) = node.subtree_id.take()
+                    && self.wants_subtree(&node.name)
+                {
+                    self.pending.push_back((node.name.clone(), subtree));
+                }
+            }
+            return Ok(Some(tree));
+        }
+        Ok(None)
+    }
+
+    /// Whether the subtree of directory `dir` may hold wanted nodes.
+    fn wants_subtree(&self, dir: &str) -> bool {
+        self.paths.is_empty()
+            || self.paths.iter().any(|path| {
+                path.is_empty() || is_at_or_below(dir, path) || is_at_or_below(path, dir)
+            })
+    }
+
+    /// Whether a page of the tree object holding directory `dir` may hold
+    /// wanted nodes: nodes under a path or directories leading to one.
+    fn wants_page(&self, dir: &str, page: &TreePage) -> bool {
+        self.paths.is_empty()
+            || self.paths.iter().any(|path| {
+                let relative = if dir.is_empty() {
+                    path.as_str()
+                } else {
+                    // Outside `dir`, the whole directory is wanted
+                    path.strip_prefix(dir)
+                        .and_then(|p| p.strip_prefix('/'))
+                        .unwrap_or_default()
+                };
+                page.overlaps_prefix(relative)
+                    || std::path::Path::new(relative)
+                        .ancestors()
+                        .skip(1)
+                        .any(|parent| page.contains(&parent.to_string_lossy()))
+            })
+    }
+}
+
+/// Whether `path` is `dir` or a path below it.
+/// Paths of a snapshot for messages, e.g. `/etc, /var/www`.
+fn display_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn is_at_or_below(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Result of [`Repository::copy_snapshot_to`].
+#[derive(Debug)]
+pub struct SnapshotCopyStats {
+    pub snapshot_id: SnapshotID,
+    pub chunks_copied: usize,
+    pub chunks_skipped: usize,
 }
 
 /// Clone operation statistics.
@@ -1024,6 +3332,23 @@ pub struct VerifyStats {
     pub corrupt_snapshots: usize,
 }
 
+/// A key file as listed by [`Repository::list_keys`].
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyInfo {
+    pub name: String,
+    /// When, where and by whom the key was added; `None` for key files
+    /// written before this was recorded
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub hostname: Option<String>,
+    pub username: Option<String>,
+    /// Key derivation algorithm
+    pub kdf: String,
+    /// Whether the key also needs a keyfile
+    pub keyfile: bool,
+    /// Whether this key unlocked the repository
+    pub current: bool,
+}
+
 /// Repository statistics.
 #[derive(Debug)]
 pub struct RepoStats {
@@ -1031,6 +3356,68 @@ pub struct RepoStats {
     pub pack_count: usize,
 }
 
+/// Per-snapshot breakdown of self-contained versus shared data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotChainStats {
+    pub snapshot_id: SnapshotID,
+    pub parent: Option<SnapshotID>,
+    /// Whether the snapshot was created in standalone mode
+    pub standalone: bool,
+    /// Chunks first introduced by this snapshot
+    pub self_contained_chunks: usize,
+    pub self_contained_bytes: u64,
+    /// Chunks already present in an earlier snapshot
+    pub referenced_chunks: usize,
+    pub referenced_bytes: u64,
+}
+
+impl SnapshotChainStats {
+    /// Returns true if the snapshot does not depend on data from earlier snapshots.
+    pub fn is_full(&self) -> bool {
+        self.referenced_chunks == 0
+    }
+}
+
+/// Result of [`Repository::forecast_forget`].
+#[derive(Debug, Default)]
+pub struct ForgetForecast {
+    /// Snapshots that would be forgotten
+    pub snapshots: usize,
+    /// Chunks referenced only by the forgotten snapshots
+    pub unique_chunks: usize,
+    pub unique_bytes: u64,
+    /// Chunks of forgotten snapshots still referenced by kept snapshots
+    pub shared_chunks: usize,
+    /// Chunks no snapshot references even now
+    pub unused_chunks: usize,
+    pub unused_bytes: u64,
+    /// Packs left without live chunks, removed outright
+    pub packs_deleted: usize,
+    pub deleted_bytes: u64,
+    /// Packs over the unused threshold that prune would rewrite
+    pub packs_repacked: usize,
+    pub repack_read_bytes: u64,
+    pub repack_write_bytes: u64,
+    /// Packs under the threshold; their unused space stays allocated
+    pub packs_kept: usize,
+    pub kept_unused_bytes: u64,
+}
+
+impl ForgetForecast {
+    /// Storage space freed once prune has deleted and repacked packs.
+    pub fn reclaimed_bytes(&self) -> u64 {
+        let repacked = self
+            .repack_read_bytes
+            .saturating_sub(self.repack_write_bytes);
+        self.deleted_bytes + repacked
+    }
+
+    /// Bytes prune would read and write to repack.
+    pub fn repack_io_bytes(&self) -> u64 {
+        self.repack_read_bytes + self.repack_write_bytes
+    }
+}
+
 /// Compaction statistics.
 #[derive(Debug)]
 pub struct CompactStats {
@@ -1050,8 +3437,237 @@ pub struct CacheStats {
     pub max_size: usize,
 }
 
+/// Outcome of [`Repository::rehydrate_packs`].
+#[derive(Debug, Default)]
+pub struct RehydrationReport {
+    /// Archived packs whose rehydration was started by this call
+    pub requested: Vec<PackID>,
+    /// Archived packs that were already rehydrating
+    pub pending: Vec<PackID>,
+}
+
+impl RehydrationReport {
+    /// Whether every pack can be read now.
+    pub fn is_ready(&self) -> bool {
+        self.requested.is_empty() && self.pending.is_empty()
+    }
+}
+
+/// Length of a sealed data key: nonce, 32-byte key and tag.
+const SEALED_KEY_LEN: usize = 12 + 32 + 16;
+
+/// Outcome of trying the secrets on one key file.
+enum KeyAttempt {
+    /// Boxed, as the master key and cipher state dwarf the other variants
+    Opened(Box<(MasterKey, Encryptor)>),
+    /// Well-formed, but the secrets don't open it
+    Rejected,
+    /// Unreadable, for the given reason
+    Corrupt(String),
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct KeyFile {
     encrypted_key: Vec<u8>,
     kdf_params: crate::KdfParams,
+    /// Whether the key-encryption key also depends on a keyfile
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    keyfile: bool,
+    /// When, where and by whom the key was added; absent from key files
+    /// written before this was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+}
+
+impl KeyFile {
+    /// A key file added now by this host and user.
+    fn new(encrypted_key: Vec<u8>, kdf_params: crate::KdfParams, keyfile: bool) -> Self {
+        Self {
+            encrypted_key,
+            kdf_params,
+            keyfile,
+            created_at: Some(chrono::Utc::now()),
+            hostname: Some(crate::snapshot::current_hostname()),
+            username: Some(crate::snapshot::current_username()),
+        }
+    }
+}
+
+/// Parses the `config` object.
+fn parse_config(data: &[u8]) -> Result<RepoConfig> {
+    let config_data = str::from_utf8(data)
+        .map_err(|e| Error::Other(format!("Invalid repository config encoding: {}", e)))?;
+    Ok(serde_json::from_str(config_data)?)
+}
+
+/// Reads all key files by name.
+async fn read_key_files(storage: &dyn RepositoryStorage) -> Result<BTreeMap<String, String>> {
+    let mut keys = BTreeMap::new();
+    for key_name in list_objects(storage, "keys").await? {
+        let data = storage.read(&format!("keys/{}", key_name)).await?;
+        let data = String::from_utf8(data.to_vec())
+            .map_err(|e| Error::Other(format!("Invalid key file encoding: {}", e)))?;
+        keys.insert(key_name, data);
+    }
+    Ok(keys)
+}
+
+/// Writes `data` to `path` and reads it back, failing unless the backend
+/// returns exactly what was written.
+async fn write_verified(storage: &dyn RepositoryStorage, path: &str, data: Bytes) -> Result<()> {
+    storage
+        .write(path, data.clone())
+        .await
+        .op_context("write", path)?;
+    let read_back = storage.read(path).await.op_context("read back", path)?;
+    if read_back != data {
+        return Err(Error::Backend(format!(
+            "Read-after-write check failed for {}: wrote {} bytes, read back {} different bytes",
+            path,
+            data.len(),
+            read_back.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Lists the names under `prefix` that are repository objects, skipping
+/// foreign objects that happen to share the prefix.
+async fn list_objects(storage: &dyn RepositoryStorage, prefix: &str) -> Result<Vec<String>> {
+    let mut names = storage.list(prefix).await?;
+    names.retain(|name| {
+        let known = layout::is_repository_entry(prefix, name);
+        if !known {
+            tracing::debug!("Ignoring foreign object {}/{}", prefix, name);
+        }
+        known
+    });
+    Ok(names)
+}
+
+/// Detects whether `path` is on a dm-crypt (LUKS) volume by finding its mount
+/// in `/proc/self/mounts` and checking the device-mapper UUID.
+#[cfg(target_os = "linux")]
+fn local_volume_encryption(path: &Path) -> Option<String> {
+    let path = std::fs::canonicalize(path).ok()?;
+    let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+
+    let (device, _) = mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let device = fields.next()?;
+            let mount_point = fields.next()?.replace("\\040", " ");
+            path.starts_with(&mount_point)
+                .then(|| (device.to_string(), mount_point.len()))
+        })
+        .max_by_key(|(_, len)| *len)?;
+
+    let dm_name = std::fs::canonicalize(&device)
+        .ok()?
+        .file_name()?
+        .to_string_lossy()
+        .to_string();
+    let uuid = std::fs::read_to_string(format!("/sys/block/{}/dm/uuid", dm_name)).ok()?;
+    uuid.starts_with("CRYPT-")
+        .then(|| format!("dm-crypt volume ({})", device))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn local_volume_encryption(_path: &Path) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::local_storage;
+    use async_trait::async_trait;
+    use std::collections::HashSet;
+
+    /// Local storage with its own chunk index, like a ghostsnap server.
+    struct ChunkServer {
+        inner: Box<dyn RepositoryStorage>,
+        known: HashSet<ChunkID>,
+    }
+
+    #[async_trait]
+    impl RepositoryStorage for ChunkServer {
+        fn location(&self) -> &RepositoryLocation {
+            self.inner.location()
+        }
+        async fn init(&self) -> Result<()> {
+            self.inner.init().await
+        }
+        async fn exists(&self, path: &str) -> Result<bool> {
+            self.inner.exists(path).await
+        }
+        async fn read(&self, path: &str) -> Result<Bytes> {
+            self.inner.read(path).await
+        }
+        async fn write(&self, path: &str, data: Bytes) -> Result<()> {
+            self.inner.write(path, data).await
+        }
+        async fn delete(&self, path: &str) -> Result<()> {
+            self.inner.delete(path).await
+        }
+        async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+            self.inner.list(prefix).await
+        }
+        async fn metadata(&self, path: &str) -> Result<ObjectMetadata> {
+            self.inner.metadata(path).await
+        }
+        async fn has_chunks(&self, chunk_ids: &[ChunkID]) -> Result<Option<Vec<bool>>> {
+            Ok(Some(
+                chunk_ids.iter().map(|id| self.known.contains(id)).collect(),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_has_chunks_asks_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let on_server = ChunkID::from_data(b"on the server");
+        let saved = ChunkID::from_data(b"saved by this process");
+        let unknown = ChunkID::from_data(b"unknown");
+        let storage = ChunkServer {
+            inner: local_storage(dir.path()),
+            known: HashSet::from([on_server]),
+        };
+        let repo = Repository::init_with_storage(Box::new(storage), &PasswordKey::new("pw"))
+            .await
+            .unwrap();
+
+        repo.save_chunk_location(&saved, &PackID::generate(), 0, 10)
+            .await
+            .unwrap();
+        assert!(!repo.has_chunk(&on_server).await.unwrap());
+        assert_eq!(
+            repo.has_chunks(&[on_server, saved, unknown]).await.unwrap(),
+            vec![true, true, false]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_has_chunks_falls_back_to_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let saved = ChunkID::from_data(b"saved by this process");
+        let unknown = ChunkID::from_data(b"unknown");
+        let repo =
+            Repository::init_with_storage(local_storage(dir.path()), &PasswordKey::new("pw"))
+                .await
+                .unwrap();
+
+        repo.save_chunk_location(&saved, &PackID::generate(), 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            repo.has_chunks(&[saved, unknown]).await.unwrap(),
+            vec![true, false]
+        );
+    }
 }
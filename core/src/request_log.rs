@@ -0,0 +1,266 @@
+//! Request logging for storage backends (`--debug-backend`).
+//!
+//! When enabled, every storage operation is logged with its method, object
+//! path, outcome, duration and size, and the S3 client additionally logs each
+//! HTTP attempt with its status code and attempt number, so retries hidden
+//! inside the SDK become visible. Bodies, headers and query strings (which can
+//! carry presigned credentials) are never logged.
+//!
+//! Lines are emitted at debug level under the `ghostsnap_core::request_log`
+//! target.
+
+use crate::storage::{ObjectMetadata, RepositoryLocation, RepositoryStorage, TierStatus};
+use crate::{AccessTier, ChunkID, RehydratePriority, Result};
+use async_trait::async_trait;
+use aws_sdk_s3::config::interceptors::{
+    BeforeTransmitInterceptorContextRef, FinalizerInterceptorContextRef,
+};
+use aws_sdk_s3::config::{ConfigBag, Intercept, RuntimeComponents};
+use aws_smithy_runtime_api::box_error::BoxError;
+use aws_smithy_runtime_api::client::retries::RequestAttempts;
+use aws_smithy_types::config_bag::{Storable, StoreReplace};
+use bytes::Bytes;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tracing::debug;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns on request logging for storage opened from now on.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether request logging is on.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Strips the query string and fragment from a request URI.
+fn redact_uri(uri: &str) -> &str {
+    uri.split(['?', '#']).next().unwrap_or(uri)
+}
+
+/// Storage wrapper that logs every operation of the storage it wraps.
+pub(crate) struct LoggedStorage {
+    inner: Box<dyn RepositoryStorage>,
+}
+
+impl LoggedStorage {
+    pub(crate) fn new(inner: Box<dyn RepositoryStorage>) -> Self {
+        Self { inner }
+    }
+
+    async fn logged<T, F>(
+        &self,
+        method: &str,
+        path: &str,
+        size: impl Fn(&T) -> Option<u64>,
+        operation: F,
+    ) -> Result<T>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        let started = Instant::now();
+        let result = operation.await;
+        let duration_ms = started.elapsed().as_millis() as u64;
+        match &result {
+            Ok(value) => match size(value) {
+                Some(bytes) => debug!(method, path, status = "ok", duration_ms, bytes),
+                None => debug!(method, path, status = "ok", duration_ms),
+            },
+            Err(e) => debug!(method, path, status = "error", duration_ms, error = %e),
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl RepositoryStorage for LoggedStorage {
+    fn location(&self) -> &RepositoryLocation {
+        self.inner.location()
+    }
+
+    async fn init(&self) -> Result<()> {
+        self.logged("INIT", "", |_| None, self.inner.init()).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool> {
+        self.logged("HEAD", path, |_| None, self.inner.exists(path))
+            .await
+    }
+
+    async fn read(&self, path: &str) -> Result<Bytes> {
+        self.logged(
+            "GET",
+            path,
+            |data: &Bytes| Some(data.len() as u64),
+            self.inner.read(path),
+        )
+        .await
+    }
+
+    async fn write(&self, path: &str, data: Bytes) -> Result<()> {
+        let bytes = data.len() as u64;
+        self.logged("PUT", path, |_| Some(bytes), self.inner.write(path, data))
+            .await
+    }
+
+    async fn delete(&self, path: &str) -> Result<()> {
+        self.logged("DELETE", path, |_| None, self.inner.delete(path))
+            .await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        self.logged(
+            "LIST",
+            prefix,
+            |names: &Vec<String>| Some(names.len() as u64),
+            self.inner.list(prefix),
+        )
+        .await
+    }
+
+    async fn metadata(&self, path: &str) -> Result<ObjectMetadata> {
+        self.logged("STAT", path, |_| None, self.inner.metadata(path))
+            .await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let path = format!("{} -> {}", from, to);
+        self.logged("COPY", &path, |_| None, self.inner.copy(from, to))
+            .await
+    }
+
+    async fn has_chunks(&self, chunk_ids: &[ChunkID]) -> Result<Option<Vec<bool>>> {
+        self.logged("HAS_CHUNKS", "", |_| None, self.inner.has_chunks(chunk_ids))
+            .await
+    }
+
+    async fn migrate_layout(&self) -> Result<usize> {
+        self.logged("MIGRATE", "", |_| None, self.inner.migrate_layout())
+            .await
+    }
+
+    async fn access_tier(&self, path: &str) -> Result<Option<TierStatus>> {
+        self.logged("GET_TIER", path, |_| None, self.inner.access_tier(path))
+            .await
+    }
+
+    async fn set_access_tier(
+        &self,
+        path: &str,
+        tier: AccessTier,
+        priority: RehydratePriority,
+    ) -> Result<()> {
+        self.logged(
+            "SET_TIER",
+            path,
+            |_| None,
+            self.inner.set_access_tier(path, tier, priority),
+        )
+        .await
+    }
+}
+
+/// Start of the current HTTP attempt, kept in the request's config bag.
+#[derive(Debug, Clone, Copy)]
+struct AttemptStart(Instant);
+
+impl Storable for AttemptStart {
+    type Storer = StoreReplace<Self>;
+}
+
+/// S3 client interceptor that logs every HTTP attempt, including retries.
+#[derive(Debug)]
+pub(crate) struct S3RequestLog;
+
+impl Intercept for S3RequestLog {
+    fn name(&self) -> &'static str {
+        "S3RequestLog"
+    }
+
+    fn read_before_attempt(
+        &self,
+        _context: &BeforeTransmitInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> std::result::Result<(), BoxError> {
+        cfg.interceptor_state()
+            .store_put(AttemptStart(Instant::now()));
+        Ok(())
+    }
+
+    fn read_after_attempt(
+        &self,
+        context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> std::result::Result<(), BoxError> {
+        let Some(request) = context.request() else {
+            return Ok(());
+        };
+        let duration_ms = cfg
+            .load::<AttemptStart>()
+            .map_or(0, |start| start.0.elapsed().as_millis() as u64);
+        let attempt = cfg
+            .load::<RequestAttempts>()
+            .map_or(1, |attempts| attempts.attempts());
+        let method = request.method();
+        let uri = redact_uri(request.uri());
+        match context.response() {
+            Some(response) => debug!(
+                method,
+                uri,
+                status = response.status().as_u16(),
+                duration_ms,
+                attempt,
+                "s3 request"
+            ),
+            None => debug!(
+                method,
+                uri,
+                status = "no response",
+                duration_ms,
+                attempt,
+                "s3 request"
+            ),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::local_storage;
+
+    #[test]
+    fn test_redact_uri() {
+        assert_eq!(
+            redact_uri("https://bucket.s3.amazonaws.com/repo/config?X-Amz-Signature=abc"),
+            "https://bucket.s3.amazonaws.com/repo/config"
+        );
+        assert_eq!(redact_uri("/repo/data/ab#frag"), "/repo/data/ab");
+        assert_eq!(redact_uri("/repo/keys"), "/repo/keys");
+    }
+
+    #[tokio::test]
+    async fn test_logged_storage_passes_through() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LoggedStorage::new(local_storage(dir.path()));
+        storage.init().await.unwrap();
+
+        storage
+            .write("config", Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+        assert!(storage.exists("config").await.unwrap());
+        assert_eq!(storage.read("config").await.unwrap(), "data");
+        assert!(storage.read("missing").await.is_err());
+
+        storage.delete("config").await.unwrap();
+        assert!(!storage.exists("config").await.unwrap());
+    }
+}
@@ -0,0 +1,711 @@
+//! Read-only access to restic repositories.
+//!
+//! Opens a restic repository (format version 1 or 2) on any ghostsnap
+//! storage backend so its snapshots can be listed, browsed and restored.
+//! Nothing is ever written to it.
+//!
+//! restic's format differs from ours in every layer:
+//!
+//! - Key files are plaintext JSON holding a master key sealed with a
+//!   scrypt-derived key.
+//! - Objects are encrypted with AES-256-CTR and authenticated with
+//!   Poly1305-AES: `IV (16) || ciphertext || MAC (16)`.
+//! - Version 2 compresses with zstd: unpacked files carry a leading version
+//!   byte, and compressed blobs are marked in the index by their
+//!   uncompressed length.
+//! - Blobs and trees are named by the SHA-256 of their plaintext; packs live
+//!   at `data/<first two hex digits>/<id>`.
+//! - Trees are nested, one tree blob per directory.
+//!
+//! [`ResticRepository::load_tree`] flattens a snapshot's trees into a
+//! ghostsnap [`Tree`] whose chunk IDs are restic blob IDs, so the usual tree
+//! tooling (listing, diffing, path filters) works unchanged; file contents
+//! are read with [`ResticRepository::load_blob`].
+
+use crate::snapshot::Tree;
+use crate::storage::{RepositoryStorage, storage_for_location};
+use crate::types::{ChunkID, ChunkRef, DeviceNumber, NodeType, TreeNode};
+use crate::{Error, ErrorContext, RepositoryLocation, Result};
+use aes::cipher::{BlockEncrypt, KeyInit, KeyIvInit, StreamCipher, generic_array::GenericArray};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+use poly1305::Poly1305;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroUsize;
+use tokio::sync::Mutex;
+
+type Aes256Ctr = ctr::Ctr128BE<aes::Aes256>;
+
+/// Size of the IV in front of and the MAC behind every encrypted object.
+const IV_LEN: usize = 16;
+const MAC_LEN: usize = 16;
+
+/// Packs kept in memory while reading blobs; consecutive blobs of a file
+/// usually share a pack.
+const PACK_CACHE_COUNT: usize = 4;
+
+/// Go's `os.FileMode` keeps the setuid, setgid and sticky bits outside the
+/// Unix permission bits.
+const GO_MODE_SETUID: u32 = 1 << 23;
+const GO_MODE_SETGID: u32 = 1 << 22;
+const GO_MODE_STICKY: u32 = 1 << 20;
+
+/// Returns whether the storage holds a restic repository: restic names its
+/// key files by SHA-256 hash, ghostsnap by UUID.
+pub async fn is_restic_repository(storage: &dyn RepositoryStorage) -> Result<bool> {
+    if !storage.exists("config").await? {
+        return Ok(false);
+    }
+    let keys = storage.list("keys").await?;
+    Ok(!keys.is_empty() && keys.iter().all(|name| is_restic_id(name)))
+}
+
+fn is_restic_id(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// A snapshot in a restic repository.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResticSnapshot {
+    /// Storage name of the snapshot file, not part of its JSON
+    #[serde(skip)]
+    pub id: String,
+    pub time: DateTime<Utc>,
+    pub tree: String,
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub hostname: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl ResticSnapshot {
+    pub fn short_id(&self) -> String {
+        self.id.chars().take(8).collect()
+    }
+}
+
+/// Where a blob is stored.
+#[derive(Debug, Clone)]
+struct BlobLocation {
+    pack: String,
+    offset: u64,
+    length: u64,
+    /// Set for zstd-compressed blobs
+    uncompressed_length: Option<u64>,
+}
+
+impl BlobLocation {
+    /// Plaintext size of the blob.
+    fn size(&self) -> u64 {
+        self.uncompressed_length
+            .unwrap_or(self.length.saturating_sub((IV_LEN + MAC_LEN) as u64))
+    }
+}
+
+/// Keys for decrypting and authenticating objects.
+#[derive(Clone)]
+struct Key {
+    encrypt: [u8; 32],
+    mac_k: [u8; 16],
+    mac_r: [u8; 16],
+}
+
+impl Key {
+    /// Derives the key that seals a master key in a key file.
+    fn derive(password: &str, key_file: &KeyFile) -> Result<Self> {
+        if key_file.kdf != "scrypt" {
+            return Err(Error::Encryption(format!(
+                "Unsupported key derivation function: {}",
+                key_file.kdf
+            )));
+        }
+        if !key_file.n.is_power_of_two() {
+            return Err(Error::Encryption(format!(
+                "Invalid scrypt parameter N={}",
+                key_file.n
+            )));
+        }
+        let params = scrypt::Params::new(
+            key_file.n.trailing_zeros() as u8,
+            key_file.r,
+            key_file.p,
+            64,
+        )
+        .map_err(|e| Error::Encryption(format!("Invalid scrypt parameters: {}", e)))?;
+        let salt = decode_base64(&key_file.salt)?;
+        let mut derived = [0u8; 64];
+        scrypt::scrypt(password.as_bytes(), &salt, &params, &mut derived)
+            .map_err(|e| Error::Encryption(format!("Key derivation failed: {}", e)))?;
+
+        let mut key = Key {
+            encrypt: [0; 32],
+            mac_k: [0; 16],
+            mac_r: [0; 16],
+        };
+        key.encrypt.copy_from_slice(&derived[..32]);
+        key.mac_k.copy_from_slice(&derived[32..48]);
+        key.mac_r.copy_from_slice(&derived[48..]);
+        Ok(key)
+    }
+
+    fn from_master_key(master: &MasterKeyJson) -> Result<Self> {
+        let copy = |encoded: &str, out: &mut [u8]| -> Result<()> {
+            let bytes = decode_base64(encoded)?;
+            if bytes.len() != out.len() {
+                return Err(Error::Encryption("Malformed master key".to_string()));
+            }
+            out.copy_from_slice(&bytes);
+            Ok(())
+        };
+        let mut key = Key {
+            encrypt: [0; 32],
+            mac_k: [0; 16],
+            mac_r: [0; 16],
+        };
+        copy(&master.encrypt, &mut key.encrypt)?;
+        copy(&master.mac.k, &mut key.mac_k)?;
+        copy(&master.mac.r, &mut key.mac_r)?;
+        Ok(key)
+    }
+
+    /// Poly1305-AES over the ciphertext: the one-time key is `r` followed by
+    /// the IV encrypted under `k`.
+    fn mac(&self, iv: &[u8], ciphertext: &[u8]) -> [u8; MAC_LEN] {
+        let mut s = GenericArray::clone_from_slice(iv);
+        aes::Aes128::new(&self.mac_k.into()).encrypt_block(&mut s);
+
+        let mut poly_key = [0u8; 32];
+        poly_key[..16].copy_from_slice(&self.mac_r);
+        poly_key[16..].copy_from_slice(&s);
+        let tag = Poly1305::new(&poly_key.into()).compute_unpadded(ciphertext);
+
+        let mut mac = [0u8; MAC_LEN];
+        mac.copy_from_slice(&tag);
+        mac
+    }
+
+    /// Authenticates and decrypts an object.
+    fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < IV_LEN + MAC_LEN {
+            return Err(Error::Encryption("Ciphertext too short".to_string()));
+        }
+        let (iv, rest) = data.split_at(IV_LEN);
+        let (ciphertext, mac) = rest.split_at(rest.len() - MAC_LEN);
+
+        let expected = self.mac(iv, ciphertext);
+        let diff = expected
+            .iter()
+            .zip(mac)
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        if diff != 0 {
+            return Err(Error::Encryption("MAC verification failed".to_string()));
+        }
+
+        let mut plaintext = ciphertext.to_vec();
+        Aes256Ctr::new(&self.encrypt.into(), GenericArray::from_slice(iv))
+            .apply_keystream(&mut plaintext);
+        Ok(plaintext)
+    }
+}
+
+#[derive(Deserialize)]
+struct KeyFile {
+    kdf: String,
+    #[serde(rename = "N")]
+    n: u64,
+    r: u32,
+    p: u32,
+    salt: String,
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct MasterKeyJson {
+    mac: MacKeyJson,
+    encrypt: String,
+}
+
+#[derive(Deserialize)]
+struct MacKeyJson {
+    k: String,
+    r: String,
+}
+
+#[derive(Deserialize)]
+struct ResticConfig {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct IndexFile {
+    #[serde(default)]
+    packs: Vec<IndexPack>,
+}
+
+#[derive(Deserialize)]
+struct IndexPack {
+    id: String,
+    blobs: Vec<IndexBlob>,
+}
+
+#[derive(Deserialize)]
+struct IndexBlob {
+    id: String,
+    offset: u64,
+    length: u64,
+    #[serde(default)]
+    uncompressed_length: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ResticTree {
+    #[serde(default)]
+    nodes: Vec<ResticNode>,
+}
+
+#[derive(Deserialize)]
+struct ResticNode {
+    name: String,
+    #[serde(rename = "type")]
+    node_type: String,
+    #[serde(default)]
+    mode: u32,
+    #[serde(default)]
+    mtime: Option<DateTime<Utc>>,
+    #[serde(default)]
+    uid: u32,
+    #[serde(default)]
+    gid: u32,
+    #[serde(default)]
+    size: u64,
+    #[serde(default)]
+    linktarget: Option<String>,
+    #[serde(default)]
+    content: Option<Vec<String>>,
+    #[serde(default)]
+    subtree: Option<String>,
+    #[serde(default)]
+    device: u64,
+    #[serde(default)]
+    extended_attributes: Option<Vec<ResticXattr>>,
+}
+
+#[derive(Deserialize)]
+struct ResticXattr {
+    name: String,
+    value: String,
+}
+
+/// A restic repository opened for reading.
+pub struct ResticRepository {
+    storage: Box<dyn RepositoryStorage>,
+    key: Key,
+    version: u32,
+    index: HashMap<String, BlobLocation>,
+    pack_cache: Mutex<LruCache<String, Bytes>>,
+}
+
+impl ResticRepository {
+    /// Opens the restic repository at `location`, trying `password` against
+    /// every key file, and loads its index.
+    pub async fn open(location: RepositoryLocation, password: &str) -> Result<Self> {
+        let storage = storage_for_location(&location).await?;
+        if !storage.exists("config").await? {
+            return Err(Error::RepositoryNotFound {
+                path: location.display(),
+            });
+        }
+
+        let key = Self::unlock(storage.as_ref(), password).await?;
+
+        let config_data = storage.read("config").await.op_context("read", "config")?;
+        let config: ResticConfig =
+            serde_json::from_slice(&decode_unpacked(&key.decrypt(&config_data)?)?)?;
+        if !(1..=2).contains(&config.version) {
+            return Err(Error::InvalidFormatVersion {
+                version: config.version,
+            });
+        }
+
+        let mut repo = Self {
+            storage,
+            key,
+            version: config.version,
+            index: HashMap::new(),
+            pack_cache: Mutex::new(LruCache::new(NonZeroUsize::new(PACK_CACHE_COUNT).unwrap())),
+        };
+        repo.load_index().await?;
+        Ok(repo)
+    }
+
+    /// Finds the key file `password` opens and returns its master key.
+    async fn unlock(storage: &dyn RepositoryStorage, password: &str) -> Result<Key> {
+        let names = storage.list("keys").await?;
+        for name in names.iter().filter(|name| is_restic_id(name)) {
+            let path = format!("keys/{}", name);
+            let data = storage.read(&path).await.op_context("read key", &path)?;
+            let key_file: KeyFile = serde_json::from_slice(&data)?;
+            let user_key = Key::derive(password, &key_file)?;
+            let Ok(master) = user_key.decrypt(&decode_base64(&key_file.data)?) else {
+                tracing::debug!("Password does not open key {}", name);
+                continue;
+            };
+            let master: MasterKeyJson = serde_json::from_slice(&master)?;
+            return Key::from_master_key(&master);
+        }
+        Err(Error::InvalidPassword)
+    }
+
+    async fn load_index(&mut self) -> Result<()> {
+        for name in self.storage.list("index").await? {
+            if !is_restic_id(&name) {
+                continue;
+            }
+            let index: IndexFile =
+                serde_json::from_slice(&self.read_unpacked("index", &name).await?)?;
+            for pack in index.packs {
+                for blob in pack.blobs {
+                    self.index.insert(
+                        blob.id,
+                        BlobLocation {
+                            pack: pack.id.clone(),
+                            offset: blob.offset,
+                            length: blob.length,
+                            uncompressed_length: blob.uncompressed_length,
+                        },
+                    );
+                }
+            }
+        }
+        tracing::debug!("Loaded restic index with {} blobs", self.index.len());
+        Ok(())
+    }
+
+    /// Repository format version (1 or 2).
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Reads and decrypts a file stored outside packs (snapshot, index).
+    async fn read_unpacked(&self, prefix: &str, name: &str) -> Result<Vec<u8>> {
+        let path = format!("{}/{}", prefix, name);
+        let data = self.storage.read(&path).await.op_context("read", &path)?;
+        if hex::encode(Sha256::digest(&data)) != name {
+            return Err(Error::Other(format!("Checksum mismatch for {}", path)));
+        }
+        decode_unpacked(&self.key.decrypt(&data).op_context("decrypt", &path)?)
+    }
+
+    /// Lists all snapshots, oldest first.
+    pub async fn list_snapshots(&self) -> Result<Vec<ResticSnapshot>> {
+        let mut snapshots = Vec::new();
+        for name in self.storage.list("snapshots").await? {
+            if is_restic_id(&name) {
+                snapshots.push(self.load_snapshot_file(&name).await?);
+            }
+        }
+        snapshots.sort_by_key(|s| s.time);
+        Ok(snapshots)
+    }
+
+    async fn load_snapshot_file(&self, name: &str) -> Result<ResticSnapshot> {
+        let mut snapshot: ResticSnapshot =
+            serde_json::from_slice(&self.read_unpacked("snapshots", name).await?)?;
+        snapshot.id = name.to_string();
+        Ok(snapshot)
+    }
+
+    /// Loads a snapshot by full ID or unique prefix.
+    pub async fn load_snapshot(&self, id: &str) -> Result<ResticSnapshot> {
+        let names = self.storage.list("snapshots").await?;
+        let matches: Vec<&String> = names
+            .iter()
+            .filter(|name| is_restic_id(name) && name.starts_with(id))
+            .collect();
+        match matches.as_slice() {
+            [name] => self.load_snapshot_file(name).await,
+            [] => Err(Error::SnapshotNotFound { id: id.to_string() }),
+            _ => Err(Error::Other(format!(
+                "Snapshot ID prefix '{}' is ambiguous ({} matches)",
+                id,
+                matches.len()
+            ))),
+        }
+    }
+
+    /// Reads a blob (file data or tree) by its hex ID.
+    pub async fn load_blob(&self, id: &str) -> Result<Vec<u8>> {
+        let location = self
+            .index
+            .get(id)
+            .ok_or_else(|| Error::ChunkNotFound { id: id.to_string() })?;
+        let pack = self.load_pack(&location.pack).await?;
+
+        let start = location.offset as usize;
+        let end = start + location.length as usize;
+        let sealed = pack.get(start..end).ok_or_else(|| Error::CorruptedPack {
+            id: location.pack.clone(),
+        })?;
+        let mut plaintext = self.key.decrypt(sealed).op_context("decrypt blob", id)?;
+        if location.uncompressed_length.is_some() {
+            plaintext = zstd::stream::decode_all(plaintext.as_slice())
+                .map_err(|e| Error::Other(format!("Failed to decompress blob {}: {}", id, e)))?;
+        }
+
+        if hex::encode(Sha256::digest(&plaintext)) != id {
+            return Err(Error::Other(format!("Checksum mismatch for blob {}", id)));
+        }
+        Ok(plaintext)
+    }
+
+    async fn load_pack(&self, pack_id: &str) -> Result<Bytes> {
+        if let Some(pack) = self.pack_cache.lock().await.get(pack_id) {
+            return Ok(pack.clone());
+        }
+        let path = format!("data/{}/{}", &pack_id[..2.min(pack_id.len())], pack_id);
+        let pack = self
+            .storage
+            .read(&path)
+            .await
+            .op_context("read pack", pack_id)?;
+        self.pack_cache
+            .lock()
+            .await
+            .put(pack_id.to_string(), pack.clone());
+        Ok(pack)
+    }
+
+    /// Loads a snapshot's directory tree, flattened into a ghostsnap tree
+    /// with paths relative to the snapshot root.
+    pub async fn load_tree(&self, snapshot: &ResticSnapshot) -> Result<Tree> {
+        let mut tree = Tree::new();
+        let mut pending = vec![(String::new(), snapshot.tree.clone())];
+        while let Some((dir, tree_id)) = pending.pop() {
+            let blob = self.load_blob(&tree_id).await?;
+            let restic_tree: ResticTree = serde_json::from_slice(&blob)?;
+            for node in restic_tree.nodes {
+                let name = if dir.is_empty() {
+                    node.name.clone()
+                } else {
+                    format!("{}/{}", dir, node.name)
+                };
+                if let Some(subtree) = &node.subtree {
+                    pending.push((name.clone(), subtree.clone()));
+                }
+                if let Some(converted) = self.convert_node(name, node)? {
+                    tree.add_node(converted);
+                }
+            }
+        }
+        tree.nodes.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(tree)
+    }
+
+    /// Converts a restic node; sockets and unknown types are skipped.
+    fn convert_node(&self, name: String, node: ResticNode) -> Result<Option<TreeNode>> {
+        let node_type = match node.node_type.as_str() {
+            "file" => NodeType::File,
+            "dir" => NodeType::Directory,
+            "symlink" => NodeType::Symlink,
+            "chardev" => NodeType::CharDevice,
+            "dev" => NodeType::BlockDevice,
+            "fifo" => NodeType::Fifo,
+            other => {
+                tracing::debug!("Skipping {} node {}", other, name);
+                return Ok(None);
+            }
+        };
+
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+        for blob_id in node.content.unwrap_or_default() {
+            let length = self.index.get(&blob_id).map_or(0, BlobLocation::size);
+            chunks.push(ChunkRef {
+                id: chunk_id(&blob_id)?,
+                offset,
+                length: length as u32,
+            });
+            offset += length;
+        }
+
+        let xattr = node.extended_attributes.map(|attrs| {
+            attrs
+                .into_iter()
+                .filter_map(|attr| Some((attr.name, BASE64.decode(attr.value).ok()?)))
+                .collect::<BTreeMap<_, _>>()
+        });
+
+        #[cfg(unix)]
+        let device = matches!(node_type, NodeType::CharDevice | NodeType::BlockDevice)
+            .then(|| DeviceNumber::from_rdev(node.device));
+        #[cfg(not(unix))]
+        let device: Option<DeviceNumber> = None;
+
+        Ok(Some(TreeNode {
+            name,
+            mode: unix_mode(node.mode),
+            uid: node.uid,
+            gid: node.gid,
+            size: node.size,
+            mtime: node.mtime.map_or(0, |t| t.timestamp()),
+            link_target: node.linktarget,
+            subtree_id: None,
+            chunks,
+            xattr,
+            sparse_holes: None,
+            inode: None,
+            nlink: None,
+            hardlink_target: None,
+            device,
+            node_type,
+        }))
+    }
+}
+
+/// Converts a Go `os.FileMode` to Unix permission bits.
+fn unix_mode(mode: u32) -> u32 {
+    let mut unix = mode & 0o777;
+    if mode & GO_MODE_SETUID != 0 {
+        unix |= 0o4000;
+    }
+    if mode & GO_MODE_SETGID != 0 {
+        unix |= 0o2000;
+    }
+    if mode & GO_MODE_STICKY != 0 {
+        unix |= 0o1000;
+    }
+    unix
+}
+
+/// Blob IDs are 32-byte SHA-256 hashes, the same size as a chunk ID.
+fn chunk_id(blob_id: &str) -> Result<ChunkID> {
+    blob_id
+        .parse()
+        .map_err(|_| Error::Other(format!("Invalid blob ID: {}", blob_id)))
+}
+
+/// Strips the version byte of an unpacked file: JSON as-is (version 1
+/// layout) or `0x02` followed by zstd-compressed JSON.
+fn decode_unpacked(plaintext: &[u8]) -> Result<Vec<u8>> {
+    match plaintext.first() {
+        Some(b'{' | b'[') => Ok(plaintext.to_vec()),
+        Some(2) => zstd::stream::decode_all(&plaintext[1..])
+            .map_err(|e| Error::Other(format!("Failed to decompress: {}", e))),
+        Some(version) => Err(Error::Other(format!(
+            "Unsupported restic file version {}",
+            version
+        ))),
+        None => Err(Error::Other("Empty restic file".to_string())),
+    }
+}
+
+fn decode_base64(encoded: &str) -> Result<Vec<u8>> {
+    BASE64
+        .decode(encoded)
+        .map_err(|e| Error::Encryption(format!("Invalid base64: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key() -> Key {
+        Key {
+            encrypt: [7; 32],
+            mac_k: [9; 16],
+            mac_r: [3; 16],
+        }
+    }
+
+    /// Seals `plaintext` the way restic does.
+    fn seal(key: &Key, iv: [u8; 16], plaintext: &[u8]) -> Vec<u8> {
+        let mut ciphertext = plaintext.to_vec();
+        Aes256Ctr::new(&key.encrypt.into(), GenericArray::from_slice(&iv))
+            .apply_keystream(&mut ciphertext);
+        let mac = key.mac(&iv, &ciphertext);
+        [iv.as_slice(), &ciphertext, &mac].concat()
+    }
+
+    #[test]
+    fn test_decrypt_round_trip_and_tamper() {
+        let key = test_key();
+        let sealed = seal(&key, [1; 16], b"{\"version\":2}");
+        assert_eq!(key.decrypt(&sealed).unwrap(), b"{\"version\":2}");
+
+        let mut tampered = sealed.clone();
+        tampered[20] ^= 1;
+        assert!(key.decrypt(&tampered).is_err());
+        assert!(key.decrypt(&sealed[..20]).is_err());
+    }
+
+    #[test]
+    fn test_mac_known_answer() {
+        // Poly1305-AES test vector 1 from Bernstein's paper; the nonce is
+        // restic's IV.
+        let key = Key {
+            encrypt: [0; 32],
+            mac_k: hex::decode("ec074c835580741701425b623235add6")
+                .unwrap()
+                .try_into()
+                .unwrap(),
+            mac_r: hex::decode("851fc40c3467ac0be05cc20404f3f700")
+                .unwrap()
+                .try_into()
+                .unwrap(),
+        };
+        let iv = hex::decode("fb447350c4e868c52ac3275cf9d4327e").unwrap();
+        assert_eq!(
+            hex::encode(key.mac(&iv, &[0xf3, 0xf6])),
+            "f4c633c3044fc145f84f335cb81953de"
+        );
+    }
+
+    #[test]
+    fn test_derive_known_answer() {
+        // scrypt test vector from RFC 7914, split into the three keys.
+        let key_file = KeyFile {
+            kdf: "scrypt".to_string(),
+            n: 1024,
+            r: 8,
+            p: 16,
+            salt: BASE64.encode("NaCl"),
+            data: String::new(),
+        };
+        let key = Key::derive("password", &key_file).unwrap();
+        assert_eq!(
+            hex::encode(key.encrypt),
+            "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162"
+        );
+        assert_eq!(hex::encode(key.mac_k), "2eaf30d92e22a3886ff109279d9830da");
+        assert_eq!(hex::encode(key.mac_r), "c727afb94a83ee6d8360cbdfa2cc0640");
+    }
+
+    #[test]
+    fn test_decode_unpacked() {
+        assert_eq!(decode_unpacked(b"{}").unwrap(), b"{}");
+
+        let compressed = zstd::stream::encode_all(&b"[1,2,3]"[..], 3).unwrap();
+        let versioned = [&[2u8][..], &compressed].concat();
+        assert_eq!(decode_unpacked(&versioned).unwrap(), b"[1,2,3]");
+
+        assert!(decode_unpacked(&[9, 0]).is_err());
+    }
+
+    #[test]
+    fn test_unix_mode_from_go_mode() {
+        // Directory with mode 0755
+        assert_eq!(unix_mode(0x8000_0000 | 0o755), 0o755);
+        assert_eq!(unix_mode(GO_MODE_SETUID | 0o755), 0o4755);
+        assert_eq!(unix_mode(GO_MODE_STICKY | 0x8000_0000 | 0o777), 0o1777);
+    }
+}
@@ -0,0 +1,232 @@
+//! Repository-wide settings shared by every client.
+//!
+//! Settings that should not depend on which host runs a backup — how packs
+//! are compressed and sized, which paths are always skipped — are stored
+//! encrypted in the repository and edited with `ghostsnap config get/set`.
+//! Options given on the command line or in a job add to them; an unset
+//! setting falls back to the built-in default.
+//!
+//! The default pack size depends on the backend (see [`default_pack_size`]):
+//! it trades requests, which cloud storage bills, against how much a restore
+//! or prune has to read or rewrite around the data it needs.
+
+use crate::crypto::Encryptor;
+use crate::source::DEFAULT_PACK_SIZE;
+use crate::storage::RepositoryLocation;
+use crate::types::AccessTier;
+use crate::{Error, Result, Validator};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Storage path of the encrypted settings.
+pub const SETTINGS_PATH: &str = "settings";
+
+/// Settings format version for schema evolution.
+const SETTINGS_VERSION: u32 = 1;
+
+/// Smallest pack size that can be configured (1 MiB).
+pub const MIN_PACK_SIZE: u64 = 1024 * 1024;
+
+/// Largest pack size that can be configured (1 GiB).
+pub const MAX_PACK_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Default pack size of local repositories (16 MiB). Reads cost nothing, and
+/// smaller packs leave less to rewrite when prune drops part of one.
+pub const LOCAL_PACK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Default pack size of repositories whose packs go to Glacier or an Azure
+/// Cold or Archive tier (256 MiB), where every request costs many times
+/// more and restores read whole packs anyway.
+pub const ARCHIVE_PACK_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Target size of new packs when the settings have none, chosen by where
+/// the packs are stored.
+pub fn default_pack_size(location: &RepositoryLocation) -> u64 {
+    match location {
+        RepositoryLocation::Local(_) => LOCAL_PACK_SIZE,
+        RepositoryLocation::S3(s3) => {
+            let cold = s3
+                .storage_classes
+                .data
+                .as_deref()
+                .is_some_and(|class| class.starts_with("GLACIER") || class == "DEEP_ARCHIVE");
+            if cold {
+                ARCHIVE_PACK_SIZE
+            } else {
+                DEFAULT_PACK_SIZE
+            }
+        }
+        RepositoryLocation::Azure(azure) => match azure.access_tiers.data {
+            Some(AccessTier::Cold | AccessTier::Archive) => ARCHIVE_PACK_SIZE,
+            _ => DEFAULT_PACK_SIZE,
+        },
+        RepositoryLocation::Rclone(_) | RepositoryLocation::Sftp(_) => DEFAULT_PACK_SIZE,
+    }
+}
+
+/// Checks that `size` is between [`MIN_PACK_SIZE`] and [`MAX_PACK_SIZE`].
+pub fn check_pack_size(size: u64) -> std::result::Result<(), String> {
+    if (MIN_PACK_SIZE..=MAX_PACK_SIZE).contains(&size) {
+        Ok(())
+    } else {
+        Err(format!(
+            "must be between {} and {} bytes, got {}",
+            MIN_PACK_SIZE, MAX_PACK_SIZE, size
+        ))
+    }
+}
+
+/// Encrypted settings applied by every client of a repository.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepoSettings {
+    pub version: u32,
+    pub updated_at: DateTime<Utc>,
+    /// zlib level (0-9) of new packs; overrides the level in the config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<u32>,
+    /// Target size of new packs; `None` uses the backend's
+    /// [`default_pack_size`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pack_size: Option<u64>,
+    /// Exclude patterns applied to every backup
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excludes: Vec<String>,
+}
+
+impl Default for RepoSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RepoSettings {
+    pub fn new() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            updated_at: Utc::now(),
+            compression_level: None,
+            pack_size: None,
+            excludes: Vec::new(),
+        }
+    }
+
+    /// Checks every setting, reporting all problems together.
+    pub fn validate(&self) -> Result<()> {
+        let mut validator = Validator::new();
+        if let Some(level) = self.compression_level {
+            validator.check(
+                level <= 9,
+                "compression_level",
+                format!("must be between 0 and 9, got {}", level),
+            );
+        }
+        if let Some(size) = self.pack_size
+            && let Err(e) = check_pack_size(size)
+        {
+            validator.error("pack_size", e);
+        }
+        for pattern in &self.excludes {
+            if let Err(e) = globset::Glob::new(pattern) {
+                validator.error("excludes", format!("invalid pattern '{}': {}", pattern, e));
+            }
+        }
+        validator.finish()
+    }
+
+    pub fn serialize(&self, encryptor: &Encryptor) -> Result<Vec<u8>> {
+        let json_data = serde_json::to_vec(self)
+            .map_err(|e| Error::Other(format!("Failed to serialize settings: {}", e)))?;
+        encryptor.encrypt(&json_data)
+    }
+
+    pub fn deserialize(data: &[u8], encryptor: &Encryptor) -> Result<Self> {
+        let decrypted_data = encryptor.decrypt(data)?;
+        let settings: Self = serde_json::from_slice(&decrypted_data)
+            .map_err(|e| Error::Other(format!("Failed to deserialize settings: {}", e)))?;
+        if settings.version != SETTINGS_VERSION {
+            return Err(Error::InvalidFormatVersion {
+                version: settings.version,
+            });
+        }
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_roundtrip() {
+        let encryptor = Encryptor::new(&[3u8; 32]).unwrap();
+        let settings = RepoSettings {
+            compression_level: Some(9),
+            pack_size: Some(16 * 1024 * 1024),
+            excludes: vec!["*.tmp".to_string()],
+            ..RepoSettings::new()
+        };
+
+        let data = settings.serialize(&encryptor).unwrap();
+        assert!(!data.windows(5).any(|w| w == b"*.tmp"));
+        assert_eq!(
+            RepoSettings::deserialize(&data, &encryptor).unwrap(),
+            settings
+        );
+    }
+
+    #[test]
+    fn test_default_pack_size() {
+        use crate::storage::{AzureLocation, S3Location};
+
+        let local = RepositoryLocation::Local("/backup/repo".into());
+        assert_eq!(default_pack_size(&local), LOCAL_PACK_SIZE);
+
+        let mut s3 = S3Location::new("bucket".to_string(), String::new());
+        assert_eq!(
+            default_pack_size(&RepositoryLocation::S3(Box::new(s3.clone()))),
+            DEFAULT_PACK_SIZE
+        );
+        for class in ["GLACIER", "GLACIER_IR", "DEEP_ARCHIVE"] {
+            s3.storage_classes.set(&format!("data={}", class)).unwrap();
+            assert_eq!(
+                default_pack_size(&RepositoryLocation::S3(Box::new(s3.clone()))),
+                ARCHIVE_PACK_SIZE
+            );
+        }
+
+        let mut azure = AzureLocation::new(
+            "account".to_string(),
+            "container".to_string(),
+            String::new(),
+        );
+        azure.access_tiers.set("data=cool").unwrap();
+        assert_eq!(
+            default_pack_size(&RepositoryLocation::Azure(azure.clone())),
+            DEFAULT_PACK_SIZE
+        );
+        azure.access_tiers.set("data=archive").unwrap();
+        assert_eq!(
+            default_pack_size(&RepositoryLocation::Azure(azure)),
+            ARCHIVE_PACK_SIZE
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(RepoSettings::new().validate().is_ok());
+
+        let settings = RepoSettings {
+            compression_level: Some(12),
+            pack_size: Some(1024),
+            excludes: vec!["a[b".to_string()],
+            ..RepoSettings::new()
+        };
+        match settings.validate() {
+            Err(Error::InvalidConfig(errors)) => {
+                let fields: Vec<_> = errors.iter().map(|e| e.field.as_str()).collect();
+                assert_eq!(fields, ["compression_level", "pack_size", "excludes"]);
+            }
+            other => panic!("expected invalid settings, got {:?}", other),
+        }
+    }
+}
@@ -1,8 +1,9 @@
 use crate::crypto::Encryptor;
-use crate::{ChunkID, Error, Result, SnapshotID, TreeNode};
+use crate::{ChunkID, Error, PackID, Result, SnapshotID, TreeNode};
 use bytes::Bytes;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
 /// A snapshot represents a point-in-time backup of one or more paths.
@@ -35,28 +36,48 @@ pub struct Snapshot {
     pub time: DateTime<Utc>,
     pub tags: Vec<String>,
     pub excludes: Vec<String>,
+    /// Include patterns; when set, only matching files were backed up.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub includes: Vec<String>,
+    /// Set when every chunk was re-uploaded for this snapshot instead of being
+    /// deduplicated against data already in the repository.
+    #[serde(default)]
+    pub standalone: bool,
+    /// Packs written by a standalone backup. Together they hold every chunk
+    /// the snapshot's files reference, including copies of chunks the index
+    /// places elsewhere, and are kept for as long as the snapshot is.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packs: Vec<PackID>,
+    /// Source snapshots, for snapshots created by `merge`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged_from: Vec<SnapshotID>,
+    /// Names of the users owning files in the snapshot, by uid, as they were
+    /// on the source system. Lets restores map owners by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub user_names: BTreeMap<u32, String>,
+    /// Names of the groups owning files in the snapshot, by gid.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub group_names: BTreeMap<u32, String>,
 }
 
 impl Snapshot {
     pub fn new(paths: Vec<PathBuf>, tree: ChunkID) -> Self {
-        let hostname = hostname::get()
-            .map(|h| h.to_string_lossy().to_string())
-            .unwrap_or_else(|_| "unknown".to_string());
-
-        let username = std::env::var("USER")
-            .or_else(|_| std::env::var("USERNAME"))
-            .unwrap_or_else(|_| "unknown".to_string());
-
         Self {
-            id: uuid::Uuid::new_v4().to_string(),
+            id: SnapshotID::generate(),
             parent: None,
             tree,
             paths,
-            hostname,
-            username,
+            hostname: current_hostname(),
+            username: current_username(),
             time: Utc::now(),
             tags: Vec::new(),
             excludes: Vec::new(),
+            includes: Vec::new(),
+            standalone: false,
+            packs: Vec::new(),
+            merged_from: Vec::new(),
+            user_names: BTreeMap::new(),
+            group_names: BTreeMap::new(),
         }
     }
 
@@ -75,6 +96,52 @@ impl Snapshot {
         self
     }
 
+    pub fn with_includes(mut self, includes: Vec<String>) -> Self {
+        self.includes = includes;
+        self
+    }
+
+    pub fn with_standalone(mut self, standalone: bool) -> Self {
+        self.standalone = standalone;
+        self
+    }
+
+    /// Records the packs a standalone backup wrote.
+    pub fn with_packs(mut self, packs: Vec<PackID>) -> Self {
+        self.packs = packs;
+        self
+    }
+
+    /// Overrides the snapshot time, e.g. when importing historical data.
+    pub fn with_time(mut self, time: DateTime<Utc>) -> Self {
+        self.time = time;
+        self
+    }
+
+    /// Records the user and group names of the owners in the snapshot.
+    pub fn with_owner_names(
+        mut self,
+        user_names: BTreeMap<u32, String>,
+        group_names: BTreeMap<u32, String>,
+    ) -> Self {
+        self.user_names = user_names;
+        self.group_names = group_names;
+        self
+    }
+
+    /// Whether both snapshots back up the same paths, in any order. Node
+    /// names are relative to a snapshot's paths, so only then do their trees
+    /// share a root.
+    pub fn has_same_paths(&self, other: &Snapshot) -> bool {
+        let mut ours: Vec<&PathBuf> = self.paths.iter().collect();
+        let mut theirs: Vec<&PathBuf> = other.paths.iter().collect();
+        ours.sort();
+        ours.dedup();
+        theirs.sort();
+        theirs.dedup();
+        ours == theirs
+    }
+
     pub fn serialize(&self, encryptor: &Encryptor) -> Result<Bytes> {
         let json_data = serde_json::to_vec(self)
             .map_err(|e| Error::Other(format!("Failed to serialize snapshot: {}", e)))?;
@@ -89,7 +156,7 @@ impl Snapshot {
     }
 
     pub fn short_id(&self) -> String {
-        self.id.chars().take(8).collect()
+        self.id.short().to_string()
     }
 
     pub fn summary(&self) -> String {
@@ -103,9 +170,69 @@ impl Snapshot {
     }
 }
 
+/// Average number of nodes per page of a paged tree. Trees with more nodes
+/// than this are stored as pages, so that a pathological directory doesn't
+/// produce one tree object of hundreds of megabytes.
+pub const TREE_PAGE_NODES: usize = 10_000;
+
+/// Directories with at least this many nodes below them (not counting those
+/// in their own subtrees) are stored as a tree object of their own. Every
+/// tree object is a separate file in the repository, so smaller directories
+/// stay in their parent's object.
+pub const SUBTREE_MIN_NODES: usize = 1_000;
+
+/// A tree, or the root of a paged tree.
+///
+/// In memory a tree is a flat list of nodes named by their path. As stored,
+/// large directories are split off into subtrees: the directory's node keeps
+/// its place and refers to a tree object holding the nodes below it, named
+/// relative to the directory (see [`Tree::split_subtrees`]).
+///
+/// A paged tree has no nodes of its own: its nodes are sorted by name and
+/// split into [`TreePage`]s, each stored as a tree object. Small trees are
+/// stored whole and serialize exactly as before paging existed.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tree {
     pub nodes: Vec<TreeNode>,
+    /// Pages holding the nodes, in name order (paged trees only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pages: Vec<TreePage>,
+}
+
+/// One page of a paged tree: a tree object holding the nodes from `first`
+/// to `last` by name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreePage {
+    pub tree: ChunkID,
+    pub nodes: usize,
+    pub first: String,
+    pub last: String,
+}
+
+impl TreePage {
+    /// Whether the page may hold the node named `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.first.as_str() <= name && name <= self.last.as_str()
+    }
+
+    /// Whether the page may hold nodes whose names start with `prefix`.
+    /// Names with a common prefix are adjacent in name order, so only pages
+    /// overlapping that range qualify.
+    pub fn overlaps_prefix(&self, prefix: &str) -> bool {
+        self.last.as_str() >= prefix
+            && (self.first.as_str() <= prefix || self.first.starts_with(prefix))
+    }
+}
+
+/// One tree object of a tree split into subtrees; see
+/// [`Tree::split_subtrees`].
+#[derive(Debug)]
+pub struct TreeLevel {
+    /// Nodes stored in this object, named relative to its directory
+    pub tree: Tree,
+    /// The level and node index of the directory this level is the subtree
+    /// of, or `None` for the root
+    pub parent: Option<(usize, usize)>,
 }
 
 impl Default for Tree {
@@ -116,17 +243,135 @@ impl Default for Tree {
 
 impl Tree {
     pub fn new() -> Self {
-        Self { nodes: Vec::new() }
+        Self {
+            nodes: Vec::new(),
+            pages: Vec::new(),
+        }
+    }
+
+    /// Whether this is the root of a paged tree.
+    pub fn is_paged(&self) -> bool {
+        !self.pages.is_empty()
+    }
+
+    /// Sorts the nodes by name and splits them into pages of about
+    /// `page_nodes` nodes.
+    ///
+    /// Page boundaries fall after nodes whose name hashes to a boundary
+    /// (between a quarter and four times `page_nodes` apart), so adding or
+    /// removing a file only changes the page holding it and unchanged pages
+    /// are deduplicated against the previous snapshot.
+    pub fn split_pages(&self, page_nodes: usize) -> Vec<Tree> {
+        let page_nodes = page_nodes.max(1);
+        let mut nodes: Vec<&TreeNode> = self.nodes.iter().collect();
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut pages = Vec::new();
+        let mut page = Tree::new();
+        for node in nodes {
+            page.add_node(node.clone());
+            let hash = blake3::hash(node.name.as_bytes());
+            let boundary = u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap())
+                % page_nodes as u64
+                == 0;
+            if (boundary && page.nodes.len() >= page_nodes / 4)
+                || page.nodes.len() >= page_nodes * 4
+            {
+                pages.push(std::mem::take(&mut page));
+            }
+        }
+        if !page.nodes.is_empty() {
+            pages.push(page);
+        }
+        pages
+    }
+
+    /// Splits the tree into the tree objects it is stored as, building
+    /// subtrees bottom-up: a directory whose nodes below it, less those in
+    /// its own subtrees, number at least `min_nodes` becomes a subtree.
+    ///
+    /// Levels come before the level holding their directory's node, so they
+    /// can be saved in order; the root is last. Unchanged directories
+    /// produce unchanged levels, which are deduplicated against earlier
+    /// snapshots. A tree without large directories is a single level equal
+    /// to the tree.
+    pub fn split_subtrees(&self, min_nodes: usize) -> Vec<TreeLevel> {
+        let nodes = &self.nodes;
+        let mut by_name: Vec<usize> = (0..nodes.len()).collect();
+        by_name.sort_by(|&a, &b| nodes[a].name.cmp(&nodes[b].name));
+
+        // Deepest directories first, so each node ends up in the subtree of
+        // its nearest split directory
+        let depth = |i: usize| nodes[i].name.matches('/').count();
+        let mut dirs: Vec<usize> = (0..nodes.len()).filter(|&i| nodes[i].is_dir()).collect();
+        dirs.sort_by(|&a, &b| {
+            depth(b)
+                .cmp(&depth(a))
+                .then_with(|| nodes[a].name.cmp(&nodes[b].name))
+        });
+
+        let mut owner: Vec<Option<usize>> = vec![None; nodes.len()];
+        let mut split: Vec<usize> = Vec::new();
+        for dir in dirs {
+            let prefix = format!("{}/", nodes[dir].name);
+            let start = by_name.partition_point(|&i| nodes[i].name.as_str() < prefix.as_str());
+            let below: Vec<usize> = by_name[start..]
+                .iter()
+                .copied()
+                .take_while(|&i| nodes[i].name.starts_with(&prefix))
+                .filter(|&i| owner[i].is_none())
+                .collect();
+            if below.len() >= min_nodes.max(1) {
+                for i in below {
+                    owner[i] = Some(dir);
+                }
+                split.push(dir);
+            }
+        }
+
+        let root = split.len();
+        let mut level_of = HashMap::new();
+        let mut levels: Vec<TreeLevel> = Vec::with_capacity(root + 1);
+        for (level, &dir) in split.iter().enumerate() {
+            level_of.insert(dir, level);
+            levels.push(TreeLevel {
+                tree: Tree::new(),
+                parent: None,
+            });
+        }
+        levels.push(TreeLevel {
+            tree: Tree::new(),
+            parent: None,
+        });
+
+        for (i, node) in nodes.iter().enumerate() {
+            let level = owner[i].map_or(root, |dir| level_of[&dir]);
+            let mut node = node.clone();
+            node.subtree_id = None;
+            if let Some(dir) = owner[i] {
+                node.name = node.name[nodes[dir].name.len() + 1..].to_string();
+            }
+            if let Some(&subtree) = level_of.get(&i) {
+                levels[subtree].parent = Some((level, levels[level].tree.nodes.len()));
+            }
+            levels[level].tree.add_node(node);
+        }
+        levels
     }
 
     pub fn add_node(&mut self, node: TreeNode) {
         self.nodes.push(node);
     }
 
+    /// Serializes the tree before encryption. Serialization is deterministic,
+    /// so equal trees produce equal bytes and therefore equal tree IDs.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self)
+            .map_err(|e| Error::Other(format!("Failed to serialize tree: {}", e)))
+    }
+
     pub fn serialize(&self, encryptor: &Encryptor) -> Result<Bytes> {
-        let json_data = serde_json::to_vec(self)
-            .map_err(|e| Error::Other(format!("Failed to serialize tree: {}", e)))?;
-        let encrypted_data = encryptor.encrypt(&json_data)?;
+        let encrypted_data = encryptor.encrypt(&self.to_bytes()?)?;
         Ok(Bytes::from(encrypted_data))
     }
 
@@ -140,8 +385,14 @@ impl Tree {
         self.nodes.iter().find(|node| node.name == path)
     }
 
+    /// Size of the regular files in the tree. Directory and symlink sizes
+    /// are filesystem metadata, not backed-up data.
     pub fn total_size(&self) -> u64 {
-        self.nodes.iter().map(|node| node.size).sum()
+        self.nodes
+            .iter()
+            .filter(|node| node.is_file())
+            .map(|node| node.size)
+            .sum()
     }
 
     pub fn file_count(&self) -> usize {
@@ -151,6 +402,67 @@ impl Tree {
     pub fn dir_count(&self) -> usize {
         self.nodes.iter().filter(|node| node.is_dir()).count()
     }
+
+    /// Returns the cumulative size of every directory: the sizes of all files
+    /// and symlinks below it. Hardlinks are counted once, at the original.
+    ///
+    /// Parent directories that have no node of their own (above the backed-up
+    /// paths) are included as well.
+    pub fn cumulative_sizes(&self) -> HashMap<String, u64> {
+        let mut sizes: HashMap<String, u64> = HashMap::new();
+        for node in &self.nodes {
+            if node.is_dir() {
+                sizes.entry(node.name.clone()).or_default();
+                continue;
+            }
+            if node.hardlink_target.is_some() {
+                continue;
+            }
+            let mut path = node.name.as_str();
+            while let Some((parent, _)) = path.rsplit_once('/') {
+                *sizes.entry(parent.to_string()).or_default() += node.size;
+                path = parent;
+            }
+        }
+        sizes
+    }
+
+    /// Replaces the chunk IDs found in `ids`, e.g. when the chunks move to a
+    /// repository that hashes them differently.
+    pub fn replace_chunk_ids(&mut self, ids: &HashMap<ChunkID, ChunkID>) {
+        for chunk_ref in self.nodes.iter_mut().flat_map(|node| &mut node.chunks) {
+            if let Some(id) = ids.get(&chunk_ref.id) {
+                chunk_ref.id = *id;
+            }
+        }
+    }
+
+    /// Builds the union of several trees, given oldest first.
+    ///
+    /// A node in a later tree replaces the node with the same path in an
+    /// earlier one. When a directory is replaced by a file or symlink, the
+    /// directory's earlier contents are dropped.
+    pub fn merge<'a>(trees: impl IntoIterator<Item = &'a Tree>) -> Tree {
+        let mut nodes: BTreeMap<String, TreeNode> = BTreeMap::new();
+        for tree in trees {
+            for node in &tree.nodes {
+                if !node.is_dir()
+                    && nodes
+                        .get(&node.name)
+                        .is_some_and(|existing| existing.is_dir())
+                {
+                    let prefix = format!("{}/", node.name);
+                    nodes.retain(|name, _| !name.starts_with(&prefix));
+                }
+                nodes.insert(node.name.clone(), node.clone());
+            }
+        }
+
+        Tree {
+            nodes: nodes.into_values().collect(),
+            pages: Vec::new(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -224,4 +536,140 @@ impl SnapshotManager {
     }
 }
 
+/// Name of this host, or `unknown`.
+pub fn current_hostname() -> String {
+    hostname::get()
+        .map(|h| h.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Name of the user running ghostsnap, or `unknown`.
+pub(crate) fn current_username() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
 use hostname;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NodeType;
+
+    fn node(name: &str) -> TreeNode {
+        TreeNode {
+            size: 1,
+            mtime: 1_700_000_000,
+            ..TreeNode::test_file(name, &[])
+        }
+    }
+
+    fn page_of(tree: &Tree) -> TreePage {
+        TreePage {
+            tree: ChunkID::from_data(&tree.to_bytes().unwrap()),
+            nodes: tree.nodes.len(),
+            first: tree.nodes.first().unwrap().name.clone(),
+            last: tree.nodes.last().unwrap().name.clone(),
+        }
+    }
+
+    #[test]
+    fn test_split_pages() {
+        let mut tree = Tree::new();
+        for i in (0..2000).rev() {
+            tree.add_node(node(&format!("big/file-{:05}", i)));
+        }
+
+        let pages = tree.split_pages(100);
+        assert!(pages.len() > 1);
+        assert!(pages.iter().all(|page| page.nodes.len() <= 400));
+        let names: Vec<_> = pages
+            .iter()
+            .flat_map(|page| page.nodes.iter().map(|node| node.name.clone()))
+            .collect();
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(names, sorted);
+        assert_eq!(names.len(), 2000);
+
+        // Removing a file leaves the pages before it, and most after it, as
+        // they were
+        tree.nodes.retain(|node| node.name != "big/file-01000");
+        let before: Vec<_> = pages.iter().map(|p| page_of(p).tree).collect();
+        let after: Vec<_> = tree
+            .split_pages(100)
+            .iter()
+            .map(|p| page_of(p).tree)
+            .collect();
+        assert_eq!(before[0], after[0]);
+        let unchanged = after.iter().filter(|id| before.contains(id)).count();
+        assert!(unchanged >= before.len() / 3);
+    }
+
+    #[test]
+    fn test_split_subtrees() {
+        let dir = |name: &str| TreeNode {
+            node_type: NodeType::Directory,
+            ..node(name)
+        };
+        let mut tree = Tree::new();
+        tree.add_node(dir("site"));
+        tree.add_node(dir("site/big"));
+        for i in 0..4 {
+            tree.add_node(node(&format!("site/big/{}.txt", i)));
+        }
+        tree.add_node(dir("site/small"));
+        tree.add_node(node("site/small/index.php"));
+        tree.add_node(node("loose/file.txt"));
+
+        let levels = tree.split_subtrees(4);
+        assert_eq!(levels.len(), 2);
+        let (big, root) = (&levels[0], &levels[1]);
+        let names = |tree: &Tree| -> Vec<String> {
+            tree.nodes.iter().map(|node| node.name.clone()).collect()
+        };
+        assert_eq!(names(&big.tree), ["0.txt", "1.txt", "2.txt", "3.txt"]);
+        assert_eq!(
+            names(&root.tree),
+            [
+                "site",
+                "site/big",
+                "site/small",
+                "site/small/index.php",
+                "loose/file.txt"
+            ]
+        );
+        assert_eq!(big.parent, Some((1, 1)));
+        assert!(root.parent.is_none());
+
+        // Without large directories the tree is stored as it is
+        let levels = tree.split_subtrees(10);
+        assert_eq!(levels.len(), 1);
+        assert_eq!(levels[0].tree.to_bytes().unwrap(), tree.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_page_ranges() {
+        let mut tree = Tree::new();
+        for name in ["a", "a.txt", "a/b", "a/c", "b"] {
+            tree.add_node(node(name));
+        }
+        let pages: Vec<_> = tree.split_pages(1).iter().map(page_of).collect();
+        let page = |name: &str| pages.iter().find(|p| p.first == name).unwrap();
+
+        assert!(page("a/b").overlaps_prefix("a/"));
+        assert!(page("a/c").overlaps_prefix("a/"));
+        assert!(!page("a").overlaps_prefix("a/"));
+        assert!(!page("a.txt").overlaps_prefix("a/"));
+        assert!(!page("b").overlaps_prefix("a/"));
+        assert!(page("a.txt").overlaps_prefix("a"));
+        assert!(page("b").overlaps_prefix(""));
+        assert!(page("a").contains("a"));
+        assert!(!page("a").contains("a/b"));
+
+        // Unpaged trees serialize without a pages field
+        let json = String::from_utf8(tree.to_bytes().unwrap()).unwrap();
+        assert!(!json.contains("pages"));
+    }
+}
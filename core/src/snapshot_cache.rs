@@ -0,0 +1,119 @@
+//! Cached snapshot summaries for fast listing.
+//!
+//! Listing snapshots otherwise means reading and decrypting every snapshot
+//! object, plus its tree to count files. The [`SnapshotCache`] keeps each
+//! snapshot's metadata and file count in one encrypted object at
+//! `index/snapshots.cache`. Snapshots are never rewritten under the same ID,
+//! so bringing the cache up to date only needs a listing of `snapshots/` and
+//! a read of the snapshots that are new since it was last written.
+
+use crate::crypto::Encryptor;
+use crate::snapshot::Snapshot;
+use crate::types::SnapshotID;
+use crate::{Error, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Storage path of the encrypted snapshot summary cache.
+pub const SNAPSHOT_CACHE_PATH: &str = "index/snapshots.cache";
+
+/// Snapshot cache format version for schema evolution.
+const SNAPSHOT_CACHE_VERSION: u32 = 1;
+
+/// Listing information for a single snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotSummary {
+    pub snapshot: Snapshot,
+    /// Number of regular files in the snapshot's tree
+    pub file_count: u64,
+}
+
+/// Encrypted, incrementally maintained snapshot summaries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotCache {
+    pub version: u32,
+    pub updated_at: DateTime<Utc>,
+    pub snapshots: BTreeMap<SnapshotID, SnapshotSummary>,
+}
+
+impl Default for SnapshotCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SnapshotCache {
+    pub fn new() -> Self {
+        Self {
+            version: SNAPSHOT_CACHE_VERSION,
+            updated_at: Utc::now(),
+            snapshots: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the summaries ordered by snapshot time, oldest first.
+    pub fn sorted(&self) -> Vec<SnapshotSummary> {
+        let mut summaries: Vec<_> = self.snapshots.values().cloned().collect();
+        summaries.sort_by(|a, b| {
+            a.snapshot
+                .time
+                .cmp(&b.snapshot.time)
+                .then_with(|| a.snapshot.id.cmp(&b.snapshot.id))
+        });
+        summaries
+    }
+
+    pub fn serialize(&self, encryptor: &Encryptor) -> Result<Vec<u8>> {
+        let json_data = serde_json::to_vec(self)
+            .map_err(|e| Error::Other(format!("Failed to serialize snapshot cache: {}", e)))?;
+        encryptor.encrypt(&json_data)
+    }
+
+    pub fn deserialize(data: &[u8], encryptor: &Encryptor) -> Result<Self> {
+        let decrypted_data = encryptor.decrypt(data)?;
+        let cache: Self = serde_json::from_slice(&decrypted_data)
+            .map_err(|e| Error::Other(format!("Failed to deserialize snapshot cache: {}", e)))?;
+        if cache.version != SNAPSHOT_CACHE_VERSION {
+            return Err(Error::InvalidFormatVersion {
+                version: cache.version,
+            });
+        }
+        Ok(cache)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChunkID;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_snapshot_cache_roundtrip_sorted() {
+        let encryptor = Encryptor::new(&[7u8; 32]).unwrap();
+
+        let mut cache = SnapshotCache::new();
+        let mut older = Snapshot::new(vec![PathBuf::from("/data")], ChunkID::from_data(b"t1"));
+        older.time -= chrono::Duration::days(1);
+        let newer = Snapshot::new(vec![PathBuf::from("/data")], ChunkID::from_data(b"t2"));
+        for (snapshot, file_count) in [(newer.clone(), 5), (older.clone(), 3)] {
+            cache.snapshots.insert(
+                snapshot.id.clone(),
+                SnapshotSummary {
+                    snapshot,
+                    file_count,
+                },
+            );
+        }
+
+        let data = cache.serialize(&encryptor).unwrap();
+        let restored = SnapshotCache::deserialize(&data, &encryptor).unwrap();
+
+        let sorted = restored.sorted();
+        assert_eq!(sorted.len(), 2);
+        assert_eq!(sorted[0].snapshot.id, older.id);
+        assert_eq!(sorted[0].file_count, 3);
+        assert_eq!(sorted[1].snapshot.id, newer.id);
+    }
+}
@@ -0,0 +1,165 @@
+//! Selecting snapshots by host, tag and path.
+//!
+//! `snapshots`, `forget` and `restore` take the same `--host`, `--tag` and
+//! `--path` flags; they all go through [`SnapshotFilter`] so that a filter
+//! lists, forgets and restores exactly the same snapshots.
+
+use crate::snapshot::Snapshot;
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+
+/// Matches snapshots by host, tag and path. An empty filter matches every
+/// snapshot.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotFilter {
+    /// Snapshot must come from one of these hosts
+    pub hosts: Vec<String>,
+    /// Snapshot must carry at least one of these tags
+    pub tags: Vec<String>,
+    /// Snapshot must include all of these paths
+    pub paths: Vec<PathBuf>,
+}
+
+impl SnapshotFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.hosts.push(host.into());
+        self
+    }
+
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.paths.push(path.into());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty() && self.tags.is_empty() && self.paths.is_empty()
+    }
+
+    pub fn matches(&self, snapshot: &Snapshot) -> bool {
+        (self.hosts.is_empty() || self.hosts.contains(&snapshot.hostname))
+            && (self.tags.is_empty() || snapshot.tags.iter().any(|tag| self.tags.contains(tag)))
+            && self.paths.iter().all(|path| snapshot.paths.contains(path))
+    }
+
+    /// The newest matching snapshot, taken at or before `at` if given.
+    pub fn newest<'a>(
+        &self,
+        snapshots: impl IntoIterator<Item = &'a Snapshot>,
+        at: Option<DateTime<Utc>>,
+    ) -> Option<&'a Snapshot> {
+        snapshots
+            .into_iter()
+            .filter(|snapshot| at.is_none_or(|at| snapshot.time <= at) && self.matches(snapshot))
+            .max_by_key(|snapshot| snapshot.time)
+    }
+
+    /// Describes the filter for messages, e.g. "host web01, tag daily".
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.hosts.is_empty() {
+            parts.push(format!("host {}", self.hosts.join(" or ")));
+        }
+        if !self.tags.is_empty() {
+            parts.push(format!("tag {}", self.tags.join(" or ")));
+        }
+        for path in &self.paths {
+            parts.push(format!("path {}", path.display()));
+        }
+        parts.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChunkID;
+    use chrono::{Duration, TimeZone};
+
+    fn snapshot(host: &str, tags: &[&str], paths: &[&str], hours_ago: i64) -> Snapshot {
+        let now = Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap();
+        let mut snapshot = Snapshot::new(
+            paths.iter().map(PathBuf::from).collect(),
+            ChunkID::from_data(b"t"),
+        )
+        .with_tags(tags.iter().map(|t| t.to_string()).collect())
+        .with_time(now - Duration::hours(hours_ago));
+        snapshot.hostname = host.to_string();
+        snapshot
+    }
+
+    #[test]
+    fn test_matches() {
+        let web = snapshot("web01", &["daily"], &["/etc", "/var/www"], 0);
+
+        assert!(SnapshotFilter::new().matches(&web));
+        assert!(SnapshotFilter::new().with_host("web01").matches(&web));
+        assert!(!SnapshotFilter::new().with_host("db01").matches(&web));
+        assert!(
+            SnapshotFilter::new()
+                .with_host("db01")
+                .with_host("web01")
+                .matches(&web)
+        );
+        assert!(
+            SnapshotFilter::new()
+                .with_tag("weekly")
+                .with_tag("daily")
+                .matches(&web)
+        );
+        assert!(!SnapshotFilter::new().with_tag("weekly").matches(&web));
+        assert!(SnapshotFilter::new().with_path("/etc").matches(&web));
+        assert!(
+            !SnapshotFilter::new()
+                .with_path("/etc")
+                .with_path("/home")
+                .matches(&web)
+        );
+        assert!(
+            !SnapshotFilter::new()
+                .with_host("web01")
+                .with_tag("weekly")
+                .matches(&web)
+        );
+    }
+
+    #[test]
+    fn test_newest() {
+        let snapshots = [
+            snapshot("web01", &[], &["/etc"], 48),
+            snapshot("web01", &[], &["/etc"], 24),
+            snapshot("db01", &[], &["/var/lib"], 1),
+        ];
+        let web = SnapshotFilter::new().with_host("web01");
+
+        assert_eq!(web.newest(&snapshots, None).unwrap().id, snapshots[1].id);
+        assert_eq!(
+            SnapshotFilter::new().newest(&snapshots, None).unwrap().id,
+            snapshots[2].id
+        );
+        let at = snapshots[1].time - Duration::minutes(1);
+        assert_eq!(
+            web.newest(&snapshots, Some(at)).unwrap().id,
+            snapshots[0].id
+        );
+        let before_all = snapshots[0].time - Duration::minutes(1);
+        assert!(web.newest(&snapshots, Some(before_all)).is_none());
+
+        assert_eq!(
+            SnapshotFilter::new()
+                .with_host("web01")
+                .with_tag("daily")
+                .with_path("/etc")
+                .describe(),
+            "host web01, tag daily, path /etc"
+        );
+    }
+}
@@ -0,0 +1,736 @@
+//! Sources of backup data.
+//!
+//! A [`BackupSource`] enumerates the entries of something to back up — a
+//! scanned directory tree, a tar stream — and opens the contents of its
+//! files. [`SourceWriter`] turns sources into a tree: it chunks contents,
+//! deduplicates them against the repository and writes new chunks to packs,
+//! so a new kind of source only has to describe its entries.
+//!
+//! ```text
+//! source.next_entry() ──► metadata ──┬──────────────────────────► tree
+//!                                    └─► source.open_entry() ──► chunks
+//! ```
+//!
+//! With [`SourceWriter::with_jobs`], entries are read and chunked on tasks
+//! of their own, a few entries ahead, and full packs upload in the
+//! background; bounded queues between the stages hold back reading when
+//! uploads fall behind.
+
+use crate::chunker::{Chunk, Chunker};
+use crate::pack::{PackFile, PackManager};
+use crate::ratelimit::RateLimiter;
+use crate::repository::Repository;
+use crate::snapshot::Tree;
+use crate::types::{ChunkID, ChunkRef, NodeType, PackID, TreeNode};
+use crate::{Error, Result};
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, TryStreamExt};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Read;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Pack size of new backups on remote backends unless the repository
+/// settings set one; see [`crate::settings::default_pack_size`].
+pub const DEFAULT_PACK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Chunks an entry read ahead may have waiting for the writer.
+const CHUNKS_AHEAD: usize = 4;
+
+/// Entries to back up, read one at a time.
+pub trait BackupSource {
+    /// Returns the metadata of the next entry, or `None` after the last.
+    /// `name` is the entry's path within the snapshot; `chunks` is left
+    /// empty and filled in by the writer.
+    fn next_entry(&mut self) -> Result<Option<TreeNode>>;
+
+    /// Opens the contents of the entry last returned by
+    /// [`next_entry`](Self::next_entry). Only called for entries that
+    /// [`has_contents`].
+    fn open_entry(&mut self) -> Result<Box<dyn Read + '_>>;
+
+    /// Like [`open_entry`](Self::open_entry), but returns a reader that can
+    /// be read on another task, so that [`SourceWriter::with_jobs`] can read
+    /// several entries at once. The default returns `None`, and entries are
+    /// read one at a time with `open_entry`.
+    fn open_entry_async(&mut self) -> Result<Option<Box<dyn AsyncRead + Send + Unpin>>> {
+        Ok(None)
+    }
+}
+
+/// Whether the contents of `node` are stored: regular files, except
+/// hardlinks to an entry stored earlier.
+pub fn has_contents(node: &TreeNode) -> bool {
+    node.node_type == NodeType::File && node.hardlink_target.is_none()
+}
+
+/// Files found by scanning the filesystem, with the path to read each from.
+///
+/// Scanning happens up front so that callers can count files and check
+/// limits before anything is written.
+pub struct FsSource {
+    entries: std::vec::IntoIter<(PathBuf, TreeNode)>,
+    current: Option<PathBuf>,
+}
+
+impl FsSource {
+    pub fn new(entries: Vec<(PathBuf, TreeNode)>) -> Self {
+        Self {
+            entries: entries.into_iter(),
+            current: None,
+        }
+    }
+}
+
+impl BackupSource for FsSource {
+    fn next_entry(&mut self) -> Result<Option<TreeNode>> {
+        Ok(self.entries.next().map(|(path, node)| {
+            self.current = Some(path);
+            node
+        }))
+    }
+
+    fn open_entry(&mut self) -> Result<Box<dyn Read + '_>> {
+        let path = self
+            .current
+            .as_ref()
+            .ok_or_else(|| Error::Other("No entry to open".to_string()))?;
+        Ok(Box::new(std::fs::File::open(path)?))
+    }
+
+    fn open_entry_async(&mut self) -> Result<Option<Box<dyn AsyncRead + Send + Unpin>>> {
+        let path = self
+            .current
+            .as_ref()
+            .ok_or_else(|| Error::Other("No entry to open".to_string()))?;
+        let file = std::fs::File::open(path)?;
+        Ok(Some(Box::new(tokio::fs::File::from_std(file))))
+    }
+}
+
+/// What storing one entry's contents wrote.
+#[derive(Debug, Clone)]
+pub struct StoredContents {
+    pub size: u64,
+    /// BLAKE3 hash of the whole contents, as recorded in manifests
+    pub content_hash: blake3::Hash,
+    pub new_chunks: u64,
+    pub dedup_chunks: u64,
+}
+
+/// Notified as a [`SourceWriter`] adds entries to a tree.
+pub trait SourceObserver {
+    /// Called for every entry added, with what storing its contents wrote.
+    fn added(&mut self, _node: &TreeNode, _contents: Option<&StoredContents>) {}
+
+    /// Called instead of [`added`](Self::added) for a file whose chunks were
+    /// taken from the parent tree without reading it (see
+    /// [`SourceWriter::with_parent`]). The default reports it as added
+    /// without contents.
+    fn unchanged(&mut self, node: &TreeNode) {
+        self.added(node, None);
+    }
+
+    /// Called when an entry's contents cannot be read from the source.
+    /// Returning `Ok` leaves the entry out and continues; the default
+    /// aborts. Errors writing to the repository always abort the backup.
+    fn failed(&mut self, _node: &TreeNode, error: Error) -> Result<()> {
+        Err(error)
+    }
+}
+
+impl SourceObserver for () {}
+
+/// Chunks the contents of backup sources and writes new chunks to packs.
+pub struct SourceWriter<'a> {
+    repo: &'a Repository,
+    chunker: Arc<Chunker>,
+    pack_manager: PackManager,
+    read_limiter: Option<&'a RateLimiter>,
+    standalone: bool,
+    jobs: usize,
+    parent: Option<ParentTree>,
+    /// Where full packs go while [`add_source`](Self::add_source) uploads
+    /// them in the background; `None` saves them in place
+    uploads: Option<mpsc::Sender<PackFile>>,
+    /// Chunks written by this writer, which the index only learns about
+    /// when their pack is saved
+    written: HashSet<ChunkID>,
+    /// Packs written by a standalone writer
+    packs: Vec<PackID>,
+    bytes_added: u64,
+    /// Files whose contents could not be stored, and were left out
+    left_out: HashSet<String>,
+    /// Hardlinks stored in place of an original that was left out, by the
+    /// original's name
+    relinked: HashMap<String, String>,
+}
+
+impl<'a> SourceWriter<'a> {
+    pub fn new(repo: &'a Repository) -> Self {
+        Self {
+            repo,
+            chunker: Arc::new(repo.chunker()),
+            pack_manager: repo.pack_manager(repo.target_pack_size()),
+            read_limiter: None,
+            standalone: false,
+            jobs: 1,
+            parent: None,
+            uploads: None,
+            written: HashSet::new(),
+            packs: Vec::new(),
+            bytes_added: 0,
+            left_out: HashSet::new(),
+            relinked: HashMap::new(),
+        }
+    }
+
+    /// Limits how many entries are opened per second.
+    pub fn with_read_limiter(mut self, limiter: Option<&'a RateLimiter>) -> Self {
+        self.read_limiter = limiter;
+        self
+    }
+
+    /// Cuts new packs at `size` instead of the repository's target size.
+    pub fn with_pack_size(mut self, size: u64) -> Self {
+        self.pack_manager = self.repo.pack_manager(size);
+        self
+    }
+
+    /// Only deduplicates against chunks written by this writer, so that
+    /// every chunk the tree references is written again. Chunks the index
+    /// already has keep their location; record [`packs`](Self::packs) in
+    /// the snapshot so that prune keeps the copies.
+    pub fn with_standalone(mut self, standalone: bool) -> Self {
+        self.standalone = standalone;
+        self
+    }
+
+    /// Lets [`add_source`](Self::add_source) read and chunk up to `jobs`
+    /// entries at once, each on its own task, while up to `jobs` packs
+    /// upload. Entries are still added in source order. Sources that can't
+    /// hand out readers for other tasks (see
+    /// [`BackupSource::open_entry_async`]) are read one entry at a time.
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Takes the chunks of files unchanged since `parent`, a tree saved at
+    /// `time`, from it instead of reading them again. A file counts as
+    /// unchanged if its size, mtime and inode match and it wasn't modified
+    /// in the second the parent was taken, when a change might not show in
+    /// the mtime. Ignored for standalone writers.
+    pub fn with_parent(mut self, parent: &Tree, time: DateTime<Utc>) -> Self {
+        self.parent = Some(ParentTree {
+            nodes: parent
+                .nodes
+                .iter()
+                .map(|node| (node.name.clone(), node.clone()))
+                .collect(),
+            time: time.timestamp(),
+        });
+        self
+    }
+
+    /// Bytes of new chunk data written, before compression.
+    pub fn bytes_added(&self) -> u64 {
+        self.bytes_added
+    }
+
+    /// Packs written by a standalone writer; empty otherwise.
+    pub fn packs(&self) -> &[PackID] {
+        &self.packs
+    }
+
+    /// Adds every entry of `source` to `tree`, storing their contents.
+    pub async fn add_source<S: BackupSource + ?Sized>(
+        &mut self,
+        source: &mut S,
+        tree: &mut Tree,
+        observer: &mut dyn SourceObserver,
+    ) -> Result<()> {
+        if self.jobs == 1 {
+            while let Some(mut node) = source.next_entry()? {
+                self.relink(&mut node);
+                if !has_contents(&node) {
+                    observer.added(&node, None);
+                    tree.add_node(node);
+                    continue;
+                }
+                if let Some(chunks) = self.unchanged_chunks(&node).await? {
+                    node.chunks = chunks;
+                    observer.unchanged(&node);
+                    tree.add_node(node);
+                    continue;
+                }
+                self.throttle_read().await;
+                let stored = self.store_entry(source).await;
+                self.add_stored(node, stored, tree, observer)?;
+            }
+            return Ok(());
+        }
+
+        // Packs go through a short queue to uploads running alongside
+        // reading, so that a slow backend holds back reading
+        let (uploads, mut queued) = mpsc::channel::<PackFile>(1);
+        self.uploads = Some(uploads);
+        let repo = self.repo;
+        let jobs = self.jobs;
+        let standalone = self.standalone;
+        let upload = async move {
+            futures::stream::poll_fn(|cx| queued.poll_recv(cx))
+                .map(|pack| async move { save_pack(repo, &pack, standalone).await })
+                .buffer_unordered(jobs)
+                .try_collect::<()>()
+                .await
+        };
+        let add = async {
+            let result = self.add_read_ahead(source, tree, observer).await;
+            // Closes the queue, so the uploads finish
+            self.uploads = None;
+            result
+        };
+
+        let (added, uploaded) = futures::join!(add, upload);
+        uploaded.and(added)
+    }
+
+    /// Adds the entries of `source`, reading up to `jobs` of them ahead.
+    async fn add_read_ahead<S: BackupSource + ?Sized>(
+        &mut self,
+        source: &mut S,
+        tree: &mut Tree,
+        observer: &mut dyn SourceObserver,
+    ) -> Result<()> {
+        let mut ahead: VecDeque<(TreeNode, Pending)> = VecDeque::new();
+        let mut listed = false;
+        loop {
+            while !listed && ahead.len() < self.jobs {
+                let Some(mut node) = source.next_entry()? else {
+                    listed = true;
+                    break;
+                };
+                self.relink(&mut node);
+                if let Some(original) = &node.hardlink_target
+                    && ahead.iter().any(|(pending, _)| &pending.name == original)
+                {
+                    // The original is still being read; keep the link open
+                    // to store it in its place should that fail
+                    let fallback = source.open_entry_async().ok().flatten();
+                    ahead.push_back((node, Pending::Link(fallback)));
+                    continue;
+                }
+                if !has_contents(&node) {
+                    ahead.push_back((node, Pending::NoContents));
+                    continue;
+                }
+                if let Some(chunks) = self.unchanged_chunks(&node).await? {
+                    node.chunks = chunks;
+                    ahead.push_back((node, Pending::Unchanged));
+                    continue;
+                }
+
+                self.throttle_read().await;
+                match source.open_entry_async() {
+                    Ok(Some(reader)) => {
+                        let read = self.read_ahead(reader);
+                        ahead.push_back((node, Pending::Read(Ok(read))));
+                    }
+                    Ok(None) => {
+                        // Only readable in place, after the entries before it
+                        while let Some((node, pending)) = ahead.pop_front() {
+                            self.add_entry(node, pending, tree, observer).await?;
+                        }
+                        let stored = self.store_entry(source).await;
+                        self.add_stored(node, stored, tree, observer)?;
+                    }
+                    Err(e) => ahead.push_back((node, Pending::Read(Err(e)))),
+                }
+            }
+
+            let Some((node, pending)) = ahead.pop_front() else {
+                return Ok(());
+            };
+            self.add_entry(node, pending, tree, observer).await?;
+        }
+    }
+
+    /// Adds an entry read ahead, storing the chunks read so far and
+    /// waiting for the rest.
+    async fn add_entry(
+        &mut self,
+        mut node: TreeNode,
+        pending: Pending,
+        tree: &mut Tree,
+        observer: &mut dyn SourceObserver,
+    ) -> Result<()> {
+        let stored = match pending {
+            Pending::NoContents => {
+                observer.added(&node, None);
+                tree.add_node(node);
+                return Ok(());
+            }
+            Pending::Unchanged => {
+                observer.unchanged(&node);
+                tree.add_node(node);
+                return Ok(());
+            }
+            Pending::Link(fallback) => {
+                self.relink(&mut node);
+                if !has_contents(&node) {
+                    observer.added(&node, None);
+                    tree.add_node(node);
+                    return Ok(());
+                }
+                match fallback {
+                    Some(reader) => self.store_read_ahead(self.read_ahead(reader)).await,
+                    None => Err(StoreError::Read(Error::Other(format!(
+                        "Cannot read {} in place of its hardlink original",
+                        node.name
+                    )))),
+                }
+            }
+            Pending::Read(Ok(read)) => self.store_read_ahead(read).await,
+            Pending::Read(Err(e)) => Err(StoreError::Read(e)),
+        };
+        self.add_stored(node, stored, tree, observer)
+    }
+
+    /// Points a hardlink at the link stored in place of its original, if
+    /// that was left out, or stores the hardlink's own contents when no
+    /// link has taken the original's place yet.
+    fn relink(&mut self, node: &mut TreeNode) {
+        let Some(mut original) = node.hardlink_target.take() else {
+            return;
+        };
+        while let Some(stored) = self.relinked.get(&original) {
+            original = stored.clone();
+        }
+        if self.left_out.contains(&original) {
+            self.relinked.insert(original, node.name.clone());
+        } else {
+            node.hardlink_target = Some(original);
+        }
+    }
+
+    /// Adds `node` to `tree` with its stored contents, or reports why they
+    /// couldn't be read. Failed writes are returned, since later entries
+    /// would be deduplicated against chunks that never made it.
+    fn add_stored(
+        &mut self,
+        mut node: TreeNode,
+        stored: Stored,
+        tree: &mut Tree,
+        observer: &mut dyn SourceObserver,
+    ) -> Result<()> {
+        match stored {
+            Ok((chunks, contents)) => {
+                node.size = contents.size;
+                node.chunks = chunks;
+                observer.added(&node, Some(&contents));
+                tree.add_node(node);
+            }
+            Err(StoreError::Read(e)) => {
+                observer.failed(&node, e)?;
+                self.left_out.insert(node.name);
+            }
+            Err(StoreError::Write(e)) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// The chunks of `node` in the parent tree if the file is unchanged
+    /// since, and all of them are still indexed.
+    async fn unchanged_chunks(&self, node: &TreeNode) -> Result<Option<Vec<ChunkRef>>> {
+        if self.standalone {
+            return Ok(None);
+        }
+        let Some(parent) = &self.parent else {
+            return Ok(None);
+        };
+        let Some(previous) = parent.nodes.get(&node.name) else {
+            return Ok(None);
+        };
+        let unchanged = has_contents(previous)
+            && previous.size == node.size
+            && previous.mtime == node.mtime
+            && previous.inode.is_some()
+            && previous.inode == node.inode
+            && node.mtime < parent.time;
+        if !unchanged {
+            return Ok(None);
+        }
+
+        let chunk_ids: Vec<ChunkID> = previous.chunks.iter().map(|chunk| chunk.id).collect();
+        let indexed = self.repo.has_chunks(&chunk_ids).await?;
+        Ok(indexed
+            .into_iter()
+            .all(|indexed| indexed)
+            .then(|| previous.chunks.clone()))
+    }
+
+    /// Chunks `reader` on a task of its own, a few chunks ahead of the
+    /// writer.
+    fn read_ahead(&self, reader: Box<dyn AsyncRead + Send + Unpin>) -> ReadAhead {
+        let (sender, chunks) = mpsc::channel(CHUNKS_AHEAD);
+        let chunker = Arc::clone(&self.chunker);
+        let task = tokio::spawn(async move {
+            let mut stream = chunker.chunk_stream(reader);
+            while let Some(chunk) = stream.next_chunk().await.transpose() {
+                let failed = chunk.is_err();
+                if sender.send(chunk).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        ReadAhead { chunks, task }
+    }
+
+    async fn store_read_ahead(&mut self, mut read: ReadAhead) -> Stored {
+        let mut contents = ContentsBuilder::default();
+        while let Some(chunk) = read.chunks.recv().await {
+            let chunk = chunk.map_err(StoreError::Read)?;
+            self.store_chunk(chunk, &mut contents)
+                .await
+                .map_err(StoreError::Write)?;
+        }
+        // The chunks also end if the task died
+        read.task
+            .await
+            .map_err(|e| StoreError::Read(Error::Other(format!("Reading task failed: {}", e))))?;
+        Ok(contents.finish())
+    }
+
+    async fn throttle_read(&self) {
+        if let Some(limiter) = self.read_limiter {
+            limiter.throttle(1).await;
+        }
+    }
+
+    async fn store_entry<S: BackupSource + ?Sized>(&mut self, source: &mut S) -> Stored {
+        let reader = source.open_entry().map_err(StoreError::Read)?;
+        self.store_stream(BlockingReader(reader)).await
+    }
+
+    /// Chunks `data` and writes the chunks not stored yet.
+    pub async fn store(&mut self, data: &[u8]) -> Result<(Vec<ChunkRef>, StoredContents)> {
+        self.store_reader(data).await
+    }
+
+    /// Chunks `reader` as it is read and writes the chunks not stored yet,
+    /// so that large files never sit in memory whole.
+    pub async fn store_reader<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: R,
+    ) -> Result<(Vec<ChunkRef>, StoredContents)> {
+        self.store_stream(reader)
+            .await
+            .map_err(StoreError::into_inner)
+    }
+
+    async fn store_stream<R: AsyncRead + Unpin>(&mut self, reader: R) -> Stored {
+        let chunker = Arc::clone(&self.chunker);
+        let mut chunks = chunker.chunk_stream(reader);
+        let mut contents = ContentsBuilder::default();
+        while let Some(chunk) = chunks.next_chunk().await.map_err(StoreError::Read)? {
+            self.store_chunk(chunk, &mut contents)
+                .await
+                .map_err(StoreError::Write)?;
+        }
+        Ok(contents.finish())
+    }
+
+    /// Writes `chunk` unless it is stored already, and records it in
+    /// `contents`.
+    async fn store_chunk(&mut self, chunk: Chunk, contents: &mut ContentsBuilder) -> Result<()> {
+        let chunk_id = chunk.id();
+        contents.size += chunk.data().len() as u64;
+        contents.hasher.update(chunk.data());
+
+        let indexed = !self.standalone && self.repo.has_chunk(&chunk_id).await?;
+        if indexed || self.written.contains(&chunk_id) {
+            contents.dedup_chunks += 1;
+        } else {
+            if let Some(pack) = self.pack_manager.add_chunk(chunk_id, chunk.data())? {
+                self.write_pack(pack).await?;
+            }
+            self.written.insert(chunk_id);
+            self.bytes_added += chunk.data().len() as u64;
+            contents.new_chunks += 1;
+        }
+        contents.refs.push(ChunkRef {
+            id: chunk_id,
+            offset: 0,
+            length: chunk.data().len() as u32,
+        });
+        Ok(())
+    }
+
+    /// Writes the pack still being filled. Call before saving a tree that
+    /// references its chunks.
+    pub async fn flush(&mut self) -> Result<()> {
+        if let Some(pack) = self.pack_manager.finish_current_pack() {
+            self.write_pack(pack).await?;
+        }
+        Ok(())
+    }
+
+    /// Saves `pack`, or queues it for upload while a source is added with
+    /// several jobs.
+    async fn write_pack(&mut self, pack: PackFile) -> Result<()> {
+        if self.standalone {
+            self.packs.push(pack.header.pack_id.clone());
+        }
+        match &self.uploads {
+            Some(uploads) => uploads
+                .send(pack)
+                .await
+                .map_err(|_| Error::Other("Pack uploads stopped".to_string())),
+            None => save_pack(self.repo, &pack, self.standalone).await,
+        }
+    }
+}
+
+/// The files of a parent tree by name, and when it was saved.
+struct ParentTree {
+    nodes: HashMap<String, TreeNode>,
+    /// Unix seconds
+    time: i64,
+}
+
+/// How the contents of an entry read ahead are stored.
+enum Pending {
+    NoContents,
+    /// A hardlink whose original was still being read, with a reader for
+    /// its own contents
+    Link(Option<Box<dyn AsyncRead + Send + Unpin>>),
+    /// Chunks taken from the parent tree
+    Unchanged,
+    Read(Result<ReadAhead>),
+}
+
+/// Chunks read from one entry by a task of its own.
+struct ReadAhead {
+    chunks: mpsc::Receiver<Result<Chunk>>,
+    task: JoinHandle<()>,
+}
+
+/// Why the contents of an entry weren't stored.
+enum StoreError {
+    /// Reading the source failed; the entry can be left out
+    Read(Error),
+    /// Writing to the repository failed; the backup can't go on
+    Write(Error),
+}
+
+impl StoreError {
+    fn into_inner(self) -> Error {
+        match self {
+            StoreError::Read(e) | StoreError::Write(e) => e,
+        }
+    }
+}
+
+type Stored = std::result::Result<(Vec<ChunkRef>, StoredContents), StoreError>;
+
+/// The stored contents of one entry, as its chunks are written.
+#[derive(Default)]
+struct ContentsBuilder {
+    refs: Vec<ChunkRef>,
+    size: u64,
+    hasher: blake3::Hasher,
+    new_chunks: u64,
+    dedup_chunks: u64,
+}
+
+impl ContentsBuilder {
+    fn finish(self) -> (Vec<ChunkRef>, StoredContents) {
+        let contents = StoredContents {
+            size: self.size,
+            content_hash: self.hasher.finalize(),
+            new_chunks: self.new_chunks,
+            dedup_chunks: self.dedup_chunks,
+        };
+        (self.refs, contents)
+    }
+}
+
+async fn save_pack(repo: &Repository, pack: &PackFile, standalone: bool) -> Result<()> {
+    if standalone {
+        repo.save_standalone_pack(pack).await?;
+    } else {
+        repo.save_pack_with_locations(pack).await?;
+    }
+    tracing::info!(
+        "Saved pack: {} with {} chunks",
+        pack.header.pack_id,
+        pack.chunks.len()
+    );
+    Ok(())
+}
+
+/// Reads a [`BackupSource`] entry as an [`AsyncRead`]. Reads block, as
+/// sources are read synchronously.
+struct BlockingReader<R>(R);
+
+impl<R: Read + Unpin> AsyncRead for BlockingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let read = self.0.read(buf.initialize_unfilled())?;
+        buf.advance(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str, node_type: NodeType, hardlink_target: Option<&str>) -> TreeNode {
+        TreeNode {
+            node_type,
+            hardlink_target: hardlink_target.map(String::from),
+            ..TreeNode::test_file(name, &[])
+        }
+    }
+
+    #[test]
+    fn test_has_contents() {
+        assert!(has_contents(&node("a", NodeType::File, None)));
+        assert!(!has_contents(&node("b", NodeType::File, Some("a"))));
+        assert!(!has_contents(&node("c", NodeType::Directory, None)));
+        assert!(!has_contents(&node("d", NodeType::Symlink, None)));
+    }
+
+    #[test]
+    fn test_fs_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        std::fs::write(&file, b"contents").unwrap();
+
+        let mut source = FsSource::new(vec![
+            (
+                dir.path().to_path_buf(),
+                node("", NodeType::Directory, None),
+            ),
+            (file, node("file.txt", NodeType::File, None)),
+        ]);
+        assert!(source.open_entry().is_err());
+
+        assert_eq!(source.next_entry().unwrap().unwrap().name, "");
+        assert_eq!(source.next_entry().unwrap().unwrap().name, "file.txt");
+        let mut data = Vec::new();
+        source.open_entry().unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"contents");
+        assert!(source.next_entry().unwrap().is_none());
+    }
+}
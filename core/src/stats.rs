@@ -0,0 +1,242 @@
+//! Cached repository statistics.
+//!
+//! Computing repository-wide statistics requires loading every snapshot tree
+//! and sizing every pack, which gets slow on large or remote repositories. The
+//! [`StatsCache`] keeps the per-snapshot and per-pack figures in an encrypted
+//! object at `index/stats.cache` so that `stats` only has to look at what
+//! changed since the cache was last written.
+//!
+//! [`HostAttribution`] answers a different question for repositories shared
+//! by several hosts: how much of the stored data each host is responsible
+//! for, and how much would be freed if its snapshots were removed. That
+//! needs every chunk reference, so it is computed on demand and not cached.
+
+use crate::crypto::Encryptor;
+use crate::snapshot::Tree;
+use crate::types::{ChunkID, PackID, SnapshotID};
+use crate::{Error, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Storage path of the encrypted stats cache.
+pub const STATS_CACHE_PATH: &str = "index/stats.cache";
+
+/// Stats cache format version for schema evolution.
+const STATS_CACHE_VERSION: u32 = 1;
+
+/// Figures recorded for a single snapshot.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotStatsEntry {
+    pub hostname: String,
+    pub file_count: u64,
+    /// Logical size of all files in the snapshot
+    pub original_size: u64,
+}
+
+/// Encrypted, incrementally maintained repository statistics.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsCache {
+    pub version: u32,
+    pub updated_at: DateTime<Utc>,
+    pub snapshots: BTreeMap<SnapshotID, SnapshotStatsEntry>,
+    /// On-disk size of every pack
+    pub packs: BTreeMap<PackID, u64>,
+}
+
+impl Default for StatsCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatsCache {
+    pub fn new() -> Self {
+        Self {
+            version: STATS_CACHE_VERSION,
+            updated_at: Utc::now(),
+            snapshots: BTreeMap::new(),
+            packs: BTreeMap::new(),
+        }
+    }
+
+    pub fn snapshot_count(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn pack_count(&self) -> usize {
+        self.packs.len()
+    }
+
+    /// Sum of the file counts of all snapshots.
+    pub fn file_count(&self) -> u64 {
+        self.snapshots.values().map(|s| s.file_count).sum()
+    }
+
+    /// Sum of the logical sizes of all snapshots.
+    pub fn original_size(&self) -> u64 {
+        self.snapshots.values().map(|s| s.original_size).sum()
+    }
+
+    /// Sum of the on-disk sizes of all packs.
+    pub fn stored_size(&self) -> u64 {
+        self.packs.values().sum()
+    }
+
+    pub fn serialize(&self, encryptor: &Encryptor) -> Result<Vec<u8>> {
+        let json_data = serde_json::to_vec(self)
+            .map_err(|e| Error::Other(format!("Failed to serialize stats cache: {}", e)))?;
+        encryptor.encrypt(&json_data)
+    }
+
+    pub fn deserialize(data: &[u8], encryptor: &Encryptor) -> Result<Self> {
+        let decrypted_data = encryptor.decrypt(data)?;
+        let cache: Self = serde_json::from_slice(&decrypted_data)
+            .map_err(|e| Error::Other(format!("Failed to deserialize stats cache: {}", e)))?;
+        if cache.version != STATS_CACHE_VERSION {
+            return Err(Error::InvalidFormatVersion {
+                version: cache.version,
+            });
+        }
+        Ok(cache)
+    }
+}
+
+/// Contribution of one host to a shared repository.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct HostStats {
+    pub hostname: String,
+    pub snapshots: usize,
+    /// Logical size of all files in the host's snapshots
+    pub logical_size: u64,
+    /// Stored size of the distinct chunks the host's snapshots reference
+    pub referenced_size: u64,
+    /// Stored size of the chunks no other host references, which removing
+    /// the host's snapshots would free
+    pub unique_size: u64,
+}
+
+/// Reference-counts chunks by host across snapshot trees.
+#[derive(Debug, Default)]
+pub struct HostAttribution {
+    hosts: Vec<HostStats>,
+    host_ids: HashMap<String, usize>,
+    /// Hosts referencing each chunk, by index into `hosts`
+    chunk_hosts: HashMap<ChunkID, Vec<usize>>,
+}
+
+impl HostAttribution {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts the chunks of one snapshot's tree towards `hostname`.
+    pub fn add_snapshot(&mut self, hostname: &str, tree: &Tree) {
+        let host = match self.host_ids.get(hostname) {
+            Some(&host) => host,
+            None => {
+                self.hosts.push(HostStats {
+                    hostname: hostname.to_string(),
+                    ..Default::default()
+                });
+                self.host_ids
+                    .insert(hostname.to_string(), self.hosts.len() - 1);
+                self.hosts.len() - 1
+            }
+        };
+
+        let stats = &mut self.hosts[host];
+        stats.snapshots += 1;
+        stats.logical_size += tree.total_size();
+
+        for chunk_ref in tree.nodes.iter().flat_map(|node| &node.chunks) {
+            let hosts = self.chunk_hosts.entry(chunk_ref.id).or_default();
+            if !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        }
+    }
+
+    /// Sums the stored size of each host's chunks, sorted by unique size,
+    /// largest first. `stored_size` gives a chunk's size in its pack;
+    /// chunks it doesn't know (missing from the index) count as empty.
+    pub fn finish(mut self, stored_size: impl Fn(&ChunkID) -> Option<u64>) -> Vec<HostStats> {
+        for (chunk_id, hosts) in &self.chunk_hosts {
+            let size = stored_size(chunk_id).unwrap_or(0);
+            for &host in hosts {
+                self.hosts[host].referenced_size += size;
+            }
+            if let [host] = hosts.as_slice() {
+                self.hosts[*host].unique_size += size;
+            }
+        }
+
+        self.hosts.sort_by(|a, b| {
+            b.unique_size
+                .cmp(&a.unique_size)
+                .then_with(|| a.hostname.cmp(&b.hostname))
+        });
+        self.hosts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_cache_roundtrip() {
+        let encryptor = Encryptor::new(&[7u8; 32]).unwrap();
+
+        let mut cache = StatsCache::new();
+        cache.snapshots.insert(
+            SnapshotID::generate(),
+            SnapshotStatsEntry {
+                hostname: "host".to_string(),
+                file_count: 3,
+                original_size: 300,
+            },
+        );
+        cache.packs.insert(PackID::generate(), 120);
+        cache.packs.insert(PackID::generate(), 80);
+
+        let data = cache.serialize(&encryptor).unwrap();
+        let restored = StatsCache::deserialize(&data, &encryptor).unwrap();
+
+        assert_eq!(restored.snapshot_count(), 1);
+        assert_eq!(restored.original_size(), 300);
+        assert_eq!(restored.file_count(), 3);
+        assert_eq!(restored.stored_size(), 200);
+        assert_eq!(restored.pack_count(), 2);
+    }
+
+    fn tree(chunks: &[&[u8]]) -> Tree {
+        let mut tree = Tree::new();
+        tree.add_node(crate::TreeNode::test_file("file", chunks));
+        tree
+    }
+
+    #[test]
+    fn test_host_attribution() {
+        let mut attribution = HostAttribution::new();
+        attribution.add_snapshot("web", &tree(&[b"shared", b"web-only"]));
+        attribution.add_snapshot("web", &tree(&[b"shared", b"web-only", b"web-new"]));
+        attribution.add_snapshot("db", &tree(&[b"shared", b"db-only"]));
+
+        // Every chunk takes 10 bytes in its pack
+        let hosts = attribution.finish(|_| Some(10));
+        assert_eq!(hosts.len(), 2);
+
+        let web = &hosts[0];
+        assert_eq!(web.hostname, "web");
+        assert_eq!(web.snapshots, 2);
+        assert_eq!(web.logical_size, 14 + 21);
+        assert_eq!(web.referenced_size, 30);
+        assert_eq!(web.unique_size, 20);
+
+        let db = &hosts[1];
+        assert_eq!(db.hostname, "db");
+        assert_eq!(db.referenced_size, 20);
+        assert_eq!(db.unique_size, 10);
+    }
+}
@@ -1,17 +1,49 @@
-use crate::{Result, S3RepoSse};
+use crate::api_cache::{self, CachedStorage};
+use crate::dry_run::{self, DryRunStorage};
+use crate::metrics::S3RetryMetrics;
+use crate::proxy::{ProxyConfig, ProxyConnector};
+use crate::request_log::{self, LoggedStorage, S3RequestLog};
+use crate::{
+    AccessTier, AzureAccessTiers, ChunkID, RehydratePriority, Result, S3Checksum, S3RepoSse,
+    S3StorageClasses, S3Tls,
+};
 use async_trait::async_trait;
-use aws_config::BehaviorVersion;
+use aws_config::provider_config::ProviderConfig;
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::web_identity_token::{StaticConfiguration, WebIdentityTokenCredentialsProvider};
+use aws_config::{BehaviorVersion, ConfigLoader, Region};
 use aws_sdk_s3::Client;
+use aws_sdk_s3::config::{
+    Credentials, RequestChecksumCalculation, ResponseChecksumValidation, SharedCredentialsProvider,
+    SharedHttpClient,
+};
+use aws_sdk_s3::error::SdkError;
 use aws_sdk_s3::primitives::ByteStream;
-use aws_sdk_s3::types::ServerSideEncryption;
+use aws_sdk_s3::types::{ChecksumAlgorithm, ChecksumMode, ServerSideEncryption, StorageClass};
+use aws_smithy_runtime_api::client::http::{
+    HttpConnector, HttpConnectorFuture, SharedHttpConnector, http_client_fn,
+};
+use aws_smithy_runtime_api::client::orchestrator::{HttpRequest, HttpResponse};
+use aws_smithy_runtime_api::client::result::ConnectorError;
+use aws_smithy_types::body::SdkBody;
 use bytes::Bytes;
 use chrono::Utc;
+use hyper_rustls::HttpsConnector;
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub enum RepositoryLocation {
     Local(PathBuf),
-    S3(S3Location),
+    S3(Box<S3Location>),
     Azure(AzureLocation),
     Rclone(RcloneLocation),
     Sftp(SftpLocation),
@@ -28,6 +60,22 @@ impl RepositoryLocation {
         }
     }
 
+    /// Routes a network backend through `proxy` instead of the proxy from
+    /// the environment. Other backends are returned unchanged.
+    pub fn with_proxy(self, proxy: ProxyConfig) -> Self {
+        match self {
+            Self::S3(mut location) => {
+                location.proxy = Some(proxy);
+                Self::S3(location)
+            }
+            Self::Azure(mut location) => {
+                location.proxy = Some(proxy);
+                Self::Azure(location)
+            }
+            other => other,
+        }
+    }
+
     pub fn parse(input: &str) -> crate::Result<Self> {
         // S3 URIs
         if let Some(rest) = input.strip_prefix("s3://") {
@@ -91,6 +139,13 @@ pub struct S3Location {
     pub endpoint: Option<String>,
     pub region: Option<String>,
     pub sse: Option<S3RepoSse>,
+    pub storage_classes: S3StorageClasses,
+    pub checksum: S3Checksum,
+    /// Credentials used instead of the default AWS credential chain
+    pub credentials: S3Credentials,
+    /// Custom CA, client certificate and hostname checking
+    pub tls: S3Tls,
+    pub proxy: Option<ProxyConfig>,
 }
 
 impl S3Location {
@@ -101,6 +156,11 @@ impl S3Location {
             endpoint: None,
             region: None,
             sse: None,
+            storage_classes: S3StorageClasses::default(),
+            checksum: S3Checksum::default(),
+            credentials: S3Credentials::default(),
+            tls: S3Tls::default(),
+            proxy: None,
         }
     }
 
@@ -120,11 +180,15 @@ impl S3Location {
         }
     }
 
-    /// Applies environment variable overrides for endpoint and region.
+    /// Applies environment variable overrides for endpoint, region and
+    /// credentials.
     ///
     /// Checks these environment variables (in order of priority):
     /// - `AWS_ENDPOINT_URL` - S3-compatible endpoint URL (Wasabi, Backblaze B2, MinIO)
     /// - `AWS_REGION` or `AWS_DEFAULT_REGION` - AWS region
+    /// - `GHOSTSNAP_S3_*` - explicit credentials and TLS settings, see
+    ///   [`S3Credentials::from_env`] and [`S3Tls::from_env`]
+    /// - `GHOSTSNAP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` - see [`ProxyConfig::from_env`]
     ///
     /// Only applies overrides if the field is not already set.
     pub fn with_env_overrides(mut self) -> Self {
@@ -140,10 +204,177 @@ impl S3Location {
                 self.region = Some(region);
             }
         }
+        if self.credentials.is_default() {
+            self.credentials = S3Credentials::from_env();
+        }
+        if self.tls.is_default() {
+            self.tls = S3Tls::from_env();
+        }
+        if self.proxy.is_none() {
+            self.proxy = ProxyConfig::from_env();
+        }
         self
     }
 }
 
+/// Explicit S3 credentials, for hosts without ambient AWS credentials.
+///
+/// Base credentials are anonymous, static keys, a web identity token or,
+/// when none is set, the default AWS credential chain. An optional role is
+/// then assumed with the base credentials. These settings may contain
+/// secrets and are never written to the repository config.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct S3Credentials {
+    /// Send unsigned requests, for public buckets
+    pub anonymous: bool,
+    pub static_keys: Option<S3StaticKeys>,
+    pub web_identity: Option<S3WebIdentity>,
+    pub assume_role: Option<S3AssumeRole>,
+}
+
+/// Access key pair with an optional STS session token.
+#[derive(Clone, PartialEq, Eq)]
+pub struct S3StaticKeys {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl std::fmt::Debug for S3StaticKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3StaticKeys")
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"<redacted>")
+            .field(
+                "session_token",
+                &self.session_token.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+/// Web identity federation, e.g. EKS IAM roles for service accounts (IRSA).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3WebIdentity {
+    pub role_arn: String,
+    /// File holding the OIDC token; re-read whenever credentials are refreshed
+    pub token_file: PathBuf,
+    pub session_name: Option<String>,
+}
+
+/// Role assumed through STS `AssumeRole`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3AssumeRole {
+    pub role_arn: String,
+    /// External ID required by the role's trust policy, if any
+    pub external_id: Option<String>,
+    pub session_name: Option<String>,
+}
+
+/// Role session name used when none is configured.
+const S3_ROLE_SESSION_NAME: &str = "ghostsnap";
+
+impl S3Credentials {
+    /// Anonymous access to a public bucket.
+    pub fn anonymous() -> Self {
+        Self {
+            anonymous: true,
+            ..Self::default()
+        }
+    }
+
+    /// Returns whether the default AWS credential chain is used unchanged.
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Reads explicit credentials from the environment:
+    ///
+    /// - `GHOSTSNAP_S3_ANONYMOUS` - `1` or `true` for unsigned requests
+    /// - `GHOSTSNAP_S3_ACCESS_KEY_ID`, `GHOSTSNAP_S3_SECRET_ACCESS_KEY` and
+    ///   optionally `GHOSTSNAP_S3_SESSION_TOKEN` - static keys
+    /// - `GHOSTSNAP_S3_WEB_IDENTITY_TOKEN_FILE`, `GHOSTSNAP_S3_WEB_IDENTITY_ROLE_ARN` -
+    ///   web identity
+    /// - `GHOSTSNAP_S3_ROLE_ARN`, `GHOSTSNAP_S3_EXTERNAL_ID` - role to assume
+    /// - `GHOSTSNAP_S3_ROLE_SESSION_NAME` - session name for either role
+    ///
+    /// Incomplete settings are kept so that [`S3Credentials::validate`] can
+    /// report them when the client is built.
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let session_name = var("GHOSTSNAP_S3_ROLE_SESSION_NAME");
+
+        let static_keys = match (
+            var("GHOSTSNAP_S3_ACCESS_KEY_ID"),
+            var("GHOSTSNAP_S3_SECRET_ACCESS_KEY"),
+        ) {
+            (None, None) => None,
+            (access_key_id, secret_access_key) => Some(S3StaticKeys {
+                access_key_id: access_key_id.unwrap_or_default(),
+                secret_access_key: secret_access_key.unwrap_or_default(),
+                session_token: var("GHOSTSNAP_S3_SESSION_TOKEN"),
+            }),
+        };
+        let web_identity = match (
+            var("GHOSTSNAP_S3_WEB_IDENTITY_ROLE_ARN"),
+            var("GHOSTSNAP_S3_WEB_IDENTITY_TOKEN_FILE"),
+        ) {
+            (None, None) => None,
+            (role_arn, token_file) => Some(S3WebIdentity {
+                role_arn: role_arn.unwrap_or_default(),
+                token_file: token_file.map(PathBuf::from).unwrap_or_default(),
+                session_name: session_name.clone(),
+            }),
+        };
+        let assume_role = var("GHOSTSNAP_S3_ROLE_ARN").map(|role_arn| S3AssumeRole {
+            role_arn,
+            external_id: var("GHOSTSNAP_S3_EXTERNAL_ID"),
+            session_name,
+        });
+
+        Self {
+            anonymous: matches!(
+                var("GHOSTSNAP_S3_ANONYMOUS").as_deref(),
+                Some("1" | "true" | "yes")
+            ),
+            static_keys,
+            web_identity,
+            assume_role,
+        }
+    }
+
+    /// Checks that the settings are complete and don't conflict.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| Err(crate::Error::Other(message.to_string()));
+        if self.anonymous
+            && (self.static_keys.is_some()
+                || self.web_identity.is_some()
+                || self.assume_role.is_some())
+        {
+            return invalid("Anonymous S3 access can't be combined with other credentials");
+        }
+        if self.static_keys.is_some() && self.web_identity.is_some() {
+            return invalid("S3 static keys and web identity are mutually exclusive");
+        }
+        if let Some(keys) = &self.static_keys
+            && (keys.access_key_id.is_empty() || keys.secret_access_key.is_empty())
+        {
+            return invalid("S3 static credentials need both an access key ID and a secret key");
+        }
+        if let Some(web_identity) = &self.web_identity
+            && (web_identity.role_arn.is_empty() || web_identity.token_file.as_os_str().is_empty())
+        {
+            return invalid("S3 web identity needs both a role ARN and a token file");
+        }
+        if let Some(role) = &self.assume_role
+            && role.role_arn.is_empty()
+        {
+            return invalid("S3 role assumption needs a role ARN");
+        }
+        Ok(())
+    }
+}
+
 fn parse_s3_location(input: &str) -> crate::Result<RepositoryLocation> {
     let trimmed = input.trim_matches('/');
     if trimmed.is_empty() {
@@ -164,7 +395,9 @@ fn parse_s3_location(input: &str) -> crate::Result<RepositoryLocation> {
         None => (trimmed.to_string(), String::new()),
     };
 
-    Ok(RepositoryLocation::S3(S3Location::new(bucket, prefix)))
+    Ok(RepositoryLocation::S3(Box::new(S3Location::new(
+        bucket, prefix,
+    ))))
 }
 
 /// Split a `bucket/prefix` string shared by the S3-compatible schemes.
@@ -210,7 +443,7 @@ fn parse_b2_location(input: &str) -> crate::Result<RepositoryLocation> {
     let mut location = S3Location::new(bucket, prefix);
     location.endpoint = endpoint;
     location.region = region;
-    Ok(RepositoryLocation::S3(location))
+    Ok(RepositoryLocation::S3(Box::new(location)))
 }
 
 /// Extract the B2 region label from an S3 endpoint host such as
@@ -251,7 +484,7 @@ fn parse_minio_location(input: &str) -> crate::Result<RepositoryLocation> {
     let mut location = S3Location::new(bucket, prefix);
     location.endpoint = endpoint;
     location.region = region;
-    Ok(RepositoryLocation::S3(location))
+    Ok(RepositoryLocation::S3(Box::new(location)))
 }
 
 // =============================================================================
@@ -263,6 +496,9 @@ pub struct AzureLocation {
     pub account_name: String,
     pub container: String,
     pub prefix: String,
+    /// Access tiers applied to newly written blobs
+    pub access_tiers: AzureAccessTiers,
+    pub proxy: Option<ProxyConfig>,
 }
 
 impl AzureLocation {
@@ -271,6 +507,8 @@ impl AzureLocation {
             account_name,
             container,
             prefix,
+            access_tiers: AzureAccessTiers::default(),
+            proxy: None,
         }
     }
 
@@ -290,13 +528,16 @@ impl AzureLocation {
         }
     }
 
-    /// Applies environment variable overrides for account name.
+    /// Applies environment variable overrides for account name and proxy.
     pub fn with_env_overrides(mut self) -> Self {
         if self.account_name.is_empty()
             && let Ok(account) = std::env::var("AZURE_STORAGE_ACCOUNT")
         {
             self.account_name = account;
         }
+        if self.proxy.is_none() {
+            self.proxy = ProxyConfig::from_env();
+        }
         self
     }
 }
@@ -501,6 +742,22 @@ pub struct ObjectMetadata {
     pub modified_at: chrono::DateTime<Utc>,
 }
 
+/// Access tier of a stored object, for storage with tiering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TierStatus {
+    /// Current tier; `None` if the service did not report one
+    pub tier: Option<AccessTier>,
+    /// Target tier of a rehydration that is still in progress
+    pub rehydrating_to: Option<AccessTier>,
+}
+
+impl TierStatus {
+    /// Whether the object can be read right now.
+    pub fn is_readable(&self) -> bool {
+        self.tier.is_none_or(|tier| tier.is_online())
+    }
+}
+
 // =============================================================================
 // Repository Storage Trait
 // =============================================================================
@@ -515,6 +772,55 @@ pub trait RepositoryStorage: Send + Sync {
     async fn delete(&self, path: &str) -> Result<()>;
     async fn list(&self, prefix: &str) -> Result<Vec<String>>;
     async fn metadata(&self, path: &str) -> Result<ObjectMetadata>;
+
+    /// Copies the object at `from` to `to`, replacing any existing object.
+    ///
+    /// Storage with a server-side copy API overrides this so the data never
+    /// passes through this host; the default downloads and re-uploads it.
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let data = self.read(from).await?;
+        self.write(to, data).await
+    }
+
+    /// Batched chunk existence lookup for storage that keeps its own chunk
+    /// index (e.g. a ghostsnap server), answering in a single round trip.
+    ///
+    /// Returns `None` when unsupported; callers then rely on the local index.
+    async fn has_chunks(&self, _chunk_ids: &[ChunkID]) -> Result<Option<Vec<bool>>> {
+        Ok(None)
+    }
+
+    /// Moves objects that older versions stored directly in the fan-out
+    /// prefix into their shard directories, returning how many were moved.
+    /// Objects are found in either layout, so this is only a cleanup; it
+    /// renames objects other processes may be listing, so callers hold the
+    /// repository's exclusive lock.
+    ///
+    /// Storage without a fan-out layout has nothing to migrate.
+    async fn migrate_layout(&self) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Access tier of the object at `path`.
+    ///
+    /// Returns `None` when the storage has no tiering.
+    async fn access_tier(&self, _path: &str) -> Result<Option<TierStatus>> {
+        Ok(None)
+    }
+
+    /// Moves the object at `path` to `tier`. Moving an archived object to an
+    /// online tier starts a rehydration with the given priority.
+    async fn set_access_tier(
+        &self,
+        _path: &str,
+        _tier: AccessTier,
+        _priority: RehydratePriority,
+    ) -> Result<()> {
+        Err(crate::Error::Backend(format!(
+            "Access tiers are not supported by {}",
+            self.location().display()
+        )))
+    }
 }
 
 pub fn local_storage<P: AsRef<Path>>(path: P) -> Box<dyn RepositoryStorage> {
@@ -540,6 +846,20 @@ pub async fn sftp_storage(location: SftpLocation) -> Result<Box<dyn RepositorySt
 pub async fn storage_for_location(
     location: &RepositoryLocation,
 ) -> Result<Box<dyn RepositoryStorage>> {
+    let mut storage: Box<dyn RepositoryStorage> = Box::new(CachedStorage::new(
+        open_storage(location).await?,
+        api_cache::ttl(),
+    ));
+    if dry_run::is_enabled() {
+        storage = Box::new(DryRunStorage::new(storage));
+    }
+    if request_log::is_enabled() {
+        return Ok(Box::new(LoggedStorage::new(storage)));
+    }
+    Ok(storage)
+}
+
+async fn open_storage(location: &RepositoryLocation) -> Result<Box<dyn RepositoryStorage>> {
     match location {
         RepositoryLocation::Local(path) => Ok(local_storage(path)),
         RepositoryLocation::S3(location) => {
@@ -565,6 +885,32 @@ pub async fn storage_for_location(
 // Local Repository Storage
 // =============================================================================
 
+/// Prefix whose objects the local and SFTP backends spread over
+/// subdirectories, so that no single directory holds millions of files.
+const FAN_OUT_PREFIX: &str = "data";
+
+/// Physical path of `path` in the fan-out layout: `data/abcdef…` is stored as
+/// `data/ab/abcdef…`. Returns `None` for paths that are not fanned out.
+pub(crate) fn fan_out(path: &str) -> Option<String> {
+    let name = path
+        .strip_prefix(FAN_OUT_PREFIX)
+        .and_then(|rest| rest.strip_prefix('/'))?;
+    let shard = name.get(..2).filter(|_| !name.contains('/'))?;
+    Some(format!("{}/{}/{}", FAN_OUT_PREFIX, shard, name))
+}
+
+/// Returns whether `name`, listed in the fan-out prefix, is a shard directory.
+fn is_shard_dir(name: &str) -> bool {
+    name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Returns whether an object found directly in the fan-out prefix (the flat
+/// layout of older repositories) should be moved into its shard. Foreign
+/// objects are left where they are.
+fn needs_fan_out(name: &str) -> bool {
+    crate::layout::classify(&format!("{}/{}", FAN_OUT_PREFIX, name)).is_some()
+}
+
 struct LocalRepositoryStorage {
     location: RepositoryLocation,
     root: PathBuf,
@@ -579,7 +925,34 @@ impl LocalRepositoryStorage {
     }
 
     fn full_path(&self, path: &str) -> PathBuf {
-        self.root.join(path)
+        match fan_out(path) {
+            Some(sharded) => self.root.join(sharded),
+            None => self.root.join(path),
+        }
+    }
+
+    /// Path of an existing object, which may still be in the flat layout if
+    /// the repository has not been migrated yet.
+    async fn existing_path(&self, path: &str) -> Result<PathBuf> {
+        let full_path = self.full_path(path);
+        if fan_out(path).is_some() && !tokio::fs::try_exists(&full_path).await? {
+            let flat = self.root.join(path);
+            if tokio::fs::try_exists(&flat).await? {
+                return Ok(flat);
+            }
+        }
+        Ok(full_path)
+    }
+
+    /// Moves an object from the flat layout into its shard directory.
+    async fn migrate_to_shard(&self, name: &str) -> Result<()> {
+        let path = format!("{}/{}", FAN_OUT_PREFIX, name);
+        let target = self.full_path(&path);
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::rename(self.root.join(&path), target).await?;
+        Ok(())
     }
 }
 
@@ -595,11 +968,13 @@ impl RepositoryStorage for LocalRepositoryStorage {
     }
 
     async fn exists(&self, path: &str) -> Result<bool> {
-        Ok(tokio::fs::try_exists(self.full_path(path)).await?)
+        Ok(tokio::fs::try_exists(self.existing_path(path).await?).await?)
     }
 
     async fn read(&self, path: &str) -> Result<Bytes> {
-        Ok(tokio::fs::read(self.full_path(path)).await?.into())
+        Ok(tokio::fs::read(self.existing_path(path).await?)
+            .await?
+            .into())
     }
 
     async fn write(&self, path: &str, data: Bytes) -> Result<()> {
@@ -612,7 +987,16 @@ impl RepositoryStorage for LocalRepositoryStorage {
     }
 
     async fn delete(&self, path: &str) -> Result<()> {
-        tokio::fs::remove_file(self.full_path(path)).await?;
+        tokio::fs::remove_file(self.existing_path(path).await?).await?;
+        Ok(())
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let target = self.full_path(to);
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::copy(self.existing_path(from).await?, target).await?;
         Ok(())
     }
 
@@ -624,18 +1008,58 @@ impl RepositoryStorage for LocalRepositoryStorage {
             return Ok(results);
         }
 
+        let fanned_out = prefix == FAN_OUT_PREFIX;
         let mut entries = tokio::fs::read_dir(base).await?;
         while let Some(entry) = entries.next_entry().await? {
-            if let Some(name) = entry.file_name().to_str() {
-                results.push(name.to_string());
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if fanned_out && is_shard_dir(&name) && entry.file_type().await?.is_dir() {
+                let mut shard = tokio::fs::read_dir(entry.path()).await?;
+                while let Some(object) = shard.next_entry().await? {
+                    if let Some(name) = object.file_name().to_str() {
+                        results.push(name.to_string());
+                    }
+                }
+                continue;
             }
+            results.push(name);
         }
 
         Ok(results)
     }
 
+    async fn migrate_layout(&self) -> Result<usize> {
+        let base = self.root.join(FAN_OUT_PREFIX);
+        if !tokio::fs::try_exists(&base).await? {
+            return Ok(0);
+        }
+
+        let mut flat = Vec::new();
+        let mut entries = tokio::fs::read_dir(base).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(name) = entry.file_name().to_str()
+                && needs_fan_out(name)
+                && entry.file_type().await?.is_file()
+            {
+                flat.push(name.to_string());
+            }
+        }
+
+        let mut migrated = 0;
+        for name in &flat {
+            // The object stays readable at its old path if the move fails,
+            // so a failure is not fatal.
+            match self.migrate_to_shard(name).await {
+                Ok(()) => migrated += 1,
+                Err(e) => tracing::warn!("Failed to move {}/{}: {}", FAN_OUT_PREFIX, name, e),
+            }
+        }
+        Ok(migrated)
+    }
+
     async fn metadata(&self, path: &str) -> Result<ObjectMetadata> {
-        let metadata = tokio::fs::metadata(self.full_path(path)).await?;
+        let metadata = tokio::fs::metadata(self.existing_path(path).await?).await?;
         let modified_at = metadata
             .modified()
             .ok()
@@ -654,6 +1078,293 @@ impl RepositoryStorage for LocalRepositoryStorage {
 // S3 Repository Storage (AWS, Wasabi, Backblaze B2, MinIO)
 // =============================================================================
 
+/// Base64 SHA-256 digest of `data`, as S3 reports it in `x-amz-checksum-sha256`.
+pub fn s3_sha256_checksum(data: &[u8]) -> String {
+    use base64::Engine;
+    use sha2::{Digest, Sha256};
+
+    base64::engine::general_purpose::STANDARD.encode(Sha256::digest(data))
+}
+
+/// Compares the SHA-256 checksum S3 stored for an upload with the digest of
+/// the data that was sent.
+///
+/// Some S3-compatible services accept the checksum trailer but don't echo a
+/// checksum back; that is logged rather than treated as a failure.
+pub fn verify_s3_sha256(path: &str, data: &[u8], returned: Option<&str>) -> Result<()> {
+    let Some(returned) = returned else {
+        tracing::warn!("No SHA-256 checksum returned for {}; not verified", path);
+        return Ok(());
+    };
+    let expected = s3_sha256_checksum(data);
+    if returned != expected {
+        return Err(crate::Error::Backend(format!(
+            "Checksum mismatch writing {}: sent sha256 {}, stored {}",
+            path, expected, returned
+        )));
+    }
+    Ok(())
+}
+
+/// Converts a failed S3 request into a backend error. Error responses keep
+/// their HTTP status and `Retry-After` header, so callers can tell
+/// throttling apart from other failures and wait as long as asked.
+pub fn s3_error<E>(message: impl std::fmt::Display, err: &SdkError<E, HttpResponse>) -> crate::Error
+where
+    SdkError<E, HttpResponse>: std::fmt::Display,
+{
+    let message = format!("{}: {}", message, err);
+    match err.raw_response() {
+        Some(response) if !response.status().is_success() => crate::Error::BackendStatus {
+            status: response.status().as_u16(),
+            message,
+            retry_after: response
+                .headers()
+                .get("retry-after")
+                .and_then(crate::parse_retry_after),
+        },
+        _ => crate::Error::Backend(message),
+    }
+}
+
+/// Starts an SDK config for an S3 or S3-compatible endpoint using
+/// `credentials` instead of the default chain where they are set.
+///
+/// STS requests for role assumption and web identity go to AWS, not to
+/// `endpoint`.
+pub async fn s3_config_loader(
+    endpoint: Option<&str>,
+    region: Option<&str>,
+    credentials: &S3Credentials,
+) -> Result<ConfigLoader> {
+    credentials.validate()?;
+
+    let region = region.map(|region| Region::new(region.to_string()));
+    let mut loader = aws_config::defaults(BehaviorVersion::latest());
+    if let Some(region) = &region {
+        loader = loader.region(region.clone());
+    }
+    if let Some(endpoint) = endpoint {
+        loader = loader.endpoint_url(endpoint);
+    }
+    if credentials.anonymous {
+        return Ok(loader.no_credentials());
+    }
+
+    let base = if let Some(keys) = &credentials.static_keys {
+        Some(SharedCredentialsProvider::new(Credentials::new(
+            keys.access_key_id.clone(),
+            keys.secret_access_key.clone(),
+            keys.session_token.clone(),
+            None,
+            "ghostsnap",
+        )))
+    } else {
+        credentials.web_identity.as_ref().map(|web_identity| {
+            let provider = WebIdentityTokenCredentialsProvider::builder()
+                .configure(&ProviderConfig::default().with_region(region.clone()))
+                .static_configuration(StaticConfiguration {
+                    web_identity_token_file: web_identity.token_file.clone(),
+                    role_arn: web_identity.role_arn.clone(),
+                    session_name: web_identity
+                        .session_name
+                        .clone()
+                        .unwrap_or_else(|| S3_ROLE_SESSION_NAME.to_string()),
+                })
+                .build();
+            SharedCredentialsProvider::new(provider)
+        })
+    };
+
+    let Some(role) = &credentials.assume_role else {
+        if let Some(base) = base {
+            loader = loader.credentials_provider(base);
+        }
+        return Ok(loader);
+    };
+
+    let mut builder = AssumeRoleProvider::builder(role.role_arn.clone()).session_name(
+        role.session_name
+            .clone()
+            .unwrap_or_else(|| S3_ROLE_SESSION_NAME.to_string()),
+    );
+    if let Some(external_id) = &role.external_id {
+        builder = builder.external_id(external_id.clone());
+    }
+    if let Some(region) = region {
+        builder = builder.region(region);
+    }
+    let provider = match base {
+        Some(base) => builder.build_from_provider(base).await,
+        None => builder.build().await,
+    };
+    Ok(loader.credentials_provider(provider))
+}
+
+/// Builds an HTTP client for the TLS and proxy settings, or `None` when the
+/// SDK's default client can be used.
+pub fn s3_http_client(
+    tls: &S3Tls,
+    proxy: Option<&ProxyConfig>,
+) -> Result<Option<SharedHttpClient>> {
+    if tls.is_default() && proxy.is_none() {
+        return Ok(None);
+    }
+    if let Some(proxy) = proxy {
+        tracing::debug!("Connecting to S3 through proxy {}", proxy);
+    }
+    let tls_error = |what: &str, path: &Path, e: &dyn std::fmt::Display| {
+        crate::Error::Other(format!("{} {}: {}", what, path.display(), e))
+    };
+
+    let mut roots = RootCertStore::empty();
+    let native = rustls_native_certs::load_native_certs();
+    for error in &native.errors {
+        tracing::warn!("Failed to load system root certificate: {}", error);
+    }
+    roots.add_parsable_certificates(native.certs);
+    if let Some(path) = &tls.ca_bundle {
+        let certs = CertificateDer::pem_file_iter(path)
+            .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+            .map_err(|e| tls_error("Failed to read CA bundle", path, &e))?;
+        if certs.is_empty() {
+            return Err(tls_error("No certificates in CA bundle", path, &"empty"));
+        }
+        for cert in certs {
+            roots
+                .add(cert)
+                .map_err(|e| tls_error("Invalid certificate in CA bundle", path, &e))?;
+        }
+    }
+
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| crate::Error::Other(format!("TLS configuration error: {}", e)))?;
+    let builder = if tls.accept_invalid_hostnames {
+        tracing::warn!("TLS hostname verification is disabled for the S3 endpoint");
+        let verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+            .build()
+            .map_err(|e| crate::Error::Other(format!("TLS configuration error: {}", e)))?;
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(IgnoreHostname(verifier)))
+    } else {
+        builder.with_root_certificates(roots)
+    };
+    let config = match (&tls.client_cert, &tls.client_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let certs = CertificateDer::pem_file_iter(cert_path)
+                .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+                .map_err(|e| tls_error("Failed to read client certificate", cert_path, &e))?;
+            let key = PrivateKeyDer::from_pem_file(key_path)
+                .map_err(|e| tls_error("Failed to read client key", key_path, &e))?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .map_err(|e| tls_error("Invalid client certificate", cert_path, &e))?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => {
+            return Err(crate::Error::Other(
+                "A TLS client certificate and key must be configured together".to_string(),
+            ));
+        }
+    };
+
+    let https = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(config)
+        .https_or_http()
+        .enable_http1()
+        .enable_http2()
+        .wrap_connector(ProxyConnector::new(proxy)?);
+    let client = hyper_util::client::legacy::Client::builder(TokioExecutor::new())
+        .pool_timer(TokioTimer::new())
+        .build(https);
+    let connector = SharedHttpConnector::new(S3Connector { client });
+    Ok(Some(http_client_fn(move |_settings, _components| {
+        connector.clone()
+    })))
+}
+
+/// Sends S3 requests through a hyper client with custom TLS and proxy
+/// settings, which the SDK's own connector builder doesn't take.
+#[derive(Clone)]
+struct S3Connector {
+    client: hyper_util::client::legacy::Client<HttpsConnector<ProxyConnector>, SdkBody>,
+}
+
+impl std::fmt::Debug for S3Connector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Connector").finish_non_exhaustive()
+    }
+}
+
+impl HttpConnector for S3Connector {
+    fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+        let request = match request.try_into_http1x() {
+            Ok(request) => request,
+            Err(e) => return HttpConnectorFuture::ready(Err(ConnectorError::user(e.into()))),
+        };
+        let response = self.client.request(request);
+        HttpConnectorFuture::new(async move {
+            match response.await {
+                Ok(response) => HttpResponse::try_from(response.map(SdkBody::from_body_1_x))
+                    .map_err(|e| ConnectorError::other(e.into(), None)),
+                // Connection failures are retried like other I/O errors
+                Err(e) if e.is_connect() => Err(ConnectorError::io(e.into())),
+                Err(e) => Err(ConnectorError::other(e.into(), None)),
+            }
+        })
+    }
+}
+
+/// Certificate verifier that verifies the chain but accepts any hostname.
+#[derive(Debug)]
+struct IgnoreHostname(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for IgnoreHostname {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        match self
+            .0
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            result => result,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
 struct S3RepositoryStorage {
     location: RepositoryLocation,
     config: S3Location,
@@ -662,19 +1373,31 @@ struct S3RepositoryStorage {
 
 impl S3RepositoryStorage {
     async fn new(config: S3Location) -> Result<Self> {
-        let mut loader = aws_config::defaults(BehaviorVersion::latest());
-        if let Some(region) = &config.region {
-            loader = loader.region(aws_config::Region::new(region.clone()));
+        let mut loader = s3_config_loader(
+            config.endpoint.as_deref(),
+            config.region.as_deref(),
+            &config.credentials,
+        )
+        .await?;
+        if let Some(http_client) = s3_http_client(&config.tls, config.proxy.as_ref())? {
+            loader = loader.http_client(http_client);
         }
-        if let Some(endpoint) = &config.endpoint {
-            loader = loader.endpoint_url(endpoint.clone());
+        if config.checksum == S3Checksum::Off {
+            loader = loader
+                .request_checksum_calculation(RequestChecksumCalculation::WhenRequired)
+                .response_checksum_validation(ResponseChecksumValidation::WhenRequired);
         }
 
         let shared = loader.load().await;
-        let client = Client::new(&shared);
+        let mut client_config =
+            aws_sdk_s3::config::Builder::from(&shared).interceptor(S3RetryMetrics);
+        if request_log::is_enabled() {
+            client_config = client_config.interceptor(S3RequestLog);
+        }
+        let client = Client::from_conf(client_config.build());
 
         Ok(Self {
-            location: RepositoryLocation::S3(config.clone()),
+            location: RepositoryLocation::S3(Box::new(config.clone())),
             config,
             client,
         })
@@ -722,24 +1445,27 @@ impl RepositoryStorage for S3RepositoryStorage {
                 if message.contains("NotFound") || message.contains("404") {
                     Ok(false)
                 } else {
-                    Err(crate::Error::Backend(format!(
-                        "Failed to check existence: {}",
-                        err
-                    )))
+                    Err(s3_error("Failed to check existence", &err))
                 }
             }
         }
     }
 
     async fn read(&self, path: &str) -> Result<Bytes> {
-        let response = self
+        let mut request = self
             .client
             .get_object()
             .bucket(&self.config.bucket)
-            .key(self.key(path))
+            .key(self.key(path));
+        if self.config.checksum == S3Checksum::Sha256 {
+            // The SDK fails the body stream if it doesn't match the stored checksum
+            request = request.checksum_mode(ChecksumMode::Enabled);
+        }
+
+        let response = request
             .send()
             .await
-            .map_err(|e| crate::Error::Backend(format!("Failed to read {}: {}", path, e)))?;
+            .map_err(|e| s3_error(format_args!("Failed to read {}", path), &e))?;
 
         let data =
             response.body.collect().await.map_err(|e| {
@@ -773,10 +1499,22 @@ impl RepositoryStorage for S3RepositoryStorage {
             }
         }
 
-        request
+        if let Some(class) = self.config.storage_classes.for_path(path) {
+            request = request.storage_class(StorageClass::from(class));
+        }
+
+        let sha256 = self.config.checksum == S3Checksum::Sha256;
+        if sha256 {
+            request = request.checksum_algorithm(ChecksumAlgorithm::Sha256);
+        }
+
+        let output = request
             .send()
             .await
-            .map_err(|e| crate::Error::Backend(format!("Failed to write {}: {}", path, e)))?;
+            .map_err(|e| s3_error(format_args!("Failed to write {}", path), &e))?;
+        if sha256 {
+            verify_s3_sha256(path, &data, output.checksum_sha256())?;
+        }
         Ok(())
     }
 
@@ -787,7 +1525,47 @@ impl RepositoryStorage for S3RepositoryStorage {
             .key(self.key(path))
             .send()
             .await
-            .map_err(|e| crate::Error::Backend(format!("Failed to delete {}: {}", path, e)))?;
+            .map_err(|e| s3_error(format_args!("Failed to delete {}", path), &e))?;
+        Ok(())
+    }
+
+    /// `CopyObject` within the bucket; a single request copies up to 5 GiB.
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let mut request = self
+            .client
+            .copy_object()
+            .bucket(&self.config.bucket)
+            .key(self.key(to))
+            .copy_source(format!("{}/{}", self.config.bucket, self.key(from)));
+
+        // The copy gets the bucket defaults unless SSE and class are repeated
+        if let Some(ref sse) = self.config.sse {
+            match sse.mode.as_str() {
+                "aes256" => {
+                    request = request.server_side_encryption(ServerSideEncryption::Aes256);
+                }
+                "kms" => {
+                    request = request.server_side_encryption(ServerSideEncryption::AwsKms);
+                    if let Some(ref key_id) = sse.kms_key_id {
+                        request = request.ssekms_key_id(key_id);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(class) = self.config.storage_classes.for_path(to) {
+            request = request.storage_class(StorageClass::from(class));
+        }
+
+        if self.config.checksum == S3Checksum::Sha256 {
+            request = request.checksum_algorithm(ChecksumAlgorithm::Sha256);
+        }
+
+        request
+            .send()
+            .await
+            .map_err(|e| s3_error(format_args!("Failed to copy {} to {}", from, to), &e))?;
         Ok(())
     }
 
@@ -810,7 +1588,7 @@ impl RepositoryStorage for S3RepositoryStorage {
             let response = request
                 .send()
                 .await
-                .map_err(|e| crate::Error::Backend(format!("Failed to list {}: {}", prefix, e)))?;
+                .map_err(|e| s3_error(format_args!("Failed to list {}", prefix), &e))?;
 
             if let Some(contents) = response.contents {
                 for object in contents {
@@ -861,7 +1639,7 @@ impl RepositoryStorage for S3RepositoryStorage {
             .key(self.key(path))
             .send()
             .await
-            .map_err(|e| crate::Error::Backend(format!("Failed to stat {}: {}", path, e)))?;
+            .map_err(|e| s3_error(format_args!("Failed to stat {}", path), &e))?;
 
         let modified_at = response
             .last_modified
@@ -879,13 +1657,18 @@ impl RepositoryStorage for S3RepositoryStorage {
 // Azure Blob Repository Storage
 // =============================================================================
 
+use azure_core::http::ClientOptions;
 use azure_identity::DeveloperToolsCredential;
-use azure_storage_blob::clients::BlobContainerClient;
+use azure_storage_blob::clients::{BlobContainerClient, BlobContainerClientOptions};
 use azure_storage_blob::models::{
-    BlobClientGetPropertiesResultHeaders, BlobContainerClientListBlobsOptions,
+    AccessTier as BlobAccessTier, BlobClientGetPropertiesResultHeaders, BlobClientSetTierOptions,
+    BlobContainerClientListBlobsOptions, RehydratePriority as BlobRehydratePriority,
 };
 use url::Url;
 
+/// How often to check on a server-side copy that is still pending.
+const COPY_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
 struct AzureRepositoryStorage {
     location: RepositoryLocation,
     config: AzureLocation,
@@ -925,9 +1708,9 @@ impl AzureRepositoryStorage {
             let sas = sas.trim_start_matches('?');
             let url = Url::parse(&format!("{}/{}?{}", endpoint, config.container, sas))
                 .map_err(|e| crate::Error::Backend(format!("Invalid Azure URL: {}", e)))?;
-            return BlobContainerClient::new(url, None, None).map_err(|e| {
-                crate::Error::Backend(format!("Failed to create Azure client: {}", e))
-            });
+            return BlobContainerClient::new(url, None, Self::client_options(config)?).map_err(
+                |e| crate::Error::Backend(format!("Failed to create Azure client: {}", e)),
+            );
         }
 
         let credential = DeveloperToolsCredential::new(None).map_err(|e| {
@@ -939,10 +1722,24 @@ impl AzureRepositoryStorage {
         })?;
         let url = Url::parse(&format!("{}/{}", endpoint, config.container))
             .map_err(|e| crate::Error::Backend(format!("Invalid Azure URL: {}", e)))?;
-        BlobContainerClient::new(url, Some(credential), None)
+        BlobContainerClient::new(url, Some(credential), Self::client_options(config)?)
             .map_err(|e| crate::Error::Backend(format!("Failed to create Azure client: {}", e)))
     }
 
+    /// Client options that route requests through the configured proxy.
+    fn client_options(config: &AzureLocation) -> Result<Option<BlobContainerClientOptions>> {
+        let Some(proxy) = &config.proxy else {
+            return Ok(None);
+        };
+        Ok(Some(BlobContainerClientOptions {
+            client_options: ClientOptions {
+                transport: Some(crate::proxy::azure_transport(proxy)?),
+                ..Default::default()
+            },
+            ..Default::default()
+        }))
+    }
+
     fn key(&self, path: &str) -> String {
         self.config.key(path)
     }
@@ -1007,6 +1804,11 @@ impl RepositoryStorage for AzureRepositoryStorage {
             .await
             .map_err(|e| crate::Error::Backend(format!("Failed to write {}: {}", path, e)))?;
 
+        if let Some(tier) = self.config.access_tiers.for_path(path) {
+            self.set_access_tier(path, tier, RehydratePriority::Standard)
+                .await?;
+        }
+
         Ok(())
     }
 
@@ -1092,6 +1894,110 @@ impl RepositoryStorage for AzureRepositoryStorage {
 
         Ok(ObjectMetadata { size, modified_at })
     }
+
+    /// Server-side Copy Blob. Copies within one storage account finish almost
+    /// immediately, but the API is asynchronous, so wait for the copy status
+    /// to leave `pending`.
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let source = self.client.blob_client(&self.key(from));
+        let target = self.client.blob_client(&self.key(to));
+
+        target
+            .start_copy_from_url(source.url().to_string(), None)
+            .await
+            .map_err(|e| {
+                crate::Error::Backend(format!("Failed to copy {} to {}: {}", from, to, e))
+            })?;
+
+        loop {
+            let response = target
+                .get_properties(None)
+                .await
+                .map_err(|e| crate::Error::Backend(format!("Failed to stat {}: {}", to, e)))?;
+            let status = response
+                .copy_status()
+                .ok()
+                .flatten()
+                .map(|status| status.to_string());
+            match status.as_deref() {
+                Some("pending") => tokio::time::sleep(COPY_POLL_INTERVAL).await,
+                None | Some("success") => break,
+                Some(status) => {
+                    return Err(crate::Error::Backend(format!(
+                        "Copy of {} to {} ended with status {}",
+                        from, to, status
+                    )));
+                }
+            }
+        }
+
+        if let Some(tier) = self.config.access_tiers.for_path(to) {
+            self.set_access_tier(to, tier, RehydratePriority::Standard)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn access_tier(&self, path: &str) -> Result<Option<TierStatus>> {
+        let blob_client = self.client.blob_client(&self.key(path));
+
+        let response = blob_client
+            .get_properties(None)
+            .await
+            .map_err(|e| crate::Error::Backend(format!("Failed to stat {}: {}", path, e)))?;
+
+        let tier = response
+            .access_tier()
+            .ok()
+            .flatten()
+            .and_then(|tier| tier.to_string().parse().ok());
+        // Reported as e.g. "rehydrate-pending-to-cool" while a rehydration runs.
+        let rehydrating_to = response.archive_status().ok().flatten().and_then(|status| {
+            status
+                .to_string()
+                .strip_prefix("rehydrate-pending-to-")
+                .and_then(|tier| tier.parse().ok())
+        });
+
+        Ok(Some(TierStatus {
+            tier,
+            rehydrating_to,
+        }))
+    }
+
+    async fn set_access_tier(
+        &self,
+        path: &str,
+        tier: AccessTier,
+        priority: RehydratePriority,
+    ) -> Result<()> {
+        let blob_client = self.client.blob_client(&self.key(path));
+
+        let blob_tier: BlobAccessTier = tier.to_string().parse().map_err(|e| {
+            crate::Error::Backend(format!("Unsupported access tier {}: {}", tier, e))
+        })?;
+        let rehydrate_priority: BlobRehydratePriority =
+            priority.to_string().parse().map_err(|e| {
+                crate::Error::Backend(format!(
+                    "Unsupported rehydrate priority {}: {}",
+                    priority, e
+                ))
+            })?;
+        let options = BlobClientSetTierOptions {
+            rehydrate_priority: Some(rehydrate_priority),
+            ..Default::default()
+        };
+
+        blob_client
+            .set_tier(blob_tier, Some(options))
+            .await
+            .map_err(|e| {
+                crate::Error::Backend(format!("Failed to set access tier of {}: {}", path, e))
+            })?;
+
+        Ok(())
+    }
 }
 
 // =============================================================================
@@ -1253,6 +2159,23 @@ impl RepositoryStorage for RcloneRepositoryStorage {
         Ok(())
     }
 
+    /// `rclone copyto` on the same remote uses the provider's server-side
+    /// copy where it has one.
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let source = self.full_path(from);
+        let target = self.full_path(to);
+        let (success, _, stderr) = self.run_rclone(&["copyto", &source, &target]).await?;
+
+        if !success {
+            return Err(crate::Error::Backend(format!(
+                "Failed to copy {} to {}: {}",
+                from, to, stderr
+            )));
+        }
+
+        Ok(())
+    }
+
     async fn list(&self, prefix: &str) -> Result<Vec<String>> {
         let full_path = self.full_path(prefix);
         let (success, stdout, stderr) = self
@@ -1319,7 +2242,6 @@ impl RepositoryStorage for RcloneRepositoryStorage {
 use russh_sftp::client::SftpSession;
 use russh_sftp::protocol::{OpenFlags, StatusCode};
 use std::collections::HashSet;
-use std::sync::Arc;
 use tokio::sync::Mutex;
 
 /// SSH client handler that verifies the server host key against the local
@@ -1355,6 +2277,14 @@ impl russh::client::Handler for SftpClientHandler {
     }
 }
 
+fn is_no_such_file(error: &russh_sftp::client::error::Error) -> bool {
+    matches!(
+        error,
+        russh_sftp::client::error::Error::Status(status)
+            if status.status_code == StatusCode::NoSuchFile
+    )
+}
+
 struct SftpRepositoryStorage {
     location: RepositoryLocation,
     config: SftpLocation,
@@ -1479,6 +2409,32 @@ impl SftpRepositoryStorage {
         paths
     }
 
+    /// Remote path of `path`, in the fan-out layout for data objects.
+    fn key(&self, path: &str) -> String {
+        self.config.key(fan_out(path).as_deref().unwrap_or(path))
+    }
+
+    /// Remote paths to try for an existing object: its fan-out path, then its
+    /// flat path for repositories that have not been migrated yet.
+    fn keys(&self, path: &str) -> Vec<String> {
+        let mut keys = vec![self.key(path)];
+        if fan_out(path).is_some() {
+            keys.push(self.config.key(path));
+        }
+        keys
+    }
+
+    /// Moves an object from the flat layout into its shard directory.
+    async fn migrate_to_shard(&self, name: &str) -> Result<()> {
+        let path = format!("{}/{}", FAN_OUT_PREFIX, name);
+        let target = self.key(&path);
+        self.ensure_parent_dirs(&target).await?;
+        self.sftp
+            .rename(self.config.key(&path), target)
+            .await
+            .map_err(|e| crate::Error::Backend(format!("Failed to move {}: {}", path, e)))
+    }
+
     /// Recursively create the parent directories of `key` (mkdir -p), caching
     /// directories that have already been created.
     async fn ensure_parent_dirs(&self, key: &str) -> Result<()> {
@@ -1543,30 +2499,38 @@ impl RepositoryStorage for SftpRepositoryStorage {
     }
 
     async fn exists(&self, path: &str) -> Result<bool> {
-        let key = self.config.key(path);
-        self.sftp
-            .try_exists(key)
-            .await
-            .map_err(|e| crate::Error::Backend(format!("Failed to stat {}: {}", path, e)))
+        for key in self.keys(path) {
+            let found =
+                self.sftp.try_exists(key).await.map_err(|e| {
+                    crate::Error::Backend(format!("Failed to stat {}: {}", path, e))
+                })?;
+            if found {
+                return Ok(true);
+            }
+        }
+        Ok(false)
     }
 
     async fn read(&self, path: &str) -> Result<Bytes> {
-        let key = self.config.key(path);
-        let data = self.sftp.read(key).await.map_err(|e| match e {
-            russh_sftp::client::error::Error::Status(status)
-                if status.status_code == StatusCode::NoSuchFile =>
-            {
-                crate::Error::ChunkNotFound {
-                    id: path.to_string(),
+        for key in self.keys(path) {
+            match self.sftp.read(key).await {
+                Ok(data) => return Ok(Bytes::from(data)),
+                Err(e) if is_no_such_file(&e) => continue,
+                Err(e) => {
+                    return Err(crate::Error::Backend(format!(
+                        "Failed to read {}: {}",
+                        path, e
+                    )));
                 }
             }
-            other => crate::Error::Backend(format!("Failed to read {}: {}", path, other)),
-        })?;
-        Ok(Bytes::from(data))
+        }
+        Err(crate::Error::ChunkNotFound {
+            id: path.to_string(),
+        })
     }
 
     async fn write(&self, path: &str, data: Bytes) -> Result<()> {
-        let key = self.config.key(path);
+        let key = self.key(path);
         self.ensure_parent_dirs(&key).await?;
 
         let mut file = self
@@ -1589,19 +2553,19 @@ impl RepositoryStorage for SftpRepositoryStorage {
     }
 
     async fn delete(&self, path: &str) -> Result<()> {
-        let key = self.config.key(path);
-        match self.sftp.remove_file(key).await {
-            Ok(()) => Ok(()),
-            Err(russh_sftp::client::error::Error::Status(status))
-                if status.status_code == StatusCode::NoSuchFile =>
-            {
-                Ok(())
+        for key in self.keys(path) {
+            match self.sftp.remove_file(key).await {
+                Ok(()) => return Ok(()),
+                Err(e) if is_no_such_file(&e) => continue,
+                Err(e) => {
+                    return Err(crate::Error::Backend(format!(
+                        "Failed to delete {}: {}",
+                        path, e
+                    )));
+                }
             }
-            Err(e) => Err(crate::Error::Backend(format!(
-                "Failed to delete {}: {}",
-                path, e
-            ))),
         }
+        Ok(())
     }
 
     async fn list(&self, prefix: &str) -> Result<Vec<String>> {
@@ -1622,21 +2586,81 @@ impl RepositoryStorage for SftpRepositoryStorage {
             }
         };
 
-        let names = read_dir.map(|entry| entry.file_name()).collect();
+        if prefix != FAN_OUT_PREFIX {
+            return Ok(read_dir.map(|entry| entry.file_name()).collect());
+        }
+
+        let mut names = Vec::new();
+        for entry in read_dir {
+            let name = entry.file_name();
+            if is_shard_dir(&name) && entry.file_type().is_dir() {
+                let shard = format!("{}/{}", prefix, name);
+                let objects = self
+                    .sftp
+                    .read_dir(self.config.key(&shard))
+                    .await
+                    .map_err(|e| {
+                        crate::Error::Backend(format!("Failed to list {}: {}", shard, e))
+                    })?;
+                names.extend(objects.map(|object| object.file_name()));
+                continue;
+            }
+            names.push(name);
+        }
         Ok(names)
     }
 
-    async fn metadata(&self, path: &str) -> Result<ObjectMetadata> {
-        let key = self.config.key(path);
-        let meta = self.sftp.metadata(key).await.map_err(|e| match e {
-            russh_sftp::client::error::Error::Status(status)
+    async fn migrate_layout(&self) -> Result<usize> {
+        let read_dir = match self.sftp.read_dir(self.config.key(FAN_OUT_PREFIX)).await {
+            Ok(entries) => entries,
+            Err(russh_sftp::client::error::Error::Status(status))
                 if status.status_code == StatusCode::NoSuchFile =>
             {
-                crate::Error::ChunkNotFound {
-                    id: path.to_string(),
+                return Ok(0);
+            }
+            Err(e) => {
+                return Err(crate::Error::Backend(format!(
+                    "Failed to list {}: {}",
+                    FAN_OUT_PREFIX, e
+                )));
+            }
+        };
+        let flat: Vec<String> = read_dir
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.file_name())
+            .filter(|name| needs_fan_out(name))
+            .collect();
+
+        // As for local storage, a failed move leaves the object readable
+        let mut migrated = 0;
+        for name in &flat {
+            match self.migrate_to_shard(name).await {
+                Ok(()) => migrated += 1,
+                Err(e) => tracing::warn!("Failed to move {}/{}: {}", FAN_OUT_PREFIX, name, e),
+            }
+        }
+        Ok(migrated)
+    }
+
+    async fn metadata(&self, path: &str) -> Result<ObjectMetadata> {
+        let mut meta = None;
+        for key in self.keys(path) {
+            match self.sftp.metadata(key).await {
+                Ok(found) => {
+                    meta = Some(found);
+                    break;
+                }
+                Err(e) if is_no_such_file(&e) => continue,
+                Err(e) => {
+                    return Err(crate::Error::Backend(format!(
+                        "Failed to stat {}: {}",
+                        path, e
+                    )));
                 }
             }
-            other => crate::Error::Backend(format!("Failed to stat {}: {}", path, other)),
+        }
+        let meta = meta.ok_or_else(|| crate::Error::ChunkNotFound {
+            id: path.to_string(),
         })?;
 
         let size = meta.size.unwrap_or(0);
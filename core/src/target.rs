@@ -0,0 +1,375 @@
+//! Destinations of restored data.
+//!
+//! The counterpart of [`crate::source`]: a [`RestoreTarget`] receives the
+//! entries of a snapshot with their contents and writes them somewhere — a
+//! tar stream, objects in a bucket. [`plan_restore`] decides which entries a
+//! restore includes and in which order, and [`restore_to`] loads their
+//! contents from the repository and hands them to a target, so targets never
+//! deal with chunks or packs.
+//!
+//! The `restore` command writes to a directory itself, since it patches
+//! existing files in place and restores metadata that only a filesystem
+//! keeps; it shares [`plan_restore`] with the other targets.
+
+use crate::repository::Repository;
+use crate::snapshot::Tree;
+use crate::storage::RepositoryStorage;
+use crate::types::{NodeType, TreeNode};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+
+/// Where restored entries are written.
+#[async_trait]
+pub trait RestoreTarget: Send {
+    /// Writes one entry. `contents` holds the data of regular files; it is
+    /// `None` for other entries and for hardlinks, whose `hardlink_target`
+    /// names a file written earlier.
+    async fn write_entry(&mut self, node: &TreeNode, contents: Option<Bytes>) -> Result<()>;
+
+    /// Called after the last entry.
+    async fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Entries and bytes [`restore_to`] wrote.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RestoreTotals {
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+/// Entries restoring `paths` includes, everything when empty: the entries at
+/// or below each path and the directories leading to them, so that those
+/// get their recorded metadata. Directories come first and hardlinks last,
+/// each sorted by name, so parents are created before their contents and
+/// originals before the links to them.
+pub fn plan_restore<'a>(tree: &'a Tree, paths: &[String]) -> Vec<&'a TreeNode> {
+    let mut nodes: Vec<&TreeNode> = if paths.is_empty() {
+        tree.nodes.iter().collect()
+    } else {
+        tree.nodes
+            .iter()
+            .filter(|node| {
+                paths.iter().any(|p| {
+                    let p = p.trim_end_matches('/');
+                    // Exact match or proper directory prefix (with path separator)
+                    node.name == p || node.name.starts_with(&format!("{}/", p))
+                })
+            })
+            .collect()
+    };
+
+    if !paths.is_empty() && !nodes.is_empty() {
+        let selected: HashSet<&str> = nodes.iter().map(|n| n.name.as_str()).collect();
+        let mut ancestors = HashSet::new();
+        for node in &nodes {
+            let mut parent = Path::new(&node.name).parent();
+            while let Some(dir) = parent {
+                let name = dir.to_string_lossy();
+                if name.is_empty() || selected.contains(name.as_ref()) {
+                    break;
+                }
+                ancestors.insert(name.to_string());
+                parent = dir.parent();
+            }
+        }
+        nodes.extend(
+            tree.nodes
+                .iter()
+                .filter(|n| n.node_type == NodeType::Directory && ancestors.contains(&n.name)),
+        );
+    }
+
+    let rank = |node: &TreeNode| match node.node_type {
+        NodeType::Directory => 0,
+        _ if node.hardlink_target.is_some() => 2,
+        _ => 1,
+    };
+    nodes.sort_by(|a, b| rank(a).cmp(&rank(b)).then_with(|| a.name.cmp(&b.name)));
+    nodes
+}
+
+/// Writes `nodes`, planned from `tree`, to `target`. A hardlink whose
+/// original was not written is written as a copy of it.
+pub async fn restore_to(
+    repo: &Repository,
+    tree: &Tree,
+    nodes: &[&TreeNode],
+    target: &mut dyn RestoreTarget,
+) -> Result<RestoreTotals> {
+    let by_name: HashMap<&str, &TreeNode> =
+        tree.nodes.iter().map(|n| (n.name.as_str(), n)).collect();
+    let mut written: HashSet<&str> = HashSet::new();
+    let mut totals = RestoreTotals::default();
+
+    for &node in nodes {
+        if node.node_type != NodeType::File {
+            target.write_entry(node, None).await?;
+        } else if let Some(link) = &node.hardlink_target {
+            if written.contains(link.as_str()) {
+                target.write_entry(node, None).await?;
+            } else {
+                let original = by_name.get(link.as_str()).ok_or_else(|| {
+                    Error::Other(format!(
+                        "Hardlink target '{}' of {} not found in snapshot tree",
+                        link, node.name
+                    ))
+                })?;
+                let contents = load_contents(repo, original).await?;
+                totals.bytes += contents.len() as u64;
+                let copy = TreeNode {
+                    hardlink_target: None,
+                    ..node.clone()
+                };
+                target.write_entry(&copy, Some(contents)).await?;
+            }
+        } else {
+            let contents = load_contents(repo, node).await?;
+            totals.bytes += contents.len() as u64;
+            target.write_entry(node, Some(contents)).await?;
+            written.insert(node.name.as_str());
+        }
+        totals.entries += 1;
+    }
+
+    target.finish().await?;
+    Ok(totals)
+}
+
+async fn load_contents(repo: &Repository, node: &TreeNode) -> Result<Bytes> {
+    let mut contents = BytesMut::with_capacity(node.size as usize);
+    for chunk_ref in &node.chunks {
+        contents.extend_from_slice(&repo.load_chunk(&chunk_ref.id).await?);
+    }
+    Ok(contents.freeze())
+}
+
+/// Writes files as objects in a storage location, e.g. a bucket, keyed by
+/// their path in the snapshot.
+///
+/// Object storage has no directories, links or permissions: directories
+/// are implied by the keys, hardlinks become copies and other entries are
+/// skipped.
+pub struct StorageTarget {
+    storage: Box<dyn RepositoryStorage>,
+}
+
+impl StorageTarget {
+    pub fn new(storage: Box<dyn RepositoryStorage>) -> Self {
+        Self { storage }
+    }
+}
+
+#[async_trait]
+impl RestoreTarget for StorageTarget {
+    async fn write_entry(&mut self, node: &TreeNode, contents: Option<Bytes>) -> Result<()> {
+        if node.node_type != NodeType::File {
+            tracing::debug!(
+                "Skipping {} ({}): only files are written to {}",
+                node.name,
+                node.node_type.as_str(),
+                self.storage.location().display()
+            );
+            return Ok(());
+        }
+        match (contents, &node.hardlink_target) {
+            (Some(data), _) => self.storage.write(&node.name, data).await,
+            (None, Some(original)) => self.storage.copy(original, &node.name).await,
+            (None, None) => self.storage.write(&node.name, Bytes::new()).await,
+        }
+    }
+}
+
+/// Writes entries as a tar stream, e.g. to stdout.
+pub struct TarTarget<W: Write + Send> {
+    builder: tar::Builder<W>,
+}
+
+impl<W: Write + Send> TarTarget<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            builder: tar::Builder::new(writer),
+        }
+    }
+
+    /// Finishes the archive and returns the writer.
+    pub fn into_inner(self) -> Result<W> {
+        Ok(self.builder.into_inner()?)
+    }
+}
+
+#[async_trait]
+impl<W: Write + Send> RestoreTarget for TarTarget<W> {
+    async fn write_entry(&mut self, node: &TreeNode, contents: Option<Bytes>) -> Result<()> {
+        // The root of a backed-up path has no name of its own
+        if node.name.is_empty() {
+            return Ok(());
+        }
+
+        let entry_type = match node.node_type {
+            NodeType::File if contents.is_none() && node.hardlink_target.is_some() => {
+                tar::EntryType::Link
+            }
+            NodeType::File => tar::EntryType::Regular,
+            NodeType::Directory => tar::EntryType::Directory,
+            NodeType::Symlink => tar::EntryType::Symlink,
+            NodeType::CharDevice => tar::EntryType::Char,
+            NodeType::BlockDevice => tar::EntryType::Block,
+            NodeType::Fifo => tar::EntryType::Fifo,
+        };
+
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(node.mode & 0o7777);
+        header.set_uid(u64::from(node.uid));
+        header.set_gid(u64::from(node.gid));
+        header.set_mtime(node.mtime.max(0) as u64);
+        header.set_size(0);
+        if let Some(device) = &node.device {
+            header.set_device_major(device.major)?;
+            header.set_device_minor(device.minor)?;
+        }
+
+        let link = match entry_type {
+            tar::EntryType::Link => node.hardlink_target.as_deref(),
+            tar::EntryType::Symlink => node.link_target.as_deref(),
+            _ => None,
+        };
+        match link {
+            Some(link) => self.builder.append_link(&mut header, &node.name, link)?,
+            None => {
+                let data = contents.unwrap_or_default();
+                header.set_size(data.len() as u64);
+                self.builder
+                    .append_data(&mut header, &node.name, data.as_ref())?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<()> {
+        self.builder.finish()?;
+        self.builder.get_mut().flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str, node_type: NodeType) -> TreeNode {
+        TreeNode {
+            node_type,
+            ..TreeNode::test_file(name, &[])
+        }
+    }
+
+    fn names(nodes: &[&TreeNode]) -> Vec<String> {
+        nodes.iter().map(|n| n.name.clone()).collect()
+    }
+
+    #[test]
+    fn test_plan_restore() {
+        let mut tree = Tree::new();
+        for (name, node_type) in [
+            ("etc", NodeType::Directory),
+            ("etc/hosts", NodeType::File),
+            ("var", NodeType::Directory),
+            ("var/www", NodeType::Directory),
+            ("var/www/site", NodeType::Directory),
+            ("var/www/site/index.php", NodeType::File),
+            ("var/www/site-old", NodeType::Directory),
+            ("var/www/current", NodeType::Symlink),
+        ] {
+            tree.add_node(node(name, node_type));
+        }
+        let mut link = node("etc/hostname", NodeType::File);
+        link.hardlink_target = Some("var/www/site/index.php".to_string());
+        tree.add_node(link);
+
+        // Directories first, so parents exist before their contents, and
+        // hardlinks after their originals
+        assert_eq!(
+            names(&plan_restore(&tree, &[])),
+            vec![
+                "etc",
+                "var",
+                "var/www",
+                "var/www/site",
+                "var/www/site-old",
+                "etc/hosts",
+                "var/www/current",
+                "var/www/site/index.php",
+                "etc/hostname",
+            ]
+        );
+
+        // Selected paths bring their ancestors but not their siblings
+        assert_eq!(
+            names(&plan_restore(&tree, &["var/www/site/".to_string()])),
+            vec!["var", "var/www", "var/www/site", "var/www/site/index.php"]
+        );
+        assert!(plan_restore(&tree, &["missing".to_string()]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tar_target() {
+        let mut file = node("www/index.php", NodeType::File);
+        file.mode = 0o100640;
+        let mut link = node("www/home.php", NodeType::File);
+        link.hardlink_target = Some("www/index.php".to_string());
+        let mut symlink = node("www/current", NodeType::Symlink);
+        symlink.link_target = Some("index.php".to_string());
+
+        let mut target = TarTarget::new(Vec::new());
+        target
+            .write_entry(&node("", NodeType::Directory), None)
+            .await
+            .unwrap();
+        target
+            .write_entry(&node("www", NodeType::Directory), None)
+            .await
+            .unwrap();
+        target
+            .write_entry(&file, Some(Bytes::from_static(b"<?php")))
+            .await
+            .unwrap();
+        target.write_entry(&link, None).await.unwrap();
+        target.write_entry(&symlink, None).await.unwrap();
+        target.finish().await.unwrap();
+        let data = target.into_inner().unwrap();
+
+        let mut archive = tar::Archive::new(data.as_slice());
+        let mut entries = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().to_string();
+            let entry_type = entry.header().entry_type();
+            let mode = entry.header().mode().unwrap();
+            let link = entry
+                .link_name()
+                .unwrap()
+                .map(|l| l.to_string_lossy().to_string());
+            let mut contents = Vec::new();
+            std::io::Read::read_to_end(&mut entry, &mut contents).unwrap();
+            entries.push((path, entry_type, mode, link, contents));
+        }
+
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].0, "www");
+        assert_eq!(entries[0].1, tar::EntryType::Directory);
+        assert_eq!(entries[1].0, "www/index.php");
+        assert_eq!(entries[1].2, 0o640);
+        assert_eq!(entries[1].4, b"<?php");
+        assert_eq!(entries[2].1, tar::EntryType::Link);
+        assert_eq!(entries[2].3.as_deref(), Some("www/index.php"));
+        assert_eq!(entries[3].1, tar::EntryType::Symlink);
+        assert_eq!(entries[3].3.as_deref(), Some("index.php"));
+    }
+}
@@ -1,5 +1,6 @@
+use crate::hash::HashAlgorithm;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -12,6 +13,8 @@ impl ChunkID {
         Self(hash)
     }
 
+    /// The ID of `data` in repositories using the default BLAKE3 hash; see
+    /// [`crate::hash::ChunkHasher`] for the others.
     pub fn from_data(data: &[u8]) -> Self {
         Self(blake3::hash(data))
     }
@@ -35,6 +38,12 @@ impl From<blake3::Hash> for ChunkID {
     }
 }
 
+impl From<[u8; 32]> for ChunkID {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(blake3::Hash::from(bytes))
+    }
+}
+
 impl FromStr for ChunkID {
     type Err = hex::FromHexError;
 
@@ -73,17 +82,375 @@ impl fmt::Display for ChunkID {
         write!(f, "{}", self.to_hex())
     }
 }
-pub type SnapshotID = String;
-pub type PackID = String;
+
+/// Defines an object ID newtype over a lowercase hyphenated UUID, the form
+/// snapshot and pack IDs have always been generated and stored in. Parsing
+/// and deserialization reject anything else, so an ID read from user input
+/// or a damaged object can't name an arbitrary storage path.
+macro_rules! uuid_id {
+    ($(#[$meta:meta])* $name:ident, $kind:literal) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(String);
+
+        impl $name {
+            /// Generates a new random ID.
+            pub fn generate() -> Self {
+                Self(uuid::Uuid::new_v4().to_string())
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+
+            /// The first 8 characters, as shown in listings.
+            pub fn short(&self) -> &str {
+                &self.0[..8]
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = crate::Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match uuid::Uuid::parse_str(s) {
+                    Ok(uuid) if uuid.hyphenated().to_string() == s => Ok(Self(s.to_string())),
+                    _ => Err(crate::Error::InvalidId {
+                        kind: $kind,
+                        id: s.to_string(),
+                    }),
+                }
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                serializer.serialize_str(&self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                let s = String::deserialize(deserializer)?;
+                s.parse().map_err(serde::de::Error::custom)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl std::borrow::Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+    };
+}
+
+uuid_id!(
+    /// Identifies a snapshot, stored at `snapshots/<id>`.
+    SnapshotID,
+    "snapshot"
+);
+
+uuid_id!(
+    /// Identifies a pack file, stored at `data/<id>.pack`.
+    PackID,
+    "pack"
+);
+
+/// Chunker polynomial of every repository created before the polynomial
+/// seeded the chunker. Repositories with it keep FastCDC's stock boundaries.
+pub const LEGACY_CHUNKER_POLYNOMIAL: u64 = 0x3DA3358B4DC173;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RepoConfig {
     pub version: u32,
     pub id: String,
+    /// Random per repository; seeds the chunker's gear hash, see
+    /// [`RepoConfig::chunker_seed`]
     pub chunker_polynomial: u64,
     pub kdf_params: KdfParams,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub transport: Option<RepoTransport>,
+    /// Repositories created before this field existed are encrypted.
+    #[serde(default)]
+    pub encryption: EncryptionMode,
+    /// Where data is expected to be encrypted; derived from `encryption` when
+    /// absent (see [`RepoConfig::encryption_layer`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption_layer: Option<EncryptionLayer>,
+    /// Average chunk size of new backups; `None` uses
+    /// [`crate::chunker::DEFAULT_CHUNK_SIZE`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<u32>,
+    /// zlib level (0-9) of new packs; `None` uses zlib's default of 6
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<u32>,
+    /// Algorithm computing chunk IDs; repositories created before this
+    /// field existed use BLAKE3
+    #[serde(default, skip_serializing_if = "HashAlgorithm::is_default")]
+    pub hash: HashAlgorithm,
+    /// Primitives the repository is restricted to; checked on every open
+    #[serde(default, skip_serializing_if = "CryptoProfile::is_default")]
+    pub profile: CryptoProfile,
+}
+
+impl RepoConfig {
+    /// Returns the configured encryption layer. Repositories created before
+    /// the setting existed use `repo`, or `backend` if they are unencrypted.
+    pub fn encryption_layer(&self) -> EncryptionLayer {
+        self.encryption_layer
+            .unwrap_or_else(|| EncryptionLayer::for_mode(self.encryption))
+    }
+
+    /// Seed for the chunker's gear hash, making chunk boundaries (and so
+    /// chunk sizes) specific to this repository. `None` for repositories
+    /// with [`LEGACY_CHUNKER_POLYNOMIAL`], whose existing chunks only
+    /// deduplicate against the stock boundaries.
+    pub fn chunker_seed(&self) -> Option<u64> {
+        (self.chunker_polynomial != LEGACY_CHUNKER_POLYNOMIAL).then_some(self.chunker_polynomial)
+    }
+
+    /// Checks the chunk size, compression level, hash algorithm and that the
+    /// crypto profile's primitives are used.
+    pub fn validate(&self) -> crate::Result<()> {
+        use crate::chunker::{MAX_CHUNK_SIZE, MIN_CHUNK_SIZE};
+
+        let mut validator = crate::Validator::new();
+        if let Some(size) = self.chunk_size {
+            validator.check(
+                (MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&size),
+                "chunk_size",
+                format!(
+                    "must be between {} and {} bytes, got {}",
+                    MIN_CHUNK_SIZE, MAX_CHUNK_SIZE, size
+                ),
+            );
+        }
+        if let Some(level) = self.compression_level {
+            validator.check(
+                level <= 9,
+                "compression_level",
+                format!("must be between 0 and 9, got {}", level),
+            );
+        }
+        validator.check(
+            !self.hash.is_keyed() || self.encryption.is_encrypted(),
+            "hash",
+            format!("{} needs an encrypted repository", self.hash),
+        );
+        if self.profile == CryptoProfile::Fips {
+            validator.check(
+                self.encryption == EncryptionMode::Aes256Gcm,
+                "encryption",
+                format!("the fips profile needs aes256-gcm, got {}", self.encryption),
+            );
+            validator.check(
+                self.kdf_params.algorithm == KDF_PBKDF2_SHA256,
+                "kdf_params",
+                format!(
+                    "the fips profile needs {}, got {}",
+                    KDF_PBKDF2_SHA256, self.kdf_params.algorithm
+                ),
+            );
+            validator.check(
+                self.hash == HashAlgorithm::Sha256,
+                "hash",
+                format!("the fips profile needs sha256, got {}", self.hash),
+            );
+        }
+        validator.finish()
+    }
+}
+
+/// How repository objects are protected at rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EncryptionMode {
+    /// ChaCha20-Poly1305 with a password-protected data key.
+    #[default]
+    Chacha20Poly1305,
+    /// AES-256-GCM with a password-protected data key.
+    #[serde(rename = "aes256-gcm")]
+    Aes256Gcm,
+    /// Plaintext objects carrying a BLAKE3 checksum. No key, no password.
+    None,
+}
+
+impl EncryptionMode {
+    pub fn is_encrypted(&self) -> bool {
+        !matches!(self, EncryptionMode::None)
+    }
+}
+
+impl fmt::Display for EncryptionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptionMode::Chacha20Poly1305 => write!(f, "chacha20-poly1305"),
+            EncryptionMode::Aes256Gcm => write!(f, "aes256-gcm"),
+            EncryptionMode::None => write!(f, "none (insecure)"),
+        }
+    }
+}
+
+/// Which layer is responsible for encrypting data at rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EncryptionLayer {
+    /// Ghostsnap encrypts every object; the backend may or may not.
+    Repo,
+    /// Ghostsnap stores checksummed plaintext and relies on the backend
+    /// (server-side encryption, an encrypted disk) to encrypt it.
+    Backend,
+    /// Ghostsnap encrypts every object and the backend is expected to
+    /// encrypt as well.
+    Both,
+}
+
+impl EncryptionLayer {
+    /// Ghostsnap-side encryption mode this layer needs.
+    pub fn mode(&self) -> EncryptionMode {
+        match self {
+            EncryptionLayer::Backend => EncryptionMode::None,
+            EncryptionLayer::Repo | EncryptionLayer::Both => EncryptionMode::Chacha20Poly1305,
+        }
+    }
+
+    /// Layer implied by an encryption mode when no layer was configured.
+    pub fn for_mode(mode: EncryptionMode) -> Self {
+        if mode.is_encrypted() {
+            EncryptionLayer::Repo
+        } else {
+            EncryptionLayer::Backend
+        }
+    }
+
+    /// Whether this layer relies on the backend encrypting data.
+    pub fn expects_backend(&self) -> bool {
+        matches!(self, EncryptionLayer::Backend | EncryptionLayer::Both)
+    }
+}
+
+impl fmt::Display for EncryptionLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptionLayer::Repo => write!(f, "repo"),
+            EncryptionLayer::Backend => write!(f, "backend"),
+            EncryptionLayer::Both => write!(f, "both"),
+        }
+    }
+}
+
+impl FromStr for EncryptionLayer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "repo" => Ok(EncryptionLayer::Repo),
+            "backend" => Ok(EncryptionLayer::Backend),
+            "both" => Ok(EncryptionLayer::Both),
+            other => Err(format!(
+                "Unknown encryption layer '{}' (expected repo, backend or both)",
+                other
+            )),
+        }
+    }
+}
+
+/// Set of primitives a repository's encryption is restricted to, chosen at
+/// init.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CryptoProfile {
+    /// ChaCha20-Poly1305, Argon2id and BLAKE3.
+    #[default]
+    Standard,
+    /// FIPS-approved algorithms only: AES-256-GCM, PBKDF2-HMAC-SHA256 and
+    /// SHA-256 chunk IDs.
+    Fips,
+}
+
+impl CryptoProfile {
+    pub fn is_default(&self) -> bool {
+        *self == CryptoProfile::default()
+    }
+
+    /// Encryption mode `layer` needs under this profile.
+    pub fn mode(&self, layer: EncryptionLayer) -> EncryptionMode {
+        match (self, layer.mode().is_encrypted()) {
+            (_, false) => EncryptionMode::None,
+            (CryptoProfile::Standard, true) => EncryptionMode::Chacha20Poly1305,
+            (CryptoProfile::Fips, true) => EncryptionMode::Aes256Gcm,
+        }
+    }
+
+    /// KDF parameters of new keys, with a fresh salt.
+    pub fn kdf_params(&self) -> KdfParams {
+        match self {
+            CryptoProfile::Standard => KdfParams::default(),
+            CryptoProfile::Fips => KdfParams::pbkdf2_sha256(),
+        }
+    }
+
+    /// Chunk ID hash of new repositories.
+    pub fn hash(&self) -> HashAlgorithm {
+        match self {
+            CryptoProfile::Standard => HashAlgorithm::default(),
+            CryptoProfile::Fips => HashAlgorithm::Sha256,
+        }
+    }
+}
+
+impl fmt::Display for CryptoProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoProfile::Standard => write!(f, "standard"),
+            CryptoProfile::Fips => write!(f, "fips"),
+        }
+    }
+}
+
+impl FromStr for CryptoProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "standard" => Ok(CryptoProfile::Standard),
+            "fips" => Ok(CryptoProfile::Fips),
+            other => Err(format!(
+                "Unknown crypto profile '{}' (expected standard or fips)",
+                other
+            )),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -105,6 +472,10 @@ pub struct S3RepoTransport {
     pub region: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub sse: Option<S3RepoSse>,
+    #[serde(default, skip_serializing_if = "S3StorageClasses::is_empty")]
+    pub storage_classes: S3StorageClasses,
+    #[serde(default, skip_serializing_if = "S3Checksum::is_default")]
+    pub checksum: S3Checksum,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -114,11 +485,322 @@ pub struct S3RepoSse {
     pub kms_key_id: Option<String>,
 }
 
+/// S3 storage classes applied on write, chosen by object type.
+///
+/// Pack files are only read for restore, check and prune, so they can use a
+/// cheaper class. Everything else (config, keys, snapshots, index, trees,
+/// locks) is read on every listing and must stay in an instantly readable
+/// class.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct S3StorageClasses {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+}
+
+/// Storage classes whose objects must be restored before they can be read.
+const ARCHIVE_STORAGE_CLASSES: &[&str] = &["GLACIER", "DEEP_ARCHIVE"];
+
+impl S3StorageClasses {
+    pub fn is_empty(&self) -> bool {
+        self.metadata.is_none() && self.data.is_none()
+    }
+
+    /// Storage class for the object at `path`, relative to the repository root.
+    pub fn for_path(&self, path: &str) -> Option<&str> {
+        if path.starts_with("data/") && path.ends_with(".pack") {
+            self.data.as_deref()
+        } else {
+            self.metadata.as_deref()
+        }
+    }
+
+    /// Applies a `metadata=CLASS` or `data=CLASS` assignment.
+    pub fn set(&mut self, assignment: &str) -> crate::Result<()> {
+        let (kind, class) = assignment.split_once('=').ok_or_else(|| {
+            crate::Error::Other(format!(
+                "Invalid storage class '{}' (expected metadata=CLASS or data=CLASS)",
+                assignment
+            ))
+        })?;
+        let class = class.trim().to_ascii_uppercase();
+        if class.is_empty() {
+            return Err(crate::Error::Other(format!(
+                "Missing storage class in '{}'",
+                assignment
+            )));
+        }
+
+        match kind.trim() {
+            "metadata" => {
+                if ARCHIVE_STORAGE_CLASSES.contains(&class.as_str()) {
+                    return Err(crate::Error::Other(format!(
+                        "Storage class {} cannot be used for metadata: snapshots and indexes must stay readable",
+                        class
+                    )));
+                }
+                self.metadata = Some(class);
+            }
+            "data" => self.data = Some(class),
+            other => {
+                return Err(crate::Error::Other(format!(
+                    "Unknown object type '{}' (expected metadata or data)",
+                    other
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether packs go to a class that needs a restore before reading.
+    pub fn data_is_archived(&self) -> bool {
+        self.data
+            .as_deref()
+            .is_some_and(|class| ARCHIVE_STORAGE_CLASSES.contains(&class))
+    }
+}
+
+/// Integrity checksum sent with S3 uploads and checked on downloads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum S3Checksum {
+    /// Whatever the SDK sends by default (CRC32 on current releases)
+    #[default]
+    Default,
+    /// SHA-256 trailer checksum on upload, compared with the checksum S3
+    /// returns; downloads are validated against the stored checksum
+    Sha256,
+    /// Only checksums an operation requires, for S3-compatible services that
+    /// reject checksum trailers
+    Off,
+}
+
+impl S3Checksum {
+    pub fn is_default(&self) -> bool {
+        *self == S3Checksum::Default
+    }
+}
+
+impl std::fmt::Display for S3Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            S3Checksum::Default => f.write_str("default"),
+            S3Checksum::Sha256 => f.write_str("sha256"),
+            S3Checksum::Off => f.write_str("off"),
+        }
+    }
+}
+
+impl std::str::FromStr for S3Checksum {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "default" => Ok(S3Checksum::Default),
+            "sha256" => Ok(S3Checksum::Sha256),
+            "off" => Ok(S3Checksum::Off),
+            other => Err(format!(
+                "Unknown S3 checksum mode '{}' (expected default, sha256 or off)",
+                other
+            )),
+        }
+    }
+}
+
+/// TLS settings for S3-compatible endpoints, e.g. MinIO behind a private CA.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct S3Tls {
+    /// PEM bundle of root certificates trusted in addition to the system roots
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<PathBuf>,
+    /// PEM client certificate chain for mutual TLS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<PathBuf>,
+    /// PEM private key of `client_cert`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<PathBuf>,
+    /// Accept certificates issued for another hostname. The chain is still
+    /// verified; only the name check is skipped.
+    #[serde(default)]
+    pub accept_invalid_hostnames: bool,
+}
+
+impl S3Tls {
+    pub fn is_default(&self) -> bool {
+        *self == S3Tls::default()
+    }
+
+    /// Reads TLS settings from the environment:
+    ///
+    /// - `GHOSTSNAP_S3_CA_BUNDLE`, falling back to `AWS_CA_BUNDLE`
+    /// - `GHOSTSNAP_S3_CLIENT_CERT`, `GHOSTSNAP_S3_CLIENT_KEY`
+    /// - `GHOSTSNAP_S3_ACCEPT_INVALID_HOSTNAMES` - `1` or `true` to opt in
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        Self {
+            ca_bundle: var("GHOSTSNAP_S3_CA_BUNDLE")
+                .or_else(|| var("AWS_CA_BUNDLE"))
+                .map(PathBuf::from),
+            client_cert: var("GHOSTSNAP_S3_CLIENT_CERT").map(PathBuf::from),
+            client_key: var("GHOSTSNAP_S3_CLIENT_KEY").map(PathBuf::from),
+            accept_invalid_hostnames: matches!(
+                var("GHOSTSNAP_S3_ACCEPT_INVALID_HOSTNAMES").as_deref(),
+                Some("1" | "true" | "yes")
+            ),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AzureRepoTransport {
     pub account_name: String,
     pub container: String,
     pub prefix: String,
+    #[serde(default, skip_serializing_if = "AzureAccessTiers::is_empty")]
+    pub access_tiers: AzureAccessTiers,
+}
+
+/// Azure blob access tier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum AccessTier {
+    Hot,
+    Cool,
+    Cold,
+    Archive,
+}
+
+impl AccessTier {
+    /// Whether blobs in this tier can be read without rehydrating them first.
+    pub fn is_online(&self) -> bool {
+        !matches!(self, AccessTier::Archive)
+    }
+}
+
+impl std::fmt::Display for AccessTier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AccessTier::Hot => "Hot",
+            AccessTier::Cool => "Cool",
+            AccessTier::Cold => "Cold",
+            AccessTier::Archive => "Archive",
+        };
+        f.write_str(name)
+    }
+}
+
+impl std::str::FromStr for AccessTier {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "hot" => Ok(AccessTier::Hot),
+            "cool" => Ok(AccessTier::Cool),
+            "cold" => Ok(AccessTier::Cold),
+            "archive" => Ok(AccessTier::Archive),
+            other => Err(format!(
+                "Unknown access tier '{}' (expected hot, cool, cold or archive)",
+                other
+            )),
+        }
+    }
+}
+
+/// How urgently an archived blob should be rehydrated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RehydratePriority {
+    /// Completes within about 15 hours.
+    #[default]
+    Standard,
+    /// Completes within about an hour for small blobs, at a higher price.
+    High,
+}
+
+impl std::fmt::Display for RehydratePriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RehydratePriority::Standard => f.write_str("Standard"),
+            RehydratePriority::High => f.write_str("High"),
+        }
+    }
+}
+
+impl std::str::FromStr for RehydratePriority {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "standard" => Ok(RehydratePriority::Standard),
+            "high" => Ok(RehydratePriority::High),
+            other => Err(format!(
+                "Unknown rehydrate priority '{}' (expected standard or high)",
+                other
+            )),
+        }
+    }
+}
+
+/// Azure access tiers applied on write, chosen by object type.
+///
+/// Same split as [`S3StorageClasses`]: packs may be demoted as far as
+/// Archive, metadata must stay in an online tier.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AzureAccessTiers {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<AccessTier>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<AccessTier>,
+}
+
+impl AzureAccessTiers {
+    pub fn is_empty(&self) -> bool {
+        self.metadata.is_none() && self.data.is_none()
+    }
+
+    /// Access tier for the object at `path`, relative to the repository root.
+    pub fn for_path(&self, path: &str) -> Option<AccessTier> {
+        if path.starts_with("data/") && path.ends_with(".pack") {
+            self.data
+        } else {
+            self.metadata
+        }
+    }
+
+    /// Applies a `metadata=TIER` or `data=TIER` assignment.
+    pub fn set(&mut self, assignment: &str) -> crate::Result<()> {
+        let (kind, tier) = assignment.split_once('=').ok_or_else(|| {
+            crate::Error::Other(format!(
+                "Invalid access tier '{}' (expected metadata=TIER or data=TIER)",
+                assignment
+            ))
+        })?;
+        let tier: AccessTier = tier.parse().map_err(crate::Error::Other)?;
+
+        match kind.trim() {
+            "metadata" => {
+                if !tier.is_online() {
+                    return Err(crate::Error::Other(format!(
+                        "Access tier {} cannot be used for metadata: snapshots and indexes must stay readable",
+                        tier
+                    )));
+                }
+                self.metadata = Some(tier);
+            }
+            "data" => self.data = Some(tier),
+            other => {
+                return Err(crate::Error::Other(format!(
+                    "Unknown object type '{}' (expected metadata or data)",
+                    other
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether packs go to a tier that needs rehydration before reading.
+    pub fn data_is_archived(&self) -> bool {
+        self.data.is_some_and(|tier| !tier.is_online())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -135,8 +817,20 @@ pub struct SftpRepoTransport {
     pub path: String,
 }
 
+/// [`KdfParams::algorithm`] of Argon2id, the default.
+pub const KDF_ARGON2ID: &str = "argon2id";
+
+/// [`KdfParams::algorithm`] of PBKDF2-HMAC-SHA256, used by the FIPS profile.
+pub const KDF_PBKDF2_SHA256: &str = "pbkdf2-sha256";
+
+/// PBKDF2 iterations of new keys, following OWASP's recommendation for
+/// PBKDF2-HMAC-SHA256.
+pub const PBKDF2_ITERATIONS: u32 = 600_000;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KdfParams {
+    /// [`KDF_ARGON2ID`] or [`KDF_PBKDF2_SHA256`]; `memory` and
+    /// `parallelism` only apply to Argon2id
     pub algorithm: String,
     pub iterations: u32,
     pub memory: u32,
@@ -172,6 +866,11 @@ pub struct FileEntry {
     pub chunks: Vec<ChunkID>,
 }
 
+/// Extended attribute holding a file's capabilities (e.g.
+/// `cap_net_bind_service`). Changing the owner clears it, so restores apply
+/// it last.
+pub const CAPABILITY_XATTR: &str = "security.capability";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TreeNode {
     pub name: String,
@@ -186,13 +885,15 @@ pub struct TreeNode {
     pub link_target: Option<String>,
     pub subtree_id: Option<ChunkID>,
     pub chunks: Vec<ChunkRef>,
-    /// Extended attributes (name -> value)
+    /// Extended attributes (name -> value), ordered so that serialized trees
+    /// are deterministic
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub xattr: Option<HashMap<String, Vec<u8>>>,
+    pub xattr: Option<BTreeMap<String, Vec<u8>>>,
     /// Sparse file holes as (offset, length) pairs
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub sparse_holes: Option<Vec<(u64, u64)>>,
-    /// Inode number for hardlink detection (Unix only)
+    /// Inode number, for hardlink detection and for recognizing files
+    /// unchanged since the parent snapshot (Unix only)
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub inode: Option<u64>,
     /// Number of hardlinks to this inode
@@ -201,13 +902,67 @@ pub struct TreeNode {
     /// Path to the original file for hardlinks (if this is a hardlink to another file)
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub hardlink_target: Option<String>,
+    /// Device number (only for NodeType::CharDevice and NodeType::BlockDevice)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<DeviceNumber>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum NodeType {
     File,
     Directory,
     Symlink,
+    /// Character device; metadata only, no content is stored
+    CharDevice,
+    /// Block device; metadata only, no content is stored
+    BlockDevice,
+    /// Named pipe; metadata only, no content is stored
+    Fifo,
+}
+
+impl NodeType {
+    /// Name used in listings and JSON output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeType::File => "file",
+            NodeType::Directory => "directory",
+            NodeType::Symlink => "symlink",
+            NodeType::CharDevice => "char_device",
+            NodeType::BlockDevice => "block_device",
+            NodeType::Fifo => "fifo",
+        }
+    }
+}
+
+/// Major and minor number of a device node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DeviceNumber {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl DeviceNumber {
+    /// Splits a raw device number (`st_rdev`).
+    #[cfg(unix)]
+    pub fn from_rdev(rdev: u64) -> Self {
+        let rdev = rdev as libc::dev_t;
+        Self {
+            major: libc::major(rdev) as u32,
+            minor: libc::minor(rdev) as u32,
+        }
+    }
+
+    /// Combines the numbers into a raw device number for `mknod`.
+    #[cfg(unix)]
+    pub fn to_rdev(self) -> u64 {
+        libc::makedev(self.major as _, self.minor as _) as u64
+    }
+}
+
+impl std::fmt::Display for DeviceNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}, {}", self.major, self.minor)
+    }
 }
 
 impl TreeNode {
@@ -222,6 +977,56 @@ impl TreeNode {
     pub fn is_symlink(&self) -> bool {
         matches!(self.node_type, NodeType::Symlink)
     }
+
+    /// Returns whether the node is a device or FIFO, stored as metadata only.
+    pub fn is_special(&self) -> bool {
+        matches!(
+            self.node_type,
+            NodeType::CharDevice | NodeType::BlockDevice | NodeType::Fifo
+        )
+    }
+
+    /// The recorded file capabilities, see [`CAPABILITY_XATTR`].
+    pub fn capabilities(&self) -> Option<&[u8]> {
+        self.xattr
+            .as_ref()?
+            .get(CAPABILITY_XATTR)
+            .map(Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+impl TreeNode {
+    /// A file made of `chunks`, owned by root with mode 0644, for tests to
+    /// adjust with struct update syntax.
+    pub(crate) fn test_file(name: &str, chunks: &[&[u8]]) -> Self {
+        let chunks: Vec<ChunkRef> = chunks
+            .iter()
+            .map(|data| ChunkRef {
+                id: ChunkID::from_data(data),
+                offset: 0,
+                length: data.len() as u32,
+            })
+            .collect();
+        Self {
+            name: name.to_string(),
+            node_type: NodeType::File,
+            mode: 0o644,
+            uid: 0,
+            gid: 0,
+            size: chunks.iter().map(|c| c.length as u64).sum(),
+            mtime: 0,
+            link_target: None,
+            subtree_id: None,
+            chunks,
+            xattr: None,
+            sparse_holes: None,
+            inode: None,
+            nlink: None,
+            hardlink_target: None,
+            device: None,
+        }
+    }
 }
 
 impl Default for RepoConfig {
@@ -229,9 +1034,15 @@ impl Default for RepoConfig {
         Self {
             version: 1,
             id: uuid::Uuid::new_v4().to_string(),
-            chunker_polynomial: 0x3DA3358B4DC173,
+            chunker_polynomial: rand::random(),
             kdf_params: KdfParams::default(),
             transport: None,
+            encryption: EncryptionMode::default(),
+            encryption_layer: None,
+            chunk_size: None,
+            compression_level: None,
+            hash: HashAlgorithm::default(),
+            profile: CryptoProfile::default(),
         }
     }
 }
@@ -243,7 +1054,7 @@ impl Default for KdfParams {
         rand::thread_rng().fill_bytes(&mut salt);
 
         Self {
-            algorithm: "argon2id".to_string(),
+            algorithm: KDF_ARGON2ID.to_string(),
             iterations: 1,
             memory: 65536,
             parallelism: 4,
@@ -252,4 +1063,94 @@ impl Default for KdfParams {
     }
 }
 
+impl KdfParams {
+    /// PBKDF2-HMAC-SHA256 with [`PBKDF2_ITERATIONS`] and a fresh salt.
+    pub fn pbkdf2_sha256() -> Self {
+        Self {
+            algorithm: KDF_PBKDF2_SHA256.to_string(),
+            iterations: PBKDF2_ITERATIONS,
+            memory: 0,
+            parallelism: 1,
+            ..Self::default()
+        }
+    }
+}
+
 use uuid;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_id_parsing() {
+        let id = SnapshotID::generate();
+        assert_eq!(id.as_str().parse::<SnapshotID>().unwrap(), id);
+        assert_eq!(id.short(), &id.as_str()[..8]);
+
+        for invalid in [
+            "",
+            "abc",
+            "../../config",
+            "0123456789abcdef0123456789abcdef",
+            "0123ABCD-0000-4000-8000-000000000000",
+        ] {
+            assert!(matches!(
+                invalid.parse::<PackID>(),
+                Err(crate::Error::InvalidId { kind: "pack", .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_id_serde() {
+        let id = PackID::generate();
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(json, format!("\"{}\"", id));
+        assert_eq!(serde_json::from_str::<PackID>(&json).unwrap(), id);
+        assert!(serde_json::from_str::<PackID>("\"not-a-pack\"").is_err());
+    }
+
+    #[test]
+    fn test_fips_profile_validation() {
+        let profile = CryptoProfile::Fips;
+        let config = RepoConfig {
+            kdf_params: profile.kdf_params(),
+            encryption: profile.mode(EncryptionLayer::Repo),
+            hash: profile.hash(),
+            profile,
+            ..RepoConfig::default()
+        };
+        assert_eq!(config.encryption, EncryptionMode::Aes256Gcm);
+        assert!(config.validate().is_ok());
+
+        for invalid in [
+            RepoConfig {
+                encryption: EncryptionMode::Chacha20Poly1305,
+                ..config.clone()
+            },
+            RepoConfig {
+                encryption: profile.mode(EncryptionLayer::Backend),
+                ..config.clone()
+            },
+            RepoConfig {
+                kdf_params: KdfParams::default(),
+                ..config.clone()
+            },
+            RepoConfig {
+                hash: HashAlgorithm::Blake3,
+                ..config.clone()
+            },
+        ] {
+            assert!(invalid.validate().is_err());
+        }
+
+        // Configs without a profile are standard
+        let mut json = serde_json::to_value(RepoConfig::default()).unwrap();
+        assert!(json.get("profile").is_none());
+        json["profile"] = "fips".into();
+        let parsed: RepoConfig = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.profile, CryptoProfile::Fips);
+        assert!(parsed.validate().is_err());
+    }
+}
@@ -0,0 +1,137 @@
+//! Field-level validation of configuration values.
+//!
+//! Bad settings (an empty bucket, a part size the backend rejects) otherwise
+//! surface only as cryptic backend errors once the first request is made.
+//! Configurations collect every problem in a [`Validator`] and report them
+//! together, each naming the offending field, before any network call.
+
+use crate::{Error, Result};
+use std::fmt;
+
+/// A problem with one configuration field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    /// Dotted path of the field, e.g. `jobs.nightly.pre_hook_timeout`
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Collects the field errors of a configuration.
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an error for `field`.
+    pub fn error(&mut self, field: impl Into<String>, message: impl fmt::Display) {
+        self.errors.push(FieldError {
+            field: field.into(),
+            message: message.to_string(),
+        });
+    }
+
+    /// Records an error for `field` unless `ok` holds.
+    pub fn check(&mut self, ok: bool, field: impl Into<String>, message: impl fmt::Display) {
+        if !ok {
+            self.error(field, message);
+        }
+    }
+
+    /// Records the error of a parse result for `field`.
+    pub fn check_result<T>(&mut self, result: Result<T>, field: impl Into<String>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.error(field, e);
+                None
+            }
+        }
+    }
+
+    pub fn into_errors(self) -> Vec<FieldError> {
+        self.errors
+    }
+
+    /// Fails with [`Error::InvalidConfig`] if any error was recorded.
+    pub fn finish(self) -> Result<()> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidConfig(self.errors))
+        }
+    }
+}
+
+/// Checks an S3 bucket name against the S3 naming rules: 3 to 63 lowercase
+/// letters, digits, dots and hyphens, starting and ending with a letter or
+/// digit.
+pub fn check_bucket_name(bucket: &str) -> std::result::Result<(), String> {
+    if bucket.is_empty() {
+        return Err("must not be empty".to_string());
+    }
+    if !(3..=63).contains(&bucket.len()) {
+        return Err(format!("'{}' must be 3 to 63 characters long", bucket));
+    }
+    if !bucket
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '.' || c == '-')
+    {
+        return Err(format!(
+            "'{}' may only contain lowercase letters, digits, dots and hyphens",
+            bucket
+        ));
+    }
+    let alphanumeric = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+    if !alphanumeric(bucket.chars().next()) || !alphanumeric(bucket.chars().last()) {
+        return Err(format!(
+            "'{}' must start and end with a letter or digit",
+            bucket
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validator_collects_field_errors() {
+        let mut validator = Validator::new();
+        validator.check(true, "bucket", "must not be empty");
+        validator.check(false, "chunk_size", "must be at least 5 MiB");
+        let parsed = validator.check_result(
+            Err::<u32, _>(Error::Other("invalid number".to_string())),
+            "jobs.web.nice",
+        );
+        assert_eq!(parsed, None);
+
+        let err = validator.finish().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid configuration: chunk_size: must be at least 5 MiB; jobs.web.nice: invalid number"
+        );
+        assert!(Validator::new().finish().is_ok());
+    }
+
+    #[test]
+    fn test_bucket_names() {
+        assert!(check_bucket_name("ghostsnap-backup").is_ok());
+        assert!(check_bucket_name("logs.example.org").is_ok());
+        assert_eq!(check_bucket_name("").unwrap_err(), "must not be empty");
+        assert!(check_bucket_name("ab").is_err());
+        assert!(check_bucket_name("My_Bucket").is_err());
+        assert!(check_bucket_name("-backups").is_err());
+    }
+}
@@ -0,0 +1,276 @@
+#!/usr/bin/env python3
+"""Generates the restic repositories under v1/ and v2/.
+
+The repositories are built straight from restic's design document
+(doc/design.rst in the restic source tree) with Python's `cryptography`
+package, hashlib's scrypt and the `zstd` command line tool, so they share no
+code with ghostsnap's reader. All randomness (keys, salts, IVs) is derived
+from fixed labels: running the script again reproduces the checked-in files
+byte for byte, as long as the zstd version produces the same frames.
+
+Both repositories open with the password `fixture-password` and hold one
+snapshot of /home/alice:
+
+    home/alice/hello.txt      one uncompressed data blob
+    home/alice/big.bin        two data blobs (zstd-compressed in v2)
+    home/alice/docs/note.md   a file in a nested directory
+    home/alice/link           symlink to hello.txt
+
+A second key file, for `other-password`, opens the same master key; with the
+main password, readers have to skip key files that do not open.
+
+Usage: python3 generate.py   (from any directory)
+"""
+
+import base64
+import hashlib
+import json
+import os
+import shutil
+import struct
+import subprocess
+
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+from cryptography.hazmat.primitives.poly1305 import Poly1305
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+PASSWORD = "fixture-password"
+
+# Small scrypt cost so tests stay fast; restic reads N, r and p from the key
+# file.
+SCRYPT_N, SCRYPT_R, SCRYPT_P = 1024, 8, 1
+
+# Go os.FileMode bits
+MODE_DIR = 1 << 31
+MODE_SYMLINK = 1 << 27
+
+
+def fixed_bytes(label, n):
+    out = b""
+    counter = 0
+    while len(out) < n:
+        out += hashlib.sha256(f"ghostsnap-restic-fixture/{label}/{counter}".encode()).digest()
+        counter += 1
+    return out[:n]
+
+
+def sha256_hex(data):
+    return hashlib.sha256(data).hexdigest()
+
+
+class Key:
+    def __init__(self, encrypt, mac_k, mac_r):
+        self.encrypt, self.mac_k, self.mac_r = encrypt, mac_k, mac_r
+
+    def mac(self, iv, ciphertext):
+        aes = Cipher(algorithms.AES(self.mac_k), modes.ECB()).encryptor()
+        s = aes.update(iv) + aes.finalize()
+        return Poly1305.generate_tag(self.mac_r + s, ciphertext)
+
+    def seal(self, label, plaintext):
+        iv = fixed_bytes("iv/" + label, 16)
+        ctr = Cipher(algorithms.AES(self.encrypt), modes.CTR(iv)).encryptor()
+        ciphertext = ctr.update(plaintext) + ctr.finalize()
+        return iv + ciphertext + self.mac(iv, ciphertext)
+
+
+def mask_r(r):
+    # restic stores r already clamped as Poly1305 requires.
+    mask = bytes.fromhex("ffffff0ffcffff0ffcffff0ffcffff0f")
+    return bytes(a & b for a, b in zip(r, mask))
+
+
+def zstd(data):
+    return subprocess.run(
+        ["zstd", "-q", "-c", "-19", "--no-check"], input=data, capture_output=True, check=True
+    ).stdout
+
+
+def b64(data):
+    return base64.b64encode(data).decode()
+
+
+def write(path, data):
+    os.makedirs(os.path.dirname(path), exist_ok=True)
+    with open(path, "wb") as f:
+        f.write(data)
+
+
+def key_file(label, password, master):
+    salt = fixed_bytes("salt/" + label, 64)
+    derived = hashlib.scrypt(
+        password.encode(), salt=salt, n=SCRYPT_N, r=SCRYPT_R, p=SCRYPT_P, dklen=64
+    )
+    user_key = Key(derived[:32], derived[32:48], derived[48:])
+    master_json = json.dumps(
+        {
+            "mac": {"k": b64(master.mac_k), "r": b64(master.mac_r)},
+            "encrypt": b64(master.encrypt),
+        }
+    ).encode()
+    return json.dumps(
+        {
+            "created": "2024-03-01T11:58:00.000000001+01:00",
+            "username": "alice",
+            "hostname": "fixture-host",
+            "kdf": "scrypt",
+            "N": SCRYPT_N,
+            "r": SCRYPT_R,
+            "p": SCRYPT_P,
+            "salt": b64(salt),
+            "data": b64(user_key.seal("key/" + label, master_json)),
+        },
+        indent=2,
+    ).encode()
+
+
+def generate(version):
+    root = os.path.join(HERE, f"v{version}")
+    shutil.rmtree(root, ignore_errors=True)
+    tag = f"v{version}"
+
+    master = Key(
+        fixed_bytes(f"{tag}/encrypt", 32),
+        fixed_bytes(f"{tag}/mac-k", 16),
+        mask_r(fixed_bytes(f"{tag}/mac-r", 16)),
+    )
+    # Every key file seals the same master key.
+    for label, password in [("other", "other-password"), ("main", PASSWORD)]:
+        data = key_file(f"{tag}/{label}", password, master)
+        write(os.path.join(root, "keys", sha256_hex(data)), data)
+
+    def unpacked(label, obj):
+        plaintext = json.dumps(obj).encode()
+        if version == 2:
+            plaintext = b"\x02" + zstd(plaintext)
+        return master.seal(f"{tag}/{label}", plaintext)
+
+    config = {
+        "version": version,
+        "id": fixed_bytes(f"{tag}/id", 32).hex(),
+        "chunker_polynomial": "3dea92648f6e83",
+    }
+    # The config is never compressed, not even in version 2.
+    write(os.path.join(root, "config"), master.seal(f"{tag}/config", json.dumps(config).encode()))
+
+    packs = []
+
+    def pack(label, blobs, compress):
+        """blobs: list of (type, plaintext); returns their IDs."""
+        body, header, index_blobs, ids = b"", b"", [], []
+        for i, (blob_type, plaintext) in enumerate(blobs):
+            blob_id = sha256_hex(plaintext)
+            stored = zstd(plaintext) if compress else plaintext
+            sealed = master.seal(f"{tag}/{label}/{i}", stored)
+            type_byte = {"data": 0, "tree": 1}[blob_type]
+            entry = {"id": blob_id, "type": blob_type, "offset": len(body), "length": len(sealed)}
+            if compress:
+                header += struct.pack("<BII", type_byte + 2, len(sealed), len(plaintext))
+                entry["uncompressed_length"] = len(plaintext)
+            else:
+                header += struct.pack("<BI", type_byte, len(sealed))
+            header += bytes.fromhex(blob_id)
+            body += sealed
+            index_blobs.append(entry)
+            ids.append(blob_id)
+        sealed_header = master.seal(f"{tag}/{label}/header", header)
+        data = body + sealed_header + struct.pack("<I", len(sealed_header))
+        pack_id = sha256_hex(data)
+        write(os.path.join(root, "data", pack_id[:2], pack_id), data)
+        packs.append({"id": pack_id, "blobs": index_blobs})
+        return ids
+
+    hello = b"hello from restic\n"
+    big = bytes((i * 7 + i // 251) % 256 for i in range(5000))
+    note = b"# Notes\n\nNested two directories deep.\n"
+
+    # Data blobs: hello and note stay uncompressed; big.bin is split into two
+    # blobs that v2 compresses.
+    [hello_id, note_id] = pack("data-plain", [("data", hello), ("data", note)], False)
+    big_ids = pack("data-big", [("data", big[:3000]), ("data", big[3000:])], version == 2)
+
+    def file_node(name, content, ids, inode):
+        return {
+            "name": name,
+            "type": "file",
+            "mode": 0o644,
+            "mtime": "2024-02-29T10:00:00.5+01:00",
+            "atime": "2024-02-29T10:00:00.5+01:00",
+            "ctime": "2024-02-29T10:00:00.5+01:00",
+            "uid": 1000,
+            "gid": 1000,
+            "user": "alice",
+            "group": "alice",
+            "inode": inode,
+            "device_id": 2049,
+            "size": len(content),
+            "links": 1,
+            "content": ids,
+        }
+
+    def dir_node(name, subtree, uid=1000):
+        return {
+            "name": name,
+            "type": "dir",
+            "mode": MODE_DIR | 0o755,
+            "mtime": "2024-02-29T09:00:00+01:00",
+            "atime": "2024-02-29T09:00:00+01:00",
+            "ctime": "2024-02-29T09:00:00+01:00",
+            "uid": uid,
+            "gid": uid,
+            "content": None,
+            "subtree": subtree,
+        }
+
+    def tree(nodes):
+        # restic trees are sorted by name and end in a newline.
+        return json.dumps({"nodes": sorted(nodes, key=lambda n: n["name"])}).encode() + b"\n"
+
+    docs = tree([file_node("note.md", note, [note_id], 12)])
+    docs_id = sha256_hex(docs)
+    alice = tree(
+        [
+            file_node("hello.txt", hello, [hello_id], 10),
+            file_node("big.bin", big, big_ids, 11),
+            dir_node("docs", docs_id),
+            {
+                "name": "link",
+                "type": "symlink",
+                "mode": MODE_SYMLINK | 0o777,
+                "mtime": "2024-02-29T10:00:00+01:00",
+                "uid": 1000,
+                "gid": 1000,
+                "linktarget": "hello.txt",
+                "content": None,
+            },
+        ]
+    )
+    alice_id = sha256_hex(alice)
+    home = tree([dir_node("alice", alice_id)])
+    home_id = sha256_hex(home)
+    root_tree = tree([dir_node("home", home_id, uid=0)])
+    root_id = sha256_hex(root_tree)
+    pack("trees", [("tree", t) for t in [docs, alice, home, root_tree]], version == 2)
+
+    index = unpacked("index", {"packs": packs})
+    write(os.path.join(root, "index", sha256_hex(index)), index)
+
+    snapshot = unpacked(
+        "snapshot",
+        {
+            "time": "2024-03-01T12:00:00.123456789+01:00",
+            "tree": root_id,
+            "paths": ["/home/alice"],
+            "hostname": "fixture-host",
+            "username": "alice",
+            "uid": 1000,
+            "gid": 1000,
+            "tags": ["fixture"],
+        },
+    )
+    write(os.path.join(root, "snapshots", sha256_hex(snapshot)), snapshot)
+
+
+if __name__ == "__main__":
+    generate(1)
+    generate(2)
@@ -0,0 +1,158 @@
+//! Reads the restic repositories in `tests/fixtures/restic`, built from
+//! restic's design document by `generate.py` independently of this crate.
+
+use ghostsnap_core::types::NodeType;
+use ghostsnap_core::{RepositoryLocation, ResticRepository};
+use std::path::PathBuf;
+
+const PASSWORD: &str = "fixture-password";
+
+const V1_SNAPSHOT: &str = "3b03ea76d7634badc0fdaaf2312d512e5c9b2ba673f368e0d0cc35e87fe3080c";
+const V2_SNAPSHOT: &str = "01624333ef009e381678e4ba5e832eb982254b9fd41ceac4c940c03930f63959";
+
+fn fixture(version: u32) -> RepositoryLocation {
+    RepositoryLocation::Local(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/restic")
+            .join(format!("v{}", version)),
+    )
+}
+
+/// Contents of `big.bin`, as written by the generator.
+fn big_bin() -> Vec<u8> {
+    (0..5000usize)
+        .map(|i| ((i * 7 + i / 251) % 256) as u8)
+        .collect()
+}
+
+async fn read_file(
+    repo: &ResticRepository,
+    tree: &ghostsnap_core::snapshot::Tree,
+    path: &str,
+) -> Vec<u8> {
+    let node = tree
+        .nodes
+        .iter()
+        .find(|n| n.name == path)
+        .unwrap_or_else(|| panic!("{} not in tree", path));
+    let mut data = Vec::new();
+    for chunk in &node.chunks {
+        assert_eq!(chunk.offset, data.len() as u64);
+        let blob = repo.load_blob(&chunk.id.to_hex()).await.unwrap();
+        assert_eq!(blob.len(), chunk.length as usize);
+        data.extend_from_slice(&blob);
+    }
+    assert_eq!(data.len() as u64, node.size);
+    data
+}
+
+async fn check_fixture(version: u32, snapshot_id: &str) {
+    let repo = ResticRepository::open(fixture(version), PASSWORD)
+        .await
+        .unwrap();
+    assert_eq!(repo.version(), version);
+
+    let snapshots = repo.list_snapshots().await.unwrap();
+    assert_eq!(snapshots.len(), 1);
+    let snapshot = &snapshots[0];
+    assert_eq!(snapshot.id, snapshot_id);
+    assert_eq!(
+        snapshot.time.to_rfc3339(),
+        "2024-03-01T11:00:00.123456789+00:00"
+    );
+    assert_eq!(snapshot.paths, vec!["/home/alice"]);
+    assert_eq!(snapshot.hostname, "fixture-host");
+    assert_eq!(snapshot.username, "alice");
+    assert_eq!(snapshot.tags, vec!["fixture"]);
+
+    let snapshot = repo.load_snapshot(&snapshot_id[..8]).await.unwrap();
+    let tree = repo.load_tree(&snapshot).await.unwrap();
+    let listing: Vec<(&str, NodeType)> = tree
+        .nodes
+        .iter()
+        .map(|n| (n.name.as_str(), n.node_type.clone()))
+        .collect();
+    assert_eq!(
+        listing,
+        vec![
+            ("home", NodeType::Directory),
+            ("home/alice", NodeType::Directory),
+            ("home/alice/big.bin", NodeType::File),
+            ("home/alice/docs", NodeType::Directory),
+            ("home/alice/docs/note.md", NodeType::File),
+            ("home/alice/hello.txt", NodeType::File),
+            ("home/alice/link", NodeType::Symlink),
+        ]
+    );
+
+    let hello = tree
+        .nodes
+        .iter()
+        .find(|n| n.name == "home/alice/hello.txt")
+        .unwrap();
+    assert_eq!(hello.mode, 0o644);
+    assert_eq!((hello.uid, hello.gid), (1000, 1000));
+    assert_eq!(hello.mtime, 1709197200);
+    let alice = tree.nodes.iter().find(|n| n.name == "home/alice").unwrap();
+    assert_eq!(alice.mode, 0o755);
+    let link = tree
+        .nodes
+        .iter()
+        .find(|n| n.name == "home/alice/link")
+        .unwrap();
+    assert_eq!(link.link_target.as_deref(), Some("hello.txt"));
+
+    assert_eq!(
+        read_file(&repo, &tree, "home/alice/hello.txt").await,
+        b"hello from restic\n"
+    );
+    assert_eq!(
+        read_file(&repo, &tree, "home/alice/docs/note.md").await,
+        b"# Notes\n\nNested two directories deep.\n"
+    );
+    let big = tree
+        .nodes
+        .iter()
+        .find(|n| n.name == "home/alice/big.bin")
+        .unwrap();
+    assert_eq!(big.chunks.len(), 2);
+    assert_eq!(
+        read_file(&repo, &tree, "home/alice/big.bin").await,
+        big_bin()
+    );
+}
+
+#[tokio::test]
+async fn test_restic_v1_fixture() {
+    check_fixture(1, V1_SNAPSHOT).await;
+}
+
+#[tokio::test]
+async fn test_restic_v2_fixture() {
+    check_fixture(2, V2_SNAPSHOT).await;
+}
+
+#[tokio::test]
+async fn test_restic_fixture_wrong_password() {
+    for version in [1, 2] {
+        let err = ResticRepository::open(fixture(version), "not-the-password")
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            matches!(err, ghostsnap_core::Error::InvalidPassword),
+            "{}",
+            err
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_restic_fixture_other_key() {
+    // The second key file opens the same repository.
+    let repo = ResticRepository::open(fixture(2), "other-password")
+        .await
+        .unwrap();
+    let snapshots = repo.list_snapshots().await.unwrap();
+    assert_eq!(snapshots[0].id, V2_SNAPSHOT);
+}